@@ -0,0 +1,95 @@
+// chat_client.rs - Quickstart client: chat lines from stdin, /ping round trips, /quit to leave
+//
+// Run with `cargo run --example chat_client -- <name> [addr]` against a running echo_server.
+// Lines typed are sent as chat on the reliable channel; `/ping` times a probe on the
+// unreliable echo channel, and `/quit` (or closing stdin) disconnects cleanly. Stdin is read
+// on its own thread so the connection keeps being updated while waiting for input.
+mod common;
+
+use std::sync::mpsc::{self, TryRecvError};
+use std::time::{Duration, Instant};
+
+use gbnet::packet::disconnect_reason;
+use gbnet::prelude::*;
+
+use common::{ClientMessage, ServerMessage, CHAT, ECHO};
+
+const TICK: Duration = Duration::from_millis(16);
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let name = args.next().unwrap_or_else(|| "guest".to_string());
+    let server_addr: SocketAddr = args.next().as_deref().unwrap_or(common::SERVER_ADDR).parse()?;
+
+    let mut socket = UdpSocket::bind("0.0.0.0:0".parse()?)?;
+    let mut client = Connection::new(common::config(), socket.local_addr()?, server_addr);
+    client.connect()?;
+
+    let (lines, input) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            if lines.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let started = Instant::now();
+    let mut joined = false;
+    loop {
+        client.update(&mut socket)?;
+
+        while let Some(event) = client.poll_event() {
+            if let ConnectionEvent::Connecting { attempt } = event {
+                println!("connecting to {} (attempt {})", server_addr, attempt);
+            }
+        }
+
+        if client.is_connected() && !joined {
+            joined = true;
+            client.send(CHAT, &common::encode(&ClientMessage::Join { name: name.clone() })?, true)?;
+            println!("connected; type to chat, /ping to measure latency, /quit to leave");
+        } else if joined && !client.is_connected() {
+            println!("disconnected by the server");
+            return Ok(());
+        }
+
+        while let Some(probe) = client.receive(ECHO) {
+            if let Ok(sent) = <[u8; 8]>::try_from(probe.as_slice()) {
+                let rtt = started.elapsed().saturating_sub(Duration::from_micros(u64::from_le_bytes(sent)));
+                println!("pong in {:.1}ms", rtt.as_secs_f64() * 1000.0);
+            }
+        }
+        while let Some(bytes) = client.receive(CHAT) {
+            match common::decode(bytes)? {
+                ServerMessage::Joined { name } => println!("* {} joined", name),
+                ServerMessage::Left { name } => println!("* {} left", name),
+                ServerMessage::Said { name, text } => println!("<{}> {}", name, text),
+            }
+        }
+
+        let line = match input.try_recv() {
+            Ok(line) => Some(line),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some("/quit".to_string()),
+        };
+        match line.as_deref().map(str::trim) {
+            Some("/quit") => break,
+            Some("/ping") if joined => {
+                let sent = started.elapsed().as_micros() as u64;
+                client.send(ECHO, &sent.to_le_bytes(), false)?;
+            }
+            Some("") | None => {}
+            Some(text) if joined => {
+                client.send(CHAT, &common::encode(&ClientMessage::Say { text: text.to_string() })?, true)?;
+            }
+            Some(_) => println!("not connected yet"),
+        }
+
+        std::thread::sleep(TICK);
+    }
+
+    client.disconnect(disconnect_reason::REQUESTED)?;
+    client.update(&mut socket)?;
+    Ok(())
+}
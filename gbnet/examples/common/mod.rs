@@ -0,0 +1,66 @@
+// common/mod.rs - Protocol shared by the echo_server and chat_client examples
+//
+// Both ends must agree on the protocol id, the channel layout and the message types, so they
+// live here and each example includes this module. Each example only uses its half of the
+// messages, hence the dead_code allowance.
+#![allow(dead_code)]
+
+use gbnet::prelude::*;
+
+pub const SERVER_ADDR: &str = "127.0.0.1:40000";
+
+/// Raw bytes the server sends straight back; clients time round trips with them.
+pub const ECHO: u8 = 0;
+/// Chat messages, encoded with the derived serializers below.
+pub const CHAT: u8 = 1;
+
+pub fn config() -> NetworkConfig {
+    NetworkConfig {
+        protocol_id: 0x6762_6e74,
+        channel_configs: vec![
+            ChannelConfig { reliability: Reliability::Unreliable, ordering: Ordering::Unordered, ..Default::default() },
+            ChannelConfig { reliability: Reliability::Reliable, ordering: Ordering::Ordered, ..Default::default() },
+        ],
+        ..Default::default()
+    }
+}
+
+#[derive(NetworkSerialize, Debug, PartialEq)]
+pub enum ClientMessage {
+    Join {
+        #[max_len = 32]
+        name: String,
+    },
+    Say {
+        #[max_len = 512]
+        text: String,
+    },
+}
+
+#[derive(NetworkSerialize, Debug, PartialEq)]
+pub enum ServerMessage {
+    Joined {
+        #[max_len = 32]
+        name: String,
+    },
+    Left {
+        #[max_len = 32]
+        name: String,
+    },
+    Said {
+        #[max_len = 32]
+        name: String,
+        #[max_len = 512]
+        text: String,
+    },
+}
+
+pub fn encode<T: BitSerialize>(message: &T) -> std::io::Result<Vec<u8>> {
+    let mut buffer = BitBuffer::new();
+    message.bit_serialize(&mut buffer)?;
+    buffer.into_bytes(true)
+}
+
+pub fn decode<T: BitDeserialize>(bytes: Vec<u8>) -> std::io::Result<T> {
+    T::bit_deserialize(&mut BitBuffer::from_bytes(bytes))
+}
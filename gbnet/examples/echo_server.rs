@@ -0,0 +1,65 @@
+// echo_server.rs - Quickstart server: echoes the ECHO channel and relays chat between clients
+//
+// Run with `cargo run --example echo_server [addr]`, then start one or more
+// `cargo run --example chat_client -- <name> [addr]`. Messages on the echo channel go
+// straight back to their sender; chat messages are decoded and relayed to everyone, with
+// join and leave notices raised from server events.
+mod common;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use gbnet::packet::disconnect_reason;
+use gbnet::prelude::*;
+
+use common::{ClientMessage, ServerMessage, CHAT, ECHO};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let addr: SocketAddr = std::env::args().nth(1).as_deref().unwrap_or(common::SERVER_ADDR).parse()?;
+    let mut server = Server::bind(addr, common::config())?;
+    println!("listening on {}", server.local_addr());
+
+    let mut names: HashMap<SocketAddr, String> = HashMap::new();
+    loop {
+        server.wait(Duration::from_millis(50))?;
+        server.update()?;
+
+        while let Some(event) = server.poll_event() {
+            match event {
+                ServerEvent::ClientConnected(client) => println!("{} connected", client),
+                ServerEvent::ClientDisconnected(client, reason) => {
+                    println!("{} disconnected (reason {})", client, reason);
+                    if let Some(name) = names.remove(&client) {
+                        server.broadcast(CHAT, &common::encode(&ServerMessage::Left { name })?, true)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let clients: Vec<SocketAddr> = server.clients().copied().collect();
+        for client in clients {
+            while let Some(probe) = server.receive(client, ECHO) {
+                server.send(client, ECHO, &probe, false)?;
+            }
+            while let Some(bytes) = server.receive(client, CHAT) {
+                let reply = match common::decode(bytes) {
+                    Ok(ClientMessage::Join { name }) => {
+                        names.insert(client, name.clone());
+                        ServerMessage::Joined { name }
+                    }
+                    Ok(ClientMessage::Say { text }) => match names.get(&client) {
+                        Some(name) => ServerMessage::Said { name: name.clone(), text },
+                        None => continue,
+                    },
+                    Err(e) => {
+                        println!("{} sent an undecodable message ({}), kicking", client, e);
+                        server.disconnect(client, disconnect_reason::KICKED)?;
+                        break;
+                    }
+                };
+                server.broadcast(CHAT, &common::encode(&reply)?, true)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+// alloc_audit.rs - Counting heap allocations around hot paths
+//
+// The send buffer, in-place packet parsing and pooled datagram buffers exist so a connection
+// that is only keeping up with traffic does not touch the allocator, but nothing noticed when
+// a change quietly added a `to_vec` to `update`. `CountingAllocator` wraps the system
+// allocator and counts per thread, so tests running in parallel do not see each other's
+// allocations; `count_allocations` measures one closure, such as a poll/flush cycle, and
+// `assert_no_allocations` fails when it allocated. Install it as the `#[global_allocator]` of
+// a test binary or a debug build; this crate's own tests do.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // Const-initialized and without a destructor, so reading them never allocates
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    static BYTES: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A global allocator that forwards to `A` and counts allocations on each thread.
+pub struct CountingAllocator<A = System> {
+    inner: A,
+}
+
+impl CountingAllocator {
+    pub const fn new() -> Self {
+        Self { inner: System }
+    }
+}
+
+impl Default for CountingAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A> CountingAllocator<A> {
+    /// Counts on top of another allocator.
+    pub const fn wrapping(inner: A) -> Self {
+        Self { inner }
+    }
+
+    /// Whether a `CountingAllocator` is the global allocator; counts stay zero otherwise.
+    pub fn is_installed() -> bool {
+        INSTALLED.load(Ordering::Relaxed)
+    }
+}
+
+fn record(size: usize) {
+    INSTALLED.store(true, Ordering::Relaxed);
+    // Allocations while the thread is exiting go uncounted
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    let _ = BYTES.try_with(|bytes| bytes.set(bytes.get() + size as u64));
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        self.inner.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        self.inner.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout)
+    }
+}
+
+/// Allocations made on one thread while something ran; reallocations count as allocations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocationCount {
+    pub allocations: u64,
+    pub bytes: u64,
+}
+
+fn current() -> AllocationCount {
+    AllocationCount {
+        allocations: ALLOCATIONS.with(Cell::get),
+        bytes: BYTES.with(Cell::get),
+    }
+}
+
+/// Runs `f` and returns what it allocated on this thread.
+///
+/// # Panics
+///
+/// If no [`CountingAllocator`] is installed, so an audit cannot pass by counting nothing.
+pub fn count_allocations<R>(f: impl FnOnce() -> R) -> (R, AllocationCount) {
+    let before = current();
+    let result = f();
+    let after = current();
+    assert!(
+        CountingAllocator::<System>::is_installed(),
+        "allocation audit needs CountingAllocator as the #[global_allocator]",
+    );
+    let count = AllocationCount {
+        allocations: after.allocations - before.allocations,
+        bytes: after.bytes - before.bytes,
+    };
+    (result, count)
+}
+
+/// Runs `f`, panicking with `what` if it allocated.
+#[track_caller]
+pub fn assert_no_allocations<R>(what: &str, f: impl FnOnce() -> R) -> R {
+    let (result, count) = count_allocations(f);
+    assert_eq!(
+        count.allocations, 0,
+        "{} made {} heap allocations ({} bytes)", what, count.allocations, count.bytes,
+    );
+    result
+}
@@ -1,12 +1,87 @@
 // channel.rs - Message channels with reliability and ordering guarantees
 use std::collections::{VecDeque, HashMap};
-use crate::config::{ChannelConfig, Reliability, Ordering};
+use std::io;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use crate::config::{ChannelConfig, ChannelGroupConfig, PayloadMode, Reliability, Ordering, SlowConsumer};
+use crate::compression::CompressOutcome;
+use crate::delta::StringBaselines;
+use crate::legacy::{self, LegacyError, LegacyMessage};
+use crate::packet::sequence_greater_than;
+use crate::reliability::SequenceBuffer;
+use crate::serialize::{BitDeserialize, BitSerialize, bit_io::{BitBuffer, BitCounter, BitWrite}};
+
+/// Sequence and length prefix in front of every message inside a payload packet.
+pub const MESSAGE_HEADER_BYTES: usize = 4;
+
+/// Key and version in front of every message on a `latest_only` channel.
+pub const KEYED_HEADER_BYTES: usize = 8;
+
+/// Item count in front of every batch message from `send_batch`.
+pub const BATCH_HEADER_BYTES: usize = 4;
+
+/// Id the internal control channel reports in its stats; never a user channel.
+pub const CONTROL_CHANNEL: u8 = u8::MAX;
+
+/// How many recent message sequences a reliable channel remembers to drop duplicates.
+const DUPLICATE_WINDOW: usize = 1024;
 
 #[derive(Debug)]
 pub enum ChannelError {
     BufferFull,
     MessageTooLarge,
     InvalidSequence,
+    /// `latest_only` channels only take `send_latest`, and other channels never do
+    KeyMismatch,
+    /// A new key was sent while `max_pending_keys` keys were already pending
+    KeySpaceFull,
+    /// An item passed to `send_batch` failed to serialize
+    InvalidBatch,
+    /// `PayloadMode::Legacy` channels only take `send_legacy`, and other channels never do
+    PayloadMismatch,
+    /// A message passed to `send_legacy` failed to encode
+    InvalidLegacyMessage,
+}
+
+impl std::fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChannelError::BufferFull => write!(f, "channel buffer full"),
+            ChannelError::MessageTooLarge => write!(f, "message too large for the channel"),
+            ChannelError::InvalidSequence => write!(f, "invalid sequence"),
+            ChannelError::KeyMismatch => write!(f, "keyed send on the wrong kind of channel"),
+            ChannelError::KeySpaceFull => write!(f, "too many pending keys"),
+            ChannelError::InvalidBatch => write!(f, "batch item failed to serialize"),
+            ChannelError::PayloadMismatch => write!(f, "payload mode does not match the channel"),
+            ChannelError::InvalidLegacyMessage => write!(f, "legacy message failed to encode"),
+        }
+    }
+}
+
+impl std::error::Error for ChannelError {}
+
+/// How the peer's channels differ from ours, found by comparing layouts in the handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelMismatch {
+    pub local_channels: usize,
+    pub remote_channels: usize,
+    /// Channels both ends have whose reliability, ordering, payload mode or encryption differ
+    pub differing: Vec<u8>,
+}
+
+impl ChannelMismatch {
+    /// Compares two channel layouts, one byte per channel; `None` when they agree.
+    pub(crate) fn between(local: &[u8], remote: &[u8]) -> Option<Self> {
+        let differing: Vec<u8> = local.iter().zip(remote)
+            .enumerate()
+            .filter(|(_, (local, remote))| local != remote)
+            .map(|(id, _)| id as u8)
+            .collect();
+        if local.len() == remote.len() && differing.is_empty() {
+            return None;
+        }
+        Some(Self { local_channels: local.len(), remote_channels: remote.len(), differing })
+    }
 }
 
 #[derive(Debug)]
@@ -17,17 +92,47 @@ pub struct Channel {
     // Send state
     send_sequence: u16,
     send_buffer: VecDeque<ChannelMessage>,
+    // Messages from `send_at` waiting for their time, soonest first, in call order for equal times
+    scheduled: VecDeque<ScheduledMessage>,
     
     // Receive state
     receive_sequence: u16,
     receive_buffer: HashMap<u16, ChannelMessage>,
     ordered_buffer: VecDeque<Vec<u8>>,
+    delivered: SequenceBuffer<u16>,
+    newest_delivered: Option<u16>,
+    
+    // Newest queued or unacked message of each key
+    pending_keys: HashMap<u32, u16>,
+    // Newest version of each key sent and delivered, for `latest_only` channels
+    latest_sent: HashMap<u32, u32>,
+    latest_received: HashMap<u32, u32>,
+    
+    // #[delta_string] baselines for each direction
+    send_baselines: StringBaselines,
+    receive_baselines: StringBaselines,
     
     // Stats
     messages_sent: u64,
     messages_received: u64,
     bytes_sent: u64,
     bytes_received: u64,
+    duplicates_dropped: u64,
+    messages_superseded: u64,
+    receive_dropped: u64,
+    receive_backpressured: u64,
+    starved_updates: u64,
+    starved_streak: u32,
+    compression_skipped: u64,
+    compression_ineffective: u64,
+    compression_saved: i64,
+}
+
+#[derive(Debug, Clone)]
+struct ScheduledMessage {
+    at: Instant,
+    data: Vec<u8>,
+    reliable: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -36,6 +141,10 @@ struct ChannelMessage {
     data: Vec<u8>,
     reliable: bool,
     retry_count: u32,
+    /// Dropped instead of sent (or resent) after this
+    expires: Option<Instant>,
+    /// Replaced by the next message queued with the same key
+    key: Option<u32>,
 }
 
 impl Channel {
@@ -45,44 +154,249 @@ impl Channel {
             config,
             send_sequence: 0,
             send_buffer: VecDeque::new(),
+            scheduled: VecDeque::new(),
             receive_sequence: 0,
             receive_buffer: HashMap::new(),
             ordered_buffer: VecDeque::new(),
+            delivered: SequenceBuffer::new(DUPLICATE_WINDOW),
+            newest_delivered: None,
+            pending_keys: HashMap::new(),
+            latest_sent: HashMap::new(),
+            latest_received: HashMap::new(),
+            send_baselines: StringBaselines::new(),
+            receive_baselines: StringBaselines::new(),
             messages_sent: 0,
             messages_received: 0,
             bytes_sent: 0,
             bytes_received: 0,
+            duplicates_dropped: 0,
+            messages_superseded: 0,
+            receive_dropped: 0,
+            receive_backpressured: 0,
+            starved_updates: 0,
+            starved_streak: 0,
+            compression_skipped: 0,
+            compression_ineffective: 0,
+            compression_saved: 0,
         }
     }
     
     /// Sends data on this channel
     pub fn send(&mut self, data: &[u8], reliable: bool) -> Result<(), ChannelError> {
+        self.check_plain_send()?;
+        self.push(data, reliable, None, None).map(|_| ())
+    }
+    
+    /// Like [`Channel::send`], but takes ownership of the buffer instead of copying it
+    pub fn send_owned(&mut self, data: Vec<u8>, reliable: bool) -> Result<(), ChannelError> {
+        self.check_plain_send()?;
+        self.push(data, reliable, None, None).map(|_| ())
+    }
+    
+    /// Sends a message in its legacy encoding on a `PayloadMode::Legacy` channel, framed with
+    /// its length; read it back with [`Channel::receive_legacy`]
+    pub fn send_legacy<T: LegacyMessage>(&mut self, message: &T, reliable: bool) -> Result<(), ChannelError> {
+        let frame = legacy::encode_frame(message).map_err(|_| ChannelError::InvalidLegacyMessage)?;
+        self.send_legacy_frame(frame, reliable)
+    }
+    
+    pub(crate) fn send_legacy_frame(&mut self, frame: Vec<u8>, reliable: bool) -> Result<(), ChannelError> {
+        if self.config.latest_only {
+            return Err(ChannelError::KeyMismatch);
+        }
+        if self.config.payload_mode != PayloadMode::Legacy {
+            return Err(ChannelError::PayloadMismatch);
+        }
+        self.push(frame, reliable, None, None).map(|_| ())
+    }
+    
+    // Plain sends go to channels that take neither keyed nor legacy messages
+    fn check_plain_send(&self) -> Result<(), ChannelError> {
+        if self.config.latest_only {
+            return Err(ChannelError::KeyMismatch);
+        }
+        if self.config.payload_mode != PayloadMode::BitPacked {
+            return Err(ChannelError::PayloadMismatch);
+        }
+        Ok(())
+    }
+    
+    /// Sends many messages of one type as batches: an item count, then the items bit-packed
+    /// back to back with no per-message framing.
+    ///
+    /// Items are split over as many batch messages as `max_message_size` requires; returns
+    /// how many were queued. Read them back with [`Channel::receive_batch`].
+    pub fn send_batch<I>(&mut self, items: I, reliable: bool) -> Result<usize, ChannelError>
+    where
+        I: IntoIterator,
+        I::Item: BitSerialize,
+    {
+        self.send_batch_within(items, reliable, self.config.max_message_size)
+    }
+    
+    pub(crate) fn send_batch_within<I>(&mut self, items: I, reliable: bool, max_size: usize) -> Result<usize, ChannelError>
+    where
+        I: IntoIterator,
+        I::Item: BitSerialize,
+    {
+        let batches = encode_batches(items, max_size)?;
+        // Queue all of the batches or none of them
+        if self.config.block_on_full && self.send_buffer.len() + batches.len() > self.config.message_buffer_size {
+            return Err(ChannelError::BufferFull);
+        }
+        for batch in &batches {
+            self.send(batch, reliable)?;
+        }
+        Ok(batches.len())
+    }
+    
+    /// Sends reliable data that is dropped if it cannot be delivered within `ttl`
+    ///
+    /// Returns the message sequence, which identifies it in delivery reports.
+    pub fn send_with_ttl(&mut self, data: &[u8], ttl: Duration) -> Result<u16, ChannelError> {
+        self.check_plain_send()?;
+        self.send_until(data, Instant::now() + ttl)
+    }
+    
+    /// Like [`Channel::send_with_ttl`], with the deadline given directly
+    pub(crate) fn send_until(&mut self, data: &[u8], expires: Instant) -> Result<u16, ChannelError> {
+        self.check_plain_send()?;
+        self.push(data, true, Some(expires), None)
+    }
+    
+    /// Holds data until `at`, then queues it like [`Channel::send`]
+    ///
+    /// Up to `message_buffer_size` messages can be waiting at once. The message gets its
+    /// sequence when it is released, so it is ordered after everything sent before then.
+    pub fn send_at(&mut self, data: &[u8], at: Instant, reliable: bool) -> Result<(), ChannelError> {
+        self.check_plain_send()?;
+        if data.len() > self.config.max_message_size {
+            return Err(ChannelError::MessageTooLarge);
+        }
+        if self.scheduled.len() >= self.config.message_buffer_size {
+            return Err(ChannelError::BufferFull);
+        }
+        let index = self.scheduled.partition_point(|message| message.at <= at);
+        self.scheduled.insert(index, ScheduledMessage { at, data: data.to_vec(), reliable });
+        Ok(())
+    }
+    
+    /// Moves messages whose time has come from the schedule into the send buffer; any that
+    /// do not fit a full `block_on_full` buffer wait for the next call
+    pub(crate) fn release_scheduled(&mut self, now: Instant) {
+        while self.scheduled.front().is_some_and(|message| message.at <= now) {
+            let Some(message) = self.scheduled.pop_front() else { break };
+            if self.push(message.data.as_slice(), message.reliable, None, None).is_err() {
+                self.scheduled.push_front(message);
+                break;
+            }
+        }
+    }
+    
+    /// Number of messages from `send_at` still waiting for their time
+    pub fn scheduled_messages(&self) -> usize {
+        self.scheduled.len()
+    }
+    
+    /// Sends data in the slot for `key`, replacing the key's previous message while it is
+    /// still queued or awaiting its ack
+    ///
+    /// At most `max_pending_keys` keys can be pending at once; a key frees up when its
+    /// newest message is acknowledged (or sent, on unreliable channels).
+    pub fn send_keyed(&mut self, key: u32, data: &[u8], reliable: bool) -> Result<(), ChannelError> {
+        self.check_plain_send()?;
+        self.push(data, reliable, None, Some(key)).map(|_| ())
+    }
+    
+    /// Sends a new version of the state stored under `key` on a `latest_only` channel
+    ///
+    /// Like [`Channel::send_keyed`], and the receiver also drops versions older than one it has.
+    pub fn send_latest(&mut self, key: u32, data: &[u8]) -> Result<(), ChannelError> {
+        if !self.config.latest_only {
+            return Err(ChannelError::KeyMismatch);
+        }
+        if data.len() + KEYED_HEADER_BYTES > self.config.max_message_size {
+            return Err(ChannelError::MessageTooLarge);
+        }
+        
+        let version = self.latest_sent.get(&key).map_or(0, |version| version.wrapping_add(1));
+        let mut message = Vec::with_capacity(KEYED_HEADER_BYTES + data.len());
+        message.extend_from_slice(&key.to_le_bytes());
+        message.extend_from_slice(&version.to_le_bytes());
+        message.extend_from_slice(data);
+        self.push(message, true, None, Some(key))?;
+        self.latest_sent.insert(key, version);
+        Ok(())
+    }
+    
+    /// Whether a sent message has since been replaced by a newer one with its key
+    pub(crate) fn is_superseded(&self, key: u32, sequence: u16) -> bool {
+        self.pending_keys.get(&key) != Some(&sequence)
+    }
+    
+    /// Frees the slot for `key` once its newest message is acknowledged or given up on
+    pub(crate) fn release_key(&mut self, key: u32, sequence: u16) {
+        if self.pending_keys.get(&key) == Some(&sequence) {
+            self.pending_keys.remove(&key);
+        }
+    }
+    
+    /// Counts messages left out of a resend because a newer one with their key was sent
+    pub(crate) fn note_superseded(&mut self, count: usize) {
+        self.messages_superseded += count as u64;
+    }
+    
+    /// Number of keys with a message queued or awaiting its ack
+    pub fn pending_keys(&self) -> usize {
+        self.pending_keys.len()
+    }
+    
+    fn push(&mut self, data: impl Into<Vec<u8>>, reliable: bool, expires: Option<Instant>, key: Option<u32>) -> Result<u16, ChannelError> {
+        let data = data.into();
         if data.len() > self.config.max_message_size {
             return Err(ChannelError::MessageTooLarge);
         }
         
+        if let Some(key) = key {
+            if !self.pending_keys.contains_key(&key) && self.pending_keys.len() >= self.config.max_pending_keys {
+                return Err(ChannelError::KeySpaceFull);
+            }
+            let before = self.send_buffer.len();
+            self.send_buffer.retain(|message| message.key != Some(key));
+            self.messages_superseded += (before - self.send_buffer.len()) as u64;
+        }
+        
         if self.send_buffer.len() >= self.config.message_buffer_size {
             if self.config.block_on_full {
                 return Err(ChannelError::BufferFull);
             } else {
                 // Drop oldest message
-                self.send_buffer.pop_front();
+                if let Some(ChannelMessage { sequence, key: Some(key), .. }) = self.send_buffer.pop_front() {
+                    self.release_key(key, sequence);
+                }
             }
         }
         
+        let size = data.len() as u64;
         let message = ChannelMessage {
             sequence: self.send_sequence,
-            data: data.to_vec(),
+            data,
             reliable,
             retry_count: 0,
+            expires,
+            key,
         };
         
+        let sequence = self.send_sequence;
         self.send_sequence = self.send_sequence.wrapping_add(1);
         self.send_buffer.push_back(message);
         self.messages_sent += 1;
-        self.bytes_sent += data.len() as u64;
+        self.bytes_sent += size;
+        if let Some(key) = key {
+            self.pending_keys.insert(key, sequence);
+        }
         
-        Ok(())
+        Ok(sequence)
     }
     
     /// Gets the next message to send over the network
@@ -96,11 +410,113 @@ impl Channel {
         }
     }
     
+    /// Removes the next message to send with its sequence, for assembly into a packet
+    pub fn take_outgoing_message(&mut self) -> Option<(u16, Vec<u8>)> {
+        self.take_outgoing().map(|message| (message.sequence, message.data))
+    }
+    
+    /// Like [`Channel::take_outgoing_message`], with the message's TTL and key
+    ///
+    /// Keys on unreliable channels are released here, since no ack will come.
+    pub(crate) fn take_outgoing(&mut self) -> Option<OutgoingMessage> {
+        let message = self.send_buffer.pop_front()?;
+        if let Some(key) = message.key.filter(|_| !self.is_reliable()) {
+            self.release_key(key, message.sequence);
+        }
+        Some(OutgoingMessage {
+            sequence: message.sequence,
+            data: message.data,
+            expires: message.expires,
+            key: message.key,
+        })
+    }
+    
+    /// Drops queued messages whose TTL has passed, returning their sequences
+    pub(crate) fn drop_expired(&mut self, now: Instant) -> Vec<u16> {
+        let mut expired = Vec::new();
+        self.send_buffer.retain(|message| {
+            let keep = message.expires.is_none_or(|expires| now < expires);
+            if !keep {
+                expired.push(message.sequence);
+            }
+            keep
+        });
+        expired
+    }
+    
+    /// Bytes of messages waiting to be sent
+    pub fn pending_bytes(&self) -> usize {
+        self.send_buffer.iter().map(|message| message.data.len()).sum()
+    }
+    
+    /// Size of the next message to send, without removing it
+    pub fn peek_outgoing_size(&self) -> Option<usize> {
+        self.send_buffer.front().map(|message| message.data.len())
+    }
+    
+    /// Returns whether messages are waiting to be sent
+    pub fn has_outgoing_messages(&self) -> bool {
+        !self.send_buffer.is_empty()
+    }
+    
+    /// Processes an incoming message, dropping duplicates on reliable channels
+    ///
+    /// Returns whether the message was accepted.
+    pub fn on_message_received(&mut self, sequence: u16, data: Vec<u8>) -> bool {
+        if self.is_reliable() {
+            if self.is_duplicate(sequence) {
+                self.duplicates_dropped += 1;
+                return false;
+            }
+            self.delivered.insert(sequence, sequence);
+            if self.newest_delivered.is_none_or(|newest| sequence_greater_than(sequence, newest)) {
+                self.newest_delivered = Some(sequence);
+            }
+        }
+        if self.config.latest_only {
+            // A version overtaken in flight by a newer one is never delivered after it
+            let Some((key, version)) = keyed_header(&data) else {
+                return false;
+            };
+            if self.latest_received.get(&key).is_some_and(|&latest| version <= latest) {
+                self.messages_superseded += 1;
+                return false;
+            }
+            self.latest_received.insert(key, version);
+            self.on_packet_received(data[KEYED_HEADER_BYTES..].to_vec());
+            return true;
+        }
+        self.on_packet_received(data);
+        true
+    }
+    
+    /// Seen before, or too old to tell, which for a resent message means seen before
+    fn is_duplicate(&self, sequence: u16) -> bool {
+        let newest = match self.newest_delivered {
+            Some(newest) => newest,
+            None => return false,
+        };
+        let window_start = newest.wrapping_sub(DUPLICATE_WINDOW as u16 - 1);
+        sequence_greater_than(window_start, sequence) || self.delivered.get(sequence) == Some(&sequence)
+    }
+    
+    /// Forgets delivered sequences, for when the remote restarts its numbering
+    pub(crate) fn reset_duplicate_tracking(&mut self) {
+        self.delivered = SequenceBuffer::new(DUPLICATE_WINDOW);
+        self.newest_delivered = None;
+    }
+    
     /// Processes an incoming packet for this channel
     pub fn on_packet_received(&mut self, data: Vec<u8>) {
         // For simplicity, we'll assume the data is the message directly
         // In a full implementation, you'd deserialize sequence numbers and handle ordering
         
+        // Unreliable data goes stale anyway, so an undrained channel keeps only the newest
+        if !self.is_reliable() && self.receive_full() {
+            self.ordered_buffer.pop_front();
+            self.receive_dropped += 1;
+        }
+        
         match self.config.ordering {
             Ordering::Unordered => {
                 // Deliver immediately
@@ -125,11 +541,63 @@ impl Channel {
         }
     }
     
+    /// Whether `receive_limit` messages are waiting for the application
+    pub fn receive_full(&self) -> bool {
+        self.config.receive_limit.is_some_and(|limit| self.ordered_buffer.len() >= limit)
+    }
+    
+    /// Counts a packet refused unacked because the channel was full
+    pub(crate) fn on_backpressure(&mut self) {
+        self.receive_backpressured += 1;
+    }
+    
+    /// Counts one payload through the channel's codec, `framed` bytes from `original`
+    pub(crate) fn record_compression(&mut self, outcome: CompressOutcome, original: usize, framed: usize) {
+        match outcome {
+            CompressOutcome::Skipped => self.compression_skipped += 1,
+            CompressOutcome::Stored => self.compression_ineffective += 1,
+            CompressOutcome::Compressed => {}
+        }
+        self.compression_saved += original as i64 - framed as i64;
+    }
+    
+    /// Records whether an assembly pass that left messages waiting got any of them out
+    pub(crate) fn record_send_turn(&mut self, sent: bool) {
+        if sent || !self.has_outgoing_messages() {
+            self.starved_streak = 0;
+        } else {
+            self.starved_updates += 1;
+            self.starved_streak += 1;
+        }
+    }
+    
+    /// Over `receive_limit` on a reliable channel set to disconnect slow consumers
+    pub(crate) fn receive_overrun(&self) -> bool {
+        self.is_reliable()
+            && self.config.slow_consumer == SlowConsumer::Disconnect
+            && self.config.receive_limit.is_some_and(|limit| self.ordered_buffer.len() > limit)
+    }
+    
     /// Receives the next available message
     pub fn receive(&mut self) -> Option<Vec<u8>> {
         self.ordered_buffer.pop_front()
     }
     
+    /// Receives the next message as a batch from [`Channel::send_batch`]; `None` if no message
+    /// is waiting or it is too short to be one.
+    pub fn receive_batch<T: BitDeserialize>(&mut self) -> Option<BatchItems<T>> {
+        self.receive().and_then(BatchItems::decode)
+    }
+    
+    /// Receives and decodes the next message from [`Channel::send_legacy`]; `None` if no
+    /// message is waiting or this is not a `PayloadMode::Legacy` channel.
+    pub fn receive_legacy<T: LegacyMessage>(&mut self) -> Option<Result<T, LegacyError>> {
+        if self.config.payload_mode != PayloadMode::Legacy {
+            return None;
+        }
+        self.receive().map(|frame| legacy::decode_frame(&frame))
+    }
+    
     /// Acknowledges a sent message (for reliable delivery)
     pub fn acknowledge_message(&mut self, sequence: u16) {
         if let Some(front) = self.send_buffer.front() {
@@ -158,8 +626,35 @@ impl Channel {
         self.send_sequence = 0;
         self.receive_sequence = 0;
         self.send_buffer.clear();
+        self.scheduled.clear();
         self.receive_buffer.clear();
         self.ordered_buffer.clear();
+        self.pending_keys.clear();
+        self.latest_sent.clear();
+        self.latest_received.clear();
+        self.reset_duplicate_tracking();
+        self.reset_baselines();
+    }
+    
+    /// Forgets `#[delta_string]` baselines so both ends restart from whole values
+    pub(crate) fn reset_baselines(&mut self) {
+        self.send_baselines.clear();
+        self.receive_baselines.clear();
+    }
+    
+    /// Baselines for `#[delta_string]` fields of messages sent on this channel
+    pub fn send_baselines(&mut self) -> &mut StringBaselines {
+        &mut self.send_baselines
+    }
+    
+    /// Baselines for `#[delta_string]` fields of messages received on this channel
+    pub fn receive_baselines(&mut self) -> &mut StringBaselines {
+        &mut self.receive_baselines
+    }
+    
+    /// Returns the channel configuration
+    pub fn config(&self) -> &ChannelConfig {
+        &self.config
     }
     
     /// Returns whether this channel uses reliable delivery
@@ -167,6 +662,30 @@ impl Channel {
         self.config.reliability == Reliability::Reliable
     }
     
+    /// Bytes of message data waiting to be sent or received
+    pub fn memory_usage(&self) -> usize {
+        self.pending_bytes()
+            + self.scheduled.iter().map(|message| message.data.len()).sum::<usize>()
+            + self.receive_buffer.values().map(|message| message.data.len()).sum::<usize>()
+            + self.ordered_buffer.iter().map(Vec::len).sum::<usize>()
+    }
+    
+    /// Zeroes the counters in [`ChannelStats`]; buffer sizes and streaks are state, not counts.
+    pub(crate) fn reset_stats(&mut self) {
+        self.messages_sent = 0;
+        self.messages_received = 0;
+        self.bytes_sent = 0;
+        self.bytes_received = 0;
+        self.duplicates_dropped = 0;
+        self.messages_superseded = 0;
+        self.receive_dropped = 0;
+        self.receive_backpressured = 0;
+        self.starved_updates = 0;
+        self.compression_skipped = 0;
+        self.compression_ineffective = 0;
+        self.compression_saved = 0;
+    }
+    
     /// Returns channel statistics
     pub fn stats(&self) -> ChannelStats {
         ChannelStats {
@@ -177,10 +696,32 @@ impl Channel {
             bytes_received: self.bytes_received,
             send_buffer_size: self.send_buffer.len(),
             receive_buffer_size: self.receive_buffer.len(),
+            duplicates_dropped: self.duplicates_dropped,
+            messages_superseded: self.messages_superseded,
+            pending_keys: self.pending_keys.len(),
+            scheduled_messages: self.scheduled.len(),
+            received_pending: self.ordered_buffer.len(),
+            receive_dropped: self.receive_dropped,
+            receive_backpressured: self.receive_backpressured,
+            starved_updates: self.starved_updates,
+            starved_streak: self.starved_streak,
+            compression_skipped: self.compression_skipped,
+            compression_ineffective: self.compression_ineffective,
+            compression_saved: self.compression_saved,
+            memory_usage: self.memory_usage(),
         }
     }
 }
 
+/// A message taken from the send buffer for assembly into a packet.
+#[derive(Debug)]
+pub(crate) struct OutgoingMessage {
+    pub(crate) sequence: u16,
+    pub(crate) data: Vec<u8>,
+    pub(crate) expires: Option<Instant>,
+    pub(crate) key: Option<u32>,
+}
+
 #[derive(Debug, Clone)]
 pub struct ChannelStats {
     pub id: u8,
@@ -190,4 +731,199 @@ pub struct ChannelStats {
     pub bytes_received: u64,
     pub send_buffer_size: usize,
     pub receive_buffer_size: usize,
-}
\ No newline at end of file
+    /// Messages discarded because they had already been delivered
+    pub duplicates_dropped: u64,
+    /// Older versions of keyed state dropped unsent, left out of resends, or received after a newer one
+    pub messages_superseded: u64,
+    /// Keys with a message queued or awaiting its ack
+    pub pending_keys: usize,
+    /// Messages from `send_at` waiting for their time
+    pub scheduled_messages: usize,
+    /// Received messages the application has not read yet
+    pub received_pending: usize,
+    /// Oldest unread messages dropped by an unreliable channel's `receive_limit`
+    pub receive_dropped: u64,
+    /// Packets left unacked because a reliable channel was at its `receive_limit`
+    pub receive_backpressured: u64,
+    /// Packet assemblies that left messages waiting here without sending any
+    pub starved_updates: u64,
+    /// Consecutive such assemblies, up to now; a steadily growing streak means starvation
+    pub starved_streak: u32,
+    /// Payloads sent without running the codec: under `compression_threshold`, or too random
+    pub compression_skipped: u64,
+    /// Payloads the codec ran on without shrinking them
+    pub compression_ineffective: u64,
+    /// Bytes compression saved across all payloads, after its one byte of framing; negative
+    /// when it cost more than it saved
+    pub compression_saved: i64,
+    /// Bytes of message data buffered in this channel
+    pub memory_usage: usize,
+}
+
+/// Bandwidth budget shared by the channels of one group.
+#[derive(Debug)]
+pub(crate) struct ChannelGroup {
+    config: ChannelGroupConfig,
+    tokens: f32,
+    last_refill: Instant,
+}
+
+impl ChannelGroup {
+    pub(crate) fn new(config: ChannelGroupConfig, now: Instant) -> Self {
+        Self {
+            config,
+            tokens: config.burst_bytes as f32,
+            last_refill: now,
+        }
+    }
+    
+    pub(crate) fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f32();
+        self.tokens = (self.tokens + elapsed * self.config.bytes_per_second).min(self.config.burst_bytes as f32);
+        self.last_refill = now;
+    }
+    
+    /// Whether any budget is left; a message may then overdraw it.
+    pub(crate) fn has_budget(&self) -> bool {
+        self.tokens > 0.0
+    }
+    
+    pub(crate) fn consume(&mut self, bytes: usize) {
+        self.tokens -= bytes as f32;
+    }
+    
+    /// Changes the rate from now on; budget already saved is kept up to the new burst.
+    pub(crate) fn set_config(&mut self, config: ChannelGroupConfig) {
+        self.config = config;
+        self.tokens = self.tokens.min(config.burst_bytes as f32);
+    }
+    
+    pub(crate) fn bytes_per_second(&self) -> f32 {
+        self.config.bytes_per_second
+    }
+}
+
+/// Items of one batch message, decoded as they are iterated.
+///
+/// Iteration stops after the first item that fails to decode, which yields the error.
+pub struct BatchItems<T> {
+    buffer: BitBuffer,
+    remaining: u32,
+    _items: PhantomData<fn() -> T>,
+}
+
+impl<T: BitDeserialize> BatchItems<T> {
+    /// Reads a batch message's header; `None` if it is too short to have one.
+    pub fn decode(message: Vec<u8>) -> Option<Self> {
+        let remaining = u32::from_le_bytes(message.get(..BATCH_HEADER_BYTES)?.try_into().ok()?);
+        Some(Self {
+            buffer: BitBuffer::from_bytes(message[BATCH_HEADER_BYTES..].to_vec()),
+            remaining,
+            _items: PhantomData,
+        })
+    }
+    
+    /// Items not yet decoded.
+    pub fn remaining(&self) -> usize {
+        self.remaining as usize
+    }
+}
+
+impl<T: BitDeserialize> Iterator for BatchItems<T> {
+    type Item = io::Result<T>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let item = T::bit_deserialize(&mut self.buffer);
+        if item.is_err() {
+            self.remaining = 0;
+        }
+        Some(item)
+    }
+    
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining as usize))
+    }
+}
+
+impl<T> std::fmt::Debug for BatchItems<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BatchItems").field("remaining", &self.remaining).finish()
+    }
+}
+
+/// Packs items into as few batch messages of at most `max_size` bytes as possible.
+fn encode_batches<I>(items: I, max_size: usize) -> Result<Vec<Vec<u8>>, ChannelError>
+where
+    I: IntoIterator,
+    I::Item: BitSerialize,
+{
+    let limit_bits = max_size.saturating_sub(BATCH_HEADER_BYTES) * 8;
+    let finish = |buffer: BitBuffer, count: u32| -> Result<Vec<u8>, ChannelError> {
+        let mut batch = count.to_le_bytes().to_vec();
+        batch.extend(buffer.into_bytes(true).map_err(|_| ChannelError::InvalidBatch)?);
+        Ok(batch)
+    };
+    
+    let mut batches = Vec::new();
+    let mut buffer = BitBuffer::new();
+    let mut count = 0u32;
+    for item in items {
+        let mut size = BitCounter::new();
+        item.bit_serialize(&mut size).map_err(|_| ChannelError::InvalidBatch)?;
+        if size.bits() > limit_bits {
+            return Err(ChannelError::MessageTooLarge);
+        }
+        if BitWrite::bit_pos(&buffer) + size.bits() > limit_bits {
+            batches.push(finish(std::mem::replace(&mut buffer, BitBuffer::new()), count)?);
+            count = 0;
+        }
+        item.bit_serialize(&mut buffer).map_err(|_| ChannelError::InvalidBatch)?;
+        count += 1;
+    }
+    if count > 0 {
+        batches.push(finish(buffer, count)?);
+    }
+    Ok(batches)
+}
+
+/// Key and version of a `latest_only` channel message.
+fn keyed_header(message: &[u8]) -> Option<(u32, u32)> {
+    let key = u32::from_le_bytes(message.get(..4)?.try_into().ok()?);
+    let version = u32::from_le_bytes(message.get(4..KEYED_HEADER_BYTES)?.try_into().ok()?);
+    Some((key, version))
+}
+
+/// Appends a sequenced, length-prefixed message to a packet payload.
+pub(crate) fn write_message(payload: &mut Vec<u8>, sequence: u16, message: &[u8]) {
+    payload.extend_from_slice(&sequence.to_le_bytes());
+    payload.extend_from_slice(&(message.len() as u16).to_le_bytes());
+    payload.extend_from_slice(message);
+}
+
+/// Number of messages framed in a payload, without copying them out.
+pub(crate) fn count_messages(mut payload: &[u8]) -> usize {
+    let mut count = 0;
+    while let Some(len) = payload.get(2..MESSAGE_HEADER_BYTES) {
+        let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+        count += 1;
+        payload = payload.get(MESSAGE_HEADER_BYTES + len..).unwrap_or_default();
+    }
+    count
+}
+
+/// Splits a packet payload back into (sequence, message) pairs; `None` if the framing is malformed.
+pub(crate) fn read_messages(mut payload: &[u8]) -> Option<Vec<(u16, Vec<u8>)>> {
+    let mut messages = Vec::new();
+    while !payload.is_empty() {
+        let sequence = u16::from_le_bytes(payload.get(..2)?.try_into().ok()?);
+        let len = u16::from_le_bytes(payload.get(2..MESSAGE_HEADER_BYTES)?.try_into().ok()?) as usize;
+        let message = payload.get(MESSAGE_HEADER_BYTES..MESSAGE_HEADER_BYTES + len)?;
+        messages.push((sequence, message.to_vec()));
+        payload = &payload[MESSAGE_HEADER_BYTES + len..];
+    }
+    Some(messages)
+}
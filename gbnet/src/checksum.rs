@@ -0,0 +1,208 @@
+// checksum.rs - Integrity check layer with pluggable algorithms
+//
+// UDP's own checksum is 16 bits and optional over IPv4, so games that care add their own.
+// `ChecksumLayer` appends the digest of each datagram on send and drops datagrams whose
+// digest does not match on receive. Install it as the last middleware so it covers the
+// bytes actually on the wire.
+use std::io;
+use std::net::SocketAddr;
+
+use crate::middleware::PacketMiddleware;
+
+/// A digest algorithm for [`ChecksumLayer`].
+pub trait Checksum: Send + Sync {
+    /// Bytes of digest appended to each datagram, at most 8
+    fn size(&self) -> usize;
+    fn compute(&self, data: &[u8]) -> u64;
+}
+
+/// CRC-32C (Castagnoli), using the SSE4.2 instruction when the CPU has it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Crc32c;
+
+/// xxHash64 with seed 0: twice the digest of CRC-32C, fast on any CPU.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct XxHash64;
+
+/// No digest at all, for transports that are already integrity checked.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoChecksum;
+
+impl Checksum for Crc32c {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn compute(&self, data: &[u8]) -> u64 {
+        crc32c(data) as u64
+    }
+}
+
+impl Checksum for XxHash64 {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn compute(&self, data: &[u8]) -> u64 {
+        xxhash64(data, 0)
+    }
+}
+
+impl Checksum for NoChecksum {
+    fn size(&self) -> usize {
+        0
+    }
+
+    fn compute(&self, _data: &[u8]) -> u64 {
+        0
+    }
+}
+
+/// Middleware appending a [`Checksum`] digest to every datagram.
+pub struct ChecksumLayer<C: Checksum> {
+    checksum: C,
+}
+
+impl<C: Checksum> ChecksumLayer<C> {
+    pub fn new(checksum: C) -> Self {
+        Self { checksum }
+    }
+}
+
+impl<C: Checksum> PacketMiddleware for ChecksumLayer<C> {
+    fn on_send(&self, _remote_addr: SocketAddr, data: &mut Vec<u8>) -> io::Result<()> {
+        let size = self.checksum.size().min(8);
+        let digest = self.checksum.compute(data).to_le_bytes();
+        data.extend_from_slice(&digest[..size]);
+        Ok(())
+    }
+
+    fn on_receive(&self, _remote_addr: SocketAddr, data: &mut Vec<u8>) -> io::Result<()> {
+        let size = self.checksum.size().min(8);
+        let body_len = data.len().checked_sub(size).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Datagram shorter than its checksum")
+        })?;
+        let digest = self.checksum.compute(&data[..body_len]).to_le_bytes();
+        if data[body_len..] != digest[..size] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Checksum mismatch"));
+        }
+        data.truncate(body_len);
+        Ok(())
+    }
+}
+
+// Reflected Castagnoli polynomial
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82F6_3B78 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32c(data: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    if std::arch::is_x86_feature_detected!("sse4.2") {
+        // SAFETY: the CPU supports SSE4.2, checked just above
+        return unsafe { crc32c_sse42(data) };
+    }
+    crc32c_software(data)
+}
+
+pub(crate) fn crc32c_software(data: &[u8]) -> u32 {
+    let crc = data.iter().fold(!0u32, |crc, &byte| {
+        CRC32C_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn crc32c_sse42(data: &[u8]) -> u32 {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut crc = !0u64;
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        crc = _mm_crc32_u64(crc, u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+    let mut crc = crc as u32;
+    for &byte in chunks.remainder() {
+        crc = _mm_crc32_u8(crc, byte);
+    }
+    !crc
+}
+
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+fn xxh64_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2)).rotate_left(31).wrapping_mul(PRIME64_1)
+}
+
+fn xxh64_merge(acc: u64, value: u64) -> u64 {
+    (acc ^ xxh64_round(0, value)).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+pub fn xxhash64(data: &[u8], seed: u64) -> u64 {
+    let mut rest = data;
+    let mut hash = if data.len() >= 32 {
+        let mut v = [
+            seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+            seed.wrapping_add(PRIME64_2),
+            seed,
+            seed.wrapping_sub(PRIME64_1),
+        ];
+        while rest.len() >= 32 {
+            for (lane, acc) in v.iter_mut().enumerate() {
+                *acc = xxh64_round(*acc, read_u64(&rest[lane * 8..]));
+            }
+            rest = &rest[32..];
+        }
+        let hash = v[0].rotate_left(1)
+            .wrapping_add(v[1].rotate_left(7))
+            .wrapping_add(v[2].rotate_left(12))
+            .wrapping_add(v[3].rotate_left(18));
+        v.iter().fold(hash, |hash, &lane| xxh64_merge(hash, lane))
+    } else {
+        seed.wrapping_add(PRIME64_5)
+    };
+    hash = hash.wrapping_add(data.len() as u64);
+
+    while rest.len() >= 8 {
+        hash ^= xxh64_round(0, read_u64(rest));
+        hash = hash.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        let word = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+        hash ^= word.wrapping_mul(PRIME64_1);
+        hash = hash.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
+        rest = &rest[4..];
+    }
+    for &byte in rest {
+        hash ^= (byte as u64).wrapping_mul(PRIME64_5);
+        hash = hash.rotate_left(11).wrapping_mul(PRIME64_1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME64_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME64_3);
+    hash ^ (hash >> 32)
+}
@@ -0,0 +1,227 @@
+// compat.rs - Protocol freeze harness: fixtures from released versions decoded by current code
+//
+// Where the conformance suite describes the wire format of *this* build, compat fixtures are
+// frozen at release time (compat/<version>.json) and never regenerated. Every later build must
+// still decode them to the same values and re-encode them to the same bytes, so an accidental
+// change to PacketHeader, the handshake or the derive output fails CI instead of shipping.
+//
+// Packet fixtures are checked against their recorded description. Derive fixtures are checked
+// against a sample value registered under the same name, so applications can freeze their own
+// message types with the same harness.
+use std::fmt;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    config::ConnectionRole,
+    debug_packets,
+    conformance::{describe_packet, encode_description, from_hex, golden_packets, to_hex, ConformanceFailure, ConformanceReport},
+    packet::{Packet, PacketHeader, PacketType},
+    serialize::{bit_io::BitBuffer, BitDeserialize, BitSerialize},
+};
+
+/// Version of the fixture file format.
+pub const FIXTURE_FORMAT: u64 = 1;
+
+const RELEASED_FIXTURES: &[(&str, &str)] = &[
+    ("0.1.0", include_str!("../compat/0.1.0.json")),
+];
+
+#[derive(Debug)]
+pub enum CompatError {
+    InvalidJson(String),
+    InvalidFixtures(String),
+    UnsupportedFormat(u64),
+}
+
+impl fmt::Display for CompatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompatError::InvalidJson(err) => write!(f, "invalid JSON: {}", err),
+            CompatError::InvalidFixtures(err) => write!(f, "invalid fixtures: {}", err),
+            CompatError::UnsupportedFormat(format) => write!(f, "unsupported fixture format {}", format),
+        }
+    }
+}
+
+impl std::error::Error for CompatError {}
+
+/// Fixture files shipped with the crate, as (crate version, JSON), oldest first.
+pub fn released_fixtures() -> &'static [(&'static str, &'static str)] {
+    RELEASED_FIXTURES
+}
+
+type SampleCheck = Box<dyn Fn(&[u8]) -> Result<(), String>>;
+
+struct DeriveSample {
+    name: String,
+    encoded: Result<Vec<u8>, String>,
+    check: SampleCheck,
+}
+
+/// Builds the current fixtures and checks released ones against this build.
+///
+/// Packet and handshake fixtures are built in; derive fixtures come from
+/// [`FreezeHarness::derive_sample`].
+#[derive(Default)]
+pub struct FreezeHarness {
+    samples: Vec<DeriveSample>,
+}
+
+impl FreezeHarness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a value whose serialized form is frozen under `name`.
+    ///
+    /// The value must be built from constants, since later builds compare against it.
+    pub fn derive_sample<T>(mut self, name: &str, value: T) -> Self
+    where
+        T: BitSerialize + BitDeserialize + PartialEq + fmt::Debug + 'static,
+    {
+        let encoded = encode_bits(&value).map_err(|err| err.to_string());
+        let check = move |bytes: &[u8]| {
+            let _untagged = debug_packets::Scope::enter();
+            let decoded = T::bit_deserialize(&mut BitBuffer::from_bytes(bytes.to_vec()))
+                .map_err(|err| format!("decode failed: {}", err))?;
+            if decoded != value {
+                return Err(format!("decoded {:?} but expected {:?}", decoded, value));
+            }
+            Ok(())
+        };
+        self.samples.push(DeriveSample { name: name.to_string(), encoded, check: Box::new(check) });
+        self
+    }
+
+    /// The fixture file to freeze for this build, e.g. as `compat/<version>.json` at release.
+    pub fn snapshot(&self) -> String {
+        let mut fixtures = Vec::new();
+        // Datagrams this build rejects may be accepted by later ones, so only decodable ones freeze
+        for (name, bytes) in golden_packets() {
+            if let Some(decoded) = describe_packet(&bytes) {
+                fixtures.push(json!({ "name": name, "group": "packet", "bytes": to_hex(&bytes), "decoded": decoded }));
+            }
+        }
+        for (name, bytes, role) in handshake_packets() {
+            fixtures.push(json!({
+                "name": name,
+                "group": "handshake",
+                "bytes": to_hex(&bytes),
+                "decoded": describe_packet(&bytes),
+                "role": format!("{:?}", role),
+            }));
+        }
+        for sample in &self.samples {
+            if let Ok(bytes) = &sample.encoded {
+                fixtures.push(json!({ "name": sample.name, "group": "derive", "bytes": to_hex(bytes) }));
+            }
+        }
+
+        let mut file = Map::new();
+        file.insert("format".to_string(), json!(FIXTURE_FORMAT));
+        file.insert("crate_version".to_string(), json!(env!("CARGO_PKG_VERSION")));
+        file.insert("fixtures".to_string(), Value::Array(fixtures));
+        let mut json = serde_json::to_string_pretty(&Value::Object(file)).expect("fixtures are valid JSON");
+        json.push('\n');
+        json
+    }
+
+    /// Decodes every fixture of a released file with this build.
+    pub fn check(&self, json: &str) -> Result<ConformanceReport, CompatError> {
+        let file: Value = serde_json::from_str(json).map_err(|err| CompatError::InvalidJson(err.to_string()))?;
+        let format = file["format"].as_u64().ok_or_else(|| invalid("missing format"))?;
+        if format != FIXTURE_FORMAT {
+            return Err(CompatError::UnsupportedFormat(format));
+        }
+        let fixtures = file["fixtures"].as_array().ok_or_else(|| invalid("missing fixtures"))?;
+
+        let mut report = ConformanceReport::default();
+        for fixture in fixtures {
+            let name = fixture["name"].as_str().ok_or_else(|| invalid("fixture without name"))?.to_string();
+            let bytes = fixture["bytes"].as_str().and_then(from_hex).ok_or_else(|| invalid(&format!("{}: bad bytes", name)))?;
+            let outcome = match fixture["group"].as_str() {
+                Some("packet") => check_packet(&bytes, &fixture["decoded"]),
+                Some("handshake") => check_packet(&bytes, &fixture["decoded"]).and_then(|()| check_role(&bytes, &fixture["role"])),
+                Some("derive") => self.check_sample(&name, &bytes),
+                _ => return Err(invalid(&format!("{}: unknown group", name))),
+            };
+            match outcome {
+                Ok(()) => report.passed.push(name),
+                Err(reason) => report.failures.push(ConformanceFailure { case: name, reason }),
+            }
+        }
+        Ok(report)
+    }
+
+    fn check_sample(&self, name: &str, bytes: &[u8]) -> Result<(), String> {
+        let sample = self.samples.iter().find(|sample| sample.name == name)
+            .ok_or_else(|| "no derive sample registered under this name".to_string())?;
+        (sample.check)(bytes)?;
+        let encoded = sample.encoded.as_ref().map_err(|err| format!("encode failed: {}", err))?;
+        if encoded != bytes {
+            return Err(format!("encoded {} but expected {}", to_hex(encoded), to_hex(bytes)));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FreezeHarness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.samples.iter().map(|sample| sample.name.as_str()).collect();
+        f.debug_struct("FreezeHarness").field("samples", &names).finish()
+    }
+}
+
+fn check_packet(bytes: &[u8], expected: &Value) -> Result<(), String> {
+    let decoded = describe_packet(bytes).ok_or_else(|| "no longer decodes".to_string())?;
+    if &decoded != expected {
+        return Err(format!("decoded {} but expected {}", decoded, expected));
+    }
+    let encoded = encode_description(expected).map_err(|err| err.to_string())?;
+    if encoded != bytes {
+        return Err(format!("encoded {} but expected {}", to_hex(&encoded), to_hex(bytes)));
+    }
+    Ok(())
+}
+
+// The challenge response carries the requested role in its first payload byte
+fn check_role(bytes: &[u8], expected: &Value) -> Result<(), String> {
+    let packet = Packet::deserialize(bytes).map_err(|err| err.to_string())?;
+    let role = match packet.payload.first() {
+        Some(&byte) => ConnectionRole::from_byte(byte).ok_or_else(|| format!("unknown role byte {}", byte))?,
+        None => ConnectionRole::Player,
+    };
+    let role = format!("{:?}", role);
+    if expected.as_str() != Some(role.as_str()) {
+        return Err(format!("decoded role {} but expected {}", role, expected));
+    }
+    Ok(())
+}
+
+fn handshake_packets() -> Vec<(&'static str, Vec<u8>, ConnectionRole)> {
+    let header = PacketHeader { protocol_id: 0x12345678, sequence: 0, ack: 0, ack_bits: 0 };
+    let response = |payload: Vec<u8>| {
+        Packet::new(header.clone(), PacketType::ConnectionResponse { client_salt: 0x0F1E_2D3C_4B5A_6978 })
+            .with_payload(payload)
+            .serialize()
+            .expect("handshake fixtures serialize")
+    };
+    vec![
+        ("handshake_response_legacy", response(Vec::new()), ConnectionRole::Player),
+        ("handshake_response_player", response(vec![ConnectionRole::Player.to_byte()]), ConnectionRole::Player),
+        ("handshake_response_observer", response(vec![ConnectionRole::Observer.to_byte()]), ConnectionRole::Observer),
+    ]
+}
+
+// Fixtures freeze the release wire format, so samples are nested in a scope that keeps the
+// debug-packets tag off them
+fn encode_bits<T: BitSerialize>(value: &T) -> std::io::Result<Vec<u8>> {
+    let _untagged = debug_packets::Scope::enter();
+    let mut buffer = BitBuffer::new();
+    value.bit_serialize(&mut buffer)?;
+    buffer.into_bytes(true)
+}
+
+fn invalid(message: &str) -> CompatError {
+    CompatError::InvalidFixtures(message.to_string())
+}
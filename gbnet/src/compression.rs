@@ -0,0 +1,295 @@
+// compression.rs - Per-channel payload compression negotiated during the handshake
+//
+// Codecs are registered on `NetworkConfig::compression` under ids both ends agree on, one id
+// per codec and dictionary pairing. The client lists the ids it has after the role byte of
+// its challenge response; the server picks a codec for each channel from its
+// `ChannelConfig::compression` among the ids both sides have, and announces the picks in a
+// header extension on the accept. A client that offers nothing (an older build) and a server
+// that never answers (also an older build) leave every channel uncompressed, so mixed
+// versions still connect.
+//
+// Payloads are compressed whole after messages are packed and decompressed before they are
+// unpacked, so reliability, resends and acks only ever see plain payloads.
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use crate::config::ChannelConfig;
+
+/// Id announcing that a channel is not compressed.
+pub const NO_CODEC: u8 = 0;
+
+/// A payload compression algorithm for [`CodecRegistry`].
+pub trait Codec: Send + Sync {
+    fn compress(&self, input: &[u8]) -> Vec<u8>;
+    /// Fails on malformed input or if the output would pass `max_len` bytes.
+    fn decompress(&self, input: &[u8], max_len: usize) -> io::Result<Vec<u8>>;
+}
+
+/// Which codec a channel asks for; the server's setting decides for both directions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// The first codec in registration order that the peer also has
+    Preferred,
+    /// This codec if the peer has it, otherwise none
+    Codec(u8),
+}
+
+/// Codecs this end supports, by id, in order of preference.
+#[derive(Clone, Default)]
+pub struct CodecRegistry {
+    codecs: Vec<(u8, Arc<dyn Codec>)>,
+}
+
+impl CodecRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a codec; ids must be nonzero and mean the same codec and dictionary on every peer.
+    pub fn with<C: Codec + 'static>(mut self, id: u8, codec: C) -> Self {
+        assert_ne!(id, NO_CODEC, "codec id 0 means no compression");
+        self.codecs.retain(|(existing, _)| *existing != id);
+        self.codecs.push((id, Arc::new(codec)));
+        self
+    }
+
+    pub fn ids(&self) -> Vec<u8> {
+        self.codecs.iter().map(|(id, _)| *id).collect()
+    }
+
+    pub fn get(&self, id: u8) -> Option<&dyn Codec> {
+        self.codecs.iter().find(|(existing, _)| *existing == id).map(|(_, codec)| codec.as_ref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codecs.is_empty()
+    }
+
+    /// The codec for each channel given the ids the peer offered, [`NO_CODEC`] where none fits.
+    pub(crate) fn select<'a>(&self, channels: impl Iterator<Item = &'a ChannelConfig>, offered: &[u8]) -> Vec<u8> {
+        let shared = |id: &u8| offered.contains(id) && self.get(*id).is_some();
+        channels
+            .map(|config| match config.compression {
+                Compression::None => NO_CODEC,
+                Compression::Preferred => self.ids().into_iter().find(shared).unwrap_or(NO_CODEC),
+                Compression::Codec(id) => if shared(&id) { id } else { NO_CODEC },
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for CodecRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CodecRegistry").field("ids", &self.ids()).finish()
+    }
+}
+
+// Payloads that do not shrink are sent stored, behind a one byte mode
+const STORED: u8 = 0;
+const COMPRESSED: u8 = 1;
+
+// Order-0 entropy in bits per byte above which a payload is taken for random, compressed or
+// encrypted data and stored without running the codec
+const INCOMPRESSIBLE_ENTROPY: f64 = 7.5;
+
+/// What happened to one payload on a compressed channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CompressOutcome {
+    /// Below the channel's threshold or too random; the codec did not run
+    Skipped,
+    /// The codec ran but did not shrink it
+    Stored,
+    Compressed,
+}
+
+pub(crate) fn compress_payload(codec: &dyn Codec, payload: &[u8], threshold: usize) -> (Vec<u8>, CompressOutcome) {
+    let skip = payload.len() < threshold || looks_incompressible(payload);
+    let compressed = if skip { Vec::new() } else { codec.compress(payload) };
+    let (mode, body, outcome) = match skip {
+        true => (STORED, payload, CompressOutcome::Skipped),
+        false if compressed.len() < payload.len() => (COMPRESSED, compressed.as_slice(), CompressOutcome::Compressed),
+        false => (STORED, payload, CompressOutcome::Stored),
+    };
+    let mut framed = Vec::with_capacity(1 + body.len());
+    framed.push(mode);
+    framed.extend_from_slice(body);
+    (framed, outcome)
+}
+
+fn looks_incompressible(payload: &[u8]) -> bool {
+    // Short payloads cannot reach the bound, so skip the histogram
+    if payload.len() < 1 << (INCOMPRESSIBLE_ENTROPY as u32) {
+        return false;
+    }
+    let mut counts = [0u32; 256];
+    for &byte in payload {
+        counts[byte as usize] += 1;
+    }
+    let len = payload.len() as f64;
+    let entropy: f64 = counts.iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum();
+    entropy > INCOMPRESSIBLE_ENTROPY
+}
+
+pub(crate) fn decompress_payload(codec: &dyn Codec, framed: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+    match framed.split_first() {
+        Some((&STORED, body)) if body.len() <= max_len => Ok(body.to_vec()),
+        Some((&COMPRESSED, body)) => codec.decompress(body, max_len),
+        _ => Err(invalid("Bad compressed payload")),
+    }
+}
+
+const MIN_MATCH: usize = 4;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+/// LZ77 in the LZ4 block layout, optionally primed with a preset dictionary.
+///
+/// Small game packets rarely repeat within themselves; a dictionary of typical payloads
+/// lets matches reach into it instead. Peers must register the same dictionary under the same id.
+#[derive(Debug, Clone, Default)]
+pub struct Lz77 {
+    dictionary: Arc<[u8]>,
+}
+
+impl Lz77 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses the last 64 KiB of `dictionary` as history before every payload.
+    pub fn with_dictionary(dictionary: &[u8]) -> Self {
+        let start = dictionary.len().saturating_sub(MAX_OFFSET);
+        Self { dictionary: dictionary[start..].into() }
+    }
+}
+
+impl Codec for Lz77 {
+    fn compress(&self, input: &[u8]) -> Vec<u8> {
+        let mut window = Vec::with_capacity(self.dictionary.len() + input.len());
+        window.extend_from_slice(&self.dictionary);
+        window.extend_from_slice(input);
+
+        let mut table = vec![usize::MAX; 1 << HASH_BITS];
+        for pos in 0..self.dictionary.len().saturating_sub(MIN_MATCH - 1) {
+            table[hash(&window[pos..])] = pos;
+        }
+
+        let mut out = Vec::with_capacity(input.len() / 2 + 8);
+        let mut anchor = self.dictionary.len();
+        let mut pos = anchor;
+        while pos + MIN_MATCH <= window.len() {
+            let slot = &mut table[hash(&window[pos..])];
+            let candidate = std::mem::replace(slot, pos);
+            if candidate != usize::MAX && pos - candidate <= MAX_OFFSET && window[candidate..candidate + MIN_MATCH] == window[pos..pos + MIN_MATCH] {
+                let mut len = MIN_MATCH;
+                while pos + len < window.len() && window[candidate + len] == window[pos + len] {
+                    len += 1;
+                }
+                write_sequence(&mut out, &window[anchor..pos], Some((pos - candidate, len)));
+                pos += len;
+                anchor = pos;
+            } else {
+                pos += 1;
+            }
+        }
+        write_sequence(&mut out, &window[anchor..], None);
+        out
+    }
+
+    fn decompress(&self, input: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+        let base = self.dictionary.len();
+        let mut out = self.dictionary.to_vec();
+        let mut i = 0;
+        loop {
+            let token = *input.get(i).ok_or_else(|| invalid("Truncated sequence"))?;
+            i += 1;
+
+            let mut literals = (token >> 4) as usize;
+            if literals == 15 {
+                literals += read_length(input, &mut i)?;
+            }
+            let bytes = input.get(i..i + literals).ok_or_else(|| invalid("Truncated literals"))?;
+            if out.len() - base + literals > max_len {
+                return Err(invalid("Decompressed payload too large"));
+            }
+            out.extend_from_slice(bytes);
+            i += literals;
+
+            // The last sequence is literals only
+            if i == input.len() {
+                break;
+            }
+            let offset = input.get(i..i + 2).ok_or_else(|| invalid("Truncated offset"))?;
+            let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+            i += 2;
+            if offset == 0 || offset > out.len() {
+                return Err(invalid("Match offset out of range"));
+            }
+            let mut len = (token & 0x0F) as usize + MIN_MATCH;
+            if token & 0x0F == 15 {
+                len += read_length(input, &mut i)?;
+            }
+            if out.len() - base + len > max_len {
+                return Err(invalid("Decompressed payload too large"));
+            }
+            // Byte by byte, since a match may overlap the bytes it produces
+            for _ in 0..len {
+                out.push(out[out.len() - offset]);
+            }
+        }
+        Ok(out.split_off(base))
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (word.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let extra = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((literals.len().min(15) as u8) << 4) | extra.min(15) as u8);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if extra >= 15 {
+            write_length(out, extra - 15);
+        }
+    }
+}
+
+fn write_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn read_length(input: &[u8], i: &mut usize) -> io::Result<usize> {
+    let mut len = 0;
+    loop {
+        let byte = *input.get(*i).ok_or_else(|| invalid("Truncated length"))?;
+        *i += 1;
+        len += byte as usize;
+        if byte != 255 {
+            return Ok(len);
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
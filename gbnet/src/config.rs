@@ -1,23 +1,81 @@
 // config.rs - Network configuration constants and structures
 use std::time::Duration;
 
+use crate::profiler::WireProfiler;
+use crate::congestion::CongestionControl;
+use crate::telemetry::{PacketTelemetry, TelemetrySampler};
+use crate::trace::TraceRecorder;
+use crate::middleware::MiddlewareChain;
+use crate::compression::{CodecRegistry, Compression};
+use crate::timestamp::TimestampResolution;
+use crate::packet::capability;
+use crate::rng::{stream, DeterministicRng};
+use crate::simulator::{NetworkSimulator, SimulatorConfig};
+use crate::instant::Clock;
+use crate::socket::SendErrorClass;
+
 #[derive(Debug, Clone)]
 pub struct NetworkConfig {
     // Protocol
     pub protocol_id: u32,
     pub max_clients: usize,
+    /// Connection requests a full server keeps waiting for a slot instead of denying;
+    /// 0 denies them with `SERVER_FULL`
+    pub join_queue_size: usize,
+    /// How often queued clients are sent their position
+    pub join_queue_update_interval: Duration,
+    /// Answer unconnected ping probes from matchmakers; see [`crate::ping`]
+    pub respond_to_pings: bool,
+    /// Role a client asks for when connecting; ignored by servers
+    pub role: ConnectionRole,
     
     // Timing
     pub connection_timeout: Duration,
     pub keepalive_interval: Duration,
+    /// How long a server holds a handshake that has not completed
     pub connection_request_timeout: Duration,
+    /// Connection requests resent without a reply before `connect` gives up
     pub connection_request_max_retries: u32,
+    /// Wait for a reply before the first resend of a connection request
+    pub connection_request_interval: Duration,
+    /// Multiplier applied to the wait after every resend
+    pub connection_request_backoff: f32,
+    /// Longest wait between resends
+    pub connection_request_max_interval: Duration,
+    /// Random spread of each wait as a fraction of it (0 to 1), so clients cut off at the
+    /// same moment do not all retry in lockstep
+    pub connection_request_jitter: f32,
+    /// Head start each address gets before `HostConnector` tries the next one as well
+    pub connect_attempt_delay: Duration,
+    /// ICMP unreachable reports in a row, with nothing received in between, after which the
+    /// peer counts as gone instead of waiting out `connection_timeout`; `None` only raises
+    /// `ConnectionEvent::PeerUnreachable`. Reports can be forged, hence more than one
+    pub peer_unreachable_limit: Option<u32>,
+    /// How long a peer that paused with `Connection::pause` is kept before it counts as gone
+    /// and, on a server, its slot is reclaimed; replaces `connection_timeout` while it is paused
+    pub pause_grace_period: Duration,
     
     // Packet settings
     pub mtu: usize,
+    /// Most message bytes in one fragment. Messages that do not fit a packet under `mtu`
+    /// are split into fragments and reassembled on arrival
     pub fragment_threshold: usize,
+    /// How long a fragmented message waits for its missing pieces before it is dropped
     pub fragment_timeout: Duration,
+    /// Most fragments per message; caps each channel's `max_message_size`
     pub max_fragments: usize,
+    /// Consecutive join snapshot packets sent before messages waiting on other channels get
+    /// a packet in, whatever their priority; `None` sends the transfer in its channel's slot
+    pub fragment_burst: Option<usize>,
+    /// Payload packets on unreliable channels older than this are dropped before their
+    /// messages are delivered, so a backlog read after a stall does not replay stale state.
+    /// Age is measured from send stamps (8 header bytes per payload packet) when the peer
+    /// also sets it and parses header extensions, otherwise from receive time. `None` keeps all
+    pub max_packet_age: Option<Duration>,
+    /// Upper bound for path MTU discovery: once connected, padded probes search between
+    /// `mtu` and this for the largest packet the path delivers, and the connection builds
+    /// packets up to that size. Set it on both ends. `None` keeps `mtu`
+    pub max_mtu: Option<usize>,
     
     // Reliability
     pub packet_buffer_size: usize,
@@ -25,15 +83,131 @@ pub struct NetworkConfig {
     pub max_sequence_distance: u16,
     pub reliable_retry_time: Duration,
     pub max_reliable_retries: u32,
+    /// Packets missing between two received ones that raise `ConnectionEvent::SequenceGap`,
+    /// for matching rubber banding reports to loss bursts; `None` raises none
+    pub sequence_gap_threshold: Option<u16>,
     
     // Channels
     pub max_channels: usize,
     pub default_channel_config: ChannelConfig,
+    /// Per-channel overrides indexed by channel id; channels past the end use `default_channel_config`
+    pub channel_configs: Vec<ChannelConfig>,
+    /// Shared bandwidth budgets, indexed by `ChannelConfig::group`
+    pub channel_groups: Vec<ChannelGroupConfig>,
+    /// How channels share the bandwidth when not everything queued can be sent
+    pub channel_scheduling: ChannelScheduling,
+    /// Reliable ordered channel reserved for join snapshots; when set, clients hold every
+    /// other channel until the server's `send_join_snapshot` baseline has arrived
+    pub join_snapshot_channel: Option<u8>,
+    /// What happens when the peer's channel count or delivery settings differ from ours,
+    /// compared during the handshake
+    pub channel_mismatch: ChannelMismatchPolicy,
+    
+    // Sending
+    /// Flush queued messages from `update`; when disabled call `flush`/`flush_all` once per tick
+    pub auto_flush: bool,
     
     // Rate limiting
     pub send_rate: f32,
     pub max_packet_rate: f32,
     pub congestion_threshold: f32,
+    /// Cap on message bytes per second across all channels, e.g. for metered connections.
+    /// Resends and keepalives are not counted. Held back messages raise
+    /// `ConnectionEvent::BandwidthLimited`
+    pub bandwidth_cap: Option<f32>,
+    /// Adjusts the send rate from reliable packet acks and losses, within `bandwidth_cap`;
+    /// `None` sends as fast as the cap allows
+    pub congestion_control: Option<CongestionControl>,
+    
+    // Connection request throttling (per source IP, before a connection exists)
+    pub connection_request_rate_limit: u32,
+    pub connection_request_rate_window: Duration,
+    pub connection_request_limiter_width: usize,
+    
+    // Receive budget (per `Server::update`, so a flood cannot starve the simulation)
+    /// Most datagrams read per update; `None` reads until the socket is empty
+    pub receive_budget_packets: Option<usize>,
+    /// Most time spent reading datagrams per update
+    pub receive_budget_time: Option<Duration>,
+    /// Most datagrams processed per source address per update; the rest are dropped
+    pub receive_budget_per_client: Option<usize>,
+    /// What happens to datagrams still queued once the budget is spent
+    pub receive_overflow: ReceiveOverflow,
+    
+    // Send errors
+    /// What a failed socket send does, per kind of failure
+    pub send_error_policy: SendErrorPolicies,
+    /// Wait before a send under `SendErrorPolicy::Retry` goes again, doubling with each
+    /// failure in a row up to `send_retry_max_interval`
+    pub send_retry_interval: Duration,
+    pub send_retry_max_interval: Duration,
+    
+    // Encryption
+    /// Pre-shared key; when unset an ephemeral X25519 exchange runs during the handshake
+    pub encryption_key: Option<[u8; 32]>,
+    /// Server identity key the client requires the key exchange to be signed with
+    pub server_public_key: Option<[u8; 32]>,
+    /// Seal every session packet with the session keys, authenticating its header too. Both
+    /// ends must set it: servers deny clients that do not offer it, and clients disconnect
+    /// from servers that do not agree
+    #[cfg(feature = "crypto")]
+    pub encrypt_packets: bool,
+    /// Key connect tokens are signed with; when set, the server ignores connection requests
+    /// without a valid [`ConnectToken`](crate::token::ConnectToken)
+    pub connect_token_key: Option<[u8; 32]>,
+    
+    // Session resumption
+    /// How long a server keeps a closed connection resumable; `None` disables tickets
+    pub resumption_ticket_lifetime: Option<Duration>,
+    
+    // Timestamps
+    /// Tick size of the timestamp epoch a server hands out with each connection accept
+    pub timestamp_resolution: TimestampResolution,
+    /// Have `Server::bind` request kernel receive timestamps for RTT sampling (Linux with the
+    /// `kernel-timestamps` feature; ignored elsewhere). Clients call `UdpSocket::enable_kernel_timestamps`
+    pub kernel_timestamps: bool,
+    /// Have `Server::bind` receive through an I/O completion port (Windows with the `iocp`
+    /// feature; ignored elsewhere). Clients call `UdpSocket::enable_iocp`
+    pub iocp: bool,
+    /// How often a client re-measures the server's clock over the control channel, keeping
+    /// `Connection::server_clock` accurate past the handshake; `None` measures it only then
+    pub time_sync_interval: Option<Duration>,
+    
+    // Memory
+    /// Bytes a connection may hold in queues and buffers before it is disconnected; `None` disables the cap
+    pub max_connection_memory: Option<usize>,
+    
+    // Telemetry
+    /// Reports metadata for a sample of sent packets; see [`NetworkConfig::telemetry_sampler`]
+    pub telemetry: Option<TelemetrySampler>,
+    /// Records every send, resend, receive and ack for export with `TraceRecorder::to_chrome_json`
+    pub trace: Option<TraceRecorder>,
+    /// Counts bits sent per message type by `send_batch`; see [`WireProfiler`]
+    pub wire_profiler: Option<WireProfiler>,
+    
+    // Transport
+    /// Byte transforms applied to every datagram after serialization (e.g. compression,
+    /// encryption, FEC); both ends need the same chain
+    pub middleware: MiddlewareChain,
+    /// Payload codecs this end supports; channels use one only when both ends have it
+    pub compression: CodecRegistry,
+    /// [`capability`](crate::packet::capability) bits this end advertises; clear one to hold
+    /// a feature back during a staged rollout. Features run only when both ends advertise them
+    pub capabilities: u32,
+    /// Permute sequence and ack numbers of session traffic with a per-connection secret, so
+    /// packet tools cannot trivially read, replay or forge them. A deterrent, not encryption;
+    /// on when both ends set it
+    pub obfuscate_sequences: bool,
+    
+    // Determinism
+    /// Seeds handshake salts, session seeds, ticket ids, telemetry sampling and simulators
+    /// built with [`NetworkConfig::simulator`], so a test run can be replayed from a printed
+    /// seed. Makes salts and tickets predictable: leave `None` (OS entropy) outside tests.
+    /// Key exchange secrets always come from the OS
+    pub random_seed: Option<u64>,
+    /// Source of every timer, timeout and RTT sample. Share one `Clock::manual()` between
+    /// both ends (and their sockets, via `UdpSocket::set_clock`) to step time by hand
+    pub clock: Clock,
 }
 
 impl Default for NetworkConfig {
@@ -41,33 +215,229 @@ impl Default for NetworkConfig {
         Self {
             protocol_id: 0x12345678, // Change this for your game
             max_clients: 64,
+            join_queue_size: 0,
+            join_queue_update_interval: Duration::from_secs(1),
+            respond_to_pings: true,
+            role: ConnectionRole::Player,
             
             connection_timeout: Duration::from_secs(10),
             keepalive_interval: Duration::from_secs(1),
             connection_request_timeout: Duration::from_secs(5),
             connection_request_max_retries: 5,
+            connection_request_interval: Duration::from_millis(500),
+            connection_request_backoff: 2.0,
+            connection_request_max_interval: Duration::from_secs(4),
+            connection_request_jitter: 0.2,
+            connect_attempt_delay: Duration::from_millis(250),
+            peer_unreachable_limit: Some(3),
+            pause_grace_period: Duration::from_secs(60),
             
             mtu: 1200,
             fragment_threshold: 1024,
             fragment_timeout: Duration::from_secs(5),
             max_fragments: 256,
+            fragment_burst: Some(4),
+            max_packet_age: None,
+            max_mtu: None,
             
             packet_buffer_size: 256,
             ack_buffer_size: 256,
             max_sequence_distance: 32768,
             reliable_retry_time: Duration::from_millis(100),
             max_reliable_retries: 10,
+            sequence_gap_threshold: None,
             
             max_channels: 8,
             default_channel_config: ChannelConfig::default(),
+            channel_configs: Vec::new(),
+            channel_groups: Vec::new(),
+            channel_scheduling: ChannelScheduling::Priority,
+            join_snapshot_channel: None,
+            channel_mismatch: ChannelMismatchPolicy::Warn,
+            
+            auto_flush: true,
             
             send_rate: 60.0, // 60 packets per second
             max_packet_rate: 120.0,
             congestion_threshold: 0.1, // 10% packet loss
+            bandwidth_cap: None,
+            congestion_control: None,
+            
+            connection_request_rate_limit: 10, // requests per window
+            connection_request_rate_window: Duration::from_secs(1),
+            connection_request_limiter_width: 1024,
+            
+            receive_budget_packets: None,
+            receive_budget_time: None,
+            receive_budget_per_client: None,
+            receive_overflow: ReceiveOverflow::Defer,
+            
+            send_error_policy: SendErrorPolicies::default(),
+            send_retry_interval: Duration::from_millis(5),
+            send_retry_max_interval: Duration::from_millis(100),
+            
+            encryption_key: None,
+            server_public_key: None,
+            #[cfg(feature = "crypto")]
+            encrypt_packets: false,
+            connect_token_key: None,
+            
+            resumption_ticket_lifetime: Some(Duration::from_secs(60)),
+            
+            timestamp_resolution: TimestampResolution::Millis,
+            kernel_timestamps: true,
+            iocp: true,
+            time_sync_interval: None,
+            
+            max_connection_memory: Some(16 * 1024 * 1024),
+            
+            telemetry: None,
+            trace: None,
+            wire_profiler: None,
+            
+            middleware: MiddlewareChain::new(),
+            compression: CodecRegistry::new(),
+            capabilities: capability::ALL,
+            obfuscate_sequences: false,
+            
+            random_seed: None,
+            clock: Clock::system(),
         }
     }
 }
 
+impl NetworkConfig {
+    /// Phones on cellular or flaky Wi-Fi: a conservative MTU, a lower tick and packet rate
+    /// under a bandwidth cap, and timeouts long enough to ride out a handover between networks.
+    pub fn preset_mobile() -> Self {
+        Self {
+            connection_timeout: Duration::from_secs(20),
+            // Waking the radio costs battery; this still beats common carrier NAT timeouts
+            keepalive_interval: Duration::from_secs(5),
+            connection_request_interval: Duration::from_secs(1),
+            connection_request_max_interval: Duration::from_secs(8),
+            peer_unreachable_limit: Some(6),
+            // Long enough to answer a call or switch apps and come back to the match
+            pause_grace_period: Duration::from_secs(180),
+            
+            mtu: 1100,
+            fragment_threshold: 900,
+            fragment_timeout: Duration::from_secs(10),
+            
+            reliable_retry_time: Duration::from_millis(250),
+            max_reliable_retries: 20,
+            
+            send_rate: 30.0,
+            max_packet_rate: 60.0,
+            congestion_threshold: 0.2,
+            bandwidth_cap: Some(64.0 * 1024.0),
+            
+            max_connection_memory: Some(4 * 1024 * 1024),
+            ..Self::default()
+        }
+    }
+    
+    /// Wired local networks: full Ethernet-sized packets, fast retries and quick detection
+    /// of a peer that went away.
+    pub fn preset_lan() -> Self {
+        Self {
+            connection_timeout: Duration::from_secs(3),
+            keepalive_interval: Duration::from_millis(250),
+            connection_request_interval: Duration::from_millis(100),
+            connection_request_max_interval: Duration::from_secs(1),
+            
+            mtu: 1400,
+            fragment_threshold: 1300,
+            fragment_timeout: Duration::from_secs(2),
+            
+            reliable_retry_time: Duration::from_millis(30),
+            
+            send_rate: 60.0,
+            max_packet_rate: 240.0,
+            
+            packet_buffer_size: 512,
+            ack_buffer_size: 512,
+            ..Self::default()
+        }
+    }
+    
+    /// Fast-paced online play: a 128 Hz tick, sequence buffers sized to cover the same span
+    /// of time as the defaults at 60 Hz, microsecond timestamps and a continuously synced clock.
+    pub fn preset_competitive() -> Self {
+        Self {
+            connection_timeout: Duration::from_secs(5),
+            keepalive_interval: Duration::from_millis(500),
+            
+            reliable_retry_time: Duration::from_millis(50),
+            max_reliable_retries: 20,
+            
+            send_rate: 128.0,
+            max_packet_rate: 256.0,
+            congestion_threshold: 0.05,
+            
+            packet_buffer_size: 1024,
+            ack_buffer_size: 1024,
+            
+            timestamp_resolution: TimestampResolution::Micros,
+            time_sync_interval: Some(Duration::from_secs(1)),
+            ..Self::default()
+        }
+    }
+    
+    /// Calls `callback` with metadata for roughly `rate` (0.0 to 1.0) of all sent packets.
+    pub fn telemetry_sampler<F>(mut self, rate: f32, callback: F) -> Self
+    where
+        F: Fn(&PacketTelemetry) + Send + Sync + 'static,
+    {
+        self.telemetry = Some(TelemetrySampler::new(rate, callback));
+        self
+    }
+    
+    /// Simulator drawing from `random_seed` when it is set, on a stream picked by
+    /// `config.seed`; otherwise seeded by `config.seed` alone.
+    pub fn simulator(&self, config: SimulatorConfig) -> NetworkSimulator {
+        match self.random_seed {
+            Some(seed) => {
+                let rng = DeterministicRng::with_stream(seed, stream::SIMULATOR.wrapping_add(config.seed));
+                NetworkSimulator::with_rng(rng, config)
+            }
+            None => NetworkSimulator::new(config),
+        }
+    }
+    
+    /// Capability bits sent in the handshake: `capabilities`, minus opt-in features left off.
+    /// One [`ChannelConfig::layout_byte`] per channel, as compared with the peer's.
+    pub(crate) fn channel_layout(&self) -> Vec<u8> {
+        (0..self.max_channels.min(u8::MAX as usize))
+            .map(|i| self.channel_configs.get(i).unwrap_or(&self.default_channel_config).layout_byte())
+            .collect()
+    }
+    
+    pub(crate) fn advertised_capabilities(&self) -> u32 {
+        let mut capabilities = self.capabilities;
+        if !self.obfuscate_sequences {
+            capabilities &= !capability::SEQUENCE_OBFUSCATION;
+        }
+        if !self.encrypts_packets() {
+            capabilities &= !capability::PACKET_ENCRYPTION;
+        }
+        capabilities
+    }
+    
+    /// Whether `encrypt_packets` is set; always false without the `crypto` feature.
+    pub(crate) fn encrypts_packets(&self) -> bool {
+        #[cfg(feature = "crypto")]
+        return self.encrypt_packets;
+        #[cfg(not(feature = "crypto"))]
+        false
+    }
+    
+    /// Generator for one stream of `random_seed`, or a fresh OS-seeded one when unset.
+    pub(crate) fn rng(&self, stream: u64) -> DeterministicRng {
+        DeterministicRng::with_stream(self.random_seed.unwrap_or_else(rand::random), stream)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ChannelConfig {
     pub reliability: Reliability,
@@ -75,6 +445,37 @@ pub struct ChannelConfig {
     pub max_message_size: usize,
     pub message_buffer_size: usize,
     pub block_on_full: bool,
+    pub resend_strategy: ResendStrategy,
+    /// Index into `NetworkConfig::channel_groups` whose budget this channel draws from;
+    /// `None` (or an index past the end) leaves the channel unlimited
+    pub group: Option<usize>,
+    /// Channels with higher priority are drained first when packets are assembled
+    pub priority: u8,
+    /// Share of the bandwidth under `ChannelScheduling::WeightedFair`, relative to the
+    /// weights of the other channels with messages waiting
+    pub weight: u32,
+    /// Gameplay input channel: observers may not send on it, and the server drops their packets
+    pub player_only: bool,
+    /// Keyed state channel: messages go through `send_latest`, and only the newest version
+    /// of each key is sent or resent. Use a reliable channel
+    pub latest_only: bool,
+    /// Most keys with a message queued or unacked at once, for `send_keyed` / `send_latest`
+    pub max_pending_keys: usize,
+    /// Codec to negotiate for this channel's payloads; the server's setting applies
+    pub compression: Compression,
+    /// Payloads shorter than this many bytes are sent without running the codec, which
+    /// rarely shrinks them enough to pay for the CPU
+    pub compression_threshold: usize,
+    /// Seal payloads with a key derived from the session keys for this channel alone.
+    /// Costs 24 bytes and a cipher pass per packet; both ends must agree
+    pub encrypted: bool,
+    /// Most received messages held until the application reads them; `None` is unbounded.
+    /// Unreliable channels drop their oldest message to make room
+    pub receive_limit: Option<usize>,
+    /// What a reliable channel does once `receive_limit` messages are waiting
+    pub slow_consumer: SlowConsumer,
+    /// How messages on this channel are encoded; both ends must agree
+    pub payload_mode: PayloadMode,
 }
 
 impl Default for ChannelConfig {
@@ -85,6 +486,207 @@ impl Default for ChannelConfig {
             max_message_size: 1024 * 1024, // 1MB
             message_buffer_size: 1024,
             block_on_full: false,
+            resend_strategy: ResendStrategy::default(),
+            group: None,
+            priority: 0,
+            weight: 1,
+            player_only: false,
+            latest_only: false,
+            max_pending_keys: 1024,
+            compression: Compression::None,
+            compression_threshold: 64,
+            encrypted: false,
+            receive_limit: None,
+            slow_consumer: SlowConsumer::Backpressure,
+            payload_mode: PayloadMode::BitPacked,
+        }
+    }
+}
+
+impl ChannelConfig {
+    /// The settings both ends must agree on: reliability in bits 0-1, ordering in bits 2-3,
+    /// then the payload mode and encryption.
+    pub(crate) fn layout_byte(&self) -> u8 {
+        let reliability = match self.reliability {
+            Reliability::Unreliable => 0,
+            Reliability::Reliable => 1,
+            Reliability::UnreliableOrdered => 2,
+        };
+        let ordering = match self.ordering {
+            Ordering::Unordered => 0,
+            Ordering::Ordered => 1,
+            Ordering::Sequenced => 2,
+        };
+        let legacy = (self.payload_mode == PayloadMode::Legacy) as u8;
+        reliability | ordering << 2 | legacy << 4 | (self.encrypted as u8) << 5
+    }
+}
+
+/// Token bucket shared by every channel in a group.
+///
+/// A message is sent while the bucket is not empty and may overdraw it, so messages
+/// larger than `burst_bytes` still get through; the debt is repaid before the next one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelGroupConfig {
+    /// Rate at which the budget refills
+    pub bytes_per_second: f32,
+    /// Most budget the group can save up while idle
+    pub burst_bytes: usize,
+}
+
+impl Default for ChannelGroupConfig {
+    fn default() -> Self {
+        Self {
+            bytes_per_second: 64.0 * 1024.0,
+            burst_bytes: 8 * 1024,
+        }
+    }
+}
+
+/// When unacknowledged reliable packets are sent again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResendStrategy {
+    /// Resend every `interval` regardless of network conditions.
+    Fixed { interval: Duration },
+    /// RFC 6298 style timeout (smoothed RTT + 4 x variance) clamped to `[min_rto, max_rto]`,
+    /// multiplied by `backoff` after every resend of the same packet.
+    RttBased { min_rto: Duration, max_rto: Duration, backoff: f32 },
+    /// `RttBased` with a backoff of 2, plus an immediate resend once `ack_gap` newer
+    /// packets have been acknowledged while this one has not.
+    FastRetransmit { ack_gap: u16, min_rto: Duration, max_rto: Duration },
+}
+
+impl Default for ResendStrategy {
+    fn default() -> Self {
+        ResendStrategy::Fixed { interval: Duration::from_millis(100) }
+    }
+}
+
+/// Handling of datagrams left over once a server's receive budget is spent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReceiveOverflow {
+    /// Leave them in the socket buffer for the next update
+    #[default]
+    Defer,
+    /// Read and discard them, counted in `ServerStats::packets_over_budget`
+    Drop,
+}
+
+/// What happens to a packet whose socket send failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendErrorPolicy {
+    /// Keep it and everything queued behind it, and try again after a backoff
+    Retry,
+    /// Drop it and carry on; reliable packets are resent as if lost on the wire
+    Drop,
+    /// Drop it like `Drop` and raise `ConnectionEvent::SendFailed`
+    Report,
+    /// Return the error from `update` / `flush`, leaving the rest of the queue for the next call
+    Fail,
+}
+
+/// A [`SendErrorPolicy`] for each [`SendErrorClass`]. Failures are counted in
+/// `NetworkStats::send_errors` whichever policy applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendErrorPolicies {
+    pub would_block: SendErrorPolicy,
+    pub message_too_large: SendErrorPolicy,
+    pub unreachable: SendErrorPolicy,
+    pub other: SendErrorPolicy,
+}
+
+impl Default for SendErrorPolicies {
+    fn default() -> Self {
+        Self {
+            // A full send buffer drains on its own
+            would_block: SendErrorPolicy::Retry,
+            // Resending the same bytes fails the same way, so let the application know
+            message_too_large: SendErrorPolicy::Report,
+            // Routes come back after a network change; the timeout covers ones that do not
+            unreachable: SendErrorPolicy::Drop,
+            other: SendErrorPolicy::Fail,
+        }
+    }
+}
+
+impl SendErrorPolicies {
+    pub fn for_class(&self, class: SendErrorClass) -> SendErrorPolicy {
+        match class {
+            SendErrorClass::WouldBlock => self.would_block,
+            SendErrorClass::MessageTooLarge => self.message_too_large,
+            SendErrorClass::Unreachable => self.unreachable,
+            SendErrorClass::Other => self.other,
+        }
+    }
+}
+
+/// Order in which channels get their messages into packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelScheduling {
+    /// Drain channels by descending `ChannelConfig::priority`; under a bandwidth cap the
+    /// lowest priorities can starve
+    #[default]
+    Priority,
+    /// Deficit round robin in priority order: each turn a channel may send `weight` MTUs
+    /// of messages, so every channel with messages waiting keeps making progress
+    WeightedFair,
+}
+
+/// How a reliable channel treats a peer sending faster than the application reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SlowConsumer {
+    /// Leave further packets for the channel unacked so the sender holds and resends them
+    #[default]
+    Backpressure,
+    /// Disconnect with `disconnect_reason::SLOW_CONSUMER`
+    Disconnect,
+}
+
+/// Response to a peer whose channels are configured differently, which otherwise shows up
+/// as undecodable or silently dropped messages long after connecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelMismatchPolicy {
+    /// Connect anyway and raise `ConnectionEvent::ChannelMismatch` / `ServerEvent::ChannelMismatch`
+    #[default]
+    Warn,
+    /// Servers deny the request with `deny_reason::CHANNEL_MISMATCH`; clients raise the event,
+    /// disconnect with `disconnect_reason::PROTOCOL_MISMATCH` and fail with `ConnectionError::ChannelMismatch`
+    Fail,
+}
+
+/// Encoding of the messages on a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PayloadMode {
+    /// Application bytes or bit-packed types, through `send` and `send_batch`
+    #[default]
+    BitPacked,
+    /// Length-prefixed messages in a pre-gbnet encoding such as bincode, through
+    /// `send_legacy` and `receive_legacy` only
+    Legacy,
+}
+
+/// Part a client plays in the session, sent with its challenge response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionRole {
+    #[default]
+    Player,
+    /// Spectator that receives everything but may not send on `player_only` channels
+    Observer,
+}
+
+impl ConnectionRole {
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            ConnectionRole::Player => 0,
+            ConnectionRole::Observer => 1,
+        }
+    }
+    
+    pub(crate) fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(ConnectionRole::Player),
+            1 => Some(ConnectionRole::Observer),
+            _ => None,
         }
     }
 }
@@ -0,0 +1,332 @@
+// conformance.rs - Golden packet suite for verifying wire compatibility of other implementations
+//
+// The suite (conformance/packets.json) lists raw datagrams as hex together with the
+// expected decode result. A conforming port must:
+//   1. decode every case with `"decoded": null` as an error,
+//   2. decode every other case to exactly the described header, type, payload and messages,
+//   3. encode the description back to exactly the same bytes.
+//
+// u64 fields are JSON strings since most JSON parsers lose precision above 2^53.
+use std::fmt;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    channel::{read_messages, write_message},
+    packet::{Packet, PacketHeader, PacketType, control_message, deny_reason, disconnect_reason, header_extension},
+};
+
+/// Version of the suite format and of the wire protocol it describes.
+pub const SUITE_VERSION: u64 = 5;
+
+const GOLDEN_SUITE: &str = include_str!("../conformance/packets.json");
+
+#[derive(Debug)]
+pub enum ConformanceError {
+    InvalidJson(String),
+    InvalidSuite(String),
+    UnsupportedVersion(u64),
+}
+
+impl fmt::Display for ConformanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConformanceError::InvalidJson(err) => write!(f, "invalid JSON: {}", err),
+            ConformanceError::InvalidSuite(err) => write!(f, "invalid suite: {}", err),
+            ConformanceError::UnsupportedVersion(version) => write!(f, "unsupported suite version {}", version),
+        }
+    }
+}
+
+impl std::error::Error for ConformanceError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConformanceFailure {
+    pub case: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConformanceReport {
+    pub passed: Vec<String>,
+    pub failures: Vec<ConformanceFailure>,
+}
+
+impl ConformanceReport {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Returns the suite shipped with this crate.
+pub fn golden_suite() -> &'static str {
+    GOLDEN_SUITE
+}
+
+/// Runs every case of a suite against this implementation.
+pub fn run_suite(json: &str) -> Result<ConformanceReport, ConformanceError> {
+    let suite: Value = serde_json::from_str(json).map_err(|err| ConformanceError::InvalidJson(err.to_string()))?;
+    let version = suite["version"].as_u64().ok_or_else(|| invalid("missing version"))?;
+    if version != SUITE_VERSION {
+        return Err(ConformanceError::UnsupportedVersion(version));
+    }
+    let cases = suite["cases"].as_array().ok_or_else(|| invalid("missing cases"))?;
+
+    let mut report = ConformanceReport::default();
+    for case in cases {
+        let name = case["name"].as_str().ok_or_else(|| invalid("case without name"))?.to_string();
+        let bytes = case["bytes"].as_str().and_then(from_hex).ok_or_else(|| invalid(&format!("{}: bad bytes", name)))?;
+        let expected = case.get("decoded").ok_or_else(|| invalid(&format!("{}: missing decoded", name)))?;
+
+        match check_case(&bytes, expected) {
+            Ok(()) => report.passed.push(name),
+            Err(reason) => report.failures.push(ConformanceFailure { case: name, reason }),
+        }
+    }
+    Ok(report)
+}
+
+fn check_case(bytes: &[u8], expected: &Value) -> Result<(), String> {
+    let decoded = describe_packet(bytes).unwrap_or(Value::Null);
+    if &decoded != expected {
+        return Err(format!("decoded {} but expected {}", decoded, expected));
+    }
+    if expected.is_null() {
+        return Ok(());
+    }
+    let encoded = encode_description(expected).map_err(|err| err.to_string())?;
+    if encoded != bytes {
+        return Err(format!("encoded {} but expected {}", to_hex(&encoded), to_hex(bytes)));
+    }
+    Ok(())
+}
+
+/// Decodes a datagram into its canonical description, or `None` if it must be rejected.
+pub fn describe_packet(bytes: &[u8]) -> Option<Value> {
+    let packet = Packet::deserialize(bytes).ok()?;
+
+    let header = json!({
+        "protocol_id": packet.header.protocol_id,
+        "sequence": packet.header.sequence,
+        "ack": packet.header.ack,
+        "ack_bits": packet.header.ack_bits,
+    });
+
+    let packet_type = match &packet.packet_type {
+        PacketType::ConnectionRequest => json!({ "kind": "ConnectionRequest" }),
+        PacketType::ConnectionChallenge { server_salt } => {
+            json!({ "kind": "ConnectionChallenge", "server_salt": server_salt.to_string() })
+        }
+        PacketType::ConnectionResponse { client_salt } => {
+            json!({ "kind": "ConnectionResponse", "client_salt": client_salt.to_string() })
+        }
+        PacketType::ConnectionAccept => json!({ "kind": "ConnectionAccept" }),
+        PacketType::ConnectionDeny { reason } => json!({ "kind": "ConnectionDeny", "reason": reason }),
+        PacketType::Disconnect { reason } => json!({ "kind": "Disconnect", "reason": reason }),
+        PacketType::KeepAlive => json!({ "kind": "KeepAlive" }),
+        PacketType::Payload { channel, is_fragment } => {
+            json!({ "kind": "Payload", "channel": channel, "is_fragment": is_fragment })
+        }
+        PacketType::ResumeRequest => json!({ "kind": "ResumeRequest" }),
+        PacketType::ConnectionQueued { position } => json!({ "kind": "ConnectionQueued", "position": position }),
+        PacketType::PingRequest => json!({ "kind": "PingRequest" }),
+        PacketType::PingResponse => json!({ "kind": "PingResponse" }),
+        PacketType::Control => json!({ "kind": "Control" }),
+    };
+
+    // Messages are only framed inside unfragmented payload packets and control packets
+    let messages = match packet.packet_type {
+        PacketType::Payload { is_fragment: false, .. } | PacketType::Control => match read_messages(&packet.payload) {
+            Some(messages) => Value::Array(
+                messages.iter()
+                    .map(|(sequence, data)| json!({ "sequence": sequence, "data": to_hex(data) }))
+                    .collect(),
+            ),
+            None => Value::Null,
+        },
+        _ => Value::Null,
+    };
+
+    let mut description = json!({
+        "header": header,
+        "type": packet_type,
+        "payload": to_hex(&packet.payload),
+        "messages": messages,
+    });
+    // Absent rather than empty, so descriptions of packets without extensions are unchanged
+    if !packet.extensions.is_empty() {
+        let extensions: Map<String, Value> = packet.extensions.iter()
+            .map(|(id, data)| (id.to_string(), json!(to_hex(data))))
+            .collect();
+        description["extensions"] = Value::Object(extensions);
+    }
+    Some(description)
+}
+
+/// Encodes a packet description back into a datagram.
+pub fn encode_description(description: &Value) -> Result<Vec<u8>, ConformanceError> {
+    let header = &description["header"];
+    let header = PacketHeader {
+        protocol_id: field(header, "protocol_id")?,
+        sequence: field(header, "sequence")?,
+        ack: field(header, "ack")?,
+        ack_bits: field(header, "ack_bits")?,
+    };
+
+    let ty = &description["type"];
+    let packet_type = match ty["kind"].as_str() {
+        Some("ConnectionRequest") => PacketType::ConnectionRequest,
+        Some("ConnectionChallenge") => PacketType::ConnectionChallenge { server_salt: u64_field(ty, "server_salt")? },
+        Some("ConnectionResponse") => PacketType::ConnectionResponse { client_salt: u64_field(ty, "client_salt")? },
+        Some("ConnectionAccept") => PacketType::ConnectionAccept,
+        Some("ConnectionDeny") => PacketType::ConnectionDeny { reason: field(ty, "reason")? },
+        Some("Disconnect") => PacketType::Disconnect { reason: field(ty, "reason")? },
+        Some("KeepAlive") => PacketType::KeepAlive,
+        Some("Payload") => PacketType::Payload {
+            channel: field(ty, "channel")?,
+            is_fragment: ty["is_fragment"].as_bool().ok_or_else(|| invalid("is_fragment"))?,
+        },
+        Some("ResumeRequest") => PacketType::ResumeRequest,
+        Some("ConnectionQueued") => PacketType::ConnectionQueued { position: field(ty, "position")? },
+        Some("PingRequest") => PacketType::PingRequest,
+        Some("PingResponse") => PacketType::PingResponse,
+        Some("Control") => PacketType::Control,
+        _ => return Err(invalid("unknown packet kind")),
+    };
+
+    let payload = description["payload"].as_str().and_then(from_hex).ok_or_else(|| invalid("payload"))?;
+    let mut packet = Packet::new(header, packet_type).with_payload(payload);
+    if let Some(extensions) = description.get("extensions") {
+        let extensions = extensions.as_object().ok_or_else(|| invalid("extensions"))?;
+        for (id, data) in extensions {
+            let id = id.parse().map_err(|_| invalid("extension id"))?;
+            let data = data.as_str().and_then(from_hex).ok_or_else(|| invalid("extension data"))?;
+            packet.extensions.set(id, data).map_err(|err| invalid(&err.to_string()))?;
+        }
+    }
+    packet.serialize().map_err(|err| invalid(&err.to_string()))
+}
+
+/// Builds the suite shipped with the crate from [`golden_packets`].
+pub fn generate_suite() -> String {
+    let cases: Vec<Value> = golden_packets()
+        .into_iter()
+        .map(|(name, bytes)| json!({
+            "name": name,
+            "bytes": to_hex(&bytes),
+            "decoded": describe_packet(&bytes),
+        }))
+        .collect();
+
+    let mut suite = Map::new();
+    suite.insert("version".to_string(), json!(SUITE_VERSION));
+    suite.insert("cases".to_string(), Value::Array(cases));
+    let mut json = serde_json::to_string_pretty(&Value::Object(suite)).expect("suite is valid JSON");
+    json.push('\n');
+    json
+}
+
+/// The datagrams covered by the suite, built from fixed values.
+pub fn golden_packets() -> Vec<(&'static str, Vec<u8>)> {
+    let header = PacketHeader {
+        protocol_id: 0x12345678,
+        sequence: 0x0102,
+        ack: 0xFFFE,
+        ack_bits: 0x8000_0001,
+    };
+    let handshake_header = PacketHeader { sequence: 0, ack: 0, ack_bits: 0, ..header.clone() };
+    let packet = |header: &PacketHeader, packet_type: PacketType, payload: Vec<u8>| {
+        Packet::new(header.clone(), packet_type)
+            .with_payload(payload)
+            .serialize()
+            .expect("golden packets serialize")
+    };
+
+    let mut messages = Vec::new();
+    write_message(&mut messages, 0, b"hello");
+    write_message(&mut messages, 1, &[]);
+    write_message(&mut messages, 0xFFFF, &[0x00, 0xFF, 0x7F]);
+
+    let mut control = Vec::new();
+    write_message(&mut control, 3, &[control_message::TIME_SYNC_REQUEST, 0x78, 0x56, 0x34, 0x12]);
+
+    let keepalive = packet(&header, PacketType::KeepAlive, Vec::new());
+    let extended = |packet_type: PacketType, payload: Vec<u8>, extensions: &[(u8, Vec<u8>)]| {
+        let mut packet = Packet::new(header.clone(), packet_type).with_payload(payload);
+        for (id, data) in extensions {
+            packet.extensions.set(*id, data.clone()).expect("golden extensions are valid");
+        }
+        packet.serialize().expect("golden packets serialize")
+    };
+    let extended_keepalive = extended(PacketType::KeepAlive, Vec::new(), &[(header_extension::MAX, vec![0xC3, 0x3C])]);
+
+    vec![
+        ("connection_request", packet(&handshake_header, PacketType::ConnectionRequest, (0..32).collect())),
+        ("connection_challenge", packet(&handshake_header, PacketType::ConnectionChallenge { server_salt: 0x0123_4567_89AB_CDEF }, (32..64).collect())),
+        ("connection_challenge_max_salt", packet(&handshake_header, PacketType::ConnectionChallenge { server_salt: u64::MAX }, Vec::new())),
+        ("connection_response", packet(&header, PacketType::ConnectionResponse { client_salt: 0xFEDC_BA98_7654_3210 }, Vec::new())),
+        ("connection_accept", packet(&header, PacketType::ConnectionAccept, vec![0xAB; 16])),
+        ("connection_deny", packet(&handshake_header, PacketType::ConnectionDeny { reason: deny_reason::SERVER_FULL }, Vec::new())),
+        ("disconnect", packet(&header, PacketType::Disconnect { reason: disconnect_reason::KICKED }, Vec::new())),
+        ("keepalive", keepalive.clone()),
+        ("payload_messages", packet(&header, PacketType::Payload { channel: 5, is_fragment: false }, messages)),
+        ("payload_empty", packet(&header, PacketType::Payload { channel: 7, is_fragment: false }, Vec::new())),
+        ("payload_fragment", packet(&header, PacketType::Payload { channel: 0, is_fragment: true }, vec![1, 2, 3])),
+        ("payload_malformed_framing", packet(&header, PacketType::Payload { channel: 1, is_fragment: false }, vec![0, 0, 9, 0, 1])),
+        ("resume_request", packet(&handshake_header, PacketType::ResumeRequest, vec![0x5A; 16])),
+        ("connection_queued", packet(&handshake_header, PacketType::ConnectionQueued { position: 0x0203 }, Vec::new())),
+        ("ping_request", packet(&handshake_header, PacketType::PingRequest, (0..16).collect())),
+        ("ping_response", packet(&handshake_header, PacketType::PingResponse, (16..32).collect())),
+        ("control_time_sync", packet(&header, PacketType::Control, control)),
+        ("payload_extensions", extended(
+            PacketType::Payload { channel: 2, is_fragment: false },
+            vec![0, 0, 1, 0, 0x42],
+            &[(header_extension::TIMESTAMP, vec![0x00, 0x01, 0x02, 0x03]), (header_extension::PATH_PROBE, Vec::new())],
+        )),
+        // Ids without a defined meaning must still be skipped and re-encoded as is
+        ("keepalive_unknown_extension", extended_keepalive.clone()),
+        ("reject_empty", Vec::new()),
+        ("reject_truncated_header", keepalive[..8].to_vec()),
+        ("reject_unknown_type", {
+            // Replace the type nibble (after the 96-bit header) with an unused discriminant
+            let mut bytes = keepalive.clone();
+            bytes[12] = (bytes[12] & 0x0F) | 0xE0;
+            bytes
+        }),
+        ("reject_truncated_extension", extended_keepalive[..extended_keepalive.len() - 2].to_vec()),
+        ("reject_extension_reserved_bits", {
+            let mut bytes = extended_keepalive.clone();
+            bytes[12] |= 0x01;
+            bytes
+        }),
+    ]
+}
+
+fn field<T: TryFrom<u64>>(value: &Value, name: &str) -> Result<T, ConformanceError> {
+    value[name].as_u64()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| invalid(name))
+}
+
+fn u64_field(value: &Value, name: &str) -> Result<u64, ConformanceError> {
+    value[name].as_str()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid(name))
+}
+
+fn invalid(message: &str) -> ConformanceError {
+    ConformanceError::InvalidSuite(message.to_string())
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
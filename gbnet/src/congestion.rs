@@ -0,0 +1,249 @@
+// congestion.rs - Send rate control that backs off when the path is congested
+//
+// A `CongestionController` is told about every acked and every lost reliable packet by the
+// connection's `ReliableEndpoint` and answers with the message bytes per second the
+// connection may send. The connection enforces that rate with the same budget as
+// `bandwidth_cap` (the lower of the two wins), so held back messages raise the usual
+// `BandwidthLimited` event and the current rate shows up in `NetworkStats::allowed_bandwidth`.
+//
+// Only reliable packets are tracked for acks, so a connection sending nothing reliable
+// gives its controller no signal and keeps whatever rate it has.
+//
+// Two controllers are built in. `Aimd` probes upward by a fixed step every round trip and
+// cuts the rate by a factor on loss or a round trip well above the usual, at most once per
+// round trip. `GoodBad` is the classic netcode flow control: a high rate while the
+// connection is good and a low one after loss or high RTT, returning to good only after a
+// penalty time that doubles when the connection relapses quickly.
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Decides how fast a connection may send from what happens to its reliable packets.
+pub trait CongestionController: Send + fmt::Debug {
+    /// A packet was acked at `now`; `rtt` is its round trip unless it had been resent.
+    fn on_ack(&mut self, rtt: Option<Duration>, now: Instant);
+
+    /// A packet was resent or given up on at `now` because its ack did not arrive in time.
+    fn on_loss(&mut self, now: Instant);
+
+    /// Message bytes per second the connection may send.
+    fn allowed_bandwidth(&self) -> f32;
+}
+
+/// Builds a fresh controller for each connection; cloned configs share the factory.
+#[derive(Clone)]
+pub struct CongestionControl {
+    factory: Arc<dyn Fn() -> Box<dyn CongestionController> + Send + Sync>,
+}
+
+impl CongestionControl {
+    pub fn new<F>(factory: F) -> Self
+    where
+        F: Fn() -> Box<dyn CongestionController> + Send + Sync + 'static,
+    {
+        Self { factory: Arc::new(factory) }
+    }
+
+    pub fn aimd(config: AimdConfig) -> Self {
+        Self::new(move || Box::new(Aimd::new(config)))
+    }
+
+    pub fn good_bad(config: GoodBadConfig) -> Self {
+        Self::new(move || Box::new(GoodBad::new(config)))
+    }
+
+    pub(crate) fn build(&self) -> Box<dyn CongestionController> {
+        (self.factory)()
+    }
+}
+
+impl fmt::Debug for CongestionControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CongestionControl").finish_non_exhaustive()
+    }
+}
+
+// Round trip assumed before the first sample, and the shortest one rates change per or
+// inflation is measured against
+const DEFAULT_RTT: Duration = Duration::from_millis(100);
+const MIN_EPOCH: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AimdConfig {
+    /// Rate before any feedback, in bytes per second
+    pub initial_rate: f32,
+    pub min_rate: f32,
+    pub max_rate: f32,
+    /// Bytes per second added each round trip without congestion
+    pub increase: f32,
+    /// Factor the rate is multiplied by on congestion
+    pub decrease: f32,
+    /// A round trip more than this many times the long-run average counts as congestion
+    pub rtt_inflation: f32,
+}
+
+impl Default for AimdConfig {
+    fn default() -> Self {
+        Self {
+            initial_rate: 64.0 * 1024.0,
+            min_rate: 4.0 * 1024.0,
+            max_rate: 1024.0 * 1024.0,
+            increase: 4.0 * 1024.0,
+            decrease: 0.5,
+            rtt_inflation: 2.0,
+        }
+    }
+}
+
+/// Additive increase, multiplicative decrease.
+#[derive(Debug, Clone)]
+pub struct Aimd {
+    config: AimdConfig,
+    rate: f32,
+    // Slow average of RTT samples, the baseline inflation is measured against
+    base_rtt: Option<Duration>,
+    last_increase: Option<Instant>,
+    last_decrease: Option<Instant>,
+}
+
+impl Aimd {
+    pub fn new(config: AimdConfig) -> Self {
+        Self {
+            config,
+            rate: config.initial_rate.clamp(config.min_rate, config.max_rate),
+            base_rtt: None,
+            last_increase: None,
+            last_decrease: None,
+        }
+    }
+
+    // Rates change at most once per round trip, so one burst of loss cuts once
+    fn epoch(&self) -> Duration {
+        self.base_rtt.unwrap_or(DEFAULT_RTT).max(MIN_EPOCH)
+    }
+
+    fn decrease(&mut self, now: Instant) {
+        if self.last_decrease.is_some_and(|at| now.saturating_duration_since(at) < self.epoch()) {
+            return;
+        }
+        self.rate = (self.rate * self.config.decrease).max(self.config.min_rate);
+        self.last_decrease = Some(now);
+        self.last_increase = Some(now);
+    }
+}
+
+impl CongestionController for Aimd {
+    fn on_ack(&mut self, rtt: Option<Duration>, now: Instant) {
+        if let Some(rtt) = rtt {
+            let base = self.base_rtt.map_or(rtt, |base| base.mul_f32(63.0 / 64.0) + rtt / 64);
+            self.base_rtt = Some(base);
+            if rtt > base.max(MIN_EPOCH).mul_f32(self.config.rtt_inflation) {
+                self.decrease(now);
+                return;
+            }
+        }
+        if self.last_increase.is_none_or(|at| now.saturating_duration_since(at) >= self.epoch()) {
+            self.rate = (self.rate + self.config.increase).min(self.config.max_rate);
+            self.last_increase = Some(now);
+        }
+    }
+
+    fn on_loss(&mut self, now: Instant) {
+        self.decrease(now);
+    }
+
+    fn allowed_bandwidth(&self) -> f32 {
+        self.rate
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GoodBadConfig {
+    /// Rate in good mode, in bytes per second
+    pub good_rate: f32,
+    /// Rate in bad mode
+    pub bad_rate: f32,
+    /// Round trips above this switch to bad mode, as does any loss
+    pub rtt_threshold: Duration,
+    /// Time conditions must stay good before returning to good mode
+    pub initial_penalty: Duration,
+    pub max_penalty: Duration,
+    /// Falling back within this long of returning to good mode doubles the penalty, and
+    /// each such stretch spent in good mode halves it
+    pub stable_period: Duration,
+}
+
+impl Default for GoodBadConfig {
+    fn default() -> Self {
+        Self {
+            good_rate: 256.0 * 1024.0,
+            bad_rate: 32.0 * 1024.0,
+            rtt_threshold: Duration::from_millis(250),
+            initial_penalty: Duration::from_secs(4),
+            max_penalty: Duration::from_secs(60),
+            stable_period: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Two rates, switching to the low one on trouble and back only after a penalty time.
+#[derive(Debug, Clone)]
+pub struct GoodBad {
+    config: GoodBadConfig,
+    good: bool,
+    penalty: Duration,
+    // When good mode last started, or good conditions last resumed in bad mode
+    since: Option<Instant>,
+}
+
+impl GoodBad {
+    pub fn new(config: GoodBadConfig) -> Self {
+        Self { config, good: true, penalty: config.initial_penalty, since: None }
+    }
+
+    pub fn is_good(&self) -> bool {
+        self.good
+    }
+
+    /// Time good conditions must last before leaving bad mode.
+    pub fn penalty(&self) -> Duration {
+        self.penalty
+    }
+
+    fn bad(&mut self, now: Instant) {
+        if self.good {
+            let relapsed = self.since.is_some_and(|since| now.saturating_duration_since(since) < self.config.stable_period);
+            if relapsed {
+                self.penalty = (self.penalty * 2).min(self.config.max_penalty);
+            }
+            self.good = false;
+        }
+        self.since = Some(now);
+    }
+}
+
+impl CongestionController for GoodBad {
+    fn on_ack(&mut self, rtt: Option<Duration>, now: Instant) {
+        if rtt.is_some_and(|rtt| rtt > self.config.rtt_threshold) {
+            self.bad(now);
+            return;
+        }
+        let since = *self.since.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(since);
+        if !self.good && elapsed >= self.penalty {
+            self.good = true;
+            self.since = Some(now);
+        } else if self.good && elapsed >= self.config.stable_period {
+            self.penalty = (self.penalty / 2).max(self.config.initial_penalty);
+            self.since = Some(now);
+        }
+    }
+
+    fn on_loss(&mut self, now: Instant) {
+        self.bad(now);
+    }
+
+    fn allowed_bandwidth(&self) -> f32 {
+        if self.good { self.config.good_rate } else { self.config.bad_rate }
+    }
+}
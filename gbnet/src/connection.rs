@@ -1,16 +1,72 @@
 // connection.rs - Connection state management for reliable UDP
 use std::net::SocketAddr;
-use std::time::Instant;
-use std::collections::VecDeque;
-use rand::random;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use std::collections::{HashMap, VecDeque};
+use rand::{rngs::OsRng, RngCore};
 
 use crate::{
     NetworkConfig, NetworkStats,
-    packet::{Packet, PacketHeader, PacketType, disconnect_reason, sequence_greater_than},
-    socket::{UdpSocket, SocketError},
-    reliability::ReliableEndpoint,
-    channel::{Channel, ChannelError},
+    packet::{HeaderExtensions, Packet, PacketHeader, PacketType, PacketView, FRAGMENT_HEADER_BYTES, PAYLOAD_HEADER_BYTES, capability, control_message, deny_reason, disconnect_reason, header_extension, sequence_greater_than, split_fragments},
+    socket::{SendErrorClass, UdpSocket, SocketError},
+    reliability::{FragmentReassembler, PacketAgeEstimator, ReliableEndpoint},
+    pmtu::{MtuSearch, MIN_PROBE_TIMEOUT, parse_probe_extension, probe_extension},
+    rtt::RttHistogram,
+    config::{ChannelConfig, ChannelGroupConfig, ChannelMismatchPolicy, ChannelScheduling, ConnectionRole, PayloadMode, SendErrorPolicy, SlowConsumer},
+    channel::{BatchItems, Channel, ChannelError, ChannelGroup, ChannelMismatch, ChannelStats, OutgoingMessage, CONTROL_CHANNEL, MESSAGE_HEADER_BYTES, KEYED_HEADER_BYTES, write_message, read_messages, count_messages},
+    crypto::{sealed_nonce, ChannelKey, KeyExchange, ReplayWindow, SequenceMask, SessionKeys, SEAL_OVERHEAD},
+    ticket::{ResumptionTicket, TICKET_ID_BYTES},
+    token::ConnectToken,
+    extensions::Extensions,
+    rng::{stream, DeterministicRng, SEED_BYTES},
+    telemetry::PacketTelemetry,
+    trace::{TraceEvent, TraceEventKind},
+    timestamp::{TimestampEpoch, EPOCH_BYTES},
+    instant::{wall_clock, SyncedClock},
+    delta::StringBaselines,
+    snapshot::{SnapshotReceiver, SnapshotSender, CHUNK_HEADER_BYTES},
+    serialize::{BitDeserialize, BitSerialize},
+    compression::{compress_payload, decompress_payload, Codec, NO_CODEC},
+    debug::{ChannelDebugState, DebugSnapshot, DebugTimers},
+    stats::{ConnectionStats, StatsSnapshot},
+    legacy::{self, LegacyError, LegacyMessage},
 };
+#[cfg(feature = "tokio")]
+use crate::socket::AsyncUdpSocket;
+
+/// How often messages held back by a bandwidth budget are rechecked when waiting.
+const PACING_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Furthest an obfuscated sequence may be from the newest received before it is dropped.
+const SEQUENCE_WINDOW: u16 = 1024;
+
+/// Packets sent once a session is up, whose sequences obfuscation permutes.
+fn is_session_traffic(packet_type: &PacketType) -> bool {
+    matches!(packet_type, PacketType::Payload { .. } | PacketType::Control | PacketType::KeepAlive | PacketType::Disconnect { .. })
+}
+
+// Header bytes a payload packet's send stamp takes, for `max_packet_age`: the 4-byte stamp
+// plus 4 bytes of extension framing (block header and length byte)
+const SEND_STAMP_BYTES: usize = 8;
+
+/// Header bytes payload packets set aside for a send stamp.
+fn stamp_overhead(config: &NetworkConfig) -> usize {
+    if config.max_packet_age.is_some() { SEND_STAMP_BYTES } else { 0 }
+}
+
+/// Bytes every session packet sets aside for sealing when `encrypt_packets` may be agreed.
+fn packet_sealing(config: &NetworkConfig) -> usize {
+    if config.encrypts_packets() { SEAL_OVERHEAD } else { 0 }
+}
+
+/// Message bytes in each fragment: what is left of a packet after the headers, at most
+/// `fragment_threshold`.
+fn fragment_size(config: &NetworkConfig, mtu: usize, encrypted: bool) -> usize {
+    let sealing = if encrypted { SEAL_OVERHEAD } else { 0 };
+    mtu
+        .saturating_sub(PAYLOAD_HEADER_BYTES + FRAGMENT_HEADER_BYTES + stamp_overhead(config) + packet_sealing(config) + sealing)
+        .min(config.fragment_threshold)
+        .max(1)
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ConnectionState {
@@ -21,6 +77,85 @@ pub enum ConnectionState {
     Disconnecting,
 }
 
+/// Events raised by a connection, drained with [`Connection::poll_event`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    /// The bandwidth cap started holding back messages; `queued_bytes` are waiting to be sent
+    BandwidthLimited { queued_bytes: usize },
+    /// The bandwidth cap is no longer holding back messages
+    BandwidthRestored,
+    /// A chunk of the join snapshot arrived; `total` is the full snapshot size in bytes
+    JoinSnapshotProgress { received: usize, total: usize },
+    /// The join snapshot is complete and ready for [`Connection::take_join_snapshot`];
+    /// messages held on other channels are released from here on
+    JoinSnapshotReady { size: usize },
+    /// Outcome of a message sent with [`Connection::send_with_ttl`]
+    MessageDelivery { channel: u8, sequence: u16, status: DeliveryStatus },
+    /// The server no longer knows this connection (it restarted or dropped it), so the
+    /// connection was reset to `Disconnected`; call `connect` to start a new session
+    SessionReset,
+    /// The server is full and holds this client in its join queue, `position` 1 being next in line
+    JoinQueuePosition { position: u16 },
+    /// The network reported the peer unreachable (ICMP port unreachable); `reports` counts
+    /// them since the last packet arrived
+    PeerUnreachable { reports: u32 },
+    /// A connection request went out; `attempt` 1 is the first, later ones are resends
+    Connecting { attempt: u32 },
+    /// A packet was dropped because the socket would not send it, under `SendErrorPolicy::Report`
+    SendFailed { class: SendErrorClass },
+    /// `missing` packets never arrived between `last_sequence` and `sequence`, at least
+    /// `sequence_gap_threshold`; `silence` is how long nothing newer arrived before `sequence`
+    SequenceGap { missing: u16, last_sequence: u16, sequence: u16, silence: Duration },
+    /// The peer paused; nothing is sent to it, and it is kept for `pause_grace_period`
+    PeerPaused,
+    /// The peer is back from a pause and held messages are flowing again
+    PeerResumed,
+    /// The peer answered [`Connection::resume`], so the session survived the pause
+    Resumed,
+    /// The server's channels are configured differently; messages on the listed channels
+    /// will not decode or arrive as expected
+    ChannelMismatch(ChannelMismatch),
+    /// Path MTU discovery found the path carries `mtu`-byte packets; budgets use it from now on
+    MtuChanged { mtu: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// The packet carrying the message was acknowledged
+    Delivered,
+    /// The TTL passed, or the packet ran out of resends, before an ack; the message was dropped
+    Expired,
+}
+
+// TTL and keyed messages coalesced into one reliable payload packet
+#[derive(Debug)]
+struct TrackedPacket {
+    channel: u8,
+    messages: Vec<TrackedMessage>,
+    sent: bool,
+}
+
+#[derive(Debug)]
+struct TrackedMessage {
+    sequence: u16,
+    expires: Option<Instant>,
+    key: Option<u32>,
+}
+
+impl TrackedPacket {
+    /// Reports the outcome of the packet's TTL messages and frees its keys.
+    fn settle(self, channels: &mut [Channel], events: &mut VecDeque<ConnectionEvent>, status: DeliveryStatus) {
+        for message in self.messages {
+            if message.expires.is_some() {
+                events.push_back(ConnectionEvent::MessageDelivery { channel: self.channel, sequence: message.sequence, status });
+            }
+            if let Some(key) = message.key {
+                channels[self.channel as usize].release_key(key, message.sequence);
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ConnectionError {
     NotConnected,
@@ -29,10 +164,55 @@ pub enum ConnectionError {
     Timeout,
     ProtocolMismatch,
     InvalidPacket,
+    KeyExchangeFailed,
+    /// An `encrypted` channel has no session keys to derive its key from, or the server did
+    /// not agree to `encrypt_packets`
+    EncryptionUnavailable,
+    MemoryLimitExceeded,
+    /// The application stopped draining a reliable channel set to `SlowConsumer::Disconnect`
+    SlowConsumer,
+    /// `peer_unreachable_limit` unreachable reports arrived with no packet in between
+    PeerUnreachable,
+    RoomNotFound,
+    /// No `join_snapshot_channel` is configured, or a snapshot is still being sent
+    JoinSnapshotUnavailable,
+    /// Observers may not send on `player_only` channels
+    PlayerOnlyChannel,
+    /// Pausing needs the control channel, which the peer did not negotiate
+    PauseUnavailable,
+    /// The server's channels are configured differently, under `ChannelMismatchPolicy::Fail`
+    ChannelMismatch(ChannelMismatch),
     SocketError(SocketError),
     ChannelError(ChannelError),
 }
 
+impl std::fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionError::NotConnected => write!(f, "not connected"),
+            ConnectionError::AlreadyConnected => write!(f, "already connected"),
+            ConnectionError::ConnectionDenied(reason) => write!(f, "connection denied (reason {})", reason),
+            ConnectionError::Timeout => write!(f, "connection timed out"),
+            ConnectionError::ProtocolMismatch => write!(f, "protocol id mismatch"),
+            ConnectionError::InvalidPacket => write!(f, "invalid packet"),
+            ConnectionError::KeyExchangeFailed => write!(f, "key exchange failed"),
+            ConnectionError::EncryptionUnavailable => write!(f, "encryption unavailable"),
+            ConnectionError::MemoryLimitExceeded => write!(f, "connection memory limit exceeded"),
+            ConnectionError::SlowConsumer => write!(f, "reliable channel not drained"),
+            ConnectionError::PeerUnreachable => write!(f, "peer unreachable"),
+            ConnectionError::RoomNotFound => write!(f, "room not found"),
+            ConnectionError::JoinSnapshotUnavailable => write!(f, "join snapshot unavailable"),
+            ConnectionError::PlayerOnlyChannel => write!(f, "observers may not send on player-only channels"),
+            ConnectionError::PauseUnavailable => write!(f, "pause not negotiated"),
+            ConnectionError::ChannelMismatch(mismatch) => write!(f, "channel layout mismatch on channels {:?}", mismatch.differing),
+            ConnectionError::SocketError(e) => write!(f, "socket error: {}", e),
+            ConnectionError::ChannelError(e) => write!(f, "channel error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
 impl From<SocketError> for ConnectionError {
     fn from(err: SocketError) -> Self {
         ConnectionError::SocketError(err)
@@ -54,6 +234,18 @@ pub struct Connection {
     // Connection handshake
     client_salt: u64,
     server_salt: u64,
+    key_exchange: Option<KeyExchange>,
+    // The server's cookie, echoed on requests until the challenge arrives
+    request_cookie: Option<Vec<u8>>,
+    session_keys: Option<SessionKeys>,
+    resumption_ticket: Option<ResumptionTicket>,
+    resuming: bool,
+    // Token bytes a client sends with its requests; the verified token on the server
+    connect_token: Option<Vec<u8>>,
+    authorized_token: Option<ConnectToken>,
+    session_seed: Option<u64>,
+    timestamp_epoch: Option<TimestampEpoch>,
+    role: ConnectionRole,
     
     // Timing
     last_packet_send_time: Instant,
@@ -61,55 +253,204 @@ pub struct Connection {
     connection_start_time: Option<Instant>,
     connection_request_time: Option<Instant>,
     connection_retry_count: u32,
+    // Wait before the next connection request resend, jittered when the request is sent
+    connection_retry_wait: Duration,
+    // Place in the server's join queue while waiting for a slot
+    queue_position: Option<u16>,
     
     // Reliability
     local_sequence: u16,
     remote_sequence: u16,
-    ack_bits: u32,
+    // Newest session packet received and when, for `sequence_gap_threshold`
+    newest_received: Option<(u16, Instant)>,
     reliability: ReliableEndpoint,
+    // Messages arriving as fragments, until their last piece is in
+    fragments: FragmentReassembler,
+    // Send stamps on payload packets, and the peer's measured against our clock
+    packet_ages: PacketAgeEstimator,
+    // Packet size budgets use, raised by path MTU discovery while `mtu_search` runs
+    mtu: usize,
+    mtu_search: Option<MtuSearch>,
+    // RTT samples behind the percentiles in `stats`
+    rtt_samples_reported: u64,
+    
+    // Pause
+    paused: bool,
+    peer_paused: bool,
+    // Nonce of a `RESUME` not yet answered
+    resume_probe: Option<u32>,
     
     // Channels
     channels: Vec<Channel>,
+    channel_groups: Vec<ChannelGroup>,
+    // Channel ids by descending priority, ties in id order
+    send_order: Vec<usize>,
+    // Whether each channel got a packet out this flush; kept so flushing does not allocate
+    sent_this_flush: Vec<bool>,
+    bandwidth_cap: Option<ChannelGroup>,
+    bandwidth_limited: bool,
+    // Join snapshot packets assembled since another channel last got one
+    fragment_streak: usize,
+    // Weighted fair scheduling: byte allowance per channel (negative after an overdraw), the
+    // position in `send_order` whose turn it is, and whether that turn already got its quantum
+    fair_deficit: Vec<isize>,
+    fair_cursor: usize,
+    fair_turn_open: bool,
+    // Negotiated capability bits, and codec id of each channel's payloads (NO_CODEC when uncompressed)
+    capabilities: u32,
+    codecs: Vec<u8>,
+    // (sending, receiving) keys of each `encrypted` channel, and the last nonce sealed with them
+    channel_keys: Vec<Option<(ChannelKey, ChannelKey)>>,
+    seal_nonce: u64,
+    // (sending, receiving) sequence permutations once obfuscation is negotiated
+    sequence_masks: Option<(SequenceMask, SequenceMask)>,
+    // (sending, receiving) keys sealing whole session packets once packet encryption is
+    // negotiated, and the nonces of the sealed packets opened so far
+    packet_keys: Option<(ChannelKey, ChannelKey)>,
+    packet_replay: ReplayWindow,
+    // Salts and telemetry sampling, seeded from `NetworkConfig::random_seed` when set
+    rng: DeterministicRng,
+    // Server wall clock estimated from the epoch sent with the accept, then from time syncs
+    server_clock: SyncedClock,
+    // Protocol messages, outside the user channels and their budgets
+    control: Channel,
+    // ICMP unreachable reports since a packet last arrived
+    unreachable_reports: u32,
+    // Client side: when the next time sync request is due, and the one awaiting its answer
+    next_time_sync: Option<Instant>,
+    time_sync_probe: Option<(u32, Instant)>,
+    // Reliable packets carrying TTL or keyed messages, by packet sequence
+    tracked: HashMap<u16, TrackedPacket>,
+    
+    // Join snapshots
+    snapshot_sender: Option<SnapshotSender>,
+    // Client side: set from accept until the snapshot completes, holding other channels
+    snapshot_receiver: Option<SnapshotReceiver>,
+    receives_join_snapshot: bool,
+    join_snapshot: Option<Vec<u8>>,
     
     // Queues
     send_queue: VecDeque<Packet>,
+    // Reused for every outgoing datagram so sends do not allocate
+    wire_buffer: Vec<u8>,
     recv_queue: VecDeque<Packet>,
+    // A send under `SendErrorPolicy::Retry` failed: when the queue goes again, and failures in a row
+    send_retry_at: Option<Instant>,
+    send_retries: u32,
     
     // Stats
     stats: NetworkStats,
+    
+    // Application state
+    extensions: Extensions,
+    events: VecDeque<ConnectionEvent>,
 }
 
 impl Connection {
     /// Creates a new connection with the given configuration and addresses.
     pub fn new(config: NetworkConfig, local_addr: SocketAddr, remote_addr: SocketAddr) -> Self {
         let mut channels = Vec::with_capacity(config.max_channels);
-        let channel_config = config.default_channel_config;
         for i in 0..config.max_channels {
+            let mut channel_config = config.channel_configs.get(i).copied().unwrap_or(config.default_channel_config);
+            // Larger messages would need more than `max_fragments` fragments
+            let fragmentable = config.max_fragments.saturating_mul(fragment_size(&config, config.mtu, channel_config.encrypted));
+            channel_config.max_message_size = channel_config.max_message_size.min(fragmentable);
             channels.push(Channel::new(i as u8, channel_config));
         }
         
-        let packet_buffer_size = config.packet_buffer_size;
+        let now = config.clock.now();
+        let channel_groups = config.channel_groups.iter().map(|&group| ChannelGroup::new(group, now)).collect();
+        let channel_count = channels.len();
+        let mut send_order: Vec<usize> = (0..channel_count).collect();
+        send_order.sort_by_key(|&id| std::cmp::Reverse(channels[id].config().priority));
+        
+        // A second's worth of budget can be saved up
+        let bandwidth_cap = config.bandwidth_cap.map(|bytes_per_second| ChannelGroup::new(ChannelGroupConfig {
+            bytes_per_second,
+            burst_bytes: bytes_per_second as usize,
+        }, now));
+        
+        let role = config.role;
+        let mtu = config.mtu;
+        let mut rng = config.rng(stream::CONNECTION);
+        let mut reliability = ReliableEndpoint::new(config.packet_buffer_size).with_max_retries(config.max_reliable_retries);
+        if let Some(congestion) = &config.congestion_control {
+            reliability = reliability.with_congestion_controller(congestion.build());
+        }
+        let (max_fragments, fragment_timeout) = (config.max_fragments, config.fragment_timeout);
         
         Self {
             config,
             state: ConnectionState::Disconnected,
             local_addr,
             remote_addr,
-            client_salt: random(),
+            client_salt: rng.next_u64(),
             server_salt: 0,
-            last_packet_send_time: Instant::now(),
-            last_packet_recv_time: Instant::now(),
+            key_exchange: None,
+            request_cookie: None,
+            session_keys: None,
+            resumption_ticket: None,
+            resuming: false,
+            connect_token: None,
+            authorized_token: None,
+            session_seed: None,
+            timestamp_epoch: None,
+            role,
+            last_packet_send_time: now,
+            last_packet_recv_time: now,
             connection_start_time: None,
             connection_request_time: None,
             connection_retry_count: 0,
+            connection_retry_wait: Duration::ZERO,
+            queue_position: None,
             local_sequence: 0,
             remote_sequence: 0,
-            ack_bits: 0,
-            reliability: ReliableEndpoint::new(packet_buffer_size),
+            newest_received: None,
+            reliability,
+            fragments: FragmentReassembler::new(max_fragments, fragment_timeout),
+            packet_ages: PacketAgeEstimator::new(now),
+            mtu,
+            mtu_search: None,
+            rtt_samples_reported: 0,
+            paused: false,
+            peer_paused: false,
+            resume_probe: None,
             channels,
+            channel_groups,
+            send_order,
+            sent_this_flush: vec![false; channel_count],
+            bandwidth_cap,
+            bandwidth_limited: false,
+            fragment_streak: 0,
+            fair_deficit: vec![0; channel_count],
+            fair_cursor: 0,
+            fair_turn_open: false,
+            capabilities: 0,
+            codecs: Vec::new(),
+            channel_keys: Vec::new(),
+            seal_nonce: 0,
+            sequence_masks: None,
+            packet_keys: None,
+            packet_replay: ReplayWindow::default(),
+            rng,
+            server_clock: SyncedClock::new(),
+            control: Channel::new(CONTROL_CHANNEL, ChannelConfig::default()),
+            unreachable_reports: 0,
+            next_time_sync: None,
+            time_sync_probe: None,
+            tracked: HashMap::new(),
+            snapshot_sender: None,
+            snapshot_receiver: None,
+            receives_join_snapshot: false,
+            join_snapshot: None,
             send_queue: VecDeque::new(),
+            wire_buffer: Vec::new(),
+            send_retry_at: None,
+            send_retries: 0,
             recv_queue: VecDeque::new(),
             stats: NetworkStats::default(),
+            extensions: Extensions::new(),
+            events: VecDeque::new(),
         }
     }
     
@@ -120,9 +461,22 @@ impl Connection {
         }
         
         self.state = ConnectionState::Connecting;
-        self.connection_request_time = Some(Instant::now());
+        self.connection_request_time = Some(self.config.clock.now());
         self.connection_retry_count = 0;
         
+        // A ticket skips the handshake and keeps the keys it was issued with.
+        // Otherwise, without a pre-shared key, derive session keys from an ephemeral
+        // exchange; the same key pair is reused across request retries.
+        self.resuming = self.resumption_ticket.as_ref().is_some_and(|ticket| ticket.session_keys.is_some());
+        if let Some(ticket) = self.resumption_ticket.as_ref().filter(|_| self.resuming) {
+            self.session_keys = ticket.session_keys.clone();
+        } else {
+            match self.config.encryption_key {
+                Some(key) => self.session_keys = Some(SessionKeys::from_static(key)),
+                None => self.key_exchange = Some(KeyExchange::new()),
+            }
+        }
+        
         // Send connection request
         self.send_connection_request()?;
         
@@ -135,25 +489,109 @@ impl Connection {
             return Ok(());
         }
         
-        // Send disconnect packet
-        let header = self.create_header();
-        let packet = Packet::new(header, PacketType::Disconnect { reason });
-        self.send_queue.push_back(packet);
-        
+        // Send disconnect packet (queued after the reset so it survives the queue clear)
+        let header = self.next_header();
         self.state = ConnectionState::Disconnecting;
         self.reset_connection();
         
+        let packet = Packet::new(header, PacketType::Disconnect { reason });
+        self.send_queue.push_back(packet);
+        
         Ok(())
     }
     
     /// Updates the connection state, processes send/receive queues, and handles timeouts.
+    ///
+    /// Outgoing packets are only written here when `auto_flush` is enabled; otherwise
+    /// call [`Connection::flush`] once per tick.
     pub fn update(&mut self, socket: &mut UdpSocket) -> Result<(), ConnectionError> {
-        let now = Instant::now();
+        self.update_state(self.config.clock.now())?;
+        
+        if self.config.auto_flush {
+            self.flush(socket)?;
+        }
+        
+        // Receive packets
+        self.receive_packets(socket)?;
+        
+        Ok(())
+    }
+    
+    /// Waits until a datagram arrives or [`Connection::next_service_time`] comes, then
+    /// updates; the async counterpart of sleeping a tick before [`Connection::update`].
+    #[cfg(feature = "tokio")]
+    pub async fn poll(&mut self, socket: &mut AsyncUdpSocket) -> Result<(), ConnectionError> {
+        let now = self.config.clock.now();
+        let wait = self.next_service_time()
+            .map_or(self.config.keepalive_interval, |next| next.saturating_duration_since(now));
+        socket.get_ref().readable(wait).await?;
+        self.update(socket.get_mut())
+    }
+    
+    /// Receives the next message on a channel, polling the connection until one arrives.
+    ///
+    /// Fails with `NotConnected` once the connection is closed and the channel drained.
+    #[cfg(feature = "tokio")]
+    pub async fn recv(&mut self, socket: &mut AsyncUdpSocket, channel_id: u8) -> Result<Vec<u8>, ConnectionError> {
+        loop {
+            if let Some(message) = self.receive(channel_id) {
+                return Ok(message);
+            }
+            if self.state == ConnectionState::Disconnected {
+                return Err(ConnectionError::NotConnected);
+            }
+            self.poll(socket).await?;
+        }
+    }
+    
+    /// Earliest time `update` has work to do without any packet arriving: a timeout,
+    /// handshake retry, keepalive, reliable resend, queued packets or messages waiting on a
+    /// bandwidth budget.
+    pub fn next_service_time(&self) -> Option<Instant> {
+        if self.state == ConnectionState::Disconnected || self.paused {
+            return None;
+        }
+        
+        let mut next = self.last_packet_recv_time + self.receive_timeout();
+        match self.state {
+            ConnectionState::Connected if self.peer_paused => {}
+            ConnectionState::Connecting => {
+                if let Some(request_time) = self.connection_request_time {
+                    next = next.min(request_time + self.connection_retry_wait);
+                }
+            }
+            ConnectionState::Connected => {
+                next = next.min(self.last_packet_send_time + self.config.keepalive_interval);
+                if let Some(resend) = self.reliability.next_resend_time() {
+                    next = next.min(resend);
+                }
+                if let Some(time_sync) = self.next_time_sync {
+                    next = next.min(time_sync);
+                }
+                if self.snapshot_sender.is_some() || self.channels.iter().any(Channel::has_outgoing_messages) {
+                    next = next.min(self.config.clock.now() + PACING_POLL_INTERVAL);
+                }
+            }
+            _ => {}
+        }
+        // Packets queued while receiving (e.g. a handshake reply) go out on the next update
+        if !self.send_queue.is_empty() {
+            next = next.min(self.send_retry_at.unwrap_or_else(|| self.config.clock.now()));
+        }
+        Some(next)
+    }
+    
+    /// Handles timeouts, handshake retries, keepalives and reliable resends without touching the socket.
+    pub(crate) fn update_state(&mut self, now: Instant) -> Result<(), ConnectionError> {
+        // A paused app is not around to notice anything
+        if self.paused {
+            return Ok(());
+        }
         
         // Check for timeout
         if self.state != ConnectionState::Disconnected {
             let time_since_recv = now.duration_since(self.last_packet_recv_time);
-            if time_since_recv > self.config.connection_timeout {
+            if time_since_recv > self.receive_timeout() {
                 self.disconnect(disconnect_reason::TIMEOUT)?;
                 return Err(ConnectionError::Timeout);
             }
@@ -161,9 +599,11 @@ impl Connection {
         
         // Handle connection state
         match self.state {
+            // Keepalives and resends would only run out a paused peer's retries
+            ConnectionState::Connected if self.peer_paused => {}
             ConnectionState::Connecting => {
                 if let Some(request_time) = self.connection_request_time {
-                    if now.duration_since(request_time) > self.config.connection_request_timeout {
+                    if now.duration_since(request_time) >= self.connection_retry_wait {
                         self.connection_retry_count += 1;
                         if self.connection_retry_count > self.config.connection_request_max_retries {
                             self.state = ConnectionState::Disconnected;
@@ -180,33 +620,189 @@ impl Connection {
                 if time_since_send > self.config.keepalive_interval {
                     self.send_keepalive()?;
                 }
+                self.probe_path_mtu(now);
+                
+                self.feed_join_snapshot()?;
+                self.stats.fragments_expired += self.fragments.expire(now) as u64;
+                
+                if self.next_time_sync.is_some_and(|due| now >= due) {
+                    self.send_time_sync_request(now);
+                }
                 
                 // Update reliability system
                 let packets_to_retry = self.reliability.update(now);
                 for (sequence, data) in packets_to_retry {
-                    // Recreate the packet for retransmission
-                    let mut header = self.create_header();
-                    header.sequence = sequence;
-                    // For now, assume it's a payload packet on channel 0
-                    let packet = Packet::new(header, PacketType::Payload { channel: 0, is_fragment: false })
-                        .with_payload(data);
-                    self.send_queue.push_back(packet);
+                    // Resend the original packet under its own sequence with fresh acks
+                    if let Ok(mut packet) = Packet::deserialize(&data) {
+                        if !self.prune_resend(sequence, &mut packet, now) {
+                            continue;
+                        }
+                        let (ack, ack_bits) = self.reliability.get_ack_info();
+                        packet.header.ack = ack;
+                        packet.header.ack_bits = ack_bits;
+                        self.send_queue.push_back(packet);
+                    }
+                }
+                
+                // Packets that ran out of resends take their TTL messages with them
+                let lost: Vec<u16> = self.tracked.iter()
+                    .filter(|(&sequence, packet)| packet.sent && !self.reliability.is_in_flight(sequence))
+                    .map(|(&sequence, _)| sequence)
+                    .collect();
+                for sequence in lost {
+                    if let Some(packet) = self.tracked.remove(&sequence) {
+                        packet.settle(&mut self.channels, &mut self.events, DeliveryStatus::Expired);
+                    }
                 }
             }
             _ => {}
         }
         
-        // Process send queue
-        self.process_send_queue(socket)?;
+        self.report_rtt_percentiles();
         
-        // Receive packets
-        self.receive_packets(socket)?;
+        // A peer that floods us, or never drains what we queue, is cut off before it exhausts memory
+        self.stats.memory_usage = self.memory_usage();
+        if self.config.max_connection_memory.is_some_and(|limit| self.stats.memory_usage > limit) {
+            self.disconnect(disconnect_reason::MEMORY_LIMIT)?;
+            return Err(ConnectionError::MemoryLimitExceeded);
+        }
+        if self.channels.iter().any(Channel::receive_overrun) {
+            self.disconnect(disconnect_reason::SLOW_CONSUMER)?;
+            return Err(ConnectionError::SlowConsumer);
+        }
+        
+        Ok(())
+    }
+    
+    /// Copies RTT percentiles and jitter into `stats` when new samples came in.
+    fn report_rtt_percentiles(&mut self) {
+        let histogram = self.reliability.rtt_histogram();
+        if histogram.count() == self.rtt_samples_reported {
+            return;
+        }
+        self.rtt_samples_reported = histogram.count();
+        let millis = |rtt: Option<Duration>| rtt.unwrap_or_default().as_secs_f32() * 1000.0;
+        let [p50, p95, p99] = histogram.percentiles([50.0, 95.0, 99.0]);
+        self.stats.rtt_p50 = millis(p50);
+        self.stats.rtt_p95 = millis(p95);
+        self.stats.rtt_p99 = millis(p99);
+        self.stats.jitter = millis(Some(histogram.jitter()));
+    }
+    
+    /// Distribution of this connection's RTT samples, since the last `stats_reset` if any.
+    pub fn rtt_histogram(&self) -> &RttHistogram {
+        self.reliability.rtt_histogram()
+    }
+    
+    /// Bytes currently held on behalf of this connection.
+    pub fn memory_usage(&self) -> usize {
+        let queued: usize = self.send_queue.iter().chain(&self.recv_queue).map(|packet| packet.payload.len()).sum();
+        let buffered: usize = self.channels.iter().chain([&self.control]).map(Channel::memory_usage).sum();
+        let snapshots = self.snapshot_sender.as_ref().map_or(0, SnapshotSender::memory_usage)
+            + self.snapshot_receiver.as_ref().map_or(0, SnapshotReceiver::memory_usage)
+            + self.join_snapshot.as_ref().map_or(0, Vec::len);
+        queued + buffered + snapshots + self.reliability.memory_usage() + self.fragments.memory_usage()
+    }
+    
+    /// Streams a full state baseline to the peer over `join_snapshot_channel`.
+    ///
+    /// Capture `baseline` when the client joins and send deltas as usual from then on; the
+    /// client holds them until the baseline is complete. Counts against `max_connection_memory`
+    /// until it has been queued.
+    pub fn send_join_snapshot(&mut self, baseline: Vec<u8>) -> Result<(), ConnectionError> {
+        if self.state != ConnectionState::Connected {
+            return Err(ConnectionError::NotConnected);
+        }
+        let Some(channel_id) = self.join_snapshot_channel() else {
+            return Err(ConnectionError::JoinSnapshotUnavailable);
+        };
+        if self.snapshot_sender.is_some() {
+            return Err(ConnectionError::JoinSnapshotUnavailable);
+        }
         
+        let chunk_size = self.max_message_size()
+            .min(self.channels[channel_id].config().max_message_size)
+            .saturating_sub(CHUNK_HEADER_BYTES);
+        self.snapshot_sender = Some(SnapshotSender::new(baseline, chunk_size));
+        self.feed_join_snapshot()
+    }
+    
+    /// Progress of the join snapshot as `(bytes, total)`: bytes queued for sending on the
+    /// server, bytes received on the client. `None` when no snapshot is in flight.
+    pub fn join_snapshot_progress(&self) -> Option<(usize, usize)> {
+        if let Some(sender) = &self.snapshot_sender {
+            return Some(sender.progress());
+        }
+        match self.snapshot_receiver.as_ref()?.progress() {
+            (received, Some(total)) => Some((received, total)),
+            _ => None,
+        }
+    }
+    
+    /// Takes the completed join snapshot, once [`ConnectionEvent::JoinSnapshotReady`] has been raised.
+    pub fn take_join_snapshot(&mut self) -> Option<Vec<u8>> {
+        self.join_snapshot.take()
+    }
+    
+    // The configured snapshot channel, if it exists
+    fn join_snapshot_channel(&self) -> Option<usize> {
+        self.config.join_snapshot_channel
+            .map(usize::from)
+            .filter(|&channel_id| channel_id < self.channels.len())
+    }
+    
+    // Tops up the snapshot channel with the next few chunks
+    fn feed_join_snapshot(&mut self) -> Result<(), ConnectionError> {
+        let channel_id = self.join_snapshot_channel();
+        let (Some(sender), Some(channel_id)) = (self.snapshot_sender.as_mut(), channel_id) else {
+            return Ok(());
+        };
+        let channel = &mut self.channels[channel_id];
+        while sender.wants_chunk(channel.pending_bytes()) {
+            let Some(chunk) = sender.next_chunk() else { break };
+            channel.send(&chunk, true)?;
+        }
+        if sender.is_done() {
+            self.snapshot_sender = None;
+        }
         Ok(())
     }
     
+    // Rebuilds the join snapshot from chunks delivered on its channel
+    fn receive_join_snapshot(&mut self, channel_id: usize) {
+        while let Some(chunk) = self.channels[channel_id].receive() {
+            let receiver = self.snapshot_receiver.get_or_insert_with(SnapshotReceiver::default);
+            if receiver.on_chunk(&chunk).is_none() {
+                continue;
+            }
+            let (received, total) = receiver.progress();
+            let total = total.unwrap_or_default();
+            self.events.push_back(ConnectionEvent::JoinSnapshotProgress { received, total });
+            if receiver.is_complete() {
+                if let Some(receiver) = self.snapshot_receiver.take() {
+                    self.join_snapshot = Some(receiver.into_data());
+                }
+                self.events.push_back(ConnectionEvent::JoinSnapshotReady { size: total });
+            }
+        }
+    }
+    
     /// Sends data on a specific channel.
     pub fn send(&mut self, channel_id: u8, data: &[u8], reliable: bool) -> Result<(), ConnectionError> {
+        self.check_send(channel_id)?;
+        self.channels[channel_id as usize].send(data, reliable)?;
+        Ok(())
+    }
+    
+    /// Sends a `bytes::Bytes` payload, reusing its buffer when nothing else shares it.
+    #[cfg(feature = "bytes")]
+    pub fn send_bytes(&mut self, channel_id: u8, data: bytes::Bytes, reliable: bool) -> Result<(), ConnectionError> {
+        self.check_send(channel_id)?;
+        self.channels[channel_id as usize].send_owned(data.into(), reliable)?;
+        Ok(())
+    }
+    
+    fn check_send(&self, channel_id: u8) -> Result<(), ConnectionError> {
         if self.state != ConnectionState::Connected {
             return Err(ConnectionError::NotConnected);
         }
@@ -215,154 +811,1346 @@ impl Connection {
             return Err(ConnectionError::InvalidPacket);
         }
         
-        self.channels[channel_id as usize].send(data, reliable)?;
+        if self.rejects_channel(channel_id) {
+            return Err(ConnectionError::PlayerOnlyChannel);
+        }
+        Ok(())
+    }
+    
+    /// Sends a message in its pre-gbnet encoding on a `PayloadMode::Legacy` channel; see
+    /// [`Channel::send_legacy`].
+    pub fn send_legacy<T: LegacyMessage>(&mut self, channel_id: u8, message: &T, reliable: bool) -> Result<(), ConnectionError> {
+        let frame = legacy::encode_frame(message).map_err(|_| ChannelError::InvalidLegacyMessage)?;
+        self.check_send(channel_id)?;
+        self.channels[channel_id as usize].send_legacy_frame(frame, reliable)?;
         Ok(())
     }
     
+    /// Sends many messages of one type in as few packets as possible; see [`Channel::send_batch`].
+    ///
+    /// Returns the number of batch messages queued, each sized to fit a single packet.
+    pub fn send_batch<I>(&mut self, channel_id: u8, items: I, reliable: bool) -> Result<usize, ConnectionError>
+    where
+        I: IntoIterator,
+        I::Item: BitSerialize,
+    {
+        self.check_send(channel_id)?;
+        let max_size = self.max_message_size();
+        let profiler = self.config.wire_profiler.clone();
+        let items = items.into_iter().inspect(move |item| {
+            if let Some(profiler) = &profiler {
+                profiler.record(item);
+            }
+        });
+        Ok(self.channels[channel_id as usize].send_batch_within(items, reliable, max_size)?)
+    }
+    
+    /// Sends a reliable message that is dropped if it is not delivered within `ttl`.
+    ///
+    /// Returns the message sequence; a [`ConnectionEvent::MessageDelivery`] with that sequence
+    /// reports whether it was delivered or expired. On unreliable channels the TTL only bounds
+    /// how long the message may wait to be sent, and no outcome is reported once it is.
+    pub fn send_with_ttl(&mut self, channel_id: u8, data: &[u8], ttl: Duration) -> Result<u16, ConnectionError> {
+        self.check_send(channel_id)?;
+        let expires = self.config.clock.now() + ttl;
+        Ok(self.channels[channel_id as usize].send_until(data, expires)?)
+    }
+    
+    /// Holds a message until `at`, for events that must go out at a precise time such as a
+    /// synchronized countdown, without timers in game code.
+    ///
+    /// It is queued by the first `update` or `flush` at or after `at`, so it goes out within
+    /// one tick of its time, and is dropped if the connection closes first.
+    pub fn send_at(&mut self, channel_id: u8, data: &[u8], at: Instant, reliable: bool) -> Result<(), ConnectionError> {
+        self.check_send(channel_id)?;
+        Ok(self.channels[channel_id as usize].send_at(data, at, reliable)?)
+    }
+    
+    /// Sends data in the slot for `key`, replacing the key's previous message on this channel
+    /// while it is still queued or awaiting its ack, so fast producers cannot bloat the queue.
+    ///
+    /// Fails with `ChannelError::KeySpaceFull` when a new key would exceed the channel's
+    /// `max_pending_keys`.
+    pub fn send_keyed(&mut self, channel_id: u8, key: u32, data: &[u8], reliable: bool) -> Result<(), ConnectionError> {
+        self.check_send(channel_id)?;
+        if data.len() > self.max_message_size() {
+            return Err(ChannelError::MessageTooLarge.into());
+        }
+        
+        Ok(self.channels[channel_id as usize].send_keyed(key, data, reliable)?)
+    }
+    
+    /// Sends a new version of the state stored under `key` on a `latest_only` channel.
+    ///
+    /// Versions still queued or awaiting an ack are dropped in its favour, and the receiver
+    /// never delivers a version older than one it already has; intermediate versions may be
+    /// skipped. The key is not handed to the receiver, so encode it in `data` if needed.
+    pub fn send_latest(&mut self, channel_id: u8, key: u32, data: &[u8]) -> Result<(), ConnectionError> {
+        self.check_send(channel_id)?;
+        if data.len() + KEYED_HEADER_BYTES > self.max_message_size() {
+            return Err(ChannelError::MessageTooLarge.into());
+        }
+        
+        Ok(self.channels[channel_id as usize].send_latest(key, data)?)
+    }
+    
+    /// Strips messages past their TTL, or replaced by a newer message with their key, from a
+    /// packet about to be resent.
+    ///
+    /// Returns false when nothing is left to resend.
+    fn prune_resend(&mut self, sequence: u16, packet: &mut Packet, now: Instant) -> bool {
+        // A fragment is resent whole: the receiver needs every piece of the message
+        if matches!(packet.packet_type, PacketType::Payload { is_fragment: true, .. }) {
+            return true;
+        }
+        let Some(entry) = self.tracked.get_mut(&sequence) else {
+            return true;
+        };
+        let channel = entry.channel;
+        let channel_state = &mut self.channels[channel as usize];
+        let events = &mut self.events;
+        let mut dropped = Vec::new();
+        let mut superseded = 0;
+        entry.messages.retain(|message| {
+            if message.expires.is_some_and(|expires| now >= expires) {
+                events.push_back(ConnectionEvent::MessageDelivery { channel, sequence: message.sequence, status: DeliveryStatus::Expired });
+                if let Some(key) = message.key {
+                    channel_state.release_key(key, message.sequence);
+                }
+            } else if message.key.is_some_and(|key| channel_state.is_superseded(key, message.sequence)) {
+                superseded += 1;
+            } else {
+                return true;
+            }
+            dropped.push(message.sequence);
+            false
+        });
+        if entry.messages.is_empty() {
+            self.tracked.remove(&sequence);
+        }
+        if dropped.is_empty() {
+            return true;
+        }
+        channel_state.note_superseded(superseded);
+        
+        let mut payload = Vec::new();
+        for (message, data) in read_messages(&packet.payload).unwrap_or_default() {
+            if !dropped.contains(&message) {
+                write_message(&mut payload, message, &data);
+            }
+        }
+        if payload.is_empty() {
+            self.reliability.cancel(sequence);
+            return false;
+        }
+        packet.payload = payload;
+        true
+    }
+    
+    /// Assembles queued channel messages into packets and writes every queued packet to the socket.
+    ///
+    /// Messages sent since the last flush are coalesced per channel into as few
+    /// MTU-sized packets as possible.
+    pub fn flush(&mut self, socket: &mut UdpSocket) -> Result<(), ConnectionError> {
+        self.assemble_packets();
+        self.process_send_queue(socket)
+    }
+    
+    /// Largest message that fits in one packet under the configured MTU; larger ones are
+    /// sent as fragments.
+    pub fn max_message_size(&self) -> usize {
+        let sealing = if self.channels.iter().any(|channel| channel.config().encrypted) { SEAL_OVERHEAD } else { 0 };
+        self.mtu
+            .saturating_sub(PAYLOAD_HEADER_BYTES + MESSAGE_HEADER_BYTES + stamp_overhead(&self.config) + packet_sealing(&self.config) + sealing)
+            .min(u16::MAX as usize)
+    }
+    
     /// Receives data from a specific channel.
     pub fn receive(&mut self, channel_id: u8) -> Option<Vec<u8>> {
         if channel_id as usize >= self.channels.len() {
             return None;
         }
         
+        // Deltas wait until the baseline they apply to is complete
+        if self.snapshot_receiver.is_some() {
+            return None;
+        }
+        
         self.channels[channel_id as usize].receive()
     }
     
-    /// Creates a packet header with current sequence and ack information.
-    fn create_header(&self) -> PacketHeader {
+    /// Receives the next message of a channel as `bytes::Bytes`, without copying it.
+    #[cfg(feature = "bytes")]
+    pub fn receive_bytes(&mut self, channel_id: u8) -> Option<bytes::Bytes> {
+        self.receive(channel_id).map(bytes::Bytes::from)
+    }
+    
+    /// Receives the next message of a channel as a batch from [`Connection::send_batch`].
+    pub fn receive_batch<T: BitDeserialize>(&mut self, channel_id: u8) -> Option<BatchItems<T>> {
+        self.receive(channel_id).and_then(BatchItems::decode)
+    }
+    
+    pub(crate) fn payload_mode(&self, channel_id: u8) -> Option<PayloadMode> {
+        self.channels.get(channel_id as usize).map(|channel| channel.config().payload_mode)
+    }
+    
+    /// Receives the next message of a `PayloadMode::Legacy` channel, decoded with its legacy codec.
+    pub fn receive_legacy<T: LegacyMessage>(&mut self, channel_id: u8) -> Option<Result<T, LegacyError>> {
+        if self.payload_mode(channel_id)? != PayloadMode::Legacy {
+            return None;
+        }
+        self.receive(channel_id).map(|frame| legacy::decode_frame(&frame))
+    }
+    
+    /// Baselines for `#[delta_string]` fields sent on a channel.
+    ///
+    /// Serialize outgoing messages inside [`StringBaselines::scope`] with these.
+    pub fn send_baselines(&mut self, channel_id: u8) -> Option<&mut StringBaselines> {
+        self.channels.get_mut(channel_id as usize).map(Channel::send_baselines)
+    }
+    
+    /// Baselines for `#[delta_string]` fields received on a channel.
+    pub fn receive_baselines(&mut self, channel_id: u8) -> Option<&mut StringBaselines> {
+        self.channels.get_mut(channel_id as usize).map(Channel::receive_baselines)
+    }
+    
+    /// Creates a packet header with the next sequence and current ack information.
+    fn next_header(&mut self) -> PacketHeader {
+        let sequence = self.local_sequence;
+        self.local_sequence = self.local_sequence.wrapping_add(1);
+        let (ack, ack_bits) = self.reliability.get_ack_info();
         PacketHeader {
             protocol_id: self.config.protocol_id,
-            sequence: self.local_sequence,
-            ack: self.remote_sequence,
-            ack_bits: self.ack_bits,
+            sequence,
+            ack,
+            ack_bits,
         }
     }
     
-    /// Sends a connection request packet.
-    fn send_connection_request(&mut self) -> Result<(), ConnectionError> {
-        let header = PacketHeader {
-            protocol_id: self.config.protocol_id,
-            sequence: 0,
-            ack: 0,
-            ack_bits: 0,
-        };
+    /// Drains channel send buffers into payload packets on the send queue.
+    fn assemble_packets(&mut self) {
+        // Messages sent to or by a paused end wait in their channels until it resumes
+        if self.state != ConnectionState::Connected || self.paused || self.peer_paused {
+            return;
+        }
         
-        let packet = Packet::new(header, PacketType::ConnectionRequest);
-        self.send_queue.push_back(packet);
-        Ok(())
+        self.assemble_control();
+        
+        let now = self.config.clock.now();
+        // The congestion controller's rate tightens the configured cap, or becomes the cap
+        if let Some(allowed) = self.reliability.allowed_bandwidth() {
+            let rate = self.config.bandwidth_cap.map_or(allowed, |cap| cap.min(allowed));
+            let cap = ChannelGroupConfig { bytes_per_second: rate, burst_bytes: rate as usize };
+            self.bandwidth_cap.get_or_insert_with(|| ChannelGroup::new(cap, now)).set_config(cap);
+        }
+        self.stats.allowed_bandwidth = self.bandwidth_cap.as_ref().map(ChannelGroup::bytes_per_second);
+        for group in self.channel_groups.iter_mut().chain(&mut self.bandwidth_cap) {
+            group.refill(now);
+        }
+        
+        let send_order = std::mem::take(&mut self.send_order);
+        for &channel_id in &send_order {
+            self.channels[channel_id].release_scheduled(now);
+            for sequence in self.channels[channel_id].drop_expired(now) {
+                self.events.push_back(ConnectionEvent::MessageDelivery {
+                    channel: channel_id as u8,
+                    sequence,
+                    status: DeliveryStatus::Expired,
+                });
+            }
+        }
+        
+        // Grouped channels stop once their group's budget runs out and keep the rest
+        // buffered, so lower priority channels in a group only get what is left over
+        let transfer = self.config.fragment_burst.and(self.join_snapshot_channel());
+        let mut sent = std::mem::take(&mut self.sent_this_flush);
+        sent.clear();
+        sent.resize(self.channels.len(), false);
+        let mut capped = false;
+        match self.config.channel_scheduling {
+            ChannelScheduling::Priority => {
+                for &channel_id in &send_order {
+                    let (packets, _, channel_capped) = self.assemble_turn(channel_id, transfer, usize::MAX);
+                    sent[channel_id] |= packets > 0;
+                    capped |= channel_capped;
+                }
+            }
+            ChannelScheduling::WeightedFair => capped = self.assemble_weighted(&send_order, transfer, &mut sent),
+        }
+        let transfer_deferred = transfer.filter(|&channel_id| self.channels[channel_id].has_outgoing_messages());
+        if let Some(channel_id) = transfer_deferred.filter(|_| !capped) {
+            // The transfer yields after a burst, then resumes once the others had their turn.
+            // Channels that are waiting but held back by their own budget do not stall it
+            let others_sent = sent.iter().enumerate().any(|(id, &sent)| sent && id != channel_id);
+            let allowance = if others_sent { self.fragment_allowance(channel_id) } else { usize::MAX };
+            let (packets, _, channel_capped) = self.assemble_channel(channel_id, allowance, usize::MAX);
+            self.fragment_streak += packets;
+            sent[channel_id] |= packets > 0;
+            capped |= channel_capped;
+        }
+        for (channel, &sent) in self.channels.iter_mut().zip(&sent) {
+            channel.record_send_turn(sent);
+        }
+        self.send_order = send_order;
+        self.sent_this_flush = sent;
+        
+        if capped && !self.bandwidth_limited {
+            let queued_bytes = self.channels.iter().map(Channel::pending_bytes).sum();
+            self.events.push_back(ConnectionEvent::BandwidthLimited { queued_bytes });
+        } else if !capped && self.bandwidth_limited {
+            self.events.push_back(ConnectionEvent::BandwidthRestored);
+        }
+        self.bandwidth_limited = capped;
     }
     
-    /// Sends a keepalive packet.
-    fn send_keepalive(&mut self) -> Result<(), ConnectionError> {
-        let header = self.create_header();
-        let packet = Packet::new(header, PacketType::KeepAlive);
-        self.send_queue.push_back(packet);
-        Ok(())
+    /// Packs queued control messages into `Control` packets, ahead of the user channels and
+    /// outside their budgets.
+    fn assemble_control(&mut self) {
+        let budget = self.mtu.saturating_sub(PAYLOAD_HEADER_BYTES + packet_sealing(&self.config));
+        let mut payload = Vec::new();
+        while let Some(message) = self.control.take_outgoing() {
+            if !payload.is_empty() && payload.len() + MESSAGE_HEADER_BYTES + message.data.len() > budget {
+                self.queue_control(std::mem::take(&mut payload));
+            }
+            write_message(&mut payload, message.sequence, &message.data);
+        }
+        if !payload.is_empty() {
+            self.queue_control(payload);
+        }
     }
     
-    /// Processes the send queue, transmitting packets via the socket.
-    fn process_send_queue(&mut self, socket: &mut UdpSocket) -> Result<(), ConnectionError> {
-        while let Some(packet) = self.send_queue.pop_front() {
-            let data = packet.serialize().map_err(|_| ConnectionError::InvalidPacket)?;
-            socket.send_to(&data, self.remote_addr)?;
-            
-            self.last_packet_send_time = Instant::now();
-            self.stats.packets_sent += 1;
-            self.stats.bytes_sent += data.len() as u64;
-            
-            // Track reliable packets
-            if let PacketType::Payload { channel, .. } = packet.packet_type {
-                if self.channels[channel as usize].is_reliable() {
-                    self.reliability.on_packet_sent(packet.header.sequence, Instant::now(), data.clone());
-                }
-            }
+    fn queue_control(&mut self, payload: Vec<u8>) {
+        let header = self.next_header();
+        self.send_queue.push_back(Packet::new(header, PacketType::Control).with_payload(payload));
+    }
+    
+    /// Queues a control message of the given [`control_message`] kind; dropped unless the
+    /// peer negotiated the control channel.
+    pub(crate) fn send_control(&mut self, kind: u8, body: &[u8]) {
+        if !self.has_capability(capability::CONTROL_CHANNEL) {
+            return;
         }
-        Ok(())
+        let mut message = Vec::with_capacity(1 + body.len());
+        message.push(kind);
+        message.extend_from_slice(body);
+        // Only a flooded control queue refuses, and every kind is periodic or answers a request
+        let _ = self.control.send_owned(message, true);
     }
     
-    /// Receives packets from the socket and processes them.
-    fn receive_packets(&mut self, socket: &mut UdpSocket) -> Result<(), ConnectionError> {
-        loop {
-            match socket.recv_from() {
-                Ok((data, addr)) => {
-                    if addr != self.remote_addr {
-                        continue; // Ignore packets from other addresses
+    /// Handles control messages that arrived in order.
+    fn receive_control(&mut self) {
+        while let Some(message) = self.control.receive() {
+            let Some((&kind, body)) = message.split_first() else {
+                continue;
+            };
+            match kind {
+                control_message::TIME_SYNC_REQUEST => {
+                    let Some(id) = body.get(..4) else { continue };
+                    let micros = wall_clock().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+                    let mut reply = id.to_vec();
+                    reply.extend_from_slice(&micros.to_le_bytes());
+                    self.send_control(control_message::TIME_SYNC, &reply);
+                }
+                control_message::TIME_SYNC => {
+                    let (Some(id), Some(micros)) = (body.get(..4), body.get(4..12)) else { continue };
+                    let id = u32::from_le_bytes(id.try_into().expect("4 bytes"));
+                    let Some((_, sent_at)) = self.time_sync_probe.filter(|&(probe, _)| probe == id) else {
+                        continue;
+                    };
+                    self.time_sync_probe = None;
+                    let reference = UNIX_EPOCH + Duration::from_micros(u64::from_le_bytes(micros.try_into().expect("8 bytes")));
+                    let rtt = self.config.clock.now().saturating_duration_since(sent_at);
+                    self.server_clock.observe(reference, wall_clock(), rtt);
+                }
+                control_message::PAUSE if !self.peer_paused => {
+                    self.peer_paused = true;
+                    self.events.push_back(ConnectionEvent::PeerPaused);
+                }
+                control_message::PAUSE => {}
+                control_message::RESUME => {
+                    let Some(nonce) = body.get(..4) else { continue };
+                    let nonce = nonce.to_vec();
+                    if self.peer_paused {
+                        self.peer_paused = false;
+                        self.events.push_back(ConnectionEvent::PeerResumed);
                     }
-                    
-                    let packet = Packet::deserialize(data)
-                        .map_err(|_| ConnectionError::InvalidPacket)?;
-                    
-                    // Validate protocol ID
-                    if packet.header.protocol_id != self.config.protocol_id {
-                        return Err(ConnectionError::ProtocolMismatch);
+                    self.send_control(control_message::RESUME_ACK, &nonce);
+                }
+                control_message::RESUME_ACK => {
+                    let Some(nonce) = body.get(..4) else { continue };
+                    let nonce = u32::from_le_bytes(nonce.try_into().expect("4 bytes"));
+                    if self.resume_probe == Some(nonce) {
+                        self.resume_probe = None;
+                        self.events.push_back(ConnectionEvent::Resumed);
                     }
-                    
-                    self.last_packet_recv_time = Instant::now();
-                    self.stats.packets_received += 1;
-                    self.stats.bytes_received += data.len() as u64;
-                    
-                    self.handle_packet(packet)?;
                 }
-                Err(SocketError::WouldBlock) => break,
-                Err(e) => return Err(e.into()),
+                // Kinds from newer versions of the protocol
+                _ => {}
             }
         }
+    }
+    
+    /// How long the peer may stay silent before it counts as gone.
+    fn receive_timeout(&self) -> Duration {
+        if self.peer_paused { self.config.pause_grace_period } else { self.config.connection_timeout }
+    }
+    
+    /// Stops sending for an app going into the background, and tells the peer, which then
+    /// holds the session for `pause_grace_period` instead of timing it out.
+    ///
+    /// Flush once more before the app is suspended so the notice goes out. Until
+    /// [`Connection::resume`], `update` only reads packets: no keepalives, resends or
+    /// timeouts, and messages sent meanwhile wait in their channels.
+    pub fn pause(&mut self) -> Result<(), ConnectionError> {
+        if self.state != ConnectionState::Connected {
+            return Err(ConnectionError::NotConnected);
+        }
+        if !self.has_capability(capability::CONTROL_CHANNEL) {
+            return Err(ConnectionError::PauseUnavailable);
+        }
+        if self.paused {
+            return Ok(());
+        }
+        self.send_control(control_message::PAUSE, &[]);
+        self.assemble_control();
+        self.paused = true;
+        self.resume_probe = None;
+        Ok(())
+    }
+    
+    /// Picks up after [`Connection::pause`]: channels flow again and the peer is asked to
+    /// confirm it still holds the session, raising [`ConnectionEvent::Resumed`] when it does.
+    ///
+    /// A peer that gave up during the pause never answers, and the connection times out
+    /// `connection_timeout` from now.
+    pub fn resume(&mut self) -> Result<(), ConnectionError> {
+        if !self.paused {
+            return Ok(());
+        }
+        self.paused = false;
+        if self.state != ConnectionState::Connected {
+            return Err(ConnectionError::NotConnected);
+        }
+        // Whatever the peer sent while the app slept was never read
+        self.last_packet_recv_time = self.config.clock.now();
+        let nonce = self.rng.next_u32();
+        self.resume_probe = Some(nonce);
+        self.send_control(control_message::RESUME, &nonce.to_le_bytes());
+        Ok(())
+    }
+    
+    /// Whether [`Connection::pause`] was called without a `resume` since.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+    
+    /// Whether the peer paused and has not resumed.
+    pub fn is_peer_paused(&self) -> bool {
+        self.peer_paused
+    }
+    
+    /// Asks the server for its clock and schedules the next request.
+    fn send_time_sync_request(&mut self, now: Instant) {
+        let id = self.time_sync_probe.map_or(0, |(id, _)| id.wrapping_add(1));
+        self.time_sync_probe = Some((id, now));
+        self.send_control(control_message::TIME_SYNC_REQUEST, &id.to_le_bytes());
+        self.next_time_sync = self.config.time_sync_interval.map(|interval| now + interval);
+    }
+    
+    /// Gives one channel its turn, keeping the join snapshot transfer to its burst.
+    fn assemble_turn(&mut self, channel_id: usize, transfer: Option<usize>, max_bytes: usize) -> (usize, usize, bool) {
+        if Some(channel_id) == transfer {
+            let result = self.assemble_channel(channel_id, self.fragment_allowance(channel_id), max_bytes);
+            self.fragment_streak += result.0;
+            return result;
+        }
+        let result = self.assemble_channel(channel_id, usize::MAX, max_bytes);
+        if result.0 > 0 {
+            self.fragment_streak = 0;
+        }
+        result
+    }
+    
+    /// Deficit round robin over `send_order`: a turn adds `weight` MTUs to the channel's
+    /// allowance and sends until it is spent. A turn cut short by the bandwidth cap resumes
+    /// on the next pass, so the cap cannot keep handing its budget to the same channel.
+    /// Returns whether the cap was hit.
+    fn assemble_weighted(&mut self, send_order: &[usize], transfer: Option<usize>, sent: &mut [bool]) -> bool {
+        // Ends once every channel in a row had a turn without sending
+        let mut idle = 0;
+        while idle < send_order.len() {
+            let position = self.fair_cursor % send_order.len();
+            let channel_id = send_order[position];
+            if !self.channels[channel_id].has_outgoing_messages() {
+                self.fair_deficit[channel_id] = 0;
+                self.fair_turn_open = false;
+                self.fair_cursor = position + 1;
+                idle += 1;
+                continue;
+            }
+            
+            let quantum = (self.mtu * self.channels[channel_id].config().weight.max(1) as usize) as isize;
+            if !self.fair_turn_open {
+                self.fair_deficit[channel_id] += quantum;
+                self.fair_turn_open = true;
+            }
+            let allowance = self.fair_deficit[channel_id].max(0) as usize;
+            let (packets, bytes, capped) = if allowance > 0 {
+                self.assemble_turn(channel_id, transfer, allowance)
+            } else {
+                (0, 0, false)
+            };
+            self.fair_deficit[channel_id] -= bytes as isize;
+            sent[channel_id] |= packets > 0;
+            if capped {
+                return true;
+            }
+            
+            if bytes == 0 {
+                // Held back by its group or the transfer burst: no saving up meanwhile
+                self.fair_deficit[channel_id] = self.fair_deficit[channel_id].min(quantum);
+                idle += 1;
+            } else {
+                idle = 0;
+            }
+            self.fair_turn_open = false;
+            self.fair_cursor = position + 1;
+        }
+        false
+    }
+    
+    /// Packs one channel's messages into at most `max_packets` payload packets, stopping once
+    /// `max_bytes` of messages were taken (the last may overdraw); returns the packets
+    /// queued, the bytes taken and whether the bandwidth cap stopped it.
+    fn assemble_channel(&mut self, channel_id: usize, max_packets: usize, max_bytes: usize) -> (usize, usize, bool) {
+        let sealing = if self.channels[channel_id].config().encrypted { SEAL_OVERHEAD } else { 0 };
+        let budget = self.mtu.saturating_sub(PAYLOAD_HEADER_BYTES + stamp_overhead(&self.config) + packet_sealing(&self.config) + sealing);
+        let group = self.channels[channel_id].config().group;
+        let mut payload = Vec::new();
+        let mut tracked = Vec::new();
+        let mut packets = 0;
+        let mut bytes = 0;
+        let mut capped = false;
+        while let Some(size) = self.channels[channel_id].peek_outgoing_size() {
+            if bytes >= max_bytes {
+                break;
+            }
+            let starts_packet = payload.is_empty() || payload.len() + MESSAGE_HEADER_BYTES + size > budget;
+            if starts_packet && packets + usize::from(!payload.is_empty()) >= max_packets {
+                break;
+            }
+            let group = group.filter(|&group| group < self.channel_groups.len());
+            if group.is_some_and(|group| !self.channel_groups[group].has_budget()) {
+                break;
+            }
+            if self.bandwidth_cap.as_ref().is_some_and(|cap| !cap.has_budget()) {
+                capped = true;
+                break;
+            }
+            for budget in group.map(|group| &mut self.channel_groups[group]).into_iter().chain(&mut self.bandwidth_cap) {
+                budget.consume(MESSAGE_HEADER_BYTES + size);
+            }
+            bytes += MESSAGE_HEADER_BYTES + size;
+            let Some(message) = self.channels[channel_id].take_outgoing() else {
+                break;
+            };
+            if MESSAGE_HEADER_BYTES + message.data.len() > budget {
+                if !payload.is_empty() {
+                    self.queue_payload(channel_id as u8, std::mem::take(&mut payload), std::mem::take(&mut tracked));
+                    packets += 1;
+                }
+                packets += self.queue_fragments(channel_id as u8, message);
+                continue;
+            }
+            if !payload.is_empty() && payload.len() + MESSAGE_HEADER_BYTES + message.data.len() > budget {
+                self.queue_payload(channel_id as u8, std::mem::take(&mut payload), std::mem::take(&mut tracked));
+                packets += 1;
+            }
+            write_message(&mut payload, message.sequence, &message.data);
+            if message.expires.is_some() || message.key.is_some() {
+                tracked.push(TrackedMessage { sequence: message.sequence, expires: message.expires, key: message.key });
+            }
+        }
+        if !payload.is_empty() {
+            self.queue_payload(channel_id as u8, payload, tracked);
+            packets += 1;
+        }
+        (packets, bytes, capped)
+    }
+    
+    /// Join snapshot packets that may be assembled before other channels get a turn.
+    fn fragment_allowance(&self, transfer: usize) -> usize {
+        let others_waiting = self.channels.iter()
+            .enumerate()
+            .any(|(channel_id, channel)| channel_id != transfer && channel.has_outgoing_messages());
+        match self.config.fragment_burst {
+            Some(burst) if others_waiting => burst.max(1).saturating_sub(self.fragment_streak),
+            _ => usize::MAX,
+        }
+    }
+    
+    fn queue_payload(&mut self, channel: u8, payload: Vec<u8>, tracked: Vec<TrackedMessage>) {
+        let header = self.next_header();
+        if !tracked.is_empty() && self.channels[channel as usize].is_reliable() {
+            self.tracked.insert(header.sequence, TrackedPacket { channel, messages: tracked, sent: false });
+        }
+        let packet = Packet::new(header, PacketType::Payload { channel, is_fragment: false })
+            .with_payload(payload);
+        self.send_queue.push_back(packet);
+    }
+    
+    /// Queues a message too large for one packet as fragments, returning how many. TTL and
+    /// key tracking go with the last fragment.
+    fn queue_fragments(&mut self, channel: u8, message: OutgoingMessage) -> usize {
+        let encrypted = self.channels[channel as usize].config().encrypted;
+        let fragments = split_fragments(message.sequence, &message.data, fragment_size(&self.config, self.mtu, encrypted));
+        let count = fragments.len();
+        for (index, fragment) in fragments.into_iter().enumerate() {
+            let header = self.next_header();
+            let last = index + 1 == count;
+            if last && (message.expires.is_some() || message.key.is_some()) && self.channels[channel as usize].is_reliable() {
+                let messages = vec![TrackedMessage { sequence: message.sequence, expires: message.expires, key: message.key }];
+                self.tracked.insert(header.sequence, TrackedPacket { channel, messages, sent: false });
+            }
+            let packet = Packet::new(header, PacketType::Payload { channel, is_fragment: true }).with_payload(fragment);
+            self.send_queue.push_back(packet);
+        }
+        self.stats.fragments_sent += count as u64;
+        count
+    }
+    
+    /// Sends a connection request packet.
+    fn send_connection_request(&mut self) -> Result<(), ConnectionError> {
+        let header = PacketHeader {
+            protocol_id: self.config.protocol_id,
+            sequence: 0,
+            ack: 0,
+            ack_bits: 0,
+        };
+        
+        self.connection_retry_wait = self.next_retry_wait();
+        self.events.push_back(ConnectionEvent::Connecting { attempt: self.connection_retry_count + 1 });
+        
+        // Each attempt proves the ticket's keys over a fresh nonce
+        let resume_request = self.resumption_ticket.as_ref()
+            .filter(|_| self.resuming)
+            .and_then(|ticket| ticket.resume_request(self.config.protocol_id));
+        let packet = match resume_request {
+            Some(payload) => Packet::new(header, PacketType::ResumeRequest).with_payload(payload),
+            None => self.connection_request(header)?,
+        };
+        self.send_queue.push_back(packet);
+        Ok(())
+    }
+    
+    /// A full handshake request, with our key exchange material, connect token and the
+    /// server's cookie once it sent one.
+    fn connection_request(&self, header: PacketHeader) -> Result<Packet, ConnectionError> {
+        let payload = self.key_exchange.as_ref().map(KeyExchange::request_payload).unwrap_or_default();
+        let mut packet = Packet::new(header, PacketType::ConnectionRequest).with_payload(payload);
+        if let Some(token) = &self.connect_token {
+            packet.extensions.set(header_extension::CONNECT_TOKEN, token.clone())
+                .map_err(|_| ConnectionError::InvalidPacket)?;
+        }
+        if let Some(cookie) = &self.request_cookie {
+            packet.extensions.set(header_extension::REQUEST_COOKIE, cookie.clone())
+                .map_err(|_| ConnectionError::InvalidPacket)?;
+        }
+        Ok(packet)
+    }
+    
+    /// Exponential backoff from `connection_request_interval` after each resend, capped and
+    /// then jittered.
+    fn next_retry_wait(&mut self) -> Duration {
+        let backoff = (self.config.connection_request_backoff.max(1.0) as f64).powi(self.connection_retry_count.min(64) as i32);
+        let cap = self.config.connection_request_max_interval.as_secs_f64();
+        let wait = (self.config.connection_request_interval.as_secs_f64() * backoff).min(cap);
+        let jitter = self.config.connection_request_jitter.clamp(0.0, 1.0) as f64;
+        if jitter == 0.0 {
+            return Duration::from_secs_f64(wait);
+        }
+        Duration::from_secs_f64(wait * (1.0 + jitter * (self.rng.next_f32() as f64 * 2.0 - 1.0)))
+    }
+    
+    /// Sends a keepalive packet.
+    fn send_keepalive(&mut self) -> Result<(), ConnectionError> {
+        let header = self.next_header();
+        let packet = Packet::new(header, PacketType::KeepAlive);
+        self.send_queue.push_back(packet);
+        Ok(())
+    }
+    
+    /// Sends the next path MTU probe if one is due, starting the search on the first call
+    /// after connecting.
+    fn probe_path_mtu(&mut self, now: Instant) {
+        let Some(max_mtu) = self.config.max_mtu.filter(|&max_mtu| max_mtu > self.config.mtu) else {
+            return;
+        };
+        // Probes and echoes are marked with a header extension the peer must parse
+        if !self.has_capability(capability::HEADER_EXTENSIONS) {
+            return;
+        }
+        let timeout = self.reliability.rtt().map_or(MIN_PROBE_TIMEOUT, |rtt| (rtt * 2).max(MIN_PROBE_TIMEOUT));
+        let mtu = self.config.mtu;
+        let search = self.mtu_search.get_or_insert_with(|| MtuSearch::new(mtu, max_mtu));
+        let Some((id, size)) = search.next_probe(now, timeout) else {
+            return;
+        };
+        let header = self.next_header();
+        let mut packet = Packet::new(header, PacketType::KeepAlive);
+        packet.extensions.set(header_extension::PATH_PROBE, probe_extension(id, false)).expect("probe fits an extension");
+        let unpadded = packet.serialize().map_or(0, |bytes| bytes.len());
+        packet.payload = vec![0; size.saturating_sub(unpadded + packet_sealing(&self.config))];
+        self.send_queue.push_back(packet);
+    }
+    
+    /// Echoes a path MTU probe from the peer, or takes the peer's echo of ours.
+    fn on_path_probe(&mut self, packet: &Packet) {
+        let Some((id, echo)) = packet.extensions.get(header_extension::PATH_PROBE).and_then(parse_probe_extension) else {
+            return;
+        };
+        if !echo {
+            let header = self.next_header();
+            let mut reply = Packet::new(header, PacketType::KeepAlive);
+            reply.extensions.set(header_extension::PATH_PROBE, probe_extension(id, true)).expect("probe fits an extension");
+            self.send_queue.push_back(reply);
+            return;
+        }
+        let Some(search) = self.mtu_search.as_mut() else {
+            return;
+        };
+        if search.on_echo(id) {
+            self.mtu = search.mtu();
+            self.events.push_back(ConnectionEvent::MtuChanged { mtu: self.mtu });
+        }
+    }
+    
+    /// Processes the send queue, transmitting packets via the socket.
+    pub(crate) fn process_send_queue(&mut self, socket: &mut UdpSocket) -> Result<(), ConnectionError> {
+        if self.send_retry_at.is_some_and(|at| self.config.clock.now() < at) {
+            return Ok(());
+        }
+        let mut wire_buffer = std::mem::take(&mut self.wire_buffer);
+        while let Some(mut packet) = self.send_queue.pop_front() {
+            // Stamped as it leaves, so resends carry their own send time
+            if matches!(packet.packet_type, PacketType::Payload { .. }) && self.stamps_packets() {
+                let stamp = self.packet_ages.stamp(self.config.clock.now());
+                packet.extensions.set(header_extension::TIMESTAMP, stamp.to_le_bytes().to_vec()).expect("send stamp fits an extension");
+            }
+            let payload = self.encode_payload(&packet)?;
+            let masked = self.sequence_masks.as_ref()
+                .filter(|_| is_session_traffic(&packet.packet_type))
+                .map(|(send, _)| PacketHeader {
+                    sequence: send.apply(packet.header.sequence),
+                    ack: send.apply(packet.header.ack),
+                    ..packet.header.clone()
+                });
+            let sealed = self.packet_keys.is_some() && is_session_traffic(&packet.packet_type);
+            let transformed = payload.is_some() || masked.is_some() || sealed;
+            let mut view = packet.view();
+            if let Some(header) = masked {
+                view.header = header;
+            }
+            if let Some(payload) = &payload {
+                view.payload = payload;
+            }
+            wire_buffer.clear();
+            if let Some((send, _)) = self.packet_keys.as_ref().filter(|_| sealed) {
+                // The payload goes last on the wire, so the header is what is written without it
+                let payload = std::mem::take(&mut view.payload);
+                view.write_to(&mut wire_buffer).map_err(|_| ConnectionError::InvalidPacket)?;
+                self.seal_nonce = self.seal_nonce.wrapping_add(1);
+                let sealed = send.seal_with_header(self.seal_nonce, &wire_buffer, payload);
+                wire_buffer.extend_from_slice(&sealed);
+            } else {
+                view.write_to(&mut wire_buffer).map_err(|_| ConnectionError::InvalidPacket)?;
+            }
+            let wire = self.config.middleware.encode(self.remote_addr, &wire_buffer)
+                .map_err(|_| ConnectionError::InvalidPacket)?;
+            let wire_len = wire.len();
+            if let Err(err) = socket.send_to(&wire, self.remote_addr) {
+                let class = err.send_error_class();
+                self.stats.send_errors.record(class);
+                // A probe the OS refuses answers its question; it is never retried
+                if let Some((id, false)) = packet.extensions.get(header_extension::PATH_PROBE).and_then(parse_probe_extension) {
+                    if class == SendErrorClass::MessageTooLarge {
+                        if let Some(search) = self.mtu_search.as_mut() {
+                            search.on_rejected(id);
+                        }
+                    }
+                    continue;
+                }
+                match self.config.send_error_policy.for_class(class) {
+                    SendErrorPolicy::Retry => {
+                        self.send_queue.push_front(packet);
+                        self.schedule_send_retry();
+                        break;
+                    }
+                    SendErrorPolicy::Fail => {
+                        self.send_queue.push_front(packet);
+                        self.wire_buffer = wire_buffer;
+                        return Err(err.into());
+                    }
+                    SendErrorPolicy::Report => self.events.push_back(ConnectionEvent::SendFailed { class }),
+                    SendErrorPolicy::Drop => {}
+                }
+                // Reliable packets are still tracked below, so they are resent like lost ones
+                self.track_sent(&packet, transformed, &wire_buffer)?;
+                continue;
+            }
+            self.send_retry_at = None;
+            self.send_retries = 0;
+            
+            self.last_packet_send_time = self.config.clock.now();
+            self.stats.packets_sent += 1;
+            self.stats.bytes_sent += wire_len as u64;
+            
+            if self.config.trace.is_some() {
+                // Resends go out under a sequence that is still awaiting its ack
+                let kind = if self.reliability.is_in_flight(packet.header.sequence) {
+                    TraceEventKind::Resend
+                } else {
+                    TraceEventKind::Send
+                };
+                self.trace(kind, packet.header.sequence, Some(&packet.packet_type), wire_len, self.last_packet_send_time);
+            }
+            
+            if let Some(sampler) = self.config.telemetry.as_ref().filter(|sampler| sampler.should_sample(&mut self.rng)) {
+                let channel = match packet.packet_type {
+                    PacketType::Payload { channel, .. } => Some(channel),
+                    _ => None,
+                };
+                sampler.report(&PacketTelemetry {
+                    remote_addr: self.remote_addr,
+                    sequence: packet.header.sequence,
+                    packet_type: packet.packet_type.clone(),
+                    size: wire_len,
+                    channel,
+                    messages: if channel.is_some() { count_messages(&packet.payload) } else { 0 },
+                    rtt: self.stats.rtt,
+                    sent_at: self.last_packet_send_time,
+                });
+            }
+            
+            self.track_sent(&packet, transformed, &wire_buffer)?;
+        }
+        self.wire_buffer = wire_buffer;
         Ok(())
     }
     
+    /// Whether payload packets carry send stamps: `max_packet_age` is set and the peer parses
+    /// header extensions.
+    fn stamps_packets(&self) -> bool {
+        self.config.max_packet_age.is_some() && self.has_capability(capability::HEADER_EXTENSIONS)
+    }
+    
+    /// Whether a payload packet on an unreliable channel is older than `max_packet_age`.
+    ///
+    /// Age comes from the peer's send stamp when it has one, otherwise from how long the
+    /// datagram waited since `received_at`. Reliable channels keep every packet.
+    fn is_stale(&mut self, packet: &Packet, received_at: Instant) -> bool {
+        let (Some(max_age), PacketType::Payload { channel, .. }) = (self.config.max_packet_age, &packet.packet_type) else {
+            return false;
+        };
+        let now = self.config.clock.now();
+        // Every stamp refines the baseline, including those on reliable channels
+        let age = match packet.extensions.get(header_extension::TIMESTAMP).and_then(|stamp| <[u8; 4]>::try_from(stamp).ok()) {
+            Some(stamp) => self.packet_ages.age(u32::from_le_bytes(stamp), now),
+            None => now.saturating_duration_since(received_at),
+        };
+        age > max_age && self.channels.get(*channel as usize).is_some_and(|channel| !channel.is_reliable())
+    }
+    
+    /// Hands reliable packets to reliability for acks and resends. `plain` is the packet as
+    /// serialized, before payload transforms and middleware.
+    fn track_sent(&mut self, packet: &Packet, transformed: bool, plain: &[u8]) -> Result<(), ConnectionError> {
+        let channel = match packet.packet_type {
+            PacketType::Payload { channel, .. } => Some(&self.channels[channel as usize]),
+            PacketType::Control => Some(&self.control),
+            _ => None,
+        };
+        if let Some(channel) = channel.filter(|channel| channel.is_reliable()) {
+            // Reliability keeps the plain packet so resends can be re-parsed and re-encoded
+            let plain = if transformed {
+                packet.serialize().map_err(|_| ConnectionError::InvalidPacket)?
+            } else {
+                plain.to_vec()
+            };
+            let strategy = channel.config().resend_strategy;
+            self.reliability.on_packet_sent_with(packet.header.sequence, self.config.clock.now(), plain, strategy);
+            if let Some(tracked) = self.tracked.get_mut(&packet.header.sequence) {
+                tracked.sent = true;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Raises `SequenceGap` when a packet skips `sequence_gap_threshold` or more sequences
+    /// past the newest one received.
+    fn check_sequence_gap(&mut self, sequence: u16, received_at: Instant) {
+        let newest = match self.newest_received {
+            Some((newest, _)) if !sequence_greater_than(sequence, newest) => return,
+            newest => newest,
+        };
+        self.newest_received = Some((sequence, received_at));
+        let (Some(threshold), Some((last_sequence, last_received))) = (self.config.sequence_gap_threshold, newest) else {
+            return;
+        };
+        let missing = sequence.wrapping_sub(last_sequence).wrapping_sub(1);
+        if missing >= threshold.max(1) {
+            self.stats.sequence_gaps += 1;
+            self.events.push_back(ConnectionEvent::SequenceGap {
+                missing,
+                last_sequence,
+                sequence,
+                silence: received_at.saturating_duration_since(last_received),
+            });
+        }
+    }
+    
+    /// Holds the send queue for `send_retry_interval`, doubled per failure in a row.
+    fn schedule_send_retry(&mut self) {
+        let backoff = 2u32.saturating_pow(self.send_retries.min(16));
+        let wait = self.config.send_retry_interval.saturating_mul(backoff).min(self.config.send_retry_max_interval);
+        self.send_retry_at = Some(self.config.clock.now() + wait);
+        self.send_retries += 1;
+    }
+    
+    /// Receives packets from the socket and processes them.
+    fn receive_packets(&mut self, socket: &mut UdpSocket) -> Result<(), ConnectionError> {
+        loop {
+            match socket.recv_from() {
+                Ok((data, addr)) => {
+                    if addr != self.remote_addr {
+                        continue; // Ignore packets from other addresses
+                    }
+                    
+                    let len = data.len();
+                    let data = self.config.middleware.decode(addr, data)
+                        .map_err(|_| ConnectionError::InvalidPacket)?;
+                    let packet = Packet::deserialize(&data)
+                        .map_err(|_| ConnectionError::InvalidPacket)?;
+                    let received_at = socket.stats().last_receive_time.unwrap_or_else(|| self.config.clock.now());
+                    
+                    // Validate protocol ID
+                    if packet.header.protocol_id != self.config.protocol_id {
+                        return Err(ConnectionError::ProtocolMismatch);
+                    }
+                    
+                    self.receive_packet(packet, len, received_at)?;
+                }
+                Err(SocketError::WouldBlock) => break,
+                Err(SocketError::PeerUnreachable) => self.on_peer_unreachable()?,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+    
+    /// Counts an unreachable report, giving up on the peer once `peer_unreachable_limit` arrive
+    /// with nothing received in between. The peer is gone, so no disconnect is sent.
+    fn on_peer_unreachable(&mut self) -> Result<(), ConnectionError> {
+        if self.state == ConnectionState::Disconnected {
+            return Ok(());
+        }
+        self.unreachable_reports += 1;
+        self.stats.peer_unreachable += 1;
+        self.events.push_back(ConnectionEvent::PeerUnreachable { reports: self.unreachable_reports });
+        if self.config.peer_unreachable_limit.is_some_and(|limit| self.unreachable_reports >= limit) {
+            self.reset_connection();
+            return Err(ConnectionError::PeerUnreachable);
+        }
+        Ok(())
+    }
+    
+    /// Records and handles a packet that has already been read and validated by the caller.
+    ///
+    /// `received_at` is the socket's receive time, from the kernel when it stamps datagrams.
+    /// It only feeds RTT, jitter and timeouts; trace events use the connection's clock like
+    /// sends do, since a kernel stamp can predate the sender's own post-send time on loopback.
+    pub(crate) fn receive_packet(&mut self, packet: Packet, len: usize, received_at: Instant) -> Result<(), ConnectionError> {
+        let mut packet = packet;
+        if let Some((_, recv)) = self.packet_keys.as_ref().filter(|_| is_session_traffic(&packet.packet_type)) {
+            // The header is rebuilt as sent, before sequences are unmasked
+            let mut header = Vec::new();
+            let view = PacketView { payload: &[], ..packet.view() };
+            view.write_to(&mut header).map_err(|_| ConnectionError::InvalidPacket)?;
+            // Forged, tampered, re-headered and cleartext packets fail to open and are dropped
+            let Some(payload) = recv.open_with_header(&header, &packet.payload) else {
+                return Ok(());
+            };
+            // So are packets opened before, whether resent whole or too old to tell
+            if !sealed_nonce(&packet.payload).is_some_and(|nonce| self.packet_replay.accept(nonce)) {
+                self.stats.replayed_packets_dropped += 1;
+                return Ok(());
+            }
+            packet.payload = payload;
+        }
+        if let Some((_, recv)) = self.sequence_masks.as_ref().filter(|_| is_session_traffic(&packet.packet_type)) {
+            packet.header.sequence = recv.remove(packet.header.sequence);
+            packet.header.ack = recv.remove(packet.header.ack);
+            // Forged sequences land anywhere in the space, and replays fall behind the window
+            if (packet.header.sequence.wrapping_sub(self.remote_sequence) as i16).unsigned_abs() > SEQUENCE_WINDOW {
+                return Ok(());
+            }
+        }
+        self.last_packet_recv_time = received_at;
+        self.unreachable_reports = 0;
+        self.stats.packets_received += 1;
+        self.stats.bytes_received += len as u64;
+        self.trace(TraceEventKind::Receive, packet.header.sequence, Some(&packet.packet_type), len, self.config.clock.now());
+        
+        if let PacketType::Payload { channel, .. } = packet.packet_type {
+            if self.channels.get(channel as usize).is_some_and(|channel| channel.config().encrypted) {
+                // Forged, tampered and cleartext payloads all fail to open and are dropped
+                match self.channel_keys.get(channel as usize).and_then(Option::as_ref).and_then(|(_, recv)| recv.open(&packet.payload)) {
+                    Some(payload) => packet.payload = payload,
+                    None => return Ok(()),
+                }
+            }
+        }
+        if let Some(codec) = self.payload_codec(&packet.packet_type) {
+            match decompress_payload(codec, &packet.payload, self.config.mtu.max(self.config.max_mtu.unwrap_or(0))) {
+                Ok(payload) => packet.payload = payload,
+                // Undecodable payloads are dropped like any other corrupt datagram
+                Err(_) => return Ok(()),
+            }
+        }
+        self.handle_packet(packet, received_at)
+    }
+    
+    /// Moves a server-side connection straight to Connected and queues the accept packet.
+    ///
+    /// The accept payload is the session seed, the timestamp epoch, then the resumption ticket if any.
+    /// Capabilities and codecs from [`Connection::set_negotiated`] go in header extensions,
+    /// left off when the client cannot parse them.
+    pub(crate) fn accept(
+        &mut self,
+        client_salt: u64,
+        server_salt: u64,
+        session_keys: Option<SessionKeys>,
+        session_seed: u64,
+        role: ConnectionRole,
+        ticket: Vec<u8>,
+    ) {
+        let now = self.config.clock.now();
+        self.role = role;
+        self.client_salt = client_salt;
+        self.server_salt = server_salt;
+        self.session_keys = session_keys;
+        self.derive_subkeys(true);
+        self.session_seed = Some(session_seed);
+        let epoch = TimestampEpoch::new(wall_clock(), self.config.timestamp_resolution);
+        self.timestamp_epoch = Some(epoch);
+        self.state = ConnectionState::Connected;
+        self.connection_start_time = Some(now);
+        self.last_packet_recv_time = now;
+        self.last_packet_send_time = now;
+        
+        let mut payload = session_seed.to_le_bytes().to_vec();
+        payload.extend_from_slice(&epoch.to_bytes());
+        payload.extend_from_slice(&ticket);
+        let header = self.next_header();
+        let mut packet = Packet::new(header, PacketType::ConnectionAccept).with_payload(payload);
+        if self.has_capability(capability::HEADER_EXTENSIONS) {
+            packet.extensions.set(header_extension::CAPABILITIES, self.capabilities.to_le_bytes().to_vec()).expect("capabilities fit an extension");
+            packet.extensions.set(header_extension::CHANNEL_LAYOUT, self.config.channel_layout()).expect("channel layout fits an extension");
+            if !self.codecs.is_empty() {
+                // Selections come from negotiate_codecs, which keeps them within an extension's size
+                packet.extensions.set(header_extension::COMPRESSION, self.codecs.clone()).expect("codec selection fits an extension");
+            }
+        }
+        self.send_queue.push_back(packet);
+    }
+    
+    /// Picks each channel's codec from the ids a client offered; empty when it offered none.
+    pub(crate) fn negotiate_codecs(&self, offered: &[u8]) -> Vec<u8> {
+        if offered.is_empty() {
+            return Vec::new();
+        }
+        // One byte per channel in a header extension; later channels stay uncompressed
+        self.config.compression.select(self.channels.iter().map(Channel::config).take(u8::MAX as usize), offered)
+    }
+    
+    /// Connect token the server verified before challenging this client.
+    pub(crate) fn set_authorized_token(&mut self, token: Option<ConnectToken>) {
+        self.authorized_token = token;
+    }
+    
+    /// Capabilities and per-channel codecs, announced to the client by [`Connection::accept`].
+    pub(crate) fn set_negotiated(&mut self, capabilities: u32, codecs: Vec<u8>) {
+        self.capabilities = capabilities;
+        self.codecs = codecs;
+    }
+    
+    /// [`capability`] bits both ends advertised; 0 until connected.
+    pub fn capabilities(&self) -> u32 {
+        self.capabilities
+    }
+    
+    pub fn has_capability(&self, capability: u32) -> bool {
+        self.capabilities & capability == capability
+    }
+    
+    /// Id of the codec compressing a channel's payloads, `None` if they are sent as is.
+    pub fn channel_codec(&self, channel_id: u8) -> Option<u8> {
+        self.codecs.get(channel_id as usize).copied().filter(|&id| id != NO_CODEC)
+    }
+    
+    /// A channel payload as it goes on the wire, compressed and then sealed as negotiated;
+    /// `None` when it goes as is.
+    fn encode_payload(&mut self, packet: &Packet) -> Result<Option<Vec<u8>>, ConnectionError> {
+        let PacketType::Payload { channel, .. } = packet.packet_type else {
+            return Ok(None);
+        };
+        let threshold = self.channels[channel as usize].config().compression_threshold;
+        let compressed = self.payload_codec(&packet.packet_type).map(|codec| compress_payload(codec, &packet.payload, threshold));
+        let mut payload = compressed.map(|(framed, outcome)| {
+            self.channels[channel as usize].record_compression(outcome, packet.payload.len(), framed.len());
+            framed
+        });
+        if self.channels[channel as usize].config().encrypted {
+            let (send, _) = self.channel_keys.get(channel as usize).and_then(Option::as_ref)
+                .ok_or(ConnectionError::EncryptionUnavailable)?;
+            self.seal_nonce = self.seal_nonce.wrapping_add(1);
+            payload = Some(send.seal(self.seal_nonce, payload.as_deref().unwrap_or(&packet.payload)));
+        }
+        Ok(payload)
+    }
+    
+    /// Derives the keys of every `encrypted` channel, and the sequence masks and packet keys
+    /// when obfuscation and packet encryption were negotiated, once the session keys and
+    /// capabilities are settled.
+    fn derive_subkeys(&mut self, server: bool) {
+        self.channel_keys = match &self.session_keys {
+            Some(keys) => self.channels.iter().enumerate()
+                .map(|(id, channel)| channel.config().encrypted.then(|| keys.channel_keys(id as u8, server)))
+                .collect(),
+            None => Vec::new(),
+        };
+        self.sequence_masks = self.session_keys.as_ref()
+            .filter(|_| self.has_capability(capability::SEQUENCE_OBFUSCATION))
+            .map(|keys| keys.sequence_masks(server));
+        self.packet_keys = self.session_keys.as_ref()
+            .filter(|_| self.has_capability(capability::PACKET_ENCRYPTION))
+            .map(|keys| keys.packet_keys(server));
+        self.packet_replay = ReplayWindow::default();
+        // A random start keeps nonces apart when resumed sessions reuse the keys
+        self.seal_nonce = OsRng.next_u64();
+    }
+    
+    fn payload_codec(&self, packet_type: &PacketType) -> Option<&dyn Codec> {
+        match packet_type {
+            PacketType::Payload { channel, .. } => self.channel_codec(*channel).and_then(|id| self.config.compression.get(id)),
+            _ => None,
+        }
+    }
+    
+    fn trace(&self, kind: TraceEventKind, sequence: u16, packet_type: Option<&PacketType>, size: usize, at: Instant) {
+        if let Some(trace) = &self.config.trace {
+            trace.record(TraceEvent {
+                kind,
+                local_addr: self.local_addr,
+                remote_addr: self.remote_addr,
+                sequence,
+                packet_type: packet_type.cloned(),
+                size,
+                at,
+            });
+        }
+    }
+    
+    /// Takes the channels out of a closing connection so their state can be resumed later.
+    pub(crate) fn take_channels(&mut self) -> Vec<Channel> {
+        std::mem::take(&mut self.channels)
+    }
+    
+    /// Restores reliable channel state from a resumed session; unreliable channels start fresh.
+    pub(crate) fn restore_channels(&mut self, channels: Vec<Channel>) {
+        for (slot, mut channel) in self.channels.iter_mut().zip(channels) {
+            if channel.is_reliable() {
+                // The client renumbers its messages after reconnecting
+                channel.reset_duplicate_tracking();
+                channel.reset_baselines();
+                *slot = channel;
+            }
+        }
+    }
+    
     /// Handles a received packet based on the current connection state.
-    fn handle_packet(&mut self, packet: Packet) -> Result<(), ConnectionError> {
+    fn handle_packet(&mut self, packet: Packet, received_at: Instant) -> Result<(), ConnectionError> {
         match (&self.state, &packet.packet_type) {
+            // The server wants proof we receive at our address before it runs the key
+            // exchange: ask again right away, echoing its cookie
+            (ConnectionState::Connecting, PacketType::ConnectionChallenge { .. })
+                if packet.extensions.get(header_extension::REQUEST_COOKIE).is_some() =>
+            {
+                self.request_cookie = packet.extensions.get(header_extension::REQUEST_COOKIE).map(<[u8]>::to_vec);
+                let header = PacketHeader { protocol_id: self.config.protocol_id, sequence: 0, ack: 0, ack_bits: 0 };
+                let request = self.connection_request(header)?;
+                self.send_queue.push_back(request);
+            }
+            
             (ConnectionState::Connecting, PacketType::ConnectionChallenge { server_salt }) => {
+                if let Some(key_exchange) = self.key_exchange.take() {
+                    match key_exchange.complete(
+                        self.config.protocol_id,
+                        &packet.payload,
+                        self.config.server_public_key.as_ref(),
+                    ) {
+                        Some(keys) => self.session_keys = Some(keys),
+                        None => {
+                            self.reset_connection();
+                            return Err(ConnectionError::KeyExchangeFailed);
+                        }
+                    }
+                }
+                
                 self.server_salt = *server_salt;
                 self.state = ConnectionState::ChallengeResponse;
+                self.queue_position = None;
                 
                 // Send response
-                let header = self.create_header();
-                let response = Packet::new(
+                let header = self.next_header();
+                // Capabilities and codec ids follow the role; servers that predate them read only the role
+                let mut payload = vec![self.role.to_byte()];
+                payload.extend_from_slice(&self.config.advertised_capabilities().to_le_bytes());
+                if self.config.capabilities & capability::COMPRESSION != 0 {
+                    payload.extend(self.config.compression.ids());
+                }
+                let mut response = Packet::new(
                     header,
                     PacketType::ConnectionResponse { client_salt: self.client_salt }
-                );
+                ).with_payload(payload);
+                if self.config.capabilities & capability::HEADER_EXTENSIONS != 0 {
+                    response.extensions.set(header_extension::CHANNEL_LAYOUT, self.config.channel_layout()).expect("channel layout fits an extension");
+                }
                 self.send_queue.push_back(response);
             }
             
             (ConnectionState::ChallengeResponse, PacketType::ConnectionAccept) => {
-                self.state = ConnectionState::Connected;
-                self.connection_start_time = Some(Instant::now());
-                self.last_packet_recv_time = Instant::now();
-                
-                // Reset sequences
-                self.local_sequence = 0;
-                self.remote_sequence = 0;
+                self.on_accepted(&packet.payload, &packet.extensions);
+                self.check_channel_layout(&packet.extensions)?;
+                self.check_packet_encryption()?;
+            }
+            
+            (ConnectionState::Connecting, PacketType::ConnectionAccept) if self.resuming => {
+                self.on_accepted(&packet.payload, &packet.extensions);
+                self.check_channel_layout(&packet.extensions)?;
+                self.check_packet_encryption()?;
+            }
+            
+            // Requests keep being retried while queued, so the server knows we are still waiting
+            (ConnectionState::Connecting, PacketType::ConnectionQueued { position }) => {
+                self.connection_retry_count = 0;
+                if self.queue_position != Some(*position) {
+                    self.queue_position = Some(*position);
+                    self.events.push_back(ConnectionEvent::JoinQueuePosition { position: *position });
+                }
             }
             
-            (_, PacketType::ConnectionDeny { reason }) => {
+            (ConnectionState::Connecting, PacketType::ConnectionDeny { reason })
+                if self.resuming && *reason == deny_reason::INVALID_TICKET =>
+            {
+                // Stale ticket: fall back to a full handshake
+                self.resumption_ticket = None;
+                self.reset_connection();
+                return self.connect();
+            }
+            
+            (ConnectionState::Connecting | ConnectionState::ChallengeResponse, PacketType::ConnectionDeny { reason }) => {
                 self.state = ConnectionState::Disconnected;
                 return Err(ConnectionError::ConnectionDenied(*reason));
             }
             
+            // A deny only answers a handshake and is never sealed, so anyone able to spoof the
+            // peer's address could send one; an established session ends with a Disconnect
+            (_, PacketType::ConnectionDeny { .. }) => {}
+            
+            // Challenge to a request delayed past the accept; its header is not part of the session
+            (ConnectionState::Connected, PacketType::ConnectionChallenge { .. }) => {}
+            
             (ConnectionState::Connected, _) => {
+                // A full reliable channel refuses the packet unacked, so the sender resends it later
+                if let PacketType::Payload { channel, .. } = packet.packet_type {
+                    if let Some(channel) = self.channels.get_mut(channel as usize) {
+                        if channel.is_reliable() && channel.config().slow_consumer == SlowConsumer::Backpressure && channel.receive_full() {
+                            channel.on_backpressure();
+                            return Ok(());
+                        }
+                    }
+                }
+                
                 // Update reliability tracking
-                self.reliability.on_packet_received(packet.header.sequence, Instant::now());
+                self.reliability.on_packet_received(packet.header.sequence, received_at);
                 
                 // Update remote sequence and acks
                 if sequence_greater_than(packet.header.sequence, self.remote_sequence) {
                     self.remote_sequence = packet.header.sequence;
                 }
+                self.check_sequence_gap(packet.header.sequence, received_at);
                 
                 // Process acks
-                self.reliability.process_acks(packet.header.ack, packet.header.ack_bits);
+                let tracked = &mut self.tracked;
+                let channels = &mut self.channels;
+                let events = &mut self.events;
+                let trace = self.config.trace.as_ref();
+                let (local_addr, remote_addr) = (self.local_addr, self.remote_addr);
+                let traced_at = self.config.clock.now();
+                self.reliability.process_acks_with(packet.header.ack, packet.header.ack_bits, received_at, |sequence| {
+                    if let Some(trace) = trace {
+                        trace.record(TraceEvent {
+                            kind: TraceEventKind::Ack,
+                            local_addr,
+                            remote_addr,
+                            sequence,
+                            packet_type: None,
+                            size: 0,
+                            at: traced_at,
+                        });
+                    }
+                    if let Some(packet) = tracked.remove(&sequence) {
+                        packet.settle(channels, events, DeliveryStatus::Delivered);
+                    }
+                });
+                if let Some(rtt) = self.reliability.rtt() {
+                    self.stats.rtt = rtt.as_secs_f32() * 1000.0;
+                }
                 
                 // Handle specific packet types
+                let stale = self.is_stale(&packet, received_at);
                 match packet.packet_type {
-                    PacketType::Payload { channel, .. } => {
+                    PacketType::Payload { .. } if stale => self.stats.stale_packets_dropped += 1,
+                    PacketType::Payload { channel, is_fragment } => {
                         if (channel as usize) < self.channels.len() {
-                            self.channels[channel as usize].on_packet_received(packet.payload);
+                            if is_fragment {
+                                if let Some((sequence, message)) = self.fragments.insert(channel, &packet.payload, received_at) {
+                                    self.channels[channel as usize].on_message_received(sequence, message);
+                                }
+                            } else {
+                                // Malformed framing drops the whole packet
+                                for (sequence, message) in read_messages(&packet.payload).unwrap_or_default() {
+                                    self.channels[channel as usize].on_message_received(sequence, message);
+                                }
+                            }
+                            if self.receives_join_snapshot && self.join_snapshot_channel() == Some(channel as usize) {
+                                self.receive_join_snapshot(channel as usize);
+                            }
+                        }
+                    }
+                    PacketType::KeepAlive => self.on_path_probe(&packet),
+                    PacketType::Control => {
+                        for (sequence, message) in read_messages(&packet.payload).unwrap_or_default() {
+                            self.control.on_message_received(sequence, message);
                         }
+                        self.receive_control();
                     }
-                    PacketType::Disconnect { reason: _ } => {
+                    PacketType::Disconnect { reason } => {
                         self.state = ConnectionState::Disconnected;
                         self.reset_connection();
+                        if reason == disconnect_reason::STALE_SESSION {
+                            self.events.push_back(ConnectionEvent::SessionReset);
+                        }
                     }
                     _ => {}
                 }
@@ -374,32 +2162,338 @@ impl Connection {
         Ok(())
     }
     
+    /// Enters the Connected state and stores the session seed and resumption ticket sent with the accept.
+    fn on_accepted(&mut self, payload: &[u8], extensions: &HeaderExtensions) {
+        self.state = ConnectionState::Connected;
+        self.resuming = false;
+        self.connection_start_time = Some(self.config.clock.now());
+        self.last_packet_recv_time = self.config.clock.now();
+        
+        // Reset sequences
+        self.local_sequence = 0;
+        self.remote_sequence = 0;
+        self.newest_received = None;
+        self.paused = false;
+        self.peer_paused = false;
+        self.resume_probe = None;
+        
+        self.session_seed = payload.get(..SEED_BYTES)
+            .and_then(|seed| seed.try_into().ok())
+            .map(u64::from_le_bytes);
+        
+        self.timestamp_epoch = payload.get(SEED_BYTES..).and_then(TimestampEpoch::from_bytes);
+        // The epoch origin is the server's clock when it accepted, one handshake round trip
+        // after our last send at most
+        if let Some(epoch) = self.timestamp_epoch {
+            self.server_clock = SyncedClock::new();
+            let round_trip = self.config.clock.now().saturating_duration_since(self.last_packet_send_time);
+            self.server_clock.observe(epoch.origin(), wall_clock(), round_trip);
+        }
+        
+        // Hold everything until the server's join snapshot arrives
+        self.receives_join_snapshot = self.join_snapshot_channel().is_some();
+        if self.receives_join_snapshot {
+            self.snapshot_receiver = Some(SnapshotReceiver::default());
+        }
+        
+        // Servers that predate capabilities enable nothing optional
+        let offered = extensions.get(header_extension::CAPABILITIES)
+            .and_then(|bits| bits.try_into().ok())
+            .map_or(0, u32::from_le_bytes);
+        self.capabilities = capability::negotiate(self.config.advertised_capabilities(), offered);
+        self.derive_subkeys(false);
+        
+        self.codecs = extensions.get(header_extension::COMPRESSION)
+            .filter(|_| self.has_capability(capability::COMPRESSION))
+            .map(|codecs| codecs.iter().map(|&id| if self.config.compression.get(id).is_some() { id } else { NO_CODEC }).collect())
+            .unwrap_or_default();
+        
+        let now = self.config.clock.now();
+        self.next_time_sync = self.config.time_sync_interval
+            .filter(|_| self.has_capability(capability::CONTROL_CHANNEL))
+            .map(|interval| now + interval);
+        
+        let ticket = payload.get(SEED_BYTES + EPOCH_BYTES..).unwrap_or_default();
+        if let Some(id) = ticket.get(..TICKET_ID_BYTES).and_then(|id| id.try_into().ok()) {
+            self.resumption_ticket = Some(ResumptionTicket {
+                id,
+                session_keys: self.session_keys.clone(),
+            });
+        }
+    }
+    
+    /// Compares the server's channel layout from the accept with ours; servers that did not
+    /// send one are taken to match.
+    fn check_channel_layout(&mut self, extensions: &HeaderExtensions) -> Result<(), ConnectionError> {
+        let Some(remote) = extensions.get(header_extension::CHANNEL_LAYOUT) else {
+            return Ok(());
+        };
+        let Some(mismatch) = ChannelMismatch::between(&self.config.channel_layout(), remote) else {
+            return Ok(());
+        };
+        self.events.push_back(ConnectionEvent::ChannelMismatch(mismatch.clone()));
+        if self.config.channel_mismatch == ChannelMismatchPolicy::Fail {
+            self.disconnect(disconnect_reason::PROTOCOL_MISMATCH)?;
+            return Err(ConnectionError::ChannelMismatch(mismatch));
+        }
+        Ok(())
+    }
+    
+    /// Refuses to carry on in the clear when `encrypt_packets` is set and the server did not
+    /// agree to it.
+    fn check_packet_encryption(&mut self) -> Result<(), ConnectionError> {
+        if self.config.encrypts_packets() && self.packet_keys.is_none() {
+            self.disconnect(disconnect_reason::PROTOCOL_MISMATCH)?;
+            return Err(ConnectionError::EncryptionUnavailable);
+        }
+        Ok(())
+    }
+    
     /// Resets the connection state and clears queues.
     fn reset_connection(&mut self) {
         self.state = ConnectionState::Disconnected;
         self.connection_start_time = None;
         self.connection_request_time = None;
+        self.queue_position = None;
         self.local_sequence = 0;
         self.remote_sequence = 0;
-        self.ack_bits = 0;
+        self.newest_received = None;
+        self.paused = false;
+        self.peer_paused = false;
+        self.resume_probe = None;
+        self.key_exchange = None;
+        self.request_cookie = None;
+        self.session_keys = None;
+        self.session_seed = None;
+        self.timestamp_epoch = None;
+        self.resuming = false;
+        self.tracked.clear();
+        self.fragments.clear();
+        self.packet_ages.clear();
+        self.mtu = self.config.mtu;
+        self.mtu_search = None;
+        self.snapshot_sender = None;
+        self.snapshot_receiver = None;
+        self.join_snapshot = None;
+        self.fragment_streak = 0;
+        self.capabilities = 0;
+        self.codecs.clear();
+        self.channel_keys.clear();
+        self.sequence_masks = None;
+        self.packet_keys = None;
+        self.packet_replay = ReplayWindow::default();
+        self.next_time_sync = None;
+        self.time_sync_probe = None;
+        self.unreachable_reports = 0;
         self.send_queue.clear();
         self.recv_queue.clear();
         
-        for channel in &mut self.channels {
+        for channel in self.channels.iter_mut().chain([&mut self.control]) {
             channel.reset();
         }
     }
     
+    /// Role of the client end: the configured role on a client, the negotiated one on a server.
+    pub fn role(&self) -> ConnectionRole {
+        self.role
+    }
+    
+    /// Whether this connection's role may not send on a channel.
+    pub(crate) fn rejects_channel(&self, channel_id: u8) -> bool {
+        self.role == ConnectionRole::Observer
+            && self.channels.get(channel_id as usize).is_some_and(|channel| channel.config().player_only)
+    }
+    
     /// Checks if the connection is in the Connected state.
     pub fn is_connected(&self) -> bool {
         self.state == ConnectionState::Connected
     }
     
+    /// Returns the keys negotiated during the handshake, if any.
+    pub fn session_keys(&self) -> Option<&SessionKeys> {
+        self.session_keys.as_ref()
+    }
+    
+    /// Place in the server's join queue while the server is full, 1 being next in line.
+    pub fn queue_position(&self) -> Option<u16> {
+        self.queue_position
+    }
+    
+    /// Returns the seed the server chose for this session, once connected.
+    pub fn session_seed(&self) -> Option<u64> {
+        self.session_seed
+    }
+    
+    /// Returns a generator for one stream of the session seed.
+    ///
+    /// Both ends get identical sequences for the same stream, so separate systems
+    /// (spawning, loot, AI, ...) can each use their own stream without affecting the others.
+    pub fn rng(&self, stream: u64) -> Option<DeterministicRng> {
+        self.session_seed.map(|seed| DeterministicRng::with_stream(seed, stream))
+    }
+    
+    /// Returns the epoch timestamps are encoded against, agreed at handshake.
+    ///
+    /// Wrap message (de)serialization in [`TimestampEpoch::scope`] with it.
+    pub fn timestamp_epoch(&self) -> Option<TimestampEpoch> {
+        self.timestamp_epoch
+    }
+    
+    /// Returns the ticket for resuming this session after a disconnect, if the server issued one.
+    pub fn resumption_ticket(&self) -> Option<&ResumptionTicket> {
+        self.resumption_ticket.as_ref()
+    }
+    
+    /// Packet acknowledgment state, for test harnesses forcing loss or advancing time.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn reliability_mut(&mut self) -> &mut ReliableEndpoint {
+        &mut self.reliability
+    }
+    
+    /// Salt the client sent with its challenge response, telling its sessions apart.
+    pub(crate) fn client_salt(&self) -> u64 {
+        self.client_salt
+    }
+    
+    /// Wall clock agreeing with the server's, for comparing timestamps across machines.
+    ///
+    /// Measured during the handshake and every `time_sync_interval` after; on the server it
+    /// is just the local clock.
+    pub fn server_clock(&self) -> &SyncedClock {
+        &self.server_clock
+    }
+    
+    /// Sets a previously saved ticket; the next `connect` tries to resume with it.
+    pub fn set_resumption_ticket(&mut self, ticket: Option<ResumptionTicket>) {
+        self.resumption_ticket = ticket;
+    }
+    
+    /// Sets the connect token bytes from the backend, sent with every connection request.
+    pub fn set_connect_token(&mut self, token: Option<Vec<u8>>) {
+        self.connect_token = token;
+    }
+    
+    /// On the server, the connect token this client was let in with, when tokens are required.
+    pub fn authorized_token(&self) -> Option<&ConnectToken> {
+        self.authorized_token.as_ref()
+    }
+    
+    /// Returns the typed application state attached to this connection.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+    
+    /// Returns the typed application state for modification.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+    
+    /// Returns the next pending connection event, if any.
+    pub fn poll_event(&mut self) -> Option<ConnectionEvent> {
+        self.events.pop_front()
+    }
+    
+    /// Largest packet this connection builds: `NetworkConfig::mtu`, or what path MTU
+    /// discovery found when `max_mtu` is set.
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+    
     /// Returns the connection statistics.
     pub fn stats(&self) -> &NetworkStats {
         &self.stats
     }
     
+    /// Returns the statistics of one channel.
+    pub fn channel_stats(&self, channel_id: u8) -> Option<ChannelStats> {
+        self.channels.get(channel_id as usize).map(Channel::stats)
+    }
+    
+    /// Statistics of the internal control channel, kept apart from the user channels; its id is
+    /// [`CONTROL_CHANNEL`].
+    pub fn control_stats(&self) -> ChannelStats {
+        self.control.stats()
+    }
+    
+    /// Copies the connection's and every channel's statistics at once; see [`crate::stats`].
+    pub fn stats_snapshot(&self) -> StatsSnapshot<ConnectionStats> {
+        StatsSnapshot {
+            taken_at: self.config.clock.now(),
+            stats: ConnectionStats {
+                network: self.stats.clone(),
+                channels: self.channels.iter().map(Channel::stats).collect(),
+                control: self.control.stats(),
+            },
+        }
+    }
+    
+    /// Zeroes the packet, byte and message counters of the connection and its channels, and
+    /// starts the RTT histogram over. RTT, loss, queue depths and memory usage are
+    /// measurements and keep their values.
+    pub fn stats_reset(&mut self) {
+        self.stats = NetworkStats {
+            packets_sent: 0,
+            packets_received: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            peer_unreachable: 0,
+            send_errors: Default::default(),
+            sequence_gaps: 0,
+            fragments_sent: 0,
+            fragments_expired: 0,
+            stale_packets_dropped: 0,
+            replayed_packets_dropped: 0,
+            ..self.stats.clone()
+        };
+        self.channels.iter_mut().for_each(Channel::reset_stats);
+        self.control.reset_stats();
+        self.reliability.rtt_histogram_mut().reset();
+        self.rtt_samples_reported = 0;
+    }
+    
+    /// Copies out sequence numbers, ack window, queue depths and timers for diagnostics.
+    pub fn debug_snapshot(&self) -> DebugSnapshot {
+        let now = self.config.clock.now();
+        let reliability = self.reliability.stats();
+        let channels = self.channels.iter()
+            .map(|channel| {
+                let stats = channel.stats();
+                ChannelDebugState {
+                    id: stats.id,
+                    send_queue: stats.send_buffer_size,
+                    send_queue_bytes: channel.pending_bytes(),
+                    receive_queue: stats.receive_buffer_size,
+                    pending_keys: stats.pending_keys,
+                }
+            })
+            .collect();
+        
+        DebugSnapshot {
+            state: self.state,
+            role: self.role,
+            local_sequence: self.local_sequence,
+            remote_sequence: reliability.remote_sequence,
+            ack_bits: reliability.ack_bits,
+            highest_acked: reliability.highest_acked,
+            packets_in_flight: reliability.packets_in_flight,
+            packets_resent: reliability.packets_resent,
+            tracked_packets: self.tracked.len(),
+            send_queue: self.send_queue.len(),
+            recv_queue: self.recv_queue.len(),
+            bandwidth_limited: self.bandwidth_limited,
+            rtt: self.reliability.rtt(),
+            channels,
+            timers: DebugTimers {
+                since_last_send: now.saturating_duration_since(self.last_packet_send_time),
+                since_last_receive: now.saturating_duration_since(self.last_packet_recv_time),
+                connected_for: self.connection_start_time.map(|start| now.saturating_duration_since(start)),
+                next_service_in: self.next_service_time().map(|next| next.saturating_duration_since(now)),
+                handshake_retries: self.connection_retry_count,
+            },
+            memory_usage: self.memory_usage(),
+        }
+    }
+    
     /// Gets the local address of this connection.
     pub fn local_addr(&self) -> SocketAddr {
         self.local_addr
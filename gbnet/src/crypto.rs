@@ -0,0 +1,547 @@
+// crypto.rs - Handshake key exchange, per-connection session keys and per-channel sealing
+//
+// Requests carrying key exchange material are first answered with a stateless cookie bound to
+// their source address, and the server only runs the exchange and signs once a request comes
+// back with it, so spoofed sources cost a MAC and get a reply no larger than what they sent.
+//
+// Connections that negotiate sequence obfuscation also permute the sequence and ack of their
+// session traffic with a keyed 16-bit Feistel network. That is no substitute for sealing,
+// only enough that sniffed sequences cannot be read, predicted or forged by hand.
+//
+// Channels marked `encrypted` seal their payloads with keys derived from the session keys,
+// one pair per channel and direction, so a key never covers more traffic than it has to
+// and the two directions never share a keystream even under a pre-shared static key.
+// Sealing is ChaCha20-Poly1305 with the 64-bit nonce sent ahead of the ciphertext and tag.
+//
+// Connections that negotiate packet encryption (the `crypto` feature) seal the payload of
+// every session packet the same way under a separate key pair, with the packet's header
+// (sequence, ack, extensions, type) as associated data. The header stays readable so the
+// reliability layer works unchanged, but it cannot be altered without the packet failing
+// to open. Each direction also keeps a window of the nonces it has opened, so a sealed packet
+// captured and sent again is dropped instead of delivering its messages a second time.
+use chacha20poly1305::aead::AeadInPlace;
+use chacha20poly1305::{ChaCha20Poly1305, Nonce, Tag};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+pub const KEY_BYTES: usize = 32;
+pub const PUBLIC_KEY_BYTES: usize = 32;
+pub const SIGNATURE_BYTES: usize = 64;
+
+const KDF_INFO: &[u8] = b"gbnet session keys v1";
+const CHANNEL_KDF_INFO: &[u8] = b"gbnet channel keys v1";
+const SEQUENCE_KDF_INFO: &[u8] = b"gbnet sequence mask v1";
+const PACKET_KDF_INFO: &[u8] = b"gbnet packet keys v1";
+const RESUME_KDF_INFO: &[u8] = b"gbnet resume proof v1";
+
+const NONCE_BYTES: usize = 8;
+const TAG_BYTES: usize = 16;
+/// Bytes a sealed payload adds: the nonce in front and the tag behind.
+pub const SEAL_OVERHEAD: usize = NONCE_BYTES + TAG_BYTES;
+pub const RESUME_PROOF_BYTES: usize = TAG_BYTES;
+
+// Direction labels keeping the two halves of a static key apart
+const CLIENT_TO_SERVER: u8 = 0;
+const SERVER_TO_CLIENT: u8 = 1;
+
+/// Symmetric keys used by the encryption layer of a single connection.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SessionKeys {
+    pub send_key: [u8; KEY_BYTES],
+    pub recv_key: [u8; KEY_BYTES],
+}
+
+impl SessionKeys {
+    /// Uses a pre-shared static key for both directions.
+    pub fn from_static(key: [u8; KEY_BYTES]) -> Self {
+        Self {
+            send_key: key,
+            recv_key: key,
+        }
+    }
+
+    /// (sending, receiving) keys for one channel's payloads, as seen from the server or the client.
+    pub(crate) fn channel_keys(&self, channel_id: u8, server: bool) -> (ChannelKey, ChannelKey) {
+        let (send_direction, recv_direction) = if server {
+            (SERVER_TO_CLIENT, CLIENT_TO_SERVER)
+        } else {
+            (CLIENT_TO_SERVER, SERVER_TO_CLIENT)
+        };
+        (
+            ChannelKey::derive(&self.send_key, CHANNEL_KDF_INFO, &[send_direction, channel_id]),
+            ChannelKey::derive(&self.recv_key, CHANNEL_KDF_INFO, &[recv_direction, channel_id]),
+        )
+    }
+
+    /// (sending, receiving) keys for whole session packets, as seen from the server or the client.
+    pub(crate) fn packet_keys(&self, server: bool) -> (ChannelKey, ChannelKey) {
+        let (send_direction, recv_direction) = if server {
+            (SERVER_TO_CLIENT, CLIENT_TO_SERVER)
+        } else {
+            (CLIENT_TO_SERVER, SERVER_TO_CLIENT)
+        };
+        (
+            ChannelKey::derive(&self.send_key, PACKET_KDF_INFO, &[send_direction]),
+            ChannelKey::derive(&self.recv_key, PACKET_KDF_INFO, &[recv_direction]),
+        )
+    }
+
+    /// Proof for a resume request that its sender holds these keys: an HMAC over the ticket
+    /// and a fresh nonce under a key derived from the client-to-server key.
+    pub(crate) fn resume_proof(&self, server: bool, protocol_id: u32, ticket_id: &[u8], nonce: &[u8]) -> [u8; RESUME_PROOF_BYTES] {
+        let tag = self.resume_authenticator(server, protocol_id, ticket_id, nonce).finalize().into_bytes();
+        tag[..RESUME_PROOF_BYTES].try_into().expect("HMAC-SHA256 output is longer than the proof")
+    }
+
+    /// Checks a [`SessionKeys::resume_proof`] in constant time.
+    pub(crate) fn check_resume_proof(&self, server: bool, protocol_id: u32, ticket_id: &[u8], nonce: &[u8], proof: &[u8]) -> bool {
+        proof.len() == RESUME_PROOF_BYTES
+            && self.resume_authenticator(server, protocol_id, ticket_id, nonce).verify_truncated_left(proof).is_ok()
+    }
+
+    fn resume_authenticator(&self, server: bool, protocol_id: u32, ticket_id: &[u8], nonce: &[u8]) -> Hmac<Sha256> {
+        let client_to_server = if server { &self.recv_key } else { &self.send_key };
+        let mut key = [0u8; KEY_BYTES];
+        Hkdf::<Sha256>::new(None, client_to_server)
+            .expand(RESUME_KDF_INFO, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC takes keys of any length");
+        mac.update(&protocol_id.to_le_bytes());
+        mac.update(ticket_id);
+        mac.update(nonce);
+        mac
+    }
+
+    /// (sending, receiving) sequence permutations, as seen from the server or the client.
+    pub(crate) fn sequence_masks(&self, server: bool) -> (SequenceMask, SequenceMask) {
+        let (send_direction, recv_direction) = if server {
+            (SERVER_TO_CLIENT, CLIENT_TO_SERVER)
+        } else {
+            (CLIENT_TO_SERVER, SERVER_TO_CLIENT)
+        };
+        (
+            SequenceMask::derive(&self.send_key, send_direction),
+            SequenceMask::derive(&self.recv_key, recv_direction),
+        )
+    }
+}
+
+// Never print key material
+impl std::fmt::Debug for SessionKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionKeys").finish_non_exhaustive()
+    }
+}
+
+/// AEAD key sealing one channel's payloads, or all session packets, in one direction.
+#[derive(Clone)]
+pub(crate) struct ChannelKey {
+    cipher: ChaCha20Poly1305,
+}
+
+impl ChannelKey {
+    fn derive(key: &[u8; KEY_BYTES], label: &[u8], context: &[u8]) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(None, key);
+        let mut okm = [0u8; KEY_BYTES];
+        hkdf.expand_multi_info(&[label, context], &mut okm)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self { cipher: <ChaCha20Poly1305 as chacha20poly1305::KeyInit>::new(&okm.into()) }
+    }
+
+    /// Encrypts and authenticates a payload as nonce, ciphertext, tag.
+    ///
+    /// A nonce must never be reused under the same key.
+    pub(crate) fn seal(&self, nonce: u64, payload: &[u8]) -> Vec<u8> {
+        self.seal_with_header(nonce, &[], payload)
+    }
+
+    /// The payload of a sealed message, `None` if it is malformed or fails authentication.
+    pub(crate) fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        self.open_with_header(&[], sealed)
+    }
+
+    /// Like [`ChannelKey::seal`], also authenticating `header`, which travels in the clear.
+    pub(crate) fn seal_with_header(&self, nonce: u64, header: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut sealed = Vec::with_capacity(SEAL_OVERHEAD + payload.len());
+        sealed.extend_from_slice(&nonce.to_le_bytes());
+        sealed.extend_from_slice(payload);
+        let tag = self.cipher.encrypt_in_place_detached(&aead_nonce(nonce), header, &mut sealed[NONCE_BYTES..])
+            .expect("payloads are far below the ChaCha20-Poly1305 limit");
+        sealed.extend_from_slice(&tag);
+        sealed
+    }
+
+    /// Opens a payload sealed with [`ChannelKey::seal_with_header`] under the same `header`.
+    pub(crate) fn open_with_header(&self, header: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < SEAL_OVERHEAD {
+            return None;
+        }
+        let (body, tag) = sealed.split_at(sealed.len() - TAG_BYTES);
+        let (nonce, ciphertext) = body.split_at(NONCE_BYTES);
+        let nonce = u64::from_le_bytes(nonce.try_into().ok()?);
+        let mut payload = ciphertext.to_vec();
+        self.cipher.decrypt_in_place_detached(&aead_nonce(nonce), header, &mut payload, Tag::from_slice(tag)).ok()?;
+        Some(payload)
+    }
+}
+
+// The 96-bit AEAD nonce is the 64-bit wire nonce, zero padded
+fn aead_nonce(nonce: u64) -> Nonce {
+    let mut bytes = [0u8; 12];
+    bytes[4..].copy_from_slice(&nonce.to_le_bytes());
+    bytes.into()
+}
+
+// Never print key material
+impl std::fmt::Debug for ChannelKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChannelKey").finish_non_exhaustive()
+    }
+}
+
+/// The nonce a sealed payload was sealed with, read from its front.
+pub(crate) fn sealed_nonce(sealed: &[u8]) -> Option<u64> {
+    sealed.get(..NONCE_BYTES)?.try_into().ok().map(u64::from_le_bytes)
+}
+
+/// How far behind the newest opened nonce a sealed packet may still arrive.
+pub(crate) const REPLAY_WINDOW: u64 = 1024;
+
+/// Nonces of the sealed packets opened in one direction, so a captured packet sent again is
+/// refused rather than processed twice.
+///
+/// Senders count nonces up, so only the newest and a ring of the [`REPLAY_WINDOW`] before it
+/// are kept; anything older cannot be told from a replay and is refused as well.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ReplayWindow {
+    newest: Option<u64>,
+    seen: [u64; REPLAY_WINDOW as usize / 64],
+}
+
+impl ReplayWindow {
+    /// Records the nonce of a packet that opened; false if it was seen before or is too old.
+    pub(crate) fn accept(&mut self, nonce: u64) -> bool {
+        let Some(newest) = self.newest else {
+            self.newest = Some(nonce);
+            self.mark(nonce);
+            return true;
+        };
+        // Counters wrap, so the nearer direction decides which side of the newest it is
+        let ahead = nonce.wrapping_sub(newest);
+        if ahead != 0 && ahead < 1 << 63 {
+            // Slots of the nonces passed over now stand for newer ones
+            if ahead >= REPLAY_WINDOW {
+                self.seen = Default::default();
+            } else {
+                (1..=ahead).for_each(|skipped| self.clear(newest.wrapping_add(skipped)));
+            }
+            self.newest = Some(nonce);
+            self.mark(nonce);
+            return true;
+        }
+        if newest.wrapping_sub(nonce) >= REPLAY_WINDOW || self.is_marked(nonce) {
+            return false;
+        }
+        self.mark(nonce);
+        true
+    }
+
+    fn slot(nonce: u64) -> (usize, u64) {
+        let bit = (nonce % REPLAY_WINDOW) as usize;
+        (bit / 64, 1 << (bit % 64))
+    }
+
+    fn mark(&mut self, nonce: u64) {
+        let (word, bit) = Self::slot(nonce);
+        self.seen[word] |= bit;
+    }
+
+    fn clear(&mut self, nonce: u64) {
+        let (word, bit) = Self::slot(nonce);
+        self.seen[word] &= !bit;
+    }
+
+    fn is_marked(&self, nonce: u64) -> bool {
+        let (word, bit) = Self::slot(nonce);
+        self.seen[word] & bit != 0
+    }
+}
+
+const MASK_ROUNDS: usize = 4;
+
+/// Keyed permutation of 16-bit sequence numbers.
+#[derive(Clone)]
+pub(crate) struct SequenceMask {
+    round_keys: [u16; MASK_ROUNDS],
+}
+
+impl SequenceMask {
+    fn derive(key: &[u8; KEY_BYTES], direction: u8) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(None, key);
+        let mut okm = [0u8; MASK_ROUNDS * 2];
+        hkdf.expand_multi_info(&[SEQUENCE_KDF_INFO, &[direction]], &mut okm)
+            .expect("8 bytes is a valid HKDF-SHA256 output length");
+
+        let mut round_keys = [0u16; MASK_ROUNDS];
+        for (round_key, bytes) in round_keys.iter_mut().zip(okm.chunks_exact(2)) {
+            *round_key = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Self { round_keys }
+    }
+
+    pub(crate) fn apply(&self, sequence: u16) -> u16 {
+        let [mut left, mut right] = sequence.to_be_bytes();
+        for &key in &self.round_keys {
+            (left, right) = (right, left ^ Self::round(right, key));
+        }
+        u16::from_be_bytes([left, right])
+    }
+
+    /// Inverse of [`SequenceMask::apply`].
+    pub(crate) fn remove(&self, masked: u16) -> u16 {
+        let [mut left, mut right] = masked.to_be_bytes();
+        for &key in self.round_keys.iter().rev() {
+            (left, right) = (right ^ Self::round(left, key), left);
+        }
+        u16::from_be_bytes([left, right])
+    }
+
+    // Any function makes a Feistel round invertible; this one just mixes the key in well
+    fn round(half: u8, key: u16) -> u8 {
+        let mixed = (half as u16 ^ key).wrapping_mul(0x9E37);
+        (mixed >> 8) as u8 ^ mixed as u8
+    }
+}
+
+// Never print key material
+impl std::fmt::Debug for SequenceMask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SequenceMask").finish_non_exhaustive()
+    }
+}
+
+pub const COOKIE_BYTES: usize = 16;
+
+/// Stateless cookies a server hands out before it spends a key exchange and signature on a
+/// connection request: only a client that can receive at its source address can echo one.
+///
+/// A cookie is an HMAC of the request and its source under a secret that rotates; cookies
+/// under the previous secret still pass, so each is good for one to two rotations.
+pub(crate) struct RequestCookies {
+    current: [u8; KEY_BYTES],
+    previous: [u8; KEY_BYTES],
+    rotated_at: Instant,
+}
+
+impl RequestCookies {
+    pub(crate) fn new(now: Instant) -> Self {
+        let mut current = [0u8; KEY_BYTES];
+        OsRng.fill_bytes(&mut current);
+        Self { current, previous: current, rotated_at: now }
+    }
+
+    /// Starts a new secret once `interval` has passed since the last one.
+    pub(crate) fn rotate(&mut self, now: Instant, interval: Duration) {
+        if now.saturating_duration_since(self.rotated_at) < interval {
+            return;
+        }
+        self.previous = self.current;
+        OsRng.fill_bytes(&mut self.current);
+        self.rotated_at = now;
+    }
+
+    pub(crate) fn issue(&self, protocol_id: u32, addr: SocketAddr, request_payload: &[u8]) -> Vec<u8> {
+        let tag = Self::authenticator(&self.current, protocol_id, addr, request_payload).finalize().into_bytes();
+        tag[..COOKIE_BYTES].to_vec()
+    }
+
+    pub(crate) fn check(&self, protocol_id: u32, addr: SocketAddr, request_payload: &[u8], cookie: &[u8]) -> bool {
+        cookie.len() == COOKIE_BYTES
+            && [&self.current, &self.previous].into_iter().any(|secret| {
+                Self::authenticator(secret, protocol_id, addr, request_payload).verify_truncated_left(cookie).is_ok()
+            })
+    }
+
+    fn authenticator(secret: &[u8; KEY_BYTES], protocol_id: u32, addr: SocketAddr, request_payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+        mac.update(&protocol_id.to_le_bytes());
+        match addr.ip() {
+            IpAddr::V4(ip) => mac.update(&ip.octets()),
+            IpAddr::V6(ip) => mac.update(&ip.octets()),
+        }
+        mac.update(&addr.port().to_le_bytes());
+        mac.update(request_payload);
+        mac
+    }
+}
+
+// Never print key material
+impl std::fmt::Debug for RequestCookies {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestCookies").finish_non_exhaustive()
+    }
+}
+
+/// Long-term server signing key used to prove server identity during the handshake.
+///
+/// Clients pin the matching public key in `NetworkConfig::server_public_key`.
+pub struct ServerIdentity {
+    signing_key: SigningKey,
+}
+
+impl ServerIdentity {
+    /// Generates a new random identity.
+    pub fn generate() -> Self {
+        Self {
+            signing_key: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    /// Restores an identity from its 32-byte secret.
+    pub fn from_secret_bytes(secret: [u8; KEY_BYTES]) -> Self {
+        Self {
+            signing_key: SigningKey::from_bytes(&secret),
+        }
+    }
+
+    /// Returns the 32-byte secret for persisting the identity.
+    pub fn secret_bytes(&self) -> [u8; KEY_BYTES] {
+        self.signing_key.to_bytes()
+    }
+
+    /// Returns the public key clients should be configured with.
+    pub fn public_key(&self) -> [u8; PUBLIC_KEY_BYTES] {
+        self.signing_key.verifying_key().to_bytes()
+    }
+
+    fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_BYTES] {
+        self.signing_key.sign(message).to_bytes()
+    }
+}
+
+/// Ephemeral X25519 key pair for one handshake.
+pub struct KeyExchange {
+    secret: EphemeralSecret,
+    public: PublicKey,
+}
+
+impl KeyExchange {
+    pub fn new() -> Self {
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    pub fn public_key(&self) -> [u8; PUBLIC_KEY_BYTES] {
+        self.public.to_bytes()
+    }
+
+    /// Client side: builds the key exchange payload carried by a connection request.
+    pub fn request_payload(&self) -> Vec<u8> {
+        self.public_key().to_vec()
+    }
+
+    /// Server side: answers a client's request payload.
+    ///
+    /// Returns the challenge payload (server public key, optionally followed by a
+    /// signature from `identity`) and the server's session keys.
+    pub fn respond(
+        self,
+        protocol_id: u32,
+        request_payload: &[u8],
+        identity: Option<&ServerIdentity>,
+    ) -> Option<(Vec<u8>, SessionKeys)> {
+        let client_public = read_public_key(request_payload)?;
+        let server_public = self.public_key();
+
+        let mut payload = server_public.to_vec();
+        if let Some(identity) = identity {
+            let transcript = transcript(protocol_id, &client_public, &server_public);
+            payload.extend_from_slice(&identity.sign(&transcript));
+        }
+
+        let shared = self.secret.diffie_hellman(&PublicKey::from(client_public));
+        let (client_to_server, server_to_client) = derive_keys(shared.as_bytes(), &client_public, &server_public);
+        let keys = SessionKeys {
+            send_key: server_to_client,
+            recv_key: client_to_server,
+        };
+        Some((payload, keys))
+    }
+
+    /// Client side: completes the exchange from the server's challenge payload.
+    ///
+    /// When `server_public_key` is set the challenge must carry a valid signature
+    /// from that key, otherwise the exchange fails.
+    pub fn complete(
+        self,
+        protocol_id: u32,
+        challenge_payload: &[u8],
+        server_public_key: Option<&[u8; PUBLIC_KEY_BYTES]>,
+    ) -> Option<SessionKeys> {
+        let client_public = self.public_key();
+        let server_public = read_public_key(challenge_payload)?;
+
+        if let Some(expected) = server_public_key {
+            let signature = challenge_payload.get(PUBLIC_KEY_BYTES..PUBLIC_KEY_BYTES + SIGNATURE_BYTES)?;
+            let signature = Signature::from_slice(signature).ok()?;
+            let verifying_key = VerifyingKey::from_bytes(expected).ok()?;
+            let transcript = transcript(protocol_id, &client_public, &server_public);
+            verifying_key.verify(&transcript, &signature).ok()?;
+        }
+
+        let shared = self.secret.diffie_hellman(&PublicKey::from(server_public));
+        let (client_to_server, server_to_client) = derive_keys(shared.as_bytes(), &client_public, &server_public);
+        Some(SessionKeys {
+            send_key: client_to_server,
+            recv_key: server_to_client,
+        })
+    }
+}
+
+impl Default for KeyExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read_public_key(payload: &[u8]) -> Option<[u8; PUBLIC_KEY_BYTES]> {
+    payload.get(..PUBLIC_KEY_BYTES)?.try_into().ok()
+}
+
+/// Data signed by the server: binds its ephemeral key to this client's request.
+fn transcript(protocol_id: u32, client_public: &[u8; PUBLIC_KEY_BYTES], server_public: &[u8; PUBLIC_KEY_BYTES]) -> Vec<u8> {
+    let mut transcript = Vec::with_capacity(4 + PUBLIC_KEY_BYTES * 2);
+    transcript.extend_from_slice(&protocol_id.to_le_bytes());
+    transcript.extend_from_slice(client_public);
+    transcript.extend_from_slice(server_public);
+    transcript
+}
+
+/// Returns (client-to-server, server-to-client) keys.
+fn derive_keys(
+    shared_secret: &[u8; 32],
+    client_public: &[u8; PUBLIC_KEY_BYTES],
+    server_public: &[u8; PUBLIC_KEY_BYTES],
+) -> ([u8; KEY_BYTES], [u8; KEY_BYTES]) {
+    let mut salt = [0u8; PUBLIC_KEY_BYTES * 2];
+    salt[..PUBLIC_KEY_BYTES].copy_from_slice(client_public);
+    salt[PUBLIC_KEY_BYTES..].copy_from_slice(server_public);
+
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared_secret);
+    let mut okm = [0u8; KEY_BYTES * 2];
+    hkdf.expand(KDF_INFO, &mut okm).expect("64 bytes is a valid HKDF-SHA256 output length");
+
+    let mut client_to_server = [0u8; KEY_BYTES];
+    let mut server_to_client = [0u8; KEY_BYTES];
+    client_to_server.copy_from_slice(&okm[..KEY_BYTES]);
+    server_to_client.copy_from_slice(&okm[KEY_BYTES..]);
+    (client_to_server, server_to_client)
+}
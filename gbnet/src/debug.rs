@@ -0,0 +1,270 @@
+// debug.rs - Point-in-time connection state for bug reports and net-debug overlays
+//
+// `Connection::debug_snapshot` copies out the state that matters when something goes wrong:
+// sequence numbers and the ack window, what is in flight, how deep each channel's queues
+// are and how long ago things happened. Snapshots are plain data, so they can be cloned
+// across threads, diffed between frames, or exported with `to_json` (`json` feature) into a bug report.
+//
+// `DebugOverlayFeed` records one frame per call from the same data and keeps a rolling
+// history of plain numbers, so any renderer can draw the classic netgraph from it.
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "json")]
+use serde_json::{json, Value};
+
+use crate::{config::ConnectionRole, connection::{Connection, ConnectionState}};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugSnapshot {
+    pub state: ConnectionState,
+    pub role: ConnectionRole,
+    /// Sequence of the next packet sent
+    pub local_sequence: u16,
+    /// Newest sequence received, the base of the ack window sent back
+    pub remote_sequence: u16,
+    /// Bit N set if `remote_sequence - 1 - N` was received
+    pub ack_bits: u32,
+    /// Newest of our packets the peer acknowledged
+    pub highest_acked: Option<u16>,
+    /// Reliable packets sent and not yet acknowledged
+    pub packets_in_flight: usize,
+    /// Reliable packets resent since the connection was created
+    pub packets_resent: u64,
+    /// Of those, packets carrying TTL or keyed messages
+    pub tracked_packets: usize,
+    pub send_queue: usize,
+    pub recv_queue: usize,
+    pub bandwidth_limited: bool,
+    pub rtt: Option<Duration>,
+    pub channels: Vec<ChannelDebugState>,
+    pub timers: DebugTimers,
+    pub memory_usage: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelDebugState {
+    pub id: u8,
+    /// Messages queued to send
+    pub send_queue: usize,
+    pub send_queue_bytes: usize,
+    /// Messages waiting for delivery to the application
+    pub receive_queue: usize,
+    pub pending_keys: usize,
+}
+
+/// Ages of the connection's timers, relative to when the snapshot was taken.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugTimers {
+    pub since_last_send: Duration,
+    pub since_last_receive: Duration,
+    /// Time since the connection was established, if it is
+    pub connected_for: Option<Duration>,
+    /// Until `update` next has work to do without any packet arriving
+    pub next_service_in: Option<Duration>,
+    pub handshake_retries: u32,
+}
+
+impl DebugSnapshot {
+    /// The snapshot as JSON, durations in milliseconds.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Value {
+        let channels: Vec<Value> = self.channels.iter()
+            .map(|channel| json!({
+                "id": channel.id,
+                "send_queue": channel.send_queue,
+                "send_queue_bytes": channel.send_queue_bytes,
+                "receive_queue": channel.receive_queue,
+                "pending_keys": channel.pending_keys,
+            }))
+            .collect();
+        json!({
+            "state": format!("{:?}", self.state),
+            "role": format!("{:?}", self.role),
+            "local_sequence": self.local_sequence,
+            "remote_sequence": self.remote_sequence,
+            "ack_bits": self.ack_bits,
+            "highest_acked": self.highest_acked,
+            "packets_in_flight": self.packets_in_flight,
+            "packets_resent": self.packets_resent,
+            "tracked_packets": self.tracked_packets,
+            "send_queue": self.send_queue,
+            "recv_queue": self.recv_queue,
+            "bandwidth_limited": self.bandwidth_limited,
+            "rtt_ms": self.rtt.map(millis),
+            "channels": channels,
+            "timers": {
+                "since_last_send_ms": millis(self.timers.since_last_send),
+                "since_last_receive_ms": millis(self.timers.since_last_receive),
+                "connected_for_ms": self.timers.connected_for.map(millis),
+                "next_service_in_ms": self.timers.next_service_in.map(millis),
+                "handshake_retries": self.timers.handshake_retries,
+            },
+            "memory_usage": self.memory_usage,
+        })
+    }
+}
+
+#[cfg(feature = "json")]
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// A quantity [`DebugOverlayFeed`] can plot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OverlayMetric {
+    /// Bytes per second sent
+    BandwidthUp,
+    /// Bytes per second received
+    BandwidthDown,
+    RttMs,
+    /// Fraction of the frame's packets that were resends, 0 to 1
+    Loss,
+    Resends,
+    /// Messages and packets waiting to be sent
+    SendQueue,
+    /// Messages and packets waiting for the application
+    ReceiveQueue,
+    PacketsInFlight,
+}
+
+/// One sample of a [`DebugOverlayFeed`]; rates and counts cover the time since the previous frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverlayFrame {
+    /// Time since the feed's first frame
+    pub at: Duration,
+    pub bytes_up_per_second: f32,
+    pub bytes_down_per_second: f32,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub resends: u64,
+    pub loss: f32,
+    pub rtt_ms: Option<f32>,
+    pub send_queue: usize,
+    pub receive_queue: usize,
+    pub packets_in_flight: usize,
+}
+
+impl OverlayFrame {
+    /// The frame's value of `metric`; RTT is 0 until the first sample.
+    pub fn value(&self, metric: OverlayMetric) -> f32 {
+        match metric {
+            OverlayMetric::BandwidthUp => self.bytes_up_per_second,
+            OverlayMetric::BandwidthDown => self.bytes_down_per_second,
+            OverlayMetric::RttMs => self.rtt_ms.unwrap_or(0.0),
+            OverlayMetric::Loss => self.loss,
+            OverlayMetric::Resends => self.resends as f32,
+            OverlayMetric::SendQueue => self.send_queue as f32,
+            OverlayMetric::ReceiveQueue => self.receive_queue as f32,
+            OverlayMetric::PacketsInFlight => self.packets_in_flight as f32,
+        }
+    }
+}
+
+// Cumulative counters as of the previous frame
+#[derive(Debug, Clone, Copy)]
+struct FrameCounters {
+    at: Instant,
+    bytes_sent: u64,
+    bytes_received: u64,
+    packets_sent: u64,
+    packets_received: u64,
+    packets_resent: u64,
+}
+
+/// Rolling per-frame connection stats for drawing a netgraph.
+///
+/// Call [`record`](Self::record) once per rendered frame (or tick) and plot
+/// [`points`](Self::points), scaled by [`peak`](Self::peak).
+#[derive(Debug, Clone)]
+pub struct DebugOverlayFeed {
+    capacity: usize,
+    frames: VecDeque<OverlayFrame>,
+    start: Option<Instant>,
+    last: Option<FrameCounters>,
+}
+
+impl DebugOverlayFeed {
+    /// Keeps the newest `capacity` frames.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            frames: VecDeque::with_capacity(capacity.max(1)),
+            start: None,
+            last: None,
+        }
+    }
+
+    /// Samples `connection` now.
+    pub fn record(&mut self, connection: &Connection) -> &OverlayFrame {
+        self.record_at(connection, Instant::now())
+    }
+
+    /// Samples `connection` as of `now`, for callers driving their own clock.
+    pub fn record_at(&mut self, connection: &Connection, now: Instant) -> &OverlayFrame {
+        let stats = connection.stats();
+        let snapshot = connection.debug_snapshot();
+        let counters = FrameCounters {
+            at: now,
+            bytes_sent: stats.bytes_sent,
+            bytes_received: stats.bytes_received,
+            packets_sent: stats.packets_sent,
+            packets_received: stats.packets_received,
+            packets_resent: snapshot.packets_resent,
+        };
+        // The first frame has no previous one to diff against, so its rates are zero
+        let last = self.last.replace(counters).unwrap_or(counters);
+        let start = *self.start.get_or_insert(now);
+
+        let seconds = now.saturating_duration_since(last.at).as_secs_f32();
+        let rate = |bytes: u64| if seconds > 0.0 { bytes as f32 / seconds } else { 0.0 };
+        let packets_sent = counters.packets_sent.saturating_sub(last.packets_sent);
+        let resends = counters.packets_resent.saturating_sub(last.packets_resent);
+        let loss = if packets_sent > 0 { (resends as f32 / packets_sent as f32).min(1.0) } else { 0.0 };
+
+        let frame = OverlayFrame {
+            at: now.saturating_duration_since(start),
+            bytes_up_per_second: rate(counters.bytes_sent.saturating_sub(last.bytes_sent)),
+            bytes_down_per_second: rate(counters.bytes_received.saturating_sub(last.bytes_received)),
+            packets_sent,
+            packets_received: counters.packets_received.saturating_sub(last.packets_received),
+            resends,
+            loss,
+            rtt_ms: snapshot.rtt.map(|rtt| rtt.as_secs_f32() * 1000.0),
+            send_queue: snapshot.send_queue + snapshot.channels.iter().map(|channel| channel.send_queue).sum::<usize>(),
+            receive_queue: snapshot.recv_queue + snapshot.channels.iter().map(|channel| channel.receive_queue).sum::<usize>(),
+            packets_in_flight: snapshot.packets_in_flight,
+        };
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+        self.frames.back().expect("frame just pushed")
+    }
+
+    /// Recorded frames, oldest first.
+    pub fn frames(&self) -> impl Iterator<Item = &OverlayFrame> {
+        self.frames.iter()
+    }
+
+    pub fn latest(&self) -> Option<&OverlayFrame> {
+        self.frames.back()
+    }
+
+    /// `[seconds since the first frame, value]` for each frame, oldest first.
+    pub fn points(&self, metric: OverlayMetric) -> Vec<[f32; 2]> {
+        self.frames.iter().map(|frame| [frame.at.as_secs_f32(), frame.value(metric)]).collect()
+    }
+
+    /// Largest value of `metric` in the history, for scaling a graph's axis.
+    pub fn peak(&self, metric: OverlayMetric) -> f32 {
+        self.frames.iter().map(|frame| frame.value(metric)).fold(0.0, f32::max)
+    }
+
+    /// Drops the history; the next frame starts a new time axis.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.start = None;
+        self.last = None;
+    }
+}
@@ -0,0 +1,107 @@
+// debug_packets.rs - Type tags on bit-packed messages in development builds
+//
+// A message read as the wrong type rarely fails where the mistake is: the bits are
+// reinterpreted, and the receiver gets garbage values or an error several fields later.
+// With the `debug-packets` feature in a debug build, every derived message writes a 32-bit
+// tag before its fields (its name hashed into the high 16 bits, its field layout into the
+// low 16) and checks it when reading. Only the outermost derived value is tagged: derived
+// types nested in its fields are covered by its layout hash and travel as they always do.
+// A wrong type or a changed layout then fails on the first read, naming both types when the
+// sender's is registered with `#[schema]`.
+//
+// Release builds drop the tag entirely: `ENABLED` is a constant, so the derive's checks
+// fold away. Both ends must agree on the mode. Types whose layout the wire format fixes,
+// such as the packet header, opt out with `#[gbnet(untagged)]`.
+use std::cell::Cell;
+use std::io;
+
+use crate::schema;
+use crate::serialize::bit_io::{BitRead, BitWrite};
+
+/// True when derived types write and check tags: the `debug-packets` feature in a debug build.
+pub const ENABLED: bool = cfg!(all(feature = "debug-packets", debug_assertions));
+
+/// Width of a tag on the wire.
+pub const TAG_BITS: usize = 32;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// One derived value being written or read on this thread, held for the length of its
+/// (de)serialization so values nested in its fields know they are not the message.
+pub struct Scope {
+    outermost: bool,
+}
+
+impl Scope {
+    pub fn enter() -> Self {
+        if !ENABLED {
+            return Self { outermost: false };
+        }
+        let depth = DEPTH.with(|depth| depth.replace(depth.get() + 1));
+        Self { outermost: depth == 0 }
+    }
+
+    /// Whether this value is the message itself and carries the tag.
+    pub fn outermost(&self) -> bool {
+        ENABLED && self.outermost
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        if ENABLED {
+            DEPTH.with(|depth| depth.set(depth.get() - 1));
+        }
+    }
+}
+
+/// The type-name half of a tag.
+pub fn type_id(tag: u32) -> u16 {
+    (tag >> 16) as u16
+}
+
+/// The field-layout half of a tag.
+pub fn layout_hash(tag: u32) -> u16 {
+    tag as u16
+}
+
+pub fn write_tag<W: BitWrite>(writer: &mut W, tag: u32) -> io::Result<()> {
+    writer.write_bits(tag as u64, TAG_BITS)
+}
+
+pub fn read_tag<R: BitRead>(reader: &mut R) -> io::Result<u32> {
+    Ok(reader.read_bits(TAG_BITS)? as u32)
+}
+
+/// Reads a tag and fails with `InvalidData` unless it is `expected`.
+pub fn check_tag<R: BitRead>(reader: &mut R, expected: u32, type_name: &str) -> io::Result<()> {
+    let found = read_tag(reader)?;
+    if found == expected {
+        return Ok(());
+    }
+    let message = if type_id(found) == type_id(expected) {
+        format!(
+            "{}: layout hash {:04x} differs from the sender's {:04x}",
+            type_name, layout_hash(expected), layout_hash(found)
+        )
+    } else {
+        let sent = schema::inventory::iter::<schema::TypeSchema>
+            .into_iter()
+            .find(|schema| schema.tag.is_some_and(|tag| type_id(tag) == type_id(found)))
+            .map_or_else(|| format!("type id {:04x}", type_id(found)), |schema| schema.name.to_string());
+        format!("{}: decoded with the wrong type, the sender wrote {}", type_name, sent)
+    };
+    Err(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// The registered `#[schema]` type whose tag starts `bytes`, for dissectors.
+/// Always `None` when tags are not enabled.
+pub fn identify(bytes: &[u8]) -> Option<&'static schema::TypeSchema> {
+    if !ENABLED {
+        return None;
+    }
+    let mut reader = crate::serialize::bit_io::BitSlice::new(bytes);
+    schema::find_by_tag(read_tag(&mut reader).ok()?)
+}
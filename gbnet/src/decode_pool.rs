@@ -0,0 +1,129 @@
+// decode_pool.rs - Decoding received messages on worker threads
+//
+// On a busy server, turning message bytes into game types can cost more CPU than reading the
+// socket, capping how fast one thread can drain it. A DecodePool takes that work off the
+// network thread: `Server::decode_received` hands it every message waiting on a channel and
+// goes back to reading, while workers run the decode function in parallel. Reliability,
+// ordering and channel state stay on the network thread; only the bytes cross over.
+//
+// Workers finish in any order, so results are held back until everything submitted earlier
+// for the same connection is done. Different connections never wait on each other.
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+type DecodeFn<T> = dyn Fn(&[u8]) -> io::Result<T> + Send + Sync;
+
+// Submission id, sender, channel, message bytes
+type Job = (u64, SocketAddr, u8, Vec<u8>);
+
+/// A decoded message, handed back by [`DecodePool::poll`].
+#[derive(Debug)]
+pub struct Decoded<T> {
+    pub addr: SocketAddr,
+    pub channel: u8,
+    pub message: io::Result<T>,
+}
+
+/// Worker threads running one decode function over received messages.
+pub struct DecodePool<T> {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<(u64, Decoded<T>)>,
+    workers: Vec<JoinHandle<()>>,
+    next_id: u64,
+    // Ids still owed to each connection, oldest first, and results that came back early
+    owed: HashMap<SocketAddr, VecDeque<u64>>,
+    finished: HashMap<u64, Decoded<T>>,
+    ready: VecDeque<Decoded<T>>,
+}
+
+impl<T: Send + 'static> DecodePool<T> {
+    /// Starts `workers` threads (at least one) decoding with `decode`.
+    pub fn new<F>(workers: usize, decode: F) -> Self
+    where
+        F: Fn(&[u8]) -> io::Result<T> + Send + Sync + 'static,
+    {
+        let decode: Arc<DecodeFn<T>> = Arc::new(decode);
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (sender, results) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let (decode, queue, sender) = (decode.clone(), queue.clone(), sender.clone());
+                std::thread::spawn(move || loop {
+                    // The lock is only held while waiting for the next job
+                    let job = queue.lock().map_err(|_| ()).and_then(|queue| queue.recv().map_err(|_| ()));
+                    let Ok((id, addr, channel, bytes)) = job else { break };
+                    if sender.send((id, Decoded { addr, channel, message: decode(&bytes) })).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            jobs: Some(jobs),
+            results,
+            workers,
+            next_id: 0,
+            owed: HashMap::new(),
+            finished: HashMap::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Queues a message for decoding.
+    pub fn submit(&mut self, addr: SocketAddr, channel: u8, bytes: Vec<u8>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.owed.entry(addr).or_default().push_back(id);
+        if let Some(jobs) = &self.jobs {
+            // Workers only stop once the pool is dropped
+            let _ = jobs.send((id, addr, channel, bytes));
+        }
+    }
+
+    /// Next decoded message whose connection has nothing older still decoding, if any.
+    pub fn poll(&mut self) -> Option<Decoded<T>> {
+        while let Ok((id, decoded)) = self.results.try_recv() {
+            let addr = decoded.addr;
+            self.finished.insert(id, decoded);
+            self.release(addr);
+        }
+        self.ready.pop_front()
+    }
+
+    /// Messages submitted and not yet returned by `poll`.
+    pub fn pending(&self) -> usize {
+        self.owed.values().map(VecDeque::len).sum::<usize>() + self.ready.len()
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    // Moves a connection's results into `ready` for as long as they are next in line
+    fn release(&mut self, addr: SocketAddr) {
+        let Some(owed) = self.owed.get_mut(&addr) else { return };
+        while let Some(decoded) = owed.front().and_then(|id| self.finished.remove(id)) {
+            owed.pop_front();
+            self.ready.push_back(decoded);
+        }
+        if owed.is_empty() {
+            self.owed.remove(&addr);
+        }
+    }
+}
+
+impl<T> Drop for DecodePool<T> {
+    fn drop(&mut self) {
+        // Closing the job queue ends every worker once it is idle
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// delta.rs - Prefix-delta encoding for `#[delta_string]` fields
+//
+// Each field remembers the last value written or read for it. A new value is sent as the
+// length of the prefix it shares with that baseline plus the remaining suffix, so progressively
+// edited text (leaderboard names, asset paths) costs only the changed tail.
+//
+// Both ends must see every value in order, so only use `#[delta_string]` on reliable ordered
+// channels. Like timestamps, the baselines are supplied per thread with `StringBaselines::scope`;
+// outside a scope values are sent whole.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+
+use crate::serialize::bit_io::{BitRead, BitWrite};
+
+thread_local! {
+    static ACTIVE_BASELINES: RefCell<Option<StringBaselines>> = const { RefCell::new(None) };
+}
+
+/// Last value of every `#[delta_string]` field for one direction of one channel.
+#[derive(Debug, Clone, Default)]
+pub struct StringBaselines {
+    values: HashMap<&'static str, String>,
+}
+
+impl StringBaselines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `f` with these baselines used by every `#[delta_string]` field (de)serialized
+    /// on the current thread, updating them as values go by.
+    pub fn scope<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        struct Restore<'a> {
+            baselines: &'a mut StringBaselines,
+            previous: Option<StringBaselines>,
+        }
+        impl Drop for Restore<'_> {
+            fn drop(&mut self) {
+                let active = ACTIVE_BASELINES.with(|active| active.replace(self.previous.take()));
+                *self.baselines = active.unwrap_or_default();
+            }
+        }
+
+        let previous = ACTIVE_BASELINES.with(|active| active.replace(Some(std::mem::take(self))));
+        let _restore = Restore { baselines: self, previous };
+        f()
+    }
+
+    /// Forgets every baseline, so the next value of each field is sent whole.
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    fn update(&mut self, key: &'static str, value: &str) {
+        let baseline = self.values.entry(key).or_default();
+        baseline.clear();
+        baseline.push_str(value);
+    }
+}
+
+// Longest shared byte prefix, backed off to a char boundary so the suffix is valid UTF-8
+fn common_prefix(a: &str, b: &str) -> usize {
+    let mut len = a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count();
+    while !b.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
+
+/// Writes `value` as a prefix length and suffix against the field's baseline.
+pub fn write_delta_string<W: BitWrite>(writer: &mut W, key: &'static str, value: &str, len_bits: usize, max_len: usize) -> io::Result<()> {
+    if value.len() > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("String length {} exceeds max_len {}", value.len(), max_len),
+        ));
+    }
+
+    let prefix = ACTIVE_BASELINES.with(|active| {
+        active.borrow().as_ref().and_then(|baselines| baselines.get(key)).map_or(0, |baseline| common_prefix(baseline, value))
+    });
+    let suffix = &value.as_bytes()[prefix..];
+    writer.write_bits(prefix as u64, len_bits)?;
+    writer.write_bits(suffix.len() as u64, len_bits)?;
+    for byte in suffix {
+        writer.write_bits(*byte as u64, 8)?;
+    }
+
+    ACTIVE_BASELINES.with(|active| {
+        if let Some(baselines) = active.borrow_mut().as_mut() {
+            baselines.update(key, value);
+        }
+    });
+    Ok(())
+}
+
+/// Reads a value written by [`write_delta_string`], rebuilding it from the field's baseline.
+pub fn read_delta_string<R: BitRead>(reader: &mut R, key: &'static str, len_bits: usize, max_len: usize) -> io::Result<String> {
+    let prefix = reader.read_bits(len_bits)? as usize;
+    let suffix_len = reader.read_bits(len_bits)? as usize;
+    if prefix + suffix_len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("String length {} exceeds max_len {}", prefix + suffix_len, max_len),
+        ));
+    }
+
+    let mut bytes = Vec::with_capacity(prefix + suffix_len);
+    if prefix > 0 {
+        ACTIVE_BASELINES.with(|active| {
+            let active = active.borrow();
+            let baseline = active.as_ref().and_then(|baselines| baselines.get(key)).ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("No baseline for delta string {}", key),
+            ))?;
+            let shared = baseline.as_bytes().get(..prefix).ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Delta prefix {} exceeds baseline length {}", prefix, baseline.len()),
+            ))?;
+            bytes.extend_from_slice(shared);
+            Ok::<_, io::Error>(())
+        })?;
+    }
+    for _ in 0..suffix_len {
+        bytes.push(reader.read_bits(8)? as u8);
+    }
+    let value = String::from_utf8(bytes).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Invalid UTF-8: {}", e))
+    })?;
+
+    ACTIVE_BASELINES.with(|active| {
+        if let Some(baselines) = active.borrow_mut().as_mut() {
+            baselines.update(key, &value);
+        }
+    });
+    Ok(value)
+}
@@ -0,0 +1,337 @@
+// delta_vec.rs - Lists sent as edits against the last version the peer acknowledged
+//
+// Inventories and scoreboards usually change by one element at a time, yet sending them as
+// a plain Vec repeats the whole list every update. A `DeltaVec` numbers each update it
+// encodes and diffs the list against the newest version the application has acked, so the
+// update survives loss on unreliable channels: the receiver keeps the last few versions it
+// applied and rebuilds from whichever one the update names. When no acked version is recent
+// enough, or the edits would cost more than the list itself, the whole list is sent.
+use std::collections::VecDeque;
+use std::io;
+use std::ops::{Deref, DerefMut};
+
+use crate::packet::sequence_greater_than;
+use crate::serialize::{BitDeserialize, BitSerialize, bit_io::{BitCounter, BitRead, BitWrite}};
+
+/// Versions each end keeps: sent and not yet acked, or received.
+pub const DELTA_VEC_HISTORY: usize = 32;
+
+// Indices, lengths and op counts, like a Vec length prefix
+const INDEX_BITS: usize = 16;
+const OP_BITS: usize = 2;
+const MAX_LEN: usize = u16::MAX as usize;
+
+/// One edit of a [`ListDelta`], applied in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListOp<T> {
+    Remove { index: u16 },
+    Insert { index: u16, value: T },
+    /// Removes the element at `from` and reinserts it at `to`
+    Move { from: u16, to: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListChange<T> {
+    /// The whole list
+    Full(Vec<T>),
+    /// Edits to the version numbered `base`
+    Edits { base: u16, ops: Vec<ListOp<T>> },
+}
+
+/// One encoded version of a [`DeltaVec`]; this is what goes on the wire.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListDelta<T> {
+    pub version: u16,
+    pub change: ListChange<T>,
+}
+
+/// A list replicated as edits; see the module docs.
+///
+/// The sender edits it like a `Vec`, sends [`DeltaVec::update`] and calls
+/// [`DeltaVec::ack`] once that update is known to be delivered. The receiver passes each
+/// update it gets to [`DeltaVec::apply`].
+#[derive(Debug, Clone)]
+pub struct DeltaVec<T> {
+    items: Vec<T>,
+    version: u16,
+    // Newest version the receiver is known to have
+    acked: Option<(u16, Vec<T>)>,
+    // Sender: versions sent and not yet acked. Receiver: versions applied
+    history: VecDeque<(u16, Vec<T>)>,
+}
+
+impl<T> Default for DeltaVec<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            version: 0,
+            acked: None,
+            history: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> Deref for DeltaVec<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.items
+    }
+}
+
+impl<T> DerefMut for DeltaVec<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        &mut self.items
+    }
+}
+
+impl<T> From<Vec<T>> for DeltaVec<T> {
+    fn from(items: Vec<T>) -> Self {
+        Self { items, ..Self::default() }
+    }
+}
+
+impl<T: Clone + PartialEq + BitSerialize> DeltaVec<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Version of the last update encoded or applied
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Newest version acked by the receiver
+    pub fn acked_version(&self) -> Option<u16> {
+        self.acked.as_ref().map(|(version, _)| *version)
+    }
+
+    /// Encodes the current list as the next version, against the newest acked one.
+    pub fn update(&mut self) -> io::Result<ListDelta<T>> {
+        if self.items.len() > MAX_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("List length {} exceeds max_len {}", self.items.len(), MAX_LEN),
+            ));
+        }
+        self.version = self.version.wrapping_add(1);
+        let full = ListDelta { version: self.version, change: ListChange::Full(self.items.clone()) };
+        let delta = match &self.acked {
+            // The receiver only keeps so many versions to rebuild from
+            Some((base, baseline)) if (self.version.wrapping_sub(*base) as usize) <= DELTA_VEC_HISTORY => {
+                let edits = ListDelta {
+                    version: self.version,
+                    change: ListChange::Edits { base: *base, ops: diff(baseline, &self.items) },
+                };
+                if encoded_bits(&edits)? < encoded_bits(&full)? { edits } else { full }
+            }
+            _ => full,
+        };
+
+        if self.history.len() >= DELTA_VEC_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back((self.version, self.items.clone()));
+        Ok(delta)
+    }
+
+    /// Marks an update as delivered, so later updates are encoded against it.
+    pub fn ack(&mut self, version: u16) {
+        let Some(position) = self.history.iter().position(|(sent, _)| *sent == version) else {
+            return;
+        };
+        let acked = self.history.drain(..=position).next_back();
+        if self.acked_version().is_none_or(|current| sequence_greater_than(version, current)) {
+            self.acked = acked;
+        }
+    }
+
+    /// Applies an update from the sender.
+    ///
+    /// Returns `false` for an update older than the list already holds. Fails if the
+    /// update edits a version no longer kept; the sender recovers once an update it has
+    /// acked reaches this end, so ack only updates that were applied.
+    pub fn apply(&mut self, delta: &ListDelta<T>) -> io::Result<bool> {
+        if !self.history.is_empty() && !sequence_greater_than(delta.version, self.version) {
+            return Ok(false);
+        }
+        let items = match &delta.change {
+            ListChange::Full(items) => items.clone(),
+            ListChange::Edits { base, ops } => {
+                let (_, baseline) = self.history.iter().find(|(version, _)| version == base).ok_or_else(|| io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("No baseline for list version {}", base),
+                ))?;
+                let mut items = baseline.clone();
+                apply_ops(&mut items, ops)?;
+                items
+            }
+        };
+
+        if self.history.len() >= DELTA_VEC_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back((delta.version, items.clone()));
+        self.items = items;
+        self.version = delta.version;
+        Ok(true)
+    }
+}
+
+fn encoded_bits<T: BitSerialize>(delta: &ListDelta<T>) -> io::Result<usize> {
+    let mut counter = BitCounter::new();
+    delta.bit_serialize(&mut counter)?;
+    Ok(counter.bits())
+}
+
+/// Edits turning `from` into `to`: removals of elements `to` no longer has, then a walk
+/// that fixes each position in turn with a move or an insert.
+fn diff<T: Clone + PartialEq>(from: &[T], to: &[T]) -> Vec<ListOp<T>> {
+    let mut ops = Vec::new();
+    let mut working = from.to_vec();
+
+    // Drop the copies of each value that `to` no longer has
+    let mut claimed = vec![false; to.len()];
+    let mut index = 0;
+    while index < working.len() {
+        match (0..to.len()).find(|&target| !claimed[target] && to[target] == working[index]) {
+            Some(target) => {
+                claimed[target] = true;
+                index += 1;
+            }
+            None => {
+                ops.push(ListOp::Remove { index: index as u16 });
+                working.remove(index);
+            }
+        }
+    }
+
+    let mut index = 0;
+    while index < to.len() {
+        if working.get(index) == Some(&to[index]) {
+            index += 1;
+            continue;
+        }
+        // The element here moved further back, so send it there in one step
+        if working.get(index + 1) == Some(&to[index]) {
+            if let Some(target) = to[index + 1..].iter().position(|value| *value == working[index]) {
+                let to_index = (index + 1 + target).min(working.len() - 1);
+                let value = working.remove(index);
+                working.insert(to_index, value);
+                ops.push(ListOp::Move { from: index as u16, to: to_index as u16 });
+                continue;
+            }
+        }
+        match working[index..].iter().position(|value| *value == to[index]) {
+            Some(offset) => {
+                let value = working.remove(index + offset);
+                working.insert(index, value);
+                ops.push(ListOp::Move { from: (index + offset) as u16, to: index as u16 });
+            }
+            None => {
+                working.insert(index, to[index].clone());
+                ops.push(ListOp::Insert { index: index as u16, value: to[index].clone() });
+            }
+        }
+        index += 1;
+    }
+    ops
+}
+
+fn apply_ops<T: Clone>(items: &mut Vec<T>, ops: &[ListOp<T>]) -> io::Result<()> {
+    let out_of_range = |index: u16| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("List edit index {} out of range", index),
+    );
+    for op in ops {
+        match op {
+            ListOp::Remove { index } => {
+                if *index as usize >= items.len() {
+                    return Err(out_of_range(*index));
+                }
+                items.remove(*index as usize);
+            }
+            ListOp::Insert { index, value } => {
+                if *index as usize > items.len() || items.len() >= MAX_LEN {
+                    return Err(out_of_range(*index));
+                }
+                items.insert(*index as usize, value.clone());
+            }
+            ListOp::Move { from, to } => {
+                if *from as usize >= items.len() || *to as usize >= items.len() {
+                    return Err(out_of_range((*from).max(*to)));
+                }
+                let value = items.remove(*from as usize);
+                items.insert(*to as usize, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl<T: BitSerialize> BitSerialize for ListDelta<T> {
+    fn bit_serialize<W: BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_bits(self.version as u64, 16)?;
+        match &self.change {
+            ListChange::Full(items) => {
+                writer.write_bit(false)?;
+                items.bit_serialize(writer)
+            }
+            ListChange::Edits { base, ops } => {
+                writer.write_bit(true)?;
+                writer.write_bits(*base as u64, 16)?;
+                if ops.len() > MAX_LEN {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} list edits exceed {}", ops.len(), MAX_LEN)));
+                }
+                writer.write_bits(ops.len() as u64, INDEX_BITS)?;
+                for op in ops {
+                    match op {
+                        ListOp::Remove { index } => {
+                            writer.write_bits(0, OP_BITS)?;
+                            writer.write_bits(*index as u64, INDEX_BITS)?;
+                        }
+                        ListOp::Insert { index, value } => {
+                            writer.write_bits(1, OP_BITS)?;
+                            writer.write_bits(*index as u64, INDEX_BITS)?;
+                            value.bit_serialize(writer)?;
+                        }
+                        ListOp::Move { from, to } => {
+                            writer.write_bits(2, OP_BITS)?;
+                            writer.write_bits(*from as u64, INDEX_BITS)?;
+                            writer.write_bits(*to as u64, INDEX_BITS)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<T: BitDeserialize> BitDeserialize for ListDelta<T> {
+    fn bit_deserialize<R: BitRead>(reader: &mut R) -> io::Result<Self> {
+        let version = reader.read_bits(16)? as u16;
+        if !reader.read_bit()? {
+            return Ok(Self { version, change: ListChange::Full(Vec::bit_deserialize(reader)?) });
+        }
+        let base = reader.read_bits(16)? as u16;
+        let count = reader.read_bits(INDEX_BITS)? as usize;
+        let mut ops = Vec::with_capacity(count.min(DELTA_VEC_HISTORY));
+        for _ in 0..count {
+            let op = match reader.read_bits(OP_BITS)? {
+                0 => ListOp::Remove { index: reader.read_bits(INDEX_BITS)? as u16 },
+                1 => ListOp::Insert {
+                    index: reader.read_bits(INDEX_BITS)? as u16,
+                    value: T::bit_deserialize(reader)?,
+                },
+                2 => ListOp::Move {
+                    from: reader.read_bits(INDEX_BITS)? as u16,
+                    to: reader.read_bits(INDEX_BITS)? as u16,
+                },
+                tag => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown list edit {}", tag))),
+            };
+            ops.push(op);
+        }
+        Ok(Self { version, change: ListChange::Edits { base, ops } })
+    }
+}
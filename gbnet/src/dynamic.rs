@@ -0,0 +1,380 @@
+// dynamic.rs - Runtime decoding of `#[schema]` types without compile-time knowledge of them
+//
+// Walks a registered TypeSchema over bit-packed data and mirrors what the derive generates,
+// so debugging tools and packet dissectors can show any registered message by field name.
+use std::io;
+#[cfg(feature = "json")]
+use serde_json::{json, Map, Value};
+
+use crate::debug_packets;
+use crate::schema::{self, FieldSchema, SchemaKind, TypeSchema};
+use crate::serialize::{CHAR_BITS, DEFAULT_MAX_BLOB_LEN, bit_io::{BitBuffer, BitRead}};
+
+// Length prefix used by Vec and String fields without a max_len
+const DEFAULT_LEN_BITS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DynamicValue {
+    Bool(bool),
+    UInt(u64),
+    Int(i64),
+    Float(f64),
+    Char(char),
+    String(String),
+    List(Vec<DynamicValue>),
+    Option(Option<Box<DynamicValue>>),
+    Message(DynamicMessage),
+}
+
+/// A decoded struct, or one variant of an enum, with its fields in wire order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynamicMessage {
+    pub type_name: &'static str,
+    pub variant: Option<&'static str>,
+    pub fields: Vec<(&'static str, DynamicValue)>,
+}
+
+impl DynamicMessage {
+    /// Decodes bit-packed bytes as the registered type with the given name.
+    pub fn decode(type_name: &str, bytes: &[u8]) -> io::Result<Self> {
+        let schema = lookup(type_name)?;
+        let mut reader = BitBuffer::from_bytes(bytes.to_vec());
+        Self::decode_schema(schema, &mut reader)
+    }
+
+    /// Decodes the first of `schemas` that reads without error, rewinding after each one
+    /// that fails. Without `debug-packets` tags a wrong type can still read cleanly, so
+    /// order the candidates from most to least specific.
+    pub fn decode_first<R: BitRead>(schemas: &[&'static TypeSchema], reader: &mut R) -> io::Result<Self> {
+        let mut last_error = None;
+        for &schema in schemas {
+            match reader.try_read(|reader| Self::decode_schema(schema, reader)) {
+                Ok(message) => return Ok(message),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No schemas to try")))
+    }
+    
+    /// Decodes one message described by `schema` from a bit reader.
+    pub fn decode_schema<R: BitRead>(schema: &'static TypeSchema, reader: &mut R) -> io::Result<Self> {
+        if let Some(tag) = schema.tag.filter(|_| debug_packets::ENABLED) {
+            debug_packets::check_tag(reader, tag, schema.name)?;
+        }
+        Self::decode_untagged(schema, reader)
+    }
+
+    // Registered types nested in a message's fields carry no tag of their own
+    fn decode_untagged<R: BitRead>(schema: &'static TypeSchema, reader: &mut R) -> io::Result<Self> {
+        match schema.kind {
+            SchemaKind::Struct { fields } => Ok(Self {
+                type_name: schema.name,
+                variant: None,
+                fields: decode_fields(fields, reader)?,
+            }),
+            SchemaKind::Enum { variant_bits, variants } => {
+                let index = reader.read_bits(variant_bits)?;
+                let variant = variants.iter().find(|variant| variant.index == index).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Unknown variant index")
+                })?;
+                Ok(Self {
+                    type_name: schema.name,
+                    variant: Some(variant.name),
+                    fields: decode_fields(variant.fields, reader)?,
+                })
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DynamicValue> {
+        self.fields.iter().find(|(field, _)| *field == name).map(|(_, value)| value)
+    }
+
+    /// Structs become objects keyed by field name; enum variants are wrapped as
+    /// `{ "Variant": { .. } }`, or just `"Variant"` when they carry no fields.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Value {
+        let fields: Map<String, Value> = self.fields.iter()
+            .map(|(name, value)| (name.to_string(), value.to_json()))
+            .collect();
+        match self.variant {
+            Some(variant) if fields.is_empty() => json!(variant),
+            Some(variant) => json!({ variant: Value::Object(fields) }),
+            None => Value::Object(fields),
+        }
+    }
+}
+
+impl DynamicValue {
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Value {
+        match self {
+            DynamicValue::Bool(value) => json!(value),
+            DynamicValue::UInt(value) => json!(value),
+            DynamicValue::Int(value) => json!(value),
+            DynamicValue::Float(value) => json!(value),
+            DynamicValue::Char(value) => json!(value.to_string()),
+            DynamicValue::String(value) => json!(value),
+            DynamicValue::List(values) => Value::Array(values.iter().map(DynamicValue::to_json).collect()),
+            DynamicValue::Option(value) => value.as_ref().map_or(Value::Null, |value| value.to_json()),
+            DynamicValue::Message(message) => message.to_json(),
+        }
+    }
+}
+
+/// Bits each field of one `schema` value takes on the wire, in wire order.
+///
+/// Enum discriminants are listed under the variant name and their fields as
+/// `Variant.field`. Byte-align padding is counted toward the field that follows it.
+pub fn field_bits<R: BitRead>(schema: &'static TypeSchema, reader: &mut R) -> io::Result<Vec<(String, usize)>> {
+    let tag = schema.tag.filter(|_| debug_packets::ENABLED);
+    if let Some(tag) = tag {
+        debug_packets::check_tag(reader, tag, schema.name)?;
+    }
+    let (prefix, fields) = match schema.kind {
+        SchemaKind::Struct { fields } => (None, fields),
+        SchemaKind::Enum { variant_bits, variants } => {
+            let index = reader.read_bits(variant_bits)?;
+            let variant = variants.iter().find(|variant| variant.index == index).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Unknown variant index")
+            })?;
+            (Some((variant.name, variant_bits)), variant.fields)
+        }
+    };
+    
+    let mut widths = Vec::with_capacity(fields.len() + 2);
+    if tag.is_some() {
+        widths.push(("tag".to_string(), debug_packets::TAG_BITS));
+    }
+    if let Some((variant, bits)) = prefix {
+        widths.push((variant.to_string(), bits));
+    }
+    for field in fields {
+        let start = reader.bit_pos();
+        decode_field(field, reader)?;
+        let name = match prefix {
+            Some((variant, _)) => format!("{}.{}", variant, field.name),
+            None => field.name.to_string(),
+        };
+        widths.push((name, reader.bit_pos() - start));
+    }
+    Ok(widths)
+}
+
+fn lookup(type_name: &str) -> io::Result<&'static TypeSchema> {
+    schema::find(type_name).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("No schema registered for type {}", type_name),
+    ))
+}
+
+fn decode_fields<R: BitRead>(fields: &'static [FieldSchema], reader: &mut R) -> io::Result<Vec<(&'static str, DynamicValue)>> {
+    fields.iter()
+        .map(|field| Ok((field.name, decode_field(field, reader)?)))
+        .collect()
+}
+
+fn decode_field<R: BitRead>(field: &FieldSchema, reader: &mut R) -> io::Result<DynamicValue> {
+    if field.byte_align {
+        while !reader.bit_pos().is_multiple_of(8) {
+            reader.read_bit()?;
+        }
+    }
+
+    if let (Some((min, max)), Some(bits)) = (field.quantize, field.bits) {
+        let steps = ((1u64 << bits) - 1) as f64;
+        let quantized = reader.read_bits(bits)? as f64;
+        return Ok(DynamicValue::Float(min + quantized / steps * (max - min)));
+    }
+
+    if field.varint {
+        return Ok(match native_width(field.ty) {
+            Some((_, true)) => DynamicValue::Int(reader.read_signed_varint()?),
+            _ => DynamicValue::UInt(reader.read_varint()?),
+        });
+    }
+
+    if field.blob {
+        let len = match byte_array_len(field.ty) {
+            Some(len) => len,
+            None => usize::try_from(reader.read_varint()?).unwrap_or(usize::MAX),
+        };
+        if len > field.max_len.unwrap_or(DEFAULT_MAX_BLOB_LEN) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Blob length {} exceeds max_len for field {}", len, field.name)));
+        }
+        let mut bytes = vec![0u8; len];
+        reader.read_aligned_into(&mut bytes)?;
+        return Ok(DynamicValue::List(bytes.into_iter().map(|byte| DynamicValue::UInt(byte as u64)).collect()));
+    }
+
+    // Only values sent whole can be rebuilt without the sender's baseline
+    if field.delta_string {
+        let len_bits = field.max_len.map_or(DEFAULT_LEN_BITS, len_bits_for);
+        if reader.read_bits(len_bits)? != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Field {} is a delta against an unknown baseline", field.name)));
+        }
+        return decode_type("String", Some(len_bits), reader);
+    }
+    
+    match (field.bits, native_width(field.ty)) {
+        (Some(bits), Some((native, signed))) => {
+            let raw = reader.read_bits(bits)?;
+            Ok(integer_value(field.ty, raw, bits == native && signed, bits))
+        }
+        _ => {
+            let len_bits = field.max_len.map(len_bits_for);
+            decode_type(field.ty, len_bits, reader)
+        }
+    }
+}
+
+// Length of a `[u8; N]` as the schema spells it ("[u8;N]")
+fn byte_array_len(ty: &str) -> Option<usize> {
+    ty.strip_prefix("[u8;")?.strip_suffix(']')?.parse().ok()
+}
+
+// Mirrors how the derive sizes the length prefix from a max_len
+fn len_bits_for(max_len: usize) -> usize {
+    ((max_len + 1) as f64).log2().ceil() as usize
+}
+
+fn native_width(ty: &str) -> Option<(usize, bool)> {
+    match ty {
+        "bool" => Some((1, false)),
+        "u8" => Some((8, false)),
+        "i8" => Some((8, true)),
+        "u16" => Some((16, false)),
+        "i16" => Some((16, true)),
+        "u32" => Some((32, false)),
+        "i32" => Some((32, true)),
+        "u64" | "usize" => Some((64, false)),
+        "i64" | "isize" => Some((64, true)),
+        _ => None,
+    }
+}
+
+// Narrowed signed fields only ever hold non-negative values (the derive range checks them),
+// so only full-width signed fields need sign extension
+fn integer_value(ty: &str, raw: u64, sign_extend: bool, bits: usize) -> DynamicValue {
+    if ty == "bool" {
+        DynamicValue::Bool(raw != 0)
+    } else if sign_extend {
+        let shift = 64 - bits;
+        DynamicValue::Int(((raw << shift) as i64) >> shift)
+    } else if native_width(ty).is_some_and(|(_, signed)| signed) {
+        DynamicValue::Int(raw as i64)
+    } else {
+        DynamicValue::UInt(raw)
+    }
+}
+
+fn decode_type<R: BitRead>(ty: &str, len_bits: Option<usize>, reader: &mut R) -> io::Result<DynamicValue> {
+    if let Some((native, signed)) = native_width(ty) {
+        let raw = reader.read_bits(native)?;
+        return Ok(integer_value(ty, raw, signed, native));
+    }
+
+    match ty {
+        "f32" => return Ok(DynamicValue::Float(f32::from_bits(reader.read_bits(32)? as u32) as f64)),
+        "f64" => return Ok(DynamicValue::Float(f64::from_bits(reader.read_bits(64)?))),
+        "char" => {
+            let value = reader.read_bits(CHAR_BITS)? as u32;
+            return char::from_u32(value).map(DynamicValue::Char).ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid char scalar value {:#x}", value),
+            ));
+        }
+        "String" => {
+            let len = reader.read_bits(len_bits.unwrap_or(DEFAULT_LEN_BITS))? as usize;
+            let mut bytes = Vec::with_capacity(len);
+            for _ in 0..len {
+                bytes.push(reader.read_bits(8)? as u8);
+            }
+            return String::from_utf8(bytes).map(DynamicValue::String).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("Invalid UTF-8: {}", e))
+            });
+        }
+        _ => {}
+    }
+
+    if let Some(inner) = generic_argument(ty, "Option") {
+        let value = if reader.read_bit()? {
+            Some(Box::new(decode_type(inner, None, reader)?))
+        } else {
+            None
+        };
+        return Ok(DynamicValue::Option(value));
+    }
+    if let Some(inner) = generic_argument(ty, "Vec") {
+        let len = reader.read_bits(len_bits.unwrap_or(DEFAULT_LEN_BITS))?;
+        return (0..len).map(|_| decode_type(inner, None, reader)).collect::<io::Result<_>>().map(DynamicValue::List);
+    }
+    // Maps come out as `[key, value]` pairs in key order
+    if let Some(inner) = generic_argument(ty, "BTreeMap").or_else(|| generic_argument(ty, "HashMap")) {
+        let [key, value] = split_top_level(inner)[..] else {
+            return Err(unsupported(ty));
+        };
+        let len = reader.read_bits(DEFAULT_LEN_BITS)?;
+        return (0..len)
+            .map(|_| Ok(DynamicValue::List(vec![decode_type(key, None, reader)?, decode_type(value, None, reader)?])))
+            .collect::<io::Result<_>>()
+            .map(DynamicValue::List);
+    }
+    if let Some(inner) = generic_argument(ty, "BTreeSet").or_else(|| generic_argument(ty, "HashSet")) {
+        let len = reader.read_bits(DEFAULT_LEN_BITS)?;
+        return (0..len).map(|_| decode_type(inner, None, reader)).collect::<io::Result<_>>().map(DynamicValue::List);
+    }
+    if let Some(inner) = ty.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+        let (element, len) = inner.rsplit_once(';').ok_or_else(|| unsupported(ty))?;
+        let len: usize = len.parse().map_err(|_| unsupported(ty))?;
+        return (0..len).map(|_| decode_type(element, None, reader)).collect::<io::Result<_>>().map(DynamicValue::List);
+    }
+    if let Some(inner) = ty.strip_prefix('(').and_then(|rest| rest.strip_suffix(')')) {
+        return split_top_level(inner)
+            .into_iter()
+            .map(|element| decode_type(element, None, reader))
+            .collect::<io::Result<_>>()
+            .map(DynamicValue::List);
+    }
+
+    // Anything else has to be another registered type
+    let name = ty.rsplit("::").next().unwrap_or(ty);
+    let schema = schema::find(name).ok_or_else(|| unsupported(ty))?;
+    DynamicMessage::decode_untagged(schema, reader).map(DynamicValue::Message)
+}
+
+// `Vec<T>` / `std::vec::Vec<T>` -> `T`
+fn generic_argument<'a>(ty: &'a str, wrapper: &str) -> Option<&'a str> {
+    let start = ty.find('<')?;
+    let path = &ty[..start];
+    if path.rsplit("::").next()? != wrapper {
+        return None;
+    }
+    ty[start + 1..].strip_suffix('>')
+}
+
+fn split_top_level(list: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            '>' | ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    // Tolerate the trailing comma of one-element tuples
+    if start < list.len() {
+        parts.push(&list[start..]);
+    }
+    parts
+}
+
+fn unsupported(ty: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("Cannot decode type {} dynamically", ty))
+}
@@ -0,0 +1,158 @@
+// filter.rs - Address allow/deny lists, raw packet filtering hooks and request throttling
+use std::collections::HashSet;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::connection::Connection;
+
+/// Callback consulted for every datagram before any parsing takes place.
+///
+/// Receives the sender address and the raw bytes; returning `false` drops the
+/// datagram immediately. Keep implementations cheap, they run on the hot path.
+pub trait PacketFilter {
+    fn allow(&mut self, addr: SocketAddr, data: &[u8]) -> bool;
+}
+
+impl<F> PacketFilter for F
+where
+    F: FnMut(SocketAddr, &[u8]) -> bool,
+{
+    fn allow(&mut self, addr: SocketAddr, data: &[u8]) -> bool {
+        self(addr, data)
+    }
+}
+
+/// Callback consulted for revoked credentials, e.g. accounts banned mid-session.
+///
+/// Connections are usually matched on the account or token the application stored in their
+/// [`Connection::extensions`] after authenticating them; `addr` covers address-based lists.
+/// The server asks when a client connects or resumes, and for every live connection in
+/// `Server::apply_revocations`.
+pub trait RevocationCheck {
+    fn is_revoked(&mut self, addr: SocketAddr, connection: &Connection) -> bool;
+}
+
+impl<F> RevocationCheck for F
+where
+    F: FnMut(SocketAddr, &Connection) -> bool,
+{
+    fn is_revoked(&mut self, addr: SocketAddr, connection: &Connection) -> bool {
+        self(addr, connection)
+    }
+}
+
+/// IP-level allow and deny lists.
+///
+/// The deny list always wins. When the allow list is non-empty only the
+/// addresses it contains are accepted.
+#[derive(Debug, Clone, Default)]
+pub struct AddressFilter {
+    allow_list: HashSet<IpAddr>,
+    deny_list: HashSet<IpAddr>,
+}
+
+impl AddressFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an address to the allow list
+    pub fn allow(&mut self, ip: IpAddr) {
+        self.allow_list.insert(ip);
+    }
+
+    /// Removes an address from the allow list
+    pub fn remove_allow(&mut self, ip: IpAddr) -> bool {
+        self.allow_list.remove(&ip)
+    }
+
+    /// Adds an address to the deny list
+    pub fn deny(&mut self, ip: IpAddr) {
+        self.deny_list.insert(ip);
+    }
+
+    /// Removes an address from the deny list
+    pub fn remove_deny(&mut self, ip: IpAddr) -> bool {
+        self.deny_list.remove(&ip)
+    }
+
+    /// Returns whether packets from this address should be processed
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny_list.contains(&ip) {
+            return false;
+        }
+        self.allow_list.is_empty() || self.allow_list.contains(&ip)
+    }
+
+    /// Returns whether the address is explicitly denied
+    pub fn is_denied(&self, ip: IpAddr) -> bool {
+        self.deny_list.contains(&ip)
+    }
+
+    /// Clears both lists
+    pub fn clear(&mut self) {
+        self.allow_list.clear();
+        self.deny_list.clear();
+    }
+}
+
+const LIMITER_DEPTH: usize = 4;
+
+/// Per-source connection request rate limiter.
+///
+/// Counts requests per IP in a count-min sketch so memory stays fixed no matter
+/// how many distinct sources an attacker uses. Estimates can only over-count,
+/// so a legitimate client is throttled at worst slightly early under heavy load.
+#[derive(Debug, Clone)]
+pub struct ConnectionRequestLimiter {
+    max_requests: u32,
+    window: Duration,
+    window_start: Option<Instant>,
+    width: usize,
+    hashers: [RandomState; LIMITER_DEPTH],
+    counters: Vec<u32>,
+}
+
+impl ConnectionRequestLimiter {
+    pub fn new(max_requests: u32, window: Duration, width: usize) -> Self {
+        let width = width.max(1);
+        Self {
+            max_requests,
+            window,
+            window_start: None,
+            width,
+            hashers: std::array::from_fn(|_| RandomState::new()),
+            counters: vec![0; width * LIMITER_DEPTH],
+        }
+    }
+
+    /// Records a request from `ip` and returns whether it is within the limit
+    pub fn allow(&mut self, ip: IpAddr, now: Instant) -> bool {
+        match self.window_start {
+            Some(start) if now.duration_since(start) < self.window => {}
+            _ => {
+                self.counters.iter_mut().for_each(|counter| *counter = 0);
+                self.window_start = Some(now);
+            }
+        }
+
+        let mut estimate = u32::MAX;
+        for row in 0..LIMITER_DEPTH {
+            let index = row * self.width + (self.hashers[row].hash_one(ip) as usize % self.width);
+            self.counters[index] = self.counters[index].saturating_add(1);
+            estimate = estimate.min(self.counters[index]);
+        }
+
+        estimate <= self.max_requests
+    }
+
+    /// Returns the estimated number of requests seen from `ip` in the current window
+    pub fn estimate(&self, ip: IpAddr) -> u32 {
+        (0..LIMITER_DEPTH)
+            .map(|row| self.counters[row * self.width + (self.hashers[row].hash_one(ip) as usize % self.width)])
+            .min()
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,40 @@
+// handler.rs - Callback alternative to polling server events
+//
+// Polling with `Server::poll_event` and `Server::receive` suits a game loop; engines built
+// around callbacks can install a ServerHandler instead. The server calls it at one fixed
+// point, at the end of `Server::update`, never while it is walking its connections, and
+// hands it the server itself, so a callback may send, disconnect or move clients freely.
+//
+// Re-entrancy: the handler is taken out of the server for the duration of a dispatch.
+// Events raised by calls made from a callback (a disconnect, say) are queued and delivered
+// in the same dispatch once the callback returns, never recursively. Calling
+// `Server::update` from a callback is allowed; its events join the same queue. Replacing or
+// clearing the handler from a callback takes effect when the dispatch ends.
+use std::net::SocketAddr;
+
+use crate::channel::ChannelMismatch;
+use crate::server::Server;
+
+/// Callbacks for server events and client messages; see [`Server::set_handler`].
+///
+/// While a handler is installed, events and messages go to it instead of
+/// [`Server::poll_event`] and [`Server::receive`].
+pub trait ServerHandler {
+    fn on_client_connected(&mut self, _server: &mut Server, _addr: SocketAddr) {}
+
+    fn on_client_resumed(&mut self, _server: &mut Server, _addr: SocketAddr) {}
+
+    /// `reason` is one of [`disconnect_reason`](crate::packet::disconnect_reason).
+    fn on_client_disconnected(&mut self, _server: &mut Server, _addr: SocketAddr, _reason: u8) {}
+
+    /// The client's app went into the background; see [`Connection::pause`](crate::Connection::pause).
+    fn on_client_paused(&mut self, _server: &mut Server, _addr: SocketAddr) {}
+
+    fn on_client_unpaused(&mut self, _server: &mut Server, _addr: SocketAddr) {}
+
+    /// The client's channels are configured differently from the server's.
+    fn on_channel_mismatch(&mut self, _server: &mut Server, _addr: SocketAddr, _mismatch: ChannelMismatch) {}
+
+    /// Every message received from a client, in channel order per client.
+    fn on_message(&mut self, server: &mut Server, addr: SocketAddr, channel_id: u8, message: Vec<u8>);
+}
@@ -0,0 +1,158 @@
+// instant.rs - Monotonic and wall clock time that also works on the web
+//
+// Timers and RTT need a clock that never jumps, while timestamps shown to players or compared
+// across machines need the wall clock, ideally agreeing with the server's despite local skew.
+// `monotonic` and `wall_clock` cover the first two on every target, and `SyncedClock`
+// corrects the wall clock by the offset measured against a peer.
+//
+// Connections and servers read `Instant`s from the `Clock` in their config instead of the
+// system directly, so tests can share a manual clock between both ends and advance it by hand.
+//
+// `std::time::Instant` and `SystemTime::now` panic on wasm32-unknown-unknown, so there both
+// are read from the host, which must provide `performance_now` and `date_now` (returning
+// `performance.now()` and `Date.now()` in milliseconds) in the `gbnet` import module.
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+#[link(wasm_import_module = "gbnet")]
+extern "C" {
+    fn performance_now() -> f64;
+    fn date_now() -> f64;
+}
+
+/// Time since an arbitrary process-wide origin; never goes backwards.
+pub fn monotonic() -> Duration {
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    {
+        // SAFETY: a host import with no arguments and no memory access
+        let millis = unsafe { performance_now() };
+        Duration::from_secs_f64(millis.max(0.0) / 1000.0)
+    }
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    {
+        use std::sync::OnceLock;
+        static ORIGIN: OnceLock<Instant> = OnceLock::new();
+        ORIGIN.get_or_init(Instant::now).elapsed()
+    }
+}
+
+/// The local wall clock, which may be skewed and may jump when it is adjusted.
+pub fn wall_clock() -> SystemTime {
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    {
+        // SAFETY: a host import with no arguments and no memory access
+        let millis = unsafe { date_now() };
+        UNIX_EPOCH + Duration::from_secs_f64(millis.max(0.0) / 1000.0)
+    }
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    {
+        SystemTime::now()
+    }
+}
+
+/// Where connections, servers and sockets get the current `Instant` from.
+///
+/// Clones share the time, so one manual clock given to both ends of a test moves them together.
+#[derive(Clone, Default)]
+pub struct Clock {
+    manual: Option<Arc<Mutex<Instant>>>,
+}
+
+impl Clock {
+    /// Reads `Instant::now()`.
+    pub fn system() -> Self {
+        Self::default()
+    }
+
+    /// Stands still until [`Clock::advance`] moves it; starts at the current time.
+    pub fn manual() -> Self {
+        Self { manual: Some(Arc::new(Mutex::new(Instant::now()))) }
+    }
+
+    pub fn is_manual(&self) -> bool {
+        self.manual.is_some()
+    }
+
+    pub fn now(&self) -> Instant {
+        match &self.manual {
+            Some(now) => *now.lock().unwrap_or_else(|poisoned| poisoned.into_inner()),
+            None => Instant::now(),
+        }
+    }
+
+    /// Moves a manual clock forward; the system clock ignores it.
+    pub fn advance(&self, by: Duration) {
+        if let Some(now) = &self.manual {
+            *now.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) += by;
+        }
+    }
+}
+
+impl fmt::Debug for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Clock").field("manual", &self.is_manual()).finish()
+    }
+}
+
+/// Wall clock corrected to agree with a reference clock, usually the server's.
+///
+/// Each sample pairs a reference reading with the local time it arrived and the round trip
+/// it took. As in NTP, the reference read it half a round trip earlier, and only the sample
+/// with the shortest round trip is kept since queuing delay makes the others less certain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SyncedClock {
+    // Reference minus local, in microseconds, and the round trip of the sample it came from
+    offset_micros: Option<i64>,
+    best_rtt: Option<Duration>,
+}
+
+impl SyncedClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a sample; returns whether it replaced the current offset.
+    pub fn observe(&mut self, reference: SystemTime, received_at: SystemTime, rtt: Duration) -> bool {
+        if self.best_rtt.is_some_and(|best| rtt >= best) {
+            return false;
+        }
+        let reference = micros_since_epoch(reference) + (rtt.as_micros() / 2) as i64;
+        self.offset_micros = Some(reference - micros_since_epoch(received_at));
+        self.best_rtt = Some(rtt);
+        true
+    }
+
+    /// Microseconds the reference clock is ahead of the local one (negative when behind);
+    /// `None` before the first sample.
+    pub fn offset_micros(&self) -> Option<i64> {
+        self.offset_micros
+    }
+
+    /// Worst-case error of the offset: half the round trip of its sample.
+    pub fn accuracy(&self) -> Option<Duration> {
+        self.best_rtt.map(|rtt| rtt / 2)
+    }
+
+    /// The reference clock's current time, or the local wall clock before the first sample.
+    pub fn now(&self) -> SystemTime {
+        self.to_reference(wall_clock())
+    }
+
+    /// Converts a local wall clock reading to the reference clock.
+    pub fn to_reference(&self, local: SystemTime) -> SystemTime {
+        match self.offset_micros {
+            Some(micros) if micros < 0 => local.checked_sub(Duration::from_micros(micros.unsigned_abs())).unwrap_or(local),
+            Some(micros) => local.checked_add(Duration::from_micros(micros as u64)).unwrap_or(local),
+            None => local,
+        }
+    }
+}
+
+fn micros_since_epoch(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_micros() as i64,
+        Err(e) => -(e.duration().as_micros() as i64),
+    }
+}
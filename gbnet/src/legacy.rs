@@ -0,0 +1,165 @@
+// legacy.rs - Carrying bincode/serde-encoded messages while a protocol moves to bit-packing
+//
+// Rewriting every message type of an existing protocol before switching transports is a big
+// bang nobody wants to ship. A channel with `PayloadMode::Legacy` carries messages in their old
+// encoding instead: each one is a little-endian u32 length followed by the legacy bytes,
+// byte-aligned and untouched, sent with `send_legacy` and read with `receive_legacy`. Inside
+// bit-packed types, `Legacy<T>` frames a single field the same way, so a new message enum can
+// keep its unconverted variants as they were. The crate does not depend on bincode: implement
+// `LegacyMessage` with the application's own encoder, or pass already-encoded `Vec<u8>`s.
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::serialize::{bit_io, BitDeserialize, BitSerialize, ByteAlignedDeserialize, ByteAlignedSerialize};
+
+/// Length prefix in front of every legacy message.
+pub const LEGACY_HEADER_BYTES: usize = 4;
+
+// Buffer reserved up front when decoding a `Legacy<T>` field, whatever length it claims
+const MAX_PREALLOCATED: usize = 4096;
+
+/// A message in the encoding the application used before gbnet.
+///
+/// With bincode 1.x:
+///
+/// ```ignore
+/// impl LegacyMessage for ChatMessage {
+///     fn encode_legacy(&self, out: &mut Vec<u8>) -> Result<(), LegacyError> {
+///         bincode::serialize_into(out, self).map_err(|e| LegacyError::Encode(e.to_string()))
+///     }
+///
+///     fn decode_legacy(bytes: &[u8]) -> Result<Self, LegacyError> {
+///         bincode::deserialize(bytes).map_err(|e| LegacyError::Decode(e.to_string()))
+///     }
+/// }
+/// ```
+pub trait LegacyMessage: Sized {
+    /// Appends the encoded message to `out`.
+    fn encode_legacy(&self, out: &mut Vec<u8>) -> Result<(), LegacyError>;
+
+    /// Decodes a message from exactly the bytes `encode_legacy` produced.
+    fn decode_legacy(bytes: &[u8]) -> Result<Self, LegacyError>;
+}
+
+/// Bytes the application already encoded, passed through as they are.
+impl LegacyMessage for Vec<u8> {
+    fn encode_legacy(&self, out: &mut Vec<u8>) -> Result<(), LegacyError> {
+        out.extend_from_slice(self);
+        Ok(())
+    }
+
+    fn decode_legacy(bytes: &[u8]) -> Result<Self, LegacyError> {
+        Ok(bytes.to_vec())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LegacyError {
+    /// The legacy encoder failed
+    Encode(String),
+    /// The legacy decoder failed
+    Decode(String),
+    /// The length prefix does not match the bytes that follow it
+    LengthMismatch { declared: usize, actual: usize },
+    /// The encoded message is longer than a u32 length can describe
+    TooLarge(usize),
+}
+
+impl fmt::Display for LegacyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LegacyError::Encode(e) => write!(f, "legacy encode failed: {}", e),
+            LegacyError::Decode(e) => write!(f, "legacy decode failed: {}", e),
+            LegacyError::LengthMismatch { declared, actual } => {
+                write!(f, "legacy frame declares {} bytes but carries {}", declared, actual)
+            }
+            LegacyError::TooLarge(len) => write!(f, "legacy message of {} bytes is too large", len),
+        }
+    }
+}
+
+impl std::error::Error for LegacyError {}
+
+impl From<LegacyError> for io::Error {
+    fn from(err: LegacyError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Encodes a message as one legacy frame: its length, then its legacy bytes.
+pub fn encode_frame<T: LegacyMessage>(message: &T) -> Result<Vec<u8>, LegacyError> {
+    let mut frame = vec![0; LEGACY_HEADER_BYTES];
+    message.encode_legacy(&mut frame)?;
+    let len = frame.len() - LEGACY_HEADER_BYTES;
+    let declared = u32::try_from(len).map_err(|_| LegacyError::TooLarge(len))?;
+    frame[..LEGACY_HEADER_BYTES].copy_from_slice(&declared.to_le_bytes());
+    Ok(frame)
+}
+
+/// Decodes a frame from [`encode_frame`], which must be the whole of `frame`.
+pub fn decode_frame<T: LegacyMessage>(frame: &[u8]) -> Result<T, LegacyError> {
+    let header = frame.get(..LEGACY_HEADER_BYTES)
+        .ok_or(LegacyError::LengthMismatch { declared: 0, actual: frame.len() })?;
+    let declared = u32::from_le_bytes(header.try_into().unwrap_or_default()) as usize;
+    let body = &frame[LEGACY_HEADER_BYTES..];
+    if body.len() != declared {
+        return Err(LegacyError::LengthMismatch { declared, actual: body.len() });
+    }
+    T::decode_legacy(body)
+}
+
+/// A legacy-encoded value inside a bit-packed message.
+///
+/// Written as a 32-bit length, padding to the next byte boundary, then the legacy bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Legacy<T>(pub T);
+
+impl<T: LegacyMessage> BitSerialize for Legacy<T> {
+    fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        self.0.encode_legacy(&mut bytes)?;
+        let len = u32::try_from(bytes.len()).map_err(|_| LegacyError::TooLarge(bytes.len()))?;
+        writer.write_bits(len as u64, 32)?;
+        while !writer.bit_pos().is_multiple_of(8) {
+            writer.write_bit(false)?;
+        }
+        for byte in &bytes {
+            writer.write_bits(*byte as u64, 8)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: LegacyMessage> BitDeserialize for Legacy<T> {
+    fn bit_deserialize<R: bit_io::BitRead>(reader: &mut R) -> io::Result<Self> {
+        let len = reader.read_bits(32)? as usize;
+        while !reader.bit_pos().is_multiple_of(8) {
+            reader.read_bit()?;
+        }
+        let mut bytes = Vec::with_capacity(len.min(MAX_PREALLOCATED));
+        for _ in 0..len {
+            bytes.push(reader.read_bits(8)? as u8);
+        }
+        Ok(Legacy(T::decode_legacy(&bytes)?))
+    }
+}
+
+impl<T: LegacyMessage> ByteAlignedSerialize for Legacy<T> {
+    fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&encode_frame(&self.0)?)
+    }
+}
+
+impl<T: LegacyMessage> ByteAlignedDeserialize for Legacy<T> {
+    fn byte_aligned_deserialize<R: Read + ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        let len = reader.read_u32::<LittleEndian>()? as usize;
+        let mut bytes = Vec::with_capacity(len.min(MAX_PREALLOCATED));
+        reader.take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(LegacyError::LengthMismatch { declared: len, actual: bytes.len() }.into());
+        }
+        Ok(Legacy(T::decode_legacy(&bytes)?))
+    }
+}
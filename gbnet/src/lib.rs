@@ -7,26 +7,99 @@ pub mod connection;
 pub mod reliability;
 pub mod channel;
 pub mod config;
+pub mod server;
+pub mod handler;
+pub mod filter;
+pub mod crypto;
+pub mod ticket;
+pub mod token;
+pub mod extensions;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+#[cfg(feature = "conformance")]
+pub mod compat;
+pub mod schema;
+pub mod dynamic;
+pub mod loadtest;
+pub mod ping;
+pub mod congestion;
+pub mod pmtu;
+pub mod resolve;
+pub mod rng;
+pub mod telemetry;
+pub mod profiler;
+pub mod trace;
+pub mod middleware;
+pub mod decode_pool;
+pub mod checksum;
+pub mod compression;
+pub mod timestamp;
+pub mod instant;
+pub mod delta;
+pub mod delta_vec;
+pub mod room;
+pub mod snapshot;
+pub mod debug;
+pub mod debug_packets;
+pub mod simulator;
+pub mod stats;
+pub mod rtt;
+pub mod legacy;
+#[cfg(any(test, feature = "alloc-audit"))]
+pub mod alloc_audit;
+#[cfg(any(test, feature = "test-util"))]
+pub mod local;
 pub mod serialize;  // Make serialize module public
+pub mod prelude;
 
 // Test modules (only compiled during testing)
 #[cfg(test)]
 mod tests;
 
 // Re-export main types for convenience
-pub use socket::{UdpSocket, SocketError};
-pub use packet::{Packet, PacketHeader, PacketType};
-pub use connection::{Connection, ConnectionState, ConnectionError};
+pub use socket::{MemoryNetwork, SendErrorClass, SendErrorCounts, UdpSocket, SocketError};
+#[cfg(feature = "tokio")]
+pub use socket::AsyncUdpSocket;
+pub use packet::{capability, header_extension, HeaderExtensions, Packet, PacketHeader, PacketType, PacketView};
+pub use connection::{Connection, ConnectionState, ConnectionError, ConnectionEvent, DeliveryStatus};
 pub use reliability::{ReliableEndpoint, SequenceBuffer};
-pub use channel::{Channel, ChannelError};
-pub use config::{NetworkConfig, ChannelConfig, Reliability, Ordering};
+pub use channel::{BatchItems, Channel, ChannelError, ChannelMismatch};
+pub use config::{NetworkConfig, ChannelConfig, ChannelGroupConfig, ChannelMismatchPolicy, ChannelScheduling, ConnectionRole, PayloadMode, ReceiveOverflow, Reliability, Ordering, ResendStrategy, SendErrorPolicies, SendErrorPolicy, SlowConsumer};
+pub use server::{Server, ServerEvent, ServerStats};
+pub use handler::ServerHandler;
+pub use filter::{AddressFilter, PacketFilter, RevocationCheck, ConnectionRequestLimiter};
+pub use crypto::{SessionKeys, ServerIdentity};
+pub use ticket::ResumptionTicket;
+pub use token::{ConnectToken, ConnectTokenKey, TokenError};
+pub use extensions::Extensions;
+pub use dynamic::{DynamicMessage, DynamicValue};
+pub use rng::DeterministicRng;
+pub use telemetry::{PacketTelemetry, TelemetrySampler};
+pub use profiler::{FieldProfile, MessageProfile, WireProfiler};
+pub use trace::{TraceEvent, TraceEventKind, TraceRecorder};
+pub use middleware::{MiddlewareChain, PacketMiddleware};
+pub use decode_pool::{DecodePool, Decoded};
+pub use checksum::{Checksum, ChecksumLayer, Crc32c, XxHash64, NoChecksum};
+pub use compression::{Codec, CodecRegistry, Compression, Lz77};
+pub use timestamp::{TimestampEpoch, TimestampResolution};
+pub use instant::{monotonic, wall_clock, Clock, SyncedClock};
+pub use delta::StringBaselines;
+pub use delta_vec::{DeltaVec, ListChange, ListDelta, ListOp};
+pub use ping::{ping_servers, PingConfig, PingResult, Pinger, PING_PAYLOAD_BYTES};
+pub use resolve::{connect_host, resolve, ConnectError, HostConnector, ResolveError};
+pub use room::{Room, RoomId, RoomStats};
+pub use simulator::{LossModel, NetworkSimulator, QueueDrop, SimulatorConfig, SimulatorStats};
+pub use legacy::{Legacy, LegacyError, LegacyMessage};
+pub use rtt::RttHistogram;
+pub use stats::{ConnectionStats, IntervalStats, ServerStatsReport, StatsInterval, StatsSnapshot};
+pub use debug::{ChannelDebugState, DebugOverlayFeed, DebugSnapshot, DebugTimers, OverlayFrame, OverlayMetric};
 
 // In gbnet/src/lib.rs, add:
 pub use gbnet_macros::NetworkSerialize;
 
 // Re-export serialization traits and types
-pub use serialize::{BitSerialize, BitDeserialize, ByteAlignedSerialize, ByteAlignedDeserialize};
-pub use serialize::bit_io::{BitBuffer, BitWrite, BitRead};  // <-- BitBuffer is re-exported here
+pub use serialize::{BitSerialize, BitDeserialize, BitDeserializeBorrowed, DynBitSerialize, ByteAlignedSerialize, ByteAlignedDeserialize};
+pub use serialize::bit_io::{BitAppender, BitBuffer, BitCounter, BitOrder, BitSlice, BitWrite, BitRead};  // <-- BitBuffer is re-exported here
 
 // Re-export commonly used std types
 pub use std::net::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr};
@@ -41,6 +114,33 @@ pub struct NetworkStats {
     pub rtt: f32,
     pub bandwidth_up: f32,
     pub bandwidth_down: f32,
+    /// Bytes held in queues, channel buffers and the reliable window, as of the last update
+    pub memory_usage: usize,
+    /// ICMP unreachable reports read for this connection
+    pub peer_unreachable: u64,
+    /// Failed socket sends by kind
+    pub send_errors: SendErrorCounts,
+    /// Gaps of `sequence_gap_threshold` packets or more, each also raised as `ConnectionEvent::SequenceGap`
+    pub sequence_gaps: u64,
+    /// RTT percentiles in milliseconds from the connection's `RttHistogram`; spikes that
+    /// `rtt`, a smoothed mean, averages away show up here
+    pub rtt_p50: f32,
+    pub rtt_p95: f32,
+    pub rtt_p99: f32,
+    /// Smoothed change between consecutive RTT samples, in milliseconds
+    pub jitter: f32,
+    /// Fragment packets queued for messages too large for one packet
+    pub fragments_sent: u64,
+    /// Fragmented messages dropped because a piece did not arrive within `fragment_timeout`
+    pub fragments_expired: u64,
+    /// Payload packets on unreliable channels dropped for being older than `max_packet_age`
+    pub stale_packets_dropped: u64,
+    /// Sealed packets dropped under `encrypt_packets` for a nonce already opened, or one too
+    /// far behind the newest to tell
+    pub replayed_packets_dropped: u64,
+    /// Message bytes per second the connection may send: the lower of `bandwidth_cap` and
+    /// the congestion controller's rate, or `None` when neither is set
+    pub allowed_bandwidth: Option<f32>,
 }
 
 impl Default for NetworkStats {
@@ -54,6 +154,19 @@ impl Default for NetworkStats {
             rtt: 0.0,
             bandwidth_up: 0.0,
             bandwidth_down: 0.0,
+            memory_usage: 0,
+            peer_unreachable: 0,
+            send_errors: SendErrorCounts::default(),
+            sequence_gaps: 0,
+            rtt_p50: 0.0,
+            rtt_p95: 0.0,
+            rtt_p99: 0.0,
+            jitter: 0.0,
+            fragments_sent: 0,
+            fragments_expired: 0,
+            stale_packets_dropped: 0,
+            replayed_packets_dropped: 0,
+            allowed_bandwidth: None,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,343 @@
+// loadtest.rs - Scripted client swarm for capacity testing a Server
+//
+// Every load message starts with a 16-byte header (client index, sequence, send time in
+// microseconds since the Unix epoch, all little endian) so the receiving side can measure
+// latency and loss without extra coordination. The swarm and server may run in different
+// processes as long as their clocks agree.
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    NetworkConfig,
+    connection::{Connection, ConnectionError},
+    server::Server,
+    socket::{UdpSocket, SocketError},
+};
+
+/// Size of the header at the start of every load message.
+pub const LOAD_HEADER_BYTES: usize = 16;
+
+/// When each client sends its messages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessagePattern {
+    /// Evenly spaced messages at `rate` per second
+    Steady { rate: f32 },
+    /// `count` messages back to back every `interval`
+    Burst { count: usize, interval: Duration },
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadTestConfig {
+    pub clients: usize,
+    /// Config used by every client connection
+    pub network: NetworkConfig,
+    pub pattern: MessagePattern,
+    /// Total message size including the header; smaller values are raised to the header size
+    pub message_size: usize,
+    pub channel: u8,
+    pub reliable: bool,
+    /// New connection attempts started per update, so the server's request throttling is not tripped
+    pub connects_per_update: usize,
+    pub bind_ip: IpAddr,
+}
+
+impl Default for LoadTestConfig {
+    fn default() -> Self {
+        Self {
+            clients: 100,
+            network: NetworkConfig::default(),
+            pattern: MessagePattern::Steady { rate: 10.0 },
+            message_size: 32,
+            channel: 0,
+            reliable: false,
+            connects_per_update: 10,
+            bind_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        }
+    }
+}
+
+struct SwarmClient {
+    socket: UdpSocket,
+    connection: Connection,
+    started: bool,
+    failed: bool,
+    next_send: Option<Instant>,
+    sequence: u32,
+}
+
+/// A set of scripted clients connecting to one server.
+pub struct Swarm {
+    config: LoadTestConfig,
+    clients: Vec<SwarmClient>,
+    sending: bool,
+    messages_sent: u64,
+}
+
+impl Swarm {
+    /// Binds one socket per client. Connections are started gradually by [`Swarm::update`].
+    pub fn new(server_addr: SocketAddr, config: LoadTestConfig) -> Result<Self, SocketError> {
+        let mut clients = Vec::with_capacity(config.clients);
+        for _ in 0..config.clients {
+            let socket = UdpSocket::bind(SocketAddr::new(config.bind_ip, 0))?;
+            let connection = Connection::new(config.network.clone(), socket.local_addr()?, server_addr);
+            clients.push(SwarmClient {
+                socket,
+                connection,
+                started: false,
+                failed: false,
+                next_send: None,
+                sequence: 0,
+            });
+        }
+
+        Ok(Self {
+            config,
+            clients,
+            sending: true,
+            messages_sent: 0,
+        })
+    }
+
+    /// Starts pending connections, sends scheduled messages and updates every client.
+    ///
+    /// A client whose connection times out, is denied or exceeds its memory cap is
+    /// marked failed instead of failing the whole swarm.
+    pub fn update(&mut self) -> Result<(), ConnectionError> {
+        let now = Instant::now();
+        let mut connects = self.config.connects_per_update;
+        let client_count = self.clients.len() as u32;
+
+        for (index, client) in self.clients.iter_mut().enumerate() {
+            if client.failed {
+                continue;
+            }
+            if !client.started {
+                if connects == 0 {
+                    continue;
+                }
+                connects -= 1;
+                client.started = true;
+                client.connection.connect()?;
+            }
+
+            if self.sending && client.connection.is_connected() {
+                // Spread the first send across one period so clients don't fire in lockstep
+                let period = pattern_period(self.config.pattern);
+                let next_send = *client.next_send.get_or_insert_with(|| {
+                    now + period.mul_f64(index as f64 / client_count as f64)
+                });
+                if now >= next_send {
+                    for _ in 0..messages_per_period(self.config.pattern) {
+                        let message = load_message(index as u32, client.sequence, self.config.message_size);
+                        match client.connection.send(self.config.channel, &message, self.config.reliable) {
+                            Ok(()) => {
+                                client.sequence += 1;
+                                self.messages_sent += 1;
+                            }
+                            Err(ConnectionError::ChannelError(_)) => break, // Buffer full: the server is not keeping up
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    client.next_send = Some(next_send + period);
+                }
+            }
+
+            match client.connection.update(&mut client.socket) {
+                Ok(()) => {}
+                Err(ConnectionError::Timeout)
+                | Err(ConnectionError::ConnectionDenied(_))
+                | Err(ConnectionError::MemoryLimitExceeded)
+                | Err(ConnectionError::SlowConsumer)
+                | Err(ConnectionError::PeerUnreachable) => client.failed = true,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops scheduling new messages; clients keep updating so in-flight data drains.
+    pub fn stop_sending(&mut self) {
+        self.sending = false;
+    }
+
+    /// Number of clients currently connected.
+    pub fn connected(&self) -> usize {
+        self.clients.iter().filter(|client| client.connection.is_connected()).count()
+    }
+
+    /// Number of clients that failed to connect or were dropped.
+    pub fn failed(&self) -> usize {
+        self.clients.iter().filter(|client| client.failed).count()
+    }
+
+    pub fn messages_sent(&self) -> u64 {
+        self.messages_sent
+    }
+}
+
+fn pattern_period(pattern: MessagePattern) -> Duration {
+    match pattern {
+        MessagePattern::Steady { rate } => Duration::from_secs_f32(1.0 / rate.max(f32::EPSILON)),
+        MessagePattern::Burst { interval, .. } => interval,
+    }
+}
+
+fn messages_per_period(pattern: MessagePattern) -> usize {
+    match pattern {
+        MessagePattern::Steady { .. } => 1,
+        MessagePattern::Burst { count, .. } => count,
+    }
+}
+
+fn unix_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64
+}
+
+fn load_message(client: u32, sequence: u32, size: usize) -> Vec<u8> {
+    let mut message = Vec::with_capacity(size.max(LOAD_HEADER_BYTES));
+    message.extend_from_slice(&client.to_le_bytes());
+    message.extend_from_slice(&sequence.to_le_bytes());
+    message.extend_from_slice(&unix_micros().to_le_bytes());
+    message.resize(size.max(LOAD_HEADER_BYTES), 0);
+    message
+}
+
+#[derive(Debug, Default)]
+struct ClientRecord {
+    received: u64,
+    highest_sequence: Option<u32>,
+}
+
+/// Collects load messages on the server side.
+#[derive(Debug, Default)]
+pub struct LoadRecorder {
+    clients: HashMap<u32, ClientRecord>,
+    latencies: Vec<Duration>,
+    rtts: HashMap<SocketAddr, f32>,
+    malformed: u64,
+}
+
+impl LoadRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drains every client's messages on `channel` and samples connection RTTs.
+    ///
+    /// Call after each [`Server::update`]; any other traffic on the channel is consumed too.
+    pub fn record(&mut self, server: &mut Server, channel: u8) {
+        let now = unix_micros();
+        let addrs: Vec<SocketAddr> = server.clients().copied().collect();
+        for addr in addrs {
+            while let Some(message) = server.receive(addr, channel) {
+                self.record_message(&message, now);
+            }
+            if let Some(connection) = server.connection(addr) {
+                self.rtts.insert(addr, connection.stats().rtt);
+            }
+        }
+    }
+
+    fn record_message(&mut self, message: &[u8], now: u64) {
+        if message.len() < LOAD_HEADER_BYTES {
+            self.malformed += 1;
+            return;
+        }
+        let client = u32::from_le_bytes(message[0..4].try_into().unwrap());
+        let sequence = u32::from_le_bytes(message[4..8].try_into().unwrap());
+        let sent = u64::from_le_bytes(message[8..16].try_into().unwrap());
+
+        let record = self.clients.entry(client).or_default();
+        record.received += 1;
+        record.highest_sequence = Some(record.highest_sequence.map_or(sequence, |highest| highest.max(sequence)));
+        self.latencies.push(Duration::from_micros(now.saturating_sub(sent)));
+    }
+
+    /// Summarizes everything recorded so far.
+    ///
+    /// Loss is inferred from sequence gaps, so messages lost after a client's last
+    /// received one are not counted.
+    pub fn report(&self) -> LoadReport {
+        let messages_received: u64 = self.clients.values().map(|record| record.received).sum();
+        let messages_expected: u64 = self.clients.values()
+            .map(|record| record.highest_sequence.map_or(0, |highest| highest as u64 + 1))
+            .sum();
+
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+        let percentile = |p: f64| -> Duration {
+            if latencies.is_empty() {
+                return Duration::ZERO;
+            }
+            latencies[((latencies.len() - 1) as f64 * p).round() as usize]
+        };
+        let latency_avg = if latencies.is_empty() {
+            Duration::ZERO
+        } else {
+            latencies.iter().sum::<Duration>() / latencies.len() as u32
+        };
+
+        let avg_rtt_ms = if self.rtts.is_empty() {
+            0.0
+        } else {
+            self.rtts.values().sum::<f32>() / self.rtts.len() as f32
+        };
+
+        LoadReport {
+            clients_seen: self.clients.len(),
+            messages_received,
+            messages_lost: messages_expected.saturating_sub(messages_received),
+            malformed_messages: self.malformed,
+            loss: if messages_expected == 0 { 0.0 } else { 1.0 - messages_received as f32 / messages_expected as f32 },
+            latency_min: latencies.first().copied().unwrap_or_default(),
+            latency_avg,
+            latency_p50: percentile(0.5),
+            latency_p99: percentile(0.99),
+            latency_max: latencies.last().copied().unwrap_or_default(),
+            avg_rtt_ms,
+        }
+    }
+}
+
+/// Aggregate results measured on the server side.
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    /// Clients that delivered at least one message
+    pub clients_seen: usize,
+    pub messages_received: u64,
+    pub messages_lost: u64,
+    pub malformed_messages: u64,
+    /// Fraction of messages lost, 0.0 to 1.0
+    pub loss: f32,
+    pub latency_min: Duration,
+    pub latency_avg: Duration,
+    pub latency_p50: Duration,
+    pub latency_p99: Duration,
+    pub latency_max: Duration,
+    /// Mean of the server's per-connection RTT estimates in milliseconds
+    pub avg_rtt_ms: f32,
+}
+
+/// Runs a swarm against an in-process server for `duration`, then drains for `drain`.
+///
+/// The server's `max_clients` and connection request rate limit must admit the swarm,
+/// since every client shares the same source IP.
+pub fn run_local(server: &mut Server, config: LoadTestConfig, duration: Duration, drain: Duration) -> Result<LoadReport, ConnectionError> {
+    let channel = config.channel;
+    let mut swarm = Swarm::new(server.local_addr(), config)?;
+    let mut recorder = LoadRecorder::new();
+
+    let start = Instant::now();
+    while start.elapsed() < duration + drain {
+        if start.elapsed() >= duration {
+            swarm.stop_sending();
+        }
+        swarm.update()?;
+        server.update()?;
+        recorder.record(server, channel);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    Ok(recorder.report())
+}
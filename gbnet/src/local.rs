@@ -0,0 +1,181 @@
+// local.rs - A client and server wired together in one process for deterministic tests
+//
+// LocalPair puts both ends on a MemoryNetwork under one manual Clock, so a test drives the
+// protocol a step at a time with no ports, threads or sleeps, and timeouts that would take
+// seconds pass in one call. With `random_seed` set (LocalPair sets one if the config has
+// none) the same script replays the same run; only key exchange secrets, and so the bytes
+// of encrypted channels, still come from the OS.
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::config::NetworkConfig;
+use crate::connection::{Connection, ConnectionError};
+use crate::instant::Clock;
+use crate::server::Server;
+use crate::socket::{MemoryNetwork, UdpSocket};
+
+/// Simulated time per [`LocalPair::step`] until changed with [`LocalPair::set_step`].
+pub const DEFAULT_STEP: Duration = Duration::from_micros(16_667);
+
+// Most steps the handshake may take before `LocalPair::new` gives up
+const HANDSHAKE_STEPS: usize = 1000;
+
+/// A connected client and server sharing a memory network and a manual clock.
+pub struct LocalPair {
+    pub client: Connection,
+    pub server: Server,
+    client_socket: UdpSocket,
+    network: MemoryNetwork,
+    clock: Clock,
+    step: Duration,
+    // Clients added with `connect_client`, each given its own address
+    extra_clients: u8,
+}
+
+impl LocalPair {
+    /// Builds both ends from `config` and runs the handshake.
+    ///
+    /// # Panics
+    ///
+    /// If the client is not connected within 1000 steps.
+    pub fn new(config: NetworkConfig) -> Self {
+        Self::with_configs(config.clone(), config)
+    }
+
+    /// Like [`LocalPair::new`], with separate server and client configs.
+    pub fn with_configs(server_config: NetworkConfig, client_config: NetworkConfig) -> Self {
+        let clock = Clock::manual();
+        let network = MemoryNetwork::new();
+
+        let server_socket = UdpSocket::bind_memory(&network, SocketAddr::from(([10, 0, 0, 1], 7777)))
+            .expect("a fresh memory network has every address free");
+        let server = Server::with_socket(server_socket, prepare(server_config, &clock))
+            .expect("memory sockets know their address");
+        let mut client_socket = UdpSocket::bind_memory(&network, SocketAddr::from(([10, 0, 0, 2], 0)))
+            .expect("a fresh memory network has every address free");
+        client_socket.set_clock(clock.clone());
+        let client_addr = client_socket.local_addr().expect("memory sockets know their address");
+        let client = Connection::new(prepare(client_config, &clock), client_addr, server.local_addr());
+
+        let mut pair = Self { client, server, client_socket, network, clock, step: DEFAULT_STEP, extra_clients: 0 };
+        pair.client.connect().expect("a new connection can connect");
+        let connected = pair.run_until(HANDSHAKE_STEPS, |pair| pair.client.is_connected())
+            .expect("the handshake runs without errors");
+        assert!(connected, "LocalPair handshake did not complete");
+        pair
+    }
+
+    /// Connects another client to the server over the pair's network and clock, stepping only
+    /// it and the server through the handshake. Update it alongside [`LocalPair::step`] with
+    /// `Connection::update` on the returned socket.
+    ///
+    /// # Panics
+    ///
+    /// If the client is not connected within 1000 steps.
+    pub fn connect_client(&mut self, config: NetworkConfig) -> (Connection, UdpSocket) {
+        self.extra_clients += 1;
+        let mut socket = UdpSocket::bind_memory(&self.network, SocketAddr::from(([10, 0, 1, self.extra_clients], 0)))
+            .expect("each added client gets an address of its own");
+        socket.set_clock(self.clock.clone());
+        let addr = socket.local_addr().expect("memory sockets know their address");
+        let mut client = Connection::new(prepare(config, &self.clock), addr, self.server.local_addr());
+        client.connect().expect("a new connection can connect");
+        for _ in 0..HANDSHAKE_STEPS {
+            if client.is_connected() {
+                break;
+            }
+            self.clock.advance(self.step);
+            client.update(&mut socket).expect("the handshake runs without errors");
+            self.server.update().expect("the handshake runs without errors");
+        }
+        assert!(client.is_connected(), "LocalPair handshake did not complete");
+        (client, socket)
+    }
+
+    /// Replaces the server with a fresh one built from `config` on the same address, as if its
+    /// process restarted; every connection it held is gone without notice.
+    pub fn restart_server(&mut self, config: NetworkConfig) {
+        let addr = self.server.local_addr();
+        // The old server has to release its address before the new one can bind it
+        let standby = UdpSocket::bind_memory(&self.network, SocketAddr::from(([10, 0, 0, 1], 0)))
+            .expect("the memory network has free ports");
+        let standby = Server::with_socket(standby, prepare(config.clone(), &self.clock))
+            .expect("memory sockets know their address");
+        drop(std::mem::replace(&mut self.server, standby));
+        let socket = UdpSocket::bind_memory(&self.network, addr).expect("the old server released its address");
+        self.server = Server::with_socket(socket, prepare(config, &self.clock)).expect("memory sockets know their address");
+    }
+
+    /// Advances the clock one step, then updates the client and the server in that order.
+    pub fn step(&mut self) -> Result<(), ConnectionError> {
+        self.step_by(self.step)
+    }
+
+    /// Like [`LocalPair::step`], advancing the clock by `elapsed` instead.
+    pub fn step_by(&mut self, elapsed: Duration) -> Result<(), ConnectionError> {
+        self.clock.advance(elapsed);
+        self.client.update(&mut self.client_socket)?;
+        self.server.update()
+    }
+
+    /// Advances the clock by `elapsed` and updates only the client, as if the server stalled.
+    pub fn step_client(&mut self, elapsed: Duration) -> Result<(), ConnectionError> {
+        self.clock.advance(elapsed);
+        self.client.update(&mut self.client_socket)
+    }
+
+    /// Runs `steps` steps.
+    pub fn run(&mut self, steps: usize) -> Result<(), ConnectionError> {
+        (0..steps).try_for_each(|_| self.step())
+    }
+
+    /// Steps until `done` holds, checked before each step; returns whether it did within `max_steps`.
+    pub fn run_until<F>(&mut self, max_steps: usize, mut done: F) -> Result<bool, ConnectionError>
+    where
+        F: FnMut(&mut Self) -> bool,
+    {
+        for _ in 0..max_steps {
+            if done(self) {
+                return Ok(true);
+            }
+            self.step()?;
+        }
+        Ok(done(self))
+    }
+
+    pub fn set_step(&mut self, step: Duration) {
+        self.step = step;
+    }
+
+    /// The clock both ends read; advance it directly to skip time without updating them.
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// The network both sockets are on, for binding more sockets to it.
+    pub fn network(&self) -> &MemoryNetwork {
+        &self.network
+    }
+
+    /// The client's socket, e.g. to install a simulator with `UdpSocket::set_simulator`.
+    pub fn client_socket_mut(&mut self) -> &mut UdpSocket {
+        &mut self.client_socket
+    }
+
+    pub fn client_addr(&self) -> SocketAddr {
+        self.client_socket.local_addr().expect("memory sockets know their address")
+    }
+
+    pub fn server_addr(&self) -> SocketAddr {
+        self.server.local_addr()
+    }
+}
+
+// Both ends read the shared clock, and a seed makes runs repeat
+fn prepare(config: NetworkConfig, clock: &Clock) -> NetworkConfig {
+    NetworkConfig {
+        clock: clock.clone(),
+        random_seed: config.random_seed.or(Some(0)),
+        ..config
+    }
+}
@@ -0,0 +1,75 @@
+// middleware.rs - Ordered byte transforms between serialized packets and the socket
+//
+// Layers see whole datagrams: on send each layer transforms the output of the one before
+// it, and on receive the chain runs in reverse, so a chain of compression, encryption and
+// FEC composes the same way on both ends. Both ends must install the same chain.
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// One layer of a [`MiddlewareChain`].
+///
+/// Layers are shared by every connection using the config, so per-peer state has to be
+/// keyed by `remote_addr` and synchronized internally. Returning an error from
+/// `on_receive` drops the datagram; from `on_send` it fails the send.
+pub trait PacketMiddleware: Send + Sync {
+    fn on_send(&self, remote_addr: SocketAddr, data: &mut Vec<u8>) -> io::Result<()>;
+    fn on_receive(&self, remote_addr: SocketAddr, data: &mut Vec<u8>) -> io::Result<()>;
+}
+
+#[derive(Clone, Default)]
+pub struct MiddlewareChain {
+    layers: Vec<Arc<dyn PacketMiddleware>>,
+}
+
+impl MiddlewareChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a layer; layers added later sit closer to the socket.
+    pub fn with<M: PacketMiddleware + 'static>(mut self, layer: M) -> Self {
+        self.layers.push(Arc::new(layer));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Runs every layer's `on_send` in order; borrows `data` untouched when the chain is empty.
+    pub fn encode<'a>(&self, remote_addr: SocketAddr, data: &'a [u8]) -> io::Result<Cow<'a, [u8]>> {
+        if self.layers.is_empty() {
+            return Ok(Cow::Borrowed(data));
+        }
+        let mut data = data.to_vec();
+        for layer in &self.layers {
+            layer.on_send(remote_addr, &mut data)?;
+        }
+        Ok(Cow::Owned(data))
+    }
+
+    /// Runs every layer's `on_receive` in reverse order.
+    pub fn decode<'a>(&self, remote_addr: SocketAddr, data: &'a [u8]) -> io::Result<Cow<'a, [u8]>> {
+        if self.layers.is_empty() {
+            return Ok(Cow::Borrowed(data));
+        }
+        let mut data = data.to_vec();
+        for layer in self.layers.iter().rev() {
+            layer.on_receive(remote_addr, &mut data)?;
+        }
+        Ok(Cow::Owned(data))
+    }
+}
+
+impl fmt::Debug for MiddlewareChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MiddlewareChain").field("layers", &self.layers.len()).finish()
+    }
+}
@@ -1,9 +1,17 @@
 // packet.rs - Core packet structures for reliable UDP
+//
+// Datagrams are parsed in place: a `PacketView` reads the header straight out of the receive
+// buffer and borrows the payload, so nothing is copied until a packet has to outlive the
+// buffer. Going out, the header is written directly in front of the payload in one buffer.
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::io;
 use gbnet_macros::NetworkSerialize;
-use crate::serialize::{BitSerialize, BitDeserialize, bit_io::{BitBuffer, BitWrite, BitRead}};
+use crate::serialize::{BitSerialize, BitDeserialize, bit_io::{BitAppender, BitSlice, BitWrite, BitRead}};
 
 #[derive(Debug, Clone, PartialEq, NetworkSerialize)]
+#[schema]
+#[gbnet(untagged)]
 pub struct PacketHeader {
     #[bits = 32]
     pub protocol_id: u32,
@@ -17,6 +25,8 @@ pub struct PacketHeader {
 
 #[derive(Debug, Clone, PartialEq, NetworkSerialize)]
 #[bits = 4] // 16 packet types max
+#[schema]
+#[gbnet(untagged)]
 pub enum PacketType {
     ConnectionRequest,
     ConnectionChallenge { 
@@ -43,6 +53,222 @@ pub enum PacketType {
         #[bits = 1]
         is_fragment: bool,
     },
+    ResumeRequest,
+    /// The server is full and holds the request in its join queue; sent again as the line moves
+    ConnectionQueued {
+        #[bits = 16]
+        position: u16,
+    },
+    /// Unconnected latency probe; see [`crate::ping`]
+    PingRequest,
+    /// Echo of a [`PacketType::PingRequest`] with the server's clock and player count
+    PingResponse,
+    /// Messages on the reliable control channel, framed like a `Payload`; each starts with
+    /// a [`control_message`] kind. Sent once `capability::CONTROL_CHANNEL` is negotiated
+    Control,
+}
+
+/// Serialized size of the header and type of a `Payload` packet (104 bits).
+pub const PAYLOAD_HEADER_BYTES: usize = 13;
+
+/// Message sequence, fragment index and fragment count in front of every fragment payload.
+pub const FRAGMENT_HEADER_BYTES: usize = 6;
+
+/// Which piece of which message a `Payload { is_fragment: true }` packet carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentHeader {
+    /// Channel sequence of the whole message
+    pub sequence: u16,
+    pub index: u16,
+    pub count: u16,
+}
+
+impl FragmentHeader {
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.sequence.to_le_bytes());
+        out.extend_from_slice(&self.index.to_le_bytes());
+        out.extend_from_slice(&self.count.to_le_bytes());
+    }
+
+    /// Splits a fragment payload into its header and data; `None` if it is too short or
+    /// the index is out of range.
+    pub fn read(payload: &[u8]) -> Option<(Self, &[u8])> {
+        let field = |at: usize| payload.get(at..at + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+        let header = Self { sequence: field(0)?, index: field(2)?, count: field(4)? };
+        (header.index < header.count).then(|| (header, &payload[FRAGMENT_HEADER_BYTES..]))
+    }
+}
+
+/// Splits a message into fragment payloads carrying at most `fragment_size` bytes of it each.
+pub fn split_fragments(sequence: u16, data: &[u8], fragment_size: usize) -> Vec<Vec<u8>> {
+    let chunks: Vec<&[u8]> = data.chunks(fragment_size.max(1)).collect();
+    let count = chunks.len() as u16;
+    chunks.into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut payload = Vec::with_capacity(FRAGMENT_HEADER_BYTES + chunk.len());
+            FragmentHeader { sequence, index: index as u16, count }.write(&mut payload);
+            payload.extend_from_slice(chunk);
+            payload
+        })
+        .collect()
+}
+
+/// Packet type nibble announcing a header extension block; never a `PacketType`.
+pub const EXTENSION_MARKER: u8 = 0xF;
+
+/// Reserved header extension ids.
+pub mod header_extension {
+    pub const TIMESTAMP: u8 = 0;
+    pub const FEC_INDEX: u8 = 1;
+    pub const PATH_PROBE: u8 = 2;
+    /// Codec picked for each channel, on the connection accept
+    pub const COMPRESSION: u8 = 3;
+    /// Negotiated [`capability`](super::capability) bits, on the connection accept
+    pub const CAPABILITIES: u8 = 4;
+    /// A byte per channel with the settings both ends must agree on, on the challenge
+    /// response and the connection accept
+    pub const CHANNEL_LAYOUT: u8 = 5;
+    /// Signed [`ConnectToken`](crate::token::ConnectToken) bytes, on the connection request
+    pub const CONNECT_TOKEN: u8 = 6;
+    /// Stateless cookie on a key-material-free challenge, which the client echoes on its next
+    /// connection request before the server runs the key exchange
+    pub const REQUEST_COOKIE: u8 = 7;
+    /// Ids run from 0 to `MAX`
+    pub const MAX: u8 = 15;
+}
+
+/// Kinds of control channel messages, in their first byte; unknown kinds are ignored.
+pub mod control_message {
+    /// Asks the peer for its wall clock; body is a `u32` probe id
+    pub const TIME_SYNC_REQUEST: u8 = 0;
+    /// Answer to `TIME_SYNC_REQUEST`: the probe id, then wall clock microseconds as `u64`
+    pub const TIME_SYNC: u8 = 1;
+    /// The sender stopped sending until it resumes; no body
+    pub const PAUSE: u8 = 2;
+    /// The sender is back from a pause; body is a `u32` nonce
+    pub const RESUME: u8 = 3;
+    /// Answer to `RESUME`, echoing its nonce
+    pub const RESUME_ACK: u8 = 4;
+}
+
+// Protocol capability bits, negotiated during the handshake
+//
+// The client lists what it supports with its challenge response and the server answers with
+// the bits both sides have; a feature is only used once it has been negotiated, so features
+// can be rolled out (or held back with `NetworkConfig::capabilities`) one side at a time.
+pub mod capability {
+    /// The peer parses packets carrying header extensions
+    pub const HEADER_EXTENSIONS: u32 = 1 << 0;
+    /// Per-channel payload compression; needs `HEADER_EXTENSIONS` to announce the codecs
+    pub const COMPRESSION: u32 = 1 << 1;
+    /// Keyed permutation of session traffic sequences; needs `HEADER_EXTENSIONS` so the
+    /// client learns it was agreed. Advertised only with `NetworkConfig::obfuscate_sequences`
+    pub const SEQUENCE_OBFUSCATION: u32 = 1 << 2;
+    /// `Control` packets carrying protocol messages outside the user channels; needs
+    /// `HEADER_EXTENSIONS` so the client learns it was agreed
+    pub const CONTROL_CHANNEL: u32 = 1 << 3;
+    /// Sealed payloads and authenticated headers on all session traffic; needs
+    /// `HEADER_EXTENSIONS` so the client learns it was agreed. Implemented with the `crypto`
+    /// feature and advertised only with `NetworkConfig::encrypt_packets`
+    pub const PACKET_ENCRYPTION: u32 = 1 << 4;
+    /// Every capability this build implements
+    pub const ALL: u32 = HEADER_EXTENSIONS | COMPRESSION | SEQUENCE_OBFUSCATION | CONTROL_CHANNEL
+        | if cfg!(feature = "crypto") { PACKET_ENCRYPTION } else { 0 };
+    
+    /// Bits both ends advertise, minus any whose prerequisites are missing.
+    pub fn negotiate(local: u32, remote: u32) -> u32 {
+        let shared = local & remote & ALL;
+        if shared & HEADER_EXTENSIONS == 0 {
+            return shared & !(COMPRESSION | SEQUENCE_OBFUSCATION | CONTROL_CHANNEL | PACKET_ENCRYPTION);
+        }
+        shared
+    }
+}
+
+/// Optional header fields, keyed by [`header_extension`] id.
+///
+/// On the wire, a packet with extensions has [`EXTENSION_MARKER`] and 4 zero bits where
+/// the type would be, then 16 presence bits and, for each id set in ascending order, a
+/// length byte and that many bytes; the usual type follows. Every field is length-prefixed,
+/// so parsers skip (and re-encode) ids they do not know, and packets without extensions
+/// are byte-for-byte what they were before extensions existed. Extension bytes come out
+/// of the MTU like the rest of the header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderExtensions {
+    fields: BTreeMap<u8, Vec<u8>>,
+}
+
+impl HeaderExtensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a field; fails for ids past `header_extension::MAX` or data over 255 bytes.
+    pub fn set(&mut self, id: u8, data: Vec<u8>) -> io::Result<()> {
+        if id > header_extension::MAX {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Header extension id out of range"));
+        }
+        if data.len() > u8::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Header extension too long"));
+        }
+        self.fields.insert(id, data);
+        Ok(())
+    }
+
+    pub fn get(&self, id: u8) -> Option<&[u8]> {
+        self.fields.get(&id).map(Vec::as_slice)
+    }
+
+    pub fn remove(&mut self, id: u8) -> Option<Vec<u8>> {
+        self.fields.remove(&id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Fields in id order.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &[u8])> {
+        self.fields.iter().map(|(&id, data)| (id, data.as_slice()))
+    }
+
+    /// Bytes the extension block adds to a packet.
+    pub fn wire_size(&self) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        3 + self.fields.values().map(|data| 1 + data.len()).sum::<usize>()
+    }
+
+    fn write<W: BitWrite>(&self, buffer: &mut W) -> io::Result<()> {
+        buffer.write_bits(EXTENSION_MARKER as u64, 4)?;
+        buffer.write_bits(0, 4)?;
+        let flags = self.fields.keys().fold(0u16, |flags, &id| flags | 1 << id);
+        buffer.write_bits(flags as u64, 16)?;
+        for data in self.fields.values() {
+            buffer.write_bits(data.len() as u64, 8)?;
+            for &byte in data {
+                buffer.write_bits(byte as u64, 8)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read<R: BitRead>(buffer: &mut R) -> io::Result<Self> {
+        buffer.read_bits(4)?;
+        if buffer.read_bits(4)? != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Reserved extension bits set"));
+        }
+        let flags = buffer.read_bits(16)? as u16;
+        let mut fields = BTreeMap::new();
+        for id in (0..=header_extension::MAX).filter(|id| flags & 1 << id != 0) {
+            let len = buffer.read_bits(8)? as usize;
+            let data = (0..len).map(|_| buffer.read_bits(8).map(|byte| byte as u8)).collect::<io::Result<Vec<u8>>>()?;
+            fields.insert(id, data);
+        }
+        Ok(Self { fields })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -50,6 +276,7 @@ pub struct Packet {
     pub header: PacketHeader,
     pub packet_type: PacketType,
     pub payload: Vec<u8>,
+    pub extensions: HeaderExtensions,
 }
 
 impl Packet {
@@ -59,6 +286,7 @@ impl Packet {
             header,
             packet_type,
             payload: Vec::new(),
+            extensions: HeaderExtensions::new(),
         }
     }
     
@@ -68,64 +296,92 @@ impl Packet {
         self
     }
     
+    /// Adds a header extension field; see [`HeaderExtensions::set`].
+    pub fn with_extension(mut self, id: u8, data: Vec<u8>) -> io::Result<Self> {
+        self.extensions.set(id, data)?;
+        Ok(self)
+    }
+    
+    /// Borrows the packet as a view, e.g. to write it with a different header or payload.
+    pub fn view(&self) -> PacketView<'_> {
+        PacketView {
+            header: self.header.clone(),
+            packet_type: self.packet_type.clone(),
+            extensions: Cow::Borrowed(&self.extensions),
+            payload: &self.payload,
+        }
+    }
+    
     /// Serializes the packet into a byte vector.
     pub fn serialize(&self) -> io::Result<Vec<u8>> {
-        let mut buffer = BitBuffer::new();
-        
-        // Serialize header
-        self.header.bit_serialize(&mut buffer)?;
-        
-        // Serialize packet type
-        self.packet_type.bit_serialize(&mut buffer)?;
-        
-        // Pad to byte boundary before payload using BitWrite trait
-        while BitWrite::bit_pos(&buffer) % 8 != 0 {
-            buffer.write_bit(false)?;
-        }
-        
-        // Get the header bytes
-        let header_bytes = buffer.into_bytes(true)?;
-        
-        // Combine header and payload
-        let mut result = header_bytes;
-        result.extend_from_slice(&self.payload);
-        
-        Ok(result)
+        let mut out = Vec::new();
+        self.view().write_to(&mut out)?;
+        Ok(out)
     }
     
     /// Deserializes a packet from a byte slice.
     pub fn deserialize(data: &[u8]) -> io::Result<Self> {
+        PacketView::parse(data).map(|view| view.to_packet())
+    }
+}
+
+/// A packet whose payload is borrowed, from the datagram it was parsed out of or from the
+/// buffers it is being sent from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PacketView<'a> {
+    pub header: PacketHeader,
+    pub packet_type: PacketType,
+    pub extensions: Cow<'a, HeaderExtensions>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> PacketView<'a> {
+    /// Parses the header in place; the payload is the rest of `data`.
+    pub fn parse(data: &'a [u8]) -> io::Result<Self> {
         if data.is_empty() {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "Empty packet"));
         }
         
-        let mut buffer = BitBuffer::from_bytes(data.to_vec());
-        
-        // Deserialize header
-        let header = PacketHeader::bit_deserialize(&mut buffer)?;
-        
-        // Deserialize packet type
-        let packet_type = PacketType::bit_deserialize(&mut buffer)?;
-        
-        // Align to byte boundary using BitRead trait
-        while BitRead::bit_pos(&buffer) % 8 != 0 {
-            buffer.read_bit()?;
-        }
-        
-        // Calculate where payload starts
-        let header_size = BitRead::bit_pos(&buffer) / 8;
-        let payload = if header_size < data.len() {
-            data[header_size..].to_vec()
-        } else {
-            Vec::new()
+        let mut reader = BitSlice::new(data);
+        let header = PacketHeader::bit_deserialize(&mut reader)?;
+        let extensions = match data.get(12) {
+            Some(&byte) if byte >> 4 == EXTENSION_MARKER => HeaderExtensions::read(&mut reader)?,
+            _ => HeaderExtensions::new(),
         };
+        let packet_type = PacketType::bit_deserialize(&mut reader)?;
         
+        // The payload starts at the next byte boundary
         Ok(Self {
             header,
             packet_type,
-            payload,
+            extensions: Cow::Owned(extensions),
+            payload: &data[reader.byte_pos()..],
         })
     }
+    
+    /// Appends the serialized packet to `out`, header and payload in one pass.
+    pub fn write_to(&self, out: &mut Vec<u8>) -> io::Result<()> {
+        out.reserve(PAYLOAD_HEADER_BYTES + self.extensions.wire_size() + self.payload.len());
+        let mut writer = BitAppender::new(out);
+        self.header.bit_serialize(&mut writer)?;
+        if !self.extensions.is_empty() {
+            self.extensions.write(&mut writer)?;
+        }
+        self.packet_type.bit_serialize(&mut writer)?;
+        // Bits past the type stay zero as padding up to the byte boundary
+        out.extend_from_slice(self.payload);
+        Ok(())
+    }
+    
+    /// Copies the payload out into an owned packet.
+    pub fn to_packet(&self) -> Packet {
+        Packet {
+            header: self.header.clone(),
+            packet_type: self.packet_type.clone(),
+            payload: self.payload.to_vec(),
+            extensions: self.extensions.clone().into_owned(),
+        }
+    }
 }
 
 // Disconnect reasons
@@ -135,6 +391,15 @@ pub mod disconnect_reason {
     pub const KICKED: u8 = 2;
     pub const SERVER_FULL: u8 = 3;
     pub const PROTOCOL_MISMATCH: u8 = 4;
+    pub const MEMORY_LIMIT: u8 = 5;
+    /// The other end lost the session, e.g. it restarted, and no longer recognizes the connection
+    pub const STALE_SESSION: u8 = 6;
+    /// The server's revocation check rejected the client's credentials
+    pub const REVOKED: u8 = 7;
+    /// A reliable channel's `receive_limit` was passed with `SlowConsumer::Disconnect`
+    pub const SLOW_CONSUMER: u8 = 8;
+    /// The server could not process the connection's traffic; other clients are unaffected
+    pub const CONNECTION_ERROR: u8 = 9;
 }
 
 // Connection deny reasons
@@ -144,6 +409,11 @@ pub mod deny_reason {
     pub const INVALID_PROTOCOL: u8 = 2;
     pub const BANNED: u8 = 3;
     pub const INVALID_CHALLENGE: u8 = 4;
+    pub const INVALID_TICKET: u8 = 5;
+    /// The client's channels are configured differently, under `ChannelMismatchPolicy::Fail`
+    pub const CHANNEL_MISMATCH: u8 = 6;
+    /// The server requires `encrypt_packets` and the client did not offer it
+    pub const ENCRYPTION_REQUIRED: u8 = 7;
 }
 
 /// Utility function to compare sequence numbers, accounting for wraparound.
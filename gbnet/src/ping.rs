@@ -0,0 +1,252 @@
+// ping.rs - Latency probes for matchmaking, answered without a connection
+//
+// Matchmakers rank servers by round trip before connecting to any of them. A `Pinger` sends
+// `PingRequest` probes to a list of addresses from one socket and matches the `PingResponse`
+// echoes as they arrive, so every server is measured at once and a dead one only costs the
+// timeout. Servers answer from `Server::update` with their wall clock and player count,
+// unless `NetworkConfig::respond_to_pings` is off.
+//
+// Requests are padded to the size of the response, and servers ignore shorter ones, so a
+// spoofed source address cannot turn a server into a traffic amplifier.
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    NetworkConfig,
+    packet::{Packet, PacketHeader, PacketType, PacketView},
+    socket::{SocketError, UdpSocket},
+};
+
+/// Payload size of both probe packets.
+pub const PING_PAYLOAD_BYTES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PingConfig {
+    /// Probes sent to each address; the fastest reply counts
+    pub probes: u32,
+    /// Time between probes to the same address
+    pub interval: Duration,
+    /// How long to wait for replies after the first probe
+    pub timeout: Duration,
+}
+
+impl Default for PingConfig {
+    fn default() -> Self {
+        Self {
+            probes: 3,
+            interval: Duration::from_millis(100),
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// What one address answered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PingResult {
+    pub addr: SocketAddr,
+    /// Shortest round trip among the replies; `None` if nothing came back
+    pub rtt: Option<Duration>,
+    pub replies: u32,
+    /// The server's wall clock when it answered the fastest probe
+    pub server_time: Option<SystemTime>,
+    pub clients: u16,
+    pub max_clients: u16,
+}
+
+// Probe id, then the server's wall clock in microseconds and its load, little endian
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PingReply {
+    pub(crate) id: u32,
+    pub(crate) server_time: SystemTime,
+    pub(crate) clients: u16,
+    pub(crate) max_clients: u16,
+}
+
+impl PingReply {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let micros = self.server_time.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        let mut payload = Vec::with_capacity(PING_PAYLOAD_BYTES);
+        payload.extend_from_slice(&self.id.to_le_bytes());
+        payload.extend_from_slice(&micros.to_le_bytes());
+        payload.extend_from_slice(&self.clients.to_le_bytes());
+        payload.extend_from_slice(&self.max_clients.to_le_bytes());
+        payload
+    }
+
+    fn decode(payload: &[u8]) -> Option<Self> {
+        let payload: &[u8; PING_PAYLOAD_BYTES] = payload.get(..PING_PAYLOAD_BYTES)?.try_into().ok()?;
+        let micros = u64::from_le_bytes(payload[4..12].try_into().ok()?);
+        Some(Self {
+            id: u32::from_le_bytes(payload[..4].try_into().ok()?),
+            server_time: UNIX_EPOCH + Duration::from_micros(micros),
+            clients: u16::from_le_bytes([payload[12], payload[13]]),
+            max_clients: u16::from_le_bytes([payload[14], payload[15]]),
+        })
+    }
+}
+
+/// Probe id of a ping request, if it is padded to the full probe size.
+pub(crate) fn probe_id(payload: &[u8]) -> Option<u32> {
+    let id = payload.get(..4).filter(|_| payload.len() >= PING_PAYLOAD_BYTES)?;
+    Some(u32::from_le_bytes(id.try_into().ok()?))
+}
+
+/// Measures round trips to many servers at once from one socket.
+///
+/// Call [`Pinger::start`], then [`Pinger::poll`] until it reports completion (or use
+/// [`ping_servers`] to block), and read [`Pinger::results`].
+pub struct Pinger {
+    socket: UdpSocket,
+    config: NetworkConfig,
+    ping: PingConfig,
+    results: Vec<PingResult>,
+    // Probe id to the address index it was sent to and when
+    in_flight: HashMap<u32, (usize, Instant)>,
+    next_id: u32,
+    probes_sent: u32,
+    started: Option<Instant>,
+}
+
+impl Pinger {
+    /// Probes from `socket`, using the protocol id, middleware and clock of `config`.
+    pub fn new(mut socket: UdpSocket, config: NetworkConfig, ping: PingConfig) -> Self {
+        socket.set_clock(config.clock.clone());
+        Self {
+            socket,
+            config,
+            ping,
+            results: Vec::new(),
+            in_flight: HashMap::new(),
+            next_id: 0,
+            probes_sent: 0,
+            started: None,
+        }
+    }
+
+    /// Forgets any earlier run and sends the first probe to every address.
+    pub fn start(&mut self, addrs: &[SocketAddr]) -> Result<(), SocketError> {
+        self.results = addrs.iter().map(|&addr| PingResult {
+            addr,
+            rtt: None,
+            replies: 0,
+            server_time: None,
+            clients: 0,
+            max_clients: 0,
+        }).collect();
+        self.in_flight.clear();
+        self.probes_sent = 0;
+        self.started = Some(self.config.clock.now());
+        self.send_round()
+    }
+
+    /// Reads replies and sends probes that are due; returns whether the run is over.
+    pub fn poll(&mut self) -> Result<bool, SocketError> {
+        let Some(started) = self.started else {
+            return Ok(true);
+        };
+        self.receive_replies()?;
+
+        let now = self.config.clock.now();
+        if now >= started + self.ping.timeout || self.results.iter().all(|result| result.replies >= self.ping.probes) {
+            return Ok(true);
+        }
+        if self.probes_sent < self.ping.probes && now >= started + self.ping.interval * self.probes_sent {
+            self.send_round()?;
+        }
+        Ok(false)
+    }
+
+    /// Results so far, fastest first, then addresses that never answered in the order given.
+    pub fn results(&self) -> Vec<PingResult> {
+        let mut results = self.results.clone();
+        results.sort_by_key(|result| (result.rtt.is_none(), result.rtt));
+        results
+    }
+
+    /// When [`Pinger::poll`] next has something to do without a reply arriving.
+    fn next_deadline(&self) -> Option<Instant> {
+        let started = self.started?;
+        let deadline = started + self.ping.timeout;
+        if self.probes_sent < self.ping.probes {
+            return Some(deadline.min(started + self.ping.interval * self.probes_sent));
+        }
+        Some(deadline)
+    }
+
+    fn send_round(&mut self) -> Result<(), SocketError> {
+        let now = self.config.clock.now();
+        for index in 0..self.results.len() {
+            let id = self.next_id;
+            self.next_id = self.next_id.wrapping_add(1);
+            let mut payload = vec![0u8; PING_PAYLOAD_BYTES];
+            payload[..4].copy_from_slice(&id.to_le_bytes());
+            let header = PacketHeader { protocol_id: self.config.protocol_id, sequence: 0, ack: 0, ack_bits: 0 };
+            let Ok(data) = Packet::new(header, PacketType::PingRequest).with_payload(payload).serialize() else {
+                continue;
+            };
+            let addr = self.results[index].addr;
+            let Ok(wire) = self.config.middleware.encode(addr, &data) else {
+                continue;
+            };
+            self.socket.send_to(&wire, addr)?;
+            self.in_flight.insert(id, (index, now));
+        }
+        self.probes_sent += 1;
+        Ok(())
+    }
+
+    fn receive_replies(&mut self) -> Result<(), SocketError> {
+        loop {
+            let (reply, from) = match self.socket.recv_from() {
+                Ok((data, from)) => {
+                    let reply = self.config.middleware.decode(from, data).ok().and_then(|data| {
+                        let view = PacketView::parse(&data).ok()?;
+                        if view.header.protocol_id != self.config.protocol_id || view.packet_type != PacketType::PingResponse {
+                            return None;
+                        }
+                        PingReply::decode(view.payload)
+                    });
+                    (reply, from)
+                }
+                Err(SocketError::WouldBlock) => return Ok(()),
+                // Some platforms report ICMP unreachable from an earlier probe here
+                Err(SocketError::PeerUnreachable) => continue,
+                Err(e) => return Err(e),
+            };
+            let Some(reply) = reply else { continue };
+            let Some(&(index, sent_at)) = self.in_flight.get(&reply.id) else { continue };
+            if self.results[index].addr != from {
+                continue;
+            }
+            self.in_flight.remove(&reply.id);
+
+            let rtt = self.config.clock.now().saturating_duration_since(sent_at);
+            let result = &mut self.results[index];
+            result.replies += 1;
+            if result.rtt.is_none_or(|best| rtt < best) {
+                result.rtt = Some(rtt);
+                result.server_time = Some(reply.server_time);
+                result.clients = reply.clients;
+                result.max_clients = reply.max_clients;
+            }
+        }
+    }
+}
+
+/// Pings every address from a fresh UDP socket and blocks until the run is over; fastest first.
+pub fn ping_servers(addrs: &[SocketAddr], config: NetworkConfig, ping: PingConfig) -> Result<Vec<PingResult>, SocketError> {
+    let any = match addrs.first() {
+        Some(SocketAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        _ => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    };
+    let mut pinger = Pinger::new(UdpSocket::bind(SocketAddr::new(any, 0))?, config, ping);
+    pinger.start(addrs)?;
+    while !pinger.poll()? {
+        if let Some(deadline) = pinger.next_deadline() {
+            let now = pinger.config.clock.now();
+            pinger.socket.wait_readable(deadline.saturating_duration_since(now))?;
+        }
+    }
+    Ok(pinger.results())
+}
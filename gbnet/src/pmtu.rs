@@ -0,0 +1,117 @@
+// pmtu.rs - Path MTU discovery by binary search over padded probe packets
+//
+// `NetworkConfig::mtu` is a guess that fits nearly every path. With `max_mtu` set, a
+// connection probes sizes between the two once it is connected: each probe is a keepalive
+// padded to the candidate size, and the peer echoes it in an unpadded keepalive, so only the
+// direction being measured carries the large packet. Every echo raises the lower bound and
+// every probe that goes unanswered lowers the upper one, until they are within
+// `SEARCH_PRECISION` bytes; the connection's MTU is then the largest size that got through.
+//
+// Probes are never resent as reliable data would be. One lost `PROBE_ATTEMPTS` times in a
+// row counts as too large, so loss can only make the result smaller than the path allows,
+// never larger. Sizes are whole packets before middleware, the bytes `mtu` budgets.
+use std::time::{Duration, Instant};
+
+/// Probes of one size sent before the size counts as too large.
+pub const PROBE_ATTEMPTS: u32 = 2;
+
+/// The search stops once the bounds are this close.
+pub const SEARCH_PRECISION: usize = 8;
+
+/// Shortest wait for an echo, however low the RTT.
+pub const MIN_PROBE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Body of the `PATH_PROBE` header extension for probe `id`: the id, then whether this
+/// is the peer's echo.
+pub(crate) fn probe_extension(id: u16, echo: bool) -> Vec<u8> {
+    let [low, high] = id.to_le_bytes();
+    vec![low, high, echo as u8]
+}
+
+/// Probe id and whether it is an echo, from a `PATH_PROBE` header extension.
+pub(crate) fn parse_probe_extension(data: &[u8]) -> Option<(u16, bool)> {
+    match data {
+        &[low, high, echo] => Some((u16::from_le_bytes([low, high]), echo != 0)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Probe {
+    id: u16,
+    size: usize,
+    sent_at: Instant,
+    attempts: u32,
+}
+
+/// Binary search for the largest packet size the path delivers.
+#[derive(Debug, Clone)]
+pub struct MtuSearch {
+    // Largest size known to get through, and largest not known to fail
+    low: usize,
+    high: usize,
+    probe: Option<Probe>,
+    next_id: u16,
+}
+
+impl MtuSearch {
+    /// Searches between `low`, assumed to work, and `high`.
+    pub fn new(low: usize, high: usize) -> Self {
+        Self { low, high: high.max(low), probe: None, next_id: 0 }
+    }
+
+    /// Id and size of the probe to send now, if one is due. A probe without an echo after
+    /// `timeout` is sent again, up to `PROBE_ATTEMPTS` times.
+    pub fn next_probe(&mut self, now: Instant, timeout: Duration) -> Option<(u16, usize)> {
+        if let Some(probe) = &mut self.probe {
+            if now.saturating_duration_since(probe.sent_at) < timeout {
+                return None;
+            }
+            if probe.attempts < PROBE_ATTEMPTS {
+                probe.attempts += 1;
+                probe.sent_at = now;
+                return Some((probe.id, probe.size));
+            }
+            self.high = probe.size - 1;
+            self.probe = None;
+        }
+        if self.is_done() {
+            return None;
+        }
+        let size = self.low + (self.high - self.low).div_ceil(2);
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.probe = Some(Probe { id, size, sent_at: now, attempts: 1 });
+        Some((id, size))
+    }
+
+    /// Records the peer's echo of probe `id`; returns whether it raised the MTU.
+    pub fn on_echo(&mut self, id: u16) -> bool {
+        match self.probe {
+            Some(probe) if probe.id == id => {
+                self.low = probe.size;
+                self.probe = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Records that probe `id` could not be sent at all, e.g. the OS refused its size.
+    pub fn on_rejected(&mut self, id: u16) {
+        if let Some(probe) = self.probe.filter(|probe| probe.id == id) {
+            self.high = probe.size - 1;
+            self.probe = None;
+        }
+    }
+
+    /// Whether the bounds have met, leaving nothing to probe.
+    pub fn is_done(&self) -> bool {
+        self.probe.is_none() && self.high - self.low < SEARCH_PRECISION
+    }
+
+    /// Largest size known to get through.
+    pub fn mtu(&self) -> usize {
+        self.low
+    }
+}
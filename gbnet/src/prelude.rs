@@ -0,0 +1,19 @@
+// prelude.rs - The intended public surface in one import
+//
+// `use gbnet::prelude::*;` brings in what a typical client or server needs: the derive and
+// serialization traits, connections and servers, their configs, events and errors. The
+// prelude only grows in minor releases; everything else stays reachable through its module
+// but may move between them.
+pub use crate::NetworkSerialize;
+pub use crate::serialize::{BitDeserialize, BitDeserializeBorrowed, BitSerialize, DynBitSerialize};
+pub use crate::serialize::bit_io::{BitBuffer, BitRead, BitWrite};
+
+pub use crate::channel::ChannelError;
+pub use crate::config::{ChannelConfig, NetworkConfig, Ordering, Reliability};
+pub use crate::connection::{Connection, ConnectionError, ConnectionEvent, ConnectionState, DeliveryStatus};
+pub use crate::handler::ServerHandler;
+pub use crate::resolve::{connect_host, ConnectError, HostConnector};
+pub use crate::server::{Server, ServerEvent};
+pub use crate::socket::{SocketError, UdpSocket};
+
+pub use std::net::SocketAddr;
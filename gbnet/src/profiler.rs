@@ -0,0 +1,149 @@
+// profiler.rs - Bits on the wire per message type, and per field in debug builds
+//
+// Finding which message or field is eating bandwidth used to take manual math over the
+// derive's packing rules. The profiler measures each value with a counting writer as it is
+// sent, so the totals are exact. In debug builds types registered with `#[schema]` are also
+// encoded and walked field by field; release builds only pay for the count.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "json")]
+use serde_json::{json, Value};
+
+use crate::serialize::{BitSerialize, bit_io::BitCounter};
+#[cfg(debug_assertions)]
+use crate::{dynamic, schema, serialize::bit_io::BitBuffer};
+
+/// Totals for one message type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageProfile {
+    pub type_name: &'static str,
+    pub messages: u64,
+    pub bits: u64,
+    /// Bits per field, largest first. Only filled in debug builds, for `#[schema]` types
+    pub fields: Vec<FieldProfile>,
+}
+
+impl MessageProfile {
+    pub fn average_bits(&self) -> f64 {
+        if self.messages == 0 { 0.0 } else { self.bits as f64 / self.messages as f64 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldProfile {
+    /// Field name; enum fields are `Variant.field` and the discriminant is listed as `Variant`
+    pub name: String,
+    pub bits: u64,
+}
+
+#[derive(Debug, Default)]
+struct ProfileTotals {
+    messages: u64,
+    bits: u64,
+    fields: HashMap<String, u64>,
+}
+
+/// Accumulates bits sent per message type.
+///
+/// Set it as [`crate::NetworkConfig::wire_profiler`] to count everything sent with
+/// `send_batch`, or call [`WireProfiler::record`] for values serialized by hand. Cloned
+/// configs share the same totals, like [`crate::TraceRecorder`].
+#[derive(Debug, Clone, Default)]
+pub struct WireProfiler {
+    totals: Arc<Mutex<HashMap<&'static str, ProfileTotals>>>,
+}
+
+impl WireProfiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts one value of `T` as sent.
+    pub fn record<T: BitSerialize>(&self, value: &T) {
+        let mut counter = BitCounter::new();
+        if value.bit_serialize(&mut counter).is_err() {
+            return;
+        }
+        let type_name = short_type_name(std::any::type_name::<T>());
+        #[cfg(debug_assertions)]
+        let fields = measure_fields(type_name, value);
+
+        let mut totals = self.totals.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = totals.entry(type_name).or_default();
+        entry.messages += 1;
+        entry.bits += counter.bits() as u64;
+        #[cfg(debug_assertions)]
+        for (field, bits) in fields {
+            *entry.fields.entry(field).or_default() += bits as u64;
+        }
+    }
+
+    /// Totals per type, most bits first.
+    pub fn report(&self) -> Vec<MessageProfile> {
+        let totals = self.totals.lock().unwrap_or_else(|e| e.into_inner());
+        let mut report: Vec<MessageProfile> = totals.iter()
+            .map(|(type_name, totals)| {
+                let mut fields: Vec<FieldProfile> = totals.fields.iter()
+                    .map(|(name, bits)| FieldProfile { name: name.clone(), bits: *bits })
+                    .collect();
+                fields.sort_by(|a, b| b.bits.cmp(&a.bits).then_with(|| a.name.cmp(&b.name)));
+                MessageProfile { type_name, messages: totals.messages, bits: totals.bits, fields }
+            })
+            .collect();
+        report.sort_by(|a, b| b.bits.cmp(&a.bits).then_with(|| a.type_name.cmp(b.type_name)));
+        report
+    }
+
+    /// Totals for one type, by the name its schema is registered under.
+    pub fn profile(&self, type_name: &str) -> Option<MessageProfile> {
+        self.report().into_iter().find(|profile| profile.type_name == type_name)
+    }
+
+    pub fn reset(&self) {
+        self.totals.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    /// The report as JSON, for dashboards and bug reports.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Value {
+        let types: Vec<Value> = self.report().iter()
+            .map(|profile| {
+                let fields: Vec<Value> = profile.fields.iter()
+                    .map(|field| json!({ "name": field.name, "bits": field.bits }))
+                    .collect();
+                json!({
+                    "type": profile.type_name,
+                    "messages": profile.messages,
+                    "bits": profile.bits,
+                    "average_bits": profile.average_bits(),
+                    "fields": fields,
+                })
+            })
+            .collect();
+        json!({ "types": types })
+    }
+}
+
+// `&game::msg::Move<u8>` is registered as `Move`
+fn short_type_name(full: &'static str) -> &'static str {
+    let path = full.trim_start_matches('&').split('<').next().unwrap_or(full);
+    path.rsplit("::").next().unwrap_or(path)
+}
+
+#[cfg(debug_assertions)]
+fn measure_fields<T: BitSerialize>(type_name: &str, value: &T) -> Vec<(String, usize)> {
+    let Some(schema) = schema::find(type_name) else {
+        return Vec::new();
+    };
+    let mut buffer = BitBuffer::new();
+    if value.bit_serialize(&mut buffer).is_err() {
+        return Vec::new();
+    }
+    let Ok(bytes) = buffer.into_bytes(true) else {
+        return Vec::new();
+    };
+    let mut reader = BitBuffer::from_bytes(bytes);
+    // Fields the schema cannot rebuild (deltas against a baseline) leave only the total
+    dynamic::field_bits(schema, &mut reader).unwrap_or_default()
+}
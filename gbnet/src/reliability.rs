@@ -1,6 +1,14 @@
 // reliability.rs - Reliable packet delivery system
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::time::{Duration, Instant};
+use crate::config::ResendStrategy;
+use crate::congestion::CongestionController;
+use crate::packet::FragmentHeader;
+use crate::rtt::RttHistogram;
+
+/// Timeout used by RTT-based strategies before the first RTT sample (RFC 6298).
+const INITIAL_RTO: Duration = Duration::from_secs(1);
 
 /// Tracks sent packets for reliability and acknowledgment
 #[derive(Debug)]
@@ -17,9 +25,21 @@ pub struct ReliableEndpoint {
     /// Received packets for duplicate detection
     received_packets: SequenceBuffer<bool>,
     
+    /// Round trip estimate from acked packets
+    smoothed_rtt: Option<Duration>,
+    rtt_variance: Duration,
+    /// Every RTT sample, for percentiles and jitter
+    rtt_histogram: RttHistogram,
+    /// Newest sequence the remote has acknowledged, for fast retransmit
+    highest_acked: Option<u16>,
+    /// Resends handed out by `update`
+    packets_resent: u64,
+    /// Told about every ack and loss, and sets the send rate when present
+    congestion: Option<Box<dyn CongestionController>>,
+    
     /// Configuration
     max_sequence_distance: u16,
-    retry_timeout: Duration,
+    default_strategy: ResendStrategy,
     max_retries: u32,
 }
 
@@ -27,6 +47,12 @@ pub struct ReliableEndpoint {
 struct SentPacketData {
     send_time: Instant,
     retry_count: u32,
+    /// Whether the packet has ever been resent; such acks are ambiguous RTT samples (Karn)
+    resent: bool,
+    fast_retransmitted: bool,
+    /// Resend on the next update regardless of timeouts
+    forced: bool,
+    strategy: ResendStrategy,
     data: Vec<u8>,
 }
 
@@ -38,12 +64,35 @@ impl ReliableEndpoint {
             ack_bits: 0,
             sent_packets: HashMap::new(),
             received_packets: SequenceBuffer::new(buffer_size),
+            smoothed_rtt: None,
+            rtt_variance: Duration::ZERO,
+            rtt_histogram: RttHistogram::new(),
+            highest_acked: None,
+            packets_resent: 0,
+            congestion: None,
             max_sequence_distance: 32768,
-            retry_timeout: Duration::from_millis(100),
+            default_strategy: ResendStrategy::default(),
             max_retries: 10,
         }
     }
     
+    /// Sets how many times a packet is resent before it is given up on
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+    
+    /// Feeds acks and losses to `controller`, whose rate [`ReliableEndpoint::allowed_bandwidth`] reports
+    pub fn with_congestion_controller(mut self, controller: Box<dyn CongestionController>) -> Self {
+        self.congestion = Some(controller);
+        self
+    }
+    
+    /// Message bytes per second the congestion controller allows; `None` without one
+    pub fn allowed_bandwidth(&self) -> Option<f32> {
+        self.congestion.as_ref().map(|controller| controller.allowed_bandwidth())
+    }
+    
     /// Gets the next sequence number to use for outgoing packets
     pub fn next_sequence(&mut self) -> u16 {
         let seq = self.local_sequence;
@@ -52,12 +101,32 @@ impl ReliableEndpoint {
     }
     
     /// Records a packet as sent for reliability tracking
+    ///
+    /// Resending a sequence that is still tracked keeps its retry count.
     pub fn on_packet_sent(&mut self, sequence: u16, send_time: Instant, data: Vec<u8>) {
-        self.sent_packets.insert(sequence, SentPacketData {
-            send_time,
-            retry_count: 0,
-            data,
-        });
+        self.on_packet_sent_with(sequence, send_time, data, self.default_strategy);
+    }
+    
+    /// Records a packet as sent, resending it according to `strategy` until acked
+    pub fn on_packet_sent_with(&mut self, sequence: u16, send_time: Instant, data: Vec<u8>, strategy: ResendStrategy) {
+        match self.sent_packets.entry(sequence) {
+            Entry::Occupied(mut entry) => {
+                let packet = entry.get_mut();
+                packet.send_time = send_time;
+                packet.data = data;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(SentPacketData {
+                    send_time,
+                    retry_count: 0,
+                    resent: false,
+                    fast_retransmitted: false,
+                    forced: false,
+                    strategy,
+                    data,
+                });
+            }
+        }
     }
     
     /// Processes an incoming packet and updates ack information
@@ -75,13 +144,14 @@ impl ReliableEndpoint {
             
             // Update remote sequence if this is newer
             if sequence_greater_than(sequence, self.remote_sequence) {
-                // Update ack bits for the gap
+                // Shift the window; the previous newest sequence becomes bit diff - 1 (ack - diff)
                 let diff = sequence_diff(sequence, self.remote_sequence) as u32;
-                if diff <= 32 {
-                    self.ack_bits = (self.ack_bits << diff) | 1;
+                let previous = if diff <= 32 && self.received_packets.exists(self.remote_sequence) {
+                    1 << (diff - 1)
                 } else {
-                    self.ack_bits = 1;
-                }
+                    0
+                };
+                self.ack_bits = self.ack_bits.checked_shl(diff).unwrap_or(0) | previous;
                 self.remote_sequence = sequence;
             } else {
                 // This is an older packet, set the appropriate bit
@@ -95,26 +165,147 @@ impl ReliableEndpoint {
     
     /// Processes acknowledgments from the remote endpoint
     pub fn process_acks(&mut self, ack: u16, ack_bits: u32) {
+        self.process_acks_at(ack, ack_bits, Instant::now());
+    }
+    
+    /// Processes acknowledgments received at `now`, sampling the round trip time
+    pub fn process_acks_at(&mut self, ack: u16, ack_bits: u32, now: Instant) {
+        self.process_acks_with(ack, ack_bits, now, |_| {});
+    }
+    
+    /// Like [`ReliableEndpoint::process_acks_at`], calling `on_ack` for each newly acked packet
+    pub fn process_acks_with(&mut self, ack: u16, ack_bits: u32, now: Instant, mut on_ack: impl FnMut(u16)) {
         // Acknowledge the main sequence
-        self.sent_packets.remove(&ack);
+        if self.acknowledge(ack, now) {
+            on_ack(ack);
+        }
         
         // Process ack bits
         for i in 0..32 {
             if (ack_bits & (1 << i)) != 0 {
                 let acked_seq = ack.wrapping_sub(i + 1);
-                self.sent_packets.remove(&acked_seq);
+                if self.acknowledge(acked_seq, now) {
+                    on_ack(acked_seq);
+                }
             }
         }
     }
     
+    fn acknowledge(&mut self, sequence: u16, now: Instant) -> bool {
+        let packet = match self.sent_packets.remove(&sequence) {
+            Some(packet) => packet,
+            None => return false,
+        };
+        
+        if self.highest_acked.is_none_or(|highest| sequence_greater_than(sequence, highest)) {
+            self.highest_acked = Some(sequence);
+        }
+        let rtt = (!packet.resent).then(|| now.saturating_duration_since(packet.send_time));
+        if let Some(rtt) = rtt {
+            self.add_rtt_sample(rtt);
+        }
+        if let Some(controller) = self.congestion.as_mut() {
+            controller.on_ack(rtt, now);
+        }
+        true
+    }
+    
+    /// Whether a packet is still awaiting acknowledgment
+    pub fn is_in_flight(&self, sequence: u16) -> bool {
+        self.sent_packets.contains_key(&sequence)
+    }
+    
+    /// Stops tracking a packet without waiting for its ack
+    pub fn cancel(&mut self, sequence: u16) {
+        self.sent_packets.remove(&sequence);
+    }
+    
+    /// RFC 6298 smoothing: rttvar = 3/4 rttvar + 1/4 |srtt - r|, srtt = 7/8 srtt + 1/8 r
+    fn add_rtt_sample(&mut self, sample: Duration) {
+        self.rtt_histogram.record(sample);
+        match self.smoothed_rtt {
+            None => {
+                self.smoothed_rtt = Some(sample);
+                self.rtt_variance = sample / 2;
+            }
+            Some(srtt) => {
+                self.rtt_variance = self.rtt_variance * 3 / 4 + srtt.abs_diff(sample) / 4;
+                self.smoothed_rtt = Some(srtt * 7 / 8 + sample / 8);
+            }
+        }
+    }
+    
+    /// Smoothed round trip time, once at least one packet has been acked
+    pub fn rtt(&self) -> Option<Duration> {
+        self.smoothed_rtt
+    }
+    
+    /// Distribution of the RTT samples taken so far
+    pub fn rtt_histogram(&self) -> &RttHistogram {
+        &self.rtt_histogram
+    }
+    
+    pub(crate) fn rtt_histogram_mut(&mut self) -> &mut RttHistogram {
+        &mut self.rtt_histogram
+    }
+    
+    /// Current retransmission timeout before backoff, clamped to `[min_rto, max_rto]`
+    pub fn rto(&self, min_rto: Duration, max_rto: Duration) -> Duration {
+        let rto = match self.smoothed_rtt {
+            Some(srtt) => srtt + self.rtt_variance * 4,
+            None => INITIAL_RTO,
+        };
+        rto.clamp(min_rto, max_rto.max(min_rto))
+    }
+    
+    /// How long a packet waits for an ack before its next resend
+    fn resend_timeout(&self, packet: &SentPacketData) -> Duration {
+        let (min_rto, max_rto, backoff) = match packet.strategy {
+            ResendStrategy::Fixed { interval } => return interval,
+            ResendStrategy::RttBased { min_rto, max_rto, backoff } => (min_rto, max_rto, backoff),
+            ResendStrategy::FastRetransmit { min_rto, max_rto, .. } => (min_rto, max_rto, 2.0),
+        };
+        let factor = (backoff.max(1.0) as f64).powi(packet.retry_count as i32);
+        let timeout = Duration::from_nanos((self.rto(min_rto, max_rto).as_nanos() as f64 * factor).round() as u64);
+        timeout.min(max_rto)
+    }
+    
+    /// When the earliest unacknowledged packet is due to be resent
+    pub fn next_resend_time(&self) -> Option<Instant> {
+        self.sent_packets.values()
+            .map(|packet| packet.send_time + self.resend_timeout(packet))
+            .min()
+    }
+    
     /// Updates the reliability system, retrying timed-out packets
     pub fn update(&mut self, current_time: Instant) -> Vec<(u16, Vec<u8>)> {
         let mut packets_to_resend = Vec::new();
         let mut packets_to_remove = Vec::new();
         
-        for (&sequence, packet_data) in &mut self.sent_packets {
-            let elapsed = current_time.duration_since(packet_data.send_time);
-            if elapsed >= self.retry_timeout {
+        let timeouts: Vec<(u16, Duration)> = self.sent_packets.iter()
+            .map(|(&sequence, packet_data)| (sequence, self.resend_timeout(packet_data)))
+            .collect();
+        let highest_acked = self.highest_acked;
+        
+        for (sequence, timeout) in timeouts {
+            let packet_data = match self.sent_packets.get_mut(&sequence) {
+                Some(packet_data) => packet_data,
+                None => continue,
+            };
+            
+            // Enough newer packets got through that this one is most likely lost
+            let fast_retransmit = match (packet_data.strategy, highest_acked) {
+                (ResendStrategy::FastRetransmit { ack_gap, .. }, Some(highest)) => {
+                    !packet_data.fast_retransmitted && sequence_diff(highest, sequence) >= ack_gap.max(1) as i32
+                }
+                _ => false,
+            };
+            
+            let elapsed = current_time.saturating_duration_since(packet_data.send_time);
+            if packet_data.forced || fast_retransmit || elapsed >= timeout {
+                if let Some(controller) = self.congestion.as_mut() {
+                    controller.on_loss(current_time);
+                }
                 if packet_data.retry_count >= self.max_retries {
                     // Packet failed after max retries
                     packets_to_remove.push(sequence);
@@ -122,7 +313,11 @@ impl ReliableEndpoint {
                     // Retry the packet
                     packet_data.retry_count += 1;
                     packet_data.send_time = current_time;
+                    packet_data.resent = true;
+                    packet_data.fast_retransmitted |= fast_retransmit;
+                    packet_data.forced = false;
                     packets_to_resend.push((sequence, packet_data.data.clone()));
+                    self.packets_resent += 1;
                 }
             }
         }
@@ -140,21 +335,211 @@ impl ReliableEndpoint {
         (self.remote_sequence, self.ack_bits)
     }
     
+    /// Bytes of packet data kept for resending
+    pub fn memory_usage(&self) -> usize {
+        self.sent_packets.values().map(|packet| packet.data.len()).sum()
+    }
+    
     /// Gets statistics about the reliability system
     pub fn stats(&self) -> ReliabilityStats {
         ReliabilityStats {
             packets_in_flight: self.sent_packets.len(),
             local_sequence: self.local_sequence,
             remote_sequence: self.remote_sequence,
+            ack_bits: self.ack_bits,
+            highest_acked: self.highest_acked,
+            packets_resent: self.packets_resent,
         }
     }
 }
 
+/// Test harness access, so edge cases do not need hand-crafted packet bytes.
+#[cfg(any(test, feature = "test-util"))]
+impl ReliableEndpoint {
+    /// Moves every in-flight packet `elapsed` further from its last send, as if that much
+    /// time passed without acks; the next `update` resends whatever has timed out.
+    pub fn advance_time(&mut self, elapsed: Duration) {
+        for packet in self.sent_packets.values_mut() {
+            packet.send_time = packet.send_time.checked_sub(elapsed).unwrap_or(packet.send_time);
+        }
+    }
+    
+    /// Declares an in-flight packet lost: the next `update` resends it, or gives up on it
+    /// if it is out of retries. Returns false if the packet is not in flight.
+    pub fn force_lost(&mut self, sequence: u16) -> bool {
+        match self.sent_packets.get_mut(&sequence) {
+            Some(packet) => {
+                packet.forced = true;
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// Sequences awaiting acknowledgment, oldest first.
+    pub fn in_flight(&self) -> Vec<u16> {
+        let mut sequences: Vec<u16> = self.sent_packets.keys().copied().collect();
+        sequences.sort_by_key(|&sequence| sequence_diff(sequence, self.local_sequence));
+        sequences
+    }
+    
+    /// Times an in-flight packet has been resent.
+    pub fn retry_count(&self, sequence: u16) -> Option<u32> {
+        self.sent_packets.get(&sequence).map(|packet| packet.retry_count)
+    }
+    
+    /// Remote sequences the next outgoing ack covers, newest first, decoded from the ack bitfield.
+    pub fn acked_sequences(&self) -> Vec<u16> {
+        let (ack, ack_bits) = self.get_ack_info();
+        if !self.received_packets.exists(ack) {
+            return Vec::new();
+        }
+        std::iter::once(ack)
+            .chain((0..32).filter(|bit| ack_bits & (1 << bit) != 0).map(|bit| ack.wrapping_sub(bit + 1)))
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ReliabilityStats {
     pub packets_in_flight: usize,
     pub local_sequence: u16,
     pub remote_sequence: u16,
+    pub ack_bits: u32,
+    /// Newest sent sequence the peer has acknowledged
+    pub highest_acked: Option<u16>,
+    pub packets_resent: u64,
+}
+
+/// Rebuilds messages sent as `Payload` fragments, per channel and message sequence.
+///
+/// Fragments may arrive in any order and more than once. Messages still missing a fragment
+/// after the timeout are dropped, as are fragments claiming more than `max_fragments` pieces.
+#[derive(Debug)]
+pub struct FragmentReassembler {
+    pending: HashMap<(u8, u16), PartialMessage>,
+    max_fragments: usize,
+    timeout: Duration,
+}
+
+#[derive(Debug)]
+struct PartialMessage {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+    started: Instant,
+}
+
+impl FragmentReassembler {
+    pub fn new(max_fragments: usize, timeout: Duration) -> Self {
+        Self { pending: HashMap::new(), max_fragments, timeout }
+    }
+
+    /// Adds a fragment payload received on `channel`; returns the message sequence and the
+    /// whole message once its last fragment is in.
+    pub fn insert(&mut self, channel: u8, payload: &[u8], now: Instant) -> Option<(u16, Vec<u8>)> {
+        let (header, data) = FragmentHeader::read(payload)?;
+        let count = header.count as usize;
+        if count > self.max_fragments {
+            return None;
+        }
+        let key = (channel, header.sequence);
+        let partial = self.pending.entry(key).or_insert_with(|| PartialMessage {
+            fragments: vec![None; count],
+            received: 0,
+            bytes: 0,
+            started: now,
+        });
+        // A reused sequence with a different split is a new message
+        if partial.fragments.len() != count {
+            *partial = PartialMessage { fragments: vec![None; count], received: 0, bytes: 0, started: now };
+        }
+        let slot = &mut partial.fragments[header.index as usize];
+        if slot.is_none() {
+            *slot = Some(data.to_vec());
+            partial.received += 1;
+            partial.bytes += data.len();
+        }
+        if partial.received < count {
+            return None;
+        }
+        let partial = self.pending.remove(&key)?;
+        let mut message = Vec::with_capacity(partial.bytes);
+        for fragment in partial.fragments.into_iter().flatten() {
+            message.extend_from_slice(&fragment);
+        }
+        Some((header.sequence, message))
+    }
+
+    /// Drops messages that have waited longer than the timeout for a fragment; returns how many.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.pending.len();
+        let timeout = self.timeout;
+        self.pending.retain(|_, partial| now.saturating_duration_since(partial.started) < timeout);
+        before - self.pending.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Messages with fragments still missing.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.pending.values().map(|partial| partial.bytes).sum()
+    }
+}
+
+// How long each minimum in a PacketAgeEstimator's baseline covers
+const AGE_BASELINE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Stamps outgoing packets and estimates how long ago incoming ones were sent.
+///
+/// Stamps are milliseconds on the sender's monotonic clock, so they share no origin with
+/// ours. Receive time minus stamp is the one-way delay plus the clock offset; the smallest
+/// seen over the last one to two windows stands in for an undelayed packet, and a packet's
+/// age is how far it lags behind that. Forgetting old minimums lets the baseline follow
+/// clock drift. Stamps wrap after about 49 days, which the modular arithmetic absorbs.
+#[derive(Debug)]
+pub struct PacketAgeEstimator {
+    origin: Instant,
+    previous_min: Option<u32>,
+    window_min: Option<u32>,
+    window_start: Instant,
+}
+
+impl PacketAgeEstimator {
+    pub fn new(now: Instant) -> Self {
+        Self { origin: now, previous_min: None, window_min: None, window_start: now }
+    }
+
+    /// Stamp for a packet sent at `now`.
+    pub fn stamp(&self, now: Instant) -> u32 {
+        now.saturating_duration_since(self.origin).as_millis() as u32
+    }
+
+    /// Age of a packet carrying the peer's `stamp`, processed at `now`.
+    pub fn age(&mut self, stamp: u32, now: Instant) -> Duration {
+        if now.saturating_duration_since(self.window_start) >= AGE_BASELINE_WINDOW {
+            self.previous_min = self.window_min.take();
+            self.window_start = now;
+        }
+        let offset = self.stamp(now).wrapping_sub(stamp);
+        let lower = |a: u32, b: u32| if (a.wrapping_sub(b) as i32) < 0 { a } else { b };
+        let window_min = self.window_min.map_or(offset, |min| lower(min, offset));
+        self.window_min = Some(window_min);
+        let baseline = self.previous_min.map_or(window_min, |min| lower(min, window_min));
+        Duration::from_millis((offset.wrapping_sub(baseline) as i32).max(0) as u64)
+    }
+
+    /// Forgets the baseline, for a new session with a new peer clock.
+    pub fn clear(&mut self) {
+        self.previous_min = None;
+        self.window_min = None;
+    }
 }
 
 /// A circular buffer for tracking sequence numbers
@@ -0,0 +1,326 @@
+// resolve.rs - Connecting to a hostname, with Happy Eyeballs racing of the resolved addresses
+//
+// Players type `play.example.com:40000`, not socket addresses. A `HostConnector` resolves the
+// name on a background thread so a game loop polling it never stalls, then races the results
+// RFC 8305 style: addresses are ordered to alternate between IPv6 and IPv4 starting with the
+// resolver's first preference, and a new handshake starts every `connect_attempt_delay` (or
+// as soon as the previous one fails) until one of them connects. The first to connect wins
+// and the rest are disconnected, so a broken IPv6 route costs a quarter second instead of a
+// full handshake timeout.
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use crate::{
+    NetworkConfig,
+    connection::{Connection, ConnectionError},
+    packet::disconnect_reason,
+    socket::{SocketError, UdpSocket},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolveError {
+    /// Not `host:port`, or the port is not a number
+    InvalidAddress(String),
+    /// The name resolved to no addresses
+    NotFound(String),
+    /// The system resolver failed
+    Lookup { host: String, kind: io::ErrorKind, message: String },
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::InvalidAddress(host) => write!(f, "invalid address {:?}, expected host:port", host),
+            ResolveError::NotFound(host) => write!(f, "{} has no addresses", host),
+            ResolveError::Lookup { host, message, .. } => write!(f, "resolving {} failed: {}", host, message),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+#[derive(Debug)]
+pub enum ConnectError {
+    Resolve(ResolveError),
+    /// Binding the socket for an attempt failed
+    Socket(SocketError),
+    /// Every address was tried; holds the error of the last attempt to fail
+    Connection(ConnectionError),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Resolve(err) => write!(f, "{}", err),
+            ConnectError::Socket(err) => write!(f, "socket error: {:?}", err),
+            ConnectError::Connection(err) => write!(f, "no address connected: {:?}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+impl From<ResolveError> for ConnectError {
+    fn from(err: ResolveError) -> Self {
+        ConnectError::Resolve(err)
+    }
+}
+
+impl From<SocketError> for ConnectError {
+    fn from(err: SocketError) -> Self {
+        ConnectError::Socket(err)
+    }
+}
+
+/// Resolves `host:port` (IPv6 literals in brackets) and orders the addresses for racing.
+///
+/// Blocks on the system resolver; [`HostConnector`] runs this on a background thread.
+pub fn resolve(host: &str) -> Result<Vec<SocketAddr>, ResolveError> {
+    if let Ok(addr) = host.parse::<SocketAddr>() {
+        return Ok(vec![addr]);
+    }
+    check_host(host)?;
+    let addrs: Vec<SocketAddr> = host.to_socket_addrs()
+        .map_err(|err| ResolveError::Lookup { host: host.to_string(), kind: err.kind(), message: err.to_string() })?
+        .collect();
+    if addrs.is_empty() {
+        return Err(ResolveError::NotFound(host.to_string()));
+    }
+    Ok(interleave_families(addrs))
+}
+
+/// Alternates address families, starting with the family of the first address and keeping
+/// the resolver's order within each family (RFC 8305 section 4).
+pub fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let prefer_v6 = first.is_ipv6();
+    let (mut preferred, mut other): (VecDeque<_>, VecDeque<_>) = addrs.into_iter().partition(|addr| addr.is_ipv6() == prefer_v6);
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    while !preferred.is_empty() || !other.is_empty() {
+        ordered.extend(preferred.pop_front());
+        ordered.extend(other.pop_front());
+    }
+    ordered
+}
+
+fn check_host(host: &str) -> Result<(), ResolveError> {
+    let invalid = || ResolveError::InvalidAddress(host.to_string());
+    let (name, port) = host.rsplit_once(':').ok_or_else(invalid)?;
+    if name.is_empty() || port.parse::<u16>().is_err() {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+struct Attempt {
+    socket: UdpSocket,
+    connection: Connection,
+}
+
+type Binder = Box<dyn FnMut(SocketAddr) -> Result<UdpSocket, SocketError> + Send>;
+
+/// Connects to the first reachable address of a host; see the module docs.
+///
+/// Call [`HostConnector::poll`] every tick until it hands back the connected connection and
+/// the socket it runs on.
+pub struct HostConnector {
+    config: NetworkConfig,
+    lookup: Option<Receiver<Result<Vec<SocketAddr>, ResolveError>>>,
+    candidates: VecDeque<SocketAddr>,
+    attempts: Vec<Attempt>,
+    next_attempt: Option<Instant>,
+    bind: Binder,
+    last_error: Option<ConnectionError>,
+}
+
+impl HostConnector {
+    /// Starts resolving `host` (`name:port`, or a literal socket address, which skips the
+    /// lookup). Malformed addresses fail here; lookup failures come from `poll`.
+    pub fn new(host: &str, config: NetworkConfig) -> Result<Self, ResolveError> {
+        if let Ok(addr) = host.parse::<SocketAddr>() {
+            return Ok(Self::from_addrs(vec![addr], config));
+        }
+        check_host(host)?;
+
+        let (sender, receiver) = mpsc::channel();
+        let name = host.to_string();
+        std::thread::spawn(move || {
+            let _ = sender.send(resolve(&name));
+        });
+        let mut connector = Self::from_addrs(Vec::new(), config);
+        connector.lookup = Some(receiver);
+        Ok(connector)
+    }
+
+    /// Races already resolved addresses, e.g. a server list from a matchmaker, in order.
+    pub fn from_addrs(addrs: Vec<SocketAddr>, config: NetworkConfig) -> Self {
+        let now = config.clock.now();
+        Self {
+            config,
+            lookup: None,
+            candidates: addrs.into(),
+            attempts: Vec::new(),
+            next_attempt: Some(now),
+            bind: Box::new(|remote| UdpSocket::bind(SocketAddr::new(unspecified(remote), 0))),
+            last_error: None,
+        }
+    }
+
+    /// Replaces how each attempt's socket is made, e.g. to use a fixed local port or a
+    /// [`crate::MemoryNetwork`]. Called with the address about to be tried.
+    pub fn bind_with<F>(mut self, bind: F) -> Self
+    where
+        F: FnMut(SocketAddr) -> Result<UdpSocket, SocketError> + Send + 'static,
+    {
+        self.bind = Box::new(bind);
+        self
+    }
+
+    /// Whether the name is still being resolved
+    pub fn is_resolving(&self) -> bool {
+        self.lookup.is_some()
+    }
+
+    /// Addresses of the handshakes in progress, oldest first
+    pub fn attempting(&self) -> Vec<SocketAddr> {
+        self.attempts.iter().map(|attempt| attempt.connection.remote_addr()).collect()
+    }
+
+    /// Advances resolution and every handshake. Returns the winner once one connects, and
+    /// an error once the lookup or every address has failed.
+    pub fn poll(&mut self) -> Result<Option<(Connection, UdpSocket)>, ConnectError> {
+        if let Some(lookup) = &self.lookup {
+            match lookup.try_recv() {
+                Ok(result) => {
+                    self.lookup = None;
+                    self.candidates = result?.into();
+                    self.next_attempt = Some(self.config.clock.now());
+                }
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => {
+                    self.lookup = None;
+                    return Err(ConnectError::Resolve(ResolveError::Lookup {
+                        host: String::new(),
+                        kind: io::ErrorKind::Other,
+                        message: "resolver thread exited".to_string(),
+                    }));
+                }
+            }
+        }
+
+        let now = self.config.clock.now();
+        if self.next_attempt.is_some_and(|at| now >= at) {
+            self.start_attempt(now)?;
+        }
+
+        let mut index = 0;
+        while index < self.attempts.len() {
+            let attempt = &mut self.attempts[index];
+            match attempt.connection.update(&mut attempt.socket) {
+                Ok(()) if attempt.connection.is_connected() => {
+                    let winner = self.attempts.swap_remove(index);
+                    self.abandon_attempts();
+                    return Ok(Some((winner.connection, winner.socket)));
+                }
+                Ok(()) => index += 1,
+                Err(err) => {
+                    self.attempts.remove(index);
+                    self.last_error = Some(err);
+                    // A failed attempt lets the next address go right away
+                    if !self.candidates.is_empty() {
+                        self.next_attempt = Some(now);
+                    }
+                }
+            }
+        }
+
+        if self.attempts.is_empty() && self.candidates.is_empty() {
+            let err = self.last_error.take().unwrap_or(ConnectionError::Timeout);
+            return Err(ConnectError::Connection(err));
+        }
+        Ok(None)
+    }
+
+    /// When `poll` next has work without a packet arriving; `None` while resolving.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.lookup.is_some() {
+            return None;
+        }
+        self.attempts.iter()
+            .filter_map(|attempt| attempt.connection.next_service_time())
+            .chain(self.next_attempt.filter(|_| !self.candidates.is_empty()))
+            .min()
+    }
+
+    fn start_attempt(&mut self, now: Instant) -> Result<(), ConnectError> {
+        let Some(remote) = self.candidates.pop_front() else {
+            self.next_attempt = None;
+            return Ok(());
+        };
+        self.next_attempt = Some(now + self.config.connect_attempt_delay);
+
+        let socket = match (self.bind)(remote) {
+            Ok(socket) => socket,
+            // No route for this family here; move on unless nothing else is left
+            Err(err) if !self.candidates.is_empty() || !self.attempts.is_empty() => {
+                self.last_error = Some(err.into());
+                self.next_attempt = Some(now);
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let mut connection = Connection::new(self.config.clone(), socket.local_addr()?, remote);
+        if let Err(err) = connection.connect() {
+            self.last_error = Some(err);
+            return Ok(());
+        }
+        self.attempts.push(Attempt { socket, connection });
+        Ok(())
+    }
+
+    // Lets servers that got this far free the slot instead of waiting for a timeout
+    fn abandon_attempts(&mut self) {
+        for mut attempt in self.attempts.drain(..) {
+            if attempt.connection.disconnect(disconnect_reason::REQUESTED).is_ok() {
+                let _ = attempt.connection.flush(&mut attempt.socket);
+            }
+        }
+        self.candidates.clear();
+        self.next_attempt = None;
+    }
+}
+
+impl fmt::Debug for HostConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostConnector")
+            .field("resolving", &self.is_resolving())
+            .field("candidates", &self.candidates)
+            .field("attempting", &self.attempting())
+            .finish()
+    }
+}
+
+/// Resolves `host` and blocks until one of its addresses connects.
+pub fn connect_host(host: &str, config: NetworkConfig) -> Result<(Connection, UdpSocket), ConnectError> {
+    let mut connector = HostConnector::new(host, config)?;
+    loop {
+        if let Some(connected) = connector.poll()? {
+            return Ok(connected);
+        }
+        // Attempts have a socket each, so there is no single socket to wait on
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn unspecified(remote: SocketAddr) -> IpAddr {
+    match remote {
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    }
+}
@@ -0,0 +1,90 @@
+// rng.rs - Deterministic random numbers shared by both ends of a connection
+//
+// The server picks a session seed during the handshake and sends it with the accept,
+// so lockstep and prediction code on client and server can draw identical sequences.
+
+/// Size of the session seed at the start of the accept payload.
+pub const SEED_BYTES: usize = 8;
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+/// Streams of `NetworkConfig::random_seed`, one per consumer so they do not disturb each other.
+pub(crate) mod stream {
+    pub const CONNECTION: u64 = 1;
+    pub const SERVER: u64 = 2;
+    pub const TICKETS: u64 = 3;
+    /// Base of the simulator streams, offset by `SimulatorConfig::seed`
+    pub const SIMULATOR: u64 = 4;
+}
+
+/// PCG32 (XSH RR) generator.
+///
+/// Platform independent and cheap to clone, so it can be snapshotted alongside
+/// simulation state for rollback. Different streams from the same seed are independent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeterministicRng {
+    state: u64,
+    increment: u64,
+}
+
+impl DeterministicRng {
+    pub fn new(seed: u64) -> Self {
+        Self::with_stream(seed, 0)
+    }
+
+    /// Creates a generator for one of 2^63 independent streams of a seed.
+    pub fn with_stream(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.step();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.step();
+        rng
+    }
+
+    fn step(&mut self) {
+        self.state = self.state.wrapping_mul(MULTIPLIER).wrapping_add(self.increment);
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.step();
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let high = self.next_u32() as u64;
+        (high << 32) | self.next_u32() as u64
+    }
+
+    /// Uniform value in `0..bound` without modulo bias. Returns 0 when `bound` is 0.
+    pub fn next_below(&mut self, bound: u32) -> u32 {
+        if bound == 0 {
+            return 0;
+        }
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let value = self.next_u32();
+            if value >= threshold {
+                return value % bound;
+            }
+        }
+    }
+
+    pub fn next_bool(&mut self) -> bool {
+        self.next_u32() >> 31 == 1
+    }
+
+    /// Uniform value in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+    }
+
+    /// Uniform value in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+}
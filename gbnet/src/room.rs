@@ -0,0 +1,79 @@
+// room.rs - Isolated groups of clients sharing one Server
+//
+// Rooms let one process host many small matches over a single socket. Each client is in at
+// most one room; its membership is the scope for room broadcasts and anything the game
+// replicates per match, and it is dropped when the client disconnects.
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+
+pub type RoomId = u32;
+
+#[derive(Debug, Default, Clone)]
+pub struct RoomStats {
+    /// Messages queued to members by room broadcasts
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    /// Messages members delivered to the application through `Server::receive`
+    pub messages_received: u64,
+    pub bytes_received: u64,
+    pub joins: u64,
+    pub leaves: u64,
+}
+
+#[derive(Debug)]
+pub struct Room {
+    id: RoomId,
+    members: BTreeSet<SocketAddr>,
+    stats: RoomStats,
+}
+
+impl Room {
+    pub(crate) fn new(id: RoomId) -> Self {
+        Self {
+            id,
+            members: BTreeSet::new(),
+            stats: RoomStats::default(),
+        }
+    }
+
+    pub fn id(&self) -> RoomId {
+        self.id
+    }
+
+    /// Members in address order.
+    pub fn members(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.members.iter()
+    }
+
+    pub fn contains(&self, addr: SocketAddr) -> bool {
+        self.members.contains(&addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn stats(&self) -> &RoomStats {
+        &self.stats
+    }
+
+    pub(crate) fn stats_mut(&mut self) -> &mut RoomStats {
+        &mut self.stats
+    }
+
+    pub(crate) fn insert(&mut self, addr: SocketAddr) {
+        if self.members.insert(addr) {
+            self.stats.joins += 1;
+        }
+    }
+
+    pub(crate) fn remove(&mut self, addr: SocketAddr) {
+        if self.members.remove(&addr) {
+            self.stats.leaves += 1;
+        }
+    }
+}
@@ -0,0 +1,130 @@
+// schema.rs - Wire layout metadata registered by `#[derive(NetworkSerialize)]`
+//
+// Types marked `#[schema]` submit a `TypeSchema` describing their bit-packed layout.
+// With the `json` feature, `dump()` turns everything registered into JSON for doc and foreign
+// serializer generators.
+#[cfg(feature = "json")]
+use serde_json::{json, Value};
+
+// The derive expands to `::gbnet::schema::inventory::submit!`
+pub use inventory;
+
+/// Version of the JSON produced by [`dump`].
+pub const SCHEMA_VERSION: u64 = 4;
+
+/// Layout of one serialized field. Fields marked `#[no_serialize]` are not listed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldSchema {
+    /// Field name, or its index for tuple fields
+    pub name: &'static str,
+    /// The Rust type as written in the source
+    pub ty: &'static str,
+    /// Fixed bit width, or `None` for types encoded through their own impl
+    pub bits: Option<usize>,
+    /// Maximum length of a `Vec` or `String`; without it the length prefix is 16 bits
+    pub max_len: Option<usize>,
+    pub byte_align: bool,
+    /// `(min, max)` range of a quantized float
+    pub quantize: Option<(f64, f64)>,
+    /// Encoded as a prefix delta against the previous value (`#[delta_string]`)
+    pub delta_string: bool,
+    /// Encoded in 7-bit groups with continuation bits, zigzag for signed types (`#[varint]`)
+    pub varint: bool,
+    /// A varint length, then the bytes from the next byte boundary (`#[blob]`); byte arrays
+    /// have no length
+    pub blob: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VariantSchema {
+    pub name: &'static str,
+    pub index: u64,
+    pub fields: &'static [FieldSchema],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchemaKind {
+    Struct { fields: &'static [FieldSchema] },
+    /// `variant_bits` is the bit-packed discriminant width; byte-aligned encoding always uses a u8
+    Enum { variant_bits: usize, variants: &'static [VariantSchema] },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TypeSchema {
+    pub name: &'static str,
+    pub module: &'static str,
+    pub kind: SchemaKind,
+    /// Tag written ahead of the value with the `debug-packets` feature, `None` for `#[gbnet(untagged)]`
+    pub tag: Option<u32>,
+}
+
+inventory::collect!(TypeSchema);
+
+/// All registered types, ordered by module and name.
+pub fn registered() -> Vec<&'static TypeSchema> {
+    let mut types: Vec<_> = inventory::iter::<TypeSchema>.into_iter().collect();
+    types.sort_by_key(|schema| (schema.module, schema.name));
+    types
+}
+
+/// Looks up a registered type by name.
+pub fn find(name: &str) -> Option<&'static TypeSchema> {
+    inventory::iter::<TypeSchema>.into_iter().find(|schema| schema.name == name)
+}
+
+/// Looks up a registered type by its debug-packets tag.
+pub fn find_by_tag(tag: u32) -> Option<&'static TypeSchema> {
+    inventory::iter::<TypeSchema>.into_iter().find(|schema| schema.tag == Some(tag))
+}
+
+/// Describes every registered type as pretty-printed JSON.
+#[cfg(feature = "json")]
+pub fn dump() -> String {
+    let types: Vec<Value> = registered().into_iter().map(describe_type).collect();
+    let schema = json!({ "version": SCHEMA_VERSION, "types": types });
+    serde_json::to_string_pretty(&schema).expect("schema is valid JSON")
+}
+
+#[cfg(feature = "json")]
+fn describe_type(schema: &TypeSchema) -> Value {
+    match schema.kind {
+        SchemaKind::Struct { fields } => json!({
+            "name": schema.name,
+            "module": schema.module,
+            "kind": "struct",
+            "tag": schema.tag,
+            "fields": describe_fields(fields),
+        }),
+        SchemaKind::Enum { variant_bits, variants } => json!({
+            "name": schema.name,
+            "module": schema.module,
+            "kind": "enum",
+            "tag": schema.tag,
+            "variant_bits": variant_bits,
+            "variants": variants.iter()
+                .map(|variant| json!({
+                    "name": variant.name,
+                    "index": variant.index,
+                    "fields": describe_fields(variant.fields),
+                }))
+                .collect::<Vec<_>>(),
+        }),
+    }
+}
+
+#[cfg(feature = "json")]
+fn describe_fields(fields: &[FieldSchema]) -> Vec<Value> {
+    fields.iter()
+        .map(|field| json!({
+            "name": field.name,
+            "type": field.ty,
+            "bits": field.bits,
+            "max_len": field.max_len,
+            "byte_align": field.byte_align,
+            "quantize": field.quantize.map(|(min, max)| json!({ "min": min, "max": max })),
+            "delta_string": field.delta_string,
+            "varint": field.varint,
+            "blob": field.blob,
+        }))
+        .collect()
+}
@@ -6,16 +6,200 @@ pub mod bit_io {
     use std::io;
     use log::{debug, trace};
 
-    pub trait BitWrite {
+    // Only the readers and writers in this module implement BitWrite and BitRead, so their
+    // methods can change (word-at-a-time access, pooled buffers) without breaking
+    // downstream code. Types implement BitSerialize/BitDeserialize against them instead.
+    mod sealed {
+        pub trait Sealed {}
+
+        impl Sealed for super::BitCounter {}
+        impl Sealed for super::BitBuffer {}
+        impl Sealed for super::BitSlice<'_> {}
+        impl Sealed for super::BitAppender<'_> {}
+        impl<T: Sealed + ?Sized> Sealed for &mut T {}
+    }
+
+    /// Destination of bit-packed serialization. Sealed: implemented only by this crate's writers.
+    pub trait BitWrite: sealed::Sealed {
         fn write_bit(&mut self, bit: bool) -> io::Result<()>;
         fn write_bits(&mut self, value: u64, bits: usize) -> io::Result<()>;
         fn bit_pos(&self) -> usize;
+
+        /// Order the bits of one `write_bits` value go out in, for callers packing several
+        /// values into a single write
+        fn bit_order(&self) -> BitOrder {
+            BitOrder::MsbFirst
+        }
+
+        /// Writes `value` in 8-bit groups of a continuation bit and 7 value bits, low
+        /// bits first: 8 bits below 128, up to 80 for the largest values
+        fn write_varint(&mut self, value: u64) -> io::Result<()> {
+            let mut value = value;
+            while value >= 0x80 {
+                self.write_bits(0x80 | (value & 0x7F), 8)?;
+                value >>= 7;
+            }
+            self.write_bits(value, 8)
+        }
+
+        /// Writes `value` zigzag encoded as a varint, so small negative values stay small too
+        fn write_signed_varint(&mut self, value: i64) -> io::Result<()> {
+            self.write_varint(zigzag_encode(value))
+        }
+
+        /// Pads with zero bits to the next byte boundary, then writes `bytes` unchanged;
+        /// the buffers copy them in one go
+        fn write_aligned_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+            self.write_bits(0, (8 - self.bit_pos() % 8) % 8)?;
+            bytes.iter().try_for_each(|&byte| self.write_bits(byte as u64, 8))
+        }
     }
 
-    pub trait BitRead {
+    /// Source of bit-packed deserialization. Sealed: implemented only by this crate's readers.
+    pub trait BitRead: sealed::Sealed {
         fn read_bit(&mut self) -> io::Result<bool>;
         fn read_bits(&mut self, bits: usize) -> io::Result<u64>;
         fn bit_pos(&self) -> usize;
+
+        /// Returns to a position saved with [`BitRead::mark`]
+        fn rewind(&mut self, mark: BitMark);
+
+        /// Saves the read position, to return to with [`BitRead::rewind`]
+        fn mark(&self) -> BitMark {
+            BitMark(self.bit_pos())
+        }
+
+        /// Reads `bits` without consuming them
+        fn peek_bits(&mut self, bits: usize) -> io::Result<u64> {
+            let mark = self.mark();
+            let value = self.read_bits(bits);
+            self.rewind(mark);
+            value
+        }
+
+        fn peek_bit(&mut self) -> io::Result<bool> {
+            self.peek_bits(1).map(|bit| bit != 0)
+        }
+
+        /// Runs a speculative decode, rewinding to where it started if it fails, so the
+        /// caller can try something else on the same bits
+        fn try_read<T>(&mut self, read: impl FnOnce(&mut Self) -> io::Result<T>) -> io::Result<T>
+        where
+            Self: Sized,
+        {
+            let mark = self.mark();
+            let result = read(self);
+            if result.is_err() {
+                self.rewind(mark);
+            }
+            result
+        }
+
+        /// Reads a value written with [`BitWrite::write_varint`]; fails on encodings that run
+        /// past 64 bits
+        fn read_varint(&mut self) -> io::Result<u64> {
+            let mut value = 0u64;
+            for shift in (0..64).step_by(7) {
+                let group = self.read_bits(8)?;
+                let bits = group & 0x7F;
+                if shift == 63 && bits > 1 {
+                    break;
+                }
+                value |= bits << shift;
+                if group & 0x80 == 0 {
+                    return Ok(value);
+                }
+            }
+            Err(io::Error::new(io::ErrorKind::InvalidData, "Varint exceeds 64 bits"))
+        }
+
+        /// Skips to the next byte boundary and fills `out` with the bytes that follow, as
+        /// written by [`BitWrite::write_aligned_bytes`]
+        fn read_aligned_into(&mut self, out: &mut [u8]) -> io::Result<()> {
+            self.read_bits((8 - self.bit_pos() % 8) % 8)?;
+            for byte in out.iter_mut() {
+                *byte = self.read_bits(8)? as u8;
+            }
+            Ok(())
+        }
+
+        /// Reads a value written with [`BitWrite::write_signed_varint`]
+        fn read_signed_varint(&mut self) -> io::Result<i64> {
+            self.read_varint().map(zigzag_decode)
+        }
+    }
+
+    /// A read position saved by [`BitRead::mark`].
+    ///
+    /// Marks are plain bit offsets: rewinding one reader to another's mark moves it to the
+    /// same offset in its own data.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct BitMark(usize);
+
+    impl BitMark {
+        pub fn bit_pos(&self) -> usize {
+            self.0
+        }
+    }
+
+    /// Maps signed values onto unsigned ones by magnitude: 0, -1, 1, -2, ... become 0, 1, 2, 3, ...
+    pub fn zigzag_encode(value: i64) -> u64 {
+        ((value << 1) ^ (value >> 63)) as u64
+    }
+
+    pub fn zigzag_decode(value: u64) -> i64 {
+        (value >> 1) as i64 ^ -((value & 1) as i64)
+    }
+
+    /// A writer that only counts bits, for measuring a value's encoded size without
+    /// encoding it anywhere.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct BitCounter {
+        bits: usize,
+    }
+
+    impl BitCounter {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Bits written so far
+        pub fn bits(&self) -> usize {
+            self.bits
+        }
+    }
+
+    impl BitWrite for BitCounter {
+        fn write_bit(&mut self, _bit: bool) -> io::Result<()> {
+            self.bits += 1;
+            Ok(())
+        }
+
+        fn write_bits(&mut self, _value: u64, bits: usize) -> io::Result<()> {
+            self.bits += bits;
+            Ok(())
+        }
+
+        fn bit_pos(&self) -> usize {
+            self.bits
+        }
+
+        fn write_aligned_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+            self.bits = self.bits.next_multiple_of(8) + bytes.len() * 8;
+            Ok(())
+        }
+    }
+
+    /// Order in which bits fill each byte of a [`BitBuffer`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum BitOrder {
+        /// Bits fill each byte from the top and values go most significant bit first,
+        /// so byte-aligned integers come out big-endian
+        #[default]
+        MsbFirst,
+        /// Bits fill each byte from the bottom and values go least significant bit first,
+        /// so byte-aligned integers come out little-endian
+        LsbFirst,
     }
 
     pub struct BitBuffer {
@@ -23,6 +207,7 @@ pub mod bit_io {
         bit_pos: usize,
         read_pos: usize,
         unpadded_length: usize, // Tracks bits before padding
+        bit_order: BitOrder,
     }
 
     impl BitBuffer {
@@ -32,9 +217,21 @@ pub mod bit_io {
                 bit_pos: 0,
                 read_pos: 0,
                 unpadded_length: 0,
+                bit_order: BitOrder::MsbFirst,
             }
         }
 
+        /// Switches the bit order, e.g. `BitBuffer::from_bytes(bytes).with_bit_order(BitOrder::LsbFirst)`
+        /// to read a protocol that packs LSB-first. Set it before any bits are written or read.
+        pub fn with_bit_order(mut self, bit_order: BitOrder) -> Self {
+            self.bit_order = bit_order;
+            self
+        }
+
+        pub fn bit_order(&self) -> BitOrder {
+            self.bit_order
+        }
+
         pub fn unpadded_length(&self) -> usize {
             self.unpadded_length
         }
@@ -50,6 +247,7 @@ pub mod bit_io {
                 bit_pos: 0,
                 read_pos: 0,
                 unpadded_length: 0,
+                bit_order: BitOrder::MsbFirst,
             }
         }
 
@@ -57,9 +255,9 @@ pub mod bit_io {
             let mut bit_string = String::new();
             let mut bits_written = 0;
             for (i, &byte) in self.buffer.iter().enumerate() {
-                for j in (0..8).rev() {
+                for j in 0..8 {
                     if bits_written < bit_length {
-                        let bit = (byte >> j) & 1;
+                        let bit = (byte >> bit_shift(self.bit_order, j)) & 1;
                         bit_string.push_str(&bit.to_string());
                         bits_written += 1;
                     } else {
@@ -82,6 +280,46 @@ pub mod bit_io {
             Ok(())
         }
 
+        // LSB-first writes: each step fills the low free bits of the current byte
+        fn write_bits_lsb(&mut self, mut value: u64, bits: usize) -> io::Result<()> {
+            let mut remaining_bits = bits;
+            while remaining_bits > 0 {
+                let byte_pos = self.bit_pos / 8;
+                let bit_offset = self.bit_pos % 8;
+                let bits_to_write = remaining_bits.min(8 - bit_offset);
+                if byte_pos >= self.buffer.len() {
+                    self.buffer.push(0);
+                }
+
+                let chunk = (value & ((1u64 << bits_to_write) - 1)) as u8;
+                self.buffer[byte_pos] |= chunk << bit_offset;
+                value >>= bits_to_write;
+                self.bit_pos += bits_to_write;
+                remaining_bits -= bits_to_write;
+            }
+            self.unpadded_length += bits;
+            Ok(())
+        }
+
+        fn read_bits_lsb(&mut self, bits: usize) -> io::Result<u64> {
+            if self.read_pos + bits > self.buffer.len() * 8 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Buffer underflow during LSB-first read"));
+            }
+            let mut value = 0u64;
+            let mut read = 0;
+            while read < bits {
+                let byte_pos = self.read_pos / 8;
+                let bit_offset = self.read_pos % 8;
+                let bits_to_read = (bits - read).min(8 - bit_offset);
+
+                let chunk = (self.buffer[byte_pos] >> bit_offset) as u64 & ((1u64 << bits_to_read) - 1);
+                value |= chunk << read;
+                self.read_pos += bits_to_read;
+                read += bits_to_read;
+            }
+            Ok(value)
+        }
+
         // OPTIMIZATION: Fast path for byte-aligned writes
         fn write_bytes_fast(&mut self, value: u64, bytes: usize) -> io::Result<()> {
             // Ensure we have enough space
@@ -220,10 +458,11 @@ pub mod bit_io {
                 self.buffer.push(0);
             }
 
+            let mask = 1 << bit_shift(self.bit_order, bit_offset);
             if bit {
-                self.buffer[byte_pos] |= 1 << (7 - bit_offset);
+                self.buffer[byte_pos] |= mask;
             } else {
-                self.buffer[byte_pos] &= !(1 << (7 - bit_offset));
+                self.buffer[byte_pos] &= !mask;
             }
 
             self.bit_pos += 1;
@@ -239,7 +478,12 @@ pub mod bit_io {
                 return Ok(());
             }
 
-            let val = value & ((1u64 << bits) - 1); // Mask to ensure only `bits` are used
+            // Mask to ensure only `bits` are used (a 64-bit shift would overflow)
+            let val = if bits == 64 { value } else { value & ((1u64 << bits) - 1) };
+
+            if self.bit_order == BitOrder::LsbFirst {
+                return self.write_bits_lsb(val, bits);
+            }
 
             // FAST PATH: Check if we can write whole bytes efficiently
             if self.bit_pos % 8 == 0 && bits % 8 == 0 {
@@ -253,6 +497,21 @@ pub mod bit_io {
         fn bit_pos(&self) -> usize {
             self.bit_pos
         }
+
+        fn bit_order(&self) -> BitOrder {
+            self.bit_order
+        }
+
+        // Whole bytes read the same in either bit order
+        fn write_aligned_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+            let padding = (8 - self.bit_pos % 8) % 8;
+            self.write_bits(0, padding)?;
+            self.buffer.truncate(self.bit_pos / 8);
+            self.buffer.extend_from_slice(bytes);
+            self.bit_pos += bytes.len() * 8;
+            self.unpadded_length += bytes.len() * 8;
+            Ok(())
+        }
     }
 
     impl BitRead for BitBuffer {
@@ -268,7 +527,7 @@ pub mod bit_io {
                 ));
             }
 
-            let bit = (self.buffer[byte_pos] & (1 << (7 - bit_offset))) != 0;
+            let bit = (self.buffer[byte_pos] & (1 << bit_shift(self.bit_order, bit_offset))) != 0;
             self.read_pos += 1;
             Ok(bit)
         }
@@ -284,6 +543,10 @@ pub mod bit_io {
                 return Ok(0);
             }
 
+            if self.bit_order == BitOrder::LsbFirst {
+                return self.read_bits_lsb(bits);
+            }
+
             // FAST PATH: Check if we can read whole bytes efficiently
             if self.read_pos % 8 == 0 && bits % 8 == 0 {
                 return self.read_bytes_fast(bits / 8);
@@ -296,6 +559,189 @@ pub mod bit_io {
         fn bit_pos(&self) -> usize {
             self.read_pos
         }
+
+        fn rewind(&mut self, mark: BitMark) {
+            self.read_pos = mark.0;
+        }
+
+        fn read_aligned_into(&mut self, out: &mut [u8]) -> io::Result<()> {
+            let start = self.read_pos.div_ceil(8);
+            let bytes = start.checked_add(out.len()).and_then(|end| self.buffer.get(start..end))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Buffer underflow"))?;
+            out.copy_from_slice(bytes);
+            self.read_pos = (start + out.len()) * 8;
+            Ok(())
+        }
+    }
+
+    /// MSB-first reader over a borrowed slice, for parsing in place without copying the
+    /// bytes into a [`BitBuffer`].
+    pub struct BitSlice<'a> {
+        bytes: &'a [u8],
+        read_pos: usize,
+    }
+
+    impl<'a> BitSlice<'a> {
+        pub fn new(bytes: &'a [u8]) -> Self {
+            Self { bytes, read_pos: 0 }
+        }
+
+        /// Bytes consumed so far, counting a partly read byte.
+        pub fn byte_pos(&self) -> usize {
+            self.read_pos.div_ceil(8)
+        }
+
+        /// Skips to the next byte boundary and returns the next `len` bytes in place.
+        pub fn read_aligned_bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+            let start = self.byte_pos();
+            let end = start.checked_add(len).filter(|&end| end <= self.bytes.len())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Buffer underflow"))?;
+            self.read_pos = end * 8;
+            Ok(&self.bytes[start..end])
+        }
+    }
+
+    impl BitRead for BitSlice<'_> {
+        fn read_bit(&mut self) -> io::Result<bool> {
+            self.read_bits(1).map(|bit| bit != 0)
+        }
+
+        fn read_bits(&mut self, bits: usize) -> io::Result<u64> {
+            if bits > 64 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Bits exceed 64"));
+            }
+            if self.read_pos + bits > self.bytes.len() * 8 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Buffer underflow"));
+            }
+            let mut value = 0u64;
+            let mut remaining = bits;
+            while remaining > 0 {
+                let offset = self.read_pos % 8;
+                let take = remaining.min(8 - offset);
+                let chunk = (self.bytes[self.read_pos / 8] >> (8 - offset - take)) as u64 & ((1 << take) - 1);
+                value = (value << take) | chunk;
+                self.read_pos += take;
+                remaining -= take;
+            }
+            Ok(value)
+        }
+
+        fn bit_pos(&self) -> usize {
+            self.read_pos
+        }
+
+        fn rewind(&mut self, mark: BitMark) {
+            self.read_pos = mark.0;
+        }
+
+        fn read_aligned_into(&mut self, out: &mut [u8]) -> io::Result<()> {
+            out.copy_from_slice(self.read_aligned_bytes(out.len())?);
+            Ok(())
+        }
+    }
+
+    /// MSB-first writer appending to a caller's buffer, so a header can be built in place
+    /// in front of the payload that follows it.
+    pub struct BitAppender<'a> {
+        out: &'a mut Vec<u8>,
+        bit_pos: usize,
+    }
+
+    impl<'a> BitAppender<'a> {
+        pub fn new(out: &'a mut Vec<u8>) -> Self {
+            Self { out, bit_pos: 0 }
+        }
+    }
+
+    impl BitWrite for BitAppender<'_> {
+        fn write_bit(&mut self, bit: bool) -> io::Result<()> {
+            self.write_bits(bit as u64, 1)
+        }
+
+        fn write_bits(&mut self, value: u64, bits: usize) -> io::Result<()> {
+            if bits > 64 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Bits exceed 64"));
+            }
+            let mut remaining = bits;
+            while remaining > 0 {
+                let offset = self.bit_pos % 8;
+                if offset == 0 {
+                    self.out.push(0);
+                }
+                let take = remaining.min(8 - offset);
+                let chunk = (value >> (remaining - take)) as u8 & ((1u16 << take) - 1) as u8;
+                if let Some(last) = self.out.last_mut() {
+                    *last |= chunk << (8 - offset - take);
+                }
+                self.bit_pos += take;
+                remaining -= take;
+            }
+            Ok(())
+        }
+
+        fn bit_pos(&self) -> usize {
+            self.bit_pos
+        }
+
+        fn write_aligned_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+            self.bit_pos = self.bit_pos.next_multiple_of(8) + bytes.len() * 8;
+            self.out.extend_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    // Shift of the bit at `offset` (0 = first in stream order) within its byte
+    fn bit_shift(bit_order: BitOrder, offset: usize) -> usize {
+        match bit_order {
+            BitOrder::MsbFirst => 7 - offset,
+            BitOrder::LsbFirst => offset,
+        }
+    }
+
+    // Forwarding impls so `&mut dyn BitWrite` / `&mut dyn BitRead` satisfy the generic
+    // serialization methods
+    impl<W: BitWrite + ?Sized> BitWrite for &mut W {
+        fn write_bit(&mut self, bit: bool) -> io::Result<()> {
+            (**self).write_bit(bit)
+        }
+
+        fn write_bits(&mut self, value: u64, bits: usize) -> io::Result<()> {
+            (**self).write_bits(value, bits)
+        }
+
+        fn bit_pos(&self) -> usize {
+            (**self).bit_pos()
+        }
+
+        fn bit_order(&self) -> BitOrder {
+            (**self).bit_order()
+        }
+
+        fn write_aligned_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+            (**self).write_aligned_bytes(bytes)
+        }
+    }
+
+    impl<R: BitRead + ?Sized> BitRead for &mut R {
+        fn read_bit(&mut self) -> io::Result<bool> {
+            (**self).read_bit()
+        }
+
+        fn read_bits(&mut self, bits: usize) -> io::Result<u64> {
+            (**self).read_bits(bits)
+        }
+
+        fn bit_pos(&self) -> usize {
+            (**self).bit_pos()
+        }
+
+        fn rewind(&mut self, mark: BitMark) {
+            (**self).rewind(mark)
+        }
+
+        fn read_aligned_into(&mut self, out: &mut [u8]) -> io::Result<()> {
+            (**self).read_aligned_into(out)
+        }
     }
 }
 
@@ -308,6 +754,44 @@ pub trait BitDeserialize: Sized {
     fn bit_deserialize<R: bit_io::BitRead>(reader: &mut R) -> std::io::Result<Self>;
 }
 
+/// Object-safe form of [`BitSerialize`], implemented for every `BitSerialize` type.
+///
+/// `BitSerialize` is generic over the writer so it cannot be boxed; heterogeneous queues
+/// store `Box<dyn DynBitSerialize>` instead, which is itself `BitSerialize`.
+pub trait DynBitSerialize {
+    fn dyn_bit_serialize(&self, writer: &mut dyn bit_io::BitWrite) -> std::io::Result<()>;
+}
+
+impl<T: BitSerialize> DynBitSerialize for T {
+    fn dyn_bit_serialize(&self, mut writer: &mut dyn bit_io::BitWrite) -> std::io::Result<()> {
+        self.bit_serialize(&mut writer)
+    }
+}
+
+impl BitSerialize for dyn DynBitSerialize + '_ {
+    fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.dyn_bit_serialize(writer)
+    }
+}
+
+impl BitSerialize for dyn DynBitSerialize + Send + '_ {
+    fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.dyn_bit_serialize(writer)
+    }
+}
+
+impl<T: BitSerialize + ?Sized> BitSerialize for &T {
+    fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> std::io::Result<()> {
+        (**self).bit_serialize(writer)
+    }
+}
+
+impl<T: BitSerialize + ?Sized> BitSerialize for Box<T> {
+    fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> std::io::Result<()> {
+        (**self).bit_serialize(writer)
+    }
+}
+
 pub trait ByteAlignedSerialize {
     fn byte_aligned_serialize<W: Write + WriteBytesExt>(
         &self,
@@ -491,6 +975,85 @@ impl ByteAlignedDeserialize for bool {
     }
 }
 
+// Platform-sized integers always travel as 64 bits so both ends agree on the width
+macro_rules! impl_platform_sized {
+    ($($t:ty, $wire:ty, $write:ident, $read:ident),*) => {
+        $(
+            impl BitSerialize for $t {
+                fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> std::io::Result<()> {
+                    writer.write_bits(*self as $wire as u64, 64)?;
+                    Ok(())
+                }
+            }
+            impl BitDeserialize for $t {
+                fn bit_deserialize<R: bit_io::BitRead>(reader: &mut R) -> std::io::Result<Self> {
+                    let value = reader.read_bits(64)? as $wire;
+                    <$t>::try_from(value).map_err(|_| io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Value {} does not fit in {}", value, stringify!($t)),
+                    ))
+                }
+            }
+            impl ByteAlignedSerialize for $t {
+                fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> std::io::Result<()> {
+                    writer.$write::<LittleEndian>(*self as $wire)?;
+                    Ok(())
+                }
+            }
+            impl ByteAlignedDeserialize for $t {
+                fn byte_aligned_deserialize<R: Read + ReadBytesExt>(reader: &mut R) -> std::io::Result<Self> {
+                    let value = reader.$read::<LittleEndian>()?;
+                    <$t>::try_from(value).map_err(|_| io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Value {} does not fit in {}", value, stringify!($t)),
+                    ))
+                }
+            }
+        )*
+    };
+}
+
+impl_platform_sized!(
+    usize, u64, write_u64, read_u64,
+    isize, i64, write_i64, read_i64
+);
+
+// Chars are 21 bits when bit-packed (max scalar value 0x10FFFF) and UTF-32 when byte-aligned
+pub const CHAR_BITS: usize = 21;
+
+fn char_from_u32(value: u32) -> io::Result<char> {
+    char::from_u32(value).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid char scalar value {:#x}", value),
+    ))
+}
+
+impl BitSerialize for char {
+    fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_bits(*self as u64, CHAR_BITS)?;
+        Ok(())
+    }
+}
+
+impl BitDeserialize for char {
+    fn bit_deserialize<R: bit_io::BitRead>(reader: &mut R) -> io::Result<Self> {
+        char_from_u32(reader.read_bits(CHAR_BITS)? as u32)
+    }
+}
+
+impl ByteAlignedSerialize for char {
+    fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(*self as u32)?;
+        Ok(())
+    }
+}
+
+impl ByteAlignedDeserialize for char {
+    fn byte_aligned_deserialize<R: Read + ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        char_from_u32(reader.read_u32::<LittleEndian>()?)
+    }
+}
+
 // String implementations
 impl BitSerialize for String {
     fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
@@ -558,6 +1121,94 @@ impl ByteAlignedDeserialize for String {
     }
 }
 
+// Borrowed strings and blobs: a 16-bit length as for String and Vec<u8>, then the bytes from
+// the next byte boundary, so a BitSlice over the packet can hand them out without copying
+const MAX_BORROWED_LEN: usize = 65535;
+
+fn bit_serialize_borrowable<W: bit_io::BitWrite>(bytes: &[u8], what: &str, writer: &mut W) -> io::Result<()> {
+    if bytes.len() > MAX_BORROWED_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} length {} exceeds max_len {}", what, bytes.len(), MAX_BORROWED_LEN),
+        ));
+    }
+    writer.write_bits(bytes.len() as u64, 16)?;
+    while !writer.bit_pos().is_multiple_of(8) {
+        writer.write_bit(false)?;
+    }
+    for byte in bytes {
+        writer.write_bits(*byte as u64, 8)?;
+    }
+    Ok(())
+}
+
+impl BitSerialize for str {
+    fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+        bit_serialize_borrowable(self.as_bytes(), "String", writer)
+    }
+}
+
+impl BitSerialize for [u8] {
+    fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+        bit_serialize_borrowable(self, "Blob", writer)
+    }
+}
+
+impl ByteAlignedSerialize for str {
+    fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        self.as_bytes().byte_aligned_serialize(writer)
+    }
+}
+
+impl ByteAlignedSerialize for [u8] {
+    fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(self.len() as u32)?;
+        writer.write_all(self)
+    }
+}
+
+impl<T: ByteAlignedSerialize + ?Sized> ByteAlignedSerialize for &T {
+    fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        (**self).byte_aligned_serialize(writer)
+    }
+}
+
+/// Deserialization that may borrow from the packet being read, for messages holding
+/// `&'a str` and `&'a [u8]` views instead of copies.
+///
+/// Every [`BitDeserialize`] type has it. `#[derive(NetworkSerialize)]` implements it in place
+/// of `BitDeserialize` for types with lifetime parameters.
+pub trait BitDeserializeBorrowed<'de>: Sized {
+    fn bit_deserialize_borrowed(reader: &mut bit_io::BitSlice<'de>) -> io::Result<Self>;
+
+    /// Reads a value from the start of `bytes`, e.g. a message from `Connection::receive`.
+    fn from_bytes_borrowed(bytes: &'de [u8]) -> io::Result<Self> {
+        Self::bit_deserialize_borrowed(&mut bit_io::BitSlice::new(bytes))
+    }
+}
+
+impl<'de, T: BitDeserialize> BitDeserializeBorrowed<'de> for T {
+    fn bit_deserialize_borrowed(reader: &mut bit_io::BitSlice<'de>) -> io::Result<Self> {
+        T::bit_deserialize(reader)
+    }
+}
+
+impl<'de: 'a, 'a> BitDeserializeBorrowed<'de> for &'a [u8] {
+    fn bit_deserialize_borrowed(reader: &mut bit_io::BitSlice<'de>) -> io::Result<Self> {
+        let len = bit_io::BitRead::read_bits(reader, 16)? as usize;
+        reader.read_aligned_bytes(len)
+    }
+}
+
+impl<'de: 'a, 'a> BitDeserializeBorrowed<'de> for &'a str {
+    fn bit_deserialize_borrowed(reader: &mut bit_io::BitSlice<'de>) -> io::Result<Self> {
+        let bytes = <&[u8]>::bit_deserialize_borrowed(reader)?;
+        std::str::from_utf8(bytes).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Invalid UTF-8: {}", e))
+        })
+    }
+}
+
 // Fixed-size array implementations - FIXED unused variable warnings
 macro_rules! impl_array {
     ($($n:expr),*) => {
@@ -768,6 +1419,51 @@ impl<T: ByteAlignedDeserialize> ByteAlignedDeserialize for Vec<T> {
     }
 }
 
+// #[blob] fields: bit-packed, a varint length and then the bytes copied whole from the next
+// byte boundary, so large byte payloads skip the per-element path and the 16-bit length cap.
+// Byte-aligned encoding keeps the Vec<u8> layout (u32 length, then the bytes), only faster.
+
+/// Longest `#[blob]` field without a `#[max_len]`; bounds what a corrupt length can allocate.
+pub const DEFAULT_MAX_BLOB_LEN: usize = 16 * 1024 * 1024;
+
+fn check_blob_len(len: usize, max_len: usize) -> io::Result<()> {
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Blob length {} exceeds max_len {}", len, max_len),
+        ));
+    }
+    Ok(())
+}
+
+pub fn write_blob<W: bit_io::BitWrite>(writer: &mut W, blob: &[u8], max_len: usize) -> io::Result<()> {
+    check_blob_len(blob.len(), max_len)?;
+    writer.write_varint(blob.len() as u64)?;
+    writer.write_aligned_bytes(blob)
+}
+
+pub fn read_blob<R: bit_io::BitRead>(reader: &mut R, max_len: usize) -> io::Result<Vec<u8>> {
+    let len = usize::try_from(reader.read_varint()?).unwrap_or(usize::MAX);
+    check_blob_len(len, max_len)?;
+    let mut blob = vec![0u8; len];
+    reader.read_aligned_into(&mut blob)?;
+    Ok(blob)
+}
+
+pub fn byte_aligned_write_blob<W: Write>(writer: &mut W, blob: &[u8], max_len: usize) -> io::Result<()> {
+    check_blob_len(blob.len(), max_len)?;
+    writer.write_u32::<LittleEndian>(blob.len() as u32)?;
+    writer.write_all(blob)
+}
+
+pub fn byte_aligned_read_blob<R: Read>(reader: &mut R, max_len: usize) -> io::Result<Vec<u8>> {
+    let len = reader.read_u32::<LittleEndian>()? as usize;
+    check_blob_len(len, max_len)?;
+    let mut blob = vec![0u8; len];
+    reader.read_exact(&mut blob)?;
+    Ok(blob)
+}
+
 // Option<T> implementations
 impl<T: BitSerialize> BitSerialize for Option<T> {
     fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> std::io::Result<()> {
@@ -819,4 +1515,336 @@ impl<T: ByteAlignedDeserialize> ByteAlignedDeserialize for Option<T> {
             Ok(None)
         }
     }
-}
\ No newline at end of file
+}
+
+// Maps and sets are written in ascending key order whatever the container, so equal
+// contents always encode to the same bits and state hashes agree across peers. HashMap and
+// HashSet sort on the way out and need `Ord` keys; decoders reject keys that are out of
+// order or repeated, so every value has exactly one encoding.
+mod map_impls {
+    use super::*;
+    use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+    use std::hash::{BuildHasher, Hash};
+
+    const MAX_BIT_LEN: usize = 65535; // 16 bits, as for Vec<T>
+
+    fn too_long(len: usize) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Map length {} exceeds max_len {}", len, MAX_BIT_LEN))
+    }
+
+    fn unordered() -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, "Map keys out of order or repeated")
+    }
+
+    fn bit_serialize_entries<'a, W, K, V>(len: usize, entries: impl Iterator<Item = (&'a K, &'a V)>, writer: &mut W) -> io::Result<()>
+    where
+        W: bit_io::BitWrite,
+        K: BitSerialize + 'a,
+        V: BitSerialize + 'a,
+    {
+        if len > MAX_BIT_LEN {
+            return Err(too_long(len));
+        }
+        writer.write_bits(len as u64, 16)?;
+        for (key, value) in entries {
+            key.bit_serialize(writer)?;
+            value.bit_serialize(writer)?;
+        }
+        Ok(())
+    }
+
+    fn bit_deserialize_entries<R, K, V>(reader: &mut R, mut insert: impl FnMut(K, V)) -> io::Result<()>
+    where
+        R: bit_io::BitRead,
+        K: BitDeserialize + Ord + Clone,
+        V: BitDeserialize,
+    {
+        let len = reader.read_bits(16)? as usize;
+        let mut previous: Option<K> = None;
+        for _ in 0..len {
+            let key = K::bit_deserialize(reader)?;
+            if previous.as_ref().is_some_and(|previous| *previous >= key) {
+                return Err(unordered());
+            }
+            previous = Some(key.clone());
+            insert(key, V::bit_deserialize(reader)?);
+        }
+        Ok(())
+    }
+
+    fn byte_aligned_serialize_entries<'a, W, K, V>(len: usize, entries: impl Iterator<Item = (&'a K, &'a V)>, writer: &mut W) -> io::Result<()>
+    where
+        W: Write + WriteBytesExt,
+        K: ByteAlignedSerialize + 'a,
+        V: ByteAlignedSerialize + 'a,
+    {
+        writer.write_u32::<LittleEndian>(len as u32)?;
+        for (key, value) in entries {
+            key.byte_aligned_serialize(writer)?;
+            value.byte_aligned_serialize(writer)?;
+        }
+        Ok(())
+    }
+
+    fn byte_aligned_deserialize_entries<R, K, V>(reader: &mut R, mut insert: impl FnMut(K, V)) -> io::Result<()>
+    where
+        R: Read + ReadBytesExt,
+        K: ByteAlignedDeserialize + Ord + Clone,
+        V: ByteAlignedDeserialize,
+    {
+        let len = reader.read_u32::<LittleEndian>()? as usize;
+        let mut previous: Option<K> = None;
+        for _ in 0..len {
+            let key = K::byte_aligned_deserialize(reader)?;
+            if previous.as_ref().is_some_and(|previous| *previous >= key) {
+                return Err(unordered());
+            }
+            previous = Some(key.clone());
+            insert(key, V::byte_aligned_deserialize(reader)?);
+        }
+        Ok(())
+    }
+
+    fn sorted<K: Ord, V, S>(map: &HashMap<K, V, S>) -> Vec<(&K, &V)> {
+        let mut entries: Vec<_> = map.iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+
+    fn sorted_set<T: Ord, S>(set: &HashSet<T, S>) -> Vec<(&T, &())> {
+        let mut entries: Vec<_> = set.iter().map(|value| (value, &())).collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+
+    impl<K: BitSerialize, V: BitSerialize> BitSerialize for BTreeMap<K, V> {
+        fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+            bit_serialize_entries(self.len(), self.iter(), writer)
+        }
+    }
+
+    impl<K: BitDeserialize + Ord + Clone, V: BitDeserialize> BitDeserialize for BTreeMap<K, V> {
+        fn bit_deserialize<R: bit_io::BitRead>(reader: &mut R) -> io::Result<Self> {
+            let mut map = BTreeMap::new();
+            bit_deserialize_entries(reader, |key, value| { map.insert(key, value); })?;
+            Ok(map)
+        }
+    }
+
+    impl<K: ByteAlignedSerialize, V: ByteAlignedSerialize> ByteAlignedSerialize for BTreeMap<K, V> {
+        fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+            byte_aligned_serialize_entries(self.len(), self.iter(), writer)
+        }
+    }
+
+    impl<K: ByteAlignedDeserialize + Ord + Clone, V: ByteAlignedDeserialize> ByteAlignedDeserialize for BTreeMap<K, V> {
+        fn byte_aligned_deserialize<R: Read + ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+            let mut map = BTreeMap::new();
+            byte_aligned_deserialize_entries(reader, |key, value| { map.insert(key, value); })?;
+            Ok(map)
+        }
+    }
+
+    impl<K: BitSerialize + Ord, V: BitSerialize, S> BitSerialize for HashMap<K, V, S> {
+        fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+            bit_serialize_entries(self.len(), sorted(self).into_iter(), writer)
+        }
+    }
+
+    impl<K, V, S> BitDeserialize for HashMap<K, V, S>
+    where
+        K: BitDeserialize + Ord + Clone + Hash,
+        V: BitDeserialize,
+        S: BuildHasher + Default,
+    {
+        fn bit_deserialize<R: bit_io::BitRead>(reader: &mut R) -> io::Result<Self> {
+            let mut map = HashMap::default();
+            bit_deserialize_entries(reader, |key, value| { map.insert(key, value); })?;
+            Ok(map)
+        }
+    }
+
+    impl<K: ByteAlignedSerialize + Ord, V: ByteAlignedSerialize, S> ByteAlignedSerialize for HashMap<K, V, S> {
+        fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+            byte_aligned_serialize_entries(self.len(), sorted(self).into_iter(), writer)
+        }
+    }
+
+    impl<K, V, S> ByteAlignedDeserialize for HashMap<K, V, S>
+    where
+        K: ByteAlignedDeserialize + Ord + Clone + Hash,
+        V: ByteAlignedDeserialize,
+        S: BuildHasher + Default,
+    {
+        fn byte_aligned_deserialize<R: Read + ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+            let mut map = HashMap::default();
+            byte_aligned_deserialize_entries(reader, |key, value| { map.insert(key, value); })?;
+            Ok(map)
+        }
+    }
+
+    // Sets are maps with nothing after each key; `()` takes no bits
+    impl BitSerialize for () {
+        fn bit_serialize<W: bit_io::BitWrite>(&self, _writer: &mut W) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl BitDeserialize for () {
+        fn bit_deserialize<R: bit_io::BitRead>(_reader: &mut R) -> io::Result<Self> {
+            Ok(())
+        }
+    }
+
+    impl ByteAlignedSerialize for () {
+        fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, _writer: &mut W) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ByteAlignedDeserialize for () {
+        fn byte_aligned_deserialize<R: Read + ReadBytesExt>(_reader: &mut R) -> io::Result<Self> {
+            Ok(())
+        }
+    }
+
+    impl<T: BitSerialize> BitSerialize for BTreeSet<T> {
+        fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+            bit_serialize_entries(self.len(), self.iter().map(|value| (value, &())), writer)
+        }
+    }
+
+    impl<T: BitDeserialize + Ord + Clone> BitDeserialize for BTreeSet<T> {
+        fn bit_deserialize<R: bit_io::BitRead>(reader: &mut R) -> io::Result<Self> {
+            let mut set = BTreeSet::new();
+            bit_deserialize_entries(reader, |value, ()| { set.insert(value); })?;
+            Ok(set)
+        }
+    }
+
+    impl<T: ByteAlignedSerialize> ByteAlignedSerialize for BTreeSet<T> {
+        fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+            byte_aligned_serialize_entries(self.len(), self.iter().map(|value| (value, &())), writer)
+        }
+    }
+
+    impl<T: ByteAlignedDeserialize + Ord + Clone> ByteAlignedDeserialize for BTreeSet<T> {
+        fn byte_aligned_deserialize<R: Read + ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+            let mut set = BTreeSet::new();
+            byte_aligned_deserialize_entries(reader, |value, ()| { set.insert(value); })?;
+            Ok(set)
+        }
+    }
+
+    impl<T: BitSerialize + Ord, S> BitSerialize for HashSet<T, S> {
+        fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+            bit_serialize_entries(self.len(), sorted_set(self).into_iter(), writer)
+        }
+    }
+
+    impl<T: BitDeserialize + Ord + Clone + Hash, S: BuildHasher + Default> BitDeserialize for HashSet<T, S> {
+        fn bit_deserialize<R: bit_io::BitRead>(reader: &mut R) -> io::Result<Self> {
+            let mut set = HashSet::default();
+            bit_deserialize_entries(reader, |value, ()| { set.insert(value); })?;
+            Ok(set)
+        }
+    }
+
+    impl<T: ByteAlignedSerialize + Ord, S> ByteAlignedSerialize for HashSet<T, S> {
+        fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+            byte_aligned_serialize_entries(self.len(), sorted_set(self).into_iter(), writer)
+        }
+    }
+
+    impl<T: ByteAlignedDeserialize + Ord + Clone + Hash, S: BuildHasher + Default> ByteAlignedDeserialize for HashSet<T, S> {
+        fn byte_aligned_deserialize<R: Read + ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+            let mut set = HashSet::default();
+            byte_aligned_deserialize_entries(reader, |value, ()| { set.insert(value); })?;
+            Ok(set)
+        }
+    }
+}
+
+// bytes::Bytes and BytesMut travel as blobs in the same layout as Vec<u8>, so either end can
+// use either type
+#[cfg(feature = "bytes")]
+mod bytes_impls {
+    use super::*;
+    use bytes::{Bytes, BytesMut};
+
+    const MAX_BIT_LEN: usize = 65535; // 16 bits, as for Vec<u8>
+
+    fn bit_serialize_blob<W: bit_io::BitWrite>(blob: &[u8], writer: &mut W) -> io::Result<()> {
+        if blob.len() > MAX_BIT_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Blob length {} exceeds max_len {}", blob.len(), MAX_BIT_LEN),
+            ));
+        }
+        writer.write_bits(blob.len() as u64, 16)?;
+        for byte in blob {
+            writer.write_bits(*byte as u64, 8)?;
+        }
+        Ok(())
+    }
+
+    fn byte_aligned_serialize_blob<W: Write + WriteBytesExt>(blob: &[u8], writer: &mut W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(blob.len() as u32)?;
+        writer.write_all(blob)
+    }
+
+    fn byte_aligned_deserialize_blob<R: Read + ReadBytesExt>(reader: &mut R) -> io::Result<Vec<u8>> {
+        let len = reader.read_u32::<LittleEndian>()? as usize;
+        let mut blob = vec![0u8; len];
+        reader.read_exact(&mut blob)?;
+        Ok(blob)
+    }
+
+    impl BitSerialize for Bytes {
+        fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+            bit_serialize_blob(self, writer)
+        }
+    }
+
+    impl BitDeserialize for Bytes {
+        fn bit_deserialize<R: bit_io::BitRead>(reader: &mut R) -> io::Result<Self> {
+            Vec::<u8>::bit_deserialize(reader).map(Bytes::from)
+        }
+    }
+
+    impl ByteAlignedSerialize for Bytes {
+        fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+            byte_aligned_serialize_blob(self, writer)
+        }
+    }
+
+    impl ByteAlignedDeserialize for Bytes {
+        fn byte_aligned_deserialize<R: Read + ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+            byte_aligned_deserialize_blob(reader).map(Bytes::from)
+        }
+    }
+
+    impl BitSerialize for BytesMut {
+        fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+            bit_serialize_blob(self, writer)
+        }
+    }
+
+    impl BitDeserialize for BytesMut {
+        fn bit_deserialize<R: bit_io::BitRead>(reader: &mut R) -> io::Result<Self> {
+            Vec::<u8>::bit_deserialize(reader).map(|blob| BytesMut::from(blob.as_slice()))
+        }
+    }
+
+    impl ByteAlignedSerialize for BytesMut {
+        fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+            byte_aligned_serialize_blob(self, writer)
+        }
+    }
+
+    impl ByteAlignedDeserialize for BytesMut {
+        fn byte_aligned_deserialize<R: Read + ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+            byte_aligned_deserialize_blob(reader).map(|blob| BytesMut::from(blob.as_slice()))
+        }
+    }
+}
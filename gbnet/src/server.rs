@@ -0,0 +1,1245 @@
+// server.rs - Multi-client server built on top of Connection
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::{
+    NetworkConfig,
+    config::{ChannelMismatchPolicy, ConnectionRole, PayloadMode, ReceiveOverflow, SendErrorPolicy},
+    packet::{HeaderExtensions, Packet, PacketHeader, PacketType, capability, deny_reason, disconnect_reason, header_extension},
+    socket::{SendErrorCounts, UdpSocket, SocketError},
+    simulator::NetworkSimulator,
+    rng::{stream, DeterministicRng},
+    connection::{Connection, ConnectionError},
+    filter::{AddressFilter, PacketFilter, RevocationCheck, ConnectionRequestLimiter},
+    handler::ServerHandler,
+    crypto::{KeyExchange, RequestCookies, ServerIdentity, SessionKeys},
+    ticket::{parse_resume_request, TicketStore},
+    token::{ConnectToken, TokenRegistry},
+    room::{Room, RoomId},
+    channel::{BatchItems, ChannelMismatch},
+    serialize::{BitDeserialize, BitSerialize},
+    ping::{self, PingReply},
+    instant::wall_clock,
+    stats::{ServerStatsReport, StatsSnapshot},
+    rtt::RttHistogram,
+    legacy::{self, LegacyError, LegacyMessage},
+    decode_pool::DecodePool,
+};
+
+/// Events raised by the server, drained with [`Server::poll_event`] or handed to a [`ServerHandler`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerEvent {
+    ClientConnected(SocketAddr),
+    ClientResumed(SocketAddr),
+    ClientDisconnected(SocketAddr, u8),
+    /// The client paused with `Connection::pause`; its slot is held for `pause_grace_period`
+    ClientPaused(SocketAddr),
+    /// The client is back from a pause
+    ClientUnpaused(SocketAddr),
+    /// The client's channels are configured differently; raised after `ClientConnected`
+    /// under `ChannelMismatchPolicy::Warn`
+    ChannelMismatch(SocketAddr, ChannelMismatch),
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ServerStats {
+    pub packets_received: u64,
+    pub packets_filtered: u64,
+    pub invalid_packets: u64,
+    pub connection_requests_throttled: u64,
+    /// Packets from observers on `player_only` channels, dropped before processing
+    pub observer_packets_rejected: u64,
+    /// Updates that stopped reading because the receive budget was spent
+    pub receive_budget_exhausted: u64,
+    /// Packets dropped by the receive budget, per client or by `ReceiveOverflow::Drop`
+    pub packets_over_budget: u64,
+    /// Connections replaced because their client restarted and connected again
+    pub stale_sessions_replaced: u64,
+    /// `STALE_SESSION` disconnects sent to addresses with no connection, e.g. clients of a previous run
+    pub stale_session_replies: u64,
+    /// Connections refused or disconnected because their credentials were revoked
+    pub connections_revoked: u64,
+    /// Connection requests ignored for a missing, invalid, expired or reused connect token
+    pub connect_tokens_rejected: u64,
+    /// Requests put in the join queue because the server was full
+    pub join_queue_entered: u64,
+    /// Queued requests given a slot and challenged
+    pub join_queue_promoted: u64,
+    /// ICMP unreachable reports read and skipped; the socket does not say which client they were for
+    pub peer_unreachable: u64,
+    /// Failed sends of handshake replies, by kind; each client's own are in its connection's stats
+    pub send_errors: SendErrorCounts,
+    /// Clients disconnected with `CONNECTION_ERROR` after their connection returned an error
+    pub connection_errors: u64,
+}
+
+/// Queues one message on many connections, remembering the first failure.
+#[derive(Debug)]
+struct FanOut {
+    sent: u64,
+    result: Result<(), ConnectionError>,
+}
+
+impl FanOut {
+    fn new() -> Self {
+        Self { sent: 0, result: Ok(()) }
+    }
+
+    fn send(&mut self, connection: Option<&mut Connection>, channel_id: u8, data: &[u8], reliable: bool) {
+        let outcome = match connection {
+            Some(connection) => connection.send(channel_id, data, reliable),
+            None => Err(ConnectionError::NotConnected),
+        };
+        match outcome {
+            Ok(()) => self.sent += 1,
+            Err(e) if self.result.is_ok() => self.result = Err(e),
+            Err(_) => {}
+        }
+    }
+}
+
+// A request waiting in the join queue; only the key exchange payload and connect token are
+// kept until promotion
+#[derive(Debug)]
+struct QueuedRequest {
+    addr: SocketAddr,
+    payload: Vec<u8>,
+    token: Option<ConnectToken>,
+    last_seen: Instant,
+}
+
+#[derive(Debug)]
+struct PendingConnection {
+    server_salt: u64,
+    request_time: Instant,
+    challenge_payload: Vec<u8>,
+    session_keys: Option<SessionKeys>,
+    token: Option<ConnectToken>,
+}
+
+pub struct Server {
+    config: NetworkConfig,
+    socket: UdpSocket,
+    local_addr: SocketAddr,
+
+    // Clients
+    connections: HashMap<SocketAddr, Connection>,
+    pending: HashMap<SocketAddr, PendingConnection>,
+    join_queue: VecDeque<QueuedRequest>,
+    last_queue_update: Instant,
+    events: VecDeque<ServerEvent>,
+    handler: Option<Box<dyn ServerHandler>>,
+    // Set when the handler is replaced or cleared while taken out for a dispatch
+    handler_replaced: bool,
+
+    // Filtering
+    address_filter: AddressFilter,
+    packet_filter: Option<Box<dyn PacketFilter>>,
+    revocation_check: Option<Box<dyn RevocationCheck>>,
+    request_limiter: ConnectionRequestLimiter,
+    request_cookies: RequestCookies,
+
+    // Encryption
+    identity: Option<ServerIdentity>,
+
+    // Session resumption
+    tickets: Option<TicketStore>,
+
+    // Connect tokens
+    connect_tokens: TokenRegistry,
+
+    // Salts and session seeds, seeded from `NetworkConfig::random_seed` when set
+    rng: DeterministicRng,
+
+    // Rooms
+    rooms: HashMap<RoomId, Room>,
+    client_rooms: HashMap<SocketAddr, RoomId>,
+    next_room_id: RoomId,
+
+    // Stats
+    stats: ServerStats,
+}
+
+impl Server {
+    /// Binds a server socket to the given address.
+    pub fn bind(addr: SocketAddr, config: NetworkConfig) -> Result<Self, SocketError> {
+        Self::with_socket(UdpSocket::bind(addr)?, config)
+    }
+
+    /// Serves on an already bound socket, e.g. one on a [`MemoryNetwork`](crate::socket::MemoryNetwork).
+    pub fn with_socket(mut socket: UdpSocket, config: NetworkConfig) -> Result<Self, SocketError> {
+        if config.kernel_timestamps {
+            socket.enable_kernel_timestamps();
+        }
+        if config.iocp {
+            socket.enable_iocp();
+        }
+        socket.set_clock(config.clock.clone());
+        let local_addr = socket.local_addr()?;
+        let request_limiter = ConnectionRequestLimiter::new(
+            config.connection_request_rate_limit,
+            config.connection_request_rate_window,
+            config.connection_request_limiter_width,
+        );
+
+        let config_tickets = config.resumption_ticket_lifetime
+            .map(|lifetime| TicketStore::new(lifetime, config.rng(stream::TICKETS)));
+        let rng = config.rng(stream::SERVER);
+        let now = config.clock.now();
+
+        Ok(Self {
+            config,
+            socket,
+            local_addr,
+            connections: HashMap::new(),
+            pending: HashMap::new(),
+            join_queue: VecDeque::new(),
+            last_queue_update: now,
+            events: VecDeque::new(),
+            handler: None,
+            handler_replaced: false,
+            address_filter: AddressFilter::new(),
+            packet_filter: None,
+            revocation_check: None,
+            request_limiter,
+            request_cookies: RequestCookies::new(now),
+            identity: None,
+            tickets: config_tickets,
+            connect_tokens: TokenRegistry::default(),
+            rng,
+            rooms: HashMap::new(),
+            client_rooms: HashMap::new(),
+            next_room_id: 0,
+            stats: ServerStats::default(),
+        })
+    }
+
+    /// Receives pending packets, runs the handshake and updates every client connection.
+    ///
+    /// Client packets are only written here when `auto_flush` is enabled; otherwise
+    /// call [`Server::flush_all`] once per tick. A client whose connection fails is
+    /// disconnected with `CONNECTION_ERROR` and the rest are still updated.
+    pub fn update(&mut self) -> Result<(), ConnectionError> {
+        self.receive_packets()?;
+
+        let now = self.config.clock.now();
+        let request_timeout = self.config.connection_request_timeout;
+        self.pending.retain(|_, pending| now.duration_since(pending.request_time) <= request_timeout);
+        self.request_cookies.rotate(now, request_timeout);
+        if let Some(tickets) = self.tickets.as_mut() {
+            tickets.remove_expired(now);
+        }
+
+        // One client's failure is kept to that client; a socket error under
+        // `SendErrorPolicy::Fail` is returned once every client has been serviced
+        let mut closed = Vec::new();
+        let mut failed = Vec::new();
+        let mut socket_error = None;
+        for (addr, connection) in self.connections.iter_mut() {
+            let result = match connection.update_state(now) {
+                Ok(()) if self.config.auto_flush => connection.flush(&mut self.socket),
+                Ok(()) => Ok(()),
+                Err(ConnectionError::Timeout) => {
+                    closed.push((*addr, disconnect_reason::TIMEOUT));
+                    continue;
+                }
+                Err(ConnectionError::MemoryLimitExceeded) => {
+                    // Let the client know why before dropping it
+                    closed.push((*addr, disconnect_reason::MEMORY_LIMIT));
+                    connection.process_send_queue(&mut self.socket)
+                }
+                Err(ConnectionError::SlowConsumer) => {
+                    closed.push((*addr, disconnect_reason::SLOW_CONSUMER));
+                    connection.process_send_queue(&mut self.socket)
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {}
+                Err(e @ ConnectionError::SocketError(_)) => {
+                    socket_error.get_or_insert(e);
+                }
+                Err(_) => failed.push(*addr),
+            }
+        }
+
+        for (addr, reason) in closed {
+            self.remove_connection(addr, reason);
+        }
+        for addr in failed {
+            self.fail_connection(addr);
+        }
+        self.update_join_queue(now)?;
+
+        self.dispatch_to_handler();
+        socket_error.map_or(Ok(()), Err)
+    }
+
+    /// Disconnects a client whose connection returned an error, telling it why on a best-effort
+    /// basis; if the notice is lost, the client times out.
+    fn fail_connection(&mut self, addr: SocketAddr) {
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return;
+        };
+        self.stats.connection_errors += 1;
+        if connection.disconnect(disconnect_reason::CONNECTION_ERROR).is_ok() {
+            let _ = connection.process_send_queue(&mut self.socket);
+        }
+        self.remove_connection(addr, disconnect_reason::CONNECTION_ERROR);
+    }
+
+    /// Hands queued events, then received messages, to the installed handler until both run dry.
+    fn dispatch_to_handler(&mut self) {
+        let Some(mut handler) = self.handler.take() else {
+            return;
+        };
+        self.handler_replaced = false;
+        loop {
+            while let Some(event) = self.events.pop_front() {
+                match event {
+                    ServerEvent::ClientConnected(addr) => handler.on_client_connected(self, addr),
+                    ServerEvent::ClientResumed(addr) => handler.on_client_resumed(self, addr),
+                    ServerEvent::ClientDisconnected(addr, reason) => handler.on_client_disconnected(self, addr, reason),
+                    ServerEvent::ClientPaused(addr) => handler.on_client_paused(self, addr),
+                    ServerEvent::ClientUnpaused(addr) => handler.on_client_unpaused(self, addr),
+                    ServerEvent::ChannelMismatch(addr, mismatch) => handler.on_channel_mismatch(self, addr, mismatch),
+                }
+            }
+            let mut delivered = false;
+            let addrs: Vec<SocketAddr> = self.connections.keys().copied().collect();
+            for addr in addrs {
+                for channel_id in 0..self.config.max_channels as u8 {
+                    while let Some(message) = self.receive(addr, channel_id) {
+                        handler.on_message(self, addr, channel_id, message);
+                        delivered = true;
+                    }
+                }
+            }
+            if !delivered && self.events.is_empty() {
+                break;
+            }
+        }
+        if !self.handler_replaced {
+            self.handler = Some(handler);
+        }
+    }
+
+    /// Sleeps until a datagram arrives, a connection needs servicing (resend, keepalive,
+    /// timeout) or `timeout` passes, whichever is first.
+    ///
+    /// Call before [`Server::update`] in a headless loop instead of sleeping a fixed tick.
+    /// Returns whether a datagram is ready.
+    pub fn wait(&mut self, timeout: Duration) -> Result<bool, ConnectionError> {
+        let wait = self.service_wait(timeout);
+        Ok(self.socket.wait_readable(wait)?)
+    }
+
+    /// Waits until a datagram arrives, a connection needs servicing or `timeout` passes, then
+    /// updates; the async counterpart of [`Server::wait`] followed by [`Server::update`].
+    ///
+    /// Await it in a loop on a socket from [`AsyncUdpSocket`](crate::socket::AsyncUdpSocket).
+    #[cfg(feature = "tokio")]
+    pub async fn poll(&mut self, timeout: Duration) -> Result<(), ConnectionError> {
+        let wait = self.service_wait(timeout);
+        self.socket.readable(wait).await?;
+        self.update()
+    }
+
+    // Time until the earliest resend, keepalive, timeout or queue update, at most `timeout`
+    fn service_wait(&self, timeout: Duration) -> Duration {
+        let now = self.config.clock.now();
+        let mut deadline = now + timeout;
+        for connection in self.connections.values() {
+            if let Some(next) = connection.next_service_time() {
+                deadline = deadline.min(next);
+            }
+        }
+        for pending in self.pending.values() {
+            deadline = deadline.min(pending.request_time + self.config.connection_request_timeout);
+        }
+        if !self.join_queue.is_empty() {
+            deadline = deadline.min(self.last_queue_update + self.config.join_queue_update_interval);
+        }
+        deadline.saturating_duration_since(now)
+    }
+
+    /// Assembles and sends queued messages for every client.
+    ///
+    /// Call once per tick when `auto_flush` is disabled.
+    pub fn flush_all(&mut self) -> Result<(), ConnectionError> {
+        for connection in self.connections.values_mut() {
+            connection.flush(&mut self.socket)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the next pending server event, if any.
+    pub fn poll_event(&mut self) -> Option<ServerEvent> {
+        self.events.pop_front()
+    }
+
+    /// Sends data to a connected client on a specific channel.
+    pub fn send(&mut self, addr: SocketAddr, channel_id: u8, data: &[u8], reliable: bool) -> Result<(), ConnectionError> {
+        match self.connections.get_mut(&addr) {
+            Some(connection) => connection.send(channel_id, data, reliable),
+            None => Err(ConnectionError::NotConnected),
+        }
+    }
+
+    /// Sends a `bytes::Bytes` payload to a client; see [`Connection::send_bytes`].
+    #[cfg(feature = "bytes")]
+    pub fn send_bytes(&mut self, addr: SocketAddr, channel_id: u8, data: bytes::Bytes, reliable: bool) -> Result<(), ConnectionError> {
+        match self.connections.get_mut(&addr) {
+            Some(connection) => connection.send_bytes(channel_id, data, reliable),
+            None => Err(ConnectionError::NotConnected),
+        }
+    }
+
+    /// Sends many messages of one type to a client; see [`Connection::send_batch`].
+    pub fn send_batch<I>(&mut self, addr: SocketAddr, channel_id: u8, items: I, reliable: bool) -> Result<usize, ConnectionError>
+    where
+        I: IntoIterator,
+        I::Item: BitSerialize,
+    {
+        match self.connections.get_mut(&addr) {
+            Some(connection) => connection.send_batch(channel_id, items, reliable),
+            None => Err(ConnectionError::NotConnected),
+        }
+    }
+
+    /// Sends a legacy-encoded message to a client; see [`Connection::send_legacy`].
+    pub fn send_legacy<T: LegacyMessage>(&mut self, addr: SocketAddr, channel_id: u8, message: &T, reliable: bool) -> Result<(), ConnectionError> {
+        match self.connections.get_mut(&addr) {
+            Some(connection) => connection.send_legacy(channel_id, message, reliable),
+            None => Err(ConnectionError::NotConnected),
+        }
+    }
+
+    /// Sends data to a client at a future time; see [`Connection::send_at`].
+    pub fn send_at(&mut self, addr: SocketAddr, channel_id: u8, data: &[u8], at: Instant, reliable: bool) -> Result<(), ConnectionError> {
+        match self.connections.get_mut(&addr) {
+            Some(connection) => connection.send_at(channel_id, data, at, reliable),
+            None => Err(ConnectionError::NotConnected),
+        }
+    }
+
+    /// Sends data in a keyed slot to a client; see [`Connection::send_keyed`].
+    pub fn send_keyed(&mut self, addr: SocketAddr, channel_id: u8, key: u32, data: &[u8], reliable: bool) -> Result<(), ConnectionError> {
+        match self.connections.get_mut(&addr) {
+            Some(connection) => connection.send_keyed(channel_id, key, data, reliable),
+            None => Err(ConnectionError::NotConnected),
+        }
+    }
+
+    /// Sends a new version of keyed state to a client; see [`Connection::send_latest`].
+    pub fn send_latest(&mut self, addr: SocketAddr, channel_id: u8, key: u32, data: &[u8]) -> Result<(), ConnectionError> {
+        match self.connections.get_mut(&addr) {
+            Some(connection) => connection.send_latest(channel_id, key, data),
+            None => Err(ConnectionError::NotConnected),
+        }
+    }
+
+    /// Streams a full state baseline to a client that joined mid-match; see [`Connection::send_join_snapshot`].
+    pub fn send_join_snapshot(&mut self, addr: SocketAddr, baseline: Vec<u8>) -> Result<(), ConnectionError> {
+        match self.connections.get_mut(&addr) {
+            Some(connection) => connection.send_join_snapshot(baseline),
+            None => Err(ConnectionError::NotConnected),
+        }
+    }
+
+    /// Receives data from a connected client on a specific channel.
+    pub fn receive(&mut self, addr: SocketAddr, channel_id: u8) -> Option<Vec<u8>> {
+        let message = self.connections.get_mut(&addr)?.receive(channel_id)?;
+        if let Some(room) = self.client_rooms.get(&addr).and_then(|id| self.rooms.get_mut(id)) {
+            room.stats_mut().messages_received += 1;
+            room.stats_mut().bytes_received += message.len() as u64;
+        }
+        Some(message)
+    }
+
+    /// Hands every message waiting on `channel_id`, from every client, to a decode pool and
+    /// returns how many; the results come back from [`DecodePool::poll`].
+    pub fn decode_received<T: Send + 'static>(&mut self, channel_id: u8, pool: &mut DecodePool<T>) -> usize {
+        let clients: Vec<SocketAddr> = self.connections.keys().copied().collect();
+        let mut submitted = 0;
+        for addr in clients {
+            while let Some(message) = self.receive(addr, channel_id) {
+                pool.submit(addr, channel_id, message);
+                submitted += 1;
+            }
+        }
+        submitted
+    }
+
+    /// Receives the next message from a client as `bytes::Bytes`, without copying it.
+    #[cfg(feature = "bytes")]
+    pub fn receive_bytes(&mut self, addr: SocketAddr, channel_id: u8) -> Option<bytes::Bytes> {
+        self.receive(addr, channel_id).map(bytes::Bytes::from)
+    }
+
+    /// Receives a batch message from a client; see [`Connection::receive_batch`].
+    pub fn receive_batch<T: BitDeserialize>(&mut self, addr: SocketAddr, channel_id: u8) -> Option<BatchItems<T>> {
+        self.receive(addr, channel_id).and_then(BatchItems::decode)
+    }
+
+    /// Receives a legacy-encoded message from a client; see [`Connection::receive_legacy`].
+    pub fn receive_legacy<T: LegacyMessage>(&mut self, addr: SocketAddr, channel_id: u8) -> Option<Result<T, LegacyError>> {
+        if self.connections.get(&addr)?.payload_mode(channel_id)? != PayloadMode::Legacy {
+            return None;
+        }
+        self.receive(addr, channel_id).map(|frame| legacy::decode_frame(&frame))
+    }
+
+    /// Creates an empty room.
+    pub fn create_room(&mut self) -> RoomId {
+        let id = self.next_room_id;
+        self.next_room_id = self.next_room_id.wrapping_add(1);
+        self.rooms.insert(id, Room::new(id));
+        id
+    }
+
+    /// Removes a room; its members stay connected without a room.
+    pub fn remove_room(&mut self, id: RoomId) -> Option<Room> {
+        let room = self.rooms.remove(&id)?;
+        for addr in room.members() {
+            self.client_rooms.remove(addr);
+        }
+        Some(room)
+    }
+
+    /// Moves a connected client into a room, leaving its current one.
+    pub fn join_room(&mut self, addr: SocketAddr, id: RoomId) -> Result<(), ConnectionError> {
+        if !self.connections.contains_key(&addr) {
+            return Err(ConnectionError::NotConnected);
+        }
+        if !self.rooms.contains_key(&id) {
+            return Err(ConnectionError::RoomNotFound);
+        }
+        self.leave_room(addr);
+        self.rooms.get_mut(&id).unwrap().insert(addr);
+        self.client_rooms.insert(addr, id);
+        Ok(())
+    }
+
+    /// Takes a client out of its room, returning the room it left.
+    pub fn leave_room(&mut self, addr: SocketAddr) -> Option<RoomId> {
+        let id = self.client_rooms.remove(&addr)?;
+        if let Some(room) = self.rooms.get_mut(&id) {
+            room.remove(addr);
+        }
+        Some(id)
+    }
+
+    pub fn room(&self, id: RoomId) -> Option<&Room> {
+        self.rooms.get(&id)
+    }
+
+    pub fn rooms(&self) -> impl Iterator<Item = &Room> {
+        self.rooms.values()
+    }
+
+    /// Returns the room a client is in, if any.
+    pub fn room_of(&self, addr: SocketAddr) -> Option<RoomId> {
+        self.client_rooms.get(&addr).copied()
+    }
+
+    /// Sends data to every member of a room.
+    ///
+    /// Every member is attempted; the first failure is returned afterwards.
+    pub fn broadcast_room(&mut self, id: RoomId, channel_id: u8, data: &[u8], reliable: bool) -> Result<(), ConnectionError> {
+        let room = self.rooms.get_mut(&id).ok_or(ConnectionError::RoomNotFound)?;
+        let mut fan_out = FanOut::new();
+        for addr in room.members() {
+            fan_out.send(self.connections.get_mut(addr), channel_id, data, reliable);
+        }
+        room.stats_mut().messages_sent += fan_out.sent;
+        room.stats_mut().bytes_sent += fan_out.sent * data.len() as u64;
+        fan_out.result
+    }
+
+    /// Sends data to every connected client.
+    ///
+    /// Every client is attempted even if some fail (e.g. a full channel buffer);
+    /// the first failure is returned afterwards.
+    pub fn broadcast(&mut self, channel_id: u8, data: &[u8], reliable: bool) -> Result<(), ConnectionError> {
+        self.broadcast_except(&[], channel_id, data, reliable)
+    }
+
+    /// Sends data to every connected client not in `except`.
+    pub fn broadcast_except(&mut self, except: &[SocketAddr], channel_id: u8, data: &[u8], reliable: bool) -> Result<(), ConnectionError> {
+        let mut fan_out = FanOut::new();
+        for (addr, connection) in self.connections.iter_mut() {
+            if !except.contains(addr) {
+                fan_out.send(Some(connection), channel_id, data, reliable);
+            }
+        }
+        fan_out.result
+    }
+
+    /// Sends data to each listed client; addresses that are not connected yield `NotConnected`.
+    pub fn send_to_many(&mut self, addrs: &[SocketAddr], channel_id: u8, data: &[u8], reliable: bool) -> Result<(), ConnectionError> {
+        let mut fan_out = FanOut::new();
+        for addr in addrs {
+            fan_out.send(self.connections.get_mut(addr), channel_id, data, reliable);
+        }
+        fan_out.result
+    }
+
+    /// Disconnects a client, notifying it with the given reason.
+    pub fn disconnect(&mut self, addr: SocketAddr, reason: u8) -> Result<(), ConnectionError> {
+        let connection = self.connections.get_mut(&addr).ok_or(ConnectionError::NotConnected)?;
+        connection.disconnect(reason)?;
+        connection.process_send_queue(&mut self.socket)?;
+        self.remove_connection(addr, reason);
+        Ok(())
+    }
+
+    /// Adds an address to the deny list and kicks any clients connected from it.
+    pub fn ban(&mut self, ip: IpAddr) -> Result<(), ConnectionError> {
+        self.address_filter.deny(ip);
+        self.pending.retain(|addr, _| addr.ip() != ip);
+
+        let banned: Vec<SocketAddr> = self.connections.keys()
+            .filter(|addr| addr.ip() == ip)
+            .copied()
+            .collect();
+        for addr in banned {
+            self.disconnect(addr, disconnect_reason::KICKED)?;
+        }
+        Ok(())
+    }
+
+    /// Removes an address from the deny list.
+    pub fn unban(&mut self, ip: IpAddr) -> bool {
+        self.address_filter.remove_deny(ip)
+    }
+
+    /// Returns the address allow/deny lists.
+    pub fn address_filter(&self) -> &AddressFilter {
+        &self.address_filter
+    }
+
+    /// Returns the address allow/deny lists for modification.
+    pub fn address_filter_mut(&mut self) -> &mut AddressFilter {
+        &mut self.address_filter
+    }
+
+    /// Installs callbacks for events and messages, called at the end of every
+    /// [`Server::update`] in place of polling; see [`ServerHandler`] for the re-entrancy rules.
+    pub fn set_handler<H: ServerHandler + 'static>(&mut self, handler: H) {
+        self.handler = Some(Box::new(handler));
+        self.handler_replaced = true;
+    }
+
+    /// Removes the installed handler; events and messages queue for polling again.
+    pub fn clear_handler(&mut self) {
+        self.handler = None;
+        self.handler_replaced = true;
+    }
+
+    /// Installs a filter invoked on every datagram before it is parsed.
+    pub fn set_packet_filter<F: PacketFilter + 'static>(&mut self, filter: F) {
+        self.packet_filter = Some(Box::new(filter));
+    }
+
+    /// Removes the installed packet filter.
+    pub fn clear_packet_filter(&mut self) {
+        self.packet_filter = None;
+    }
+
+    /// Installs a revocation check consulted whenever a client connects or resumes.
+    ///
+    /// Connections already live are only checked again by [`Server::apply_revocations`].
+    pub fn set_revocation_check<R: RevocationCheck + 'static>(&mut self, check: R) {
+        self.revocation_check = Some(Box::new(check));
+    }
+
+    /// Removes the installed revocation check.
+    pub fn clear_revocation_check(&mut self) {
+        self.revocation_check = None;
+    }
+
+    /// Runs the revocation check over every live connection and disconnects the revoked ones
+    /// with `disconnect_reason::REVOKED`, voiding their resumption tickets. Call it after the
+    /// revocation list changes. Returns how many were disconnected.
+    pub fn apply_revocations(&mut self) -> Result<usize, ConnectionError> {
+        let Some(mut check) = self.revocation_check.take() else {
+            return Ok(0);
+        };
+        let result = self.revoke_where(|addr, connection| check.is_revoked(addr, connection));
+        self.revocation_check = Some(check);
+        result
+    }
+
+    /// Disconnects every live connection matching `revoked` with `disconnect_reason::REVOKED`.
+    ///
+    /// Cheaper than [`Server::apply_revocations`] when only a few entries were added, since
+    /// the predicate only has to look for those.
+    pub fn revoke_where<F>(&mut self, mut revoked: F) -> Result<usize, ConnectionError>
+    where
+        F: FnMut(SocketAddr, &Connection) -> bool,
+    {
+        let matched: Vec<SocketAddr> = self.connections.iter()
+            .filter(|(addr, connection)| revoked(**addr, connection))
+            .map(|(addr, _)| *addr)
+            .collect();
+        for &addr in &matched {
+            self.disconnect(addr, disconnect_reason::REVOKED)?;
+        }
+        self.stats.connections_revoked += matched.len() as u64;
+        Ok(matched.len())
+    }
+
+    fn is_revoked(&mut self, addr: SocketAddr, connection: &Connection) -> bool {
+        self.revocation_check.as_mut().is_some_and(|check| check.is_revoked(addr, connection))
+    }
+
+    /// Sets the identity used to sign key exchanges so clients can authenticate the server.
+    pub fn set_identity(&mut self, identity: ServerIdentity) {
+        self.identity = Some(identity);
+    }
+
+    /// Returns the public key of the server identity, if one is set.
+    pub fn identity_public_key(&self) -> Option<[u8; 32]> {
+        self.identity.as_ref().map(ServerIdentity::public_key)
+    }
+
+    /// Returns the connection for a client address.
+    pub fn connection(&self, addr: SocketAddr) -> Option<&Connection> {
+        self.connections.get(&addr)
+    }
+
+    /// Returns the connection for a client address for modification.
+    pub fn connection_mut(&mut self, addr: SocketAddr) -> Option<&mut Connection> {
+        self.connections.get_mut(&addr)
+    }
+
+    /// Returns the addresses of all connected clients.
+    pub fn clients(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.connections.keys()
+    }
+
+    /// Returns the number of connected clients.
+    pub fn num_clients(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Returns the address the server socket is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns server statistics.
+    pub fn stats(&self) -> &ServerStats {
+        &self.stats
+    }
+
+    /// RTT samples of every connected client in one histogram, for server-wide percentiles.
+    pub fn rtt_histogram(&self) -> RttHistogram {
+        let mut histogram = RttHistogram::new();
+        for connection in self.connections.values() {
+            histogram.merge(connection.rtt_histogram());
+        }
+        histogram
+    }
+
+    /// Copies the server's statistics and every client connection's at once.
+    pub fn stats_snapshot(&self) -> StatsSnapshot<ServerStatsReport> {
+        StatsSnapshot {
+            taken_at: self.config.clock.now(),
+            stats: ServerStatsReport {
+                server: self.stats.clone(),
+                clients: self.connections.iter()
+                    .map(|(addr, connection)| (*addr, connection.stats_snapshot().stats))
+                    .collect(),
+            },
+        }
+    }
+
+    /// Zeroes the server's counters and those of every client connection.
+    pub fn stats_reset(&mut self) {
+        self.stats = ServerStats::default();
+        self.connections.values_mut().for_each(Connection::stats_reset);
+    }
+
+    /// Impairs everything the server sends; see [`UdpSocket::set_simulator`].
+    pub fn set_simulator(&mut self, simulator: Option<NetworkSimulator>) {
+        self.socket.set_simulator(simulator);
+    }
+
+    pub fn simulator(&self) -> Option<&NetworkSimulator> {
+        self.socket.simulator()
+    }
+
+    /// Reads available datagrams within the receive budget, dropping filtered and malformed ones.
+    fn receive_packets(&mut self) -> Result<(), ConnectionError> {
+        let deadline = self.config.receive_budget_time.map(|budget| Instant::now() + budget);
+        let mut read = 0;
+        let mut per_client: HashMap<SocketAddr, usize> = HashMap::new();
+        loop {
+            let out_of_packets = self.config.receive_budget_packets.is_some_and(|budget| read >= budget);
+            if out_of_packets || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                self.stats.receive_budget_exhausted += 1;
+                if self.config.receive_overflow == ReceiveOverflow::Drop {
+                    self.drain_over_budget()?;
+                }
+                break;
+            }
+            let (data, addr) = match self.socket.recv_from() {
+                Ok(received) => received,
+                Err(SocketError::WouldBlock) => break,
+                // Departed clients time out as usual
+                Err(SocketError::PeerUnreachable) => {
+                    self.stats.peer_unreachable += 1;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            read += 1;
+            self.stats.packets_received += 1;
+
+            // Filters run before any parsing so banned sources cost as little as possible
+            if !self.address_filter.is_allowed(addr.ip()) {
+                self.stats.packets_filtered += 1;
+                continue;
+            }
+            if let Some(filter) = self.packet_filter.as_mut() {
+                if !filter.allow(addr, data) {
+                    self.stats.packets_filtered += 1;
+                    continue;
+                }
+            }
+
+            if let Some(budget) = self.config.receive_budget_per_client {
+                let count = per_client.entry(addr).or_insert(0);
+                *count += 1;
+                if *count > budget {
+                    self.stats.packets_over_budget += 1;
+                    continue;
+                }
+            }
+
+            let len = data.len();
+            let Ok(data) = self.config.middleware.decode(addr, data) else {
+                self.stats.invalid_packets += 1;
+                continue;
+            };
+            let packet = match Packet::deserialize(&data) {
+                Ok(packet) if packet.header.protocol_id == self.config.protocol_id => packet,
+                _ => {
+                    self.stats.invalid_packets += 1;
+                    continue;
+                }
+            };
+            let received_at = self.socket.stats().last_receive_time.unwrap_or_else(|| self.config.clock.now());
+
+            self.handle_packet(addr, packet, len, received_at)?;
+        }
+        Ok(())
+    }
+
+    /// Discards every datagram still queued on the socket.
+    fn drain_over_budget(&mut self) -> Result<(), ConnectionError> {
+        loop {
+            match self.socket.recv_from() {
+                Ok(_) => {
+                    self.stats.packets_received += 1;
+                    self.stats.packets_over_budget += 1;
+                }
+                Err(SocketError::WouldBlock) => return Ok(()),
+                Err(SocketError::PeerUnreachable) => self.stats.peer_unreachable += 1,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Routes a packet to the handshake or to the owning client connection.
+    fn handle_packet(&mut self, addr: SocketAddr, packet: Packet, len: usize, received_at: Instant) -> Result<(), ConnectionError> {
+        match packet.packet_type {
+            PacketType::ConnectionRequest => self.handle_connection_request(addr, &packet.payload, &packet.extensions)?,
+            PacketType::ResumeRequest => self.handle_resume_request(addr, &packet.payload)?,
+            PacketType::ConnectionResponse { client_salt } => self.handle_connection_response(addr, client_salt, &packet.payload, &packet.extensions)?,
+            PacketType::PingRequest => self.handle_ping(addr, &packet.payload)?,
+            PacketType::PingResponse => {}
+            // Session traffic from an unknown address: its end is half open, most likely from
+            // before this server restarted. Throttled like requests, and never answering a
+            // Disconnect, so two servers cannot bounce these back and forth
+            PacketType::Payload { .. } | PacketType::KeepAlive if !self.connections.contains_key(&addr) => {
+                if !self.pending.contains_key(&addr) && self.allow_connection_request(addr) {
+                    self.stats.stale_session_replies += 1;
+                    self.send_packet(addr, PacketType::Disconnect { reason: disconnect_reason::STALE_SESSION }, Vec::new())?;
+                }
+            }
+            _ => {
+                if let Some(connection) = self.connections.get_mut(&addr) {
+                    if let PacketType::Payload { channel, .. } = packet.packet_type {
+                        if connection.rejects_channel(channel) {
+                            self.stats.observer_packets_rejected += 1;
+                            return Ok(());
+                        }
+                    }
+                    let was_paused = connection.is_peer_paused();
+                    match connection.receive_packet(packet, len, received_at) {
+                        Ok(()) => {}
+                        Err(e @ ConnectionError::SocketError(_)) => return Err(e),
+                        Err(_) => {
+                            self.fail_connection(addr);
+                            return Ok(());
+                        }
+                    }
+                    if !connection.is_connected() {
+                        self.remove_connection(addr, disconnect_reason::REQUESTED);
+                    } else if connection.is_peer_paused() != was_paused {
+                        let event = if was_paused { ServerEvent::ClientUnpaused(addr) } else { ServerEvent::ClientPaused(addr) };
+                        self.events.push_back(event);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Echoes a ping probe with the server's clock and load, never larger than the probe.
+    fn handle_ping(&mut self, addr: SocketAddr, payload: &[u8]) -> Result<(), ConnectionError> {
+        let Some(id) = ping::probe_id(payload) else {
+            return Ok(());
+        };
+        if !self.config.respond_to_pings || !self.allow_connection_request(addr) {
+            return Ok(());
+        }
+        let reply = PingReply {
+            id,
+            server_time: wall_clock(),
+            clients: self.connections.len().min(u16::MAX as usize) as u16,
+            max_clients: self.config.max_clients.min(u16::MAX as usize) as u16,
+        };
+        self.send_packet(addr, PacketType::PingResponse, reply.encode())
+    }
+
+    /// Answers a connection request with a challenge, or denies it when full.
+    ///
+    /// Requests from a connected address are challenged too, since the client may have
+    /// restarted; the old connection is only replaced once the handshake completes.
+    /// With `connect_token_key` set, requests without a valid token get no answer at all.
+    ///
+    /// Requests carrying key exchange material are answered with a cookie until one comes
+    /// back echoing it, so the exchange and signature are only spent on sources that can
+    /// receive. Those echoing a valid cookie already passed the rate limiter once.
+    fn handle_connection_request(&mut self, addr: SocketAddr, payload: &[u8], extensions: &HeaderExtensions) -> Result<(), ConnectionError> {
+        let needs_cookie = self.config.encryption_key.is_none() && !payload.is_empty();
+        let verified = needs_cookie && extensions.get(header_extension::REQUEST_COOKIE)
+            .is_some_and(|cookie| self.request_cookies.check(self.config.protocol_id, addr, payload, cookie));
+        if !verified && !self.allow_connection_request(addr) {
+            return Ok(());
+        }
+
+        let token = match self.config.connect_token_key {
+            Some(key) => {
+                let bytes = extensions.get(header_extension::CONNECT_TOKEN).unwrap_or_default();
+                match self.connect_tokens.claim(bytes, &key, self.config.protocol_id, addr) {
+                    Ok(token) => Some(token),
+                    Err(_) => {
+                        self.stats.connect_tokens_rejected += 1;
+                        return Ok(());
+                    }
+                }
+            }
+            None => None,
+        };
+
+        if needs_cookie && !verified {
+            let cookie = self.request_cookies.issue(self.config.protocol_id, addr, payload);
+            let mut packet = self.handshake_packet(PacketType::ConnectionChallenge { server_salt: 0 }, Vec::new());
+            packet.extensions.set(header_extension::REQUEST_COOKIE, cookie).map_err(|_| ConnectionError::InvalidPacket)?;
+            return self.send_handshake_packet(addr, packet);
+        }
+
+        if let Some(pending) = self.pending.get(&addr) {
+            // Retried request: answer with the same challenge and key material
+            let packet_type = PacketType::ConnectionChallenge { server_salt: pending.server_salt };
+            let payload = pending.challenge_payload.clone();
+            return self.send_packet(addr, packet_type, payload);
+        }
+
+        let now = self.config.clock.now();
+        if let Some(index) = self.join_queue.iter().position(|queued| queued.addr == addr) {
+            self.join_queue[index].last_seen = now;
+            return self.send_queue_position(addr, index);
+        }
+
+        // Nobody skips the line while others are queued
+        let occupied = self.connections.len() + self.pending.len() - usize::from(self.connections.contains_key(&addr));
+        if occupied >= self.config.max_clients || !self.join_queue.is_empty() {
+            if self.join_queue.len() >= self.config.join_queue_size {
+                return self.send_packet(addr, PacketType::ConnectionDeny { reason: deny_reason::SERVER_FULL }, Vec::new());
+            }
+            self.join_queue.push_back(QueuedRequest { addr, payload: payload.to_vec(), token, last_seen: now });
+            self.stats.join_queue_entered += 1;
+            return self.send_queue_position(addr, self.join_queue.len() - 1);
+        }
+
+        self.challenge(addr, payload, token)
+    }
+
+    /// Starts the handshake for a request that has a slot: answers it with a challenge and
+    /// holds the key material until the response.
+    fn challenge(&mut self, addr: SocketAddr, payload: &[u8], token: Option<ConnectToken>) -> Result<(), ConnectionError> {
+        let (challenge_payload, session_keys) = match self.config.encryption_key {
+            Some(key) => (Vec::new(), Some(SessionKeys::from_static(key))),
+            None if payload.is_empty() => (Vec::new(), None),
+            None => match KeyExchange::new().respond(self.config.protocol_id, payload, self.identity.as_ref()) {
+                Some((challenge_payload, keys)) => (challenge_payload, Some(keys)),
+                None => {
+                    self.stats.invalid_packets += 1;
+                    return Ok(());
+                }
+            },
+        };
+
+        let server_salt = self.rng.next_u64();
+        self.pending.insert(addr, PendingConnection {
+            server_salt,
+            request_time: self.config.clock.now(),
+            challenge_payload: challenge_payload.clone(),
+            session_keys,
+            token,
+        });
+
+        self.send_packet(addr, PacketType::ConnectionChallenge { server_salt }, challenge_payload)
+    }
+
+    /// Drops queued requests whose clients went quiet, challenges as many as there are free
+    /// slots, and tells the rest their position when it is time.
+    fn update_join_queue(&mut self, now: Instant) -> Result<(), ConnectionError> {
+        let timeout = self.config.connection_timeout;
+        self.join_queue.retain(|queued| now.saturating_duration_since(queued.last_seen) <= timeout);
+
+        while self.connections.len() + self.pending.len() < self.config.max_clients {
+            let Some(queued) = self.join_queue.pop_front() else { break };
+            self.stats.join_queue_promoted += 1;
+            self.challenge(queued.addr, &queued.payload, queued.token)?;
+        }
+
+        if now.saturating_duration_since(self.last_queue_update) >= self.config.join_queue_update_interval {
+            self.last_queue_update = now;
+            for index in 0..self.join_queue.len() {
+                self.send_queue_position(self.join_queue[index].addr, index)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_queue_position(&mut self, addr: SocketAddr, index: usize) -> Result<(), ConnectionError> {
+        let position = u16::try_from(index + 1).unwrap_or(u16::MAX);
+        self.send_packet(addr, PacketType::ConnectionQueued { position }, Vec::new())
+    }
+
+    /// Number of requests waiting in the join queue.
+    pub fn join_queue_len(&self) -> usize {
+        self.join_queue.len()
+    }
+
+    /// Place of an address in the join queue, 1 being next in line.
+    pub fn queue_position(&self, addr: SocketAddr) -> Option<usize> {
+        self.join_queue.iter().position(|queued| queued.addr == addr).map(|index| index + 1)
+    }
+
+    /// Completes the handshake for a client that answered our challenge.
+    ///
+    /// The response payload carries the client's role; an empty one is a player.
+    fn handle_connection_response(
+        &mut self,
+        addr: SocketAddr,
+        client_salt: u64,
+        payload: &[u8],
+        extensions: &HeaderExtensions,
+    ) -> Result<(), ConnectionError> {
+        let role = match payload.first() {
+            Some(&byte) => ConnectionRole::from_byte(byte),
+            None => Some(ConnectionRole::Player),
+        };
+        let Some(role) = role else {
+            self.stats.invalid_packets += 1;
+            return Ok(());
+        };
+        let pending = match self.pending.remove(&addr) {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        let mut connection = Connection::new(self.config.clone(), self.local_addr, addr);
+        if self.is_revoked(addr, &connection) {
+            self.stats.connections_revoked += 1;
+            return self.send_packet(addr, PacketType::ConnectionDeny { reason: deny_reason::BANNED }, Vec::new());
+        }
+
+        // A new client salt means a new client process behind the address: the old session is half open
+        match self.connections.get(&addr) {
+            Some(existing) if existing.client_salt() == client_salt => return Ok(()),
+            Some(_) => {
+                self.stats.stale_sessions_replaced += 1;
+                self.remove_connection(addr, disconnect_reason::STALE_SESSION);
+            }
+            None => {}
+        }
+
+        // Clients that send no channel layout are taken to match
+        let mismatch = extensions.get(header_extension::CHANNEL_LAYOUT)
+            .and_then(|remote| ChannelMismatch::between(&self.config.channel_layout(), remote));
+        if mismatch.is_some() && self.config.channel_mismatch == ChannelMismatchPolicy::Fail {
+            return self.send_packet(addr, PacketType::ConnectionDeny { reason: deny_reason::CHANNEL_MISMATCH }, Vec::new());
+        }
+
+        // Capability bits and then codec ids follow the role byte; clients that predate
+        // them send neither and get no optional features
+        let offered = payload.get(1..5).and_then(|bits| bits.try_into().ok()).map_or(0, u32::from_le_bytes);
+        let capabilities = capability::negotiate(self.config.advertised_capabilities(), offered);
+        if self.config.encrypts_packets() && capabilities & capability::PACKET_ENCRYPTION == 0 {
+            return self.send_packet(addr, PacketType::ConnectionDeny { reason: deny_reason::ENCRYPTION_REQUIRED }, Vec::new());
+        }
+        let codecs = if capabilities & capability::COMPRESSION != 0 {
+            connection.negotiate_codecs(payload.get(5..).unwrap_or_default())
+        } else {
+            Vec::new()
+        };
+
+        let session_seed = self.rng.next_u64();
+        // Tickets are redeemed by proving the session keys, so keyless sessions get none
+        let ticket = self.tickets.as_mut()
+            .filter(|_| pending.session_keys.is_some())
+            .map(|tickets| {
+                let id = tickets.issue(addr, pending.session_keys.clone(), session_seed, role);
+                tickets.set_negotiated(addr, capabilities, codecs.clone());
+                id.to_vec()
+            })
+            .unwrap_or_default();
+
+        connection.set_negotiated(capabilities, codecs);
+        connection.set_authorized_token(pending.token);
+        connection.accept(client_salt, pending.server_salt, pending.session_keys, session_seed, role, ticket);
+        connection.process_send_queue(&mut self.socket)?;
+
+        self.connections.insert(addr, connection);
+        self.events.push_back(ServerEvent::ClientConnected(addr));
+        if let Some(mismatch) = mismatch {
+            self.events.push_back(ServerEvent::ChannelMismatch(addr, mismatch));
+        }
+        Ok(())
+    }
+
+    /// Restores a session from a resumption ticket without a full handshake.
+    fn handle_resume_request(&mut self, addr: SocketAddr, payload: &[u8]) -> Result<(), ConnectionError> {
+        if !self.allow_connection_request(addr) || self.connections.contains_key(&addr) {
+            return Ok(());
+        }
+
+        // Only the holder of the session keys can redeem, and only once the session is over;
+        // anything else falls back to a full handshake
+        let now = self.config.clock.now();
+        let protocol_id = self.config.protocol_id;
+        let redeemed = match (self.tickets.as_mut(), parse_resume_request(payload)) {
+            (Some(tickets), Some((id, nonce, proof))) => tickets.redeem(&id, now, |keys| {
+                keys.check_resume_proof(true, protocol_id, &id, nonce, proof)
+            }),
+            _ => None,
+        };
+        let Some(entry) = redeemed else {
+            let packet_type = PacketType::ConnectionDeny { reason: deny_reason::INVALID_TICKET };
+            return self.send_packet(addr, packet_type, Vec::new());
+        };
+
+        if self.connections.len() + self.pending.len() >= self.config.max_clients {
+            let packet_type = PacketType::ConnectionDeny { reason: deny_reason::SERVER_FULL };
+            return self.send_packet(addr, packet_type, Vec::new());
+        }
+
+        let mut connection = Connection::new(self.config.clone(), self.local_addr, addr);
+        if self.is_revoked(addr, &connection) {
+            // The redeemed ticket is gone, so the session cannot come back
+            self.stats.connections_revoked += 1;
+            return self.send_packet(addr, PacketType::ConnectionDeny { reason: deny_reason::BANNED }, Vec::new());
+        }
+
+        // The resumed session keeps its seed so deterministic simulations stay in step
+        let ticket = self.tickets.as_mut()
+            .map(|tickets| {
+                let id = tickets.issue(addr, entry.session_keys.clone(), entry.session_seed, entry.role);
+                tickets.set_negotiated(addr, entry.capabilities, entry.codecs.clone());
+                id.to_vec()
+            })
+            .unwrap_or_default();
+
+        connection.set_negotiated(entry.capabilities, entry.codecs);
+        connection.accept(self.rng.next_u64(), self.rng.next_u64(), entry.session_keys, entry.session_seed, entry.role, ticket);
+        if let Some(channels) = entry.channels {
+            connection.restore_channels(channels);
+        }
+        connection.process_send_queue(&mut self.socket)?;
+
+        self.connections.insert(addr, connection);
+        self.events.push_back(ServerEvent::ClientResumed(addr));
+        Ok(())
+    }
+
+    /// Applies the per-source request rate limit.
+    fn allow_connection_request(&mut self, addr: SocketAddr) -> bool {
+        if self.request_limiter.allow(addr.ip(), self.config.clock.now()) {
+            return true;
+        }
+        self.stats.connection_requests_throttled += 1;
+        false
+    }
+
+    /// Sends a connectionless handshake packet.
+    ///
+    /// Nothing queues these, so a failed send is dropped unless its policy is `Fail`; the
+    /// client resends its request.
+    fn send_packet(&mut self, addr: SocketAddr, packet_type: PacketType, payload: Vec<u8>) -> Result<(), ConnectionError> {
+        let packet = self.handshake_packet(packet_type, payload);
+        self.send_handshake_packet(addr, packet)
+    }
+
+    fn handshake_packet(&self, packet_type: PacketType, payload: Vec<u8>) -> Packet {
+        let header = PacketHeader {
+            protocol_id: self.config.protocol_id,
+            sequence: 0,
+            ack: 0,
+            ack_bits: 0,
+        };
+        Packet::new(header, packet_type).with_payload(payload)
+    }
+
+    fn send_handshake_packet(&mut self, addr: SocketAddr, packet: Packet) -> Result<(), ConnectionError> {
+        let data = packet.serialize().map_err(|_| ConnectionError::InvalidPacket)?;
+        let data = self.config.middleware.encode(addr, &data).map_err(|_| ConnectionError::InvalidPacket)?;
+        if let Err(err) = self.socket.send_to(&data, addr) {
+            let class = err.send_error_class();
+            self.stats.send_errors.record(class);
+            if self.config.send_error_policy.for_class(class) == SendErrorPolicy::Fail {
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_connection(&mut self, addr: SocketAddr, reason: u8) {
+        let mut connection = match self.connections.remove(&addr) {
+            Some(connection) => connection,
+            None => return,
+        };
+        self.leave_room(addr);
+
+        // Only sessions that dropped or left on their own may be resumed
+        if let Some(tickets) = self.tickets.as_mut() {
+            match reason {
+                disconnect_reason::TIMEOUT | disconnect_reason::REQUESTED => {
+                    tickets.park(addr, connection.take_channels(), self.config.clock.now());
+                }
+                _ => tickets.revoke(addr),
+            }
+        }
+
+        self.events.push_back(ServerEvent::ClientDisconnected(addr, reason));
+    }
+}
@@ -0,0 +1,269 @@
+// simulator.rs - Lossy, laggy links for testing on a perfect network
+//
+// A NetworkSimulator sits on a socket's send path: every outgoing datagram is either dropped
+// by the loss model or held until its delivery time, then sent for real on the next socket
+// call. Only the sending side needs one, so installing it on both the client and the server
+// socket impairs both directions independently.
+//
+// Uniform loss drops packets independently, which recovers far more gracefully than real
+// Wi-Fi, where interference takes out runs of packets at once. The Gilbert-Elliott model
+// reproduces that with a good and a bad state, each with its own loss rate, and a chance of
+// switching after every packet.
+//
+// With a bandwidth set, datagrams that survive the loss model first pass a bottleneck: a
+// queue drained at that many bytes per second, holding at most `queue_bytes`, which drops
+// the newest or the oldest datagram when it overflows. Latency and jitter start once a
+// datagram has been transmitted, so a sender that outpaces the link sees its round trips
+// grow and then its packets dropped, as a congestion controller would on a real path.
+use std::collections::{BinaryHeap, VecDeque};
+use std::cmp::Reverse;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::rng::DeterministicRng;
+
+// Delivery time, submission order, destination, datagram
+type Delayed = (Instant, u64, SocketAddr, Vec<u8>);
+
+/// How the simulator decides which datagrams never arrive.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LossModel {
+    #[default]
+    None,
+    /// Each datagram is lost independently with probability `loss`
+    Uniform { loss: f32 },
+    /// Two-state Markov chain, moved once per datagram before it is sent
+    GilbertElliott {
+        /// Chance per datagram of entering the bad state from the good one
+        good_to_bad: f32,
+        /// Chance per datagram of leaving the bad state
+        bad_to_good: f32,
+        /// Loss probability in the good state
+        good_loss: f32,
+        /// Loss probability in the bad state
+        bad_loss: f32,
+    },
+}
+
+impl LossModel {
+    /// Gilbert-Elliott loss averaging `average_loss`, in bursts of `mean_burst` datagrams on average.
+    pub fn bursty(average_loss: f32, mean_burst: f32) -> Self {
+        let average_loss = average_loss.clamp(0.0, 0.99);
+        let bad_to_good = 1.0 / mean_burst.max(1.0);
+        LossModel::GilbertElliott {
+            // The bad state's long-run share, good_to_bad / (good_to_bad + bad_to_good), is the loss rate
+            good_to_bad: (average_loss * bad_to_good / (1.0 - average_loss)).min(1.0),
+            bad_to_good,
+            good_loss: 0.0,
+            bad_loss: 1.0,
+        }
+    }
+}
+
+/// Which datagram a full bottleneck queue gives up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueueDrop {
+    /// The arriving datagram, as most routers do
+    #[default]
+    Tail,
+    /// The oldest queued datagram, making room for the newest
+    Head,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatorConfig {
+    /// One-way delay added to every datagram
+    pub latency: Duration,
+    /// Extra delay drawn uniformly from `0..=jitter`; may reorder datagrams
+    pub jitter: Duration,
+    pub loss: LossModel,
+    /// Seed for every random choice, so a run can be repeated exactly
+    pub seed: u64,
+    /// Largest datagram the path carries; bigger ones are dropped whatever the loss model says
+    pub mtu: Option<usize>,
+    /// Link rate in bytes per second; `None` transmits every datagram instantly
+    pub bandwidth: Option<f64>,
+    /// Bytes the bottleneck queue holds waiting for the link
+    pub queue_bytes: usize,
+    pub queue_drop: QueueDrop,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: LossModel::None,
+            seed: 0,
+            mtu: None,
+            bandwidth: None,
+            queue_bytes: 64 * 1024,
+            queue_drop: QueueDrop::Tail,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SimulatorStats {
+    pub packets_submitted: u64,
+    pub packets_dropped: u64,
+    /// Runs of consecutive dropped datagrams
+    pub loss_bursts: u64,
+    /// Length of the longest run of consecutive dropped datagrams
+    pub longest_burst: u64,
+    /// Datagrams dropped for exceeding `SimulatorConfig::mtu`, not counted as loss
+    pub packets_oversized: u64,
+    /// Datagrams dropped by a full bottleneck queue, not counted as loss
+    pub packets_queue_dropped: u64,
+    /// Most bytes the bottleneck queue held at once
+    pub peak_queue_bytes: usize,
+}
+
+#[derive(Debug)]
+pub struct NetworkSimulator {
+    config: SimulatorConfig,
+    rng: DeterministicRng,
+    bad_state: bool,
+    current_burst: u64,
+    // Ordered by delivery time, then submission order so equal times keep their order
+    queue: BinaryHeap<Reverse<Delayed>>,
+    submitted: u64,
+    // Datagrams waiting for the link, with their arrival times, and when it is next free
+    bottleneck: VecDeque<(Instant, SocketAddr, Vec<u8>)>,
+    queued_bytes: usize,
+    link_free_at: Option<Instant>,
+    stats: SimulatorStats,
+}
+
+impl NetworkSimulator {
+    pub fn new(config: SimulatorConfig) -> Self {
+        Self::with_rng(DeterministicRng::new(config.seed), config)
+    }
+
+    pub(crate) fn with_rng(rng: DeterministicRng, config: SimulatorConfig) -> Self {
+        Self {
+            rng,
+            config,
+            bad_state: false,
+            current_burst: 0,
+            queue: BinaryHeap::new(),
+            submitted: 0,
+            bottleneck: VecDeque::new(),
+            queued_bytes: 0,
+            link_free_at: None,
+            stats: SimulatorStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> &SimulatorConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &SimulatorStats {
+        &self.stats
+    }
+
+    /// Whether a Gilbert-Elliott model is currently in its bad state.
+    pub fn in_bad_state(&self) -> bool {
+        self.bad_state
+    }
+
+    /// Datagrams waiting out their delay or for the link.
+    pub fn pending(&self) -> usize {
+        self.queue.len() + self.bottleneck.len()
+    }
+
+    /// Bytes waiting in the bottleneck queue.
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes
+    }
+
+    /// Runs a datagram through the loss model; returns false if it was dropped.
+    pub fn submit(&mut self, data: &[u8], addr: SocketAddr, now: Instant) -> bool {
+        self.stats.packets_submitted += 1;
+        if self.config.mtu.is_some_and(|mtu| data.len() > mtu) {
+            self.stats.packets_oversized += 1;
+            return false;
+        }
+        if self.roll_loss() {
+            self.stats.packets_dropped += 1;
+            if self.current_burst == 0 {
+                self.stats.loss_bursts += 1;
+            }
+            self.current_burst += 1;
+            self.stats.longest_burst = self.stats.longest_burst.max(self.current_burst);
+            return false;
+        }
+        self.current_burst = 0;
+
+        if self.config.bandwidth.is_none() {
+            self.delay(data.to_vec(), addr, now);
+            return true;
+        }
+        self.transmit(now);
+        while self.queued_bytes + data.len() > self.config.queue_bytes {
+            self.stats.packets_queue_dropped += 1;
+            // Tail drop, or a datagram larger than the whole queue
+            let oldest = match self.config.queue_drop {
+                QueueDrop::Head => self.bottleneck.pop_front(),
+                QueueDrop::Tail => None,
+            };
+            let Some((_, _, oldest)) = oldest else { return false };
+            self.queued_bytes -= oldest.len();
+        }
+        self.queued_bytes += data.len();
+        self.stats.peak_queue_bytes = self.stats.peak_queue_bytes.max(self.queued_bytes);
+        self.bottleneck.push_back((now, addr, data.to_vec()));
+        true
+    }
+
+    /// Next datagram whose delivery time has come, if any.
+    pub fn take_due(&mut self, now: Instant) -> Option<(Vec<u8>, SocketAddr)> {
+        self.transmit(now);
+        let Reverse((deliver_at, ..)) = self.queue.peek()?;
+        if *deliver_at > now {
+            return None;
+        }
+        let Reverse((_, _, addr, data)) = self.queue.pop()?;
+        Some((data, addr))
+    }
+
+    // Moves datagrams the link has finished sending by `now` on to their latency
+    fn transmit(&mut self, now: Instant) {
+        let Some(bandwidth) = self.config.bandwidth else { return };
+        while let Some((arrived, _, data)) = self.bottleneck.front() {
+            let start = self.link_free_at.map_or(*arrived, |free| free.max(*arrived));
+            let sent = start + Duration::from_secs_f64(data.len() as f64 / bandwidth.max(1.0));
+            if sent > now {
+                break;
+            }
+            let (_, addr, data) = self.bottleneck.pop_front().expect("front was just read");
+            self.queued_bytes -= data.len();
+            self.link_free_at = Some(sent);
+            self.delay(data, addr, sent);
+        }
+    }
+
+    fn delay(&mut self, data: Vec<u8>, addr: SocketAddr, sent: Instant) {
+        let mut delay = self.config.latency;
+        if !self.config.jitter.is_zero() {
+            delay += self.config.jitter.mul_f64(self.rng.next_f64());
+        }
+        self.submitted += 1;
+        self.queue.push(Reverse((sent + delay, self.submitted, addr, data)));
+    }
+
+    fn roll_loss(&mut self) -> bool {
+        match self.config.loss {
+            LossModel::None => false,
+            LossModel::Uniform { loss } => self.rng.next_f32() < loss,
+            LossModel::GilbertElliott { good_to_bad, bad_to_good, good_loss, bad_loss } => {
+                let switch = if self.bad_state { bad_to_good } else { good_to_bad };
+                if self.rng.next_f32() < switch {
+                    self.bad_state = !self.bad_state;
+                }
+                self.rng.next_f32() < if self.bad_state { bad_loss } else { good_loss }
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// snapshot.rs - Full state baselines for clients joining mid-match
+//
+// The server captures the world state once when a client joins and hands it to the client's
+// connection, which cuts it into chunks and feeds a few at a time to the reserved
+// `join_snapshot_channel` so it never floods the send queue. The client holds every other
+// channel's messages until the last chunk arrives, so the deltas the server sends from the
+// moment the baseline was captured are delivered after it, in order, with no game code.
+
+/// Each chunk starts with the total snapshot length.
+pub(crate) const CHUNK_HEADER_BYTES: usize = 4;
+
+// Chunks kept queued on the channel ahead of the network
+const CHUNKS_QUEUED: usize = 4;
+
+/// Outgoing snapshot, released to the channel chunk by chunk.
+#[derive(Debug)]
+pub(crate) struct SnapshotSender {
+    data: Vec<u8>,
+    offset: usize,
+    chunk_size: usize,
+    started: bool,
+}
+
+impl SnapshotSender {
+    pub(crate) fn new(data: Vec<u8>, chunk_size: usize) -> Self {
+        Self {
+            data,
+            offset: 0,
+            chunk_size: chunk_size.max(1),
+            started: false,
+        }
+    }
+
+    /// Bytes handed to the channel so far, and the snapshot size.
+    pub(crate) fn progress(&self) -> (usize, usize) {
+        (self.offset, self.data.len())
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.started && self.offset >= self.data.len()
+    }
+
+    /// Whether another chunk should be queued, given the bytes already waiting on the channel.
+    pub(crate) fn wants_chunk(&self, pending_bytes: usize) -> bool {
+        !self.is_done() && pending_bytes < self.chunk_size * CHUNKS_QUEUED
+    }
+
+    /// The next chunk framed for the wire; an empty snapshot is still sent as one chunk.
+    pub(crate) fn next_chunk(&mut self) -> Option<Vec<u8>> {
+        if self.is_done() {
+            return None;
+        }
+        let end = (self.offset + self.chunk_size).min(self.data.len());
+        let mut chunk = Vec::with_capacity(CHUNK_HEADER_BYTES + end - self.offset);
+        chunk.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(&self.data[self.offset..end]);
+        self.offset = end;
+        self.started = true;
+        Some(chunk)
+    }
+
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.data.len() - self.offset
+    }
+}
+
+/// Incoming snapshot, rebuilt from in-order chunks.
+#[derive(Debug, Default)]
+pub(crate) struct SnapshotReceiver {
+    data: Vec<u8>,
+    total: Option<usize>,
+}
+
+impl SnapshotReceiver {
+    /// Bytes received so far, and the snapshot size once the first chunk has arrived.
+    pub(crate) fn progress(&self) -> (usize, Option<usize>) {
+        (self.data.len(), self.total)
+    }
+
+    /// Appends a chunk; `None` for a malformed one.
+    pub(crate) fn on_chunk(&mut self, chunk: &[u8]) -> Option<()> {
+        let total = u32::from_le_bytes(chunk.get(..CHUNK_HEADER_BYTES)?.try_into().ok()?) as usize;
+        let body = &chunk[CHUNK_HEADER_BYTES..];
+        if self.total.is_some_and(|expected| expected != total) || self.data.len() + body.len() > total {
+            return None;
+        }
+        self.total = Some(total);
+        self.data.extend_from_slice(body);
+        Some(())
+    }
+
+    pub(crate) fn is_complete(&self) -> bool {
+        self.total == Some(self.data.len())
+    }
+
+    pub(crate) fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.data.len()
+    }
+}
@@ -1,29 +1,152 @@
 // socket.rs - Platform-agnostic UDP socket wrapper
+//
+// Sockets normally wrap an OS UDP socket. Bound to a MemoryNetwork instead, they trade
+// datagrams through in-process queues, which with a manual Clock lets both ends of a
+// connection run in one thread with no real time or ports involved.
+//
+// Received datagrams are read straight into a fixed buffer owned by the socket and handed out
+// as slices for parsing in place. Memory networks keep a pool of datagram buffers: a delivered
+// datagram's buffer becomes the receiving socket's buffer, and the one it replaces goes back.
+//
+// With the `tokio` feature, sockets can also be registered with the tokio reactor. Reads and
+// writes stay non-blocking attempts, so connections and servers run the same code on them;
+// only waiting differs, awaiting readiness instead of blocking a thread.
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
 use std::net::{SocketAddr, UdpSocket as StdUdpSocket};
 use std::io::{Error as IoError, ErrorKind};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::{Duration, Instant};
 
+use crate::instant::Clock;
+use crate::simulator::NetworkSimulator;
+
 #[derive(Debug)]
 pub enum SocketError {
     Io(IoError),
     InvalidAddress,
     SocketClosed,
     WouldBlock,
+    /// An ICMP port or host unreachable came back for an earlier send (reported by Windows,
+    /// and by Linux on connected sockets); nothing says which peer it was for
+    PeerUnreachable,
+    /// The datagram is larger than the socket or the path allows (EMSGSIZE)
+    MessageTooLarge,
+    /// No route to the destination network or host
+    NetworkUnreachable,
+}
+
+impl SocketError {
+    /// Which kind of send failure this is, for [`crate::config::SendErrorPolicies`].
+    pub fn send_error_class(&self) -> SendErrorClass {
+        match self {
+            SocketError::WouldBlock => SendErrorClass::WouldBlock,
+            SocketError::MessageTooLarge => SendErrorClass::MessageTooLarge,
+            SocketError::NetworkUnreachable | SocketError::PeerUnreachable => SendErrorClass::Unreachable,
+            _ => SendErrorClass::Other,
+        }
+    }
+}
+
+impl std::fmt::Display for SocketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketError::Io(e) => write!(f, "{}", e),
+            SocketError::InvalidAddress => write!(f, "invalid address"),
+            SocketError::SocketClosed => write!(f, "socket closed"),
+            SocketError::WouldBlock => write!(f, "operation would block"),
+            SocketError::PeerUnreachable => write!(f, "peer unreachable"),
+            SocketError::MessageTooLarge => write!(f, "datagram too large"),
+            SocketError::NetworkUnreachable => write!(f, "network unreachable"),
+        }
+    }
 }
 
+impl std::error::Error for SocketError {}
+
 impl From<IoError> for SocketError {
     fn from(err: IoError) -> Self {
         match err.kind() {
             ErrorKind::WouldBlock => SocketError::WouldBlock,
+            ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset => SocketError::PeerUnreachable,
+            ErrorKind::NetworkUnreachable | ErrorKind::HostUnreachable => SocketError::NetworkUnreachable,
+            _ if err.raw_os_error() == Some(EMSGSIZE) => SocketError::MessageTooLarge,
             _ => SocketError::Io(err),
         }
     }
 }
 
+// EMSGSIZE has no ErrorKind of its own
+#[cfg(windows)]
+const EMSGSIZE: i32 = 10040;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const EMSGSIZE: i32 = 90;
+#[cfg(not(any(windows, target_os = "linux", target_os = "android")))]
+const EMSGSIZE: i32 = 40;
+
+/// Kinds of socket send failure, each handled by its own [`crate::config::SendErrorPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SendErrorClass {
+    /// The socket's send buffer is full
+    WouldBlock,
+    /// The datagram exceeds what the socket or path carries; usually `mtu` is set too high
+    MessageTooLarge,
+    /// No route to the peer, or an ICMP unreachable reported on send
+    Unreachable,
+    /// Anything else the OS returned
+    Other,
+}
+
+/// Failed sends counted per [`SendErrorClass`], whatever their policy did with them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SendErrorCounts {
+    pub would_block: u64,
+    pub message_too_large: u64,
+    pub unreachable: u64,
+    pub other: u64,
+}
+
+impl SendErrorCounts {
+    pub fn get(&self, class: SendErrorClass) -> u64 {
+        match class {
+            SendErrorClass::WouldBlock => self.would_block,
+            SendErrorClass::MessageTooLarge => self.message_too_large,
+            SendErrorClass::Unreachable => self.unreachable,
+            SendErrorClass::Other => self.other,
+        }
+    }
+    
+    pub fn total(&self) -> u64 {
+        self.would_block + self.message_too_large + self.unreachable + self.other
+    }
+    
+    pub(crate) fn record(&mut self, class: SendErrorClass) {
+        match class {
+            SendErrorClass::WouldBlock => self.would_block += 1,
+            SendErrorClass::MessageTooLarge => self.message_too_large += 1,
+            SendErrorClass::Unreachable => self.unreachable += 1,
+            SendErrorClass::Other => self.other += 1,
+        }
+    }
+}
+
 pub struct UdpSocket {
-    socket: StdUdpSocket,
+    transport: Transport,
     recv_buffer: Vec<u8>,
     stats: SocketStats,
+    kernel_timestamps: bool,
+    iocp: bool,
+    simulator: Option<NetworkSimulator>,
+    clock: Clock,
+    #[cfg(all(feature = "iocp", windows))]
+    completion_port: Option<iocp::CompletionPort>,
+}
+
+enum Transport {
+    Udp(StdUdpSocket),
+    Memory(MemoryEndpoint),
+    #[cfg(feature = "tokio")]
+    Tokio(tokio::net::UdpSocket),
 }
 
 #[derive(Debug, Default)]
@@ -32,6 +155,7 @@ pub struct SocketStats {
     pub packets_received: u64,
     pub bytes_sent: u64,
     pub bytes_received: u64,
+    /// Kernel receive time of the last datagram when kernel timestamps are enabled
     pub last_receive_time: Option<Instant>,
     pub last_send_time: Option<Instant>,
 }
@@ -41,78 +165,310 @@ impl UdpSocket {
     pub fn bind(addr: SocketAddr) -> Result<Self, SocketError> {
         let socket = StdUdpSocket::bind(addr)?;
         socket.set_nonblocking(true)?;
-        
-        Ok(Self {
-            socket,
-            recv_buffer: vec![0u8; 65536], // Max UDP packet size
+        Ok(Self::with_transport(Transport::Udp(socket)))
+    }
+    
+    /// Creates a socket on an in-process network; port 0 picks a free port.
+    pub fn bind_memory(network: &MemoryNetwork, addr: SocketAddr) -> Result<Self, SocketError> {
+        let addr = network.bind(addr)?;
+        Ok(Self::with_transport(Transport::Memory(MemoryEndpoint { network: network.clone(), addr })))
+    }
+    
+    fn with_transport(transport: Transport) -> Self {
+        // Memory sockets take their buffers from the network's pool
+        let recv_buffer = match transport {
+            Transport::Udp(_) => vec![0u8; 65536], // Max UDP packet size
+            Transport::Memory(_) => Vec::new(),
+            #[cfg(feature = "tokio")]
+            Transport::Tokio(_) => vec![0u8; 65536],
+        };
+        Self {
+            transport,
+            recv_buffer,
             stats: SocketStats::default(),
-        })
+            kernel_timestamps: false,
+            iocp: false,
+            simulator: None,
+            clock: Clock::system(),
+            #[cfg(all(feature = "iocp", windows))]
+            completion_port: None,
+        }
     }
     
-    /// Connects the socket to a specific remote address
+    /// Sets where send and receive times (and simulator delays) are read from; use the
+    /// connection's `NetworkConfig::clock`.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+    
+    /// Asks the kernel to timestamp received datagrams, so `last_receive_time` (and the RTT
+    /// samples taken from it) exclude the time a packet sat in the receive queue.
+    ///
+    /// Returns false where unsupported (the `kernel-timestamps` feature is off, not Linux,
+    /// the kernel refused, or a memory socket), in which case receive times keep coming from the clock.
+    pub fn enable_kernel_timestamps(&mut self) -> bool {
+        #[cfg(all(feature = "kernel-timestamps", target_os = "linux"))]
+        if let Transport::Udp(socket) = &self.transport {
+            self.kernel_timestamps = kernel::enable_timestamps(socket).is_ok();
+        }
+        self.kernel_timestamps
+    }
+    
+    /// Whether received datagrams carry kernel timestamps
+    pub fn kernel_timestamps(&self) -> bool {
+        self.kernel_timestamps
+    }
+    
+    /// Receives through overlapped I/O on a completion port, with many reads posted at once,
+    /// so a busy server is not bound by one syscall per datagram. Irreversible.
+    ///
+    /// Returns false where unsupported (the `iocp` feature is off, not Windows, the kernel
+    /// refused, or a memory socket), in which case receives keep using plain non-blocking reads.
+    pub fn enable_iocp(&mut self) -> bool {
+        #[cfg(all(feature = "iocp", windows))]
+        if let (Transport::Udp(socket), None) = (&self.transport, &self.completion_port) {
+            self.completion_port = iocp::CompletionPort::new(socket).ok();
+            self.iocp = self.completion_port.is_some();
+        }
+        self.iocp
+    }
+    
+    /// Whether receives go through a completion port
+    pub fn iocp(&self) -> bool {
+        self.iocp
+    }
+    
+    /// Routes `send_to` through a simulated lossy, delayed link; `None` restores direct sends.
+    ///
+    /// Delayed datagrams go out during later `send_to` and `recv_from` calls.
+    pub fn set_simulator(&mut self, simulator: Option<NetworkSimulator>) {
+        self.simulator = simulator;
+    }
+    
+    pub fn simulator(&self) -> Option<&NetworkSimulator> {
+        self.simulator.as_ref()
+    }
+    
+    /// Connects the socket to a specific remote address; tokio sockets use `AsyncUdpSocket::connect`
     pub fn connect(&self, addr: SocketAddr) -> Result<(), SocketError> {
-        self.socket.connect(addr)?;
+        match &self.transport {
+            Transport::Udp(socket) => socket.connect(addr)?,
+            Transport::Memory(endpoint) => {
+                endpoint.network.lock().peers.insert(endpoint.addr, addr);
+            }
+            #[cfg(feature = "tokio")]
+            Transport::Tokio(_) => return Err(IoError::from(ErrorKind::Unsupported).into()),
+        }
         Ok(())
     }
     
     /// Returns the local address this socket is bound to
     pub fn local_addr(&self) -> Result<SocketAddr, SocketError> {
-        Ok(self.socket.local_addr()?)
+        match &self.transport {
+            Transport::Udp(socket) => Ok(socket.local_addr()?),
+            Transport::Memory(endpoint) => Ok(endpoint.addr),
+            #[cfg(feature = "tokio")]
+            Transport::Tokio(socket) => Ok(socket.local_addr()?),
+        }
     }
     
     /// Sends data to a specific address
     pub fn send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize, SocketError> {
-        let sent = self.socket.send_to(data, addr)?;
+        if let Some(simulator) = &mut self.simulator {
+            // Dropped datagrams still report success, as a lossy link would
+            simulator.submit(data, addr, self.clock.now());
+            self.send_simulated()?;
+            return Ok(data.len());
+        }
+        self.send_datagram(data, addr)
+    }
+    
+    fn send_datagram(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize, SocketError> {
+        let sent = match &self.transport {
+            Transport::Udp(socket) => socket.send_to(data, addr)?,
+            Transport::Memory(endpoint) => endpoint.network.deliver(endpoint.addr, addr, data)?,
+            #[cfg(feature = "tokio")]
+            Transport::Tokio(socket) => socket.try_send_to(data, addr)?,
+        };
         self.stats.bytes_sent += sent as u64;
         self.stats.packets_sent += 1;
-        self.stats.last_send_time = Some(Instant::now());
+        self.stats.last_send_time = Some(self.clock.now());
         Ok(sent)
     }
     
+    fn send_simulated(&mut self) -> Result<(), SocketError> {
+        let now = self.clock.now();
+        while let Some((data, addr)) = self.simulator.as_mut().and_then(|simulator| simulator.take_due(now)) {
+            self.send_datagram(&data, addr)?;
+        }
+        Ok(())
+    }
+    
     /// Receives data from any address (returns data slice and sender address)
     pub fn recv_from(&mut self) -> Result<(&[u8], SocketAddr), SocketError> {
-        match self.socket.recv_from(&mut self.recv_buffer) {
-            Ok((len, addr)) => {
-                self.stats.bytes_received += len as u64;
-                self.stats.packets_received += 1;
-                self.stats.last_receive_time = Some(Instant::now());
-                Ok((&self.recv_buffer[..len], addr))
+        let (len, addr) = self.recv_len_from()?;
+        Ok((&self.recv_buffer[..len], addr))
+    }
+    
+    fn recv_len_from(&mut self) -> Result<(usize, SocketAddr), SocketError> {
+        self.send_simulated()?;
+        let (len, addr, received_at) = self.read_datagram()?;
+        self.stats.bytes_received += len as u64;
+        self.stats.packets_received += 1;
+        self.stats.last_receive_time = Some(received_at);
+        Ok((len, addr))
+    }
+    
+    fn read_datagram(&mut self) -> Result<(usize, SocketAddr, Instant), IoError> {
+        let socket = match &self.transport {
+            Transport::Udp(socket) => socket,
+            Transport::Memory(endpoint) => {
+                if endpoint.network.take_unreachable(endpoint.addr) {
+                    return Err(ErrorKind::ConnectionRefused.into());
+                }
+                let (data, from) = endpoint.network.take(endpoint.addr).ok_or(ErrorKind::WouldBlock)?;
+                let len = data.len();
+                endpoint.network.recycle(std::mem::replace(&mut self.recv_buffer, data));
+                return Ok((len, from, self.clock.now()));
+            }
+            #[cfg(feature = "tokio")]
+            Transport::Tokio(socket) => {
+                let (len, addr) = socket.try_recv_from(&mut self.recv_buffer)?;
+                return Ok((len, addr, self.clock.now()));
             }
-            Err(e) => Err(e.into()),
+        };
+        #[cfg(all(feature = "iocp", windows))]
+        if let Some(completion_port) = &mut self.completion_port {
+            let (len, addr) = completion_port.recv_from(&mut self.recv_buffer)?;
+            return Ok((len, addr, self.clock.now()));
         }
+        #[cfg(all(feature = "kernel-timestamps", target_os = "linux"))]
+        if self.kernel_timestamps {
+            let (len, addr, timestamp) = kernel::recv_from(socket, &mut self.recv_buffer)?;
+            return Ok((len, addr, timestamp.map_or_else(|| self.clock.now(), kernel::to_instant)));
+        }
+        let (len, addr) = socket.recv_from(&mut self.recv_buffer)?;
+        Ok((len, addr, self.clock.now()))
     }
     
     /// Sends data to the connected address (socket must be connected first)
     pub fn send(&mut self, data: &[u8]) -> Result<usize, SocketError> {
-        let sent = self.socket.send(data)?;
+        let sent = match &self.transport {
+            Transport::Udp(socket) => socket.send(data)?,
+            Transport::Memory(endpoint) => {
+                let peer = endpoint.network.lock().peers.get(&endpoint.addr).copied().ok_or(SocketError::InvalidAddress)?;
+                endpoint.network.deliver(endpoint.addr, peer, data)?
+            }
+            #[cfg(feature = "tokio")]
+            Transport::Tokio(socket) => socket.try_send(data)?,
+        };
         self.stats.bytes_sent += sent as u64;
         self.stats.packets_sent += 1;
-        self.stats.last_send_time = Some(Instant::now());
+        self.stats.last_send_time = Some(self.clock.now());
         Ok(sent)
     }
     
     /// Receives data from the connected address
     pub fn recv(&mut self) -> Result<&[u8], SocketError> {
-        match self.socket.recv(&mut self.recv_buffer) {
-            Ok(len) => {
-                self.stats.bytes_received += len as u64;
-                self.stats.packets_received += 1;
-                self.stats.last_receive_time = Some(Instant::now());
-                Ok(&self.recv_buffer[..len])
+        // Completion ports and memory sockets only read from any address
+        let filtered = matches!(self.transport, Transport::Memory(_)) || self.iocp;
+        let len = match &self.transport {
+            Transport::Udp(socket) if !filtered => socket.recv(&mut self.recv_buffer)?,
+            #[cfg(feature = "tokio")]
+            Transport::Tokio(socket) => socket.try_recv(&mut self.recv_buffer)?,
+            _ => {
+                let peer = self.connected_peer()?;
+                loop {
+                    let (len, from, _) = self.read_datagram()?;
+                    // A connected UDP socket only sees its peer's datagrams
+                    if from == peer {
+                        break len;
+                    }
+                }
             }
-            Err(e) => Err(e.into()),
+        };
+        self.stats.bytes_received += len as u64;
+        self.stats.packets_received += 1;
+        self.stats.last_receive_time = Some(self.clock.now());
+        Ok(&self.recv_buffer[..len])
+    }
+    
+    fn connected_peer(&self) -> Result<SocketAddr, SocketError> {
+        match &self.transport {
+            Transport::Memory(endpoint) => endpoint.network.lock().peers.get(&endpoint.addr).copied().ok_or(SocketError::InvalidAddress),
+            Transport::Udp(socket) => Ok(socket.peer_addr()?),
+            #[cfg(feature = "tokio")]
+            Transport::Tokio(socket) => Ok(socket.peer_addr()?),
+        }
+    }
+    
+    /// Blocks until a datagram is ready to be read or `timeout` passes.
+    ///
+    /// Returns whether data is ready; nothing is consumed. The socket stays non-blocking
+    /// for every other call. Memory sockets never block, since nothing else could deliver
+    /// while the only thread waits.
+    pub fn wait_readable(&mut self, timeout: Duration) -> Result<bool, SocketError> {
+        #[cfg(all(feature = "iocp", windows))]
+        if let Some(completion_port) = &mut self.completion_port {
+            return Ok(completion_port.wait(timeout)?);
+        }
+        let socket = match &self.transport {
+            Transport::Udp(socket) => socket,
+            Transport::Memory(endpoint) => return Ok(endpoint.network.has_pending(endpoint.addr)),
+            // A reactor's socket cannot block the thread; let the read find out
+            #[cfg(feature = "tokio")]
+            Transport::Tokio(_) => return Ok(true),
+        };
+        // A zero read timeout means "block forever", so overdue callers still get a brief check
+        let timeout = timeout.max(Duration::from_millis(1));
+        let previous_timeout = socket.read_timeout()?;
+        socket.set_nonblocking(false)?;
+        socket.set_read_timeout(Some(timeout))?;
+        let result = socket.peek_from(&mut [0u8; 1]);
+        socket.set_read_timeout(previous_timeout)?;
+        socket.set_nonblocking(true)?;
+        
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(false),
+            // Anything else (e.g. a datagram larger than the peek buffer on Windows) is
+            // left for recv_from to report
+            Err(_) => Ok(true),
         }
     }
     
-    /// Sets the read timeout for the socket
+    /// Waits without blocking the thread until a datagram is ready or `timeout` passes, the
+    /// async counterpart of [`UdpSocket::wait_readable`].
+    ///
+    /// Only tokio sockets (see [`AsyncUdpSocket`]) can be awaited; others are polled every millisecond.
+    #[cfg(feature = "tokio")]
+    pub async fn readable(&self, timeout: Duration) -> Result<bool, SocketError> {
+        match &self.transport {
+            Transport::Tokio(socket) => match tokio::time::timeout(timeout, socket.readable()).await {
+                Ok(ready) => ready.map(|()| true).map_err(SocketError::from),
+                Err(_) => Ok(false),
+            },
+            Transport::Memory(endpoint) if endpoint.network.has_pending(endpoint.addr) => Ok(true),
+            _ => {
+                tokio::time::sleep(timeout.min(Duration::from_millis(1))).await;
+                Ok(true)
+            }
+        }
+    }
+    
+    /// Sets the read timeout for the socket; memory sockets never block, so ignore it
     pub fn set_read_timeout(&self, dur: Option<Duration>) -> Result<(), SocketError> {
-        self.socket.set_read_timeout(dur)?;
+        if let Transport::Udp(socket) = &self.transport {
+            socket.set_read_timeout(dur)?;
+        }
         Ok(())
     }
     
-    /// Sets the write timeout for the socket
+    /// Sets the write timeout for the socket; memory sockets never block, so ignore it
     pub fn set_write_timeout(&self, dur: Option<Duration>) -> Result<(), SocketError> {
-        self.socket.set_write_timeout(dur)?;
+        if let Transport::Udp(socket) = &self.transport {
+            socket.set_write_timeout(dur)?;
+        }
         Ok(())
     }
     
@@ -125,4 +481,529 @@ impl UdpSocket {
     pub fn reset_stats(&mut self) {
         self.stats = SocketStats::default();
     }
-}
\ No newline at end of file
+}
+
+/// A socket registered with the tokio reactor, so connections and servers can run inside an
+/// async runtime without a polling thread of their own.
+///
+/// Drive a client with [`Connection::poll`](crate::connection::Connection::poll) and
+/// [`Connection::recv`](crate::connection::Connection::recv), or hand the socket to
+/// [`Server::with_socket`](crate::server::Server::with_socket) with [`AsyncUdpSocket::into_inner`]
+/// and await [`Server::poll`](crate::server::Server::poll).
+#[cfg(feature = "tokio")]
+pub struct AsyncUdpSocket {
+    socket: UdpSocket,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncUdpSocket {
+    /// Binds a socket to the current runtime's reactor.
+    ///
+    /// # Panics
+    ///
+    /// Outside a tokio runtime, or in one without I/O enabled.
+    pub fn bind(addr: SocketAddr) -> Result<Self, SocketError> {
+        let socket = StdUdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        let socket = tokio::net::UdpSocket::from_std(socket)?;
+        Ok(Self { socket: UdpSocket::with_transport(Transport::Tokio(socket)) })
+    }
+    
+    /// Connects the socket to a specific remote address
+    pub async fn connect(&self, addr: SocketAddr) -> Result<(), SocketError> {
+        match &self.socket.transport {
+            Transport::Tokio(socket) => Ok(socket.connect(addr).await?),
+            _ => self.socket.connect(addr),
+        }
+    }
+    
+    pub fn local_addr(&self) -> Result<SocketAddr, SocketError> {
+        self.socket.local_addr()
+    }
+    
+    /// Waits until a datagram is ready to be read.
+    pub async fn readable(&self) -> Result<(), SocketError> {
+        match &self.socket.transport {
+            Transport::Tokio(socket) => Ok(socket.readable().await?),
+            _ => Ok(()),
+        }
+    }
+    
+    /// Sends data to a specific address, waiting while the send buffer is full.
+    pub async fn send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize, SocketError> {
+        loop {
+            match self.socket.send_to(data, addr) {
+                Err(SocketError::WouldBlock) => {
+                    if let Transport::Tokio(socket) = &self.socket.transport {
+                        socket.writable().await?;
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+    
+    /// Receives the next datagram from any address.
+    pub async fn recv_from(&mut self) -> Result<(&[u8], SocketAddr), SocketError> {
+        loop {
+            match self.socket.recv_len_from() {
+                Ok((len, addr)) => return Ok((&self.socket.recv_buffer[..len], addr)),
+                Err(SocketError::WouldBlock) => self.readable().await?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    
+    /// The socket as the sync API sees it, e.g. for [`Connection::update`](crate::connection::Connection::update).
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+    
+    pub fn get_mut(&mut self) -> &mut UdpSocket {
+        &mut self.socket
+    }
+    
+    pub fn into_inner(self) -> UdpSocket {
+        self.socket
+    }
+}
+
+// First port handed out for memory sockets bound to port 0, as the OS would
+const EPHEMERAL_PORTS: u16 = 49152;
+
+// Most spare datagram buffers a memory network keeps for reuse
+const POOLED_BUFFERS: usize = 256;
+
+#[derive(Default)]
+struct MemoryState {
+    queues: HashMap<SocketAddr, VecDeque<(Vec<u8>, SocketAddr)>>,
+    peers: HashMap<SocketAddr, SocketAddr>,
+    pool: Vec<Vec<u8>>,
+    // Unreachable reports waiting for each sender, when they are emulated
+    report_unreachable: bool,
+    unreachable: HashMap<SocketAddr, u32>,
+    // Emulated send failures left for each sender
+    send_failures: HashMap<SocketAddr, (SendErrorClass, u32)>,
+}
+
+/// In-process datagram network for tests; see [`UdpSocket::bind_memory`].
+///
+/// Clones share the network. Datagrams arrive in order and are never lost, unless a socket's
+/// simulator says otherwise; ones sent to an address nobody is bound to vanish, as with UDP.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    fn lock(&self) -> MutexGuard<'_, MemoryState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    
+    /// Emulates platforms that report ICMP port unreachable: a datagram sent to an address
+    /// nobody is bound to fails the sender's next receive with `SocketError::PeerUnreachable`.
+    pub fn set_report_unreachable(&self, report: bool) {
+        let mut state = self.lock();
+        state.report_unreachable = report;
+        state.unreachable.clear();
+    }
+    
+    fn bind(&self, mut addr: SocketAddr) -> Result<SocketAddr, IoError> {
+        let mut state = self.lock();
+        if addr.port() == 0 {
+            let free = (EPHEMERAL_PORTS..=u16::MAX).find(|&port| !state.queues.contains_key(&SocketAddr::new(addr.ip(), port)));
+            addr.set_port(free.ok_or(ErrorKind::AddrInUse)?);
+        }
+        if state.queues.contains_key(&addr) {
+            return Err(ErrorKind::AddrInUse.into());
+        }
+        state.queues.insert(addr, VecDeque::new());
+        Ok(addr)
+    }
+    
+    /// Fails the next `count` sends from `from` with an error of `class`, as a full send
+    /// buffer, an oversized datagram or a lost route would on a real socket.
+    pub fn fail_sends(&self, from: SocketAddr, class: SendErrorClass, count: u32) {
+        let mut state = self.lock();
+        if count == 0 {
+            state.send_failures.remove(&from);
+        } else {
+            state.send_failures.insert(from, (class, count));
+        }
+    }
+    
+    fn deliver(&self, from: SocketAddr, to: SocketAddr, data: &[u8]) -> Result<usize, SocketError> {
+        let mut state = self.lock();
+        if let Some((class, count)) = state.send_failures.get_mut(&from) {
+            let class = *class;
+            *count -= 1;
+            if *count == 0 {
+                state.send_failures.remove(&from);
+            }
+            return Err(match class {
+                SendErrorClass::WouldBlock => SocketError::WouldBlock,
+                SendErrorClass::MessageTooLarge => SocketError::MessageTooLarge,
+                SendErrorClass::Unreachable => SocketError::NetworkUnreachable,
+                SendErrorClass::Other => SocketError::Io(ErrorKind::Other.into()),
+            });
+        }
+        if state.queues.contains_key(&to) {
+            let mut buffer = state.pool.pop().unwrap_or_default();
+            buffer.clear();
+            buffer.extend_from_slice(data);
+            if let Some(queue) = state.queues.get_mut(&to) {
+                queue.push_back((buffer, from));
+            }
+        } else if state.report_unreachable {
+            *state.unreachable.entry(from).or_insert(0) += 1;
+        }
+        Ok(data.len())
+    }
+    
+    fn take_unreachable(&self, addr: SocketAddr) -> bool {
+        let mut state = self.lock();
+        let Some(count) = state.unreachable.get_mut(&addr) else {
+            return false;
+        };
+        *count -= 1;
+        if *count == 0 {
+            state.unreachable.remove(&addr);
+        }
+        true
+    }
+    
+    fn take(&self, addr: SocketAddr) -> Option<(Vec<u8>, SocketAddr)> {
+        self.lock().queues.get_mut(&addr)?.pop_front()
+    }
+    
+    /// Returns a datagram buffer to the pool once its socket is done with it.
+    fn recycle(&self, buffer: Vec<u8>) {
+        let mut state = self.lock();
+        if buffer.capacity() > 0 && state.pool.len() < POOLED_BUFFERS {
+            state.pool.push(buffer);
+        }
+    }
+    
+    fn has_pending(&self, addr: SocketAddr) -> bool {
+        let state = self.lock();
+        state.unreachable.contains_key(&addr) || state.queues.get(&addr).is_some_and(|queue| !queue.is_empty())
+    }
+}
+
+impl fmt::Debug for MemoryNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryNetwork").field("sockets", &self.lock().queues.len()).finish()
+    }
+}
+
+// Unbinds its address when the socket is dropped
+struct MemoryEndpoint {
+    network: MemoryNetwork,
+    addr: SocketAddr,
+}
+
+impl Drop for MemoryEndpoint {
+    fn drop(&mut self) {
+        let mut state = self.network.lock();
+        state.queues.remove(&self.addr);
+        state.peers.remove(&self.addr);
+        state.unreachable.remove(&self.addr);
+    }
+}
+
+// SO_TIMESTAMPING software receive stamps, read back from recvmsg control messages
+#[cfg(all(feature = "kernel-timestamps", target_os = "linux"))]
+mod kernel {
+    use std::io::{Error as IoError, ErrorKind};
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+    use std::os::unix::io::AsRawFd;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+    
+    pub fn enable_timestamps(socket: &UdpSocket) -> Result<(), IoError> {
+        let flags = (libc::SOF_TIMESTAMPING_RX_SOFTWARE | libc::SOF_TIMESTAMPING_SOFTWARE) as libc::c_int;
+        // SAFETY: flags outlives the call and its size is passed alongside it
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TIMESTAMPING,
+                &flags as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result == 0 { Ok(()) } else { Err(IoError::last_os_error()) }
+    }
+    
+    pub fn recv_from(socket: &UdpSocket, buffer: &mut [u8]) -> Result<(usize, SocketAddr, Option<SystemTime>), IoError> {
+        // SAFETY: all-zero is a valid sockaddr_storage and msghdr
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut iov = libc::iovec {
+            iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buffer.len(),
+        };
+        // u64s keep the control buffer aligned for cmsghdr
+        let mut control = [0u64; 16];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut storage as *mut libc::sockaddr_storage as *mut libc::c_void;
+        msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+        
+        // SAFETY: every pointer in msg refers to a live local buffer of the stated length
+        let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+        if len < 0 {
+            return Err(IoError::last_os_error());
+        }
+        let addr = socket_addr(&storage)?;
+        
+        let mut timestamp = None;
+        // SAFETY: the CMSG macros only walk the control buffer the kernel just filled
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_TIMESTAMPING {
+                    // scm_timestamping: software stamp first, then two hardware stamps
+                    let stamp = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::timespec);
+                    if stamp.tv_sec != 0 || stamp.tv_nsec != 0 {
+                        timestamp = Some(UNIX_EPOCH + Duration::new(stamp.tv_sec as u64, stamp.tv_nsec as u32));
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        Ok((len as usize, addr, timestamp))
+    }
+    
+    /// Maps a wall clock kernel stamp onto the monotonic clock by its age.
+    pub fn to_instant(timestamp: SystemTime) -> Instant {
+        let now = Instant::now();
+        let age = SystemTime::now().duration_since(timestamp).unwrap_or_default();
+        now.checked_sub(age).unwrap_or(now)
+    }
+    
+    fn socket_addr(storage: &libc::sockaddr_storage) -> Result<SocketAddr, IoError> {
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                // SAFETY: ss_family says the storage holds a sockaddr_in
+                let addr = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+                Ok(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(addr.sin_port))))
+            }
+            libc::AF_INET6 => {
+                // SAFETY: ss_family says the storage holds a sockaddr_in6
+                let addr = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+                Ok(SocketAddr::V6(SocketAddrV6::new(ip, u16::from_be(addr.sin6_port), addr.sin6_flowinfo, addr.sin6_scope_id)))
+            }
+            _ => Err(IoError::new(ErrorKind::InvalidData, "Unsupported address family")),
+        }
+    }
+}
+
+// Overlapped WSARecvFrom calls kept posted on an I/O completion port. Datagrams land in the
+// posted buffers without a syscall each, and one GetQueuedCompletionStatusEx call collects
+// every receive that finished; a filled buffer is swapped with the socket's receive buffer,
+// which is posted in its place
+#[cfg(all(feature = "iocp", windows))]
+mod iocp {
+    use std::collections::VecDeque;
+    use std::io::{Error as IoError, ErrorKind};
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+    use std::os::windows::io::AsRawSocket;
+    use std::ptr;
+    use std::time::Duration;
+    
+    use windows_sys::Win32::Foundation::{CloseHandle, FALSE, HANDLE, WAIT_TIMEOUT};
+    use windows_sys::Win32::Networking::WinSock::{
+        WSAGetLastError, WSAGetOverlappedResult, WSARecvFrom, AF_INET, AF_INET6, SOCKADDR, SOCKADDR_IN,
+        SOCKADDR_IN6, SOCKADDR_STORAGE, SOCKET, WSABUF, WSA_IO_PENDING,
+    };
+    use windows_sys::Win32::System::IO::{CancelIoEx, CreateIoCompletionPort, GetQueuedCompletionStatusEx, OVERLAPPED, OVERLAPPED_ENTRY};
+    
+    // Receives kept posted, each with a buffer the size of the largest datagram
+    const POSTED_RECEIVES: usize = 32;
+    const BUFFER_BYTES: usize = 65536;
+    const INFINITE: u32 = u32::MAX;
+    
+    // The OVERLAPPED comes first, so a completion's pointer leads back to its receive
+    #[repr(C)]
+    struct Receive {
+        overlapped: OVERLAPPED,
+        index: usize,
+        buffer: Vec<u8>,
+        from: SOCKADDR_STORAGE,
+        from_len: i32,
+        flags: u32,
+    }
+    
+    pub struct CompletionPort {
+        port: HANDLE,
+        socket: SOCKET,
+        // Never resized, so the addresses handed to the kernel stay put while a receive is posted
+        receives: Vec<Receive>,
+        posted: usize,
+        // Finished receives by index, in completion order
+        ready: VecDeque<(usize, Result<usize, IoError>)>,
+    }
+    
+    // SAFETY: the port and socket handles may be used from any thread, and the receive
+    // buffers are only touched through &mut self
+    unsafe impl Send for CompletionPort {}
+    
+    impl CompletionPort {
+        /// Associates the socket with a new completion port and posts every receive.
+        /// The association is permanent, so the socket must not be read any other way after.
+        pub fn new(socket: &UdpSocket) -> Result<Self, IoError> {
+            let raw = socket.as_raw_socket() as SOCKET;
+            // SAFETY: raw is a live socket opened for overlapped I/O, as std opens them
+            let port = unsafe { CreateIoCompletionPort(raw as HANDLE, ptr::null_mut(), 0, 1) };
+            if port.is_null() {
+                return Err(IoError::last_os_error());
+            }
+            let receives = (0..POSTED_RECEIVES).map(|index| Receive {
+                // SAFETY: all-zero is a valid OVERLAPPED and SOCKADDR_STORAGE
+                overlapped: unsafe { mem::zeroed() },
+                index,
+                buffer: vec![0; BUFFER_BYTES],
+                from: unsafe { mem::zeroed() },
+                from_len: 0,
+                flags: 0,
+            }).collect();
+            let mut completion_port = Self { port, socket: raw, receives, posted: 0, ready: VecDeque::new() };
+            for index in 0..POSTED_RECEIVES {
+                completion_port.post(index);
+            }
+            Ok(completion_port)
+        }
+        
+        /// Takes the next received datagram, swapping its buffer with `buffer`.
+        pub fn recv_from(&mut self, buffer: &mut Vec<u8>) -> Result<(usize, SocketAddr), IoError> {
+            if self.ready.is_empty() {
+                self.collect(0)?;
+            }
+            let (index, result) = self.ready.pop_front().ok_or(ErrorKind::WouldBlock)?;
+            let received = result.and_then(|len| Ok((len, socket_addr(&self.receives[index].from)?)));
+            if received.is_ok() {
+                mem::swap(buffer, &mut self.receives[index].buffer);
+            }
+            self.post(index);
+            received
+        }
+        
+        /// Waits up to `timeout` for a receive to finish; returns whether one is ready.
+        pub fn wait(&mut self, timeout: Duration) -> Result<bool, IoError> {
+            if self.ready.is_empty() {
+                self.collect(timeout.as_millis().min(INFINITE as u128 - 1) as u32)?;
+            }
+            Ok(!self.ready.is_empty())
+        }
+        
+        fn post(&mut self, index: usize) {
+            let receive = &mut self.receives[index];
+            receive.buffer.resize(BUFFER_BYTES, 0);
+            // SAFETY: all-zero is a valid OVERLAPPED, and a fresh one is needed per call
+            receive.overlapped = unsafe { mem::zeroed() };
+            receive.from_len = mem::size_of::<SOCKADDR_STORAGE>() as i32;
+            receive.flags = 0;
+            let buffers = WSABUF { len: BUFFER_BYTES as u32, buf: receive.buffer.as_mut_ptr() };
+            // SAFETY: the buffer, address and OVERLAPPED are neither moved nor freed until the
+            // completion is collected; the WSABUF is copied by the call
+            let result = unsafe {
+                WSARecvFrom(
+                    self.socket,
+                    &buffers,
+                    1,
+                    ptr::null_mut(),
+                    &mut receive.flags,
+                    &mut receive.from as *mut SOCKADDR_STORAGE as *mut SOCKADDR,
+                    &mut receive.from_len,
+                    &mut receive.overlapped,
+                    None,
+                )
+            };
+            // Immediate completions are queued on the port as well
+            let error = if result == 0 { WSA_IO_PENDING } else { unsafe { WSAGetLastError() } };
+            if error == WSA_IO_PENDING {
+                self.posted += 1;
+            } else {
+                // e.g. an ICMP report from an earlier send; the receive is posted again once read
+                self.ready.push_back((index, Err(IoError::from_raw_os_error(error))));
+            }
+        }
+        
+        fn collect(&mut self, timeout_ms: u32) -> Result<(), IoError> {
+            // SAFETY: all-zero is a valid OVERLAPPED_ENTRY
+            let mut entries: [OVERLAPPED_ENTRY; POSTED_RECEIVES] = unsafe { mem::zeroed() };
+            let mut removed = 0u32;
+            // SAFETY: entries has room for the count passed
+            let ok = unsafe {
+                GetQueuedCompletionStatusEx(self.port, entries.as_mut_ptr(), POSTED_RECEIVES as u32, &mut removed, timeout_ms, FALSE)
+            };
+            if ok == 0 {
+                let error = IoError::last_os_error();
+                if error.raw_os_error() == Some(WAIT_TIMEOUT as i32) {
+                    return Ok(());
+                }
+                return Err(error);
+            }
+            for entry in &entries[..removed as usize] {
+                let (mut bytes, mut flags) = (0u32, 0u32);
+                // SAFETY: every OVERLAPPED queued on this port is the head of one of our receives
+                let index = unsafe { (*(entry.lpOverlapped as *const Receive)).index };
+                let ok = unsafe { WSAGetOverlappedResult(self.socket, entry.lpOverlapped, &mut bytes, FALSE, &mut flags) };
+                let result = if ok != 0 {
+                    Ok(bytes as usize)
+                } else {
+                    Err(IoError::from_raw_os_error(unsafe { WSAGetLastError() }))
+                };
+                self.posted -= 1;
+                self.ready.push_back((index, result));
+            }
+            Ok(())
+        }
+    }
+    
+    impl Drop for CompletionPort {
+        fn drop(&mut self) {
+            // The kernel may write into posted receives until their cancellations are collected
+            // SAFETY: cancelling I/O on a handle we own; failure only means nothing was pending
+            unsafe { CancelIoEx(self.socket as HANDLE, ptr::null()) };
+            while self.posted > 0 {
+                if self.collect(INFINITE - 1).is_err() {
+                    // Leaking beats freeing memory the kernel may still write to
+                    mem::forget(mem::take(&mut self.receives));
+                    break;
+                }
+            }
+            // SAFETY: the port handle is ours and closed once
+            unsafe { CloseHandle(self.port) };
+        }
+    }
+    
+    fn socket_addr(storage: &SOCKADDR_STORAGE) -> Result<SocketAddr, IoError> {
+        match storage.ss_family {
+            AF_INET => {
+                // SAFETY: ss_family says the storage holds a SOCKADDR_IN
+                let addr = unsafe { &*(storage as *const SOCKADDR_STORAGE as *const SOCKADDR_IN) };
+                let ip = Ipv4Addr::from(u32::from_be(unsafe { addr.sin_addr.S_un.S_addr }));
+                Ok(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(addr.sin_port))))
+            }
+            AF_INET6 => {
+                // SAFETY: ss_family says the storage holds a SOCKADDR_IN6
+                let addr = unsafe { &*(storage as *const SOCKADDR_STORAGE as *const SOCKADDR_IN6) };
+                let ip = Ipv6Addr::from(unsafe { addr.sin6_addr.u.Byte });
+                let scope_id = unsafe { addr.Anonymous.sin6_scope_id };
+                Ok(SocketAddr::V6(SocketAddrV6::new(ip, u16::from_be(addr.sin6_port), addr.sin6_flowinfo, scope_id)))
+            }
+            _ => Err(IoError::new(ErrorKind::InvalidData, "Unsupported address family")),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+// stats.rs - Point-in-time copies of endpoint statistics, and what changed between two
+//
+// Reporters that export numbers every few seconds want per-interval counts, and reading a live
+// connection's counters field by field between updates mixes values from different moments.
+// A snapshot copies everything an endpoint counts in one call, stamped with the endpoint's
+// clock; `since` turns two snapshots into the counts for the interval between them, with gauges
+// (RTT, loss, queue depths, memory) taken from the later one. Reporters that prefer to start
+// from zero call `stats_reset` instead, which clears the counters and leaves gauges alone.
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::{channel::ChannelStats, server::ServerStats, socket::SendErrorCounts, NetworkStats};
+
+/// Statistics that can be split into intervals.
+pub trait IntervalStats: Clone {
+    /// Counters minus those in `earlier`, gauges as in `self`. A counter reset in between
+    /// counts from zero.
+    fn since(&self, earlier: &Self) -> Self;
+}
+
+/// Statistics copied at one moment.
+#[derive(Debug, Clone)]
+pub struct StatsSnapshot<T> {
+    pub taken_at: Instant,
+    pub stats: T,
+}
+
+impl<T: IntervalStats> StatsSnapshot<T> {
+    /// What was counted between `earlier` and this snapshot.
+    pub fn since(&self, earlier: &StatsSnapshot<T>) -> StatsInterval<T> {
+        StatsInterval {
+            elapsed: self.taken_at.saturating_duration_since(earlier.taken_at),
+            stats: self.stats.since(&earlier.stats),
+        }
+    }
+}
+
+/// Counts for the time between two snapshots.
+#[derive(Debug, Clone)]
+pub struct StatsInterval<T> {
+    pub elapsed: Duration,
+    pub stats: T,
+}
+
+impl<T> StatsInterval<T> {
+    /// `count` spread over the interval, e.g. `interval.per_second(interval.stats.network.bytes_sent)`.
+    pub fn per_second(&self, count: u64) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 { count as f64 / seconds } else { 0.0 }
+    }
+}
+
+/// Everything one connection counts.
+#[derive(Debug, Clone)]
+pub struct ConnectionStats {
+    pub network: NetworkStats,
+    pub channels: Vec<ChannelStats>,
+    pub control: ChannelStats,
+}
+
+/// Everything a server counts, with each client's connection.
+#[derive(Debug, Clone)]
+pub struct ServerStatsReport {
+    pub server: ServerStats,
+    pub clients: BTreeMap<SocketAddr, ConnectionStats>,
+}
+
+// A counter lower than before was reset in between, so all of it was counted since
+fn counted(now: u64, before: u64) -> u64 {
+    if now >= before { now - before } else { now }
+}
+
+impl IntervalStats for NetworkStats {
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            packets_sent: counted(self.packets_sent, earlier.packets_sent),
+            packets_received: counted(self.packets_received, earlier.packets_received),
+            bytes_sent: counted(self.bytes_sent, earlier.bytes_sent),
+            bytes_received: counted(self.bytes_received, earlier.bytes_received),
+            peer_unreachable: counted(self.peer_unreachable, earlier.peer_unreachable),
+            send_errors: self.send_errors.since(&earlier.send_errors),
+            sequence_gaps: counted(self.sequence_gaps, earlier.sequence_gaps),
+            fragments_sent: counted(self.fragments_sent, earlier.fragments_sent),
+            fragments_expired: counted(self.fragments_expired, earlier.fragments_expired),
+            stale_packets_dropped: counted(self.stale_packets_dropped, earlier.stale_packets_dropped),
+            replayed_packets_dropped: counted(self.replayed_packets_dropped, earlier.replayed_packets_dropped),
+            ..self.clone()
+        }
+    }
+}
+
+impl IntervalStats for SendErrorCounts {
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            would_block: counted(self.would_block, earlier.would_block),
+            message_too_large: counted(self.message_too_large, earlier.message_too_large),
+            unreachable: counted(self.unreachable, earlier.unreachable),
+            other: counted(self.other, earlier.other),
+        }
+    }
+}
+
+impl IntervalStats for ChannelStats {
+    fn since(&self, earlier: &Self) -> Self {
+        // Savings can go negative, so a reset is told apart by the sends counted
+        let compression_saved = if self.messages_sent < earlier.messages_sent {
+            self.compression_saved
+        } else {
+            self.compression_saved - earlier.compression_saved
+        };
+        Self {
+            messages_sent: counted(self.messages_sent, earlier.messages_sent),
+            messages_received: counted(self.messages_received, earlier.messages_received),
+            bytes_sent: counted(self.bytes_sent, earlier.bytes_sent),
+            bytes_received: counted(self.bytes_received, earlier.bytes_received),
+            duplicates_dropped: counted(self.duplicates_dropped, earlier.duplicates_dropped),
+            messages_superseded: counted(self.messages_superseded, earlier.messages_superseded),
+            receive_dropped: counted(self.receive_dropped, earlier.receive_dropped),
+            receive_backpressured: counted(self.receive_backpressured, earlier.receive_backpressured),
+            starved_updates: counted(self.starved_updates, earlier.starved_updates),
+            compression_skipped: counted(self.compression_skipped, earlier.compression_skipped),
+            compression_ineffective: counted(self.compression_ineffective, earlier.compression_ineffective),
+            compression_saved,
+            ..self.clone()
+        }
+    }
+}
+
+impl IntervalStats for ConnectionStats {
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            network: self.network.since(&earlier.network),
+            channels: self.channels.iter()
+                .map(|channel| match earlier.channels.iter().find(|before| before.id == channel.id) {
+                    Some(before) => channel.since(before),
+                    None => channel.clone(),
+                })
+                .collect(),
+            control: self.control.since(&earlier.control),
+        }
+    }
+}
+
+impl IntervalStats for ServerStats {
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            packets_received: counted(self.packets_received, earlier.packets_received),
+            packets_filtered: counted(self.packets_filtered, earlier.packets_filtered),
+            invalid_packets: counted(self.invalid_packets, earlier.invalid_packets),
+            connection_requests_throttled: counted(self.connection_requests_throttled, earlier.connection_requests_throttled),
+            observer_packets_rejected: counted(self.observer_packets_rejected, earlier.observer_packets_rejected),
+            receive_budget_exhausted: counted(self.receive_budget_exhausted, earlier.receive_budget_exhausted),
+            packets_over_budget: counted(self.packets_over_budget, earlier.packets_over_budget),
+            stale_sessions_replaced: counted(self.stale_sessions_replaced, earlier.stale_sessions_replaced),
+            stale_session_replies: counted(self.stale_session_replies, earlier.stale_session_replies),
+            connections_revoked: counted(self.connections_revoked, earlier.connections_revoked),
+            connect_tokens_rejected: counted(self.connect_tokens_rejected, earlier.connect_tokens_rejected),
+            join_queue_entered: counted(self.join_queue_entered, earlier.join_queue_entered),
+            join_queue_promoted: counted(self.join_queue_promoted, earlier.join_queue_promoted),
+            peer_unreachable: counted(self.peer_unreachable, earlier.peer_unreachable),
+            send_errors: self.send_errors.since(&earlier.send_errors),
+            connection_errors: counted(self.connection_errors, earlier.connection_errors),
+        }
+    }
+}
+
+impl IntervalStats for ServerStatsReport {
+    /// Clients that connected during the interval count from zero; ones that left are dropped.
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            server: self.server.since(&earlier.server),
+            clients: self.clients.iter()
+                .map(|(addr, stats)| {
+                    let stats = match earlier.clients.get(addr) {
+                        Some(before) => stats.since(before),
+                        None => stats.clone(),
+                    };
+                    (*addr, stats)
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// telemetry.rs - Sampled per-packet metadata for production analytics
+//
+// Cheaper than full packet capture: only a fraction of sent packets are reported, and the
+// callback sees sizes and timing but never payload bytes.
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::packet::PacketType;
+use crate::rng::DeterministicRng;
+
+/// Metadata for one sent packet.
+#[derive(Debug, Clone)]
+pub struct PacketTelemetry {
+    pub remote_addr: SocketAddr,
+    pub sequence: u16,
+    pub packet_type: PacketType,
+    /// Bytes written to the socket
+    pub size: usize,
+    /// Channel of a payload packet
+    pub channel: Option<u8>,
+    /// Messages coalesced into a payload packet
+    pub messages: usize,
+    /// Connection RTT estimate in milliseconds when the packet was sent
+    pub rtt: f32,
+    pub sent_at: Instant,
+}
+
+/// Calls a callback with a random sample of sent packets.
+///
+/// Cloned configs share the same callback, so one sampler can collect from every
+/// connection of a server.
+#[derive(Clone)]
+pub struct TelemetrySampler {
+    rate: f32,
+    callback: Arc<dyn Fn(&PacketTelemetry) + Send + Sync>,
+}
+
+impl TelemetrySampler {
+    /// `rate` is the fraction of packets sampled, clamped to `[0, 1]`.
+    pub fn new<F>(rate: f32, callback: F) -> Self
+    where
+        F: Fn(&PacketTelemetry) + Send + Sync + 'static,
+    {
+        Self {
+            rate: rate.clamp(0.0, 1.0),
+            callback: Arc::new(callback),
+        }
+    }
+
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    /// Whether the next packet should be reported; callers skip building metadata otherwise.
+    pub(crate) fn should_sample(&self, rng: &mut DeterministicRng) -> bool {
+        self.rate >= 1.0 || (self.rate > 0.0 && rng.next_f32() < self.rate)
+    }
+
+    pub(crate) fn report(&self, telemetry: &PacketTelemetry) {
+        (self.callback)(telemetry)
+    }
+}
+
+impl fmt::Debug for TelemetrySampler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TelemetrySampler").field("rate", &self.rate).finish_non_exhaustive()
+    }
+}
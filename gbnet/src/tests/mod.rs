@@ -1,5 +1,13 @@
 // src/tests/mod.rs - Centralized unit tests for gbnet
 
+// Lets tests audit hot paths for heap allocations
+#[global_allocator]
+static ALLOCATOR: crate::alloc_audit::CountingAllocator = crate::alloc_audit::CountingAllocator::new();
+
+// Bits of the debug-packets tag written ahead of each derived message when it is enabled
+#[cfg(test)]
+const TAG_BITS: usize = if crate::debug_packets::ENABLED { crate::debug_packets::TAG_BITS } else { 0 };
+
 #[cfg(test)]
 pub mod serialize_tests;
 
@@ -10,6 +10,7 @@ use crate::{
 };
 use std::net::{SocketAddr, IpAddr, Ipv4Addr};
 use std::time::Instant;
+use super::TAG_BITS;
 
 #[test]
 fn test_socket_basic() {
@@ -33,6 +34,37 @@ fn test_packet_construction() {
     assert!(packet.payload.is_empty());
 }
 
+#[test]
+fn test_packet_header_extensions() {
+    use crate::packet::{header_extension, PAYLOAD_HEADER_BYTES};
+    
+    let header = PacketHeader { protocol_id: 0x12345678, sequence: 7, ack: 6, ack_bits: 1 };
+    let plain = Packet::new(header.clone(), PacketType::Payload { channel: 3, is_fragment: false })
+        .with_payload(vec![9, 8, 7]);
+    let bytes = plain.serialize().unwrap();
+    assert_eq!(bytes.len(), PAYLOAD_HEADER_BYTES + 3);
+    
+    // Fields round trip, and the block costs exactly its wire size
+    let extended = plain.clone()
+        .with_extension(header_extension::TIMESTAMP, 1234u32.to_le_bytes().to_vec()).unwrap()
+        .with_extension(header_extension::FEC_INDEX, vec![2]).unwrap();
+    let extended_bytes = extended.serialize().unwrap();
+    assert_eq!(extended_bytes.len(), bytes.len() + extended.extensions.wire_size());
+    let decoded = Packet::deserialize(&extended_bytes).unwrap();
+    assert_eq!(decoded.extensions, extended.extensions);
+    assert_eq!(decoded.extensions.get(header_extension::TIMESTAMP), Some(&1234u32.to_le_bytes()[..]));
+    assert_eq!(decoded.extensions.get(header_extension::PATH_PROBE), None);
+    assert_eq!(decoded.payload, vec![9, 8, 7]);
+    assert!(matches!(decoded.packet_type, PacketType::Payload { channel: 3, is_fragment: false }));
+    
+    // Packets without extensions decode with none
+    assert!(Packet::deserialize(&bytes).unwrap().extensions.is_empty());
+    
+    // Ids and lengths the format cannot carry are refused up front
+    assert!(plain.clone().with_extension(header_extension::MAX + 1, Vec::new()).is_err());
+    assert!(plain.with_extension(0, vec![0; 256]).is_err());
+}
+
 #[test]
 fn test_sequence_math() {
     // Basic increment
@@ -166,4 +198,3856 @@ fn test_config_defaults() {
     let channel_config = config.default_channel_config;
     assert_eq!(channel_config.reliability, Reliability::Reliable);
     assert_eq!(channel_config.ordering, Ordering::Ordered);
-}
\ No newline at end of file
+}
+#[test]
+fn test_address_filter() {
+    use crate::filter::AddressFilter;
+    
+    let good = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    let bad = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+    let mut filter = AddressFilter::new();
+    
+    // Empty lists allow everything
+    assert!(filter.is_allowed(good));
+    assert!(filter.is_allowed(bad));
+    
+    filter.deny(bad);
+    assert!(filter.is_allowed(good));
+    assert!(!filter.is_allowed(bad));
+    
+    // A non-empty allow list rejects everything else, and deny still wins
+    filter.allow(good);
+    filter.allow(bad);
+    assert!(filter.is_allowed(good));
+    assert!(!filter.is_allowed(bad));
+    assert!(!filter.is_allowed(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3))));
+    
+    assert!(filter.remove_deny(bad));
+    assert!(filter.is_allowed(bad));
+}
+
+#[test]
+fn test_connection_request_limiter() {
+    use crate::filter::ConnectionRequestLimiter;
+    use std::time::Duration;
+    
+    let flooder = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+    let mut limiter = ConnectionRequestLimiter::new(3, Duration::from_secs(1), 256);
+    let now = Instant::now();
+    
+    for _ in 0..3 {
+        assert!(limiter.allow(flooder, now));
+    }
+    assert!(!limiter.allow(flooder, now));
+    assert!(limiter.estimate(flooder) >= 4);
+    
+    // Other sources are unaffected
+    assert!(limiter.allow(other, now));
+    
+    // A new window resets the counts
+    assert!(limiter.allow(flooder, now + Duration::from_secs(1)));
+}
+
+#[test]
+fn test_key_exchange() {
+    use crate::crypto::{KeyExchange, ServerIdentity};
+    
+    let protocol_id = 0x12345678;
+    let identity = ServerIdentity::generate();
+    let client = KeyExchange::new();
+    let request = client.request_payload();
+    
+    let (challenge, server_keys) = KeyExchange::new()
+        .respond(protocol_id, &request, Some(&identity))
+        .unwrap();
+    let client_keys = client
+        .complete(protocol_id, &challenge, Some(&identity.public_key()))
+        .unwrap();
+    
+    assert_eq!(client_keys.send_key, server_keys.recv_key);
+    assert_eq!(client_keys.recv_key, server_keys.send_key);
+    assert_ne!(client_keys.send_key, client_keys.recv_key);
+}
+
+#[test]
+fn test_key_exchange_rejects_unknown_server() {
+    use crate::crypto::{KeyExchange, ServerIdentity};
+    
+    let protocol_id = 0x12345678;
+    let pinned = ServerIdentity::generate();
+    let impostor = ServerIdentity::generate();
+    
+    // Signed by the wrong identity
+    let client = KeyExchange::new();
+    let (challenge, _) = KeyExchange::new()
+        .respond(protocol_id, &client.request_payload(), Some(&impostor))
+        .unwrap();
+    assert!(client.complete(protocol_id, &challenge, Some(&pinned.public_key())).is_none());
+    
+    // Not signed at all
+    let client = KeyExchange::new();
+    let (challenge, _) = KeyExchange::new()
+        .respond(protocol_id, &client.request_payload(), None)
+        .unwrap();
+    assert!(client.complete(protocol_id, &challenge, Some(&pinned.public_key())).is_none());
+}
+
+#[test]
+fn test_resumption_tickets() {
+    use crate::crypto::SessionKeys;
+    use crate::config::ConnectionRole;
+    use crate::ticket::{parse_resume_request, ResumptionTicket, TicketStore};
+    use std::time::Duration;
+    
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 4000);
+    let keys = SessionKeys { send_key: [1; 32], recv_key: [2; 32] };
+    let server_keys = SessionKeys { send_key: [2; 32], recv_key: [1; 32] };
+    let mut store = TicketStore::new(Duration::from_secs(30), crate::rng::DeterministicRng::new(1));
+    let now = Instant::now();
+    
+    // Client-side persistence round trip
+    let id = store.issue(addr, Some(server_keys.clone()), 7, ConnectionRole::Player);
+    let ticket = ResumptionTicket { id, session_keys: Some(keys.clone()) };
+    assert_eq!(ResumptionTicket::from_bytes(&ticket.to_bytes()), Some(ticket));
+    
+    // A live session's ticket cannot be taken over, even with its keys
+    assert!(store.redeem(&id, now, |_| true).is_none());
+    
+    // Once closed, redeeming needs proof of the session keys, and only works once
+    store.park(addr, Vec::new(), now);
+    let request = ResumptionTicket { id, session_keys: Some(keys.clone()) }.resume_request(0x12345678).unwrap();
+    let (parsed, nonce, proof) = parse_resume_request(&request).unwrap();
+    let forged = SessionKeys { send_key: [3; 32], recv_key: [4; 32] };
+    assert!(!forged.check_resume_proof(true, 0x12345678, &parsed, nonce, proof));
+    assert!(!server_keys.check_resume_proof(true, 0x87654321, &parsed, nonce, proof));
+    assert!(store.redeem(&id, now, |keys| keys.check_resume_proof(true, 0x12345678, &parsed, nonce, &[0; 16])).is_none());
+    let entry = store.redeem(&id, now, |keys| keys.check_resume_proof(true, 0x12345678, &parsed, nonce, proof)).unwrap();
+    assert_eq!(entry.session_keys, Some(server_keys));
+    assert_eq!(entry.session_seed, 7);
+    assert!(store.redeem(&id, now, |_| true).is_none());
+    assert!(parse_resume_request(&id).is_none());
+    assert!(ResumptionTicket { id, session_keys: None }.resume_request(0x12345678).is_none());
+    
+    // Closed connections keep their channels until the ticket expires
+    let id = store.issue(addr, Some(keys.clone()), 0, ConnectionRole::Player);
+    store.park(addr, vec![Channel::new(0, ChannelConfig::default())], now);
+    store.remove_expired(now + Duration::from_secs(31));
+    assert!(store.redeem(&id, now, |_| true).is_none());
+    
+    let id = store.issue(addr, Some(keys.clone()), 0, ConnectionRole::Player);
+    store.park(addr, vec![Channel::new(0, ChannelConfig::default())], now);
+    let entry = store.redeem(&id, now + Duration::from_secs(10), |_| true).unwrap();
+    assert_eq!(entry.channels.map(|channels| channels.len()), Some(1));
+    
+    // Revoked tickets cannot be redeemed
+    let id = store.issue(addr, Some(keys), 0, ConnectionRole::Player);
+    store.revoke(addr);
+    assert!(store.redeem(&id, now, |_| true).is_none());
+}
+
+#[test]
+fn test_connection_extensions() {
+    #[derive(Debug, PartialEq)]
+    struct PlayerState {
+        name: String,
+        score: u32,
+    }
+    
+    let config = NetworkConfig::default();
+    let local = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let remote = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 1234);
+    let mut conn = Connection::new(config, local, remote);
+    assert!(conn.extensions().is_empty());
+    
+    let state = PlayerState { name: "alice".to_string(), score: 0 };
+    assert!(conn.extensions_mut().insert(state).is_none());
+    assert!(conn.extensions_mut().insert(7u32).is_none());
+    conn.extensions_mut().get_mut::<PlayerState>().unwrap().score += 10;
+    
+    assert_eq!(conn.extensions().get::<PlayerState>().unwrap().score, 10);
+    assert_eq!(conn.extensions().len(), 2);
+    
+    // One value per type; inserting again replaces it
+    assert_eq!(conn.extensions_mut().insert(8u32), Some(7));
+    *conn.extensions_mut().get_or_insert_with(|| 0u64) += 1;
+    assert_eq!(conn.extensions().get::<u64>(), Some(&1));
+    
+    let removed = conn.extensions_mut().remove::<PlayerState>().unwrap();
+    assert_eq!(removed.name, "alice");
+    assert!(!conn.extensions().contains::<PlayerState>());
+    assert!(conn.extensions().get::<i32>().is_none());
+}
+
+#[test]
+fn test_message_framing() {
+    use crate::channel::{write_message, read_messages, MESSAGE_HEADER_BYTES};
+    use crate::packet::PAYLOAD_HEADER_BYTES;
+    
+    let header = PacketHeader { protocol_id: 1, sequence: 2, ack: 3, ack_bits: 4 };
+    let packet = Packet::new(header, PacketType::Payload { channel: 7, is_fragment: false });
+    assert_eq!(packet.serialize().unwrap().len(), PAYLOAD_HEADER_BYTES);
+    
+    let mut payload = Vec::new();
+    write_message(&mut payload, 0, b"hello");
+    write_message(&mut payload, 1, b"");
+    write_message(&mut payload, 65535, b"world");
+    assert_eq!(payload.len(), 3 * MESSAGE_HEADER_BYTES + 10);
+    assert_eq!(
+        read_messages(&payload),
+        Some(vec![(0, b"hello".to_vec()), (1, Vec::new()), (65535, b"world".to_vec())])
+    );
+    
+    // Truncated messages are rejected
+    assert_eq!(read_messages(&payload[..payload.len() - 1]), None);
+    assert_eq!(read_messages(&[5]), None);
+}
+
+#[test]
+fn test_resend_strategies() {
+    use crate::config::ResendStrategy;
+    use std::time::Duration;
+    
+    let ms = Duration::from_millis;
+    let start = Instant::now();
+    
+    // Fixed interval ignores network conditions
+    let mut endpoint = ReliableEndpoint::new(256);
+    endpoint.on_packet_sent_with(0, start, vec![0], ResendStrategy::Fixed { interval: ms(250) });
+    assert!(endpoint.update(start + ms(200)).is_empty());
+    assert_eq!(endpoint.update(start + ms(250)), vec![(0, vec![0])]);
+    assert!(endpoint.update(start + ms(400)).is_empty());
+    
+    // RTT-based: one 200ms sample gives srtt 200ms, variance 100ms, so an RTO of 600ms
+    let rtt_based = ResendStrategy::RttBased { min_rto: ms(100), max_rto: ms(2000), backoff: 2.0 };
+    let mut endpoint = ReliableEndpoint::new(256).with_max_retries(3);
+    endpoint.on_packet_sent_with(0, start, vec![0], rtt_based);
+    endpoint.process_acks_at(0, 0, start + ms(200));
+    assert_eq!(endpoint.rtt(), Some(ms(200)));
+    assert_eq!(endpoint.rto(ms(100), ms(2000)), ms(600));
+    
+    let sent = start + ms(200);
+    endpoint.on_packet_sent_with(1, sent, vec![1], rtt_based);
+    assert!(endpoint.update(sent + ms(599)).is_empty());
+    assert_eq!(endpoint.update(sent + ms(600)).len(), 1);
+    // Backoff doubles the wait after each resend
+    assert!(endpoint.update(sent + ms(600) + ms(1199)).is_empty());
+    assert_eq!(endpoint.update(sent + ms(600) + ms(1200)).len(), 1);
+    
+    // Acks of resent packets are ambiguous and don't feed the estimate
+    endpoint.process_acks_at(1, 0, sent + ms(5000));
+    assert_eq!(endpoint.rtt(), Some(ms(200)));
+    
+    // Fast retransmit resends once enough newer packets were acked
+    let fast = ResendStrategy::FastRetransmit { ack_gap: 3, min_rto: ms(100), max_rto: ms(2000) };
+    let mut endpoint = ReliableEndpoint::new(256);
+    for sequence in 0..4 {
+        endpoint.on_packet_sent_with(sequence, start, vec![sequence as u8], fast);
+    }
+    endpoint.process_acks_at(2, 0b1, start + ms(10));
+    assert!(endpoint.update(start + ms(10)).is_empty());
+    endpoint.process_acks_at(3, 0b11, start + ms(10));
+    assert_eq!(endpoint.update(start + ms(10)), vec![(0, vec![0])]);
+    assert!(endpoint.update(start + ms(20)).is_empty());
+}
+
+#[test]
+fn test_duplicate_message_suppression() {
+    let mut reliable = Channel::new(0, ChannelConfig::default());
+    assert!(reliable.on_message_received(10, b"a".to_vec()));
+    assert!(reliable.on_message_received(12, b"c".to_vec()));
+    
+    // A spurious retransmit delivers the same sequences again
+    assert!(!reliable.on_message_received(10, b"a".to_vec()));
+    assert!(!reliable.on_message_received(12, b"c".to_vec()));
+    
+    // Gaps fill in late without being mistaken for duplicates
+    assert!(reliable.on_message_received(11, b"b".to_vec()));
+    assert_eq!(reliable.receive(), Some(b"a".to_vec()));
+    assert_eq!(reliable.receive(), Some(b"c".to_vec()));
+    assert_eq!(reliable.receive(), Some(b"b".to_vec()));
+    assert_eq!(reliable.receive(), None);
+    assert_eq!(reliable.stats().duplicates_dropped, 2);
+    
+    // Anything older than the window is assumed delivered, across wraparound too
+    assert!(reliable.on_message_received(2000, Vec::new()));
+    assert!(!reliable.on_message_received(500, Vec::new()));
+    assert!(!reliable.on_message_received(65000, Vec::new()));
+    assert_eq!(reliable.stats().duplicates_dropped, 4);
+    
+    // Unreliable channels deliver whatever arrives
+    let config = ChannelConfig { reliability: Reliability::Unreliable, ..ChannelConfig::default() };
+    let mut unreliable = Channel::new(1, config);
+    assert!(unreliable.on_message_received(3, b"x".to_vec()));
+    assert!(unreliable.on_message_received(3, b"x".to_vec()));
+    assert_eq!(unreliable.stats().duplicates_dropped, 0);
+}
+
+#[cfg(feature = "conformance")]
+#[test]
+fn test_conformance_suite() {
+    use crate::conformance::{golden_suite, generate_suite, run_suite, ConformanceError};
+    
+    // The shipped suite must match what the current encoder produces
+    assert_eq!(golden_suite(), generate_suite(), "regenerate conformance/packets.json");
+    
+    let report = run_suite(golden_suite()).unwrap();
+    assert!(report.is_success(), "{:?}", report.failures);
+    assert_eq!(report.passed.len(), 24);
+    
+    // A port that decodes a salt differently is caught by name
+    let tampered = golden_suite().replacen("\"81985529216486895\"", "\"81985529216486896\"", 1);
+    let report = run_suite(&tampered).unwrap();
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].case, "connection_challenge");
+    
+    assert!(matches!(
+        run_suite(r#"{"version": 99, "cases": []}"#),
+        Err(ConformanceError::UnsupportedVersion(99))
+    ));
+    assert!(matches!(run_suite("not json"), Err(ConformanceError::InvalidJson(_))));
+}
+
+#[test]
+fn test_load_test_swarm() {
+    use crate::loadtest::{run_local, LoadTestConfig, MessagePattern};
+    use crate::server::Server;
+    use std::time::Duration;
+    
+    let server_config = NetworkConfig {
+        max_clients: 32,
+        connection_request_rate_limit: 1000,
+        ..Default::default()
+    };
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let mut server = Server::bind(addr, server_config).unwrap();
+    
+    let config = LoadTestConfig {
+        clients: 24,
+        pattern: MessagePattern::Burst { count: 3, interval: Duration::from_millis(20) },
+        message_size: 64,
+        reliable: true,
+        connects_per_update: 8,
+        ..Default::default()
+    };
+    let report = run_local(&mut server, config, Duration::from_millis(400), Duration::from_millis(200)).unwrap();
+    
+    assert_eq!(server.num_clients(), 24);
+    assert_eq!(report.clients_seen, 24);
+    assert!(report.messages_received >= 24 * 3);
+    assert_eq!(report.malformed_messages, 0);
+    assert_eq!(report.messages_lost, 0);
+    assert!(report.latency_min <= report.latency_p50 && report.latency_p50 <= report.latency_max);
+    assert!(report.latency_max < Duration::from_secs(1));
+}
+
+#[test]
+fn test_session_seed_exchange() {
+    use crate::local::LocalPair;
+    use crate::rng::DeterministicRng;
+    
+    // PCG32 reference output for seed 42, stream 54
+    let mut rng = DeterministicRng::with_stream(42, 54);
+    let expected = [0xa15c02b7u32, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e];
+    assert_eq!(expected.map(|_| rng.next_u32()), expected);
+    
+    let mut rng = DeterministicRng::new(1);
+    assert!((0..1000).all(|_| rng.next_below(10) < 10 && (0.0..1.0).contains(&rng.next_f64())));
+    assert_ne!(DeterministicRng::with_stream(1, 0).next_u64(), DeterministicRng::with_stream(1, 1).next_u64());
+    
+    let pair = LocalPair::new(NetworkConfig::default());
+    
+    // Both ends draw the same numbers from the same stream
+    let server_side = pair.server.connection(pair.client_addr()).unwrap();
+    assert_eq!(pair.client.session_seed(), server_side.session_seed());
+    let mut client_rng = pair.client.rng(3).unwrap();
+    let mut server_rng = server_side.rng(3).unwrap();
+    for _ in 0..16 {
+        assert_eq!(client_rng.next_u64(), server_rng.next_u64());
+    }
+}
+
+#[test]
+fn test_connection_memory_limit() {
+    use crate::local::{LocalPair, DEFAULT_STEP};
+    use crate::packet::disconnect_reason;
+    use crate::server::ServerEvent;
+    
+    let config = NetworkConfig {
+        max_connection_memory: Some(2000),
+        ..Default::default()
+    };
+    let mut pair = LocalPair::with_configs(config, NetworkConfig::default());
+    let client_addr = pair.client_addr();
+    
+    // The server application never drains the channel, so messages pile up on its side
+    for _ in 0..10 {
+        pair.client.send(0, &[7u8; 300], true).unwrap();
+    }
+    assert!(pair.client.memory_usage() >= 3000);
+    
+    let mut dropped = false;
+    for _ in 0..100 {
+        if dropped {
+            break;
+        }
+        let _ = pair.step_client(DEFAULT_STEP);
+        pair.server.update().unwrap();
+        while let Some(event) = pair.server.poll_event() {
+            if let ServerEvent::ClientDisconnected(addr, reason) = event {
+                assert_eq!((addr, reason), (client_addr, disconnect_reason::MEMORY_LIMIT));
+                dropped = true;
+            }
+        }
+    }
+    assert!(dropped);
+    assert!(pair.server.connection(client_addr).is_none());
+    assert_eq!(pair.server.num_clients(), 0);
+}
+
+#[test]
+fn test_channel_group_budget() {
+    use crate::config::ChannelGroupConfig;
+    use crate::local::LocalPair;
+    
+    // Channel 0 is ungrouped; gameplay (1) outranks background (2) in the same group
+    let gameplay = ChannelConfig { group: Some(0), priority: 1, ..Default::default() };
+    let background = ChannelConfig { group: Some(0), ..Default::default() };
+    let config = NetworkConfig {
+        channel_configs: vec![ChannelConfig::default(), gameplay, background],
+        channel_groups: vec![ChannelGroupConfig { bytes_per_second: 10.0, burst_bytes: 1000 }],
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config);
+    let client_addr = pair.client_addr();
+    
+    // Background is queued first but gameplay still goes out ahead of it
+    for _ in 0..5 {
+        pair.client.send(2, &[2u8; 300], true).unwrap();
+    }
+    for _ in 0..3 {
+        pair.client.send(1, &[1u8; 300], true).unwrap();
+    }
+    for _ in 0..5 {
+        pair.client.send(0, &[0u8; 300], true).unwrap();
+    }
+    
+    // A tenth of a second refills about a byte of the budget
+    let mut received = [0; 3];
+    for _ in 0..6 {
+        pair.step().unwrap();
+        for (channel, count) in received.iter_mut().enumerate() {
+            while pair.server.receive(client_addr, channel as u8).is_some() {
+                *count += 1;
+            }
+        }
+    }
+    
+    // 1000 bytes covers all of gameplay plus one overdrawing background message;
+    // the ungrouped channel is unaffected by the exhausted budget
+    assert_eq!(received, [5, 3, 1]);
+}
+
+#[test]
+fn test_telemetry_sampler() {
+    use crate::local::LocalPair;
+    use crate::packet::PacketType;
+    use crate::telemetry::PacketTelemetry;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    
+    let samples: Arc<Mutex<Vec<PacketTelemetry>>> = Arc::default();
+    let sink = samples.clone();
+    let config = NetworkConfig::default().telemetry_sampler(1.0, move |packet| sink.lock().unwrap().push(packet.clone()));
+    let mut pair = LocalPair::with_configs(NetworkConfig::default(), config);
+    assert!(matches!(samples.lock().unwrap()[0].packet_type, PacketType::ConnectionRequest));
+    
+    for message in [&b"a"[..], b"bb", b"ccc"] {
+        pair.client.send(1, message, true).unwrap();
+    }
+    pair.step_client(Duration::ZERO).unwrap();
+    
+    let samples = samples.lock().unwrap();
+    let payload = samples.iter().find(|packet| packet.channel.is_some()).unwrap();
+    assert_eq!(payload.channel, Some(1));
+    assert_eq!(payload.messages, 3);
+    assert_eq!(payload.remote_addr, pair.server_addr());
+    assert!(payload.size > 3 * 4 + 6);
+    
+    // Nothing is sampled at a rate of zero
+    let count = Arc::new(Mutex::new(0));
+    let counter = count.clone();
+    let config = NetworkConfig::default().telemetry_sampler(0.0, move |_| *counter.lock().unwrap() += 1);
+    let (mut client, mut socket) = pair.connect_client(config);
+    client.send(0, b"unsampled", true).unwrap();
+    client.update(&mut socket).unwrap();
+    assert_eq!(*count.lock().unwrap(), 0);
+}
+
+#[test]
+#[cfg(feature = "timestamps")]
+fn test_timestamp_epoch() {
+    use crate::local::LocalPair;
+    use crate::timestamp::{TimestampEpoch, TimestampResolution};
+    use crate::{BitSerialize, BitDeserialize, BitBuffer, BitWrite, NetworkSerialize};
+    use std::time::{Duration, SystemTime};
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    struct Event {
+        id: u8,
+        at: SystemTime,
+    }
+    
+    let config = NetworkConfig { timestamp_resolution: TimestampResolution::Micros, ..Default::default() };
+    let pair = LocalPair::with_configs(config, NetworkConfig::default());
+    
+    // The client adopts the server's epoch and resolution
+    let epoch = pair.client.timestamp_epoch().unwrap();
+    assert_eq!(Some(epoch), pair.server.connection(pair.client_addr()).unwrap().timestamp_epoch());
+    assert_eq!(epoch.resolution(), TimestampResolution::Micros);
+    
+    // Times near the epoch take a handful of bits, and times before it still round trip
+    let event = Event { id: 1, at: epoch.origin() + Duration::from_micros(1500) };
+    let mut buffer = BitBuffer::new();
+    epoch.scope(|| event.bit_serialize(&mut buffer)).unwrap();
+    assert_eq!(buffer.bit_pos(), TAG_BITS + 8 + 1 + 7 + 11);
+    let decoded = epoch.scope(|| Event::bit_deserialize(&mut BitBuffer::from_bytes(buffer.into_bytes(true).unwrap()))).unwrap();
+    assert_eq!(decoded, event);
+    
+    let earlier = epoch.origin() - Duration::from_secs(3600);
+    assert_eq!(epoch.decode(epoch.encode(earlier).unwrap()).unwrap(), earlier);
+    
+    // Millisecond epochs truncate sub-millisecond precision
+    let millis = TimestampEpoch::new(epoch.origin(), TimestampResolution::Millis);
+    assert_eq!(millis.decode(millis.encode(epoch.origin() + Duration::from_micros(2999)).unwrap()).unwrap(),
+        epoch.origin() + Duration::from_millis(2));
+    
+    #[cfg(feature = "chrono")]
+    {
+        let time = chrono::DateTime::<chrono::Utc>::from(event.at);
+        let mut buffer = BitBuffer::new();
+        epoch.scope(|| time.bit_serialize(&mut buffer)).unwrap();
+        let bytes = buffer.into_bytes(true).unwrap();
+        assert_eq!(epoch.scope(|| chrono::DateTime::bit_deserialize(&mut BitBuffer::from_bytes(bytes))).unwrap(), time);
+    }
+    
+    // Without an epoch in scope timestamps refuse to serialize
+    assert!(TimestampEpoch::current().is_none());
+    assert!(SystemTime::now().bit_serialize(&mut BitBuffer::new()).is_err());
+}
+
+#[test]
+fn test_bandwidth_cap() {
+    use crate::connection::ConnectionEvent;
+    use crate::local::LocalPair;
+    use std::time::Duration;
+    
+    let config = NetworkConfig { bandwidth_cap: Some(1000.0), ..Default::default() };
+    let mut pair = LocalPair::with_configs(NetworkConfig::default(), config);
+    let client_addr = pair.client_addr();
+    assert_eq!(pair.client.poll_event(), Some(ConnectionEvent::Connecting { attempt: 1 }));
+    
+    // The one second burst covers three 400 byte messages (the third overdraws it)
+    for _ in 0..5 {
+        pair.client.send(0, &[0u8; 400], true).unwrap();
+    }
+    let start = pair.clock().now();
+    pair.step_client(Duration::ZERO).unwrap();
+    assert_eq!(pair.client.poll_event(), Some(ConnectionEvent::BandwidthLimited { queued_bytes: 800 }));
+    assert_eq!(pair.client.poll_event(), None);
+    
+    let mut received = 0;
+    pair.run_until(180, |pair| {
+        received += std::iter::from_fn(|| pair.server.receive(client_addr, 0)).count();
+        received == 5
+    }).unwrap();
+    
+    // The rest trickles out at 1000 bytes per second
+    assert_eq!(received, 5);
+    assert!(pair.clock().now() - start >= Duration::from_millis(500));
+    assert_eq!(pair.client.poll_event(), Some(ConnectionEvent::BandwidthRestored));
+    assert_eq!(pair.client.poll_event(), None);
+}
+
+#[test]
+fn test_server_rooms() {
+    use crate::connection::ConnectionError;
+    use crate::local::LocalPair;
+    use crate::packet::disconnect_reason;
+    
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    let mut clients: Vec<(Connection, UdpSocket)> = (0..3).map(|_| pair.connect_client(NetworkConfig::default())).collect();
+    let addrs: Vec<SocketAddr> = clients.iter().map(|(_, socket)| socket.local_addr().unwrap()).collect();
+    let server = &mut pair.server;
+    
+    let lobby = server.create_room();
+    let arena = server.create_room();
+    server.join_room(addrs[0], lobby).unwrap();
+    server.join_room(addrs[1], lobby).unwrap();
+    server.join_room(addrs[2], arena).unwrap();
+    assert!(matches!(server.join_room(addrs[0], 99), Err(ConnectionError::RoomNotFound)));
+    
+    // Moving a client takes it out of its previous room
+    server.join_room(addrs[1], arena).unwrap();
+    assert_eq!(server.room_of(addrs[1]), Some(arena));
+    assert_eq!(server.room(lobby).unwrap().members().collect::<Vec<_>>(), vec![&addrs[0]]);
+    assert_eq!(server.room(arena).unwrap().len(), 2);
+    
+    server.broadcast_room(arena, 0, b"arena only", true).unwrap();
+    let mut received = [0; 3];
+    for _ in 0..6 {
+        pair.step().unwrap();
+        for (i, (client, socket)) in clients.iter_mut().enumerate() {
+            client.update(socket).unwrap();
+            while let Some(message) = client.receive(0) {
+                assert_eq!(message, b"arena only");
+                received[i] += 1;
+            }
+        }
+    }
+    assert_eq!(received, [0, 1, 1]);
+    
+    let server = &mut pair.server;
+    let stats = server.room(arena).unwrap().stats();
+    assert_eq!((stats.messages_sent, stats.bytes_sent, stats.joins, stats.leaves), (2, 20, 2, 0));
+    assert_eq!(server.room(lobby).unwrap().stats().leaves, 1);
+    
+    // Disconnected clients leave their room; removing a room keeps its members connected
+    server.disconnect(addrs[2], disconnect_reason::KICKED).unwrap();
+    assert_eq!(server.room(arena).unwrap().members().collect::<Vec<_>>(), vec![&addrs[1]]);
+    assert!(server.remove_room(arena).is_some());
+    assert_eq!(server.room_of(addrs[1]), None);
+    assert!(server.connection(addrs[1]).is_some());
+    assert_eq!(server.rooms().count(), 1);
+}
+
+#[test]
+fn test_server_fan_out() {
+    use crate::connection::ConnectionError;
+    use crate::local::LocalPair;
+    
+    // The pair's own client takes the first slot
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    let mut clients: Vec<(Connection, UdpSocket)> = (0..2).map(|_| pair.connect_client(NetworkConfig::default())).collect();
+    let addrs: Vec<SocketAddr> = std::iter::once(pair.client_addr())
+        .chain(clients.iter().map(|(_, socket)| socket.local_addr().unwrap()))
+        .collect();
+    
+    pair.server.broadcast(0, b"all", true).unwrap();
+    pair.server.broadcast_except(&[addrs[0]], 0, b"not first", true).unwrap();
+    pair.server.send_to_many(&[addrs[0], addrs[2]], 0, b"ends", true).unwrap();
+    
+    // Unknown addresses fail without stopping delivery to the rest
+    let stranger = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 1);
+    assert!(matches!(pair.server.send_to_many(&[stranger, addrs[1]], 0, b"middle", true), Err(ConnectionError::NotConnected)));
+    
+    let mut received: Vec<Vec<Vec<u8>>> = vec![Vec::new(); 3];
+    for _ in 0..6 {
+        pair.step().unwrap();
+        received[0].extend(std::iter::from_fn(|| pair.client.receive(0)));
+        for (i, (client, socket)) in clients.iter_mut().enumerate() {
+            client.update(socket).unwrap();
+            received[i + 1].extend(std::iter::from_fn(|| client.receive(0)));
+        }
+    }
+    assert_eq!(received[0], [&b"all"[..], b"ends"]);
+    assert_eq!(received[1], [&b"all"[..], b"not first", b"middle"]);
+    assert_eq!(received[2], [&b"all"[..], b"not first", b"ends"]);
+}
+
+#[test]
+fn test_server_wait() {
+    use crate::server::Server;
+    use std::time::Duration;
+    
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let config = NetworkConfig { keepalive_interval: Duration::from_millis(200), ..Default::default() };
+    let mut server = Server::bind(addr, config).unwrap();
+    
+    // With no clients only the timeout ends the wait
+    let start = Instant::now();
+    assert!(!server.wait(Duration::from_millis(50)).unwrap());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    
+    // Blocking on a real socket is what is under test, so this handshake runs over loopback
+    let mut socket = UdpSocket::bind(addr).unwrap();
+    let mut client = Connection::new(NetworkConfig::default(), socket.local_addr().unwrap(), server.local_addr());
+    client.connect().unwrap();
+    for _ in 0..100 {
+        if client.is_connected() {
+            break;
+        }
+        client.update(&mut socket).unwrap();
+        server.wait(Duration::from_millis(10)).unwrap();
+        server.update().unwrap();
+    }
+    assert!(client.is_connected());
+    while server.wait(Duration::ZERO).unwrap() {
+        server.update().unwrap();
+    }
+    
+    // An idle client wakes the server for its keepalive long before the timeout
+    server.update().unwrap();
+    let start = Instant::now();
+    assert!(!server.wait(Duration::from_secs(5)).unwrap());
+    assert!(start.elapsed() < Duration::from_secs(1));
+    server.update().unwrap();
+    
+    // Incoming data ends the wait right away
+    client.send(0, b"wake up", true).unwrap();
+    client.update(&mut socket).unwrap();
+    let start = Instant::now();
+    assert!(server.wait(Duration::from_secs(5)).unwrap());
+    assert!(start.elapsed() < Duration::from_millis(100));
+    server.update().unwrap();
+    assert_eq!(server.receive(socket.local_addr().unwrap(), 0).unwrap(), b"wake up");
+}
+
+#[test]
+fn test_kernel_receive_timestamps() {
+    use std::time::Duration;
+    
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let mut receiver = UdpSocket::bind(addr).unwrap();
+    let mut sender = UdpSocket::bind(addr).unwrap();
+    let supported = cfg!(all(feature = "kernel-timestamps", target_os = "linux"));
+    assert_eq!(receiver.enable_kernel_timestamps(), supported);
+    assert_eq!(receiver.kernel_timestamps(), supported);
+    
+    let receiver_addr = receiver.local_addr().unwrap();
+    let sender_addr = sender.local_addr().unwrap();
+    sender.send_to(b"stamped", receiver_addr).unwrap();
+    let sent_at = Instant::now();
+    
+    // Let the datagram sit in the receive queue before reading it
+    std::thread::sleep(Duration::from_millis(50));
+    let (data, from) = receiver.recv_from().unwrap();
+    assert_eq!(data, b"stamped");
+    assert_eq!(from, sender_addr);
+    
+    let received_at = receiver.stats().last_receive_time.unwrap();
+    if supported {
+        // The kernel stamp is taken on arrival, not when the application got around to reading
+        assert!(received_at < sent_at + Duration::from_millis(40));
+    } else {
+        assert!(received_at >= sent_at + Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_iocp_receives() {
+    use crate::socket::SocketError;
+    use std::time::Duration;
+    
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let mut receiver = UdpSocket::bind(addr).unwrap();
+    let mut sender = UdpSocket::bind(addr).unwrap();
+    let supported = cfg!(all(feature = "iocp", windows));
+    assert_eq!(receiver.enable_iocp(), supported);
+    assert_eq!(receiver.iocp(), supported);
+    
+    // Either way reads are non-blocking, wait_readable sees arrivals, and datagrams keep their sender
+    assert!(matches!(receiver.recv_from(), Err(SocketError::WouldBlock)));
+    let receiver_addr = receiver.local_addr().unwrap();
+    let sender_addr = sender.local_addr().unwrap();
+    for i in 0..100u8 {
+        sender.send_to(&[i; 32], receiver_addr).unwrap();
+    }
+    let mut received = Vec::new();
+    while received.len() < 100 && receiver.wait_readable(Duration::from_secs(1)).unwrap() {
+        while let Ok((data, from)) = receiver.recv_from() {
+            assert_eq!(from, sender_addr);
+            received.push(data[0]);
+        }
+    }
+    assert_eq!(received, (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_join_snapshot() {
+    use crate::connection::ConnectionEvent;
+    use crate::local::LocalPair;
+    
+    let reliable = ChannelConfig { reliability: Reliability::Reliable, ordering: Ordering::Ordered, ..Default::default() };
+    let config = NetworkConfig {
+        channel_configs: vec![reliable, reliable],
+        join_snapshot_channel: Some(1),
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config);
+    let client_addr = pair.client_addr();
+    
+    // Deltas sent right after the baseline must not overtake it
+    let baseline: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+    pair.server.send_join_snapshot(client_addr, baseline.clone()).unwrap();
+    assert!(matches!(pair.server.send_join_snapshot(client_addr, Vec::new()), Err(ConnectionError::JoinSnapshotUnavailable)));
+    pair.server.send(client_addr, 0, b"delta", true).unwrap();
+    
+    let mut progress = Vec::new();
+    let mut delta_before_ready = false;
+    let mut ready = None;
+    pair.run_until(300, |pair| {
+        while let Some(event) = pair.client.poll_event() {
+            match event {
+                ConnectionEvent::JoinSnapshotProgress { received, total } => progress.push((received, total)),
+                ConnectionEvent::JoinSnapshotReady { size } => ready = Some(size),
+                _ => {}
+            }
+        }
+        if ready.is_none() {
+            delta_before_ready |= pair.client.receive(0).is_some();
+        }
+        ready.is_some()
+    }).unwrap();
+    
+    assert_eq!(ready, Some(baseline.len()));
+    assert!(!delta_before_ready);
+    assert!(progress.len() > 1);
+    assert!(progress.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    assert_eq!(progress.last(), Some(&(baseline.len(), baseline.len())));
+    assert_eq!(pair.client.take_join_snapshot(), Some(baseline));
+    assert!(pair.client.join_snapshot_progress().is_none());
+    assert_eq!(pair.client.receive(0), Some(b"delta".to_vec()));
+}
+
+#[test]
+fn test_observer_role() {
+    use crate::config::ConnectionRole;
+    use crate::local::LocalPair;
+    
+    let input = ChannelConfig { player_only: true, ..Default::default() };
+    let config = NetworkConfig { channel_configs: vec![ChannelConfig::default(), input], ..Default::default() };
+    let mut pair = LocalPair::new(config.clone());
+    let observer_config = NetworkConfig { role: ConnectionRole::Observer, ..config };
+    let (mut observer, mut observer_socket) = pair.connect_client(observer_config);
+    let player_addr = pair.client_addr();
+    let observer_addr = observer_socket.local_addr().unwrap();
+    assert_eq!(pair.server.connection(player_addr).unwrap().role(), ConnectionRole::Player);
+    assert_eq!(pair.server.connection(observer_addr).unwrap().role(), ConnectionRole::Observer);
+    
+    // Observers are refused locally on input channels but still receive replication
+    assert!(matches!(observer.send(1, b"move", true), Err(ConnectionError::PlayerOnlyChannel)));
+    observer.send(0, b"chat", true).unwrap();
+    pair.client.send(1, b"move", true).unwrap();
+    pair.server.broadcast(0, b"state", true).unwrap();
+    
+    // A modified client that skips the local check is dropped by the server
+    let header = PacketHeader { protocol_id: 0x12345678, sequence: 1000, ack: 0, ack_bits: 0 };
+    let mut payload = Vec::new();
+    crate::channel::write_message(&mut payload, 0, b"cheat");
+    let forged = Packet::new(header, PacketType::Payload { channel: 1, is_fragment: false }).with_payload(payload);
+    observer_socket.send_to(&forged.serialize().unwrap(), pair.server_addr()).unwrap();
+    
+    let mut observer_state = None;
+    for _ in 0..6 {
+        observer.update(&mut observer_socket).unwrap();
+        pair.step().unwrap();
+        observer_state = observer_state.or_else(|| observer.receive(0));
+    }
+    observer.update(&mut observer_socket).unwrap();
+    observer_state = observer_state.or_else(|| observer.receive(0));
+    assert_eq!(pair.server.receive(player_addr, 1).unwrap(), b"move");
+    assert_eq!(pair.server.receive(observer_addr, 0).unwrap(), b"chat");
+    assert!(pair.server.receive(observer_addr, 1).is_none());
+    assert_eq!(pair.server.stats().observer_packets_rejected, 1);
+    assert_eq!(observer_state.unwrap(), b"state");
+}
+
+#[test]
+fn test_message_ttl() {
+    use crate::connection::{ConnectionEvent, DeliveryStatus};
+    use crate::local::{LocalPair, DEFAULT_STEP};
+    use std::time::Duration;
+    
+    fn deliveries(client: &mut Connection) -> Vec<(u16, DeliveryStatus)> {
+        std::iter::from_fn(|| client.poll_event())
+            .filter_map(|event| match event {
+                ConnectionEvent::MessageDelivery { channel: 0, sequence, status } => Some((sequence, status)),
+                _ => None,
+            })
+            .collect()
+    }
+    
+    // Acks ride on the server's keepalives
+    let config = NetworkConfig { keepalive_interval: Duration::from_millis(20), ..Default::default() };
+    let mut pair = LocalPair::with_configs(config, NetworkConfig::default());
+    
+    // Acked in time
+    let delivered = pair.client.send_with_ttl(0, b"buff applied", Duration::from_secs(5)).unwrap();
+    let mut statuses = Vec::new();
+    pair.run_until(60, |pair| {
+        statuses = deliveries(&mut pair.client);
+        !statuses.is_empty()
+    }).unwrap();
+    assert_eq!(statuses, [(delivered, DeliveryStatus::Delivered)]);
+    
+    // Expired while still queued
+    let queued = pair.client.send_with_ttl(0, b"stale", Duration::ZERO).unwrap();
+    pair.step_client(Duration::ZERO).unwrap();
+    assert_eq!(deliveries(&mut pair.client), [(queued, DeliveryStatus::Expired)]);
+    
+    // Sent but never acked: dropped at the first resend after the TTL instead of retried
+    let unacked = pair.client.send_with_ttl(0, b"buff expired", Duration::from_millis(50)).unwrap();
+    pair.step_client(Duration::ZERO).unwrap();
+    let mut statuses = Vec::new();
+    for _ in 0..60 {
+        pair.step_client(DEFAULT_STEP).unwrap();
+        statuses = deliveries(&mut pair.client);
+        if !statuses.is_empty() {
+            break;
+        }
+    }
+    assert_eq!(statuses, [(unacked, DeliveryStatus::Expired)]);
+}
+
+#[test]
+fn test_trace_export() {
+    use crate::local::LocalPair;
+    use crate::trace::{TraceEventKind, TraceRecorder};
+    use std::time::Duration;
+    
+    let recorder = TraceRecorder::new(1000);
+    let config = NetworkConfig {
+        keepalive_interval: Duration::from_millis(20),
+        trace: Some(recorder.clone()),
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config);
+    let client_addr = pair.client_addr();
+    recorder.clear();
+    
+    pair.client.send(0, b"traced", true).unwrap();
+    pair.run(6).unwrap();
+    
+    // The payload's trip: sent by the client, received by the server, acked back
+    let events = recorder.events();
+    let payload = events.iter()
+        .find(|event| event.kind == TraceEventKind::Send && event.local_addr == client_addr
+            && matches!(event.packet_type, Some(PacketType::Payload { .. })))
+        .unwrap();
+    let received = events.iter()
+        .find(|event| event.kind == TraceEventKind::Receive && event.remote_addr == client_addr && event.sequence == payload.sequence)
+        .unwrap();
+    let acked = events.iter()
+        .find(|event| event.kind == TraceEventKind::Ack && event.local_addr == client_addr && event.sequence == payload.sequence)
+        .unwrap();
+    assert!(payload.at <= received.at && received.at <= acked.at);
+    
+    // One process per local socket with a named track per peer
+    #[cfg(feature = "json")]
+    {
+        let json = recorder.to_chrome_json();
+        let trace_events = json["traceEvents"].as_array().unwrap();
+        let names = |kind: &str| trace_events.iter()
+            .filter(|event| event["name"] == kind)
+            .map(|event| event["args"]["name"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names("process_name").len(), 2);
+        assert!(names("thread_name").contains(&client_addr.to_string()));
+        assert!(trace_events.iter().any(|event| event["name"] == "ack" && event["ph"] == "i"));
+        let mut exported = Vec::new();
+        recorder.write_chrome_json(&mut exported).unwrap();
+        let exported: serde_json::Value = serde_json::from_slice(&exported).unwrap();
+        assert_eq!(exported["traceEvents"].as_array().unwrap().len(), trace_events.len());
+    }
+    
+    // Only the newest events are kept
+    let small = TraceRecorder::new(2);
+    for sequence in 0..5 {
+        small.record(crate::trace::TraceEvent {
+            kind: TraceEventKind::Send,
+            local_addr: client_addr,
+            remote_addr: pair.server_addr(),
+            sequence,
+            packet_type: None,
+            size: 0,
+            at: Instant::now(),
+        });
+    }
+    assert_eq!(small.events().iter().map(|event| event.sequence).collect::<Vec<_>>(), [3, 4]);
+}
+
+#[test]
+fn test_middleware_chain() {
+    use crate::local::LocalPair;
+    use crate::middleware::{MiddlewareChain, PacketMiddleware};
+    use std::io;
+    
+    // Appends a trailer byte and checks it on the way in
+    struct Trailer(u8);
+    impl PacketMiddleware for Trailer {
+        fn on_send(&self, _: SocketAddr, data: &mut Vec<u8>) -> io::Result<()> {
+            data.push(self.0);
+            Ok(())
+        }
+        fn on_receive(&self, _: SocketAddr, data: &mut Vec<u8>) -> io::Result<()> {
+            match data.pop() {
+                Some(byte) if byte == self.0 => Ok(()),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "missing trailer")),
+            }
+        }
+    }
+    struct Xor(u8);
+    impl PacketMiddleware for Xor {
+        fn on_send(&self, _: SocketAddr, data: &mut Vec<u8>) -> io::Result<()> {
+            data.iter_mut().for_each(|byte| *byte ^= self.0);
+            Ok(())
+        }
+        fn on_receive(&self, addr: SocketAddr, data: &mut Vec<u8>) -> io::Result<()> {
+            self.on_send(addr, data)
+        }
+    }
+    
+    // Layers run in order on send and in reverse on receive
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let chain = MiddlewareChain::new().with(Trailer(0xAA)).with(Xor(0x0F));
+    assert_eq!(chain.len(), 2);
+    let encoded = chain.encode(addr, &[1, 2]).unwrap().into_owned();
+    assert_eq!(encoded, [0x0E, 0x0D, 0xA5]);
+    assert_eq!(chain.decode(addr, &encoded).unwrap().as_ref(), [1, 2]);
+    assert!(chain.decode(addr, &[1, 2]).is_err());
+    assert!(matches!(MiddlewareChain::new().encode(addr, &[1]).unwrap(), std::borrow::Cow::Borrowed(_)));
+    
+    let config = NetworkConfig { middleware: chain, ..Default::default() };
+    let mut pair = LocalPair::new(config);
+    pair.client.send(0, b"layered", true).unwrap();
+    let client_addr = pair.client_addr();
+    let mut received = None;
+    pair.run_until(60, |pair| {
+        received = pair.server.receive(client_addr, 0);
+        received.is_some()
+    }).unwrap();
+    assert_eq!(received.unwrap(), b"layered");
+    
+    // A peer without the chain is not understood
+    let invalid = pair.server.stats().invalid_packets;
+    let mut plain = UdpSocket::bind_memory(pair.network(), SocketAddr::from(([10, 0, 0, 3], 0))).unwrap();
+    let header = PacketHeader { protocol_id: 0x12345678, sequence: 0, ack: 0, ack_bits: 0 };
+    let request = Packet::new(header, PacketType::ConnectionRequest).serialize().unwrap();
+    plain.send_to(&request, pair.server_addr()).unwrap();
+    pair.server.update().unwrap();
+    assert_eq!(pair.server.stats().invalid_packets, invalid + 1);
+}
+
+#[test]
+fn test_checksum_layer() {
+    use crate::checksum::{crc32c, crc32c_software, xxhash64, ChecksumLayer, Crc32c, NoChecksum, XxHash64};
+    use crate::middleware::PacketMiddleware;
+    
+    // Reference vectors
+    assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+    assert_eq!(crc32c(b""), 0);
+    let long: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+    assert_eq!(crc32c(&long), crc32c_software(&long));
+    assert_eq!(xxhash64(b"", 0), 0xEF46_DB37_51D8_E999);
+    assert_eq!(xxhash64(b"abc", 0), 0x44BC_2CF5_AD77_0999);
+    assert_eq!(xxhash64(b"Nobody inspects the spammish repetition", 0), 0xFBCE_A83C_8A37_8BF1);
+    
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    fn roundtrip(layer: &dyn PacketMiddleware, addr: SocketAddr, digest_len: usize) {
+        let mut data = b"payload".to_vec();
+        layer.on_send(addr, &mut data).unwrap();
+        assert_eq!(data.len(), 7 + digest_len);
+        let mut received = data.clone();
+        layer.on_receive(addr, &mut received).unwrap();
+        assert_eq!(received, b"payload");
+        
+        if digest_len > 0 {
+            data[0] ^= 1;
+            assert!(layer.on_receive(addr, &mut data).is_err());
+        }
+    }
+    roundtrip(&ChecksumLayer::new(Crc32c), addr, 4);
+    roundtrip(&ChecksumLayer::new(XxHash64), addr, 8);
+    roundtrip(&ChecksumLayer::new(NoChecksum), addr, 0);
+    assert!(ChecksumLayer::new(XxHash64).on_receive(addr, &mut vec![1, 2, 3]).is_err());
+}
+
+#[test]
+fn test_receive_budget() {
+    use crate::config::ReceiveOverflow;
+    use crate::server::Server;
+    use crate::socket::MemoryNetwork;
+    use std::time::Duration;
+    
+    let network = MemoryNetwork::new();
+    let bind = |addr: [u8; 4], port: u16| UdpSocket::bind_memory(&network, SocketAddr::from((addr, port))).unwrap();
+    let flood = |server: &Server, socket: &mut UdpSocket, count: usize| {
+        for _ in 0..count {
+            socket.send_to(&[0xFF; 16], server.local_addr()).unwrap();
+        }
+    };
+    let mut flooder = bind([10, 0, 0, 2], 0);
+    
+    // Deferred datagrams stay queued for the next update
+    let config = NetworkConfig { receive_budget_packets: Some(2), ..Default::default() };
+    let mut server = Server::with_socket(bind([10, 0, 0, 1], 1), config).unwrap();
+    flood(&server, &mut flooder, 5);
+    server.update().unwrap();
+    assert_eq!(server.stats().packets_received, 2);
+    assert_eq!(server.stats().receive_budget_exhausted, 1);
+    server.update().unwrap();
+    server.update().unwrap();
+    assert_eq!(server.stats().packets_received, 5);
+    assert_eq!(server.stats().invalid_packets, 5);
+    assert_eq!(server.stats().packets_over_budget, 0);
+    
+    // Dropped datagrams are drained and counted
+    let config = NetworkConfig {
+        receive_budget_packets: Some(2),
+        receive_overflow: ReceiveOverflow::Drop,
+        ..Default::default()
+    };
+    let mut server = Server::with_socket(bind([10, 0, 0, 1], 2), config).unwrap();
+    flood(&server, &mut flooder, 5);
+    server.update().unwrap();
+    assert_eq!(server.stats().packets_received, 5);
+    assert_eq!(server.stats().invalid_packets, 2);
+    assert_eq!(server.stats().packets_over_budget, 3);
+    
+    // One noisy address cannot use up another's share
+    let config = NetworkConfig { receive_budget_per_client: Some(2), ..Default::default() };
+    let mut server = Server::with_socket(bind([10, 0, 0, 1], 3), config).unwrap();
+    let mut quiet = bind([10, 0, 0, 3], 0);
+    flood(&server, &mut flooder, 6);
+    flood(&server, &mut quiet, 1);
+    server.update().unwrap();
+    assert_eq!(server.stats().invalid_packets, 3);
+    assert_eq!(server.stats().packets_over_budget, 4);
+    
+    // A time budget of zero reads nothing
+    let config = NetworkConfig { receive_budget_time: Some(Duration::ZERO), ..Default::default() };
+    let mut server = Server::with_socket(bind([10, 0, 0, 1], 4), config).unwrap();
+    flood(&server, &mut flooder, 1);
+    server.update().unwrap();
+    assert_eq!(server.stats().packets_received, 0);
+    assert_eq!(server.stats().receive_budget_exhausted, 1);
+}
+
+#[test]
+fn test_latest_only_channel() {
+    use crate::config::ResendStrategy;
+    use crate::local::LocalPair;
+    use std::time::Duration;
+    
+    // Queued versions are replaced, and a late older version is never delivered
+    let keyed = ChannelConfig { latest_only: true, ..Default::default() };
+    let mut sender = Channel::new(0, keyed);
+    let mut receiver = Channel::new(0, keyed);
+    assert!(matches!(sender.send(b"plain", true), Err(ChannelError::KeyMismatch)));
+    assert!(matches!(Channel::new(0, ChannelConfig::default()).send_latest(1, b"x"), Err(ChannelError::KeyMismatch)));
+    sender.send_latest(7, b"v0").unwrap();
+    sender.send_latest(7, b"v1").unwrap();
+    sender.send_latest(8, b"other").unwrap();
+    let v1 = sender.take_outgoing_message().unwrap();
+    let other = sender.take_outgoing_message().unwrap();
+    assert!(!sender.has_outgoing_messages());
+    sender.send_latest(7, b"v2").unwrap();
+    let v2 = sender.take_outgoing_message().unwrap();
+    assert!(receiver.on_message_received(v2.0, v2.1));
+    assert!(!receiver.on_message_received(v1.0, v1.1));
+    assert!(receiver.on_message_received(other.0, other.1));
+    assert_eq!(receiver.receive(), Some(b"v2".to_vec()));
+    assert_eq!(receiver.receive(), Some(b"other".to_vec()));
+    assert_eq!(sender.stats().messages_superseded, 1);
+    assert_eq!(receiver.stats().messages_superseded, 1);
+    
+    // A version replaced while awaiting its ack is left out of the resend
+    let keyed = ChannelConfig {
+        latest_only: true,
+        resend_strategy: ResendStrategy::Fixed { interval: Duration::from_millis(20) },
+        ..Default::default()
+    };
+    let config = NetworkConfig { channel_configs: vec![keyed], ..Default::default() };
+    let mut pair = LocalPair::new(config);
+    let client_addr = pair.client_addr();
+    
+    pair.client.send_latest(0, 1, b"inventory v0").unwrap();
+    pair.step_client(Duration::ZERO).unwrap();
+    pair.client.send_latest(0, 1, b"inventory v1").unwrap();
+    pair.step_client(Duration::ZERO).unwrap();
+    let sent = pair.client.stats().packets_sent;
+    pair.step_client(Duration::from_millis(30)).unwrap();
+    assert_eq!(pair.client.stats().packets_sent, sent + 1);
+    assert_eq!(pair.client.channel_stats(0).unwrap().messages_superseded, 1);
+    
+    pair.server.update().unwrap();
+    let mut received = Vec::new();
+    while let Some(message) = pair.server.receive(client_addr, 0) {
+        received.push(message);
+    }
+    assert_eq!(received.last().map(Vec::as_slice), Some(&b"inventory v1"[..]));
+}
+
+#[test]
+fn test_keyed_message_slots() {
+    use crate::local::LocalPair;
+    use std::time::Duration;
+    
+    // Queued messages are replaced in place, and new keys are refused once the key space is full
+    let limited = ChannelConfig { max_pending_keys: 2, ..Default::default() };
+    let mut channel = Channel::new(0, limited);
+    channel.send_keyed(1, b"a0", true).unwrap();
+    channel.send_keyed(2, b"b0", true).unwrap();
+    channel.send_keyed(1, b"a1", true).unwrap();
+    assert!(matches!(channel.send_keyed(3, b"c0", true), Err(ChannelError::KeySpaceFull)));
+    channel.send(b"unkeyed", true).unwrap();
+    assert_eq!(channel.take_outgoing_message().unwrap().1, b"b0");
+    assert_eq!(channel.take_outgoing_message().unwrap().1, b"a1");
+    assert_eq!(channel.take_outgoing_message().unwrap().1, b"unkeyed");
+    assert_eq!(channel.stats().messages_superseded, 1);
+    // Sent reliable messages hold their key until acknowledged
+    assert_eq!(channel.pending_keys(), 2);
+    
+    // Unreliable channels free a key as soon as its message is sent
+    let unreliable = ChannelConfig { reliability: Reliability::Unreliable, max_pending_keys: 1, ..Default::default() };
+    let mut channel = Channel::new(0, unreliable);
+    channel.send_keyed(1, b"a", false).unwrap();
+    channel.take_outgoing_message().unwrap();
+    channel.send_keyed(2, b"b", false).unwrap();
+    
+    // Acks free the key on a live connection
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    let client_addr = pair.client_addr();
+    for progress in 0..100u8 {
+        pair.client.send_keyed(0, 42, &[progress], true).unwrap();
+    }
+    pair.step_client(Duration::ZERO).unwrap();
+    assert_eq!(pair.client.channel_stats(0).unwrap().messages_superseded, 99);
+    assert_eq!(pair.client.channel_stats(0).unwrap().pending_keys, 1);
+    
+    let mut received = Vec::new();
+    pair.run_until(60, |pair| {
+        pair.server.send(client_addr, 0, b"ack carrier", true).unwrap();
+        while let Some(message) = pair.server.receive(client_addr, 0) {
+            received.push(message);
+        }
+        pair.client.channel_stats(0).unwrap().pending_keys == 0
+    }).unwrap();
+    assert_eq!(pair.client.channel_stats(0).unwrap().pending_keys, 0);
+    assert_eq!(received, vec![vec![99]]);
+}
+
+#[test]
+fn test_decode_pool() {
+    use crate::decode_pool::DecodePool;
+    use crate::local::LocalPair;
+    use std::time::Duration;
+    
+    let poll_all = |pool: &mut DecodePool<u32>| {
+        let mut decoded = Vec::new();
+        let start = Instant::now();
+        while pool.pending() > 0 && start.elapsed() < Duration::from_secs(5) {
+            decoded.extend(std::iter::from_fn(|| pool.poll()));
+            std::thread::yield_now();
+        }
+        decoded
+    };
+    
+    // A slow first message holds back later ones from its connection, but not other connections
+    let mut pool = DecodePool::new(4, |bytes: &[u8]| {
+        if bytes[0] == 0 {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        Ok(bytes[0] as u32)
+    });
+    assert_eq!(pool.workers(), 4);
+    let (a, b) = (SocketAddr::from(([10, 0, 0, 1], 1)), SocketAddr::from(([10, 0, 0, 2], 1)));
+    pool.submit(a, 0, vec![0]);
+    pool.submit(a, 0, vec![1]);
+    pool.submit(b, 0, vec![2]);
+    let decoded = poll_all(&mut pool);
+    let order = |addr| decoded.iter().filter(|d| d.addr == addr).map(|d| *d.message.as_ref().unwrap()).collect::<Vec<_>>();
+    assert_eq!(order(a), [0, 1]);
+    assert_eq!(order(b), [2]);
+    
+    // Received messages go from the server's channels to the workers and come back in order
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    let client_addr = pair.client_addr();
+    for i in 0..50u32 {
+        pair.client.send(0, &i.to_le_bytes(), true).unwrap();
+    }
+    pair.run(6).unwrap();
+    let mut pool = DecodePool::new(3, |bytes: &[u8]| {
+        bytes.try_into().map(u32::from_le_bytes).map_err(|_| std::io::ErrorKind::InvalidData.into())
+    });
+    assert_eq!(pair.server.decode_received(0, &mut pool), 50);
+    let decoded = poll_all(&mut pool);
+    assert!(decoded.iter().all(|d| d.addr == client_addr && d.channel == 0));
+    assert_eq!(decoded.into_iter().map(|d| d.message.unwrap()).collect::<Vec<_>>(), (0..50).collect::<Vec<_>>());
+}
+
+#[cfg(feature = "conformance")]
+#[test]
+fn test_wire_freeze_fixtures() {
+    use crate::compat::{released_fixtures, CompatError, FreezeHarness};
+    use gbnet_macros::NetworkSerialize;
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    struct FrozenState {
+        #[bits = 10]
+        id: u16,
+        alive: bool,
+        #[max_len = 16]
+        name: String,
+        #[byte_align]
+        position: (i32, i32),
+        inventory: Vec<u8>,
+        target: Option<u16>,
+    }
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    enum FrozenEvent {
+        Spawn { #[bits = 12] x: u16, #[bits = 12] y: u16 },
+        Chat(String),
+        Despawn,
+    }
+    
+    // Never edit these values; freeze new samples under new names instead
+    let harness = FreezeHarness::new()
+        .derive_sample("derive_struct", FrozenState {
+            id: 777,
+            alive: true,
+            name: "frozen".to_string(),
+            position: (-12345, 67890),
+            inventory: vec![3, 1, 4, 1, 5],
+            target: Some(0xBEEF),
+        })
+        .derive_sample("derive_enum_fields", FrozenEvent::Spawn { x: 4000, y: 17 })
+        .derive_sample("derive_enum_tuple", FrozenEvent::Chat("gg".to_string()))
+        .derive_sample("derive_enum_unit", FrozenEvent::Despawn)
+        .derive_sample("derive_f32", -1.5f32);
+    
+    // Every released version must still decode and re-encode bit for bit
+    let (newest, _) = *released_fixtures().last().unwrap();
+    assert_eq!(newest, env!("CARGO_PKG_VERSION"), "freeze compat/{}.json from FreezeHarness::snapshot", env!("CARGO_PKG_VERSION"));
+    for (version, fixtures) in released_fixtures() {
+        let report = harness.check(fixtures).unwrap();
+        assert!(report.is_success(), "{}: {:?}", version, report.failures);
+        assert!(report.passed.len() >= 21);
+    }
+    
+    // A changed derive sample or a missing one is flagged by name
+    let changed = FreezeHarness::new().derive_sample("derive_f32", -2.5f32);
+    let report = changed.check(released_fixtures()[0].1).unwrap();
+    let failed: Vec<_> = report.failures.iter().map(|failure| failure.case.as_str()).collect();
+    assert_eq!(failed, ["derive_struct", "derive_enum_fields", "derive_enum_tuple", "derive_enum_unit", "derive_f32"]);
+    
+    // So is a header that no longer decodes the same
+    let tampered = released_fixtures()[0].1.replacen("\"sequence\": 258", "\"sequence\": 259", 1);
+    assert!(!harness.check(&tampered).unwrap().is_success());
+    assert!(matches!(harness.check(r#"{"format": 9, "fixtures": []}"#), Err(CompatError::UnsupportedFormat(9))));
+
+}
+
+#[test]
+fn test_connection_debug_snapshot() {
+    use crate::connection::ConnectionState;
+    use crate::local::LocalPair;
+    use std::time::Duration;
+    
+    let config = NetworkConfig::default();
+    let idle = Connection::new(config.clone(), "127.0.0.1:1".parse().unwrap(), "127.0.0.1:2".parse().unwrap());
+    let snapshot = idle.debug_snapshot();
+    assert_eq!(snapshot.state, ConnectionState::Disconnected);
+    assert_eq!(snapshot.timers.connected_for, None);
+    assert_eq!(snapshot.timers.next_service_in, None);
+    assert_eq!(snapshot.channels.len(), config.max_channels);
+    
+    let mut pair = LocalPair::new(config);
+    
+    // Queue depths show up per channel before the update sends them
+    pair.client.send(0, b"one", true).unwrap();
+    pair.client.send(0, b"two", true).unwrap();
+    let queued = pair.client.debug_snapshot();
+    assert_eq!(queued.state, ConnectionState::Connected);
+    assert_eq!(queued.channels[0].send_queue, 2);
+    assert_eq!(queued.channels[0].send_queue_bytes, 6);
+    assert!(queued.timers.connected_for.is_some());
+    assert!(queued.timers.next_service_in.is_some());
+    
+    pair.step_client(Duration::ZERO).unwrap();
+    let sent = pair.client.debug_snapshot();
+    assert_eq!(sent.channels[0].send_queue, 0);
+    assert!(sent.packets_in_flight >= 1);
+    assert_eq!(sent.local_sequence, queued.local_sequence.wrapping_add(1));
+    assert_eq!(sent.timers.since_last_send, Duration::ZERO);
+    
+    // Snapshots are plain data and export to JSON for bug reports
+    #[cfg(feature = "json")]
+    {
+        let json = sent.clone().to_json();
+        assert_eq!(json["state"], "Connected");
+        assert_eq!(json["local_sequence"], sent.local_sequence);
+        assert_eq!(json["channels"].as_array().unwrap().len(), sent.channels.len());
+        assert!(json["timers"]["since_last_send_ms"].as_f64().is_some());
+    }
+}
+
+#[test]
+fn test_debug_overlay_feed() {
+    use crate::debug::{DebugOverlayFeed, OverlayMetric};
+    use crate::local::LocalPair;
+    use std::time::Duration;
+    
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    
+    // The first frame only sets the baseline
+    let mut feed = DebugOverlayFeed::new(3);
+    let start = pair.clock().now();
+    let first = feed.record_at(&pair.client, start).clone();
+    assert_eq!(first.at, Duration::ZERO);
+    assert_eq!(first.bytes_up_per_second, 0.0);
+    
+    // Traffic between frames shows up as a rate over the frame's duration
+    let sent_before = pair.client.stats().bytes_sent;
+    pair.client.send(0, &[7; 100], true).unwrap();
+    pair.step_client(Duration::ZERO).unwrap();
+    let sent = pair.client.stats().bytes_sent - sent_before;
+    let frame = feed.record_at(&pair.client, start + Duration::from_millis(500)).clone();
+    assert_eq!(frame.at, Duration::from_millis(500));
+    assert_eq!(frame.packets_sent, 1);
+    assert_eq!(frame.bytes_up_per_second, sent as f32 * 2.0);
+    assert!(frame.packets_in_flight >= 1);
+    assert_eq!(frame.resends, 0);
+    assert_eq!(frame.loss, 0.0);
+    
+    // Old frames roll off, and points and peaks come from what is kept
+    feed.record_at(&pair.client, start + Duration::from_secs(1));
+    feed.record_at(&pair.client, start + Duration::from_millis(1500));
+    assert_eq!(feed.frames().count(), 3);
+    let points = feed.points(OverlayMetric::BandwidthUp);
+    assert_eq!(points[0], [0.5, sent as f32 * 2.0]);
+    assert_eq!(points[2][0], 1.5);
+    assert_eq!(feed.peak(OverlayMetric::BandwidthUp), sent as f32 * 2.0);
+    assert_eq!(feed.latest().unwrap().value(OverlayMetric::BandwidthUp), 0.0);
+    
+    feed.clear();
+    assert!(feed.latest().is_none());
+    assert_eq!(feed.record_at(&pair.client, start + Duration::from_secs(2)).at, Duration::ZERO);
+}
+
+#[test]
+fn test_channel_batch_send() {
+    use crate::NetworkSerialize;
+    use crate::local::LocalPair;
+    
+    #[derive(NetworkSerialize, Debug, PartialEq, Clone, Copy)]
+    struct Moved {
+        #[bits = 10]
+        entity: u16,
+        #[bits = 10]
+        x: u16,
+        #[bits = 4]
+        facing: u8,
+    }
+    let moved: Vec<Moved> = (0..1000u16).map(|i| Moved { entity: i, x: i % 1024, facing: (i % 16) as u8 }).collect();
+    
+    // 24-bit items (plus any debug tag) pack back to back, split at the message size limit
+    let item_bits = 24 + TAG_BITS;
+    let per_message = 300 * 8 / item_bits;
+    let batches = moved.len().div_ceil(per_message);
+    let packed = |items: usize| crate::channel::BATCH_HEADER_BYTES + (items * item_bits).div_ceil(8);
+    let config = ChannelConfig { max_message_size: 304, ..Default::default() };
+    let mut channel = Channel::new(0, config);
+    assert_eq!(channel.send_batch(&moved, true).unwrap(), batches);
+    assert_eq!(channel.pending_bytes(), (batches - 1) * packed(per_message) + packed(moved.len() - (batches - 1) * per_message));
+    assert_eq!(channel.send_batch(Vec::<Moved>::new(), true).unwrap(), 0);
+    while let Some((sequence, data)) = channel.take_outgoing_message() {
+        channel.on_message_received(sequence, data);
+    }
+    let mut received = Vec::new();
+    while let Some(batch) = channel.receive_batch::<Moved>() {
+        assert_eq!(batch.remaining(), per_message.min(moved.len() - received.len()));
+        received.extend(batch.map(Result::unwrap));
+    }
+    assert_eq!(received, moved);
+    
+    // Items too large for any batch are refused, as are ones that fail to serialize
+    let tiny = ChannelConfig { max_message_size: 6, ..Default::default() };
+    assert!(matches!(Channel::new(0, tiny).send_batch([u64::MAX], true), Err(ChannelError::MessageTooLarge)));
+    let too_long = vec![vec![0u8; 70_000]];
+    assert!(matches!(channel.send_batch(&too_long, true), Err(ChannelError::InvalidBatch)));
+    
+    // A truncated batch yields the decode error, then stops
+    let mut item = crate::BitBuffer::new();
+    crate::BitSerialize::bit_serialize(&moved[0], &mut item).unwrap();
+    let mut truncated = 3u32.to_le_bytes().to_vec();
+    truncated.extend(item.into_bytes(true).unwrap());
+    let items: Vec<_> = crate::channel::BatchItems::<Moved>::decode(truncated).unwrap().collect();
+    assert_eq!(items.len(), 2);
+    assert!(items[0].is_ok() && items[1].is_err());
+    
+    // Over a connection, batches are sized to fit a packet
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    let client_addr = pair.client_addr();
+    let batches = pair.client.send_batch(0, &moved, true).unwrap();
+    let per_batch = (pair.client.max_message_size() - crate::channel::BATCH_HEADER_BYTES) * 8 / item_bits;
+    assert_eq!(batches, moved.len().div_ceil(per_batch));
+    
+    let mut received = Vec::new();
+    pair.run_until(120, |pair| {
+        while let Some(batch) = pair.server.receive_batch::<Moved>(client_addr, 0) {
+            received.extend(batch.map(Result::unwrap));
+        }
+        received.len() == moved.len()
+    }).unwrap();
+    assert_eq!(received, moved);
+}
+
+#[test]
+fn test_fragment_interleaving() {
+    use crate::local::LocalPair;
+    use crate::packet::PacketType;
+    use crate::trace::{TraceEventKind, TraceRecorder};
+    use std::time::Duration;
+    
+    // Channels the server sent payloads on while a capped join snapshot was in progress
+    let run = |fragment_burst: Option<usize>| -> Vec<u8> {
+        let recorder = TraceRecorder::new(10_000);
+        let updates = ChannelConfig { priority: 0, ..Default::default() };
+        let snapshot = ChannelConfig { priority: 10, reliability: Reliability::Reliable, ordering: Ordering::Ordered, ..Default::default() };
+        let config = NetworkConfig {
+            channel_configs: vec![updates, snapshot],
+            join_snapshot_channel: Some(1),
+            fragment_burst,
+            trace: Some(recorder.clone()),
+            ..Default::default()
+        };
+        let mut pair = LocalPair::with_configs(NetworkConfig { bandwidth_cap: Some(20_000.0), ..config.clone() }, config);
+        let client_addr = pair.client_addr();
+        
+        recorder.clear();
+        // Larger than the cap's one second burst, so the transfer is paced
+        pair.server.send_join_snapshot(client_addr, vec![7; 30_000]).unwrap();
+        pair.set_step(Duration::from_millis(5));
+        let mut snapshot = None;
+        pair.run_until(600, |pair| {
+            pair.server.send(client_addr, 0, b"state", false).unwrap();
+            snapshot = pair.client.take_join_snapshot();
+            snapshot.is_some()
+        }).unwrap();
+        assert_eq!(snapshot.map(|snapshot| snapshot.len()), Some(30_000));
+        
+        recorder.events().iter()
+            .filter(|event| event.kind == TraceEventKind::Send && event.remote_addr == client_addr)
+            .filter_map(|event| match event.packet_type {
+                Some(PacketType::Payload { channel, .. }) => Some(channel),
+                _ => None,
+            })
+            .collect()
+    };
+    
+    // Strict priority: once the cap bites, the higher priority transfer takes the whole budget
+    let strict = run(None);
+    let last_chunk = strict.iter().rposition(|&channel| channel == 1).unwrap();
+    assert!(strict[last_chunk - 5..=last_chunk].iter().all(|&channel| channel == 1), "{:?}", strict);
+    
+    // With a burst of one, waiting updates get a packet in after every chunk
+    let interleaved = run(Some(1));
+    assert_eq!(interleaved[..2], [1, 0]);
+    let last_chunk = interleaved.iter().rposition(|&channel| channel == 1).unwrap();
+    assert!(interleaved[last_chunk - 5..=last_chunk].windows(2).all(|pair| pair != [1, 1]), "{:?}", interleaved);
+}
+
+#[test]
+fn test_compression_negotiation() {
+    use crate::compression::{Codec, CodecRegistry, Compression, Lz77};
+    use crate::local::LocalPair;
+    use std::time::Duration;
+    
+    // Round trips, with matches reaching into the dictionary, and malformed input is refused
+    let text = b"position update position update position update for entity 42".to_vec();
+    let plain = Lz77::new();
+    assert!(plain.compress(&text).len() < text.len());
+    assert_eq!(plain.decompress(&plain.compress(&text), 1200).unwrap(), text);
+    assert_eq!(plain.decompress(&plain.compress(&[]), 1200).unwrap(), b"");
+    let primed = Lz77::with_dictionary(b"for entity position update");
+    assert!(primed.compress(&text).len() < plain.compress(&text).len());
+    assert_eq!(primed.decompress(&primed.compress(&text), 1200).unwrap(), text);
+    assert!(plain.decompress(&plain.compress(&text), 10).is_err());
+    assert!(plain.decompress(&[0x04, 0xFF, 0xFF], 1200).is_err());
+    assert!(plain.decompress(&[0xF0], 1200).is_err());
+    
+    let registry = CodecRegistry::new().with(1, Lz77::new()).with(2, Lz77::with_dictionary(b"snapshot"));
+    let server_config = NetworkConfig {
+        compression: registry.clone(),
+        channel_configs: vec![
+            ChannelConfig { compression: Compression::Preferred, ..Default::default() },
+            ChannelConfig { compression: Compression::Codec(2), ..Default::default() },
+            ChannelConfig { compression: Compression::Codec(3), ..Default::default() },
+        ],
+        ..Default::default()
+    };
+    
+    // Both ends agree on the server's picks among the codecs they share
+    let client_config = NetworkConfig { compression: registry, ..Default::default() };
+    let mut pair = LocalPair::with_configs(server_config, client_config);
+    let client_addr = pair.client_addr();
+    for (channel, expected) in [(0, Some(1)), (1, Some(2)), (2, None), (3, None)] {
+        assert_eq!(pair.client.channel_codec(channel), expected);
+        assert_eq!(pair.server.connection(client_addr).unwrap().channel_codec(channel), expected);
+    }
+    
+    // Compressed payloads arrive intact in both directions, in fewer bytes
+    let message = vec![b'a'; 600];
+    let sent_before = pair.client.stats().bytes_sent;
+    pair.client.send(0, &message, true).unwrap();
+    pair.step_client(Duration::ZERO).unwrap();
+    assert!(pair.client.stats().bytes_sent - sent_before < 100);
+    pair.server.send(client_addr, 1, &message, true).unwrap();
+    let (mut to_server, mut to_client) = (None, None);
+    pair.run_until(60, |pair| {
+        to_server = to_server.take().or_else(|| pair.server.receive(client_addr, 0));
+        to_client = to_client.take().or_else(|| pair.client.receive(1));
+        to_server.is_some() && to_client.is_some()
+    }).unwrap();
+    assert_eq!(to_server.unwrap(), message);
+    assert_eq!(to_client.unwrap(), message);
+    
+    // A client without codecs offers none and every channel stays uncompressed
+    let (mut legacy, mut legacy_socket) = pair.connect_client(NetworkConfig::default());
+    let legacy_addr = legacy_socket.local_addr().unwrap();
+    assert_eq!(legacy.channel_codec(0), None);
+    assert_eq!(pair.server.connection(legacy_addr).unwrap().channel_codec(0), None);
+    legacy.send(0, b"plain", true).unwrap();
+    let mut received = None;
+    for _ in 0..60 {
+        legacy.update(&mut legacy_socket).unwrap();
+        pair.step().unwrap();
+        received = pair.server.receive(legacy_addr, 0);
+        if received.is_some() {
+            break;
+        }
+    }
+    assert_eq!(received.unwrap(), b"plain");
+}
+
+#[test]
+fn test_simulator_burst_loss() {
+    use crate::local::LocalPair;
+    use crate::simulator::{LossModel, NetworkSimulator, SimulatorConfig};
+    use std::time::Duration;
+    
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9);
+    let now = Instant::now();
+    let run = |loss: LossModel, seed: u64| {
+        let mut simulator = NetworkSimulator::new(SimulatorConfig { loss, seed, ..Default::default() });
+        for _ in 0..20_000 {
+            simulator.submit(&[0], addr, now);
+            while simulator.take_due(now).is_some() {}
+        }
+        simulator.stats().clone()
+    };
+    
+    // Same average loss, but Gilbert-Elliott loses packets in runs where uniform loss scatters them
+    let uniform = run(LossModel::Uniform { loss: 0.1 }, 1);
+    let bursty = run(LossModel::bursty(0.1, 5.0), 1);
+    for stats in [&uniform, &bursty] {
+        let loss = stats.packets_dropped as f32 / stats.packets_submitted as f32;
+        assert!((0.08..0.12).contains(&loss), "loss {}", loss);
+    }
+    let mean_burst = |stats: &crate::simulator::SimulatorStats| stats.packets_dropped as f32 / stats.loss_bursts as f32;
+    assert!(mean_burst(&uniform) < 1.5);
+    assert!((4.0..6.0).contains(&mean_burst(&bursty)), "mean burst {}", mean_burst(&bursty));
+    assert!(bursty.longest_burst > uniform.longest_burst);
+    
+    // A seed reproduces a run exactly
+    assert_eq!(run(LossModel::bursty(0.1, 5.0), 1), bursty);
+    assert_ne!(run(LossModel::bursty(0.1, 5.0), 2), bursty);
+    assert_eq!(run(LossModel::None, 1).packets_dropped, 0);
+    
+    // Datagrams wait out the latency, in order
+    let mut simulator = NetworkSimulator::new(SimulatorConfig { latency: Duration::from_millis(50), ..Default::default() });
+    assert!(simulator.submit(&[1], addr, now));
+    assert!(simulator.submit(&[2], addr, now));
+    assert!(simulator.take_due(now + Duration::from_millis(49)).is_none());
+    assert_eq!(simulator.take_due(now + Duration::from_millis(50)).unwrap().0, [1]);
+    assert_eq!(simulator.take_due(now + Duration::from_millis(50)).unwrap().0, [2]);
+    
+    // Reliable messages still get through a bursty, delayed link in both directions
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    let client_addr = pair.client_addr();
+    let impaired = SimulatorConfig { latency: Duration::from_millis(5), loss: LossModel::bursty(0.5, 3.0), seed: 7, ..Default::default() };
+    pair.client_socket_mut().set_simulator(Some(NetworkSimulator::new(impaired.clone())));
+    pair.server.set_simulator(Some(NetworkSimulator::new(SimulatorConfig { seed: 8, ..impaired })));
+    for i in 0..10u8 {
+        pair.client.send(0, &[i], true).unwrap();
+        pair.server.send(client_addr, 0, &[i], true).unwrap();
+    }
+    let (mut to_server, mut to_client) = (Vec::new(), Vec::new());
+    pair.run_until(180, |pair| {
+        to_server.extend(std::iter::from_fn(|| pair.server.receive(client_addr, 0)));
+        to_client.extend(std::iter::from_fn(|| pair.client.receive(0)));
+        to_server.len() == 10 && to_client.len() == 10
+    }).unwrap();
+    let expected: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i]).collect();
+    assert_eq!(to_server, expected);
+    assert_eq!(to_client, expected);
+    let dropped = pair.client_socket_mut().simulator().unwrap().stats().packets_dropped;
+    assert!(dropped + pair.server.simulator().unwrap().stats().packets_dropped > 0);
+}
+
+#[test]
+fn test_seeded_replay() {
+    use crate::local::LocalPair;
+    use crate::server::Server;
+    use crate::simulator::{LossModel, SimulatorConfig};
+    use crate::socket::MemoryNetwork;
+    
+    let config = NetworkConfig { random_seed: Some(0x5EED), ..Default::default() };
+    let session = |client: &Connection| (client.session_seed(), client.resumption_ticket().map(|ticket| ticket.id));
+    
+    let run = |config: &NetworkConfig| session(&LocalPair::new(config.clone()).client);
+    let first = run(&config);
+    assert_eq!(run(&config), first);
+    assert!(first.1.is_some());
+    
+    // Without a seed every run draws fresh values; LocalPair would seed these, so wire them by hand
+    let unseeded = || {
+        let network = MemoryNetwork::new();
+        let server_socket = UdpSocket::bind_memory(&network, SocketAddr::from(([10, 0, 0, 1], 1))).unwrap();
+        let mut server = Server::with_socket(server_socket, NetworkConfig::default()).unwrap();
+        let mut socket = UdpSocket::bind_memory(&network, SocketAddr::from(([10, 0, 0, 2], 1))).unwrap();
+        let mut client = Connection::new(NetworkConfig::default(), socket.local_addr().unwrap(), server.local_addr());
+        client.connect().unwrap();
+        for _ in 0..10 {
+            client.update(&mut socket).unwrap();
+            server.update().unwrap();
+        }
+        assert!(client.is_connected());
+        session(&client)
+    };
+    assert_ne!(unseeded(), unseeded());
+    
+    // Simulators built from the config replay the same drops; their own seed picks the stream
+    let simulator_config = SimulatorConfig { loss: LossModel::bursty(0.2, 4.0), ..Default::default() };
+    let drops = |seed: u64| {
+        let mut simulator = config.simulator(SimulatorConfig { seed, ..simulator_config.clone() });
+        let now = Instant::now();
+        (0..500).map(|_| simulator.submit(&[0], "127.0.0.1:9".parse().unwrap(), now)).collect::<Vec<_>>()
+    };
+    assert_eq!(drops(0), drops(0));
+    assert_ne!(drops(0), drops(1));
+}
+
+#[test]
+fn test_ack_bits_skip_unreceived_sequences() {
+    let now = Instant::now();
+    let mut endpoint = ReliableEndpoint::new(256);
+    
+    // Only sequences that actually arrived are acked; gaps stay clear
+    endpoint.on_packet_received(10, now);
+    assert_eq!(endpoint.get_ack_info(), (10, 0));
+    endpoint.on_packet_received(12, now);
+    assert_eq!(endpoint.get_ack_info(), (12, 0b10));
+    endpoint.on_packet_received(15, now);
+    assert_eq!(endpoint.get_ack_info(), (15, 0b10100));
+    assert_eq!(endpoint.acked_sequences(), vec![15, 12, 10]);
+    
+    // A late packet fills in its bit, and a jump past the window clears everything behind it
+    endpoint.on_packet_received(14, now);
+    assert_eq!(endpoint.get_ack_info(), (15, 0b10101));
+    endpoint.on_packet_received(15 + 32, now);
+    assert_eq!(endpoint.get_ack_info(), (47, 1 << 31));
+    endpoint.on_packet_received(47 + 40, now);
+    assert_eq!(endpoint.get_ack_info(), (87, 0));
+}
+
+#[test]
+fn test_reliability_test_hooks() {
+    use crate::local::LocalPair;
+    use std::time::Duration;
+    
+    let now = Instant::now();
+    let mut endpoint = ReliableEndpoint::new(256).with_max_retries(1);
+    for sequence in 0..3 {
+        assert_eq!(endpoint.next_sequence(), sequence);
+        endpoint.on_packet_sent(sequence, now, vec![sequence as u8]);
+    }
+    assert_eq!(endpoint.in_flight(), vec![0, 1, 2]);
+    
+    // Virtual time passes without waiting, resending everything that timed out
+    assert!(endpoint.update(now).is_empty());
+    endpoint.advance_time(Duration::from_millis(100));
+    assert_eq!(endpoint.update(now).len(), 3);
+    assert_eq!(endpoint.retry_count(1), Some(1));
+    
+    // A forced loss resends on the next update, then gives up once out of retries
+    endpoint.process_acks_at(2, 0b1, now);
+    assert_eq!(endpoint.in_flight(), vec![0]);
+    assert!(endpoint.force_lost(0));
+    assert!(!endpoint.force_lost(2));
+    assert!(endpoint.update(now).is_empty());
+    assert!(endpoint.in_flight().is_empty());
+    
+    // Through a connection, a forced loss shows up as a resend the peer still delivers once
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    let client_addr = pair.client_addr();
+    pair.client.send(0, b"once", true).unwrap();
+    pair.step_client(Duration::ZERO).unwrap();
+    let sequence = *pair.client.reliability_mut().in_flight().last().unwrap();
+    assert!(pair.client.reliability_mut().force_lost(sequence));
+    pair.step_client(Duration::ZERO).unwrap();
+    assert_eq!(pair.client.reliability_mut().stats().packets_resent, 1);
+    
+    let mut received = Vec::new();
+    for _ in 0..6 {
+        pair.step().unwrap();
+        received.extend(std::iter::from_fn(|| pair.server.receive(client_addr, 0)));
+    }
+    assert_eq!(received, vec![b"once".to_vec()]);
+}
+
+#[test]
+fn test_half_open_connection_reset() {
+    use crate::connection::ConnectionEvent;
+    use crate::local::LocalPair;
+    use crate::packet::disconnect_reason;
+    use crate::server::ServerEvent;
+    
+    let config = NetworkConfig::default();
+    let mut pair = LocalPair::new(config.clone());
+    let (client_addr, server_addr) = (pair.client_addr(), pair.server_addr());
+    while pair.server.poll_event().is_some() {}
+    
+    // The client restarts on the same address: the handshake goes through and replaces the old session
+    pair.client = Connection::new(NetworkConfig { clock: pair.clock().clone(), ..config.clone() }, client_addr, server_addr);
+    pair.client.connect().unwrap();
+    assert!(pair.run_until(120, |pair| pair.client.is_connected()).unwrap());
+    assert_eq!(pair.server.poll_event(), Some(ServerEvent::ClientDisconnected(client_addr, disconnect_reason::STALE_SESSION)));
+    assert_eq!(pair.server.poll_event(), Some(ServerEvent::ClientConnected(client_addr)));
+    assert_eq!(pair.server.stats().stale_sessions_replaced, 1);
+    assert_eq!(pair.server.num_clients(), 1);
+    
+    // The server restarts: the client's next packet is answered with a reset instead of silence
+    pair.restart_server(config);
+    pair.client.send(0, b"anyone there?", true).unwrap();
+    assert!(pair.run_until(120, |pair| !pair.client.is_connected()).unwrap());
+    assert!(std::iter::from_fn(|| pair.client.poll_event()).any(|event| matches!(event, ConnectionEvent::SessionReset)));
+    assert_eq!(pair.server.stats().stale_session_replies, 1);
+    assert_eq!(pair.server.num_clients(), 0);
+    
+    // A new session starts cleanly
+    pair.client.connect().unwrap();
+    assert!(pair.run_until(120, |pair| pair.client.is_connected()).unwrap());
+}
+
+#[test]
+fn test_revocation_check() {
+    use crate::local::{LocalPair, DEFAULT_STEP};
+    use crate::packet::disconnect_reason;
+    use crate::server::ServerEvent;
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+    
+    #[derive(Clone, Copy)]
+    struct AccountId(u32);
+    
+    let config = NetworkConfig::default();
+    let mut pair = LocalPair::new(config.clone());
+    let revoked = Arc::new(Mutex::new(HashSet::new()));
+    let list = revoked.clone();
+    pair.server.set_revocation_check(move |_addr: SocketAddr, connection: &Connection| {
+        connection.extensions().get::<AccountId>().is_some_and(|account| list.lock().unwrap().contains(&account.0))
+    });
+    
+    // Accounts are attached once clients authenticate; the pair's client is alice
+    let (_, bob_socket) = pair.connect_client(config.clone());
+    let (alice_addr, bob_addr) = (pair.client_addr(), bob_socket.local_addr().unwrap());
+    let server = &mut pair.server;
+    server.connection_mut(alice_addr).unwrap().extensions_mut().insert(AccountId(1));
+    server.connection_mut(bob_addr).unwrap().extensions_mut().insert(AccountId(2));
+    while server.poll_event().is_some() {}
+    
+    // Nothing changes until the list does and the server applies it
+    assert_eq!(server.apply_revocations().unwrap(), 0);
+    revoked.lock().unwrap().insert(1);
+    assert_eq!(server.apply_revocations().unwrap(), 1);
+    assert_eq!(server.poll_event(), Some(ServerEvent::ClientDisconnected(alice_addr, disconnect_reason::REVOKED)));
+    assert!(server.connection(alice_addr).is_none());
+    assert!(server.connection(bob_addr).is_some());
+    assert_eq!(server.stats().connections_revoked, 1);
+    
+    // The client hears why, and its ticket no longer resumes the session
+    for _ in 0..60 {
+        if !pair.client.is_connected() {
+            break;
+        }
+        pair.step_client(DEFAULT_STEP).unwrap();
+    }
+    assert!(!pair.client.is_connected());
+    assert!(pair.client.resumption_ticket().is_some());
+    pair.client.connect().unwrap();
+    pair.run_until(60, |pair| pair.client.is_connected()).unwrap();
+    let server = &mut pair.server;
+    assert_eq!(server.poll_event(), Some(ServerEvent::ClientConnected(alice_addr)));
+    assert!(server.connection(alice_addr).unwrap().extensions().get::<AccountId>().is_none());
+    
+    // Narrow predicates for just the new entries work the same way
+    let bob_revoked = server.revoke_where(|_, connection| connection.extensions().get::<AccountId>().is_some_and(|account| account.0 == 2));
+    assert_eq!(bob_revoked.unwrap(), 1);
+    assert_eq!(server.num_clients(), 1);
+    
+    // Address-based checks refuse new connections outright
+    let mut socket = UdpSocket::bind_memory(pair.network(), SocketAddr::from(([10, 0, 0, 9], 0))).unwrap();
+    let stranger = socket.local_addr().unwrap();
+    pair.server.set_revocation_check(move |addr: SocketAddr, _: &Connection| addr == stranger);
+    let mut client = Connection::new(NetworkConfig { clock: pair.clock().clone(), ..config }, stranger, pair.server_addr());
+    client.connect().unwrap();
+    let mut denied = None;
+    for _ in 0..60 {
+        if let Err(ConnectionError::ConnectionDenied(reason)) = client.update(&mut socket) {
+            denied = Some(reason);
+            break;
+        }
+        pair.step().unwrap();
+    }
+    assert_eq!(denied, Some(crate::packet::deny_reason::BANNED));
+}
+
+#[test]
+fn test_synced_clock() {
+    use crate::instant::{monotonic, wall_clock, SyncedClock};
+    use crate::local::LocalPair;
+    use std::time::{Duration, UNIX_EPOCH};
+    
+    let before = monotonic();
+    std::thread::sleep(Duration::from_millis(2));
+    assert!(monotonic() >= before + Duration::from_millis(2));
+    
+    // The reference read its clock half a round trip before the sample arrived
+    let local = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let mut clock = SyncedClock::new();
+    assert_eq!(clock.offset_micros(), None);
+    assert_eq!(clock.to_reference(local), local);
+    assert!(clock.observe(local + Duration::from_secs(5), local, Duration::from_millis(100)));
+    assert_eq!(clock.offset_micros(), Some(5_050_000));
+    assert_eq!(clock.accuracy(), Some(Duration::from_millis(50)));
+    
+    // Slower samples are less certain and ignored; faster ones replace the offset, skew either way
+    assert!(!clock.observe(local, local, Duration::from_millis(200)));
+    assert!(clock.observe(local - Duration::from_secs(2), local, Duration::from_millis(20)));
+    assert_eq!(clock.offset_micros(), Some(-1_990_000));
+    assert_eq!(clock.to_reference(local), local - Duration::from_micros(1_990_000));
+    
+    // Clients measure the server's clock during the handshake
+    let pair = LocalPair::new(NetworkConfig::default());
+    let server_clock = pair.client.server_clock();
+    assert!(server_clock.offset_micros().unwrap().abs() < 1_000_000);
+    let skew = server_clock.now().duration_since(wall_clock()).unwrap_or_else(|e| e.duration());
+    assert!(skew < Duration::from_secs(1));
+}
+
+#[test]
+fn test_capability_negotiation() {
+    use crate::compression::{CodecRegistry, Compression, Lz77};
+    use crate::local::LocalPair;
+    use crate::packet::capability;
+    
+    // Shared bits only, and compression cannot be announced without header extensions
+    assert_eq!(capability::negotiate(capability::ALL, capability::ALL), capability::ALL);
+    assert_eq!(capability::negotiate(capability::ALL, capability::HEADER_EXTENSIONS), capability::HEADER_EXTENSIONS);
+    assert_eq!(capability::negotiate(capability::ALL, capability::COMPRESSION), 0);
+    assert_eq!(capability::negotiate(capability::ALL, 1 << 31), 0);
+    
+    let registry = CodecRegistry::new().with(1, Lz77::new());
+    let compressed = NetworkConfig {
+        compression: registry,
+        channel_configs: vec![ChannelConfig { compression: Compression::Preferred, ..Default::default() }],
+        ..Default::default()
+    };
+    let connect = |server_config: &NetworkConfig, client_config: &NetworkConfig| {
+        let pair = LocalPair::with_configs(server_config.clone(), client_config.clone());
+        let (client, server_side) = (&pair.client, pair.server.connection(pair.client_addr()).unwrap());
+        assert_eq!(server_side.capabilities(), client.capabilities());
+        assert_eq!(server_side.channel_codec(0), client.channel_codec(0));
+        (client.capabilities(), client.channel_codec(0))
+    };
+    
+    // Both sides on the new build: everything not opt-in is enabled
+    assert_eq!(connect(&compressed, &compressed), (capability::ALL & !(capability::SEQUENCE_OBFUSCATION | capability::PACKET_ENCRYPTION), Some(1)));
+    
+    // A client holding compression back keeps the rest
+    let held_back = NetworkConfig { capabilities: capability::HEADER_EXTENSIONS, ..compressed.clone() };
+    assert_eq!(connect(&compressed, &held_back), (capability::HEADER_EXTENSIONS, None));
+    
+    // A server advertising nothing turns every optional feature off, for both ends
+    let server_off = NetworkConfig { capabilities: 0, ..compressed.clone() };
+    assert_eq!(connect(&server_off, &compressed), (0, None));
+}
+
+#[test]
+fn test_channel_encryption() {
+    use crate::crypto::SessionKeys;
+    use crate::local::LocalPair;
+    use crate::middleware::{MiddlewareChain, PacketMiddleware};
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    
+    // Keys are separate per channel and direction, even under one static key
+    let keys = SessionKeys::from_static([7; 32]);
+    let (client_send, _) = keys.channel_keys(0, false);
+    let (server_send, server_recv) = keys.channel_keys(0, true);
+    let (_, other_channel) = keys.channel_keys(1, true);
+    let sealed = client_send.seal(1, b"secret");
+    assert_eq!(server_recv.open(&sealed).unwrap(), b"secret");
+    assert!(other_channel.open(&sealed).is_none());
+    assert!(server_recv.open(&server_send.seal(1, b"secret")).is_none());
+    let mut tampered = sealed.clone();
+    tampered[9] ^= 1;
+    assert!(server_recv.open(&tampered).is_none());
+    
+    // Records every datagram the client sends
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<Vec<u8>>>>);
+    impl PacketMiddleware for Capture {
+        fn on_send(&self, _: SocketAddr, data: &mut Vec<u8>) -> io::Result<()> {
+            self.0.lock().unwrap().push(data.clone());
+            Ok(())
+        }
+        fn on_receive(&self, _: SocketAddr, _: &mut Vec<u8>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    
+    let channel_configs = vec![
+        ChannelConfig { encrypted: true, ..Default::default() },
+        ChannelConfig::default(),
+    ];
+    let config = NetworkConfig { channel_configs, ..Default::default() };
+    let capture = Capture::default();
+    let client_config = NetworkConfig { middleware: MiddlewareChain::new().with(capture.clone()), ..config.clone() };
+    let mut pair = LocalPair::with_configs(config, client_config);
+    let client_addr = pair.client_addr();
+    
+    // Both channels deliver, in both directions, but only the clear one shows its bytes
+    capture.0.lock().unwrap().clear();
+    pair.client.send(0, b"chat: meet at the bridge", true).unwrap();
+    pair.client.send(1, b"position 12 40 7", true).unwrap();
+    pair.server.send(client_addr, 0, b"auth ok", true).unwrap();
+    let (mut chat, mut position, mut auth) = (None, None, None);
+    pair.run_until(60, |pair| {
+        chat = chat.take().or_else(|| pair.server.receive(client_addr, 0));
+        position = position.take().or_else(|| pair.server.receive(client_addr, 1));
+        auth = auth.take().or_else(|| pair.client.receive(0));
+        chat.is_some() && position.is_some() && auth.is_some()
+    }).unwrap();
+    assert_eq!(chat.unwrap(), b"chat: meet at the bridge");
+    assert_eq!(position.unwrap(), b"position 12 40 7");
+    assert_eq!(auth.unwrap(), b"auth ok");
+    let contains = |needle: &[u8]| capture.0.lock().unwrap().iter().any(|datagram| datagram.windows(needle.len()).any(|window| window == needle));
+    assert!(contains(b"position 12 40 7"));
+    assert!(!contains(b"meet at the bridge"));
+}
+
+#[test]
+fn test_sequence_obfuscation() {
+    use crate::crypto::SessionKeys;
+    use crate::local::LocalPair;
+    use crate::middleware::{MiddlewareChain, PacketMiddleware};
+    use crate::packet::capability;
+    use std::collections::HashSet;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    
+    // The mask is a permutation, inverted by the other end's receiving mask
+    let keys = SessionKeys::from_static([3; 32]);
+    let (client_send, _) = keys.sequence_masks(false);
+    let (_, server_recv) = keys.sequence_masks(true);
+    let masked: HashSet<u16> = (0..=u16::MAX).map(|sequence| client_send.apply(sequence)).collect();
+    assert_eq!(masked.len(), 1 << 16);
+    assert!((0..=u16::MAX).all(|sequence| server_recv.remove(client_send.apply(sequence)) == sequence));
+    assert!((1..=8).any(|sequence| client_send.apply(sequence) != sequence));
+    
+    // Records the sequence of every payload packet the client sends
+    #[derive(Clone, Default)]
+    struct Sequences(Arc<Mutex<Vec<u16>>>);
+    impl PacketMiddleware for Sequences {
+        fn on_send(&self, _: SocketAddr, data: &mut Vec<u8>) -> io::Result<()> {
+            if let Ok(packet) = Packet::deserialize(data) {
+                if matches!(packet.packet_type, PacketType::Payload { .. }) {
+                    self.0.lock().unwrap().push(packet.header.sequence);
+                }
+            }
+            Ok(())
+        }
+        fn on_receive(&self, _: SocketAddr, _: &mut Vec<u8>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    
+    let obfuscated = NetworkConfig { obfuscate_sequences: true, ..Default::default() };
+    let sequences = Sequences::default();
+    let client_config = NetworkConfig { middleware: MiddlewareChain::new().with(sequences.clone()), ..obfuscated.clone() };
+    let mut pair = LocalPair::with_configs(obfuscated, client_config);
+    let client_addr = pair.client_addr();
+    assert!(pair.client.has_capability(capability::SEQUENCE_OBFUSCATION));
+    assert!(pair.server.connection(client_addr).unwrap().has_capability(capability::SEQUENCE_OBFUSCATION));
+    
+    // Traffic flows and is acked as usual, under sequences that do not count up on the wire
+    let mut received = Vec::new();
+    for i in 0..8u8 {
+        pair.client.send(0, &[i], true).unwrap();
+        pair.step().unwrap();
+    }
+    pair.run_until(60, |pair| {
+        while let Some(message) = pair.server.receive(client_addr, 0) {
+            received.push(message[0]);
+        }
+        received.len() == 8
+    }).unwrap();
+    assert_eq!(received, (0..8).collect::<Vec<_>>());
+    let wire = sequences.0.lock().unwrap().clone();
+    assert!(wire.len() >= 8);
+    assert!(wire.windows(2).any(|pair| pair[1] != pair[0].wrapping_add(1)));
+    
+    // Opt-in: a peer that does not ask for it keeps plain sequences on both ends
+    let (plain, plain_socket) = pair.connect_client(NetworkConfig::default());
+    assert!(!plain.has_capability(capability::SEQUENCE_OBFUSCATION));
+    let plain_addr = plain_socket.local_addr().unwrap();
+    assert!(!pair.server.connection(plain_addr).unwrap().has_capability(capability::SEQUENCE_OBFUSCATION));
+}
+
+#[test]
+fn test_scheduled_send() {
+    use crate::channel::Channel;
+    use crate::local::LocalPair;
+    use std::time::Duration;
+    
+    // Released in time order, equal times in call order, and sequenced on release
+    let mut channel = Channel::new(0, ChannelConfig::default());
+    let now = Instant::now();
+    channel.send_at(b"third", now + Duration::from_millis(20), true).unwrap();
+    channel.send_at(b"first", now + Duration::from_millis(10), true).unwrap();
+    channel.send_at(b"second", now + Duration::from_millis(10), true).unwrap();
+    channel.send(b"now", true).unwrap();
+    assert_eq!(channel.scheduled_messages(), 3);
+    channel.release_scheduled(now + Duration::from_millis(15));
+    assert_eq!(channel.scheduled_messages(), 1);
+    let taken: Vec<_> = std::iter::from_fn(|| channel.take_outgoing_message()).collect();
+    assert_eq!(taken, [(0, b"now".to_vec()), (1, b"first".to_vec()), (2, b"second".to_vec())]);
+    channel.reset();
+    assert_eq!(channel.scheduled_messages(), 0);
+    
+    // Held by the connection until its time, then delivered
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    let client_addr = pair.client_addr();
+    let at = pair.clock().now() + Duration::from_millis(100);
+    pair.client.send_at(0, b"go!", at, true).unwrap();
+    let mut received = None;
+    pair.run_until(60, |pair| {
+        received = pair.server.receive(client_addr, 0);
+        received.is_some()
+    }).unwrap();
+    assert!(pair.clock().now() >= at);
+    assert_eq!(received.unwrap(), b"go!");
+    assert_eq!(pair.client.channel_stats(0).unwrap().scheduled_messages, 0);
+}
+
+#[test]
+fn test_server_handler() {
+    use crate::handler::ServerHandler;
+    use crate::local::LocalPair;
+    use crate::packet::disconnect_reason;
+    use crate::server::{Server, ServerEvent};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    
+    // Echoes messages, and kicks the sender of "quit" from inside the callback
+    struct Echo(Rc<RefCell<Vec<String>>>);
+    impl ServerHandler for Echo {
+        fn on_client_connected(&mut self, _: &mut Server, _: SocketAddr) {
+            self.0.borrow_mut().push("connected".into());
+        }
+        fn on_client_disconnected(&mut self, _: &mut Server, _: SocketAddr, reason: u8) {
+            self.0.borrow_mut().push(format!("disconnected {reason}"));
+        }
+        fn on_message(&mut self, server: &mut Server, addr: SocketAddr, channel_id: u8, message: Vec<u8>) {
+            self.0.borrow_mut().push(String::from_utf8(message.clone()).unwrap());
+            if message == b"quit" {
+                server.disconnect(addr, disconnect_reason::KICKED).unwrap();
+            } else {
+                server.send(addr, channel_id, &message, true).unwrap();
+            }
+        }
+    }
+    
+    // The pair's own client connects before the handler is set, so its event is polled instead
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    assert!(matches!(pair.server.poll_event(), Some(ServerEvent::ClientConnected(_))));
+    pair.server.set_handler(Echo(log.clone()));
+    let (mut client, mut socket) = pair.connect_client(NetworkConfig::default());
+    let client_addr = socket.local_addr().unwrap();
+    assert_eq!(*log.borrow(), ["connected"]);
+    assert!(pair.server.poll_event().is_none());
+    
+    client.send(0, b"hello", true).unwrap();
+    let mut echoed = None;
+    for _ in 0..60 {
+        client.update(&mut socket).unwrap();
+        pair.step().unwrap();
+        echoed = client.receive(0);
+        if echoed.is_some() {
+            break;
+        }
+    }
+    assert_eq!(echoed.unwrap(), b"hello");
+    
+    // The disconnect raised by a callback is delivered in the same dispatch, after it returns
+    client.send(0, b"quit", true).unwrap();
+    for _ in 0..60 {
+        if pair.server.connection(client_addr).is_none() {
+            break;
+        }
+        client.update(&mut socket).unwrap();
+        pair.step().unwrap();
+    }
+    assert_eq!(*log.borrow(), ["connected", "hello", "quit", &format!("disconnected {}", disconnect_reason::KICKED)]);
+    
+    // Without a handler, events queue for polling again
+    pair.server.clear_handler();
+    let (_, _socket) = pair.connect_client(NetworkConfig::default());
+    assert!(matches!(pair.server.poll_event(), Some(ServerEvent::ClientConnected(_))));
+}
+
+#[test]
+fn test_local_pair() {
+    use crate::local::LocalPair;
+    use crate::packet::disconnect_reason;
+    use crate::server::ServerEvent;
+    use std::time::Duration;
+    
+    // The same script replays the same run, with no real time passing
+    let config = NetworkConfig { random_seed: Some(11), ..Default::default() };
+    let script = || {
+        let mut pair = LocalPair::new(config.clone());
+        let client_addr = pair.client_addr();
+        let mut received = Vec::new();
+        for i in 0..20u8 {
+            pair.client.send(0, &[i], true).unwrap();
+            pair.server.send(client_addr, 0, &[i, i], false).unwrap();
+            pair.step().unwrap();
+            while let Some(message) = pair.server.receive(client_addr, 0) {
+                received.push(message);
+            }
+        }
+        let session = pair.client.session_seed();
+        (received, session, pair.client.stats().packets_sent, pair.client.stats().bytes_received, pair.client.stats().rtt)
+    };
+    let started = Instant::now();
+    let first = script();
+    assert_eq!(first.0.len(), 20);
+    assert_eq!(first, script());
+    assert!(started.elapsed() < Duration::from_secs(1));
+    
+    // Timeouts pass in one step of the manual clock
+    let mut pair = LocalPair::new(config);
+    assert!(matches!(pair.server.poll_event(), Some(ServerEvent::ClientConnected(_))));
+    pair.clock().advance(Duration::from_secs(30));
+    pair.server.update().unwrap();
+    assert_eq!(pair.server.poll_event(), Some(ServerEvent::ClientDisconnected(pair.client_addr(), disconnect_reason::TIMEOUT)));
+}
+
+#[test]
+fn test_slow_consumer() {
+    use crate::config::SlowConsumer;
+    use crate::local::LocalPair;
+    use crate::packet::disconnect_reason;
+    use crate::server::ServerEvent;
+    
+    let limited = ChannelConfig { receive_limit: Some(4), ..ChannelConfig::default() };
+    let config = NetworkConfig {
+        random_seed: Some(3),
+        channel_configs: vec![
+            limited,
+            ChannelConfig { reliability: Reliability::Unreliable, ..limited },
+            ChannelConfig { slow_consumer: SlowConsumer::Disconnect, ..limited },
+        ],
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config);
+    let client_addr = pair.client_addr();
+    assert!(matches!(pair.server.poll_event(), Some(ServerEvent::ClientConnected(_))));
+    
+    // Unreliable: only the newest messages survive
+    for i in 0..10u8 {
+        pair.client.send(1, &[i], false).unwrap();
+    }
+    pair.run(5).unwrap();
+    let stats = pair.server.connection(client_addr).unwrap().channel_stats(1).unwrap();
+    assert_eq!((stats.received_pending, stats.receive_dropped), (4, 6));
+    let newest: Vec<_> = std::iter::from_fn(|| pair.server.receive(client_addr, 1)).collect();
+    assert_eq!(newest, vec![vec![6], vec![7], vec![8], vec![9]]);
+    
+    // Reliable backpressure: held at the limit, then everything arrives once drained
+    for i in 0..12u8 {
+        pair.client.send(0, &[i], true).unwrap();
+        pair.step().unwrap();
+    }
+    pair.run(20).unwrap();
+    let stats = pair.server.connection(client_addr).unwrap().channel_stats(0).unwrap();
+    assert!(stats.received_pending < 12);
+    assert!(stats.receive_backpressured > 0);
+    let mut received = Vec::new();
+    let done = pair.run_until(2000, |pair| {
+        while let Some(message) = pair.server.receive(client_addr, 0) {
+            received.push(message[0]);
+        }
+        received.len() == 12
+    }).unwrap();
+    assert!(done);
+    // Refused packets come back as resends, behind later ones
+    received.sort();
+    assert_eq!(received, (0..12).collect::<Vec<_>>());
+    
+    // Reliable disconnect: passing the limit drops the client
+    for i in 0..6u8 {
+        pair.client.send(2, &[i], true).unwrap();
+    }
+    pair.run(5).unwrap();
+    let reason = std::iter::from_fn(|| pair.server.poll_event()).find_map(|event| match event {
+        ServerEvent::ClientDisconnected(addr, reason) if addr == client_addr => Some(reason),
+        _ => None,
+    });
+    assert_eq!(reason, Some(disconnect_reason::SLOW_CONSUMER));
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_bytes_payloads() {
+    use crate::local::LocalPair;
+    use crate::{BitBuffer, BitDeserialize, BitSerialize, ByteAlignedDeserialize, ByteAlignedSerialize};
+    use bytes::{Bytes, BytesMut};
+    
+    // Blobs share the Vec<u8> layout in both encodings
+    let blob = Bytes::from_static(b"tokio frame");
+    let mut bits = BitBuffer::new();
+    blob.bit_serialize(&mut bits).unwrap();
+    let bits = bits.into_bytes(true).unwrap();
+    assert_eq!(Vec::<u8>::bit_deserialize(&mut BitBuffer::from_bytes(bits.clone())).unwrap(), blob.to_vec());
+    assert_eq!(BytesMut::bit_deserialize(&mut BitBuffer::from_bytes(bits)).unwrap(), blob);
+    let mut aligned = Vec::new();
+    BytesMut::from(&blob[..]).byte_aligned_serialize(&mut aligned).unwrap();
+    assert_eq!(Bytes::byte_aligned_deserialize(&mut aligned.as_slice()).unwrap(), blob);
+    
+    let mut pair = LocalPair::new(NetworkConfig { random_seed: Some(5), ..Default::default() });
+    let client_addr = pair.client_addr();
+    pair.client.send_bytes(0, blob.clone(), true).unwrap();
+    pair.server.send_bytes(client_addr, 0, Bytes::from(vec![1, 2, 3]), true).unwrap();
+    pair.run(3).unwrap();
+    assert_eq!(pair.server.receive_bytes(client_addr, 0), Some(blob));
+    assert_eq!(pair.client.receive_bytes(0).as_deref(), Some(&[1u8, 2, 3][..]));
+}
+
+#[test]
+fn test_packet_view_in_place() {
+    use crate::packet::{header_extension, PacketHeader, PacketType, PacketView};
+    use crate::{BitAppender, BitBuffer, BitRead, BitSlice, BitWrite};
+    
+    // The slice reader and appender agree bit for bit with BitBuffer
+    let fields = [(0x5u64, 3), (0xABCD, 16), (1, 1), (u64::MAX, 64), (0x7F, 7)];
+    let mut buffer = BitBuffer::new();
+    let mut appended = vec![0xEE];
+    let mut appender = BitAppender::new(&mut appended);
+    for (value, bits) in fields {
+        buffer.write_bits(value, bits).unwrap();
+        appender.write_bits(value, bits).unwrap();
+    }
+    let expected = buffer.into_bytes(true).unwrap();
+    assert_eq!(appended[1..], expected[..]);
+    let mut reader = BitSlice::new(&expected);
+    for (value, bits) in fields {
+        assert_eq!(reader.read_bits(bits).unwrap(), value);
+    }
+    assert!(reader.read_bits(8).is_err());
+    
+    let header = PacketHeader { protocol_id: 7, sequence: 300, ack: 299, ack_bits: 0xF0F0 };
+    let packet = Packet::new(header, PacketType::Payload { channel: 2, is_fragment: false })
+        .with_payload(vec![9; 40])
+        .with_extension(header_extension::TIMESTAMP, vec![1, 2, 3])
+        .unwrap();
+    let data = packet.serialize().unwrap();
+    
+    // The view borrows the payload from the datagram instead of copying it
+    let view = PacketView::parse(&data).unwrap();
+    assert_eq!(view.payload.as_ptr(), data[data.len() - 40..].as_ptr());
+    assert_eq!(view.extensions.get(header_extension::TIMESTAMP), Some(&[1u8, 2, 3][..]));
+    let mut written = vec![0xAA];
+    view.write_to(&mut written).unwrap();
+    assert_eq!(written[1..], data[..]);
+    let owned = view.to_packet();
+    assert_eq!((owned.header, owned.packet_type, owned.payload), (packet.header, packet.packet_type, packet.payload));
+}
+
+#[test]
+fn test_join_queue() {
+    use crate::connection::{ConnectionError, ConnectionEvent};
+    use crate::local::LocalPair;
+    use crate::packet::{deny_reason, disconnect_reason};
+    use crate::socket::UdpSocket;
+    use std::time::Duration;
+    
+    let config = NetworkConfig {
+        max_clients: 1,
+        join_queue_size: 1,
+        join_queue_update_interval: Duration::from_millis(100),
+        random_seed: Some(8),
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config.clone());
+    let client_config = NetworkConfig { clock: pair.clock().clone(), ..config };
+    let mut waiting = Vec::new();
+    for host in [3, 4] {
+        let mut socket = UdpSocket::bind_memory(pair.network(), SocketAddr::from(([10, 0, 0, host], 0))).unwrap();
+        socket.set_clock(pair.clock().clone());
+        let mut client = Connection::new(client_config.clone(), socket.local_addr().unwrap(), pair.server_addr());
+        client.connect().unwrap();
+        waiting.push((client, socket));
+    }
+    let step = |pair: &mut LocalPair, waiting: &mut Vec<(Connection, UdpSocket)>| {
+        pair.step().unwrap();
+        waiting.iter_mut().map(|(client, socket)| client.update(socket)).collect::<Vec<_>>()
+    };
+    
+    // After the cookie round trip the first extra client waits in line; the queue is then
+    // full, so the second is turned away
+    for _ in 0..3 {
+        step(&mut pair, &mut waiting);
+    }
+    let results = step(&mut pair, &mut waiting);
+    assert!(matches!(results[1], Err(ConnectionError::ConnectionDenied(deny_reason::SERVER_FULL))));
+    let (queued, queued_socket) = &mut waiting[0];
+    assert!(results[0].is_ok());
+    assert_eq!(queued.queue_position(), Some(1));
+    assert_eq!(queued.poll_event(), Some(ConnectionEvent::Connecting { attempt: 1 }));
+    assert_eq!(queued.poll_event(), Some(ConnectionEvent::JoinQueuePosition { position: 1 }));
+    let queued_addr = queued_socket.local_addr().unwrap();
+    assert_eq!(pair.server.queue_position(queued_addr), Some(1));
+    assert_eq!(pair.server.stats().join_queue_entered, 1);
+    
+    // Still queued well past the request timeout, kept alive by position updates and retries
+    for _ in 0..600 {
+        step(&mut pair, &mut waiting).into_iter().take(1).for_each(|result| result.unwrap());
+    }
+    assert!(!waiting[0].0.is_connected());
+    assert_eq!(pair.server.join_queue_len(), 1);
+    
+    // A freed slot goes to the head of the queue
+    pair.client.disconnect(disconnect_reason::REQUESTED).unwrap();
+    for _ in 0..20 {
+        step(&mut pair, &mut waiting).into_iter().take(1).for_each(|result| result.unwrap());
+    }
+    assert!(waiting[0].0.is_connected());
+    assert_eq!(waiting[0].0.queue_position(), None);
+    assert_eq!(pair.server.join_queue_len(), 0);
+    assert_eq!(pair.server.stats().join_queue_promoted, 1);
+    assert!(pair.server.connection(queued_addr).is_some());
+}
+
+#[test]
+fn test_ping_probes() {
+    use crate::local::LocalPair;
+    use crate::packet::{Packet, PacketHeader, PacketType};
+    use crate::ping::{PingConfig, Pinger};
+    use crate::socket::UdpSocket;
+    use std::time::Duration;
+    
+    let config = NetworkConfig { random_seed: Some(9), ..Default::default() };
+    let mut pair = LocalPair::new(config.clone());
+    let mut socket = UdpSocket::bind_memory(pair.network(), SocketAddr::from(([10, 0, 0, 7], 0))).unwrap();
+    socket.set_clock(pair.clock().clone());
+    let ping_config = NetworkConfig { clock: pair.clock().clone(), ..config.clone() };
+    let ping = PingConfig { probes: 3, interval: Duration::from_millis(50), timeout: Duration::from_millis(500) };
+    let mut pinger = Pinger::new(socket, ping_config, ping);
+    
+    // Every server is probed at once; the one that never answers sorts last
+    let dead = SocketAddr::from(([10, 0, 0, 99], 4000));
+    pinger.start(&[dead, pair.server_addr()]).unwrap();
+    let mut steps = 0;
+    while !pinger.poll().unwrap() {
+        pair.step().unwrap();
+        steps += 1;
+    }
+    assert!(steps >= 29, "ran until the timeout because one address is dead");
+    let results = pinger.results();
+    assert_eq!(results[0].addr, pair.server_addr());
+    assert_eq!(results[0].replies, 3);
+    assert!(results[0].rtt.unwrap() > Duration::ZERO && results[0].rtt.unwrap() < Duration::from_millis(50));
+    assert_eq!((results[0].clients, results[0].max_clients), (1, config.max_clients as u16));
+    assert!(results[0].server_time.is_some());
+    assert_eq!(results[1].addr, dead);
+    assert_eq!((results[1].rtt, results[1].replies), (None, 0));
+    
+    // Probes shorter than the reply are ignored, so they cannot be used for amplification
+    let mut raw = UdpSocket::bind_memory(pair.network(), SocketAddr::from(([10, 0, 0, 8], 0))).unwrap();
+    let header = PacketHeader { protocol_id: config.protocol_id, sequence: 0, ack: 0, ack_bits: 0 };
+    let short = Packet::new(header, PacketType::PingRequest).with_payload(vec![0; 4]).serialize().unwrap();
+    raw.send_to(&short, pair.server_addr()).unwrap();
+    pair.run(2).unwrap();
+    assert!(raw.recv_from().is_err());
+}
+
+#[test]
+fn test_weighted_fair_scheduling() {
+    use crate::config::ChannelScheduling;
+    use crate::local::LocalPair;
+    
+    // Both channels always have messages waiting; once the cap's saved up burst is spent it
+    // lets through a fraction of them, counted from then on
+    let run = |channel_scheduling: ChannelScheduling| {
+        let config = NetworkConfig {
+            channel_configs: vec![
+                ChannelConfig { priority: 10, weight: 3, ..Default::default() },
+                ChannelConfig { priority: 0, weight: 1, ..Default::default() },
+            ],
+            channel_scheduling,
+            random_seed: Some(10),
+            ..Default::default()
+        };
+        let client_config = NetworkConfig { bandwidth_cap: Some(30_000.0), ..config.clone() };
+        let mut pair = LocalPair::with_configs(config, client_config);
+        let client_addr = pair.client_addr();
+        let mut received = [0usize; 2];
+        for step in 0..300 {
+            if step == 50 {
+                received = [0; 2];
+            }
+            for channel in 0..2 {
+                while pair.client.channel_stats(channel).unwrap().send_buffer_size < 16 {
+                    pair.client.send(channel, &[channel; 100], true).unwrap();
+                }
+            }
+            pair.step().unwrap();
+            for channel in 0..2 {
+                while pair.server.receive(client_addr, channel).is_some() {
+                    received[channel as usize] += 1;
+                }
+            }
+        }
+        (received, pair.client.channel_stats(1).unwrap())
+    };
+    
+    // Strict priority: the background channel gets nothing
+    let (received, background) = run(ChannelScheduling::Priority);
+    assert!(received[0] > 200);
+    assert_eq!(received[1], 0);
+    assert!(background.starved_streak >= 250);
+    assert_eq!(background.starved_updates, background.starved_streak as u64);
+    
+    // Weighted fair: the bandwidth splits 3:1 and the background channel keeps moving
+    let (received, background) = run(ChannelScheduling::WeightedFair);
+    assert!(received[1] > 100);
+    let share = received[0] as f64 / received[1] as f64;
+    assert!((2.5..3.5).contains(&share), "share {share}");
+    assert!(background.starved_streak < 30);
+}
+
+#[test]
+fn test_control_channel() {
+    use crate::channel::CONTROL_CHANNEL;
+    use crate::local::LocalPair;
+    use crate::packet::capability;
+    use std::time::Duration;
+    
+    let config = NetworkConfig {
+        time_sync_interval: Some(Duration::from_millis(100)),
+        random_seed: Some(11),
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config.clone());
+    let client_addr = pair.client_addr();
+    assert!(pair.client.has_capability(capability::CONTROL_CHANNEL));
+    pair.run(40).unwrap();
+    
+    // Time syncs ran on the control channel, leaving the user channels untouched
+    let control = pair.client.control_stats();
+    assert_eq!(control.id, CONTROL_CHANNEL);
+    assert!(control.messages_sent >= 5);
+    assert!(control.messages_received >= 5);
+    assert_eq!(pair.server.connection(client_addr).unwrap().control_stats().messages_received, control.messages_sent);
+    for channel in 0..8 {
+        assert_eq!(pair.client.channel_stats(channel).unwrap().messages_sent, 0);
+    }
+    // The shared manual clock answers within two steps, the tightest round trip possible here
+    assert!(pair.client.server_clock().accuracy().unwrap() <= Duration::from_millis(17));
+    
+    // A server without the capability gets no control traffic
+    let server_config = NetworkConfig { capabilities: capability::ALL & !capability::CONTROL_CHANNEL, ..config.clone() };
+    let mut pair = LocalPair::with_configs(server_config, config);
+    pair.run(40).unwrap();
+    assert!(!pair.client.has_capability(capability::CONTROL_CHANNEL));
+    assert_eq!(pair.client.control_stats().messages_sent, 0);
+}
+
+#[test]
+fn test_peer_unreachable() {
+    use crate::connection::{ConnectionError, ConnectionEvent};
+    use crate::local::LocalPair;
+    use crate::server::Server;
+    use crate::socket::UdpSocket;
+    
+    let config = NetworkConfig { random_seed: Some(12), ..Default::default() };
+    let mut pair = LocalPair::new(config.clone());
+    pair.network().set_report_unreachable(true);
+    
+    // The server goes away; its port now answers with unreachable reports
+    let socket = UdpSocket::bind_memory(pair.network(), SocketAddr::from(([10, 0, 0, 5], 0))).unwrap();
+    pair.server = Server::with_socket(socket, NetworkConfig { clock: pair.clock().clone(), ..config }).unwrap();
+    let mut result = Ok(());
+    for step in 0..10 {
+        pair.client.send(0, &[step], false).unwrap();
+        result = pair.step();
+        if result.is_err() {
+            break;
+        }
+    }
+    
+    // Three reports in a row end the connection long before the timeout would
+    assert!(matches!(result, Err(ConnectionError::PeerUnreachable)));
+    assert!(!pair.client.is_connected());
+    assert_eq!(pair.client.stats().peer_unreachable, 3);
+    let reports: Vec<_> = std::iter::from_fn(|| pair.client.poll_event())
+        .filter_map(|event| match event {
+            ConnectionEvent::PeerUnreachable { reports } => Some(reports),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(reports, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_wire_profiler() {
+    use crate::NetworkSerialize;
+    use crate::local::LocalPair;
+    use crate::profiler::WireProfiler;
+    
+    #[derive(NetworkSerialize)]
+    #[schema]
+    struct ProfiledMove {
+        #[bits = 10]
+        entity: u16,
+        #[max_len = 15]
+        path: Vec<u8>,
+    }
+    
+    let profiler = WireProfiler::new();
+    let config = NetworkConfig { wire_profiler: Some(profiler.clone()), random_seed: Some(13), ..Default::default() };
+    let mut pair = LocalPair::new(config);
+    pair.run_until(20, |pair| pair.client.is_connected()).unwrap();
+    
+    let moves: Vec<ProfiledMove> = (0..4).map(|i| ProfiledMove { entity: i, path: vec![0; i as usize] }).collect();
+    pair.client.send_batch(0, &moves, true).unwrap();
+    let addr = pair.client_addr();
+    pair.server.send_batch(addr, 0, [7u32, 8], false).unwrap();
+    
+    // 10 + 4 length bits each (plus any debug tag), plus 0..=3 path bytes
+    let report = profiler.report();
+    let profile = profiler.profile("ProfiledMove").unwrap();
+    assert_eq!(report[0], profile);
+    assert_eq!(profile.messages, 4);
+    assert_eq!(profile.bits as usize, 4 * (14 + TAG_BITS) + 6 * 8);
+    let u32s = profiler.profile("u32").unwrap();
+    assert_eq!((u32s.messages, u32s.bits), (2, 64));
+    
+    // Field breakdown comes from the schema in debug builds
+    if cfg!(debug_assertions) {
+        let fields: Vec<_> = profile.fields.iter().map(|field| (field.name.as_str(), field.bits)).collect();
+        let mut expected = vec![("path", 4 * 4 + 6 * 8), ("entity", 40)];
+        if crate::debug_packets::ENABLED {
+            expected.insert(0, ("tag", 4 * TAG_BITS as u64));
+        }
+        assert_eq!(fields, expected);
+    }
+    #[cfg(feature = "json")]
+    assert_eq!(profiler.to_json()["types"][0]["type"], "ProfiledMove");
+    
+    profiler.reset();
+    assert!(profiler.report().is_empty());
+}
+
+#[test]
+fn test_host_connector() {
+    use crate::local::LocalPair;
+    use crate::resolve::{interleave_families, resolve, ConnectError, HostConnector, ResolveError};
+    use crate::socket::UdpSocket;
+    use std::time::Duration;
+    
+    let v4 = |last: u8| SocketAddr::from(([10, 0, 0, last], 7777));
+    let v6 = |last: u16| SocketAddr::from(([0xfd00, 0, 0, 0, 0, 0, 0, last], 7777));
+    assert_eq!(interleave_families(vec![v6(1), v6(2), v6(3), v4(1)]), vec![v6(1), v4(1), v6(2), v6(3)]);
+    assert_eq!(resolve("10.0.0.1:7777"), Ok(vec![v4(1)]));
+    assert!(matches!(resolve("play.example.com"), Err(ResolveError::InvalidAddress(_))));
+    assert!(matches!(HostConnector::new(":40000", NetworkConfig::default()), Err(ResolveError::InvalidAddress(_))));
+    
+    let config = NetworkConfig { random_seed: Some(14), ..Default::default() };
+    let mut pair = LocalPair::new(config.clone());
+    let network = pair.network().clone();
+    let clock = pair.clock().clone();
+    let mut connector = HostConnector::from_addrs(vec![v4(9), pair.server_addr()], NetworkConfig { clock: clock.clone(), ..config })
+        .bind_with(move |_| {
+            let mut socket = UdpSocket::bind_memory(&network, SocketAddr::from(([10, 0, 0, 3], 0)))?;
+            socket.set_clock(clock.clone());
+            Ok(socket)
+        });
+    
+    // Nothing answers at the first address, so the second starts after the attempt delay
+    // and wins the race
+    let mut raced = false;
+    let mut connected = None;
+    for _ in 0..100 {
+        pair.clock().advance(Duration::from_millis(10));
+        match connector.poll() {
+            Ok(Some(result)) => {
+                connected = Some(result);
+                break;
+            }
+            Ok(None) => raced |= connector.attempting() == vec![v4(9), pair.server_addr()],
+            Err(ConnectError::Connection(err)) => panic!("no address connected: {:?}", err),
+            Err(err) => panic!("{}", err),
+        }
+        pair.server.update().unwrap();
+    }
+    let (connection, _socket) = connected.expect("the live address connects");
+    assert!(raced);
+    assert!(connection.is_connected());
+    assert_eq!(connection.remote_addr(), pair.server_addr());
+    assert_eq!(pair.server.num_clients(), 2);
+}
+
+#[test]
+fn test_connection_request_backoff() {
+    use crate::connection::{ConnectionError, ConnectionEvent};
+    use crate::instant::Clock;
+    use crate::socket::MemoryNetwork;
+    use std::time::Duration;
+    
+    let clock = Clock::manual();
+    let network = MemoryNetwork::new();
+    let mut socket = UdpSocket::bind_memory(&network, SocketAddr::from(([10, 0, 0, 2], 0))).unwrap();
+    socket.set_clock(clock.clone());
+    let config = NetworkConfig {
+        clock: clock.clone(),
+        random_seed: Some(15),
+        connection_timeout: Duration::from_secs(60),
+        connection_request_interval: Duration::from_millis(100),
+        connection_request_max_interval: Duration::from_millis(700),
+        connection_request_max_retries: 5,
+        ..Default::default()
+    };
+    
+    // Nobody answers, so requests go out at growing, jittered intervals
+    let mut client = Connection::new(config, socket.local_addr().unwrap(), SocketAddr::from(([10, 0, 0, 1], 7777)));
+    client.connect().unwrap();
+    let start = clock.now();
+    let mut sent_at = Vec::new();
+    let result = loop {
+        let result = client.update(&mut socket);
+        while let Some(event) = client.poll_event() {
+            if let ConnectionEvent::Connecting { attempt } = event {
+                assert_eq!(attempt as usize, sent_at.len() + 1);
+                sent_at.push(clock.now() - start);
+            }
+        }
+        if result.is_err() || clock.now() - start > Duration::from_secs(10) {
+            break result;
+        }
+        clock.advance(Duration::from_millis(1));
+    };
+    assert!(matches!(result, Err(ConnectionError::Timeout)));
+    assert_eq!(sent_at.len(), 6);
+    
+    // 100, 200, 400 then capped at 700 ms, each within 20% either way
+    let gaps: Vec<Duration> = sent_at.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    for (gap, expected) in gaps.iter().zip([100u64, 200, 400, 700, 700]) {
+        let expected = Duration::from_millis(expected);
+        assert!(*gap >= expected.mul_f32(0.8) && *gap <= expected.mul_f32(1.2) + Duration::from_millis(1), "{:?} vs {:?}", gap, expected);
+    }
+    assert_ne!(gaps[3], gaps[4]);
+}
+
+#[test]
+fn test_compression_heuristics() {
+    use crate::compression::{CodecRegistry, Compression, Lz77};
+    use crate::local::LocalPair;
+    use crate::rng::DeterministicRng;
+    
+    let config = NetworkConfig {
+        compression: CodecRegistry::new().with(1, Lz77::new()),
+        channel_configs: vec![ChannelConfig { compression: Compression::Preferred, compression_threshold: 64, ..Default::default() }],
+        random_seed: Some(16),
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config);
+    assert_eq!(pair.client.channel_codec(0), Some(1));
+    let send = |pair: &mut LocalPair, message: &[u8]| {
+        pair.client.send(0, message, true).unwrap();
+        pair.run(2).unwrap();
+        assert_eq!(pair.server.receive(pair.client_addr(), 0).as_deref(), Some(message));
+    };
+    
+    // Short payloads go out stored, costing only the mode byte
+    send(&mut pair, &[7; 20]);
+    let stats = pair.client.channel_stats(0).unwrap();
+    assert_eq!((stats.compression_skipped, stats.compression_ineffective, stats.compression_saved), (1, 0, -1));
+    
+    // Repetitive ones compress
+    send(&mut pair, &[b'a'; 600]);
+    let stats = pair.client.channel_stats(0).unwrap();
+    assert_eq!(stats.compression_skipped, 1);
+    assert!(stats.compression_saved > 500);
+    
+    // Random bytes are recognized as incompressible before the codec runs
+    let mut rng = DeterministicRng::new(3);
+    let noise: Vec<u8> = (0..800).map(|_| rng.next_u32() as u8).collect();
+    let saved = stats.compression_saved;
+    send(&mut pair, &noise);
+    let stats = pair.client.channel_stats(0).unwrap();
+    assert_eq!((stats.compression_skipped, stats.compression_ineffective), (2, 0));
+    assert_eq!(stats.compression_saved, saved - 1);
+}
+
+#[test]
+fn test_config_presets() {
+    use crate::local::LocalPair;
+    use crate::packet::PAYLOAD_HEADER_BYTES;
+    
+    let presets = [
+        ("mobile", NetworkConfig::preset_mobile()),
+        ("lan", NetworkConfig::preset_lan()),
+        ("competitive", NetworkConfig::preset_competitive()),
+    ];
+    for (name, config) in presets {
+        assert!(config.fragment_threshold + PAYLOAD_HEADER_BYTES <= config.mtu, "{}", name);
+        assert!(config.mtu <= 1400, "{}: leaves room for IP and UDP headers", name);
+        assert!(config.keepalive_interval * 3 <= config.connection_timeout, "{}", name);
+        assert!(config.connection_request_interval <= config.connection_request_max_interval, "{}", name);
+        assert!(config.send_rate <= config.max_packet_rate, "{}", name);
+        assert!(config.reliable_retry_time * config.max_reliable_retries <= config.connection_timeout * 2, "{}", name);
+        // Sequence buffers cover at least two seconds of packets at the send rate
+        assert!(config.packet_buffer_size as f32 >= config.send_rate * 2.0, "{}", name);
+        assert_eq!(config.packet_buffer_size, config.ack_buffer_size, "{}", name);
+        
+        let mut pair = LocalPair::new(NetworkConfig { random_seed: Some(7), ..config });
+        let client_addr = pair.client_addr();
+        let big = vec![0x5a; pair.client.max_message_size()];
+        pair.client.send(0, &big, true).unwrap();
+        pair.server.send(client_addr, 0, b"hello", true).unwrap();
+        let mut got_big = false;
+        let mut got_hello = false;
+        pair.run_until(200, |pair| {
+            got_big |= pair.server.receive(client_addr, 0).is_some_and(|message| message == big);
+            got_hello |= pair.client.receive(0).is_some_and(|message| message == b"hello");
+            got_big && got_hello
+        }).unwrap();
+        assert!(got_big && got_hello, "{}", name);
+    }
+}
+
+#[test]
+fn test_idle_cycles_do_not_allocate() {
+    use crate::alloc_audit::{assert_no_allocations, count_allocations};
+    use crate::local::LocalPair;
+    
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    // Let buffers, queues and the pools reach their working size first
+    pair.run(200).unwrap();
+    
+    // Keepalives, acks and timeout checks go through the reused send buffer, in-place
+    // parsing and the memory network's pooled datagrams
+    for _ in 0..300 {
+        assert_no_allocations("an idle poll/flush cycle", || pair.step().unwrap());
+    }
+    
+    // The audit does see allocations
+    let (_, count) = count_allocations(|| pair.client.send(0, &[1, 2, 3], true).unwrap());
+    assert!(count.allocations > 0);
+}
+
+#[test]
+fn test_stats_snapshot_and_reset() {
+    use crate::local::LocalPair;
+    use std::time::Duration;
+    
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    let client_addr = pair.client_addr();
+    let before = pair.client.stats_snapshot();
+    let server_before = pair.server.stats_snapshot();
+    
+    for i in 0..5u8 {
+        pair.client.send(0, &[i; 10], true).unwrap();
+        pair.step().unwrap();
+    }
+    pair.run(5).unwrap();
+    let after = pair.client.stats_snapshot();
+    let interval = after.since(&before);
+    assert_eq!(interval.elapsed, Duration::from_micros(16_667 * 10));
+    assert_eq!(interval.stats.channels[0].messages_sent, 5);
+    assert_eq!(interval.stats.channels[0].bytes_sent, 50);
+    assert_eq!(interval.stats.network.packets_sent, after.stats.network.packets_sent - before.stats.network.packets_sent);
+    assert!(interval.per_second(interval.stats.network.bytes_sent) > 0.0);
+    // The snapshot is a copy; later traffic does not change it
+    pair.client.send(0, &[9], true).unwrap();
+    pair.step().unwrap();
+    assert_eq!(after.stats.channels[0].messages_sent, 5);
+    
+    let server_interval = pair.server.stats_snapshot().since(&server_before);
+    assert!(server_interval.stats.server.packets_received >= 5);
+    assert_eq!(server_interval.stats.clients[&client_addr].channels[0].messages_received, 6);
+    
+    // Resets clear counters, not measurements
+    let rtt = pair.client.stats().rtt;
+    pair.client.stats_reset();
+    pair.server.stats_reset();
+    assert_eq!(pair.client.stats().packets_sent, 0);
+    assert_eq!(pair.client.stats().rtt, rtt);
+    assert_eq!(pair.client.channel_stats(0).unwrap().messages_sent, 0);
+    assert_eq!(pair.server.stats().packets_received, 0);
+    assert_eq!(pair.server.connection(client_addr).unwrap().channel_stats(0).unwrap().messages_received, 0);
+    
+    // An interval spanning a reset counts from zero
+    pair.client.send(0, &[1, 2], true).unwrap();
+    pair.step().unwrap();
+    let interval = pair.client.stats_snapshot().since(&after);
+    assert_eq!(interval.stats.channels[0].messages_sent, 1);
+}
+
+#[test]
+fn test_send_error_policies() {
+    use crate::connection::ConnectionEvent;
+    use crate::local::LocalPair;
+    use crate::socket::{SendErrorClass, SocketError};
+    
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    let client_addr = pair.client_addr();
+    while pair.client.poll_event().is_some() {}
+    let delivered = |pair: &mut LocalPair, message: &[u8]| {
+        pair.run_until(100, |pair| pair.server.receive(client_addr, 0).as_deref() == Some(message)).unwrap()
+    };
+    
+    // A full send buffer holds the queue and retries after a backoff
+    pair.network().fail_sends(client_addr, SendErrorClass::WouldBlock, 3);
+    pair.client.send(0, b"blocked", true).unwrap();
+    pair.step().unwrap();
+    assert_eq!(pair.client.stats().send_errors.would_block, 1);
+    assert!(delivered(&mut pair, b"blocked"));
+    assert_eq!(pair.client.stats().send_errors.would_block, 3);
+    assert_eq!(pair.client.poll_event(), None);
+    
+    // Oversized datagrams are reported, and reliable ones resent like lost packets
+    pair.network().fail_sends(client_addr, SendErrorClass::MessageTooLarge, 1);
+    pair.client.send(0, b"too large", true).unwrap();
+    pair.step().unwrap();
+    assert_eq!(pair.client.poll_event(), Some(ConnectionEvent::SendFailed { class: SendErrorClass::MessageTooLarge }));
+    assert!(delivered(&mut pair, b"too large"));
+    
+    // Unreachable sends are dropped quietly and counted
+    pair.network().fail_sends(client_addr, SendErrorClass::Unreachable, 1);
+    pair.client.send(0, b"no route", false).unwrap();
+    pair.run(3).unwrap();
+    assert_eq!(pair.client.stats().send_errors.unreachable, 1);
+    assert_eq!(pair.server.receive(client_addr, 0), None);
+    assert_eq!(pair.client.poll_event(), None);
+    
+    // Anything else fails the update, and the packet goes out on the next one
+    pair.network().fail_sends(client_addr, SendErrorClass::Other, 1);
+    pair.client.send(0, b"failed", false).unwrap();
+    assert!(matches!(pair.step(), Err(ConnectionError::SocketError(SocketError::Io(_)))));
+    assert!(delivered(&mut pair, b"failed"));
+    assert_eq!(pair.client.stats().send_errors.total(), 6);
+}
+
+#[test]
+fn test_sequence_gap_events() {
+    use crate::connection::ConnectionEvent;
+    use crate::local::{LocalPair, DEFAULT_STEP};
+    use crate::socket::SendErrorClass;
+    
+    let config = NetworkConfig {
+        sequence_gap_threshold: Some(5),
+        channel_configs: vec![ChannelConfig { reliability: Reliability::Unreliable, ..Default::default() }],
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config);
+    let client_addr = pair.client_addr();
+    let server_addr = pair.server_addr();
+    while pair.client.poll_event().is_some() {}
+    
+    // A short loss burst stays under the threshold
+    for (step, lost) in [(0, 0), (1, 3), (2, 0), (3, 8), (4, 0)] {
+        pair.network().fail_sends(server_addr, SendErrorClass::Unreachable, lost);
+        for i in 0..lost.max(1) {
+            pair.server.send(client_addr, 0, &[step, i as u8], false).unwrap();
+            pair.step().unwrap();
+        }
+    }
+    pair.server.send(client_addr, 0, b"after", false).unwrap();
+    pair.step().unwrap();
+    
+    let gaps: Vec<ConnectionEvent> = std::iter::from_fn(|| pair.client.poll_event())
+        .filter(|event| matches!(event, ConnectionEvent::SequenceGap { .. }))
+        .collect();
+    assert_eq!(gaps.len(), 1);
+    let ConnectionEvent::SequenceGap { missing, last_sequence, sequence, silence } = gaps[0] else { unreachable!() };
+    assert_eq!(missing, 8);
+    assert_eq!(sequence.wrapping_sub(last_sequence), 9);
+    assert_eq!(silence, DEFAULT_STEP * 9);
+    assert_eq!(pair.client.stats().sequence_gaps, 1);
+}
+
+#[test]
+fn test_legacy_payload_channel() {
+    use crate::config::PayloadMode;
+    use crate::legacy::{Legacy, LegacyError, LegacyMessage};
+    use crate::local::LocalPair;
+    use crate::{BitSerialize, BitDeserialize, BitBuffer, NetworkSerialize};
+    
+    // Laid out like bincode 1.x: fixed-width little-endian integers, u64 string lengths
+    #[derive(Debug, Clone, PartialEq)]
+    struct Chat {
+        from: u32,
+        text: String,
+    }
+    
+    impl LegacyMessage for Chat {
+        fn encode_legacy(&self, out: &mut Vec<u8>) -> Result<(), LegacyError> {
+            out.extend_from_slice(&self.from.to_le_bytes());
+            out.extend_from_slice(&(self.text.len() as u64).to_le_bytes());
+            out.extend_from_slice(self.text.as_bytes());
+            Ok(())
+        }
+        
+        fn decode_legacy(bytes: &[u8]) -> Result<Self, LegacyError> {
+            let invalid = || LegacyError::Decode("truncated chat".into());
+            let from = u32::from_le_bytes(bytes.get(..4).ok_or_else(invalid)?.try_into().unwrap());
+            let text = String::from_utf8(bytes.get(12..).ok_or_else(invalid)?.to_vec())
+                .map_err(|e| LegacyError::Decode(e.to_string()))?;
+            Ok(Chat { from, text })
+        }
+    }
+    
+    let config = NetworkConfig {
+        channel_configs: vec![
+            ChannelConfig::default(),
+            ChannelConfig { payload_mode: PayloadMode::Legacy, ..Default::default() },
+        ],
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config);
+    let client_addr = pair.client_addr();
+    let chat = Chat { from: 7, text: "gg".into() };
+    
+    // Each channel takes only its own kind of message
+    assert!(matches!(pair.client.send_legacy(0, &chat, true), Err(ConnectionError::ChannelError(ChannelError::PayloadMismatch))));
+    assert!(matches!(pair.client.send(1, b"raw", true), Err(ConnectionError::ChannelError(ChannelError::PayloadMismatch))));
+    
+    // Legacy messages arrive as their length and legacy bytes, untouched
+    pair.client.send_legacy(1, &chat, true).unwrap();
+    pair.client.send(0, b"packed", true).unwrap();
+    assert!(pair.run_until(100, |pair| pair.server.connection(client_addr).is_some_and(|c| c.channel_stats(1).unwrap().messages_received == 1)).unwrap());
+    assert!(pair.server.receive_legacy::<Chat>(client_addr, 0).is_none());
+    assert_eq!(pair.server.receive_legacy::<Chat>(client_addr, 1), Some(Ok(chat.clone())));
+    
+    // Already-encoded buffers pass straight through, and bad frames are reported
+    pair.server.send_legacy(client_addr, 1, &vec![1u8, 2, 3], true).unwrap();
+    assert!(pair.run_until(100, |pair| pair.client.channel_stats(1).unwrap().messages_received == 1).unwrap());
+    assert_eq!(pair.client.receive_legacy::<Vec<u8>>(1), Some(Ok(vec![1, 2, 3])));
+    assert_eq!(crate::legacy::decode_frame::<Vec<u8>>(&[5, 0, 0, 0, 1]), Err(LegacyError::LengthMismatch { declared: 5, actual: 1 }));
+    
+    // Unconverted messages can ride inside bit-packed ones
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    struct Envelope {
+        #[bits = 5]
+        kind: u8,
+        chat: Legacy<Chat>,
+    }
+    let envelope = Envelope { kind: 3, chat: Legacy(chat) };
+    let mut buffer = BitBuffer::new();
+    envelope.bit_serialize(&mut buffer).unwrap();
+    let bytes = buffer.into_bytes(true).unwrap();
+    assert_eq!(bytes.len(), TAG_BITS / 8 + 5 + 12 + 2);
+    assert_eq!(Envelope::bit_deserialize(&mut BitBuffer::from_bytes(bytes)).unwrap(), envelope);
+}
+
+#[test]
+fn test_pause_and_resume() {
+    use crate::connection::ConnectionEvent;
+    use crate::local::LocalPair;
+    use crate::packet::disconnect_reason;
+    use crate::server::ServerEvent;
+    use std::time::Duration;
+    
+    let config = NetworkConfig {
+        connection_timeout: Duration::from_secs(1),
+        pause_grace_period: Duration::from_secs(30),
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config);
+    let client_addr = pair.client_addr();
+    while pair.client.poll_event().is_some() {}
+    while pair.server.poll_event().is_some() {}
+    
+    // The notice goes out with the next flush, and the server holds the slot
+    pair.client.pause().unwrap();
+    assert!(pair.client.is_paused());
+    pair.step().unwrap();
+    assert_eq!(pair.server.poll_event(), Some(ServerEvent::ClientPaused(client_addr)));
+    assert!(pair.server.connection(client_addr).unwrap().is_peer_paused());
+    
+    // Neither end sends or times out while the app sleeps; messages wait in their channels
+    pair.client.send(0, b"from the background", true).unwrap();
+    pair.server.send(client_addr, 0, b"while you were away", true).unwrap();
+    let sent = |pair: &LocalPair| {
+        (pair.client.stats().packets_sent, pair.server.connection(client_addr).unwrap().stats().packets_sent)
+    };
+    let before = sent(&pair);
+    pair.step_by(Duration::from_secs(10)).unwrap();
+    assert_eq!(sent(&pair), before);
+    assert!(pair.client.is_connected());
+    assert!(pair.server.connection(client_addr).is_some());
+    
+    // Resuming confirms the session and releases what was held on both sides
+    pair.client.resume().unwrap();
+    assert!(pair.run_until(100, |pair| pair.client.poll_event() == Some(ConnectionEvent::Resumed)).unwrap());
+    assert_eq!(pair.server.poll_event(), Some(ServerEvent::ClientUnpaused(client_addr)));
+    assert!(pair.run_until(100, |pair| pair.client.receive(0).is_some()).unwrap());
+    assert_eq!(pair.server.receive(client_addr, 0).as_deref(), Some(&b"from the background"[..]));
+    
+    // A client paused past the grace period loses its slot
+    pair.client.pause().unwrap();
+    pair.step().unwrap();
+    pair.step_by(Duration::from_secs(31)).unwrap();
+    let events: Vec<_> = std::iter::from_fn(|| pair.server.poll_event()).collect();
+    assert!(events.contains(&ServerEvent::ClientDisconnected(client_addr, disconnect_reason::TIMEOUT)));
+    assert!(pair.server.connection(client_addr).is_none());
+}
+
+#[test]
+fn test_rtt_histogram() {
+    use crate::local::LocalPair;
+    use crate::rtt::RttHistogram;
+    use std::time::Duration;
+    
+    // Percentiles land within the bucket precision, and the spikes a mean hides show up
+    let mut histogram = RttHistogram::new();
+    assert_eq!(histogram.percentile(50.0), None);
+    for i in 0..100 {
+        let rtt = if i % 20 == 19 { 200 } else { 20 };
+        histogram.record(Duration::from_millis(rtt));
+    }
+    let [p50, p95, p99] = histogram.percentiles([50.0, 95.0, 99.0]);
+    let within = |value: Option<Duration>, expected: u64| {
+        let millis = value.unwrap().as_secs_f64() * 1000.0;
+        (millis - expected as f64).abs() <= expected as f64 * 0.07
+    };
+    assert!(within(p50, 20) && within(p95, 20) && within(p99, 200));
+    assert_eq!(histogram.max(), Some(Duration::from_millis(200)));
+    assert_eq!(histogram.mean(), Some(Duration::from_millis(29)));
+    assert!(histogram.jitter() > Duration::ZERO);
+    
+    let mut merged = RttHistogram::new();
+    merged.merge(&histogram);
+    merged.merge(&histogram);
+    assert_eq!(merged.count(), 200);
+    assert_eq!(merged.percentile(99.0), p99);
+    
+    // Connections export percentiles with their stats, servers across all clients
+    let mut pair = LocalPair::new(NetworkConfig::default());
+    let client_addr = pair.client_addr();
+    for _ in 0..20 {
+        pair.client.send(0, b"tick", true).unwrap();
+        pair.server.send(client_addr, 0, b"tock", true).unwrap();
+        pair.step().unwrap();
+    }
+    let stats = pair.client.stats();
+    assert!(pair.client.rtt_histogram().count() > 0);
+    assert!(stats.rtt_p50 > 0.0 && stats.rtt_p50 <= stats.rtt_p95 && stats.rtt_p95 <= stats.rtt_p99);
+    assert!(pair.server.rtt_histogram().count() > 0);
+    
+    pair.client.stats_reset();
+    assert_eq!(pair.client.rtt_histogram().count(), 0);
+    assert!(pair.client.stats().rtt_p50 > 0.0);
+}
+
+#[test]
+fn test_channel_mismatch() {
+    use crate::channel::ChannelMismatch;
+    use crate::config::{ChannelConfig, ChannelMismatchPolicy, Reliability};
+    use crate::connection::{Connection, ConnectionError, ConnectionEvent};
+    use crate::local::LocalPair;
+    use crate::packet::deny_reason;
+    use crate::server::{Server, ServerEvent};
+    use crate::socket::UdpSocket;
+    use std::time::Duration;
+    
+    let client_config = NetworkConfig { max_channels: 4, random_seed: Some(21), ..Default::default() };
+    let server_config = NetworkConfig {
+        channel_configs: vec![ChannelConfig::default(), ChannelConfig { reliability: Reliability::Unreliable, ..Default::default() }],
+        ..client_config.clone()
+    };
+    let expected = ChannelMismatch { local_channels: 4, remote_channels: 4, differing: vec![1] };
+    
+    // Warn: both ends connect and say which channel differs
+    let mut pair = LocalPair::with_configs(server_config.clone(), client_config.clone());
+    assert!(std::iter::from_fn(|| pair.client.poll_event()).any(|event| event == ConnectionEvent::ChannelMismatch(expected.clone())));
+    let client_addr = pair.client_addr();
+    assert!(std::iter::from_fn(|| pair.server.poll_event())
+        .any(|event| matches!(&event, ServerEvent::ChannelMismatch(addr, mismatch) if *addr == client_addr && *mismatch == expected)));
+    
+    // Fail: the server denies the request
+    let clock = pair.clock().clone();
+    let socket = UdpSocket::bind_memory(pair.network(), SocketAddr::from(([10, 0, 0, 9], 7777))).unwrap();
+    let fail = ChannelMismatchPolicy::Fail;
+    let mut server = Server::with_socket(socket, NetworkConfig { channel_mismatch: fail, clock: clock.clone(), ..server_config }).unwrap();
+    let mut socket = UdpSocket::bind_memory(pair.network(), SocketAddr::from(([10, 0, 0, 10], 0))).unwrap();
+    socket.set_clock(clock.clone());
+    let client_addr = socket.local_addr().unwrap();
+    let mut client = Connection::new(NetworkConfig { clock: clock.clone(), ..client_config }, client_addr, server.local_addr());
+    client.connect().unwrap();
+    let mut result = Ok(());
+    for _ in 0..20 {
+        clock.advance(Duration::from_millis(16));
+        result = client.update(&mut socket);
+        if result.is_err() {
+            break;
+        }
+        server.update().unwrap();
+    }
+    assert!(matches!(result, Err(ConnectionError::ConnectionDenied(deny_reason::CHANNEL_MISMATCH))));
+    assert_eq!(server.num_clients(), 0);
+}
+
+#[test]
+fn test_fragmentation() {
+    use crate::channel::ChannelError;
+    use crate::connection::ConnectionError;
+    use crate::local::LocalPair;
+    use crate::packet::{split_fragments, FRAGMENT_HEADER_BYTES};
+    use crate::reliability::FragmentReassembler;
+    use crate::simulator::{LossModel, NetworkSimulator, SimulatorConfig};
+    use std::time::Duration;
+    
+    // Pieces arrive in any order and more than once; incomplete messages time out
+    let snapshot: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+    let mut fragments = split_fragments(9, &snapshot, 1000);
+    assert_eq!(fragments.len(), 3);
+    assert!(fragments.iter().all(|fragment| fragment.len() <= FRAGMENT_HEADER_BYTES + 1000));
+    fragments.reverse();
+    let now = Instant::now();
+    let mut reassembler = FragmentReassembler::new(256, Duration::from_secs(5));
+    assert_eq!(reassembler.insert(0, &fragments[0], now), None);
+    assert_eq!(reassembler.insert(0, &fragments[0], now), None);
+    assert_eq!(reassembler.insert(0, &fragments[1], now), None);
+    assert_eq!(reassembler.insert(0, &fragments[2], now), Some((9, snapshot.clone())));
+    assert_eq!(reassembler.insert(1, &fragments[0], now), None);
+    assert_eq!(reassembler.expire(now + Duration::from_secs(5)), 1);
+    assert_eq!(reassembler.pending(), 0);
+    
+    // A world snapshot well over the MTU crosses a lossy link in MTU-sized pieces
+    let config = NetworkConfig { random_seed: Some(22), max_fragments: 8, ..Default::default() };
+    let mut pair = LocalPair::new(config);
+    let lossy = SimulatorConfig { loss: LossModel::Uniform { loss: 0.2 }, seed: 3, ..Default::default() };
+    pair.client_socket_mut().set_simulator(Some(NetworkSimulator::new(lossy)));
+    let snapshot: Vec<u8> = (0..5000u32).map(|i| (i * 7) as u8).collect();
+    pair.client.send(0, &snapshot, true).unwrap();
+    let client_addr = pair.client_addr();
+    let mut received = None;
+    pair.run_until(500, |pair| {
+        received = received.take().or_else(|| pair.server.receive(client_addr, 0));
+        received.is_some()
+    }).unwrap();
+    assert_eq!(received, Some(snapshot));
+    assert!(pair.client.stats().fragments_sent >= 5);
+    
+    // Messages needing more than max_fragments pieces are refused up front
+    assert!(matches!(
+        pair.client.send(0, &vec![0; 8 * 1024 + 1], true),
+        Err(ConnectionError::ChannelError(ChannelError::MessageTooLarge))
+    ));
+}
+
+#[test]
+fn test_max_packet_age() {
+    use crate::local::LocalPair;
+    use crate::reliability::PacketAgeEstimator;
+    use std::time::Duration;
+    
+    // Age is the lag behind the fastest packet seen, whatever the peer clock's origin
+    let start = Instant::now();
+    let sender = PacketAgeEstimator::new(start + Duration::from_millis(30));
+    let mut receiver = PacketAgeEstimator::new(start);
+    let early = sender.stamp(start + Duration::from_millis(100));
+    assert_eq!(receiver.age(early, start + Duration::from_millis(140)), Duration::ZERO);
+    let late = sender.stamp(start + Duration::from_millis(200));
+    assert_eq!(receiver.age(late, start + Duration::from_millis(490)), Duration::from_millis(250));
+    
+    // The server stalls while the client keeps streaming state; on its next read only the
+    // recent unreliable packets are delivered, and the reliable one still arrives
+    let unreliable = ChannelConfig { reliability: Reliability::Unreliable, ..ChannelConfig::default() };
+    let config = NetworkConfig {
+        channel_configs: vec![ChannelConfig::default(), unreliable],
+        max_packet_age: Some(Duration::from_millis(120)),
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config);
+    let client_addr = pair.client_addr();
+    pair.client.send(1, b"warmup", false).unwrap();
+    pair.run(3).unwrap();
+    assert_eq!(pair.server.receive(client_addr, 1), Some(b"warmup".to_vec()));
+    
+    pair.client.send(0, b"reliable", true).unwrap();
+    for tick in 0..10u8 {
+        pair.client.send(1, &[tick], false).unwrap();
+        pair.step_client(Duration::from_millis(50)).unwrap();
+    }
+    pair.server.update().unwrap();
+    let mut delivered = Vec::new();
+    while let Some(message) = pair.server.receive(client_addr, 1) {
+        delivered.extend(message);
+    }
+    assert_eq!(delivered, vec![7, 8, 9]);
+    assert!(pair.server.connection(client_addr).unwrap().stats().stale_packets_dropped >= 7);
+    let mut reliable = None;
+    pair.run_until(50, |pair| {
+        reliable = reliable.take().or_else(|| pair.server.receive(client_addr, 0));
+        reliable.is_some()
+    }).unwrap();
+    assert_eq!(reliable, Some(b"reliable".to_vec()));
+}
+
+#[test]
+fn test_path_mtu_discovery() {
+    use crate::connection::ConnectionEvent;
+    use crate::local::LocalPair;
+    use crate::pmtu::{MtuSearch, SEARCH_PRECISION};
+    use crate::simulator::{NetworkSimulator, SimulatorConfig};
+    use std::time::Duration;
+    
+    // Echoes raise the lower bound, silence after every attempt lowers the upper one
+    let now = Instant::now();
+    let timeout = Duration::from_millis(100);
+    let mut search = MtuSearch::new(1200, 1400);
+    let (id, size) = search.next_probe(now, timeout).unwrap();
+    assert_eq!(size, 1300);
+    assert_eq!(search.next_probe(now, timeout), None);
+    assert!(search.on_echo(id));
+    let (_, size) = search.next_probe(now, timeout).unwrap();
+    assert_eq!(size, 1350);
+    assert_eq!(search.next_probe(now + timeout, timeout), Some((1, 1350)));
+    assert_eq!(search.next_probe(now + timeout * 2, timeout).map(|(_, size)| size), Some(1325));
+    assert_eq!(search.mtu(), 1300);
+    
+    // The client's outgoing path drops datagrams over 1340 bytes; the server's does not
+    let config = NetworkConfig { max_mtu: Some(1400), ..Default::default() };
+    let mut pair = LocalPair::new(config);
+    let narrow = SimulatorConfig { mtu: Some(1340), ..Default::default() };
+    pair.client_socket_mut().set_simulator(Some(NetworkSimulator::new(narrow)));
+    let client_addr = pair.client_addr();
+    pair.run(300).unwrap();
+    
+    let client_mtu = pair.client.mtu();
+    assert!(client_mtu <= 1340 && client_mtu > 1340 - SEARCH_PRECISION, "client mtu {}", client_mtu);
+    let mut changes = Vec::new();
+    while let Some(event) = pair.client.poll_event() {
+        if let ConnectionEvent::MtuChanged { mtu } = event {
+            changes.push(mtu);
+        }
+    }
+    assert_eq!(changes.last(), Some(&client_mtu));
+    assert!(pair.server.connection(client_addr).unwrap().mtu() > 1400 - SEARCH_PRECISION);
+    
+    // Messages that needed fragments at the configured MTU now fit one packet
+    assert!(pair.client.max_message_size() > 1200);
+}
+
+#[test]
+fn test_congestion_control() {
+    use crate::congestion::{Aimd, AimdConfig, CongestionControl, CongestionController, GoodBad, GoodBadConfig};
+    use crate::local::LocalPair;
+    use crate::reliability::ReliableEndpoint;
+    use std::time::Duration;
+    
+    let ms = Duration::from_millis;
+    let start = Instant::now();
+    
+    // One step up per round trip, and one cut per round trip however many losses it saw
+    let config = AimdConfig::default();
+    let mut aimd = Aimd::new(config);
+    aimd.on_ack(Some(ms(50)), start);
+    assert_eq!(aimd.allowed_bandwidth(), config.initial_rate + config.increase);
+    aimd.on_ack(Some(ms(50)), start + ms(10));
+    assert_eq!(aimd.allowed_bandwidth(), config.initial_rate + config.increase);
+    aimd.on_ack(Some(ms(50)), start + ms(60));
+    let raised = config.initial_rate + config.increase * 2.0;
+    assert_eq!(aimd.allowed_bandwidth(), raised);
+    aimd.on_loss(start + ms(70));
+    aimd.on_loss(start + ms(80));
+    assert_eq!(aimd.allowed_bandwidth(), raised * config.decrease);
+    aimd.on_loss(start + ms(130));
+    assert_eq!(aimd.allowed_bandwidth(), raised * config.decrease * config.decrease);
+    
+    // Bad mode lasts the penalty, which doubles when good mode does not last
+    let config = GoodBadConfig::default();
+    let secs = Duration::from_secs;
+    let mut flow = GoodBad::new(config);
+    flow.on_ack(Some(ms(50)), start);
+    flow.on_ack(Some(ms(50)), start + secs(10));
+    flow.on_loss(start + secs(20));
+    assert!(!flow.is_good());
+    assert_eq!(flow.allowed_bandwidth(), config.bad_rate);
+    assert_eq!(flow.penalty(), config.initial_penalty);
+    flow.on_ack(Some(ms(50)), start + secs(22));
+    assert!(!flow.is_good());
+    flow.on_ack(Some(ms(50)), start + secs(24));
+    assert!(flow.is_good());
+    flow.on_loss(start + secs(25));
+    assert_eq!(flow.penalty(), config.initial_penalty * 2);
+    flow.on_ack(Some(ms(300)), start + secs(30));
+    flow.on_ack(Some(ms(50)), start + secs(37));
+    assert!(!flow.is_good());
+    flow.on_ack(Some(ms(50)), start + secs(38));
+    assert_eq!(flow.allowed_bandwidth(), config.good_rate);
+    
+    // The endpoint reports losses to its controller
+    let mut endpoint = ReliableEndpoint::new(256).with_congestion_controller(Box::new(Aimd::new(AimdConfig::default())));
+    assert_eq!(endpoint.allowed_bandwidth(), Some(AimdConfig::default().initial_rate));
+    endpoint.on_packet_sent(0, start, vec![0; 16]);
+    assert!(endpoint.force_lost(0));
+    endpoint.update(start + ms(1));
+    assert!(endpoint.allowed_bandwidth().unwrap() < AimdConfig::default().initial_rate);
+    
+    // Connections enforce the controller's rate under the configured cap
+    let config = NetworkConfig {
+        bandwidth_cap: Some(48.0 * 1024.0),
+        congestion_control: Some(CongestionControl::aimd(AimdConfig::default())),
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config);
+    let client_addr = pair.client_addr();
+    pair.client.send(0, b"hello", true).unwrap();
+    pair.run(10).unwrap();
+    assert_eq!(pair.server.receive(client_addr, 0), Some(b"hello".to_vec()));
+    let allowed = pair.client.stats().allowed_bandwidth.unwrap();
+    assert!(allowed <= 48.0 * 1024.0 && allowed >= AimdConfig::default().min_rate, "allowed {}", allowed);
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn test_packet_encryption() {
+    use crate::crypto::{ReplayWindow, SessionKeys, REPLAY_WINDOW};
+    use crate::local::LocalPair;
+    use crate::middleware::{MiddlewareChain, PacketMiddleware};
+    use crate::packet::{capability, deny_reason};
+    use crate::server::Server;
+    use std::io;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    
+    // The header is bound to the sealed payload, and packet keys are not channel keys
+    let keys = SessionKeys::from_static([7; 32]);
+    let (client_send, _) = keys.packet_keys(false);
+    let (_, server_recv) = keys.packet_keys(true);
+    let (_, channel_recv) = keys.channel_keys(0, true);
+    let sealed = client_send.seal_with_header(1, b"header", b"secret");
+    assert_eq!(server_recv.open_with_header(b"header", &sealed).unwrap(), b"secret");
+    assert!(server_recv.open_with_header(b"headex", &sealed).is_none());
+    assert!(server_recv.open(&sealed).is_none());
+    assert!(channel_recv.open_with_header(b"header", &sealed).is_none());
+    
+    // Records every datagram the client sends, flipping a header bit while tampering
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<Vec<u8>>>>, Arc<AtomicBool>);
+    impl PacketMiddleware for Capture {
+        fn on_send(&self, _: SocketAddr, data: &mut Vec<u8>) -> io::Result<()> {
+            if self.1.load(Ordering::Relaxed) {
+                data[6] ^= 1;
+            }
+            self.0.lock().unwrap().push(data.clone());
+            Ok(())
+        }
+        fn on_receive(&self, _: SocketAddr, _: &mut Vec<u8>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    
+    let config = NetworkConfig { encrypt_packets: true, ..Default::default() };
+    let capture = Capture::default();
+    let client_config = NetworkConfig { middleware: MiddlewareChain::new().with(capture.clone()), ..config.clone() };
+    let mut pair = LocalPair::with_configs(config.clone(), client_config);
+    let client_addr = pair.client_addr();
+    assert!(pair.client.has_capability(capability::PACKET_ENCRYPTION));
+    
+    // Messages deliver both ways and never appear on the wire
+    capture.0.lock().unwrap().clear();
+    pair.client.send(0, b"meet at the bridge", true).unwrap();
+    pair.server.send(client_addr, 0, b"on my way", true).unwrap();
+    pair.run(5).unwrap();
+    assert_eq!(pair.server.receive(client_addr, 0), Some(b"meet at the bridge".to_vec()));
+    assert_eq!(pair.client.receive(0), Some(b"on my way".to_vec()));
+    let wire = capture.0.lock().unwrap().clone();
+    assert!(!wire.is_empty());
+    assert!(wire.iter().all(|datagram| !datagram.windows(6).any(|bytes| bytes == b"bridge")));
+    
+    // A packet whose header was altered is dropped; the resend of the untouched one delivers
+    capture.1.store(true, Ordering::Relaxed);
+    pair.client.send(0, b"second", true).unwrap();
+    pair.run(3).unwrap();
+    assert_eq!(pair.server.receive(client_addr, 0), None);
+    capture.1.store(false, Ordering::Relaxed);
+    let mut received = None;
+    pair.run_until(50, |pair| {
+        received = received.take().or_else(|| pair.server.receive(client_addr, 0));
+        received.is_some()
+    }).unwrap();
+    assert_eq!(received, Some(b"second".to_vec()));
+    
+    // Sealed packets captured and sent again open, but are dropped rather than delivered twice
+    capture.0.lock().unwrap().clear();
+    pair.client.send(0, b"once", false).unwrap();
+    pair.run(3).unwrap();
+    assert_eq!(pair.server.receive(client_addr, 0), Some(b"once".to_vec()));
+    let server_addr = pair.server_addr();
+    for datagram in capture.0.lock().unwrap().clone() {
+        pair.client_socket_mut().send_to(&datagram, server_addr).unwrap();
+    }
+    pair.run(3).unwrap();
+    assert_eq!(pair.server.receive(client_addr, 0), None);
+    assert!(pair.server.connection(client_addr).unwrap().stats().replayed_packets_dropped > 0);
+    let mut window = ReplayWindow::default();
+    assert!(window.accept(5000) && window.accept(4990) && window.accept(6000));
+    assert!(!window.accept(5000) && !window.accept(6000 - REPLAY_WINDOW) && window.accept(5999));
+    
+    // A cleartext deny, which anyone could spoof, no longer ends the session
+    let header = PacketHeader { protocol_id: 0x12345678, sequence: 0, ack: 0, ack_bits: 0 };
+    let now = pair.clock().now();
+    pair.client.receive_packet(Packet::new(header, PacketType::ConnectionDeny { reason: 0 }), 12, now).unwrap();
+    pair.run(3).unwrap();
+    assert!(pair.client.is_connected());
+    
+    // A server requiring encryption denies clients that do not offer it
+    let clock = pair.clock().clone();
+    let socket = UdpSocket::bind_memory(pair.network(), SocketAddr::from(([10, 0, 0, 9], 7777))).unwrap();
+    let mut server = Server::with_socket(socket, NetworkConfig { clock: clock.clone(), ..config }).unwrap();
+    let mut socket = UdpSocket::bind_memory(pair.network(), SocketAddr::from(([10, 0, 0, 10], 0))).unwrap();
+    socket.set_clock(clock.clone());
+    let client_addr = socket.local_addr().unwrap();
+    let mut client = Connection::new(NetworkConfig { clock: clock.clone(), ..Default::default() }, client_addr, server.local_addr());
+    client.connect().unwrap();
+    let mut result = Ok(());
+    for _ in 0..20 {
+        clock.advance(Duration::from_millis(16));
+        result = client.update(&mut socket);
+        if result.is_err() {
+            break;
+        }
+        server.update().unwrap();
+    }
+    assert!(matches!(result, Err(ConnectionError::ConnectionDenied(deny_reason::ENCRYPTION_REQUIRED))));
+    assert_eq!(server.num_clients(), 0);
+}
+
+#[test]
+fn test_connect_tokens() {
+    use crate::instant::{wall_clock, Clock};
+    use crate::server::Server;
+    use crate::socket::MemoryNetwork;
+    use crate::token::{ConnectToken, TokenError, MAX_USER_DATA_BYTES};
+    use std::time::Duration;
+    
+    // Tokens verify only under their key and protocol id, unaltered and unexpired
+    let key = [3; 32];
+    let token = ConnectToken::new(42, Duration::from_secs(30), b"region=eu".to_vec());
+    let bytes = token.generate(&key, 0x1234).unwrap();
+    assert_eq!(ConnectToken::verify(&bytes, &key, 0x1234, wall_clock()), Ok(token.clone()));
+    assert_eq!(ConnectToken::verify(&bytes, &[4; 32], 0x1234, wall_clock()), Err(TokenError::BadSignature));
+    assert_eq!(ConnectToken::verify(&bytes, &key, 0x1235, wall_clock()), Err(TokenError::BadSignature));
+    let mut altered = bytes.clone();
+    altered[0] ^= 1;
+    assert_eq!(ConnectToken::verify(&altered, &key, 0x1234, wall_clock()), Err(TokenError::BadSignature));
+    assert_eq!(ConnectToken::verify(&bytes[..10], &key, 0x1234, wall_clock()), Err(TokenError::Malformed));
+    let later = wall_clock() + Duration::from_secs(60);
+    assert_eq!(ConnectToken::verify(&bytes, &key, 0x1234, later), Err(TokenError::Expired));
+    let oversized = ConnectToken { user_data: vec![0; MAX_USER_DATA_BYTES + 1], ..token.clone() };
+    assert_eq!(oversized.generate(&key, 0x1234), Err(TokenError::Malformed));
+    
+    let clock = Clock::manual();
+    let network = MemoryNetwork::new();
+    let config = NetworkConfig { clock: clock.clone(), ..Default::default() };
+    let socket = UdpSocket::bind_memory(&network, SocketAddr::from(([10, 0, 0, 1], 7777))).unwrap();
+    let mut server = Server::with_socket(socket, NetworkConfig { connect_token_key: Some(key), ..config.clone() }).unwrap();
+    let bytes = token.generate(&key, config.protocol_id).unwrap();
+    let connect = |server: &mut Server, host: u8, token: Option<Vec<u8>>| {
+        let mut socket = UdpSocket::bind_memory(&network, SocketAddr::from(([10, 0, 0, host], 0))).unwrap();
+        socket.set_clock(clock.clone());
+        let mut client = Connection::new(config.clone(), socket.local_addr().unwrap(), server.local_addr());
+        client.set_connect_token(token);
+        client.connect().unwrap();
+        for _ in 0..20 {
+            clock.advance(Duration::from_millis(16));
+            client.update(&mut socket).unwrap();
+            server.update().unwrap();
+        }
+        (client, socket.local_addr().unwrap())
+    };
+    
+    // Requests without a token are never answered
+    let (client, _) = connect(&mut server, 2, None);
+    assert!(!client.is_connected());
+    assert_eq!(server.num_clients(), 0);
+    assert!(server.stats().connect_tokens_rejected > 0);
+    
+    // A valid token lets the client in, and the server sees what the backend vouched for
+    let (client, addr) = connect(&mut server, 3, Some(bytes.clone()));
+    assert!(client.is_connected());
+    assert_eq!(server.connection(addr).unwrap().authorized_token(), Some(&token));
+    
+    // The same token from another address is refused
+    let rejected = server.stats().connect_tokens_rejected;
+    let (client, _) = connect(&mut server, 4, Some(bytes));
+    assert!(!client.is_connected());
+    assert!(server.stats().connect_tokens_rejected > rejected);
+    assert_eq!(server.num_clients(), 1);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_async_echo() {
+    use crate::server::Server;
+    use crate::socket::AsyncUdpSocket;
+    use std::time::Duration;
+    
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let local = tokio::task::LocalSet::new();
+    local.block_on(&runtime, async {
+        let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+        let server_socket = AsyncUdpSocket::bind(localhost).unwrap();
+        let server_addr = server_socket.local_addr().unwrap();
+        let mut server = Server::with_socket(server_socket.into_inner(), NetworkConfig::default()).unwrap();
+        
+        // The server echoes channel 0 from its own task, with no thread of its own
+        tokio::task::spawn_local(async move {
+            loop {
+                server.poll(Duration::from_millis(100)).await.unwrap();
+                let clients: Vec<SocketAddr> = server.clients().copied().collect();
+                for client in clients {
+                    while let Some(message) = server.receive(client, 0) {
+                        server.send(client, 0, &message, true).unwrap();
+                    }
+                }
+            }
+        });
+        
+        let mut socket = AsyncUdpSocket::bind(localhost).unwrap();
+        let mut client = Connection::new(NetworkConfig::default(), socket.local_addr().unwrap(), server_addr);
+        client.connect().unwrap();
+        let connected = tokio::time::timeout(Duration::from_secs(5), async {
+            while !client.is_connected() {
+                client.poll(&mut socket).await.unwrap();
+            }
+        }).await;
+        assert!(connected.is_ok());
+        
+        client.send(0, b"hello async", true).unwrap();
+        let echoed = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut socket, 0)).await;
+        assert_eq!(echoed.unwrap().unwrap(), b"hello async");
+        
+        // Closed connections stop waiting once their channels are drained
+        client.disconnect(crate::packet::disconnect_reason::REQUESTED).unwrap();
+        assert!(matches!(client.recv(&mut socket, 0).await, Err(ConnectionError::NotConnected)));
+    });
+}
+
+#[test]
+fn test_simulator_bandwidth() {
+    use crate::congestion::{AimdConfig, CongestionControl};
+    use crate::local::LocalPair;
+    use crate::simulator::{NetworkSimulator, QueueDrop, SimulatorConfig};
+    use std::time::Duration;
+    
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9);
+    let now = Instant::now();
+    let ms = Duration::from_millis;
+    let link = SimulatorConfig { bandwidth: Some(1000.0), queue_bytes: 250, latency: ms(10), ..Default::default() };
+    
+    // Datagrams leave one after another at the link rate, then wait out the latency
+    let mut simulator = NetworkSimulator::new(link.clone());
+    for i in 1..=2u8 {
+        assert!(simulator.submit(&[i; 100], addr, now));
+    }
+    assert_eq!(simulator.queued_bytes(), 200);
+    assert!(simulator.take_due(now + ms(109)).is_none());
+    assert_eq!(simulator.take_due(now + ms(110)).unwrap().0, [1; 100]);
+    assert!(simulator.take_due(now + ms(209)).is_none());
+    assert_eq!(simulator.take_due(now + ms(210)).unwrap().0, [2; 100]);
+    assert_eq!(simulator.pending(), 0);
+    
+    // A full queue drops the arrival, or under head drop the oldest waiting datagram
+    let deliveries = |queue_drop: QueueDrop| {
+        let mut simulator = NetworkSimulator::new(SimulatorConfig { queue_drop, ..link.clone() });
+        let accepted: Vec<bool> = (1..=3u8).map(|i| simulator.submit(&[i; 100], addr, now)).collect();
+        let delivered: Vec<u8> = std::iter::from_fn(|| simulator.take_due(now + ms(1000))).map(|(data, _)| data[0]).collect();
+        (accepted, delivered, simulator.stats().packets_queue_dropped, simulator.stats().peak_queue_bytes)
+    };
+    assert_eq!(deliveries(QueueDrop::Tail), (vec![true, true, false], vec![1, 2], 1, 200));
+    assert_eq!(deliveries(QueueDrop::Head), (vec![true, true, true], vec![2, 3], 1, 200));
+    
+    // AIMD pushed through a 16KB/s bottleneck backs off from its initial rate
+    let config = NetworkConfig {
+        congestion_control: Some(CongestionControl::aimd(AimdConfig::default())),
+        ..Default::default()
+    };
+    let mut pair = LocalPair::new(config);
+    let client_addr = pair.client_addr();
+    let bottleneck = SimulatorConfig { bandwidth: Some(16.0 * 1024.0), queue_bytes: 8 * 1024, latency: ms(20), ..Default::default() };
+    pair.client_socket_mut().set_simulator(Some(NetworkSimulator::new(bottleneck)));
+    let mut received = 0;
+    for _ in 0..300 {
+        // The channel buffer fills once the link backs up; those sends just fail
+        for _ in 0..4 {
+            let _ = pair.client.send(0, &[0; 400], true);
+        }
+        pair.step().unwrap();
+        received += std::iter::from_fn(|| pair.server.receive(client_addr, 0)).count();
+    }
+    let simulator = pair.client_socket_mut().simulator().unwrap();
+    assert!(simulator.stats().packets_queue_dropped > 0);
+    assert!(received > 0);
+    let allowed = pair.client.stats().allowed_bandwidth.unwrap();
+    assert!(allowed < AimdConfig::default().initial_rate, "allowed {}", allowed);
+}
+
+#[test]
+fn test_server_isolates_connection_errors() {
+    use crate::local::LocalPair;
+    use crate::packet::disconnect_reason;
+    use crate::server::ServerEvent;
+    
+    let encrypted = ChannelConfig { encrypted: true, ..Default::default() };
+    let config = NetworkConfig { channel_configs: vec![ChannelConfig::default(), encrypted], ..Default::default() };
+    let mut pair = LocalPair::new(config.clone());
+    let good_addr = pair.client_addr();
+    // A pre-shared key the server lacks leaves its side of the session without keys
+    let (_bad, bad_socket) = pair.connect_client(NetworkConfig { encryption_key: Some([7; 32]), ..config });
+    let bad_addr = bad_socket.local_addr().unwrap();
+    while pair.server.poll_event().is_some() {}
+    
+    // A connection that cannot seal its encrypted channel fails, and only that connection
+    pair.server.send(bad_addr, 1, b"secret", true).unwrap();
+    pair.client.send(0, b"still here", true).unwrap();
+    
+    let mut received = None;
+    pair.run_until(60, |pair| {
+        received = pair.server.receive(good_addr, 0);
+        received.is_some()
+    }).unwrap();
+    assert_eq!(received.as_deref(), Some(&b"still here"[..]));
+    assert!(pair.server.connection(bad_addr).is_none());
+    assert_eq!(pair.server.stats().connection_errors, 1);
+    assert_eq!(pair.server.poll_event(), Some(ServerEvent::ClientDisconnected(bad_addr, disconnect_reason::CONNECTION_ERROR)));
+}
+
+#[test]
+fn test_request_cookie_before_key_exchange() {
+    use crate::crypto::KeyExchange;
+    use crate::packet::header_extension;
+    use crate::server::Server;
+    use crate::socket::MemoryNetwork;
+    
+    let network = MemoryNetwork::new();
+    let bind = |addr: [u8; 4]| UdpSocket::bind_memory(&network, SocketAddr::from((addr, 0))).unwrap();
+    let mut server = Server::with_socket(bind([10, 0, 0, 1]), NetworkConfig::default()).unwrap();
+    let mut socket = bind([10, 0, 0, 2]);
+    let mut other = bind([10, 0, 0, 3]);
+    let header = PacketHeader { protocol_id: 0x12345678, sequence: 0, ack: 0, ack_bits: 0 };
+    let key_share = KeyExchange::new().request_payload();
+    
+    // Sends a keyed request, returning the reply and how many bytes larger it was
+    let exchange = |server: &mut Server, socket: &mut UdpSocket, cookie: Option<Vec<u8>>| {
+        let mut request = Packet::new(header.clone(), PacketType::ConnectionRequest).with_payload(key_share.clone());
+        if let Some(cookie) = cookie {
+            request.extensions.set(header_extension::REQUEST_COOKIE, cookie).unwrap();
+        }
+        let request = request.serialize().unwrap();
+        socket.send_to(&request, server.local_addr()).unwrap();
+        server.update().unwrap();
+        let (data, _) = socket.recv_from().expect("no reply");
+        let growth = data.len() as isize - request.len() as isize;
+        (Packet::deserialize(data).unwrap(), growth)
+    };
+    let is_cookie = |reply: &Packet| reply.payload.is_empty() && reply.extensions.get(header_extension::REQUEST_COOKIE).is_some();
+    
+    // An unproven source gets a cookie and no key material, in a reply no larger than its request
+    let (reply, growth) = exchange(&mut server, &mut socket, None);
+    assert!(matches!(reply.packet_type, PacketType::ConnectionChallenge { .. }));
+    assert!(is_cookie(&reply));
+    assert!(growth <= 0, "reply {} bytes larger than the request", growth);
+    let cookie = reply.extensions.get(header_extension::REQUEST_COOKIE).unwrap().to_vec();
+    
+    // The cookie is bound to its source address and cannot be forged
+    assert!(is_cookie(&exchange(&mut server, &mut other, Some(cookie.clone())).0));
+    let mut forged = cookie.clone();
+    forged[0] ^= 1;
+    assert!(is_cookie(&exchange(&mut server, &mut socket, Some(forged)).0));
+    
+    // Echoing it from the address it was issued to starts the key exchange
+    let (reply, _) = exchange(&mut server, &mut socket, Some(cookie));
+    assert!(matches!(reply.packet_type, PacketType::ConnectionChallenge { .. }));
+    assert_eq!(reply.payload.len(), 32);
+    assert!(reply.extensions.get(header_extension::REQUEST_COOKIE).is_none());
+}
+
+#[test]
+fn test_resume_requires_session_keys() {
+    use crate::local::LocalPair;
+    use crate::packet::{deny_reason, disconnect_reason};
+    use crate::server::ServerEvent;
+    
+    // The pair's client is alice
+    let config = NetworkConfig::default();
+    let mut pair = LocalPair::new(config.clone());
+    let alice_addr = pair.client_addr();
+    let ticket = pair.client.resumption_ticket().cloned().unwrap();
+    while pair.server.poll_event().is_some() {}
+    let client_config = NetworkConfig { clock: pair.clock().clone(), ..config };
+    
+    // The ticket id travels in the clear; on its own it is refused
+    let mut attacker = UdpSocket::bind_memory(pair.network(), SocketAddr::from(([10, 0, 0, 66], 0))).unwrap();
+    let header = PacketHeader { protocol_id: 0x12345678, sequence: 0, ack: 0, ack_bits: 0 };
+    let forged = Packet::new(header, PacketType::ResumeRequest).with_payload(ticket.id.to_vec());
+    attacker.send_to(&forged.serialize().unwrap(), pair.server_addr()).unwrap();
+    pair.server.update().unwrap();
+    let reply = attacker.recv_from().ok().map(|(data, _)| Packet::deserialize(data).unwrap());
+    assert!(matches!(reply.unwrap().packet_type, PacketType::ConnectionDeny { reason: deny_reason::INVALID_TICKET }));
+    
+    // Connects a fresh client holding the ticket, stepping it alongside the pair
+    let resume = |pair: &mut LocalPair, host: u8| {
+        let mut socket = UdpSocket::bind_memory(pair.network(), SocketAddr::from(([10, 0, 0, host], 0))).unwrap();
+        let mut client = Connection::new(client_config.clone(), socket.local_addr().unwrap(), pair.server_addr());
+        client.set_resumption_ticket(Some(ticket.clone()));
+        client.connect().unwrap();
+        for _ in 0..120 {
+            if client.is_connected() {
+                break;
+            }
+            client.update(&mut socket).unwrap();
+            pair.step().unwrap();
+        }
+        assert!(client.is_connected());
+        socket.local_addr().unwrap()
+    };
+    
+    // Even the keys cannot take over a live session; the client falls back to a full handshake
+    let addr = resume(&mut pair, 3);
+    assert_eq!(pair.server.poll_event(), Some(ServerEvent::ClientConnected(addr)));
+    assert!(pair.server.connection(alice_addr).is_some());
+    assert!(pair.client.is_connected());
+    
+    // Once the session is over, the key holder resumes it from anywhere
+    pair.client.disconnect(disconnect_reason::REQUESTED).unwrap();
+    pair.run_until(60, |pair| pair.server.connection(alice_addr).is_none()).unwrap();
+    assert_eq!(pair.server.poll_event(), Some(ServerEvent::ClientDisconnected(alice_addr, disconnect_reason::REQUESTED)));
+    let addr = resume(&mut pair, 4);
+    assert_eq!(pair.server.poll_event(), Some(ServerEvent::ClientResumed(addr)));
+}
@@ -1,7 +1,8 @@
 // src/tests/serialize_tests.rs - Serialization unit tests
 
-use crate::serialize::{BitSerialize, BitDeserialize, bit_io::BitBuffer};
+use crate::serialize::{BitSerialize, BitDeserialize, bit_io::{BitBuffer, BitRead, BitWrite}};
 use gbnet_macros::NetworkSerialize;
+use super::TAG_BITS;
 
 #[derive(NetworkSerialize, Debug, PartialEq)]
 struct TestPacket {
@@ -139,7 +140,7 @@ fn test_bit_packing() -> std::io::Result<()> {
     packed.bit_serialize(&mut buffer)?;
     
     // Should only use 9 bits total
-    assert_eq!(buffer.unpadded_length(), 9);
+    assert_eq!(buffer.unpadded_length(), TAG_BITS + 9);
     
     let bytes = buffer.into_bytes(false)?;
     let mut buffer = BitBuffer::from_bytes(bytes);
@@ -147,4 +148,1013 @@ fn test_bit_packing() -> std::io::Result<()> {
     
     assert_eq!(packed, deserialized);
     Ok(())
-}
\ No newline at end of file
+}
+#[test]
+fn test_byte_aligned_enum_range_checks() -> std::io::Result<()> {
+    use crate::serialize::{ByteAlignedSerialize, ByteAlignedDeserialize};
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    enum Command {
+        Move {
+            #[bits = 4]
+            speed: u8,
+            #[bits = 10]
+            heading: u16,
+        },
+        Shift(#[bits = 3] i8),
+    }
+    
+    // In-range values round trip
+    let command = Command::Move { speed: 15, heading: 1023 };
+    let mut bytes = Vec::new();
+    command.byte_aligned_serialize(&mut bytes)?;
+    assert_eq!(Command::byte_aligned_deserialize(&mut bytes.as_slice())?, command);
+    
+    let command = Command::Shift(7);
+    let mut bytes = Vec::new();
+    command.byte_aligned_serialize(&mut bytes)?;
+    assert_eq!(Command::byte_aligned_deserialize(&mut bytes.as_slice())?, command);
+    
+    // Out-of-range values are rejected like the bit path does
+    for command in [Command::Move { speed: 16, heading: 0 }, Command::Move { speed: 0, heading: 1024 }] {
+        let mut bytes = Vec::new();
+        assert!(command.byte_aligned_serialize(&mut bytes).is_err());
+        
+        let mut buffer = BitBuffer::new();
+        assert!(command.bit_serialize(&mut buffer).is_err());
+    }
+    
+    // Malformed input is rejected on read
+    let bytes = [0u8, 16, 0, 0];
+    assert!(Command::byte_aligned_deserialize(&mut bytes.as_slice()).is_err());
+    
+    Ok(())
+}
+
+#[test]
+fn test_float_fields() -> std::io::Result<()> {
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    struct Transform {
+        x: f32,
+        y: f32,
+        #[bits = 12]
+        #[quantize(min = -100.0, max = 100.0)]
+        yaw: f32,
+        flag: bool,
+    }
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    enum Motion {
+        Still,
+        Moving {
+            speed: f32,
+            #[bits = 8]
+            #[quantize(min = 0, max = 1)]
+            throttle: f64,
+        },
+    }
+    
+    let transform = Transform { x: -1234.5678, y: std::f32::consts::PI, yaw: 42.0, flag: true };
+    let mut buffer = BitBuffer::new();
+    transform.bit_serialize(&mut buffer)?;
+    assert_eq!(buffer.unpadded_length(), TAG_BITS + 32 + 32 + 12 + 1);
+    
+    let bytes = buffer.into_bytes(true)?;
+    let mut buffer = BitBuffer::from_bytes(bytes);
+    let decoded = Transform::bit_deserialize(&mut buffer)?;
+    
+    // Full-width floats are exact, quantized ones are within one step
+    assert_eq!(decoded.x, transform.x);
+    assert_eq!(decoded.y, transform.y);
+    assert!((decoded.yaw - transform.yaw).abs() <= 200.0 / 4095.0);
+    assert!(decoded.flag);
+    
+    let motion = Motion::Moving { speed: 7.25, throttle: 2.0 };
+    let mut buffer = BitBuffer::new();
+    motion.bit_serialize(&mut buffer)?;
+    let bytes = buffer.into_bytes(true)?;
+    let mut buffer = BitBuffer::from_bytes(bytes);
+    
+    // Out-of-range quantized values clamp
+    assert_eq!(Motion::bit_deserialize(&mut buffer)?, Motion::Moving { speed: 7.25, throttle: 1.0 });
+    Ok(())
+}
+
+#[test]
+fn test_full_width_64_bit_fields() -> std::io::Result<()> {
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    struct Wide {
+        flag: bool,
+        salt: u64,
+        #[bits = 64]
+        offset: i64,
+        delta: i16,
+        time: f64,
+        #[bits = 40]
+        tick: u64,
+    }
+    
+    // Leading bool keeps every wide field off byte boundaries
+    let wide = Wide { flag: true, salt: u64::MAX, offset: -1, delta: -300, time: -0.1, tick: (1 << 40) - 1 };
+    let mut buffer = BitBuffer::new();
+    wide.bit_serialize(&mut buffer)?;
+    assert_eq!(buffer.unpadded_length(), TAG_BITS + 1 + 64 + 64 + 16 + 64 + 40);
+    
+    let bytes = buffer.into_bytes(true)?;
+    let mut buffer = BitBuffer::from_bytes(bytes);
+    assert_eq!(Wide::bit_deserialize(&mut buffer)?, wide);
+    
+    // Narrower #[bits] on a u64 is still range checked
+    let too_big = Wide { tick: 1 << 40, ..wide };
+    assert!(too_big.bit_serialize(&mut BitBuffer::new()).is_err());
+    
+    let mut buffer = BitBuffer::new();
+    buffer.write_bits(u64::MAX, 64)?;
+    buffer.write_bits(0xABCD, 64)?;
+    let mut buffer = BitBuffer::from_bytes(buffer.into_bytes(true)?);
+    assert_eq!(buffer.read_bits(64)?, u64::MAX);
+    assert_eq!(buffer.read_bits(64)?, 0xABCD);
+    Ok(())
+}
+
+#[test]
+fn test_char_and_platform_sized_fields() -> std::io::Result<()> {
+    use crate::serialize::{ByteAlignedSerialize, ByteAlignedDeserialize};
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    struct Glyph {
+        symbol: char,
+        #[bits = 12]
+        index: usize,
+        #[bits = 64]
+        offset: isize,
+    }
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    enum Input {
+        Key(char),
+        Cursor { #[bits = 10] column: usize },
+        Scroll { #[bits = 64] delta: isize },
+    }
+    
+    let glyph = Glyph { symbol: '\u{1F600}', index: 4095, offset: -42 };
+    let mut buffer = BitBuffer::new();
+    glyph.bit_serialize(&mut buffer)?;
+    assert_eq!(buffer.unpadded_length(), TAG_BITS + 21 + 12 + 64);
+    let mut buffer = BitBuffer::from_bytes(buffer.into_bytes(true)?);
+    assert_eq!(Glyph::bit_deserialize(&mut buffer)?, glyph);
+    
+    // Byte-aligned chars are UTF-32
+    let mut bytes = Vec::new();
+    glyph.byte_aligned_serialize(&mut bytes)?;
+    assert_eq!(&bytes[..4], &0x1F600u32.to_le_bytes());
+    assert_eq!(Glyph::byte_aligned_deserialize(&mut bytes.as_slice())?, glyph);
+    
+    for input in [Input::Key('é'), Input::Cursor { column: 1023 }, Input::Scroll { delta: -3 }] {
+        let mut buffer = BitBuffer::new();
+        input.bit_serialize(&mut buffer)?;
+        let mut buffer = BitBuffer::from_bytes(buffer.into_bytes(true)?);
+        assert_eq!(Input::bit_deserialize(&mut buffer)?, input);
+        
+        let mut bytes = Vec::new();
+        input.byte_aligned_serialize(&mut bytes)?;
+        assert_eq!(Input::byte_aligned_deserialize(&mut bytes.as_slice())?, input);
+    }
+    
+    // Narrow usize fields are range checked and surrogates are rejected on read
+    let too_big = Glyph { index: 4096, ..glyph };
+    assert!(too_big.bit_serialize(&mut BitBuffer::new()).is_err());
+    
+    let mut buffer = BitBuffer::new();
+    buffer.write_bits(0xD800, 21)?;
+    let mut buffer = BitBuffer::from_bytes(buffer.into_bytes(true)?);
+    assert!(char::bit_deserialize(&mut buffer).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_schema_registration() {
+    use crate::schema::{self, FieldSchema, SchemaKind};
+    
+    #[allow(dead_code)]
+    #[derive(NetworkSerialize)]
+    #[schema]
+    #[default_max_len = 8]
+    struct PlayerState {
+        #[bits = 10]
+        id: u16,
+        #[bits = 12]
+        #[quantize(min = -1.0, max = 1.0)]
+        aim: f32,
+        name: String,
+        #[no_serialize]
+        cached: u32,
+        #[byte_align]
+        inventory: Vec<u8>,
+    }
+    
+    #[allow(dead_code)]
+    #[derive(NetworkSerialize)]
+    #[schema]
+    #[bits = 3]
+    enum Command {
+        Stop,
+        Move(#[bits = 4] u8, bool),
+    }
+    
+    #[allow(dead_code)]
+    #[derive(NetworkSerialize)]
+    struct Unregistered {
+        value: u8,
+    }
+    
+    let SchemaKind::Struct { fields } = schema::find("PlayerState").unwrap().kind else {
+        panic!("PlayerState is a struct");
+    };
+    let field = |name, ty, bits, max_len, byte_align, quantize| FieldSchema { name, ty, bits, max_len, byte_align, quantize, delta_string: false, varint: false, blob: false };
+    assert_eq!(fields, &[
+        field("id", "u16", Some(10), None, false, None),
+        field("aim", "f32", Some(12), None, false, Some((-1.0, 1.0))),
+        field("name", "String", None, Some(8), false, None),
+        field("inventory", "Vec<u8>", None, Some(8), true, None),
+    ]);
+    
+    let SchemaKind::Enum { variant_bits, variants } = schema::find("Command").unwrap().kind else {
+        panic!("Command is an enum");
+    };
+    assert_eq!(variant_bits, 3);
+    assert_eq!(variants[1].name, "Move");
+    assert_eq!(variants[1].index, 1);
+    assert_eq!(variants[1].fields, &[field("0", "u8", Some(4), None, false, None), field("1", "bool", Some(1), None, false, None)]);
+    
+    assert!(schema::find("Unregistered").is_none());
+    
+    #[cfg(feature = "json")]
+    {
+        let dump: serde_json::Value = serde_json::from_str(&schema::dump()).unwrap();
+        let types = dump["types"].as_array().unwrap();
+        let header = types.iter().find(|t| t["name"] == "PacketHeader").unwrap();
+        assert_eq!(header["module"], "gbnet::packet");
+        assert_eq!(header["fields"][0]["name"], "protocol_id");
+        assert_eq!(header["fields"][0]["bits"], 32);
+        assert!(types.iter().any(|t| t["name"] == "PlayerState" && t["fields"][1]["quantize"]["min"] == -1.0));
+    }
+}
+
+#[test]
+fn test_dynamic_message_decoding() -> std::io::Result<()> {
+    use crate::dynamic::{DynamicMessage, DynamicValue};
+    
+    #[derive(NetworkSerialize, Debug, Clone, Copy, PartialEq, Default)]
+    #[schema]
+    struct DynVec2 {
+        #[bits = 8]
+        #[quantize(min = 0.0, max = 255.0)]
+        x: f32,
+        y: i16,
+    }
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[schema]
+    enum DynAction {
+        Idle,
+        Jump { #[bits = 5] height: u8 },
+        Say(String),
+    }
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[schema]
+    struct DynSnapshot {
+        #[bits = 12]
+        tick: u16,
+        origin: DynVec2,
+        #[max_len = 3]
+        path: Vec<DynVec2>,
+        target: Option<u32>,
+        pair: (bool, char),
+        grid: [u8; 2],
+        #[byte_align]
+        label: String,
+        action: DynAction,
+    }
+    
+    let snapshot = DynSnapshot {
+        tick: 4000,
+        origin: DynVec2 { x: 12.0, y: -300 },
+        path: vec![DynVec2 { x: 1.0, y: 2 }],
+        target: None,
+        pair: (true, 'é'),
+        grid: [7, 9],
+        label: "gb".to_string(),
+        action: DynAction::Jump { height: 31 },
+    };
+    let mut buffer = BitBuffer::new();
+    snapshot.bit_serialize(&mut buffer)?;
+    let bytes = buffer.into_bytes(true)?;
+    
+    let message = DynamicMessage::decode("DynSnapshot", &bytes)?;
+    assert_eq!(message.get("tick"), Some(&DynamicValue::UInt(4000)));
+    #[cfg(feature = "json")]
+    assert_eq!(message.to_json(), serde_json::json!({
+        "tick": 4000,
+        "origin": { "x": 12.0, "y": -300 },
+        "path": [{ "x": 1.0, "y": 2 }],
+        "target": null,
+        "pair": [true, "é"],
+        "grid": [7, 9],
+        "label": "gb",
+        "action": { "Jump": { "height": 31 } },
+    }));
+    
+    let mut buffer = BitBuffer::new();
+    DynAction::Idle.bit_serialize(&mut buffer)?;
+    let idle = DynamicMessage::decode("DynAction", &buffer.into_bytes(true)?)?;
+    assert_eq!(idle.variant, Some("Idle"));
+    #[cfg(feature = "json")]
+    assert_eq!(idle.to_json(), serde_json::json!("Idle"));
+    
+    assert_eq!(DynamicMessage::decode("NotRegistered", &bytes).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    assert!(DynamicMessage::decode("DynSnapshot", &bytes[..2]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_delta_string_fields() -> std::io::Result<()> {
+    use crate::delta::StringBaselines;
+    
+    #[derive(NetworkSerialize, Debug, PartialEq, Clone)]
+    struct AssetLoad {
+        #[bits = 4]
+        slot: u8,
+        #[delta_string]
+        #[max_len = 255]
+        path: String,
+    }
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    struct RankName(#[delta_string] String);
+    
+    let encode = |value: &AssetLoad, baselines: &mut StringBaselines| -> std::io::Result<(Vec<u8>, usize)> {
+        let mut buffer = BitBuffer::new();
+        baselines.scope(|| value.bit_serialize(&mut buffer))?;
+        let bits = BitWrite::bit_pos(&buffer);
+        Ok((buffer.into_bytes(true)?, bits))
+    };
+    
+    let mut sent = StringBaselines::new();
+    let mut received = StringBaselines::new();
+    let first = AssetLoad { slot: 1, path: "assets/textures/grass.png".to_string() };
+    let second = AssetLoad { slot: 2, path: "assets/textures/gravel.png".to_string() };
+    
+    // The first value goes out whole, the next only sends what follows "assets/textures/gra"
+    let (bytes, bits) = encode(&first, &mut sent)?;
+    assert_eq!(bits, TAG_BITS + 4 + 8 + 8 + 8 * first.path.len());
+    assert_eq!(received.scope(|| AssetLoad::bit_deserialize(&mut BitBuffer::from_bytes(bytes)))?, first);
+    
+    let (bytes, bits) = encode(&second, &mut sent)?;
+    assert_eq!(bits, TAG_BITS + 4 + 8 + 8 + 8 * "vel.png".len());
+    assert_eq!(received.scope(|| AssetLoad::bit_deserialize(&mut BitBuffer::from_bytes(bytes.clone())))?, second);
+    
+    // A receiver without the baseline cannot rebuild a delta
+    assert!(AssetLoad::bit_deserialize(&mut BitBuffer::from_bytes(bytes)).is_err());
+    
+    // Prefixes never split a multi-byte character
+    let mut sent = StringBaselines::new();
+    let mut received = StringBaselines::new();
+    for name in ["Zoë", "Zoé", "Zoé the Great"] {
+        let mut buffer = BitBuffer::new();
+        sent.scope(|| RankName(name.to_string()).bit_serialize(&mut buffer))?;
+        let decoded = received.scope(|| RankName::bit_deserialize(&mut BitBuffer::from_bytes(buffer.into_bytes(true)?)))?;
+        assert_eq!(decoded.0, name);
+    }
+    
+    // Outside a scope values are always sent whole
+    for _ in 0..2 {
+        let mut buffer = BitBuffer::new();
+        first.bit_serialize(&mut buffer)?;
+        assert_eq!(AssetLoad::bit_deserialize(&mut BitBuffer::from_bytes(buffer.into_bytes(true)?))?, first);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_delta_vec() -> std::io::Result<()> {
+    use crate::delta_vec::{DeltaVec, ListChange, ListDelta, ListOp};
+    
+    let send = |delta: &ListDelta<u32>| -> std::io::Result<ListDelta<u32>> {
+        let mut buffer = BitBuffer::new();
+        delta.bit_serialize(&mut buffer)?;
+        ListDelta::bit_deserialize(&mut BitBuffer::from_bytes(buffer.into_bytes(true)?))
+    };
+    
+    let mut sender: DeltaVec<u32> = (0..20).collect::<Vec<_>>().into();
+    let mut receiver = DeltaVec::new();
+    
+    // Nothing acked yet, so the whole list goes
+    let first = sender.update()?;
+    assert!(matches!(first.change, ListChange::Full(_)));
+    assert!(receiver.apply(&send(&first)?)?);
+    sender.ack(first.version);
+    
+    // One element changing costs one removal and one insertion
+    sender[5] = 500;
+    sender.push(20);
+    let second = sender.update()?;
+    assert_eq!(second.change, ListChange::Edits { base: 1, ops: vec![
+        ListOp::Remove { index: 5 },
+        ListOp::Insert { index: 5, value: 500 },
+        ListOp::Insert { index: 20, value: 20 },
+    ] });
+    
+    // Lost on the way; the next update still edits version 1, the last one acked
+    let moved = sender.remove(0);
+    sender.push(moved);
+    let third = sender.update()?;
+    assert_eq!(third.change, ListChange::Edits { base: 1, ops: vec![
+        ListOp::Remove { index: 5 },
+        ListOp::Move { from: 0, to: 18 },
+        ListOp::Insert { index: 4, value: 500 },
+        ListOp::Insert { index: 19, value: 20 },
+    ] });
+    assert!(receiver.apply(&send(&third)?)?);
+    assert_eq!(*receiver, *sender);
+    sender.ack(third.version);
+    
+    // Stale updates are ignored, unknown baselines rejected
+    assert!(!receiver.apply(&first)?);
+    let unknown = ListDelta { version: 9, change: ListChange::Edits { base: 7, ops: Vec::new() } };
+    assert!(receiver.apply(&unknown).is_err());
+    
+    // Rewriting everything is cheaper as a full list
+    for value in sender.iter_mut() {
+        *value += 1000;
+    }
+    let rewrite = sender.update()?;
+    assert!(matches!(rewrite.change, ListChange::Full(_)));
+    assert!(receiver.apply(&send(&rewrite)?)?);
+    assert_eq!(*receiver, *sender);
+    Ok(())
+}
+
+#[test]
+fn test_map_serialization_is_deterministic() -> std::io::Result<()> {
+    use crate::dynamic::{DynamicMessage, DynamicValue};
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[schema]
+    struct Scores {
+        by_player: HashMap<u16, u32>,
+        muted: HashSet<u8>,
+    }
+    
+    let encode = |value: &dyn crate::serialize::DynBitSerialize| -> std::io::Result<Vec<u8>> {
+        let mut buffer = BitBuffer::new();
+        value.bit_serialize(&mut buffer)?;
+        buffer.into_bytes(true)
+    };
+    
+    // Insertion order and hasher seeds do not change the bits: keys go out sorted
+    let entries: Vec<(u16, u32)> = (0..64).map(|i| ((i * 37) % 101, i as u32)).collect();
+    let forward: HashMap<u16, u32> = entries.iter().copied().collect();
+    let backward: HashMap<u16, u32> = entries.iter().rev().copied().collect();
+    let sorted: BTreeMap<u16, u32> = entries.iter().copied().collect();
+    assert_eq!(encode(&forward)?, encode(&backward)?);
+    assert_eq!(encode(&forward)?, encode(&sorted)?);
+    assert_eq!(HashMap::<u16, u32>::bit_deserialize(&mut BitBuffer::from_bytes(encode(&sorted)?))?, forward);
+    
+    // A second encoding of the same contents is rejected
+    let mut buffer = BitBuffer::new();
+    buffer.write_bits(2, 16)?;
+    for key in [5u16, 3] {
+        key.bit_serialize(&mut buffer)?;
+        0u32.bit_serialize(&mut buffer)?;
+    }
+    assert!(BTreeMap::<u16, u32>::bit_deserialize(&mut BitBuffer::from_bytes(buffer.into_bytes(true)?)).is_err());
+    
+    let scores = Scores { by_player: forward, muted: [9, 2, 4].into_iter().collect() };
+    let bytes = encode(&scores)?;
+    assert_eq!(Scores::bit_deserialize(&mut BitBuffer::from_bytes(bytes.clone()))?, scores);
+    let message = DynamicMessage::decode("Scores", &bytes)?;
+    assert_eq!(message.get("muted"), Some(&DynamicValue::List(vec![DynamicValue::UInt(2), DynamicValue::UInt(4), DynamicValue::UInt(9)])));
+    let Some(DynamicValue::List(pairs)) = message.get("by_player") else {
+        panic!("maps decode as lists");
+    };
+    assert_eq!(pairs[0], DynamicValue::List(vec![DynamicValue::UInt(0), DynamicValue::UInt(0)]));
+    Ok(())
+}
+
+#[test]
+fn test_dyn_bit_serialize() -> std::io::Result<()> {
+    use crate::serialize::DynBitSerialize;
+    
+    // A heterogeneous queue of boxed messages, written through a `dyn BitWrite`
+    let queue: Vec<Box<dyn DynBitSerialize + Send>> = vec![
+        Box::new(TestPacket { id: 7, active: true }),
+        Box::new(1234u16),
+        Box::new(vec![1u8, 2, 3]),
+    ];
+    
+    let mut buffer = BitBuffer::new();
+    {
+        let writer: &mut dyn BitWrite = &mut buffer;
+        for message in &queue {
+            message.dyn_bit_serialize(writer)?;
+        }
+    }
+    let bytes = buffer.into_bytes(true)?;
+    
+    // Generic deserialization works through a `dyn BitRead` too
+    let mut buffer = BitBuffer::from_bytes(bytes.clone());
+    let mut reader: &mut dyn BitRead = &mut buffer;
+    assert_eq!(TestPacket::bit_deserialize(&mut reader)?, TestPacket { id: 7, active: true });
+    assert_eq!(u16::bit_deserialize(&mut reader)?, 1234);
+    assert_eq!(Vec::<u8>::bit_deserialize(&mut reader)?, vec![1, 2, 3]);
+    
+    // Boxed messages are themselves `BitSerialize`, so the queue serializes like any Vec
+    let mut buffer = BitBuffer::new();
+    for message in &queue {
+        message.bit_serialize(&mut buffer)?;
+    }
+    assert_eq!(buffer.into_bytes(true)?, bytes);
+    Ok(())
+}
+
+#[test]
+fn test_lsb_first_bit_order() -> std::io::Result<()> {
+    use crate::serialize::bit_io::BitOrder;
+    
+    // The first value takes the low bits of the byte
+    let mut lsb = BitBuffer::new().with_bit_order(BitOrder::LsbFirst);
+    lsb.write_bits(0b101, 3)?;
+    lsb.write_bits(0b11000, 5)?;
+    lsb.write_bits(0x1234, 16)?;
+    lsb.write_bit(true)?;
+    assert_eq!(lsb.to_bit_string(25), "10100011 00101100 01001000 1");
+    assert_eq!(lsb.into_bytes(true)?, [0b1100_0101, 0x34, 0x12, 0x01]);
+    
+    let mut msb = BitBuffer::new();
+    msb.write_bits(0b101, 3)?;
+    msb.write_bits(0b11000, 5)?;
+    msb.write_bits(0x1234, 16)?;
+    assert_eq!(msb.into_bytes(true)?, [0b1011_1000, 0x12, 0x34]);
+    
+    // Values straddling bytes, up to 64 bits, read back in the same order
+    let values = [(0x3FFu64, 10), (0, 1), (u64::MAX, 64), (0x5A5A_5A5A, 31), (1, 1), (0xABCDEF, 24)];
+    let mut buffer = BitBuffer::new().with_bit_order(BitOrder::LsbFirst);
+    for &(value, bits) in &values {
+        buffer.write_bits(value & (u64::MAX >> (64 - bits)), bits)?;
+    }
+    let mut buffer = BitBuffer::from_bytes(buffer.into_bytes(true)?).with_bit_order(BitOrder::LsbFirst);
+    for &(value, bits) in &values {
+        assert_eq!(buffer.read_bits(bits)?, value & (u64::MAX >> (64 - bits)));
+    }
+    assert!(buffer.read_bits(8).is_err());
+    
+    // The derive does not care which order the buffer uses
+    let packet = TestPacket { id: 42, active: true };
+    let mut buffer = BitBuffer::new().with_bit_order(BitOrder::LsbFirst);
+    packet.bit_serialize(&mut buffer)?;
+    let bytes = buffer.into_bytes(true)?;
+    assert_eq!(bytes[TAG_BITS / 8..], [42 | 1 << 6]);
+    let mut buffer = BitBuffer::from_bytes(bytes).with_bit_order(BitOrder::LsbFirst);
+    assert_eq!(TestPacket::bit_deserialize(&mut buffer)?, packet);
+    Ok(())
+}
+
+#[test]
+fn test_prelude() -> std::io::Result<()> {
+    use crate::prelude::*;
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    struct Hello {
+        #[bits = 4]
+        version: u8,
+    }
+    
+    let mut buffer = BitBuffer::new();
+    Hello { version: 3 }.bit_serialize(&mut buffer)?;
+    assert_eq!(BitWrite::bit_pos(&buffer), TAG_BITS + 4);
+    assert_eq!(Hello::bit_deserialize(&mut BitBuffer::from_bytes(buffer.into_bytes(true)?))?, Hello { version: 3 });
+    
+    let config = NetworkConfig { default_channel_config: ChannelConfig { reliability: Reliability::Reliable, ..Default::default() }, ..Default::default() };
+    let mut connection = Connection::new(config, SocketAddr::from(([127, 0, 0, 1], 1)), SocketAddr::from(([127, 0, 0, 1], 2)));
+    assert!(matches!(connection.send(0, b"hi", true), Err(ConnectionError::NotConnected)));
+    Ok(())
+}
+
+#[test]
+fn test_borrowed_deserialization() -> std::io::Result<()> {
+    use crate::serialize::BitDeserializeBorrowed;
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    struct Upload<'a> {
+        #[bits = 3]
+        kind: u8,
+        name: &'a str,
+        data: &'a [u8],
+        tags: Vec<u16>,
+    }
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    enum Chat<'a> {
+        Say { text: &'a str },
+        Upload(Upload<'a>),
+    }
+    
+    let data: Vec<u8> = (0..200).collect();
+    let message = Chat::Upload(Upload { kind: 5, name: "map.bin", data: &data, tags: vec![7, 9] });
+    let mut buffer = BitBuffer::new();
+    message.bit_serialize(&mut buffer)?;
+    let bytes = buffer.into_bytes(true)?;
+    
+    let decoded = Chat::from_bytes_borrowed(&bytes)?;
+    assert_eq!(decoded, message);
+    // The blob is a view into the received bytes, not a copy
+    let Chat::Upload(upload) = &decoded else { unreachable!() };
+    assert!(bytes.as_ptr_range().contains(&upload.data.as_ptr()));
+    
+    let mut buffer = BitBuffer::new();
+    Chat::Say { text: "hello" }.bit_serialize(&mut buffer)?;
+    let bytes = buffer.into_bytes(true)?;
+    assert_eq!(Chat::from_bytes_borrowed(&bytes)?, Chat::Say { text: "hello" });
+    
+    // Owned types read through the same trait, and truncated or invalid input fails
+    assert_eq!(u16::from_bytes_borrowed(&[0x12, 0x34])?, 0x1234);
+    assert!(Chat::from_bytes_borrowed(&bytes[..bytes.len() - 1]).is_err());
+    assert!(<&str>::from_bytes_borrowed(&[0x00, 0x01, 0xff]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_combined_field_writes() -> std::io::Result<()> {
+    use crate::serialize::bit_io::{BitAppender, BitOrder};
+    
+    // Runs of fixed-width fields go out in one write each, split where 64 bits would overflow
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    struct Input {
+        #[bits = 4]
+        buttons: u8,
+        crouch: bool,
+        delta: i8,
+        #[bits = 12]
+        yaw: u16,
+        tick: u32,
+        frame: u32,
+        name: String,
+        #[bits = 3]
+        weapon: u8,
+        fire: bool,
+    }
+    
+    let input = Input { buttons: 9, crouch: true, delta: -3, yaw: 3000, tick: 0xDEADBEEF, frame: 7, name: "a".into(), weapon: 5, fire: true };
+    fn field_by_field<W: BitWrite>(writer: &mut W) -> std::io::Result<()> {
+        writer.write_bits(9, 4)?;
+        writer.write_bits(1, 1)?;
+        writer.write_bits(-3i8 as u64, 8)?;
+        writer.write_bits(3000, 12)?;
+        writer.write_bits(0xDEADBEEF, 32)?;
+        writer.write_bits(7, 32)?;
+        "a".to_string().bit_serialize(writer)?;
+        writer.write_bits(5, 3)?;
+        writer.write_bits(1, 1)
+    }
+    
+    // The same bits as writing each field on its own, in either bit order
+    for order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+        let mut combined = BitBuffer::new().with_bit_order(order);
+        input.bit_serialize(&mut combined)?;
+        let mut separate = BitBuffer::new().with_bit_order(order);
+        field_by_field(&mut separate)?;
+        let bytes = combined.into_bytes(true)?;
+        assert_eq!(bytes[TAG_BITS / 8..], separate.into_bytes(true)?);
+        let mut reader = BitBuffer::from_bytes(bytes).with_bit_order(order);
+        assert_eq!(Input::bit_deserialize(&mut reader)?, input);
+    }
+    let mut appended = Vec::new();
+    input.bit_serialize(&mut BitAppender::new(&mut appended))?;
+    let mut separate = BitBuffer::new();
+    field_by_field(&mut separate)?;
+    assert_eq!(appended[TAG_BITS / 8..], separate.into_bytes(true)?);
+    
+    // Range checks still run for every field of a combined run
+    let too_wide = Input { buttons: 16, ..input };
+    assert!(too_wide.bit_serialize(&mut BitBuffer::new()).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_derive_inline_and_byte_helpers() -> std::io::Result<()> {
+    use crate::serialize::BitDeserializeBorrowed;
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[gbnet(no_inline, to_bytes)]
+    struct Chat {
+        #[bits = 5]
+        channel: u8,
+        text: String,
+    }
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[gbnet(inline(always))]
+    enum Move {
+        Step { #[bits = 4] dx: u8 },
+        Stop,
+    }
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[gbnet(inline, to_bytes)]
+    struct Shout<'a> {
+        text: &'a str,
+    }
+    
+    // The helpers match what the generic trait methods write
+    let chat = Chat { channel: 3, text: "hi".into() };
+    let bytes = chat.to_bytes()?;
+    let mut buffer = BitBuffer::new();
+    chat.bit_serialize(&mut buffer)?;
+    assert_eq!(bytes, buffer.into_bytes(true)?);
+    assert_eq!(Chat::from_bytes(&bytes)?, chat);
+    assert!(Chat { channel: 32, text: String::new() }.to_bytes().is_err());
+    
+    let mut buffer = BitBuffer::new();
+    Move::Step { dx: 2 }.bit_serialize(&mut buffer)?;
+    assert_eq!(Move::bit_deserialize(&mut BitBuffer::from_bytes(buffer.into_bytes(true)?))?, Move::Step { dx: 2 });
+    
+    let shout = Shout { text: "hey" };
+    let bytes = shout.to_bytes()?;
+    assert_eq!(Shout::from_bytes_borrowed(&bytes)?, shout);
+    Ok(())
+}
+
+#[test]
+fn test_derive_convenience_helpers() -> std::io::Result<()> {
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[gbnet(helpers)]
+    struct Spawn {
+        #[bits = 6]
+        kind: u8,
+        id: u32,
+    }
+    
+    let spawn = Spawn { kind: 9, id: 1234 };
+    let bytes = spawn.serialize_to_vec()?;
+    assert_eq!(spawn.to_bitbuffer()?.into_bytes(true)?, bytes);
+    assert_eq!(Spawn::deserialize_from_slice(&bytes)?, spawn);
+    
+    // Errors name the type; leftovers and short input are both rejected
+    let err = Spawn { kind: 64, id: 0 }.serialize_to_vec().unwrap_err();
+    assert!(err.to_string().starts_with("Spawn: "));
+    let mut padded = bytes.clone();
+    padded.push(0);
+    assert_eq!(Spawn::deserialize_from_slice(&padded).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    assert!(Spawn::deserialize_from_slice(&bytes[..2]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_varint_fields() -> std::io::Result<()> {
+    use crate::serialize::bit_io::{zigzag_decode, zigzag_encode};
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    struct Update {
+        #[varint]
+        entity: u32,
+        #[varint]
+        tick_delta: i16,
+        #[varint]
+        count: usize,
+    }
+    
+    for value in [0, -1, 1, -64, 63, i64::MIN, i64::MAX] {
+        assert_eq!(zigzag_decode(zigzag_encode(value)), value);
+    }
+    assert_eq!(zigzag_encode(-1), 1);
+    
+    // Small values take a byte each, large ones grow instead of being cut off
+    let small = Update { entity: 5, tick_delta: -3, count: 2 };
+    let mut buffer = BitBuffer::new();
+    small.bit_serialize(&mut buffer)?;
+    assert_eq!(BitWrite::bit_pos(&buffer), TAG_BITS + 24);
+    let large = Update { entity: u32::MAX, tick_delta: i16::MIN, count: 300 };
+    let mut buffer = BitBuffer::new();
+    large.bit_serialize(&mut buffer)?;
+    assert_eq!(BitWrite::bit_pos(&buffer), TAG_BITS + 40 + 24 + 16);
+    assert_eq!(Update::bit_deserialize(&mut BitBuffer::from_bytes(buffer.into_bytes(true)?))?, large);
+    
+    // Values past the field's type and encodings past 64 bits are rejected
+    let mut buffer = BitBuffer::new();
+    buffer.write_varint(u32::MAX as u64 + 1)?;
+    buffer.write_varint(0)?;
+    buffer.write_varint(0)?;
+    assert!(Update::bit_deserialize(&mut BitBuffer::from_bytes(buffer.into_bytes(true)?)).is_err());
+    let mut buffer = BitBuffer::from_bytes(vec![0xFF; 11]);
+    assert!(buffer.read_varint().is_err());
+    let mut buffer = BitBuffer::new();
+    buffer.write_varint(u64::MAX)?;
+    assert_eq!(BitBuffer::from_bytes(buffer.into_bytes(true)?).read_varint()?, u64::MAX);
+    Ok(())
+}
+
+#[test]
+fn test_debug_packet_tags() -> std::io::Result<()> {
+    use crate::{debug_packets, schema, serialize::bit_io::BitSlice};
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[schema]
+    struct TaggedMove {
+        #[bits = 10]
+        x: u16,
+        #[bits = 10]
+        y: u16,
+    }
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[schema]
+    struct TaggedChat {
+        #[bits = 10]
+        room: u16,
+        #[bits = 10]
+        len: u16,
+    }
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[gbnet(untagged)]
+    struct Untagged {
+        #[bits = 10]
+        x: u16,
+    }
+    
+    let move_tag = schema::find("TaggedMove").and_then(|schema| schema.tag).unwrap();
+    let chat_tag = schema::find("TaggedChat").and_then(|schema| schema.tag).unwrap();
+    assert_ne!(debug_packets::type_id(move_tag), debug_packets::type_id(chat_tag));
+    
+    // The tag is only on the wire with the feature in a debug build
+    let moved = TaggedMove { x: 300, y: 12 };
+    let mut buffer = BitBuffer::new();
+    moved.bit_serialize(&mut buffer)?;
+    assert_eq!(BitWrite::bit_pos(&buffer), 20 + TAG_BITS);
+    let bytes = buffer.into_bytes(true)?;
+    assert_eq!(TaggedMove::bit_deserialize(&mut BitSlice::new(&bytes))?, moved);
+    
+    let mut buffer = BitBuffer::new();
+    Untagged { x: 5 }.bit_serialize(&mut buffer)?;
+    assert_eq!(BitWrite::bit_pos(&buffer), 10);
+    
+    let wrong = TaggedChat::bit_deserialize(&mut BitSlice::new(&bytes));
+    if debug_packets::ENABLED {
+        let err = wrong.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("TaggedChat: decoded with the wrong type, the sender wrote TaggedMove"));
+        assert_eq!(debug_packets::identify(&bytes).map(|schema| schema.name), Some("TaggedMove"));
+    } else {
+        assert!(wrong.is_ok());
+        assert!(debug_packets::identify(&bytes).is_none());
+    }
+    
+    // Same type id with a different layout hash reports a layout change
+    let mut buffer = BitBuffer::new();
+    debug_packets::write_tag(&mut buffer, move_tag)?;
+    debug_packets::write_tag(&mut buffer, move_tag ^ 1)?;
+    let bytes = buffer.into_bytes(true)?;
+    let mut reader = BitSlice::new(&bytes);
+    debug_packets::check_tag(&mut reader, move_tag, "TaggedMove")?;
+    let err = debug_packets::check_tag(&mut reader, move_tag, "TaggedMove").unwrap_err();
+    assert!(err.to_string().contains("layout hash"));
+    Ok(())
+}
+
+#[test]
+fn test_bit_cursor_mark_and_peek() -> std::io::Result<()> {
+    use crate::dynamic::DynamicMessage;
+    use crate::{debug_packets, schema};
+    use crate::serialize::bit_io::BitSlice;
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[schema]
+    enum PeekCommand {
+        Stop,
+        Go,
+        Wait,
+    }
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[schema]
+    struct PeekRaw {
+        #[bits = 2]
+        kind: u8,
+        #[bits = 6]
+        value: u8,
+    }
+    
+    let bytes = [0b1100_0101, 0xAB];
+    let mut slice = BitSlice::new(&bytes);
+    let mut buffer = BitBuffer::from_bytes(bytes.to_vec());
+    let readers: [&mut dyn BitRead; 2] = [&mut slice, &mut buffer];
+    for reader in readers {
+        assert_eq!(reader.peek_bits(2)?, 0b11);
+        assert!(reader.peek_bit()?);
+        assert_eq!(reader.bit_pos(), 0);
+        reader.read_bits(4)?;
+        let mark = reader.mark();
+        assert_eq!(reader.read_bits(8)?, 0x5A);
+        reader.rewind(mark);
+        assert_eq!(mark.bit_pos(), 4);
+        assert_eq!(reader.read_bits(12)?, 0x5AB);
+        // A failed peek leaves the position alone too
+        assert!(reader.peek_bits(1).is_err());
+        assert_eq!(reader.bit_pos(), 16);
+    }
+    
+    // Failed speculative reads rewind; successful ones consume
+    let mut message = BitBuffer::new();
+    if debug_packets::ENABLED {
+        debug_packets::write_tag(&mut message, schema::find("PeekRaw").and_then(|schema| schema.tag).unwrap())?;
+    }
+    message.write_bits(bytes[0] as u64, 8)?;
+    let message = message.into_bytes(true)?;
+    let mut reader = BitSlice::new(&message);
+    assert!(reader.try_read(PeekCommand::bit_deserialize).is_err());
+    assert_eq!(reader.bit_pos(), 0);
+    let raw = reader.try_read(PeekRaw::bit_deserialize)?;
+    assert_eq!(raw, PeekRaw { kind: 3, value: 5 });
+    assert_eq!(reader.bit_pos(), TAG_BITS + 8);
+    
+    // The dynamic decoder tries candidate schemas in order on the same bits
+    let candidates = [schema::find("PeekCommand").unwrap(), schema::find("PeekRaw").unwrap()];
+    let mut reader = BitSlice::new(&message);
+    let message = DynamicMessage::decode_first(&candidates, &mut reader)?;
+    assert_eq!(message.type_name, "PeekRaw");
+    assert_eq!(reader.bit_pos(), TAG_BITS + 8);
+    Ok(())
+}
+
+#[test]
+fn test_blob_fields() -> std::io::Result<()> {
+    use crate::dynamic::{DynamicMessage, DynamicValue};
+    use crate::serialize::{ByteAlignedDeserialize, ByteAlignedSerialize};
+    use crate::serialize::bit_io::{BitAppender, BitCounter, BitSlice};
+    
+    #[derive(NetworkSerialize, Debug, PartialEq)]
+    #[schema]
+    struct TexturePatch {
+        #[bits = 3]
+        layer: u8,
+        #[blob]
+        pixels: Vec<u8>,
+        #[blob]
+        hash: [u8; 4],
+        #[blob]
+        #[max_len = 8]
+        label: Vec<u8>,
+    }
+    
+    // Far past the 16-bit Vec limit: a 3-byte varint length, padding, then the raw bytes
+    let pixels: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    let patch = TexturePatch { layer: 5, pixels, hash: [1, 2, 3, 4], label: b"rock".to_vec() };
+    let mut buffer = BitBuffer::new();
+    patch.bit_serialize(&mut buffer)?;
+    assert_eq!(BitWrite::bit_pos(&buffer), TAG_BITS + 32 + 300_000 * 8 + 32 + 8 + 32);
+    let mut counter = BitCounter::new();
+    patch.bit_serialize(&mut counter)?;
+    assert_eq!(counter.bits(), BitWrite::bit_pos(&buffer));
+    let bytes = buffer.into_bytes(true)?;
+    assert_eq!(&bytes[TAG_BITS / 8 + 4..TAG_BITS / 8 + 8], &patch.pixels[..4]);
+    assert_eq!(TexturePatch::bit_deserialize(&mut BitBuffer::from_bytes(bytes.clone()))?, patch);
+    assert_eq!(TexturePatch::bit_deserialize(&mut BitSlice::new(&bytes))?, patch);
+    let mut appended = Vec::new();
+    patch.bit_serialize(&mut BitAppender::new(&mut appended))?;
+    assert_eq!(appended, bytes);
+    
+    // Byte-aligned encoding round trips too
+    let mut aligned = Vec::new();
+    patch.byte_aligned_serialize(&mut aligned)?;
+    assert_eq!(TexturePatch::byte_aligned_deserialize(&mut aligned.as_slice())?, patch);
+    
+    // max_len bounds both ends, and truncated data fails instead of reading past the end
+    let long = TexturePatch { label: vec![0; 9], ..patch };
+    assert!(long.bit_serialize(&mut BitBuffer::new()).is_err());
+    let mut buffer = BitBuffer::new();
+    if crate::debug_packets::ENABLED {
+        let tag = crate::schema::find("TexturePatch").and_then(|schema| schema.tag).unwrap();
+        crate::debug_packets::write_tag(&mut buffer, tag)?;
+    }
+    buffer.write_bits(5, 3)?;
+    buffer.write_varint(0)?;
+    buffer.write_aligned_bytes(&[0; 4])?;
+    buffer.write_varint(9)?;
+    buffer.write_aligned_bytes(&[0; 9])?;
+    assert!(TexturePatch::bit_deserialize(&mut BitBuffer::from_bytes(buffer.into_bytes(true)?)).is_err());
+    assert!(TexturePatch::bit_deserialize(&mut BitSlice::new(&bytes[..1000])).is_err());
+    
+    // Schema-driven decoding reads the same layout
+    let small = TexturePatch { layer: 1, pixels: vec![9, 8, 7], hash: [0xAB; 4], label: Vec::new() };
+    let mut buffer = BitBuffer::new();
+    small.bit_serialize(&mut buffer)?;
+    let decoded = DynamicMessage::decode("TexturePatch", &buffer.into_bytes(true)?)?;
+    let bytes = |values: &[u8]| DynamicValue::List(values.iter().map(|&value| DynamicValue::UInt(value as u64)).collect());
+    assert_eq!(decoded.get("pixels"), Some(&bytes(&[9, 8, 7])));
+    assert_eq!(decoded.get("hash"), Some(&bytes(&[0xAB; 4])));
+    assert_eq!(decoded.get("label"), Some(&bytes(&[])));
+    Ok(())
+}
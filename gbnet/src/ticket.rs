@@ -0,0 +1,178 @@
+// ticket.rs - Session resumption tickets
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::{
+    channel::Channel,
+    config::ConnectionRole,
+    crypto::{SessionKeys, KEY_BYTES, RESUME_PROOF_BYTES},
+    rng::DeterministicRng,
+};
+
+pub const TICKET_ID_BYTES: usize = 16;
+pub const RESUME_NONCE_BYTES: usize = 8;
+
+pub type TicketId = [u8; TICKET_ID_BYTES];
+
+/// Client-side credentials for skipping the handshake on reconnect.
+///
+/// Persist the bytes from [`ResumptionTicket::to_bytes`] to survive an app restart.
+/// Tickets are single use; the server hands out a fresh one on every accept. The id alone
+/// redeems nothing: resume requests prove the session keys are held too.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResumptionTicket {
+    pub id: TicketId,
+    pub session_keys: Option<SessionKeys>,
+}
+
+impl ResumptionTicket {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.id.to_vec();
+        if let Some(keys) = &self.session_keys {
+            bytes.extend_from_slice(&keys.send_key);
+            bytes.extend_from_slice(&keys.recv_key);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let id = bytes.get(..TICKET_ID_BYTES)?.try_into().ok()?;
+        let session_keys = match bytes.len() - TICKET_ID_BYTES {
+            0 => None,
+            n if n == KEY_BYTES * 2 => {
+                let keys = &bytes[TICKET_ID_BYTES..];
+                Some(SessionKeys {
+                    send_key: keys[..KEY_BYTES].try_into().ok()?,
+                    recv_key: keys[KEY_BYTES..].try_into().ok()?,
+                })
+            }
+            _ => return None,
+        };
+        Some(Self { id, session_keys })
+    }
+
+    /// Payload of a resume request: the id, a fresh nonce and proof of the session keys.
+    /// Tickets without keys cannot be resumed.
+    pub(crate) fn resume_request(&self, protocol_id: u32) -> Option<Vec<u8>> {
+        let keys = self.session_keys.as_ref()?;
+        let mut nonce = [0u8; RESUME_NONCE_BYTES];
+        OsRng.fill_bytes(&mut nonce);
+        let mut payload = self.id.to_vec();
+        payload.extend_from_slice(&nonce);
+        payload.extend_from_slice(&keys.resume_proof(false, protocol_id, &self.id, &nonce));
+        Some(payload)
+    }
+}
+
+/// Splits a resume request payload into ticket id, nonce and proof.
+pub(crate) fn parse_resume_request(payload: &[u8]) -> Option<(TicketId, &[u8], &[u8])> {
+    if payload.len() != TICKET_ID_BYTES + RESUME_NONCE_BYTES + RESUME_PROOF_BYTES {
+        return None;
+    }
+    let (id, rest) = payload.split_at(TICKET_ID_BYTES);
+    let (nonce, proof) = rest.split_at(RESUME_NONCE_BYTES);
+    Some((id.try_into().ok()?, nonce, proof))
+}
+
+/// State a server keeps for a ticket until it is redeemed or expires.
+#[derive(Debug)]
+pub(crate) struct TicketEntry {
+    pub session_keys: Option<SessionKeys>,
+    pub session_seed: u64,
+    /// Resumed sessions keep the role they were accepted with
+    pub role: ConnectionRole,
+    pub channels: Option<Vec<Channel>>,
+    /// Capabilities and per-channel codecs negotiated at the handshake, reused on resumption
+    pub capabilities: u32,
+    pub codecs: Vec<u8>,
+    /// Set while the owning connection is alive; the ticket only starts expiring once it closes
+    pub expires: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub(crate) struct TicketStore {
+    lifetime: Duration,
+    entries: HashMap<TicketId, TicketEntry>,
+    active: HashMap<SocketAddr, TicketId>,
+    rng: DeterministicRng,
+}
+
+impl TicketStore {
+    pub fn new(lifetime: Duration, rng: DeterministicRng) -> Self {
+        Self {
+            lifetime,
+            entries: HashMap::new(),
+            active: HashMap::new(),
+            rng,
+        }
+    }
+
+    /// Issues a ticket for a live connection.
+    pub fn issue(&mut self, addr: SocketAddr, session_keys: Option<SessionKeys>, session_seed: u64, role: ConnectionRole) -> TicketId {
+        let mut id: TicketId = [0; TICKET_ID_BYTES];
+        for chunk in id.chunks_mut(8) {
+            chunk.copy_from_slice(&self.rng.next_u64().to_le_bytes()[..chunk.len()]);
+        }
+        self.entries.insert(id, TicketEntry {
+            session_keys,
+            session_seed,
+            role,
+            channels: None,
+            capabilities: 0,
+            codecs: Vec::new(),
+            expires: None,
+        });
+        if let Some(previous) = self.active.insert(addr, id) {
+            self.entries.remove(&previous);
+        }
+        id
+    }
+
+    /// Redeems a ticket once `prove` accepts its session keys, consuming it.
+    ///
+    /// Tickets without keys, expired ones and those of connections still alive are refused
+    /// and left as they are; a live session is never handed to someone else.
+    pub fn redeem<F>(&mut self, id: &TicketId, now: Instant, prove: F) -> Option<TicketEntry>
+    where
+        F: FnOnce(&SessionKeys) -> bool,
+    {
+        let entry = self.entries.get(id)?;
+        if entry.expires.is_none_or(|expires| now > expires) || !entry.session_keys.as_ref().is_some_and(prove) {
+            return None;
+        }
+        self.entries.remove(id)
+    }
+
+    /// Records what the connection holding `addr`'s ticket negotiated at its handshake.
+    pub fn set_negotiated(&mut self, addr: SocketAddr, capabilities: u32, codecs: Vec<u8>) {
+        if let Some(entry) = self.active.get(&addr).and_then(|id| self.entries.get_mut(id)) {
+            entry.capabilities = capabilities;
+            entry.codecs = codecs;
+        }
+    }
+
+    /// Starts the expiry clock for the ticket of a closed connection, keeping its channels.
+    pub fn park(&mut self, addr: SocketAddr, channels: Vec<Channel>, now: Instant) {
+        if let Some(id) = self.active.remove(&addr) {
+            if let Some(entry) = self.entries.get_mut(&id) {
+                entry.channels = Some(channels);
+                entry.expires = Some(now + self.lifetime);
+            }
+        }
+    }
+
+    /// Invalidates the ticket of a connection that must not be resumed.
+    pub fn revoke(&mut self, addr: SocketAddr) {
+        if let Some(id) = self.active.remove(&addr) {
+            self.entries.remove(&id);
+        }
+    }
+
+    pub fn remove_expired(&mut self, now: Instant) {
+        self.entries.retain(|_, entry| entry.expires.is_none_or(|expires| now <= expires));
+    }
+}
@@ -0,0 +1,223 @@
+// timestamp.rs - Compact wall clock timestamps relative to a per-connection epoch
+//
+// The server picks an epoch (its clock at accept time plus a resolution) and sends it with
+// the connection accept. Timestamps are then written as a variable-length tick count from
+// that epoch, so times near the session start take a few bits instead of a full u64.
+//
+// Serialization traits carry no context, so the epoch is supplied per thread with
+// `TimestampEpoch::scope` around the (de)serialize calls.
+use std::cell::Cell;
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "timestamps")]
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "timestamps")]
+use std::io::{Read, Write};
+#[cfg(feature = "timestamps")]
+use crate::serialize::{BitSerialize, BitDeserialize, ByteAlignedSerialize, ByteAlignedDeserialize, bit_io};
+
+/// Size of the epoch in the accept payload: origin in Unix microseconds, then the resolution.
+pub const EPOCH_BYTES: usize = 9;
+
+// Bit length of a tick count, 0 to 64
+#[cfg(feature = "timestamps")]
+const TICK_LEN_BITS: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampResolution {
+    Millis,
+    Micros,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampEpoch {
+    origin: SystemTime,
+    resolution: TimestampResolution,
+}
+
+thread_local! {
+    static CURRENT_EPOCH: Cell<Option<TimestampEpoch>> = const { Cell::new(None) };
+}
+
+impl TimestampEpoch {
+    /// `origin` is truncated to whole microseconds so both ends agree on it exactly.
+    pub fn new(origin: SystemTime, resolution: TimestampResolution) -> Self {
+        let micros = origin.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        Self {
+            origin: UNIX_EPOCH + Duration::from_micros(micros),
+            resolution,
+        }
+    }
+
+    pub fn origin(&self) -> SystemTime {
+        self.origin
+    }
+
+    pub fn resolution(&self) -> TimestampResolution {
+        self.resolution
+    }
+
+    /// Runs `f` with this epoch used by every timestamp (de)serialized on the current thread.
+    pub fn scope<T>(self, f: impl FnOnce() -> T) -> T {
+        struct Restore(Option<TimestampEpoch>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                CURRENT_EPOCH.with(|current| current.set(self.0));
+            }
+        }
+
+        let _restore = Restore(CURRENT_EPOCH.with(|current| current.replace(Some(self))));
+        f()
+    }
+
+    /// The epoch set by the innermost enclosing [`TimestampEpoch::scope`].
+    pub fn current() -> Option<Self> {
+        CURRENT_EPOCH.with(Cell::get)
+    }
+
+    /// Signed ticks from the origin; anything finer than the resolution is truncated.
+    pub fn encode(&self, time: SystemTime) -> io::Result<i64> {
+        let (offset, negative) = match time.duration_since(self.origin) {
+            Ok(offset) => (offset, false),
+            Err(e) => (e.duration(), true),
+        };
+        let ticks = match self.resolution {
+            TimestampResolution::Millis => offset.as_millis(),
+            TimestampResolution::Micros => offset.as_micros(),
+        };
+        let ticks = i64::try_from(ticks).map_err(|_| out_of_range())?;
+        Ok(if negative { -ticks } else { ticks })
+    }
+
+    pub fn decode(&self, ticks: i64) -> io::Result<SystemTime> {
+        let offset = match self.resolution {
+            TimestampResolution::Millis => Duration::from_millis(ticks.unsigned_abs()),
+            TimestampResolution::Micros => Duration::from_micros(ticks.unsigned_abs()),
+        };
+        let time = if ticks < 0 {
+            self.origin.checked_sub(offset)
+        } else {
+            self.origin.checked_add(offset)
+        };
+        time.ok_or_else(out_of_range)
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; EPOCH_BYTES] {
+        let micros = self.origin.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        let mut bytes = [0u8; EPOCH_BYTES];
+        bytes[..8].copy_from_slice(&micros.to_le_bytes());
+        bytes[8] = match self.resolution {
+            TimestampResolution::Millis => 0,
+            TimestampResolution::Micros => 1,
+        };
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let micros = u64::from_le_bytes(bytes.get(..8)?.try_into().ok()?);
+        let resolution = match bytes.get(8)? {
+            0 => TimestampResolution::Millis,
+            1 => TimestampResolution::Micros,
+            _ => return None,
+        };
+        Some(Self::new(UNIX_EPOCH + Duration::from_micros(micros), resolution))
+    }
+}
+
+fn out_of_range() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Timestamp out of range for its epoch")
+}
+
+#[cfg(feature = "timestamps")]
+fn current_epoch() -> io::Result<TimestampEpoch> {
+    TimestampEpoch::current().ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Timestamps need an epoch; wrap serialization in TimestampEpoch::scope",
+    ))
+}
+
+// Sign bit, bit length, then only the significant bits of the magnitude
+#[cfg(feature = "timestamps")]
+fn write_ticks<W: bit_io::BitWrite>(writer: &mut W, ticks: i64) -> io::Result<()> {
+    let magnitude = ticks.unsigned_abs();
+    let bits = (u64::BITS - magnitude.leading_zeros()) as usize;
+    writer.write_bit(ticks < 0)?;
+    writer.write_bits(bits as u64, TICK_LEN_BITS)?;
+    if bits > 0 {
+        writer.write_bits(magnitude, bits)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "timestamps")]
+fn read_ticks<R: bit_io::BitRead>(reader: &mut R) -> io::Result<i64> {
+    let negative = reader.read_bit()?;
+    let bits = reader.read_bits(TICK_LEN_BITS)? as usize;
+    if bits > 64 {
+        return Err(out_of_range());
+    }
+    let magnitude = if bits > 0 { reader.read_bits(bits)? } else { 0 };
+    let ticks = if negative { -(magnitude as i128) } else { magnitude as i128 };
+    i64::try_from(ticks).map_err(|_| out_of_range())
+}
+
+#[cfg(feature = "timestamps")]
+impl BitSerialize for SystemTime {
+    fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+        write_ticks(writer, current_epoch()?.encode(*self)?)
+    }
+}
+
+#[cfg(feature = "timestamps")]
+impl BitDeserialize for SystemTime {
+    fn bit_deserialize<R: bit_io::BitRead>(reader: &mut R) -> io::Result<Self> {
+        let ticks = read_ticks(reader)?;
+        current_epoch()?.decode(ticks)
+    }
+}
+
+#[cfg(feature = "timestamps")]
+impl ByteAlignedSerialize for SystemTime {
+    fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_i64::<LittleEndian>(current_epoch()?.encode(*self)?)
+    }
+}
+
+#[cfg(feature = "timestamps")]
+impl ByteAlignedDeserialize for SystemTime {
+    fn byte_aligned_deserialize<R: Read + ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        let ticks = reader.read_i64::<LittleEndian>()?;
+        current_epoch()?.decode(ticks)
+    }
+}
+
+#[cfg(feature = "chrono")]
+mod chrono_impls {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    impl BitSerialize for DateTime<Utc> {
+        fn bit_serialize<W: bit_io::BitWrite>(&self, writer: &mut W) -> io::Result<()> {
+            SystemTime::from(*self).bit_serialize(writer)
+        }
+    }
+
+    impl BitDeserialize for DateTime<Utc> {
+        fn bit_deserialize<R: bit_io::BitRead>(reader: &mut R) -> io::Result<Self> {
+            SystemTime::bit_deserialize(reader).map(DateTime::from)
+        }
+    }
+
+    impl ByteAlignedSerialize for DateTime<Utc> {
+        fn byte_aligned_serialize<W: Write + WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+            SystemTime::from(*self).byte_aligned_serialize(writer)
+        }
+    }
+
+    impl ByteAlignedDeserialize for DateTime<Utc> {
+        fn byte_aligned_deserialize<R: Read + ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+            SystemTime::byte_aligned_deserialize(reader).map(DateTime::from)
+        }
+    }
+}
@@ -0,0 +1,145 @@
+// token.rs - Signed, time-limited connect tokens issued by a backend
+//
+// A matchmaker or login service that shares a key with its game servers mints a token for
+// each client it lets in; the client hands it over with its connection request. A server
+// configured with `NetworkConfig::connect_token_key` ignores requests without a valid one,
+// never answering them, so spoofed requests cannot be bounced off it at a victim and
+// clients that skipped the backend never get a challenge. A token only works from the
+// first address that presents it, until it expires.
+//
+// Tokens are signed, not encrypted: the client can read its own id and user data. Signing
+// is HMAC-SHA256 over the protocol id and the token body, truncated to 16 bytes.
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::crypto::KEY_BYTES;
+
+/// Most application bytes a token carries, e.g. a session ticket or an account region.
+pub const MAX_USER_DATA_BYTES: usize = 128;
+const TAG_BYTES: usize = 16;
+const FIXED_BYTES: usize = 8 + 8 + 1;
+
+/// Key shared by the backend minting tokens and the servers checking them.
+pub type ConnectTokenKey = [u8; KEY_BYTES];
+
+/// Who a backend let in, until when, and what it wants the server to know about them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectToken {
+    /// Account or player id assigned by the backend
+    pub client_id: u64,
+    /// Unix time in seconds after which the token is refused
+    pub expires_at: u64,
+    pub user_data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    /// Not a token, or user data past `MAX_USER_DATA_BYTES`
+    Malformed,
+    /// Signed with another key or for another protocol id, or altered since
+    BadSignature,
+    Expired,
+    /// Already presented from another address
+    InUse,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Malformed => write!(f, "malformed connect token"),
+            TokenError::BadSignature => write!(f, "connect token signature does not match"),
+            TokenError::Expired => write!(f, "connect token expired"),
+            TokenError::InUse => write!(f, "connect token in use from another address"),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+impl ConnectToken {
+    /// A token for `client_id` valid for `lifetime` from now.
+    pub fn new(client_id: u64, lifetime: Duration, user_data: Vec<u8>) -> Self {
+        Self {
+            client_id,
+            expires_at: unix_secs(crate::instant::wall_clock()).saturating_add(lifetime.as_secs()),
+            user_data,
+        }
+    }
+
+    /// Signs the token for servers of `protocol_id`; hand the bytes to the client.
+    pub fn generate(&self, key: &ConnectTokenKey, protocol_id: u32) -> Result<Vec<u8>, TokenError> {
+        if self.user_data.len() > MAX_USER_DATA_BYTES {
+            return Err(TokenError::Malformed);
+        }
+        let mut bytes = Vec::with_capacity(FIXED_BYTES + self.user_data.len() + TAG_BYTES);
+        bytes.extend_from_slice(&self.client_id.to_le_bytes());
+        bytes.extend_from_slice(&self.expires_at.to_le_bytes());
+        bytes.push(self.user_data.len() as u8);
+        bytes.extend_from_slice(&self.user_data);
+        let tag = authenticator(key, protocol_id, &bytes).finalize().into_bytes();
+        bytes.extend_from_slice(&tag[..TAG_BYTES]);
+        Ok(bytes)
+    }
+
+    /// Checks the signature and expiry of token bytes and reads them back.
+    pub fn verify(bytes: &[u8], key: &ConnectTokenKey, protocol_id: u32, now: SystemTime) -> Result<Self, TokenError> {
+        if bytes.len() < FIXED_BYTES + TAG_BYTES {
+            return Err(TokenError::Malformed);
+        }
+        let (body, tag) = bytes.split_at(bytes.len() - TAG_BYTES);
+        let user_data_len = body[FIXED_BYTES - 1] as usize;
+        if user_data_len > MAX_USER_DATA_BYTES || body.len() != FIXED_BYTES + user_data_len {
+            return Err(TokenError::Malformed);
+        }
+        authenticator(key, protocol_id, body).verify_truncated_left(tag).map_err(|_| TokenError::BadSignature)?;
+
+        let token = Self {
+            client_id: u64::from_le_bytes(body[..8].try_into().expect("8 bytes")),
+            expires_at: u64::from_le_bytes(body[8..16].try_into().expect("8 bytes")),
+            user_data: body[FIXED_BYTES..].to_vec(),
+        };
+        if unix_secs(now) > token.expires_at {
+            return Err(TokenError::Expired);
+        }
+        Ok(token)
+    }
+}
+
+fn authenticator(key: &ConnectTokenKey, protocol_id: u32, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(&protocol_id.to_le_bytes());
+    mac.update(body);
+    mac
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+/// Addresses tokens were first presented from, until the tokens expire.
+#[derive(Debug, Default)]
+pub(crate) struct TokenRegistry {
+    claimed: HashMap<[u8; TAG_BYTES], (SocketAddr, u64)>,
+}
+
+impl TokenRegistry {
+    /// Verifies token bytes and binds them to `addr`; the same address may present them again.
+    pub fn claim(&mut self, bytes: &[u8], key: &ConnectTokenKey, protocol_id: u32, addr: SocketAddr) -> Result<ConnectToken, TokenError> {
+        let now = crate::instant::wall_clock();
+        let token = ConnectToken::verify(bytes, key, protocol_id, now)?;
+
+        let now_secs = unix_secs(now);
+        self.claimed.retain(|_, (_, expires_at)| *expires_at >= now_secs);
+        let tag = bytes[bytes.len() - TAG_BYTES..].try_into().expect("verified tokens end in a tag");
+        let (owner, _) = *self.claimed.entry(tag).or_insert((addr, token.expires_at));
+        if owner != addr {
+            return Err(TokenError::InUse);
+        }
+        Ok(token)
+    }
+}
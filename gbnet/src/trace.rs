@@ -0,0 +1,157 @@
+// trace.rs - Packet event traces exported as Chrome trace / Perfetto JSON
+//
+// Unlike telemetry, a trace keeps every send, resend, receive and ack of every connection
+// sharing the recorder, so it is meant for debugging sessions rather than production. Open
+// the export in chrome://tracing or ui.perfetto.dev: each local socket is a process and each
+// peer a track inside it, so a packet's trip shows up as a send on one track and a receive
+// (then an ack back) on the other.
+#[cfg(feature = "json")]
+use std::collections::HashMap;
+use std::collections::VecDeque;
+#[cfg(feature = "json")]
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+#[cfg(feature = "json")]
+use serde_json::{json, Value};
+
+use crate::packet::PacketType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEventKind {
+    Send,
+    /// A reliable packet sent again under its original sequence
+    Resend,
+    Receive,
+    /// A sent packet was acknowledged by the peer
+    Ack,
+}
+
+impl TraceEventKind {
+    /// Event name in the Chrome trace export.
+    pub fn name(self) -> &'static str {
+        match self {
+            TraceEventKind::Send => "send",
+            TraceEventKind::Resend => "resend",
+            TraceEventKind::Receive => "receive",
+            TraceEventKind::Ack => "ack",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceEvent {
+    pub kind: TraceEventKind,
+    pub local_addr: SocketAddr,
+    pub remote_addr: SocketAddr,
+    pub sequence: u16,
+    /// Type of the packet sent or received; `None` for acks
+    pub packet_type: Option<PacketType>,
+    /// Bytes on the wire; 0 for acks
+    pub size: usize,
+    pub at: Instant,
+}
+
+#[derive(Debug)]
+struct TraceLog {
+    #[cfg(feature = "json")]
+    origin: Instant,
+    max_events: usize,
+    events: VecDeque<TraceEvent>,
+}
+
+/// Collects packet events from every connection configured with it.
+///
+/// Cloned configs share the same log, like [`crate::TelemetrySampler`].
+#[derive(Debug, Clone)]
+pub struct TraceRecorder {
+    log: Arc<Mutex<TraceLog>>,
+}
+
+impl TraceRecorder {
+    /// Keeps the newest `max_events` events.
+    pub fn new(max_events: usize) -> Self {
+        Self {
+            log: Arc::new(Mutex::new(TraceLog {
+                #[cfg(feature = "json")]
+                origin: Instant::now(),
+                max_events,
+                events: VecDeque::new(),
+            })),
+        }
+    }
+
+    pub(crate) fn record(&self, event: TraceEvent) {
+        let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        if log.max_events == 0 {
+            return;
+        }
+        if log.events.len() >= log.max_events {
+            log.events.pop_front();
+        }
+        log.events.push_back(event);
+    }
+
+    /// Recorded events, oldest first.
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.log.lock().unwrap_or_else(|e| e.into_inner()).events.iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.log.lock().unwrap_or_else(|e| e.into_inner()).events.clear();
+    }
+
+    /// The trace in Chrome's JSON trace event format, with timestamps in microseconds
+    /// since the recorder was created.
+    #[cfg(feature = "json")]
+    pub fn to_chrome_json(&self) -> Value {
+        let log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        let mut processes: HashMap<SocketAddr, usize> = HashMap::new();
+        let mut tracks: HashMap<(SocketAddr, SocketAddr), usize> = HashMap::new();
+        let mut trace_events = Vec::new();
+
+        for event in &log.events {
+            let next_pid = processes.len() + 1;
+            let pid = *processes.entry(event.local_addr).or_insert_with(|| {
+                trace_events.push(json!({
+                    "name": "process_name", "ph": "M", "pid": next_pid,
+                    "args": { "name": event.local_addr.to_string() },
+                }));
+                next_pid
+            });
+            let next_tid = tracks.len() + 1;
+            let tid = *tracks.entry((event.local_addr, event.remote_addr)).or_insert_with(|| {
+                trace_events.push(json!({
+                    "name": "thread_name", "ph": "M", "pid": pid, "tid": next_tid,
+                    "args": { "name": event.remote_addr.to_string() },
+                }));
+                next_tid
+            });
+
+            let mut args = json!({ "sequence": event.sequence, "size": event.size });
+            if let Some(packet_type) = &event.packet_type {
+                args["packet_type"] = json!(format!("{:?}", packet_type));
+            }
+            trace_events.push(json!({
+                "name": event.kind.name(),
+                "cat": "packet",
+                "ph": "i",
+                "s": "t",
+                "ts": event.at.saturating_duration_since(log.origin).as_secs_f64() * 1_000_000.0,
+                "pid": pid,
+                "tid": tid,
+                "args": args,
+            }));
+        }
+
+        json!({ "traceEvents": trace_events, "displayTimeUnit": "ms" })
+    }
+
+    /// Writes [`TraceRecorder::to_chrome_json`] to `writer`, e.g. a `.json` file.
+    #[cfg(feature = "json")]
+    pub fn write_chrome_json<W: io::Write>(&self, writer: W) -> io::Result<()> {
+        serde_json::to_writer(writer, &self.to_chrome_json()).map_err(io::Error::other)
+    }
+}
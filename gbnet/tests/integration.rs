@@ -1,6 +1,7 @@
 use gbnet::{
     UdpSocket, Packet, PacketHeader, PacketType,
     Connection, NetworkConfig,
+    Server, ServerEvent,
     BitSerialize, BitDeserialize,
     BitBuffer,
 };
@@ -146,4 +147,119 @@ fn test_multi_channel_messages() -> std::io::Result<()> {
     assert_eq!(unreliable_channel.receive().unwrap(), b"position update");
     
     Ok(())
-}
\ No newline at end of file
+}
+
+fn pump(server: &mut Server, client: &mut Connection, socket: &mut UdpSocket, rounds: usize) {
+    for _ in 0..rounds {
+        let _ = client.update(socket);
+        thread::sleep(Duration::from_millis(5));
+        server.update().unwrap();
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn test_server_ban_and_packet_filter() {
+    let config = NetworkConfig::default();
+    let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    
+    let mut server = Server::bind(localhost, config.clone()).unwrap();
+    let mut socket = UdpSocket::bind(localhost).unwrap();
+    let client_addr = socket.local_addr().unwrap();
+    
+    // Drop everything from the client before parsing
+    server.set_packet_filter(move |addr: SocketAddr, _data: &[u8]| addr != client_addr);
+    let mut client = Connection::new(config.clone(), client_addr, server.local_addr());
+    client.connect().unwrap();
+    pump(&mut server, &mut client, &mut socket, 3);
+    assert!(!client.is_connected());
+    assert_eq!(server.num_clients(), 0);
+    assert_eq!(server.stats().packets_filtered, server.stats().packets_received);
+    assert!(server.stats().packets_filtered > 0);
+    
+    // Banned addresses are dropped the same way
+    server.clear_packet_filter();
+    server.ban(client_addr.ip()).unwrap();
+    assert!(server.address_filter().is_denied(client_addr.ip()));
+    
+    let mut client = Connection::new(config, client_addr, server.local_addr());
+    client.connect().unwrap();
+    pump(&mut server, &mut client, &mut socket, 3);
+    assert!(!client.is_connected());
+    assert_eq!(server.num_clients(), 0);
+    assert_eq!(server.poll_event(), None);
+    assert_eq!(server.stats().packets_filtered, server.stats().packets_received);
+}
+
+#[test]
+fn test_server_handshake() {
+    let config = NetworkConfig::default();
+    let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    
+    let mut server = Server::bind(localhost, config.clone()).unwrap();
+    let mut socket = UdpSocket::bind(localhost).unwrap();
+    let client_addr = socket.local_addr().unwrap();
+    
+    // Challenge packets carry full-width u64 salts
+    let mut client = Connection::new(config, client_addr, server.local_addr());
+    client.connect().unwrap();
+    pump(&mut server, &mut client, &mut socket, 10);
+    
+    assert!(client.is_connected());
+    assert_eq!(server.num_clients(), 1);
+    assert_eq!(server.poll_event(), Some(ServerEvent::ClientConnected(client_addr)));
+    
+    // Both ends derived mirrored session keys
+    let client_keys = client.session_keys().unwrap();
+    let server_keys = server.connection(client_addr).and_then(|c| c.session_keys()).unwrap();
+    assert_eq!(client_keys.send_key, server_keys.recv_key);
+    assert_eq!(client_keys.recv_key, server_keys.send_key);
+}
+
+#[test]
+fn test_explicit_flush_coalesces_messages() {
+    let config = NetworkConfig { auto_flush: false, ..NetworkConfig::default() };
+    let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    
+    let mut server = Server::bind(localhost, config.clone()).unwrap();
+    let mut socket = UdpSocket::bind(localhost).unwrap();
+    let client_addr = socket.local_addr().unwrap();
+    
+    let mut client = Connection::new(config, client_addr, server.local_addr());
+    client.connect().unwrap();
+    for _ in 0..10 {
+        client.update(&mut socket).unwrap();
+        client.flush(&mut socket).unwrap();
+        thread::sleep(Duration::from_millis(5));
+        server.update().unwrap();
+        server.flush_all().unwrap();
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert!(client.is_connected());
+    
+    // Without a flush nothing leaves the client
+    let received = server.stats().packets_received;
+    for i in 0..3u8 {
+        client.send(0, &[i; 100], true).unwrap();
+    }
+    client.update(&mut socket).unwrap();
+    thread::sleep(Duration::from_millis(5));
+    server.update().unwrap();
+    assert_eq!(server.stats().packets_received, received);
+    assert_eq!(server.receive(client_addr, 0), None);
+    
+    // One flush sends all three messages in a single packet
+    client.flush(&mut socket).unwrap();
+    thread::sleep(Duration::from_millis(5));
+    server.update().unwrap();
+    assert_eq!(server.stats().packets_received, received + 1);
+    for i in 0..3u8 {
+        assert_eq!(server.receive(client_addr, 0), Some(vec![i; 100]));
+    }
+    
+    // Messages larger than a packet go as fragments; ones needing more than max_fragments are rejected up front
+    assert!(client.send(0, &vec![0; client.max_message_size() + 1], true).is_ok());
+    let defaults = NetworkConfig::default();
+    let too_large = vec![0; defaults.max_fragments * defaults.fragment_threshold + 1];
+    assert!(client.send(0, &too_large, true).is_err());
+}
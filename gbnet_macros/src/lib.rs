@@ -1,5 +1,5 @@
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{quote, ToTokens};
 use syn::{parse_macro_input, DeriveInput, Data, Fields, Index, GenericParam, Generics, Field, Type};
 
 fn add_trait_bounds(mut generics: Generics, bound: proc_macro2::TokenStream) -> Generics {
@@ -142,15 +142,312 @@ fn get_default_bits(input: &DeriveInput) -> Vec<(String, usize)> {
         .collect()
 }
 
+fn is_float_type(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path.path.is_ident("f32") || type_path.path.is_ident("f64"),
+        _ => false,
+    }
+}
+
+// #[quantize(min = -10.0, max = 10.0)]
+fn get_quantize(field: &Field) -> Option<(f64, f64)> {
+    let attr = field.attrs.iter().find(|attr| attr.path().is_ident("quantize"))?;
+    parse_quantize(attr).ok()
+}
+
+fn parse_quantize(attr: &syn::Attribute) -> syn::Result<(f64, f64)> {
+    let mut min = None;
+    let mut max = None;
+    attr.parse_nested_meta(|meta| {
+        let value = parse_f64_expr(&meta.value()?.parse::<syn::Expr>()?)?;
+        if meta.path.is_ident("min") {
+            min = Some(value);
+        } else if meta.path.is_ident("max") {
+            max = Some(value);
+        } else {
+            return Err(meta.error("expected `min` or `max`"));
+        }
+        Ok(())
+    })?;
+    match (min, max) {
+        (Some(min), Some(max)) if min < max => Ok((min, max)),
+        (Some(_), Some(_)) => Err(syn::Error::new_spanned(attr, "quantize requires min < max")),
+        _ => Err(syn::Error::new_spanned(attr, "quantize requires both `min` and `max`")),
+    }
+}
+
+// #[gbnet(inline)], #[gbnet(inline(always))], #[gbnet(no_inline)], #[gbnet(to_bytes)] and
+// #[gbnet(helpers)]
+#[derive(Default)]
+struct GbnetOptions {
+    inline: proc_macro2::TokenStream,
+    to_bytes: bool,
+    helpers: bool,
+    untagged: bool,
+}
+
+fn parse_gbnet_options(input: &DeriveInput) -> syn::Result<GbnetOptions> {
+    let mut options = GbnetOptions::default();
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("gbnet")) {
+        attr.parse_nested_meta(|meta| {
+            let inline = if meta.path.is_ident("inline") {
+                if meta.input.peek(syn::token::Paren) {
+                    let content;
+                    syn::parenthesized!(content in meta.input);
+                    let mode: syn::Ident = content.parse()?;
+                    if mode != "always" {
+                        return Err(syn::Error::new_spanned(mode, "expected `inline(always)`"));
+                    }
+                    quote! { #[inline(always)] }
+                } else {
+                    quote! { #[inline] }
+                }
+            } else if meta.path.is_ident("no_inline") {
+                quote! { #[inline(never)] }
+            } else if meta.path.is_ident("to_bytes") {
+                options.to_bytes = true;
+                return Ok(());
+            } else if meta.path.is_ident("helpers") {
+                options.helpers = true;
+                return Ok(());
+            } else if meta.path.is_ident("untagged") {
+                options.untagged = true;
+                return Ok(());
+            } else {
+                return Err(meta.error("expected `inline`, `inline(always)`, `no_inline`, `to_bytes`, `helpers` or `untagged`"));
+            };
+            if !options.inline.is_empty() {
+                return Err(meta.error("only one of `inline`, `inline(always)` and `no_inline` may be given"));
+            }
+            options.inline = inline;
+            Ok(())
+        })?;
+    }
+    Ok(options)
+}
+
+// Attribute for every generated method; invalid options were reported by validate_input
+fn inline_attr(input: &DeriveInput) -> proc_macro2::TokenStream {
+    parse_gbnet_options(input).map(|options| options.inline).unwrap_or_default()
+}
+
+// FNV-1a folded to 16 bits
+fn hash16(text: &str) -> u16 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in text.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    ((hash >> 16) ^ (hash & 0xffff)) as u16
+}
+
+// debug-packets tag: the type name hashed into the high 16 bits and the field layout
+// (names, types and attributes other than doc comments, in order) into the low 16.
+// None for #[gbnet(untagged)] types
+fn debug_tag(input: &DeriveInput) -> Option<u32> {
+    if parse_gbnet_options(input).map(|options| options.untagged).unwrap_or(false) {
+        return None;
+    }
+    let describe_fields = |fields: &Fields, layout: &mut String| {
+        for field in fields.iter() {
+            if let Some(ident) = &field.ident {
+                layout.push_str(&ident.to_string());
+            }
+            layout.push(':');
+            layout.push_str(&field.ty.to_token_stream().to_string());
+            for attr in field.attrs.iter().filter(|attr| !attr.path().is_ident("doc")) {
+                layout.push_str(&attr.to_token_stream().to_string());
+            }
+            layout.push(';');
+        }
+    };
+    let mut layout = String::new();
+    for attr in input.attrs.iter().filter(|attr| ["bits", "default_bits", "default_max_len"].iter().any(|name| attr.path().is_ident(name))) {
+        layout.push_str(&attr.to_token_stream().to_string());
+    }
+    match &input.data {
+        Data::Struct(data) => describe_fields(&data.fields, &mut layout),
+        Data::Enum(data) => {
+            for variant in &data.variants {
+                layout.push_str(&variant.ident.to_string());
+                layout.push('{');
+                describe_fields(&variant.fields, &mut layout);
+                layout.push('}');
+            }
+        }
+        Data::Union(_) => {}
+    }
+    Some(((hash16(&input.ident.to_string()) as u32) << 16) | hash16(&layout) as u32)
+}
+
+fn parse_f64_expr(expr: &syn::Expr) -> syn::Result<f64> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Float(lit), .. }) => lit.base10_parse::<f64>(),
+        syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(lit), .. }) => lit.base10_parse::<f64>(),
+        syn::Expr::Unary(syn::ExprUnary { op: syn::UnOp::Neg(_), expr, .. }) => Ok(-parse_f64_expr(expr)?),
+        _ => Err(syn::Error::new_spanned(expr, "expected a numeric literal")),
+    }
+}
+
+// Maps [min, max] linearly onto 0..=2^bits-1, clamping out-of-range values
+fn quantized_serialize(value: proc_macro2::TokenStream, bits: usize, min: f64, max: f64) -> proc_macro2::TokenStream {
+    quote! {
+        {
+            let steps = ((1u64 << #bits) - 1) as f64;
+            let clamped = (#value as f64).clamp(#min, #max);
+            let quantized = ((clamped - #min) / (#max - #min) * steps).round() as u64;
+            writer.write_bits(quantized, #bits)?;
+        }
+    }
+}
+
+fn quantized_deserialize(name: proc_macro2::TokenStream, bits: usize, min: f64, max: f64) -> proc_macro2::TokenStream {
+    quote! {
+        let #name = {
+            let steps = ((1u64 << #bits) - 1) as f64;
+            let quantized = reader.read_bits(#bits)? as f64;
+            (#min + quantized / steps * (#max - #min)) as _
+        };
+    }
+}
+
+fn is_varint(field: &Field) -> bool {
+    field.attrs.iter().any(|attr| attr.path().is_ident("varint"))
+}
+
+fn is_signed_integer(type_name: Option<&str>) -> bool {
+    matches!(type_name, Some("i8" | "i16" | "i32" | "i64" | "isize"))
+}
+
+// #[varint]: 7 value bits per group instead of a fixed width, zigzag for signed types
+fn varint_serialize(value: proc_macro2::TokenStream, field: &Field) -> proc_macro2::TokenStream {
+    if is_signed_integer(field_type_name(&field.ty).as_deref()) {
+        quote! { writer.write_signed_varint(#value as i64)?; }
+    } else {
+        quote! { writer.write_varint(#value as u64)?; }
+    }
+}
+
+fn varint_deserialize(name: proc_macro2::TokenStream, field: &Field) -> proc_macro2::TokenStream {
+    let ty = &field.ty;
+    let (read, wide) = if is_signed_integer(field_type_name(ty).as_deref()) {
+        (quote! { reader.read_signed_varint()? }, quote! { i64 })
+    } else {
+        (quote! { reader.read_varint()? }, quote! { u64 })
+    };
+    quote! {
+        let #name = <#ty as ::std::convert::TryFrom<#wide>>::try_from(#read).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, concat!("Varint out of range for ", stringify!(#ty)))
+        })?;
+    }
+}
+
+fn is_blob(field: &Field) -> bool {
+    field.attrs.iter().any(|attr| attr.path().is_ident("blob"))
+}
+
+fn is_u8(ty: &Type) -> bool {
+    matches!(ty, Type::Path(type_path) if type_path.path.is_ident("u8"))
+}
+
+// Vec<u8> as written, the only Vec a #[blob] accepts
+fn is_byte_vec(ty: &Type) -> bool {
+    let Type::Path(type_path) = ty else {
+        return false;
+    };
+    let Some(segment) = type_path.path.segments.last().filter(|segment| segment.ident == "Vec") else {
+        return false;
+    };
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => {
+            matches!(args.args.first(), Some(syn::GenericArgument::Type(ty)) if is_u8(ty)) && args.args.len() == 1
+        }
+        _ => false,
+    }
+}
+
+fn is_byte_array(ty: &Type) -> bool {
+    matches!(ty, Type::Array(array) if is_u8(&array.elem))
+}
+
+// #[blob]: a Vec<u8> goes as a varint length and then its bytes copied whole from the next
+// byte boundary; a [u8; N] needs no length. Byte-aligned encoding keeps the usual layout
+fn blob_serialize(value: proc_macro2::TokenStream, field: &Field, max_len: Option<usize>, is_bit: bool) -> proc_macro2::TokenStream {
+    let max_len = max_len.map_or(quote! { ::gbnet::serialize::DEFAULT_MAX_BLOB_LEN }, |max_len| quote! { #max_len });
+    match (is_byte_array(&field.ty), is_bit) {
+        (true, true) => quote! { writer.write_aligned_bytes(&#value)?; },
+        (true, false) => quote! { ::std::io::Write::write_all(writer, &#value)?; },
+        (false, true) => quote! { ::gbnet::serialize::write_blob(writer, &#value, #max_len)?; },
+        (false, false) => quote! { ::gbnet::serialize::byte_aligned_write_blob(writer, &#value, #max_len)?; },
+    }
+}
+
+fn blob_deserialize(name: proc_macro2::TokenStream, field: &Field, max_len: Option<usize>, is_bit: bool) -> proc_macro2::TokenStream {
+    let max_len = max_len.map_or(quote! { ::gbnet::serialize::DEFAULT_MAX_BLOB_LEN }, |max_len| quote! { #max_len });
+    match &field.ty {
+        Type::Array(array) if is_bit => {
+            let len = &array.len;
+            quote! {
+                let mut #name = [0u8; #len];
+                reader.read_aligned_into(&mut #name)?;
+            }
+        }
+        Type::Array(array) => {
+            let len = &array.len;
+            quote! {
+                let mut #name = [0u8; #len];
+                ::std::io::Read::read_exact(reader, &mut #name)?;
+            }
+        }
+        _ if is_bit => quote! { let #name = ::gbnet::serialize::read_blob(reader, #max_len)?; },
+        _ => quote! { let #name = ::gbnet::serialize::byte_aligned_read_blob(reader, #max_len)?; },
+    }
+}
+
+fn is_delta_string(field: &Field) -> bool {
+    field.attrs.iter().any(|attr| attr.path().is_ident("delta_string"))
+}
+
+// Baseline key for a #[delta_string] field, unique per type and field
+fn delta_string_key(input: &DeriveInput, field: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let name = &input.ident;
+    quote! { concat!(module_path!(), "::", stringify!(#name), ".", stringify!(#field)) }
+}
+
+// Same length prefix sizing as plain String fields, used for both the prefix and suffix lengths
+fn delta_string_len(max_len: Option<usize>) -> (usize, usize) {
+    match max_len {
+        Some(max_len) => (((max_len + 1) as f64).log2().ceil() as usize, max_len),
+        None => (16, 65535),
+    }
+}
+
+fn delta_string_serialize(value: proc_macro2::TokenStream, key: proc_macro2::TokenStream, max_len: Option<usize>) -> proc_macro2::TokenStream {
+    let (len_bits, max_len) = delta_string_len(max_len);
+    quote! { ::gbnet::delta::write_delta_string(writer, #key, &#value, #len_bits, #max_len)?; }
+}
+
+fn delta_string_deserialize(name: proc_macro2::TokenStream, key: proc_macro2::TokenStream, max_len: Option<usize>) -> proc_macro2::TokenStream {
+    let (len_bits, max_len) = delta_string_len(max_len);
+    quote! { let #name = ::gbnet::delta::read_delta_string(reader, #key, #len_bits, #max_len)?; }
+}
+
 fn get_field_bit_width(field: &Field, defaults: &[(String, usize)]) -> usize {
+    if is_varint(field) {
+        return 0;
+    }
+    // Floats are copied bit-for-bit through to_bits() unless quantized
+    if is_float_type(&field.ty) {
+        return match get_quantize(field) {
+            Some(_) => get_field_bits(field).unwrap_or(0),
+            None => 0,
+        };
+    }
     if let Some(bits) = get_field_bits(field) {
         validate_field_bits(field, bits).expect("Invalid bits attribute");
         bits
     } else {
-        let type_name = match &field.ty {
-            Type::Path(type_path) => type_path.path.get_ident().map(|i| i.to_string()),
-            _ => None,
-        };
+        let type_name = field_type_name(&field.ty);
         if let Some(type_name) = &type_name {
             if let Some((_, bits)) = defaults.iter().find(|(t, _)| t == type_name) {
                 validate_field_bits(field, *bits).expect("Invalid default bits");
@@ -162,8 +459,6 @@ fn get_field_bit_width(field: &Field, defaults: &[(String, usize)]) -> usize {
             Some("u16") | Some("i16") => 16,
             Some("u32") | Some("i32") => 32,
             Some("u64") | Some("i64") => 64,
-            Some("f32") => 32,
-            Some("f64") => 64,
             Some("bool") => 1,
             _ => 0,
         }
@@ -171,6 +466,9 @@ fn get_field_bit_width(field: &Field, defaults: &[(String, usize)]) -> usize {
 }
 
 fn validate_field_bits(field: &Field, bits: usize) -> syn::Result<()> {
+    if bits == 0 {
+        return Err(syn::Error::new_spanned(&field.ty, "Bits attribute must be at least 1"));
+    }
     if bits > 64 {
         return Err(syn::Error::new_spanned(&field.ty, "Bits attribute exceeds 64"));
     }
@@ -190,6 +488,64 @@ fn validate_field_bits(field: &Field, bits: usize) -> syn::Result<()> {
     }
 }
 
+fn native_bit_width(type_name: Option<&str>) -> Option<usize> {
+    match type_name {
+        Some("u8") | Some("i8") => Some(8),
+        Some("u16") | Some("i16") => Some(16),
+        Some("u32") | Some("i32") => Some(32),
+        Some("u64") | Some("i64") | Some("usize") | Some("isize") => Some(64),
+        Some("bool") => Some(1),
+        _ => None,
+    }
+}
+
+fn field_type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(type_path) => type_path.path.get_ident().map(|i| i.to_string()),
+        _ => None,
+    }
+}
+
+// Scalars with no wire representation; without this check they would fall through to
+// the trait path and fail with an unhelpful missing-impl error
+fn is_unsupported_scalar(type_name: &str) -> bool {
+    matches!(type_name, "u128" | "i128" | "f16" | "f128" | "str")
+}
+
+// Only a #[bits] narrower than the native width can be exceeded; at full width every
+// value fits (and `1u64 << 64` would overflow). Shared by the bit and byte-aligned paths
+// so both reject the same values.
+fn range_check(value: proc_macro2::TokenStream, bits: usize, type_name: Option<&str>, field: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    match native_bit_width(type_name) {
+        Some(native) if bits < native => quote! {
+            if #value as u64 > (1u64 << #bits) - 1 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Value {} exceeds {} bits for field {:?}", #value, #bits, #field)
+                ));
+            }
+        },
+        _ => quote! {},
+    }
+}
+
+/// Binds a field read as `raw` (a `u64`), checking platform-sized integers fit rather than
+/// truncating them; the wire always carries them as 64 bits.
+fn bind_read(name: proc_macro2::TokenStream, raw: proc_macro2::TokenStream, type_name: Option<&str>) -> proc_macro2::TokenStream {
+    let (ty, wire) = match type_name {
+        Some("usize") => (quote! { usize }, quote! { u64 }),
+        Some("isize") => (quote! { isize }, quote! { i64 }),
+        _ => return quote! { let #name = #raw as _; },
+    };
+    quote! {
+        let value = #raw as #wire;
+        let #name = <#ty>::try_from(value).map_err(|_| std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Value {} does not fit in {}", value, stringify!(#ty)),
+        ))?;
+    }
+}
+
 fn get_enum_bits(input: &DeriveInput) -> Option<usize> {
     input.attrs.iter()
         .find(|attr| attr.path().is_ident("bits"))
@@ -207,27 +563,369 @@ fn get_enum_bits(input: &DeriveInput) -> Option<usize> {
         })
 }
 
-#[proc_macro_derive(NetworkSerialize, attributes(no_serialize, bits, max_len, byte_align, default_bits, default_max_len))]
+// Checks attribute combinations up front so misuse is reported as a spanned compile error
+fn validate_input(input: &DeriveInput) -> syn::Result<()> {
+    parse_gbnet_options(input)?;
+    let defaults = get_default_bits(input);
+    for (type_name, bits) in &defaults {
+        if type_name == "f32" || type_name == "f64" {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "default_bits cannot be applied to floats; use #[quantize(min = .., max = ..)] with #[bits = N] per field",
+            ));
+        }
+        match native_bit_width(Some(type_name)) {
+            Some(native) if (1..=native).contains(bits) => {}
+            Some(native) => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    format!("default_bits for `{}` must be between 1 and {}", type_name, native),
+                ));
+            }
+            None => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    format!("default_bits is only supported for integer types, not `{}`", type_name),
+                ));
+            }
+        }
+    }
+
+    if let Data::Enum(data) = &input.data {
+        if let Some(field) = data.variants.iter().flat_map(|v| v.fields.iter()).find(|f| is_delta_string(f)) {
+            return Err(syn::Error::new_spanned(&field.ty, "#[delta_string] is only supported on struct fields"));
+        }
+    }
+
+    let fields: Vec<&Field> = match &input.data {
+        Data::Struct(data) => data.fields.iter().collect(),
+        Data::Enum(data) => data.variants.iter().flat_map(|v| v.fields.iter()).collect(),
+        Data::Union(_) => Vec::new(),
+    };
+
+    for field in fields {
+        let type_name = field_type_name(&field.ty);
+        let field_bits = get_field_bits(field);
+
+        if let Some(type_name) = type_name.as_deref() {
+            if is_unsupported_scalar(type_name) {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    format!("`{}` has no wire encoding; use a narrower integer or a custom type", type_name),
+                ));
+            }
+            if (type_name == "usize" || type_name == "isize")
+                && field_bits.is_none()
+                && !is_varint(field)
+                && !defaults.iter().any(|(t, _)| t == type_name)
+                && should_serialize_field(field)
+            {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    format!("`{}` is platform-sized; add #[bits = N] (at most 64) to fix its wire width", type_name),
+                ));
+            }
+            if type_name == "char" && field_bits.is_some() {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    "char is always 21 bits (UTF-32 when byte-aligned); remove #[bits]",
+                ));
+            }
+        }
+
+        if let Some(bits) = field_bits {
+            if !is_float_type(&field.ty) {
+                if is_vec_type(&field.ty) || is_string_type(&field.ty) || is_option_type(&field.ty)
+                    || matches!(field.ty, Type::Array(_) | Type::Tuple(_) | Type::Reference(_))
+                {
+                    return Err(syn::Error::new_spanned(
+                        &field.ty,
+                        "#[bits] is only supported on integer and bool fields; use #[max_len] for collections",
+                    ));
+                }
+                validate_field_bits(field, bits)?;
+            }
+        }
+
+        if is_blob(field) && !is_byte_vec(&field.ty) && !is_byte_array(&field.ty) {
+            return Err(syn::Error::new_spanned(&field.ty, "#[blob] is only supported on Vec<u8> and [u8; N] fields"));
+        }
+
+        if is_delta_string(field) && !is_string_type(&field.ty) {
+            return Err(syn::Error::new_spanned(&field.ty, "#[delta_string] is only supported on String fields"));
+        }
+
+        if is_varint(field) {
+            if !matches!(type_name.as_deref(), Some("u8" | "u16" | "u32" | "u64" | "usize")) && !is_signed_integer(type_name.as_deref()) {
+                return Err(syn::Error::new_spanned(&field.ty, "#[varint] is only supported on integer fields"));
+            }
+            if field_bits.is_some() {
+                return Err(syn::Error::new_spanned(&field.ty, "#[varint] sizes the field itself; remove #[bits]"));
+            }
+        }
+
+        let quantize = field.attrs.iter().find(|attr| attr.path().is_ident("quantize"));
+        match (is_float_type(&field.ty), quantize) {
+            (true, Some(attr)) => {
+                parse_quantize(attr)?;
+                match get_field_bits(field) {
+                    Some(bits) if (1..=32).contains(&bits) => {}
+                    Some(_) => return Err(syn::Error::new_spanned(attr, "quantized floats support 1 to 32 bits")),
+                    None => return Err(syn::Error::new_spanned(attr, "#[quantize] requires #[bits = N]")),
+                }
+            }
+            (true, None) if get_field_bits(field).is_some() => {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    "#[bits] on a float requires #[quantize(min = .., max = ..)]; remove #[bits] for full-width encoding",
+                ));
+            }
+            (false, Some(attr)) => {
+                return Err(syn::Error::new_spanned(attr, "#[quantize] is only supported on f32 and f64 fields"));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn has_schema(input: &DeriveInput) -> bool {
+    input.attrs.iter().any(|attr| attr.path().is_ident("schema"))
+}
+
+// Bit width reported in the schema: the packed width where one is fixed, including
+// the widths that come from trait impls (full floats, 21-bit chars)
+fn schema_field_bits(field: &Field, defaults: &[(String, usize)]) -> Option<usize> {
+    match field_type_name(&field.ty).as_deref() {
+        Some("f32") if get_quantize(field).is_none() => Some(32),
+        Some("f64") if get_quantize(field).is_none() => Some(64),
+        Some("char") => Some(21),
+        _ => match get_field_bit_width(field, defaults) {
+            0 => None,
+            bits => Some(bits),
+        },
+    }
+}
+
+// Enum variants only apply max_len to Vec fields; their Strings go through the trait impl
+fn generate_field_schemas<'a>(fields: impl Iterator<Item = &'a Field>, in_enum: bool, input: &DeriveInput) -> proc_macro2::TokenStream {
+    let defaults = get_default_bits(input);
+    let schemas = fields.enumerate().filter(|(_, f)| should_serialize_field(f)).map(|(i, f)| {
+        let name = f.ident.as_ref().map(|ident| ident.to_string()).unwrap_or_else(|| i.to_string());
+        let ty = &f.ty;
+        let ty = quote!(#ty).to_string().replace(' ', "");
+        let bits = match schema_field_bits(f, &defaults) {
+            Some(bits) => quote! { Some(#bits) },
+            None => quote! { None },
+        };
+        let max_len = match get_max_len(f, input) {
+            Some(max_len) if is_vec_type(&f.ty) || (is_string_type(&f.ty) && !in_enum) => quote! { Some(#max_len) },
+            _ => quote! { None },
+        };
+        let byte_align = is_byte_aligned(f);
+        let delta_string = is_delta_string(f);
+        let varint = is_varint(f);
+        let blob = is_blob(f);
+        let quantize = match get_quantize(f) {
+            Some((min, max)) => quote! { Some((#min, #max)) },
+            None => quote! { None },
+        };
+        quote! {
+            ::gbnet::schema::FieldSchema {
+                name: #name,
+                ty: #ty,
+                bits: #bits,
+                max_len: #max_len,
+                byte_align: #byte_align,
+                quantize: #quantize,
+                delta_string: #delta_string,
+                varint: #varint,
+                blob: #blob,
+            }
+        }
+    });
+    quote! { &[#(#schemas),*] }
+}
+
+fn generate_schema_registration(input: &DeriveInput) -> proc_macro2::TokenStream {
+    if !has_schema(input) {
+        return quote! {};
+    }
+
+    let name = input.ident.to_string();
+    let tag = match debug_tag(input) {
+        Some(tag) => quote! { Some(#tag) },
+        None => quote! { None },
+    };
+    let kind = match &input.data {
+        Data::Struct(data) => {
+            let fields = generate_field_schemas(data.fields.iter(), false, input);
+            quote! { ::gbnet::schema::SchemaKind::Struct { fields: #fields } }
+        }
+        Data::Enum(data) => {
+            let variant_count = data.variants.len();
+            let min_bits = if variant_count == 0 { 0 } else { (variant_count as f64).log2().ceil() as usize };
+            let variant_bits = get_enum_bits(input).unwrap_or(min_bits);
+            let variants = data.variants.iter().enumerate().map(|(i, variant)| {
+                let variant_name = variant.ident.to_string();
+                let index = i as u64;
+                let fields = generate_field_schemas(variant.fields.iter(), true, input);
+                quote! {
+                    ::gbnet::schema::VariantSchema { name: #variant_name, index: #index, fields: #fields }
+                }
+            });
+            quote! {
+                ::gbnet::schema::SchemaKind::Enum { variant_bits: #variant_bits, variants: &[#(#variants),*] }
+            }
+        }
+        Data::Union(_) => panic!("Unions are not supported"),
+    };
+
+    quote! {
+        ::gbnet::schema::inventory::submit! {
+            ::gbnet::schema::TypeSchema {
+                name: #name,
+                module: module_path!(),
+                kind: #kind,
+                tag: #tag,
+            }
+        }
+    }
+}
+
+#[proc_macro_derive(NetworkSerialize, attributes(no_serialize, bits, max_len, byte_align, default_bits, default_max_len, quantize, schema, delta_string, varint, blob, gbnet))]
 pub fn derive_network_serialize(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let name = &input.ident;
 
+    if let Err(err) = validate_input(&input) {
+        return err.to_compile_error().into();
+    }
+
     let bit_serialize_impl = generate_bit_serialize_impl(&input, name);
-    let bit_deserialize_impl = generate_bit_deserialize_impl(&input, name);
     let byte_aligned_serialize_impl = generate_byte_aligned_serialize_impl(&input, name);
-    let byte_aligned_deserialize_impl = generate_byte_aligned_deserialize_impl(&input, name);
+    // Types with lifetimes hold borrowed fields, which only a reader over the packet can fill
+    let (bit_deserialize_impl, byte_aligned_deserialize_impl) = if input.generics.lifetimes().next().is_some() {
+        (generate_bit_deserialize_borrowed_impl(&input, name), quote! {})
+    } else {
+        (generate_bit_deserialize_impl(&input, name), generate_byte_aligned_deserialize_impl(&input, name))
+    };
+    let schema_registration = generate_schema_registration(&input);
+    let byte_helpers = generate_byte_helpers(&input, name);
 
     let expanded = quote! {
         #bit_serialize_impl
         #bit_deserialize_impl
         #byte_aligned_serialize_impl
         #byte_aligned_deserialize_impl
+        #schema_registration
+        #byte_helpers
     };
 
     TokenStream::from(expanded)
 }
 
+// Non-generic entry points for #[gbnet(to_bytes)] and #[gbnet(helpers)]: the serializers
+// are instantiated for BitBuffer and BitSlice once, here, instead of in every caller's crate
+fn generate_byte_helpers(input: &DeriveInput, name: &syn::Ident) -> proc_macro2::TokenStream {
+    let Ok(options) = parse_gbnet_options(input) else {
+        return quote! {};
+    };
+    if !options.to_bytes && !options.helpers {
+        return quote! {};
+    }
+    let inline = &options.inline;
+    let borrowed = input.generics.lifetimes().next().is_some();
+    let mut generics = add_trait_bounds(input.generics.clone(), quote! { ::gbnet::serialize::BitSerialize });
+    if !borrowed {
+        generics = add_trait_bounds(generics, quote! { ::gbnet::serialize::BitDeserialize });
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let to_bytes = if options.to_bytes {
+        quote! {
+            /// Bit-packs the value into bytes, padded to a whole byte.
+            #inline
+            pub fn to_bytes(&self) -> std::io::Result<Vec<u8>> {
+                let mut buffer = ::gbnet::serialize::bit_io::BitBuffer::new();
+                ::gbnet::serialize::BitSerialize::bit_serialize(self, &mut buffer)?;
+                buffer.into_bytes(true)
+            }
+        }
+    } else {
+        quote! {}
+    };
+    // Borrowing types already have BitDeserializeBorrowed::from_bytes_borrowed
+    let from_bytes = if options.to_bytes && !borrowed {
+        quote! {
+            /// Reads a value from the start of `bytes`.
+            #inline
+            pub fn from_bytes(bytes: &[u8]) -> std::io::Result<Self> {
+                <Self as ::gbnet::serialize::BitDeserialize>::bit_deserialize(&mut ::gbnet::serialize::bit_io::BitSlice::new(bytes))
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // Errors name the type, so a log line says which message failed
+    let context = quote! {
+        |err: std::io::Error| std::io::Error::new(err.kind(), format!("{}: {}", stringify!(#name), err))
+    };
+    let helpers = if options.helpers {
+        quote! {
+            /// A buffer holding the bit-packed value, unpadded so more can be written after it.
+            #inline
+            pub fn to_bitbuffer(&self) -> std::io::Result<::gbnet::serialize::bit_io::BitBuffer> {
+                let mut buffer = ::gbnet::serialize::bit_io::BitBuffer::new();
+                ::gbnet::serialize::BitSerialize::bit_serialize(self, &mut buffer).map_err(#context)?;
+                Ok(buffer)
+            }
+
+            /// Bit-packs the value into bytes, padded to a whole byte.
+            #inline
+            pub fn serialize_to_vec(&self) -> std::io::Result<Vec<u8>> {
+                self.to_bitbuffer()?.into_bytes(true).map_err(#context)
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let deserialize_from_slice = if options.helpers && !borrowed {
+        quote! {
+            /// Reads a value that fills `bytes`, such as one from `serialize_to_vec`; bytes left
+            /// over after it are an error.
+            #inline
+            pub fn deserialize_from_slice(bytes: &[u8]) -> std::io::Result<Self> {
+                let mut reader = ::gbnet::serialize::bit_io::BitSlice::new(bytes);
+                let value = <Self as ::gbnet::serialize::BitDeserialize>::bit_deserialize(&mut reader).map_err(#context)?;
+                if reader.byte_pos() < bytes.len() {
+                    let trailing = bytes.len() - reader.byte_pos();
+                    return Err((#context)(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("{} trailing bytes", trailing),
+                    )));
+                }
+                Ok(value)
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            #to_bytes
+            #from_bytes
+            #helpers
+            #deserialize_from_slice
+        }
+    }
+}
+
 fn generate_bit_serialize_impl(input: &DeriveInput, name: &syn::Ident) -> proc_macro2::TokenStream {
+    let inline = inline_attr(input);
     let generics = add_trait_bounds(input.generics.clone(), quote! { ::gbnet::serialize::BitSerialize });
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
@@ -237,9 +935,23 @@ fn generate_bit_serialize_impl(input: &DeriveInput, name: &syn::Ident) -> proc_m
         Data::Union(_) => panic!("Unions are not supported"),
     };
 
+    // Only the outermost derived value is tagged. ENABLED is a constant, so release builds
+    // compile the scope and the tag out
+    let write_tag = match debug_tag(input) {
+        Some(tag) => quote! {
+            let __gbnet_tag_scope = ::gbnet::debug_packets::Scope::enter();
+            if __gbnet_tag_scope.outermost() {
+                ::gbnet::debug_packets::write_tag(writer, #tag)?;
+            }
+        },
+        None => quote! { let __gbnet_tag_scope = ::gbnet::debug_packets::Scope::enter(); },
+    };
+
     quote! {
         impl #impl_generics ::gbnet::serialize::BitSerialize for #name #ty_generics #where_clause {
+            #inline
             fn bit_serialize<W: ::gbnet::serialize::bit_io::BitWrite>(&self, writer: &mut W) -> std::io::Result<()> {
+                #write_tag
                 #serialize_body
             }
         }
@@ -247,18 +959,67 @@ fn generate_bit_serialize_impl(input: &DeriveInput, name: &syn::Ident) -> proc_m
 }
 
 fn generate_bit_deserialize_impl(input: &DeriveInput, name: &syn::Ident) -> proc_macro2::TokenStream {
+    let inline = inline_attr(input);
     let generics = add_trait_bounds(input.generics.clone(), quote! { ::gbnet::serialize::BitDeserialize });
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let bit_read = quote! { ::gbnet::serialize::BitDeserialize::bit_deserialize };
+    let check_tag = generate_check_tag(input, name);
 
     let deserialize_body = match &input.data {
-        Data::Struct(data) => generate_struct_deserialize(&data.fields, true, input),
-        Data::Enum(data) => generate_enum_deserialize(data, true, input),
+        Data::Struct(data) => generate_struct_deserialize(&data.fields, true, &bit_read, input),
+        Data::Enum(data) => generate_enum_deserialize(data, true, &bit_read, input),
         Data::Union(_) => panic!("Unions are not supported"),
     };
 
     quote! {
         impl #impl_generics ::gbnet::serialize::BitDeserialize for #name #ty_generics #where_clause {
+            #inline
             fn bit_deserialize<R: ::gbnet::serialize::bit_io::BitRead>(reader: &mut R) -> std::io::Result<Self> {
+                #check_tag
+                #deserialize_body
+            }
+        }
+    }
+}
+
+// Untagged types still enter the scope, so the derived types inside them stay untagged too
+fn generate_check_tag(input: &DeriveInput, name: &syn::Ident) -> proc_macro2::TokenStream {
+    match debug_tag(input) {
+        Some(tag) => quote! {
+            let __gbnet_tag_scope = ::gbnet::debug_packets::Scope::enter();
+            if __gbnet_tag_scope.outermost() {
+                ::gbnet::debug_packets::check_tag(reader, #tag, stringify!(#name))?;
+            }
+        },
+        None => quote! { let __gbnet_tag_scope = ::gbnet::debug_packets::Scope::enter(); },
+    }
+}
+
+// Same body as BitDeserialize, reading nested values with BitDeserializeBorrowed from a
+// BitSlice whose bytes outlive every lifetime of the type
+fn generate_bit_deserialize_borrowed_impl(input: &DeriveInput, name: &syn::Ident) -> proc_macro2::TokenStream {
+    let inline = inline_attr(input);
+    let generics = add_trait_bounds(input.generics.clone(), quote! { ::gbnet::serialize::BitDeserializeBorrowed<'__de> });
+    let (_, ty_generics, where_clause) = generics.split_for_impl();
+    let mut impl_generics = generics.clone();
+    let lifetimes: Vec<syn::Lifetime> = input.generics.lifetimes().map(|param| param.lifetime.clone()).collect();
+    impl_generics.params.insert(0, syn::parse_quote! { '__de: #(#lifetimes)+* });
+    let (impl_generics, _, _) = impl_generics.split_for_impl();
+    let bit_read = quote! { ::gbnet::serialize::BitDeserializeBorrowed::bit_deserialize_borrowed };
+    let check_tag = generate_check_tag(input, name);
+
+    let deserialize_body = match &input.data {
+        Data::Struct(data) => generate_struct_deserialize(&data.fields, true, &bit_read, input),
+        Data::Enum(data) => generate_enum_deserialize(data, true, &bit_read, input),
+        Data::Union(_) => panic!("Unions are not supported"),
+    };
+
+    quote! {
+        impl #impl_generics ::gbnet::serialize::BitDeserializeBorrowed<'__de> for #name #ty_generics #where_clause {
+            #inline
+            fn bit_deserialize_borrowed(reader: &mut ::gbnet::serialize::bit_io::BitSlice<'__de>) -> std::io::Result<Self> {
+                use ::gbnet::serialize::bit_io::BitRead;
+                #check_tag
                 #deserialize_body
             }
         }
@@ -266,6 +1027,7 @@ fn generate_bit_deserialize_impl(input: &DeriveInput, name: &syn::Ident) -> proc
 }
 
 fn generate_byte_aligned_serialize_impl(input: &DeriveInput, name: &syn::Ident) -> proc_macro2::TokenStream {
+    let inline = inline_attr(input);
     let generics = add_trait_bounds(input.generics.clone(), quote! { ::gbnet::serialize::ByteAlignedSerialize });
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
@@ -277,6 +1039,7 @@ fn generate_byte_aligned_serialize_impl(input: &DeriveInput, name: &syn::Ident)
 
     quote! {
         impl #impl_generics ::gbnet::serialize::ByteAlignedSerialize for #name #ty_generics #where_clause {
+            #inline
             fn byte_aligned_serialize<W: std::io::Write + byteorder::WriteBytesExt>(&self, writer: &mut W) -> std::io::Result<()> {
                 #serialize_body
             }
@@ -285,17 +1048,20 @@ fn generate_byte_aligned_serialize_impl(input: &DeriveInput, name: &syn::Ident)
 }
 
 fn generate_byte_aligned_deserialize_impl(input: &DeriveInput, name: &syn::Ident) -> proc_macro2::TokenStream {
+    let inline = inline_attr(input);
     let generics = add_trait_bounds(input.generics.clone(), quote! { ::gbnet::serialize::ByteAlignedDeserialize });
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let bit_read = quote! { ::gbnet::serialize::BitDeserialize::bit_deserialize };
 
     let deserialize_body = match &input.data {
-        Data::Struct(data) => generate_struct_deserialize(&data.fields, false, input),
-        Data::Enum(data) => generate_enum_deserialize(data, false, input),
+        Data::Struct(data) => generate_struct_deserialize(&data.fields, false, &bit_read, input),
+        Data::Enum(data) => generate_enum_deserialize(data, false, &bit_read, input),
         Data::Union(_) => panic!("Unions are not supported"),
     };
 
     quote! {
         impl #impl_generics ::gbnet::serialize::ByteAlignedDeserialize for #name #ty_generics #where_clause {
+            #inline
             fn byte_aligned_deserialize<R: std::io::Read + byteorder::ReadBytesExt>(reader: &mut R) -> std::io::Result<Self> {
                 #deserialize_body
             }
@@ -303,6 +1069,75 @@ fn generate_byte_aligned_deserialize_impl(input: &DeriveInput, name: &syn::Ident
     }
 }
 
+// One field's part of a struct's serialize body
+enum SerializeStep {
+    /// A fixed-width `write_bits`, which `combine_writes` may merge with its neighbours
+    Bits { value: proc_macro2::TokenStream, bits: usize, range_check: proc_macro2::TokenStream },
+    Code(proc_macro2::TokenStream),
+}
+
+// Runs of fixed-width fields totalling at most 64 bits are written with one `write_bits`
+// call. The shifts are computed here for both bit orders, so the generated code only
+// masks, shifts and ors; the bits on the wire are the same as writing field by field
+fn combine_writes(steps: impl Iterator<Item = SerializeStep>) -> proc_macro2::TokenStream {
+    let mut output = proc_macro2::TokenStream::new();
+    let mut run: Vec<(proc_macro2::TokenStream, usize, proc_macro2::TokenStream)> = Vec::new();
+    let flush = |run: &mut Vec<(proc_macro2::TokenStream, usize, proc_macro2::TokenStream)>, output: &mut proc_macro2::TokenStream| {
+        match run.len() {
+            0 => {}
+            1 => {
+                let (value, bits, range_check) = run.remove(0);
+                output.extend(quote! {
+                    #range_check
+                    writer.write_bits(#value as u64, #bits)?;
+                });
+            }
+            _ => {
+                let total: usize = run.iter().map(|(_, bits, _)| bits).sum();
+                let range_checks = run.iter().map(|(_, _, range_check)| range_check);
+                let mut msb_first = Vec::new();
+                let mut lsb_first = Vec::new();
+                let mut offset = 0;
+                for (value, bits, _) in run.iter() {
+                    let mask = (1u64 << bits) - 1;
+                    let msb_shift = total - offset - bits;
+                    msb_first.push(quote! { (((#value as u64) & #mask) << #msb_shift) });
+                    lsb_first.push(quote! { (((#value as u64) & #mask) << #offset) });
+                    offset += bits;
+                }
+                output.extend(quote! {
+                    #(#range_checks)*
+                    {
+                        let packed = match writer.bit_order() {
+                            ::gbnet::serialize::bit_io::BitOrder::MsbFirst => #(#msb_first)|*,
+                            ::gbnet::serialize::bit_io::BitOrder::LsbFirst => #(#lsb_first)|*,
+                        };
+                        writer.write_bits(packed, #total)?;
+                    }
+                });
+                run.clear();
+            }
+        }
+    };
+    for step in steps {
+        match step {
+            SerializeStep::Bits { value, bits, range_check } => {
+                let pending: usize = run.iter().map(|(_, bits, _)| bits).sum();
+                if pending + bits > 64 {
+                    flush(&mut run, &mut output);
+                }
+                run.push((value, bits, range_check));
+            }
+            SerializeStep::Code(code) => {
+                flush(&mut run, &mut output);
+                output.extend(code);
+            }
+        }
+    }
+    flush(&mut run, &mut output);
+    output
+}
+
 fn generate_struct_serialize(fields: &Fields, is_bit: bool, input: &DeriveInput) -> proc_macro2::TokenStream {
     let defaults = get_default_bits(input);
     match fields {
@@ -315,17 +1150,25 @@ fn generate_struct_serialize(fields: &Fields, is_bit: bool, input: &DeriveInput)
                     let max_len = get_max_len(f, input);
                     let value_expr = quote! { self.#name };
                     
-                    let serialize_code = if is_bit {
-                        if bits > 0 {
+                    let serialize_code = if is_blob(f) {
+                        blob_serialize(quote! { self.#name }, f, max_len, is_bit)
+                    } else if is_bit {
+                        if is_varint(f) {
+                            varint_serialize(quote! { self.#name }, f)
+                        } else if let Some((min, max)) = get_quantize(f) {
+                            quantized_serialize(quote! { self.#name }, bits, min, max)
+                        } else if bits > 0 {
+                            let type_name = field_type_name(&f.ty);
+                            let range_check = range_check(value_expr.clone(), bits, type_name.as_deref(), quote! { stringify!(#name) });
+                            if !is_byte_align {
+                                return Some(SerializeStep::Bits { value: value_expr, bits, range_check });
+                            }
                             quote! {
-                                if #value_expr as u64 > (1u64 << #bits) - 1 {
-                                    return Err(std::io::Error::new(
-                                        std::io::ErrorKind::InvalidData,
-                                        format!("Value {} exceeds {} bits for field {:?}", #value_expr, #bits, stringify!(#name))
-                                    ));
-                                }
+                                #range_check
                                 writer.write_bits(#value_expr as u64, #bits)?;
                             }
+                        } else if is_delta_string(f) {
+                            delta_string_serialize(quote! { self.#name }, delta_string_key(input, quote! { #name }), max_len)
                         } else if is_vec_type(&f.ty) {
                             let (len_bits, max_len_expr) = if let Some(max_len) = max_len {
                                 let len_bits = ((max_len + 1) as f64).log2().ceil() as usize;
@@ -380,20 +1223,21 @@ fn generate_struct_serialize(fields: &Fields, is_bit: bool, input: &DeriveInput)
                     };
                     
                     if is_byte_align && is_bit {
-                        Some(quote! {
+                        Some(SerializeStep::Code(quote! {
                             while writer.bit_pos() % 8 != 0 {
                                 writer.write_bit(false)?;
                             }
                             #serialize_code
-                        })
+                        }))
                     } else {
-                        Some(serialize_code)
+                        Some(SerializeStep::Code(serialize_code))
                     }
                 } else {
                     None
                 }
             });
-            quote! { #(#serialize_fields)* Ok(()) }
+            let serialize_fields = combine_writes(serialize_fields);
+            quote! { #serialize_fields Ok(()) }
         }
         Fields::Unnamed(fields) => {
             let serialize_fields = (0..fields.unnamed.len()).filter_map(|i| {
@@ -404,17 +1248,25 @@ fn generate_struct_serialize(fields: &Fields, is_bit: bool, input: &DeriveInput)
                     let max_len = get_max_len(&fields.unnamed[i], input);
                     let value_expr = quote! { self.#index };
                     
-                    let serialize_code = if is_bit {
-                        if bits > 0 {
+                    let serialize_code = if is_blob(&fields.unnamed[i]) {
+                        blob_serialize(quote! { self.#index }, &fields.unnamed[i], max_len, is_bit)
+                    } else if is_bit {
+                        if is_varint(&fields.unnamed[i]) {
+                            varint_serialize(quote! { self.#index }, &fields.unnamed[i])
+                        } else if let Some((min, max)) = get_quantize(&fields.unnamed[i]) {
+                            quantized_serialize(quote! { self.#index }, bits, min, max)
+                        } else if bits > 0 {
+                            let type_name = field_type_name(&fields.unnamed[i].ty);
+                            let range_check = range_check(value_expr.clone(), bits, type_name.as_deref(), quote! { #index });
+                            if !is_byte_align {
+                                return Some(SerializeStep::Bits { value: value_expr, bits, range_check });
+                            }
                             quote! {
-                                if #value_expr as u64 > (1u64 << #bits) - 1 {
-                                    return Err(std::io::Error::new(
-                                        std::io::ErrorKind::InvalidData,
-                                        format!("Value {} exceeds {} bits for field {}", #value_expr, #bits, #index)
-                                    ));
-                                }
+                                #range_check
                                 writer.write_bits(#value_expr as u64, #bits)?;
                             }
+                        } else if is_delta_string(&fields.unnamed[i]) {
+                            delta_string_serialize(quote! { self.#index }, delta_string_key(input, quote! { #index }), max_len)
                         } else if is_vec_type(&fields.unnamed[i].ty) {
                             let (len_bits, max_len_expr) = if let Some(max_len) = max_len {
                                 let len_bits = ((max_len + 1) as f64).log2().ceil() as usize;
@@ -469,26 +1321,27 @@ fn generate_struct_serialize(fields: &Fields, is_bit: bool, input: &DeriveInput)
                     };
                     
                     if is_byte_align && is_bit {
-                        Some(quote! {
+                        Some(SerializeStep::Code(quote! {
                             while writer.bit_pos() % 8 != 0 {
                                 writer.write_bit(false)?;
                             }
                             #serialize_code
-                        })
+                        }))
                     } else {
-                        Some(serialize_code)
+                        Some(SerializeStep::Code(serialize_code))
                     }
                 } else {
                     None
                 }
             });
-            quote! { #(#serialize_fields)* Ok(()) }
+            let serialize_fields = combine_writes(serialize_fields);
+            quote! { #serialize_fields Ok(()) }
         }
         Fields::Unit => quote! { Ok(()) },
     }
 }
 
-fn generate_struct_deserialize(fields: &Fields, is_bit: bool, input: &DeriveInput) -> proc_macro2::TokenStream {
+fn generate_struct_deserialize(fields: &Fields, is_bit: bool, bit_read: &proc_macro2::TokenStream, input: &DeriveInput) -> proc_macro2::TokenStream {
     let defaults = get_default_bits(input);
     match fields {
         Fields::Named(fields) => {
@@ -512,18 +1365,23 @@ fn generate_struct_deserialize(fields: &Fields, is_bit: bool, input: &DeriveInpu
                     let is_byte_align = is_byte_aligned(f);
                     let bits = get_field_bit_width(f, &defaults);
                     let max_len = get_max_len(f, input);
-                    let type_name = match &f.ty {
-                        Type::Path(type_path) => type_path.path.get_ident().map(|i| i.to_string()),
-                        _ => None,
-                    };
+                    let type_name = field_type_name(&f.ty);
                     
-                    let deserialize_code = if is_bit {
-                        if bits > 0 {
+                    let deserialize_code = if is_blob(f) {
+                        blob_deserialize(quote! { #name }, f, max_len, is_bit)
+                    } else if is_bit {
+                        if is_varint(f) {
+                            varint_deserialize(quote! { #name }, f)
+                        } else if let Some((min, max)) = get_quantize(f) {
+                            quantized_deserialize(quote! { #name }, bits, min, max)
+                        } else if bits > 0 {
                             if type_name.as_deref() == Some("bool") {
                                 quote! { let #name = reader.read_bits(#bits)? != 0; }
                             } else {
-                                quote! { let #name = reader.read_bits(#bits)? as _; }
+                                bind_read(quote! { #name }, quote! { reader.read_bits(#bits)? }, type_name.as_deref())
                             }
+                        } else if is_delta_string(f) {
+                            delta_string_deserialize(quote! { #name }, delta_string_key(input, quote! { #name }), max_len)
                         } else if is_vec_type(&f.ty) {
                             let (len_bits, max_len_expr) = if let Some(max_len) = max_len {
                                 let len_bits = ((max_len + 1) as f64).log2().ceil() as usize;
@@ -540,7 +1398,7 @@ fn generate_struct_deserialize(fields: &Fields, is_bit: bool, input: &DeriveInpu
                                 }
                                 let mut #name = Vec::with_capacity(len);
                                 for _ in 0..len {
-                                    #name.push(::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?);
+                                    #name.push(#bit_read(reader)?);
                                 }
                             }
                         } else if is_string_type(&f.ty) {
@@ -570,19 +1428,19 @@ fn generate_struct_deserialize(fields: &Fields, is_bit: bool, input: &DeriveInpu
                                 quote! {
                                     let mut #name = Vec::with_capacity(#array_len);
                                     for _ in 0..#array_len {
-                                        #name.push(::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?);
+                                        #name.push(#bit_read(reader)?);
                                     }
                                     let #name: [_; #array_len] = #name.try_into().map_err(|_| {
                                         std::io::Error::new(std::io::ErrorKind::InvalidData, "Array length mismatch")
                                     })?;
                                 }
                             } else {
-                                quote! { let #name = ::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?; }
+                                quote! { let #name = #bit_read(reader)?; }
                             }
                         } else if is_option_type(&f.ty) {
-                            quote! { let #name = ::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?; }
+                            quote! { let #name = #bit_read(reader)?; }
                         } else {
-                            quote! { let #name = ::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?; }
+                            quote! { let #name = #bit_read(reader)?; }
                         }
                     } else {
                         quote! { let #name = ::gbnet::serialize::ByteAlignedDeserialize::byte_aligned_deserialize(reader)?; }
@@ -631,18 +1489,26 @@ fn generate_struct_deserialize(fields: &Fields, is_bit: bool, input: &DeriveInpu
                     let is_byte_align = is_byte_aligned(f);
                     let bits = get_field_bit_width(f, &defaults);
                     let max_len = get_max_len(f, input);
-                    let type_name = match &f.ty {
-                        Type::Path(type_path) => type_path.path.get_ident().map(|i| i.to_string()),
-                        _ => None,
-                    };
+                    let type_name = field_type_name(&f.ty);
                     
-                    let deserialize_code = if is_bit {
-                        if bits > 0 {
+                    let deserialize_code = if is_blob(f) {
+                        blob_deserialize(quote! { #name }, f, max_len, is_bit)
+                    } else if is_bit {
+                        if is_varint(f) {
+                            varint_deserialize(quote! { #name }, f)
+                        } else if let Some((min, max)) = get_quantize(f) {
+                            quantized_deserialize(quote! { #name }, bits, min, max)
+                        } else if bits > 0 {
                             if type_name.as_deref() == Some("bool") {
                                 quote! { let #name = reader.read_bits(#bits)? != 0; }
                             } else {
-                                quote! { let #name = reader.read_bits(#bits)? as _; }
+                                bind_read(quote! { #name }, quote! { reader.read_bits(#bits)? }, type_name.as_deref())
                             }
+                        } else if is_delta_string(f) {
+                            {
+                            let index = Index::from(i);
+                            delta_string_deserialize(quote! { #name }, delta_string_key(input, quote! { #index }), max_len)
+                        }
                         } else if is_vec_type(&f.ty) {
                             let (len_bits, max_len_expr) = if let Some(max_len) = max_len {
                                 let len_bits = ((max_len + 1) as f64).log2().ceil() as usize;
@@ -659,7 +1525,7 @@ fn generate_struct_deserialize(fields: &Fields, is_bit: bool, input: &DeriveInpu
                                 }
                                 let mut #name = Vec::with_capacity(len);
                                 for _ in 0..len {
-                                    #name.push(::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?);
+                                    #name.push(#bit_read(reader)?);
                                 }
                             }
                         } else if is_string_type(&f.ty) {
@@ -689,19 +1555,19 @@ fn generate_struct_deserialize(fields: &Fields, is_bit: bool, input: &DeriveInpu
                                 quote! {
                                     let mut #name = Vec::with_capacity(#array_len);
                                     for _ in 0..#array_len {
-                                        #name.push(::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?);
+                                        #name.push(#bit_read(reader)?);
                                     }
                                     let #name: [_; #array_len] = #name.try_into().map_err(|_| {
                                         std::io::Error::new(std::io::ErrorKind::InvalidData, "Array length mismatch")
                                     })?;
                                 }
                             } else {
-                                quote! { let #name = ::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?; }
+                                quote! { let #name = #bit_read(reader)?; }
                             }
                         } else if is_option_type(&f.ty) {
-                            quote! { let #name = ::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?; }
+                            quote! { let #name = #bit_read(reader)?; }
                         } else {
-                            quote! { let #name = ::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?; }
+                            quote! { let #name = #bit_read(reader)?; }
                         }
                     } else {
                         quote! { let #name = ::gbnet::serialize::ByteAlignedDeserialize::byte_aligned_deserialize(reader)?; }
@@ -763,15 +1629,18 @@ fn generate_enum_serialize(data: &syn::DataEnum, is_bit: bool, input: &DeriveInp
                         let is_byte_align = is_byte_aligned(f);
                         let bits = get_field_bit_width(f, &defaults);
                         let max_len = get_max_len(f, input);
-                        let serialize_code = if is_bit {
-                            if bits > 0 {
+                        let serialize_code = if is_blob(f) {
+                            blob_serialize(quote! { *#name }, f, max_len, is_bit)
+                        } else if is_bit {
+                            if is_varint(f) {
+                                varint_serialize(quote! { *#name }, f)
+                            } else if let Some((min, max)) = get_quantize(f) {
+                                quantized_serialize(quote! { *#name }, bits, min, max)
+                            } else if bits > 0 {
+                                let type_name = field_type_name(&f.ty);
+                                let range_check = range_check(quote! { *#name }, bits, type_name.as_deref(), quote! { stringify!(#name) });
                                 quote! {
-                                    if *#name as u64 > (1u64 << #bits) - 1 {
-                                        return Err(std::io::Error::new(
-                                            std::io::ErrorKind::InvalidData,
-                                            format!("Value {} exceeds {} bits for field {:?}", *#name, #bits, stringify!(#name))
-                                        ));
-                                    }
+                                    #range_check
                                     writer.write_bits(*#name as u64, #bits)?;
                                 }
                             } else if is_vec_type(&f.ty) {
@@ -798,17 +1667,19 @@ fn generate_enum_serialize(data: &syn::DataEnum, is_bit: bool, input: &DeriveInp
                             }
                         } else {
                             if bits > 0 {
-                                let type_name = match &f.ty {
-                                    Type::Path(type_path) => type_path.path.get_ident().map(|i| i.to_string()),
-                                    _ => None,
-                                };
-                                match type_name.as_deref() {
-                                    Some("u8") | Some("i8") => quote! { writer.write_u8(*#name)?; },
+                                let type_name = field_type_name(&f.ty);
+                                let range_check = range_check(quote! { *#name }, bits, type_name.as_deref(), quote! { stringify!(#name) });
+                                let write = match type_name.as_deref() {
+                                    Some("u8") | Some("i8") => quote! { writer.write_u8(*#name as u8)?; },
                                     Some("u16") | Some("i16") => quote! { writer.write_u16::<byteorder::LittleEndian>(*#name as u16)?; },
                                     Some("u32") | Some("i32") => quote! { writer.write_u32::<byteorder::LittleEndian>(*#name as u32)?; },
-                                    Some("u64") | Some("i64") => quote! { writer.write_u64::<byteorder::LittleEndian>(*#name as u64)?; },
+                                    Some("u64") | Some("i64") | Some("usize") | Some("isize") => quote! { writer.write_u64::<byteorder::LittleEndian>(*#name as u64)?; },
                                     Some("bool") => quote! { writer.write_u8(if *#name { 1 } else { 0 })?; },
                                     _ => quote! { #name.byte_aligned_serialize(writer)?; },
+                                };
+                                quote! {
+                                    #range_check
+                                    #write
                                 }
                             } else {
                                 quote! { #name.byte_aligned_serialize(writer)?; }
@@ -846,15 +1717,18 @@ fn generate_enum_serialize(data: &syn::DataEnum, is_bit: bool, input: &DeriveInp
                         let is_byte_align = is_byte_aligned(f);
                         let bits = get_field_bit_width(f, &defaults);
                         let max_len = get_max_len(f, input);
-                        let serialize_code = if is_bit {
-                            if bits > 0 {
+                        let serialize_code = if is_blob(f) {
+                            blob_serialize(quote! { *#name }, f, max_len, is_bit)
+                        } else if is_bit {
+                            if is_varint(f) {
+                                varint_serialize(quote! { *#name }, f)
+                            } else if let Some((min, max)) = get_quantize(f) {
+                                quantized_serialize(quote! { *#name }, bits, min, max)
+                            } else if bits > 0 {
+                                let type_name = field_type_name(&f.ty);
+                                let range_check = range_check(quote! { *#name }, bits, type_name.as_deref(), quote! { #i });
                                 quote! {
-                                    if *#name as u64 > (1u64 << #bits) - 1 {
-                                        return Err(std::io::Error::new(
-                                            std::io::ErrorKind::InvalidData,
-                                            format!("Value {} exceeds {} bits for field {}", *#name, #bits, #i)
-                                        ));
-                                    }
+                                    #range_check
                                     writer.write_bits(*#name as u64, #bits)?;
                                 }
                             } else if is_vec_type(&f.ty) {
@@ -881,17 +1755,19 @@ fn generate_enum_serialize(data: &syn::DataEnum, is_bit: bool, input: &DeriveInp
                             }
                         } else {
                             if bits > 0 {
-                                let type_name = match &f.ty {
-                                    Type::Path(type_path) => type_path.path.get_ident().map(|i| i.to_string()),
-                                    _ => None,
-                                };
-                                match type_name.as_deref() {
-                                    Some("u8") | Some("i8") => quote! { writer.write_u8(*#name)?; },
+                                let type_name = field_type_name(&f.ty);
+                                let range_check = range_check(quote! { *#name }, bits, type_name.as_deref(), quote! { #i });
+                                let write = match type_name.as_deref() {
+                                    Some("u8") | Some("i8") => quote! { writer.write_u8(*#name as u8)?; },
                                     Some("u16") | Some("i16") => quote! { writer.write_u16::<byteorder::LittleEndian>(*#name as u16)?; },
                                     Some("u32") | Some("i32") => quote! { writer.write_u32::<byteorder::LittleEndian>(*#name as u32)?; },
-                                    Some("u64") | Some("i64") => quote! { writer.write_u64::<byteorder::LittleEndian>(*#name as u64)?; },
+                                    Some("u64") | Some("i64") | Some("usize") | Some("isize") => quote! { writer.write_u64::<byteorder::LittleEndian>(*#name as u64)?; },
                                     Some("bool") => quote! { writer.write_u8(if *#name { 1 } else { 0 })?; },
                                     _ => quote! { #name.byte_aligned_serialize(writer)?; },
+                                };
+                                quote! {
+                                    #range_check
+                                    #write
                                 }
                             } else {
                                 quote! { #name.byte_aligned_serialize(writer)?; }
@@ -935,7 +1811,7 @@ fn generate_enum_serialize(data: &syn::DataEnum, is_bit: bool, input: &DeriveInp
     }
 }
 
-fn generate_enum_deserialize(data: &syn::DataEnum, is_bit: bool, input: &DeriveInput) -> proc_macro2::TokenStream {
+fn generate_enum_deserialize(data: &syn::DataEnum, is_bit: bool, bit_read: &proc_macro2::TokenStream, input: &DeriveInput) -> proc_macro2::TokenStream {
     let defaults = get_default_bits(input);
     let variant_count = data.variants.len();
     let min_bits = if variant_count == 0 { 0 } else { (variant_count as f64).log2().ceil() as usize };
@@ -976,16 +1852,19 @@ fn generate_enum_deserialize(data: &syn::DataEnum, is_bit: bool, input: &DeriveI
                         let is_byte_align = is_byte_aligned(f);
                         let bits = get_field_bit_width(f, &defaults);
                         let max_len = get_max_len(f, input);
-                        let type_name = match &f.ty {
-                            Type::Path(type_path) => type_path.path.get_ident().map(|i| i.to_string()),
-                            _ => None,
-                        };
-                        let deserialize_code = if is_bit {
-                            if bits > 0 {
+                        let type_name = field_type_name(&f.ty);
+                        let deserialize_code = if is_blob(f) {
+                            blob_deserialize(quote! { #name }, f, max_len, is_bit)
+                        } else if is_bit {
+                            if is_varint(f) {
+                                varint_deserialize(quote! { #name }, f)
+                            } else if let Some((min, max)) = get_quantize(f) {
+                                quantized_deserialize(quote! { #name }, bits, min, max)
+                            } else if bits > 0 {
                                 if type_name.as_deref() == Some("bool") {
                                     quote! { let #name = reader.read_bits(#bits)? != 0; }
                                 } else {
-                                    quote! { let #name = reader.read_bits(#bits)? as _; }
+                                    bind_read(quote! { #name }, quote! { reader.read_bits(#bits)? }, type_name.as_deref())
                                 }
                             } else if is_vec_type(&f.ty) {
                                 let (len_bits, max_len_expr) = if let Some(max_len) = max_len {
@@ -1003,19 +1882,23 @@ fn generate_enum_deserialize(data: &syn::DataEnum, is_bit: bool, input: &DeriveI
                                     }
                                     let mut #name = Vec::with_capacity(len);
                                     for _ in 0..len {
-                                        #name.push(::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?);
+                                        #name.push(#bit_read(reader)?);
                                     }
                                 }
                             } else {
-                                quote! { let #name = ::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?; }
+                                quote! { let #name = #bit_read(reader)?; }
                             }
                         } else {
                             if bits > 0 {
+                                let range_check = range_check(quote! { raw }, bits, type_name.as_deref(), quote! { stringify!(#name) });
                                 match type_name.as_deref() {
-                                    Some("u8") | Some("i8") => quote! { let #name = reader.read_u8()?; },
-                                    Some("u16") | Some("i16") => quote! { let #name = reader.read_u16::<byteorder::LittleEndian>()? as _; },
-                                    Some("u32") | Some("i32") => quote! { let #name = reader.read_u32::<byteorder::LittleEndian>()? as _; },
-                                    Some("u64") | Some("i64") => quote! { let #name = reader.read_u64::<byteorder::LittleEndian>()? as _; },
+                                    Some("u8") | Some("i8") => quote! { let raw = reader.read_u8()?; #range_check let #name = raw as _; },
+                                    Some("u16") | Some("i16") => quote! { let raw = reader.read_u16::<byteorder::LittleEndian>()?; #range_check let #name = raw as _; },
+                                    Some("u32") | Some("i32") => quote! { let raw = reader.read_u32::<byteorder::LittleEndian>()?; #range_check let #name = raw as _; },
+                                    Some("u64") | Some("i64") | Some("usize") | Some("isize") => {
+                                        let bind = bind_read(quote! { #name }, quote! { raw }, type_name.as_deref());
+                                        quote! { let raw = reader.read_u64::<byteorder::LittleEndian>()?; #range_check #bind }
+                                    }
                                     Some("bool") => quote! { let #name = reader.read_u8()? != 0; },
                                     _ => quote! { let #name = ::gbnet::serialize::ByteAlignedDeserialize::byte_aligned_deserialize(reader)?; },
                                 }
@@ -1068,16 +1951,19 @@ fn generate_enum_deserialize(data: &syn::DataEnum, is_bit: bool, input: &DeriveI
                         let is_byte_align = is_byte_aligned(f);
                         let bits = get_field_bit_width(f, &defaults);
                         let max_len = get_max_len(f, input);
-                        let type_name = match &f.ty {
-                            Type::Path(type_path) => type_path.path.get_ident().map(|i| i.to_string()),
-                            _ => None,
-                        };
-                        let deserialize_code = if is_bit {
-                            if bits > 0 {
+                        let type_name = field_type_name(&f.ty);
+                        let deserialize_code = if is_blob(f) {
+                            blob_deserialize(quote! { #name }, f, max_len, is_bit)
+                        } else if is_bit {
+                            if is_varint(f) {
+                                varint_deserialize(quote! { #name }, f)
+                            } else if let Some((min, max)) = get_quantize(f) {
+                                quantized_deserialize(quote! { #name }, bits, min, max)
+                            } else if bits > 0 {
                                 if type_name.as_deref() == Some("bool") {
                                     quote! { let #name = reader.read_bits(#bits)? != 0; }
                                 } else {
-                                    quote! { let #name = reader.read_bits(#bits)? as _; }
+                                    bind_read(quote! { #name }, quote! { reader.read_bits(#bits)? }, type_name.as_deref())
                                 }
                             } else if is_vec_type(&f.ty) {
                                 let (len_bits, max_len_expr) = if let Some(max_len) = max_len {
@@ -1095,19 +1981,23 @@ fn generate_enum_deserialize(data: &syn::DataEnum, is_bit: bool, input: &DeriveI
                                     }
                                     let mut #name = Vec::with_capacity(len);
                                     for _ in 0..len {
-                                        #name.push(::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?);
+                                        #name.push(#bit_read(reader)?);
                                     }
                                 }
                             } else {
-                                quote! { let #name = ::gbnet::serialize::BitDeserialize::bit_deserialize(reader)?; }
+                                quote! { let #name = #bit_read(reader)?; }
                             }
                         } else {
                             if bits > 0 {
+                                let range_check = range_check(quote! { raw }, bits, type_name.as_deref(), quote! { #i });
                                 match type_name.as_deref() {
-                                    Some("u8") | Some("i8") => quote! { let #name = reader.read_u8()?; },
-                                    Some("u16") | Some("i16") => quote! { let #name = reader.read_u16::<byteorder::LittleEndian>()? as _; },
-                                    Some("u32") | Some("i32") => quote! { let #name = reader.read_u32::<byteorder::LittleEndian>()? as _; },
-                                    Some("u64") | Some("i64") => quote! { let #name = reader.read_u64::<byteorder::LittleEndian>()? as _; },
+                                    Some("u8") | Some("i8") => quote! { let raw = reader.read_u8()?; #range_check let #name = raw as _; },
+                                    Some("u16") | Some("i16") => quote! { let raw = reader.read_u16::<byteorder::LittleEndian>()?; #range_check let #name = raw as _; },
+                                    Some("u32") | Some("i32") => quote! { let raw = reader.read_u32::<byteorder::LittleEndian>()?; #range_check let #name = raw as _; },
+                                    Some("u64") | Some("i64") | Some("usize") | Some("isize") => {
+                                        let bind = bind_read(quote! { #name }, quote! { raw }, type_name.as_deref());
+                                        quote! { let raw = reader.read_u64::<byteorder::LittleEndian>()?; #range_check #bind }
+                                    }
                                     Some("bool") => quote! { let #name = reader.read_u8()? != 0; },
                                     _ => quote! { let #name = ::gbnet::serialize::ByteAlignedDeserialize::byte_aligned_deserialize(reader)?; },
                                 }